@@ -0,0 +1,372 @@
+use std::collections;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Invalid glob `{0}` in the attributes: {1}")]
+    Glob(String, globset::Error),
+    #[error("The attributes of `{0}` match none of the protobuf {1}")]
+    Unmatched(String, &'static str),
+}
+
+/// The attributes added to the generated items matching a protobuf path, by the kind of the item.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Default, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// The attributes of the messages, enums and oneofs.
+    #[serde(default, rename = "type")]
+    pub types: Vec<String>,
+    /// The attributes of the enums and oneofs.
+    #[serde(default, rename = "enum")]
+    pub enums: Vec<String>,
+    #[serde(default)]
+    pub field: Vec<String>,
+    /// The outer attributes of the declarations of the package modules.
+    #[serde(default, rename = "mod")]
+    pub modules: Vec<String>,
+}
+
+/// The attributes of the generated items, keyed by a protobuf path, e.g. `.crabs.Ferris`, or a
+/// glob matched against the fully qualified names, e.g. `.crabs.*Request`.
+pub type Rules = collections::BTreeMap<String, Rule>;
+
+/// The attributes of the [`Rules`] matched against the compiled protobuf files, the paths of the
+/// types and the fields ready to be handed to prost.
+#[derive(Default, PartialEq, Debug)]
+pub struct Resolved {
+    pub types: Vec<(String, String)>,
+    pub enums: Vec<(String, String)>,
+    pub fields: Vec<(String, String)>,
+    /// The attributes of the package modules, keyed by the protobuf package, e.g. `crabs.disney`.
+    pub modules: collections::BTreeMap<String, Vec<String>>,
+}
+
+/// The fully qualified names of the protobuf items, with a leading `.`, by their kind.
+#[derive(Default)]
+struct Names {
+    messages: Vec<String>,
+    enums: Vec<String>,
+    oneofs: Vec<String>,
+    fields: Vec<String>,
+    packages: collections::BTreeSet<String>,
+}
+
+impl Names {
+    fn new(fds: &prost_types::FileDescriptorSet) -> Self {
+        let mut names = Self::default();
+        fds.file.iter().for_each(|file| {
+            let scope = match file.package() {
+                "" => String::new(),
+                package => format!(".{package}"),
+            };
+
+            scope
+                .match_indices('.')
+                .map(|(idx, _)| &scope[..idx])
+                .skip(1)
+                .chain(Some(scope.as_str()).filter(|scope| !scope.is_empty()))
+                .for_each(|package| {
+                    names.packages.insert(package.to_string());
+                });
+            names.push(&scope, &file.message_type, &file.enum_type);
+        });
+
+        names
+    }
+
+    fn push(
+        &mut self,
+        scope: &str,
+        messages: &[prost_types::DescriptorProto],
+        enums: &[prost_types::EnumDescriptorProto],
+    ) {
+        enums
+            .iter()
+            .for_each(|enumeration| self.enums.push(format!("{scope}.{}", enumeration.name())));
+        messages.iter().for_each(|message| {
+            let name = format!("{scope}.{}", message.name());
+
+            message
+                .field
+                .iter()
+                .for_each(|field| self.fields.push(format!("{name}.{}", field.name())));
+            message
+                .oneof_decl
+                .iter()
+                .for_each(|oneof| self.oneofs.push(format!("{name}.{}", oneof.name())));
+            self.push(&name, &message.nested_type, &message.enum_type);
+            self.messages.push(name);
+        });
+    }
+}
+
+/// Whether the protobuf `path` matches the item with the fully qualified `name` the way prost
+/// matches them: the item itself, any of the items it is nested in, or a suffix of its name when
+/// the path is not fully qualified.
+pub(crate) fn matches(path: &str, name: &str) -> bool {
+    let is_prefix = |path: &str| {
+        name.strip_prefix(path)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    };
+
+    match path.strip_prefix('.') {
+        Some("") => true,
+        Some(_) => is_prefix(path),
+        None => name
+            .strip_suffix(path)
+            .is_some_and(|rest| rest.ends_with('.')),
+    }
+}
+
+fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '[', '{'])
+}
+
+/// The paths of the `names` matched by the `path` or the glob, leaving out the ones nested in
+/// another match, which prost already matches along with it.
+fn expand<'a>(
+    path: &'a str,
+    glob: Option<&globset::GlobMatcher>,
+    names: &'a [String],
+) -> Vec<&'a str> {
+    let Some(glob) = glob else {
+        return match names.iter().any(|name| matches(path, name)) {
+            true => vec![path],
+            false => vec![],
+        };
+    };
+
+    let matched = names
+        .iter()
+        .filter(|name| glob.is_match(name.as_str()))
+        .collect::<Vec<_>>();
+
+    matched
+        .iter()
+        .filter(|name| {
+            !matched
+                .iter()
+                .any(|other| other != *name && matches(other, name))
+        })
+        .map(|name| name.as_str())
+        .collect()
+}
+
+/// Match the `rules` against the items of the `fds`, failing if any attributes of a rule match
+/// none of the items of their kind, e.g. a mistyped path.
+pub fn resolve(fds: &prost_types::FileDescriptorSet, rules: &Rules) -> Result<Resolved, Error> {
+    let names = Names::new(fds);
+    let types = names
+        .messages
+        .iter()
+        .chain(&names.enums)
+        .chain(&names.oneofs)
+        .cloned()
+        .collect::<Vec<_>>();
+    let enums = names
+        .enums
+        .iter()
+        .chain(&names.oneofs)
+        .cloned()
+        .collect::<Vec<_>>();
+    let packages = names.packages.iter().cloned().collect::<Vec<_>>();
+
+    rules
+        .iter()
+        .try_fold(Resolved::default(), |mut resolved, (path, rule)| {
+            let glob = match is_glob(path) {
+                true => Some(
+                    globset::Glob::new(path)
+                        .map_err(|e| Error::Glob(path.clone(), e))?
+                        .compile_matcher(),
+                ),
+                false => None,
+            };
+            let expand = |attributes: &[String], names: &[String], kind| {
+                let paths = match attributes.is_empty() {
+                    true => vec![],
+                    false => expand(path, glob.as_ref(), names),
+                };
+                if !attributes.is_empty() && paths.is_empty() {
+                    return Err(Error::Unmatched(path.clone(), kind));
+                }
+
+                Ok(paths
+                    .into_iter()
+                    .flat_map(|path| {
+                        attributes
+                            .iter()
+                            .map(move |attribute| (path.to_string(), attribute.clone()))
+                    })
+                    .collect::<Vec<_>>())
+            };
+
+            resolved.types.extend(expand(&rule.types, &types, "types")?);
+            resolved.enums.extend(expand(&rule.enums, &enums, "enums")?);
+            resolved
+                .fields
+                .extend(expand(&rule.field, &names.fields, "fields")?);
+
+            // The attributes of a module cover the modules nested in it, so only the packages
+            // matching exactly get them.
+            let module = format!(".{}", path.trim_start_matches('.'));
+            let modules = packages
+                .iter()
+                .filter(|package| match &glob {
+                    Some(glob) => glob.is_match(package.as_str()),
+                    None => **package == module,
+                })
+                .collect::<Vec<_>>();
+            if !rule.modules.is_empty() && modules.is_empty() {
+                return Err(Error::Unmatched(path.clone(), "packages"));
+            }
+            modules.into_iter().for_each(|package| {
+                resolved
+                    .modules
+                    .entry(package.trim_start_matches('.').to_string())
+                    .or_default()
+                    .extend(rule.modules.iter().cloned());
+            });
+
+            Ok(resolved)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections;
+
+    fn fds() -> prost_types::FileDescriptorSet {
+        let message = |name: &str, fields: &[&str], nested: Vec<prost_types::DescriptorProto>| {
+            prost_types::DescriptorProto {
+                name: Some(name.to_string()),
+                field: fields
+                    .iter()
+                    .map(|field| prost_types::FieldDescriptorProto {
+                        name: Some(field.to_string()),
+                        ..Default::default()
+                    })
+                    .collect(),
+                nested_type: nested,
+                ..Default::default()
+            }
+        };
+
+        prost_types::FileDescriptorSet {
+            file: vec![prost_types::FileDescriptorProto {
+                package: Some("crabs.disney".to_string()),
+                message_type: vec![
+                    message("GetCrabRequest", &["id"], vec![]),
+                    message(
+                        "Sebastian",
+                        &["id", "name"],
+                        vec![message("ShellRequest", &["id"], vec![])],
+                    ),
+                ],
+                enum_type: vec![prost_types::EnumDescriptorProto {
+                    name: Some("Kind".to_string()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn resolve_expands_the_globs() {
+        let rules = super::Rules::from([
+            (
+                ".crabs.*Request".to_string(),
+                super::Rule {
+                    types: vec!["#[derive(Eq)]".to_string()],
+                    ..Default::default()
+                },
+            ),
+            (
+                ".crabs.*.*.id".to_string(),
+                super::Rule {
+                    field: vec!["#[deprecated]".to_string()],
+                    ..Default::default()
+                },
+            ),
+            (
+                "Kind".to_string(),
+                super::Rule {
+                    enums: vec!["#[non_exhaustive]".to_string()],
+                    ..Default::default()
+                },
+            ),
+            (
+                "crabs".to_string(),
+                super::Rule {
+                    modules: vec!["#[allow(missing_docs)]".to_string()],
+                    ..Default::default()
+                },
+            ),
+        ]);
+
+        let resolved = super::resolve(&fds(), &rules).expect("Failed to resolve the attributes");
+        let pairs = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(path, attribute)| (path.to_string(), attribute.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            resolved,
+            super::Resolved {
+                types: pairs(&[
+                    (".crabs.disney.GetCrabRequest", "#[derive(Eq)]"),
+                    (".crabs.disney.Sebastian.ShellRequest", "#[derive(Eq)]"),
+                ]),
+                enums: pairs(&[("Kind", "#[non_exhaustive]")]),
+                fields: pairs(&[
+                    (".crabs.disney.GetCrabRequest.id", "#[deprecated]"),
+                    (".crabs.disney.Sebastian.id", "#[deprecated]"),
+                    (".crabs.disney.Sebastian.ShellRequest.id", "#[deprecated]"),
+                ]),
+                modules: collections::BTreeMap::from([(
+                    "crabs".to_string(),
+                    vec!["#[allow(missing_docs)]".to_string()],
+                )]),
+            },
+            "Invalid resolved attributes",
+        );
+    }
+
+    #[test]
+    fn resolve_rejects_the_unmatched_rules() {
+        let rule = |path: &str, rule: super::Rule| super::Rules::from([(path.to_string(), rule)]);
+
+        let err = super::resolve(
+            &fds(),
+            &rule(
+                ".crabs.Ferris",
+                super::Rule {
+                    types: vec!["#[derive(Eq)]".to_string()],
+                    ..Default::default()
+                },
+            ),
+        );
+        assert!(
+            matches!(&err, Err(super::Error::Unmatched(path, "types")) if path == ".crabs.Ferris"),
+            "Expected `Err(Error::Unmatched)`, got: `{:?}`",
+            err
+        );
+
+        let err = super::resolve(
+            &fds(),
+            &rule(
+                ".crabs.disney.Sebastian",
+                super::Rule {
+                    enums: vec!["#[non_exhaustive]".to_string()],
+                    ..Default::default()
+                },
+            ),
+        );
+        assert!(
+            matches!(&err, Err(super::Error::Unmatched(_, "enums"))),
+            "Expected `Err(Error::Unmatched)`, got: `{:?}`",
+            err
+        );
+    }
+}
@@ -0,0 +1,4130 @@
+use std::{
+    collections, env, ffi, fs,
+    io::{self, IsTerminal},
+    iter, path, sync, time,
+};
+
+use crate::{
+    attributes, cache, check, codeowners, config, defaults, descriptor, descriptor_endpoint,
+    encoded_size, events, exclude, execution, failure_report, history, imports, internal, manifest,
+    modgen, naming, newline, patcher, pbjson, per_service, portable, reachability, redact,
+    reexports, registry, reproducible, reserved, retry, root_file, rows, rustfmt, sandbox, semver,
+    serialize, services, size, size_guard, split, staging, strings, verify, wire_compat,
+};
+
+mod pipeline;
+
+use pipeline::ResolvedTarget;
+
+pub(crate) const DEFAULT_OUTPUT: &str = "out";
+const DEFAULT_TARGET: &str = "default";
+const DEFAULT_RETRY_BACKOFF: time::Duration = time::Duration::from_millis(100);
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to create a temporary directory: {0}")]
+    MkTempDir(io::Error),
+    #[error("Failed to remove previous output directory: {0}")]
+    RemoveOutDir(io::Error),
+    #[error("Failed to create an output directory: {0}")]
+    CreateOutDir(io::Error),
+    #[error("Failed to compile the proto file: {0}")]
+    CompileProto(io::Error),
+    #[error("Failed to generate the code for the `{1}` target: {0}")]
+    GenerateCode(io::Error, String),
+    #[error("Failed to patch protobuf files: {0}")]
+    PatchEdition(#[from] crate::Error),
+    #[error("Failed to create a temporary directory for generate source code `{1}`: {0}")]
+    MkTempCompileDir(io::Error, path::PathBuf),
+    #[error("Failed to assemble the generated code into modules: {0}")]
+    Modularize(#[from] modgen::Error),
+    #[error("Failed to verify the generated code: {0}")]
+    VerifyBuild(#[from] verify::Error),
+    #[error("Failed to make the generated code machine-independent: {0}")]
+    Reproducible(#[from] reproducible::Error),
+    #[error("Failed to remap the file descriptor set: {0}")]
+    RemapDescriptor(#[from] descriptor::Error),
+    #[error("Failed to merge the file descriptor sets: {0}")]
+    MergeDescriptors(descriptor::Error),
+    #[error("Failed to snapshot the effective configuration: {0}")]
+    EffectiveConfig(#[from] config::Error),
+    #[error("Failed to write the effective configuration `{1}`: {0}")]
+    WriteEffectiveConfig(io::Error, path::PathBuf),
+    #[error("Invalid target name `{0}`, it must be unique and can not contain path separators")]
+    InvalidTargetName(String),
+    #[error("Failed to update the code owners: {0}")]
+    Codeowners(#[from] codeowners::Error),
+    #[error("Failed to compare the generated code with the existing output: {0}")]
+    Check(#[from] check::Error),
+    #[error("Failed to use the incremental build cache: {0}")]
+    Cache(#[from] cache::Error),
+    #[error("Failed to compile the proto file in a sandbox: {0}")]
+    Sandbox(#[from] sandbox::Error),
+    #[error("Failed to measure the generated code: {0}")]
+    Size(#[from] size::Error),
+    #[error("Failed to find the unreachable schema: {0}")]
+    Reachability(#[from] reachability::Error),
+    #[error("Failed to replace the output directory: {0}")]
+    Staging(#[from] staging::Error),
+    #[error("Failed to read the imports: {0}")]
+    Imports(#[from] imports::Error),
+    #[error(
+        "The protobuf files import each other: {}",
+        .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+    )]
+    ImportCycles(Vec<imports::Cycle>),
+    #[error(
+        "The protobuf files import missing files: {}",
+        .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+    )]
+    UnresolvedImports(Vec<imports::Unresolved>),
+    #[error(
+        "The numbers of the removed fields are not reserved: {}",
+        .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+    )]
+    UnreservedFields(Vec<reserved::Removed>),
+    #[error(
+        "The strict mode requires the file descriptor set, which the schema changes are checked \
+        against"
+    )]
+    StrictWithoutDescriptorSet,
+    #[error(
+        "The strict mode checks the schema changes against the file descriptor set of the previous \
+        run, but `{0}` does not exist, pass `--allow-missing-descriptor-set` on the first run to \
+        create it"
+    )]
+    MissingPreviousDescriptorSet(path::PathBuf),
+    #[error(
+        "The minimum supported Rust version of the `{0}` target only applies to the manifests of \
+        the crates, it requires `--verify-build` or `--output-layout crate`"
+    )]
+    MsrvWithoutCrate(String),
+    #[error("The messages-only mode generates no service code, it can not be combined with {0}")]
+    MessagesOnly(&'static str),
+    #[error("The {0} connect over the tonic transport, it can not be turned off for them")]
+    NoTransport(&'static str),
+    #[error("Failed to exclude the protobuf files: {0}")]
+    Exclude(#[from] exclude::Error),
+    #[error("Failed to apply the message size limits: {0}")]
+    SizeGuard(#[from] size_guard::Error),
+    #[error("Failed to generate the event envelopes: {0}")]
+    Events(#[from] events::Error),
+    #[error("Failed to map the database rows: {0}")]
+    Rows(#[from] rows::Error),
+    #[error("Failed to generate the default constructors: {0}")]
+    Defaults(#[from] defaults::Error),
+    #[error("Failed to generate the size reports: {0}")]
+    EncodedSize(#[from] encoded_size::Error),
+    #[error("Failed to generate the crate: {0}")]
+    Manifest(#[from] manifest::Error),
+    #[error("Failed to version the generated crate: {0}")]
+    Semver(#[from] semver::Error),
+    #[error("Failed to read the license header `{1}`: {0}")]
+    LicenseHeader(io::Error, path::PathBuf),
+    #[error("Failed to lay out the module tree: {0}")]
+    Portable(#[from] portable::Error),
+    #[error("Failed to format the generated code: {0}")]
+    Rustfmt(#[from] rustfmt::Error),
+    #[error("Failed to rewrite the line endings of the generated code: {0}")]
+    Newline(#[from] newline::Error),
+    #[error("Failed to redact the sensitive fields: {0}")]
+    Redact(#[from] redact::Error),
+    #[error("Failed to hide the internal messages: {0}")]
+    Internal(#[from] internal::Error),
+    #[error("Failed to derive serde for the generated types: {0}")]
+    Serialize(#[from] serialize::Error),
+    #[error("Failed to generate the canonical JSON mapping: {0}")]
+    Pbjson(#[from] pbjson::Error),
+    #[error("Failed to generate the string fields as bytes: {0}")]
+    Strings(#[from] strings::Error),
+    #[error("Failed to apply the attributes: {0}")]
+    Attributes(#[from] attributes::Error),
+    #[error("Failed to split the server traits: {0}")]
+    Split(#[from] split::Error),
+    #[error("Failed to select the generated clients and servers: {0}")]
+    PerService(#[from] per_service::Error),
+    #[error("Failed to rename the generated modules: {0}")]
+    Naming(#[from] naming::Error),
+    #[error("Failed to re-export the generated types: {0}")]
+    Reexports(#[from] reexports::Error),
+    #[error("Failed to generate the descriptor endpoint: {0}")]
+    DescriptorEndpoint(#[from] descriptor_endpoint::Error),
+    #[error(transparent)]
+    WireCompat(#[from] wire_compat::Error),
+    #[error(transparent)]
+    Registry(#[from] registry::Error),
+    #[error(transparent)]
+    RootFile(#[from] root_file::Error),
+    #[error("Failed to generate the code extending the services: {0}")]
+    Services(#[from] services::Error),
+    #[error("Failed to execute the protoc phase: {0}")]
+    Execution(#[from] execution::Error),
+    #[error(transparent)]
+    FailureReport(#[from] failure_report::Error),
+}
+
+impl Error {
+    /// Whether the error is likely to go away when the failed phase is retried.
+    fn is_transient(&self) -> bool {
+        match self {
+            Self::RemoveOutDir(e)
+            | Self::CreateOutDir(e)
+            | Self::CompileProto(e)
+            | Self::GenerateCode(e, _)
+            | Self::Sandbox(sandbox::Error::Spawn(e, _))
+            | Self::Execution(execution::Error::Spawn(e, _)) => retry::is_transient(e),
+            Self::Modularize(e) => Self::is_transient_modgen(e),
+            _ => false,
+        }
+    }
+
+    fn is_transient_modgen(error: &modgen::Error) -> bool {
+        match error {
+            modgen::Error::MkModDir(e, _)
+            | modgen::Error::MkModFile(e, _)
+            | modgen::Error::WriteModFile(e, _)
+            | modgen::Error::ReadSourceFile(e, _) => retry::is_transient(e),
+            modgen::Error::Multiple(errors) => errors.iter().all(Self::is_transient_modgen),
+            _ => false,
+        }
+    }
+
+    /// Point the paths reported by `protoc` at the original protobuf files in the source roots
+    /// instead of their `patched` copies.
+    fn relocate(self, patched: &crate::PatchReport) -> Self {
+        let relocate = |output: &str| {
+            patched
+                .protos
+                .iter()
+                .fold(output.to_string(), |output, proto| {
+                    output.replace(
+                        &proto.destination.display().to_string(),
+                        &proto.source.display().to_string(),
+                    )
+                })
+        };
+
+        match self {
+            Self::CompileProto(e) => {
+                Self::CompileProto(io::Error::new(e.kind(), relocate(&e.to_string())))
+            }
+            Self::Sandbox(sandbox::Error::Protoc(output)) => {
+                Self::Sandbox(sandbox::Error::Protoc(relocate(&output)))
+            }
+            Self::Execution(execution::Error::Failed(output)) => {
+                Self::Execution(execution::Error::Failed(relocate(&output)))
+            }
+            e => e,
+        }
+    }
+
+    /// The output of `protoc`, if it failed.
+    fn protoc_stderr(&self) -> Option<String> {
+        match self {
+            Self::CompileProto(e) => Some(e.to_string()),
+            Self::Sandbox(sandbox::Error::Protoc(output))
+            | Self::Execution(execution::Error::Failed(output)) => Some(output.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Drives the whole pipeline: patches the protobuf files, compiles them once and generates the
+/// module tree of every target. This is the library counterpart of the command line interface,
+/// meant to be used from `build.rs` scripts.
+///
+/// ```no_run
+/// let report = pbuildrs::Builder::new("proto")
+///     .build_client(true)
+///     .cargo_integration(true)
+///     .output("src/autogen")
+///     .build()
+///     .expect("Failed to generate the protobuf code");
+///
+/// report.targets.iter().for_each(|target| {
+///     println!("Generated {} module files", target.files.len());
+/// });
+/// ```
+#[derive(Clone, Debug)]
+pub struct Builder {
+    sources: Vec<path::PathBuf>,
+    exclude: Vec<String>,
+    respect_gitignore: bool,
+    follow_symlinks: bool,
+    build_client: bool,
+    build_server: bool,
+    messages_only: bool,
+    no_transport: bool,
+    with_well_known_types: bool,
+    include_paths: Vec<path::PathBuf>,
+    output: path::PathBuf,
+    temp_dir: Option<path::PathBuf>,
+    file_descriptor_set_path: Option<path::PathBuf>,
+    /// The descriptor set of the previous run, when it is not the one being written, e.g. while
+    /// checking the output.
+    previous_descriptor_set: Option<path::PathBuf>,
+    descriptor_strip_prefix: Option<String>,
+    descriptor_root: Option<String>,
+    descriptor_sets_in: Vec<path::PathBuf>,
+    verify_build: bool,
+    verify_feature_matrix: bool,
+    msrv: Option<verify::RustVersion>,
+    semver_baseline: Option<path::PathBuf>,
+    reproducible: bool,
+    cargo_integration: bool,
+    dry_run: bool,
+    type_attributes: Vec<(String, String)>,
+    field_attributes: Vec<(String, String)>,
+    attributes: attributes::Rules,
+    extern_paths: Vec<(String, String)>,
+    package_outputs: Vec<modgen::PackageOutput>,
+    codeowners: Option<path::PathBuf>,
+    owners: collections::BTreeMap<String, Vec<String>>,
+    cache_file: Option<path::PathBuf>,
+    cache_dir: Option<path::PathBuf>,
+    shared_caches: Vec<path::PathBuf>,
+    history_file: Option<path::PathBuf>,
+    history_limit: usize,
+    failure_report: Option<path::PathBuf>,
+    retry: retry::Policy,
+    progress: bool,
+    sandbox: sandbox::Sandbox,
+    execution: Option<sync::Arc<dyn execution::Backend>>,
+    target_syntax: patcher::Syntax,
+    entry_services: Vec<String>,
+    internal_messages: Vec<String>,
+    sensitive_fields: Vec<String>,
+    serde: Vec<String>,
+    pbjson: bool,
+    bytes: Vec<String>,
+    btree_map: Vec<String>,
+    string_bytes: Vec<String>,
+    fail_on_import_cycles: bool,
+    force: bool,
+    default_stubs: bool,
+    reexports: bool,
+    descriptor_endpoint: bool,
+    wire_compat_tests: Option<path::PathBuf>,
+    effective_config: bool,
+    strict: bool,
+    allow_missing_descriptor_set: bool,
+    offline: bool,
+    registry: Option<registry::Registry>,
+    allow_unknown_editions: bool,
+    conformance_tests: bool,
+    recording_client: bool,
+    shadow_client: bool,
+    message_size_limits: collections::BTreeMap<String, usize>,
+    events: collections::BTreeMap<String, String>,
+    row_models: rows::Models,
+    field_defaults: collections::BTreeMap<String, String>,
+    encoded_size_reports: Vec<String>,
+    method_groups: split::Groups,
+    service_sides: per_service::Overrides,
+    naming: naming::Naming,
+    layout: modgen::Layout,
+    descriptor_tree: bool,
+    visibilities: modgen::Visibilities,
+    root_file: Option<String>,
+    root_attributes: Vec<String>,
+    crate_metadata: manifest::Metadata,
+    banner: Option<String>,
+    license_header: Option<path::PathBuf>,
+    rustfmt: bool,
+    rustfmt_path: Option<path::PathBuf>,
+    rustfmt_edition: rustfmt::Edition,
+    newline: Option<newline::Newline>,
+    targets: Vec<Target>,
+}
+
+/// A generation target, producing a separate module tree from the same compiled protobuf files.
+/// Unset options fall back to the ones of the [`Builder`].
+#[derive(Clone, Debug)]
+pub struct Target {
+    name: String,
+    output: path::PathBuf,
+    build_client: Option<bool>,
+    build_server: Option<bool>,
+    with_well_known_types: Option<bool>,
+    verify_build: Option<bool>,
+    verify_feature_matrix: Option<bool>,
+    msrv: Option<verify::RustVersion>,
+    semver_baseline: Option<path::PathBuf>,
+    type_attributes: Vec<(String, String)>,
+    field_attributes: Vec<(String, String)>,
+    extern_paths: Vec<(String, String)>,
+    package_outputs: Vec<modgen::PackageOutput>,
+}
+
+/// The outcome of a successful [`Builder::build`] run.
+#[derive(Debug)]
+pub struct Report {
+    /// Every protobuf file found in the source directories, sorted by path within each of them.
+    pub protos: Vec<ProtoReport>,
+    /// Every generated target, in the order they were declared.
+    pub targets: Vec<TargetReport>,
+    /// Whether the generation was skipped because nothing changed since the previous run, in which
+    /// case no protobuf files or targets are reported.
+    pub cached: bool,
+    /// How many times a phase was attempted again after a transient failure, across the whole
+    /// run.
+    pub retries: u32,
+    /// The parts of the schema unreachable from the entry services, if any were given.
+    pub unreachable: Option<reachability::Unreachable>,
+    /// The next versions suggested for the targets with a semver baseline, with their names.
+    pub versions: Vec<(String, semver::Suggestion)>,
+}
+
+/// A protobuf file compiled by the [`Builder`].
+#[derive(Debug)]
+pub struct ProtoReport {
+    /// The path of the protobuf file in its source directory.
+    pub source: path::PathBuf,
+    /// Whether the file had to be patched before compiling it.
+    pub outcome: crate::Outcome,
+}
+
+/// A module tree generated by the [`Builder`].
+#[derive(Debug)]
+pub struct TargetReport {
+    pub name: String,
+    pub output: path::PathBuf,
+    /// The module files written into the output directory.
+    pub files: Vec<path::PathBuf>,
+    /// The size of the code generated from every protobuf package, sorted by package.
+    pub packages: Vec<size::PackageSize>,
+    /// How long checking that the generated code compiles took, if it was verified.
+    pub compile_time: Option<time::Duration>,
+}
+
+pub(crate) fn create_temp_working_dir(
+    path: &Option<path::PathBuf>,
+) -> Result<tempfile::TempDir, io::Error> {
+    let mut tempdir = tempfile::Builder::new();
+    let tempdir = tempdir.prefix("pbuildrs-");
+
+    if let Some(path) = path {
+        tempdir.tempdir_in(path)
+    } else {
+        tempdir.tempdir()
+    }
+}
+
+/// Ensure none of the `targets` generates or checks the services in the messages-only mode.
+fn validate_messages_only(
+    targets: &[ResolvedTarget],
+    service_sides: &per_service::Overrides,
+) -> Result<(), Error> {
+    if targets
+        .iter()
+        .any(|target| target.build_client || target.build_server)
+    {
+        return Err(Error::MessagesOnly("the gRPC clients and servers"));
+    }
+    if !service_sides.is_empty() {
+        return Err(Error::MessagesOnly(
+            "the clients and servers chosen per service",
+        ));
+    }
+    if targets.iter().any(|target| target.verify_feature_matrix) {
+        return Err(Error::MessagesOnly("the feature matrix verification"));
+    }
+
+    Ok(())
+}
+
+fn to_attributes(attributes: &[(String, String)]) -> Vec<config::Attribute> {
+    attributes
+        .iter()
+        .map(|(path, attribute)| config::Attribute {
+            path: path.clone(),
+            attribute: attribute.clone(),
+        })
+        .collect()
+}
+
+fn to_extern_paths(extern_paths: &[(String, String)]) -> Vec<config::ExternPath> {
+    extern_paths
+        .iter()
+        .map(|(proto_path, rust_path)| config::ExternPath {
+            proto_path: proto_path.clone(),
+            rust_path: rust_path.clone(),
+        })
+        .collect()
+}
+
+fn hash_outputs(
+    outputs: &[path::PathBuf],
+) -> Result<collections::BTreeMap<path::PathBuf, String>, Error> {
+    Ok(cache::hash_files(
+        &outputs
+            .iter()
+            .map(path::PathBuf::as_path)
+            .collect::<Vec<_>>(),
+        |_| true,
+    )?)
+}
+
+/// Run `f` on a blocking thread of the tokio runtime, resuming its panics.
+#[cfg(feature = "async")]
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => value,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+/// A run between its phases: patched and ready to be compiled, then compiled and ready to generate
+/// the code.
+struct Prepared {
+    targets: Vec<ResolvedTarget>,
+    tempdir: tempfile::TempDir,
+    /// The outputs staged next to the ones they replace once every target succeeded.
+    staged: Vec<staging::Staged>,
+    owned: Vec<codeowners::Entry>,
+    progress: indicatif::ProgressBar,
+    patched: crate::PatchReport,
+    patched_dir: path::PathBuf,
+    patched_files: Vec<path::PathBuf>,
+    includes: Vec<path::PathBuf>,
+    descriptor_set_path: Option<path::PathBuf>,
+    /// Where `protoc` writes the file descriptor set, the `descriptor_set_path` if any, read again
+    /// for the custom options prost drops.
+    compiled_descriptor_path: path::PathBuf,
+    /// The file descriptor set of the previous run, in the strict mode.
+    previous: Option<prost_types::FileDescriptorSet>,
+    descriptor_sets: Vec<(path::PathBuf, prost_types::FileDescriptorSet)>,
+    protoc_args: Vec<ffi::OsString>,
+    retries: u32,
+}
+
+/// Where the code of a run comes from.
+enum Lookup {
+    /// Restored from the previous run or a store.
+    Cached(Report),
+    /// Generated by running the pipeline for the targets, then stored if it is `Pending`.
+    Run(Vec<ResolvedTarget>, Option<Pending>),
+}
+
+/// The code of a run yet to be stored in the caches.
+struct Pending {
+    fingerprint: cache::Fingerprint,
+    outputs: Vec<path::PathBuf>,
+    /// The outputs saved in the store, leaving out the ones nested in another output.
+    stored_outputs: Vec<path::PathBuf>,
+    key: String,
+}
+
+impl Pending {
+    fn stored_outputs(&self) -> Vec<&path::Path> {
+        self.stored_outputs
+            .iter()
+            .map(path::PathBuf::as_path)
+            .collect()
+    }
+}
+
+impl Builder {
+    /// Create a builder compiling the protobuf files found in the `source` directory.
+    pub fn new(source: impl Into<path::PathBuf>) -> Self {
+        Self {
+            sources: vec![source.into()],
+            exclude: vec![],
+            respect_gitignore: false,
+            follow_symlinks: false,
+            build_client: false,
+            build_server: false,
+            messages_only: false,
+            no_transport: false,
+            with_well_known_types: false,
+            include_paths: vec![],
+            output: path::PathBuf::from(DEFAULT_OUTPUT),
+            temp_dir: None,
+            file_descriptor_set_path: None,
+            previous_descriptor_set: None,
+            descriptor_strip_prefix: None,
+            descriptor_root: None,
+            descriptor_sets_in: vec![],
+            verify_build: false,
+            verify_feature_matrix: false,
+            msrv: None,
+            semver_baseline: None,
+            reproducible: false,
+            cargo_integration: false,
+            dry_run: false,
+            type_attributes: vec![],
+            field_attributes: vec![],
+            attributes: attributes::Rules::new(),
+            extern_paths: vec![],
+            package_outputs: vec![],
+            codeowners: None,
+            owners: collections::BTreeMap::new(),
+            cache_file: None,
+            cache_dir: None,
+            shared_caches: vec![],
+            history_file: None,
+            history_limit: history::DEFAULT_LIMIT,
+            failure_report: None,
+            retry: retry::Policy {
+                retries: 0,
+                backoff: DEFAULT_RETRY_BACKOFF,
+            },
+            progress: false,
+            sandbox: sandbox::Sandbox::Off,
+            execution: None,
+            target_syntax: patcher::Syntax::Proto3,
+            entry_services: vec![],
+            internal_messages: vec![],
+            sensitive_fields: vec![],
+            serde: vec![],
+            pbjson: false,
+            bytes: vec![],
+            btree_map: vec![],
+            string_bytes: vec![],
+            fail_on_import_cycles: false,
+            force: false,
+            default_stubs: false,
+            reexports: false,
+            descriptor_endpoint: false,
+            wire_compat_tests: None,
+            effective_config: false,
+            strict: false,
+            allow_missing_descriptor_set: false,
+            offline: false,
+            registry: None,
+            allow_unknown_editions: false,
+            conformance_tests: false,
+            recording_client: false,
+            shadow_client: false,
+            message_size_limits: collections::BTreeMap::new(),
+            events: collections::BTreeMap::new(),
+            row_models: collections::BTreeMap::new(),
+            field_defaults: collections::BTreeMap::new(),
+            encoded_size_reports: vec![],
+            method_groups: collections::BTreeMap::new(),
+            service_sides: collections::BTreeMap::new(),
+            naming: naming::Naming::default(),
+            layout: modgen::Layout::default(),
+            descriptor_tree: false,
+            visibilities: modgen::Visibilities::default(),
+            root_file: None,
+            root_attributes: vec![],
+            crate_metadata: manifest::Metadata::default(),
+            banner: None,
+            license_header: None,
+            rustfmt: false,
+            rustfmt_path: None,
+            rustfmt_edition: rustfmt::Edition::default(),
+            newline: None,
+            targets: vec![],
+        }
+    }
+
+    /// Compile the protobuf files found in another `source` directory along with the first one.
+    /// The files of every source are placed relative to their own directory, so the same path can
+    /// not be found in more than one of them.
+    pub fn source(mut self, source: impl Into<path::PathBuf>) -> Self {
+        self.sources.push(source.into());
+        self
+    }
+
+    /// Skip the files and directories matching the glob `pattern`, relative to the source
+    /// directory they are found in, e.g. `**/internal/**`.
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    /// Whether to also skip the files ignored by the `.gitignore` files of the git repository the
+    /// sources are in. The `.pbuildrsignore` files are always respected.
+    pub fn respect_gitignore(mut self, enable: bool) -> Self {
+        self.respect_gitignore = enable;
+        self
+    }
+
+    /// Whether to descend into the symlinked directories of the sources and of the generated code.
+    /// Symlinks pointing back at a directory being walked are skipped with a warning.
+    pub fn follow_symlinks(mut self, enable: bool) -> Self {
+        self.follow_symlinks = enable;
+        self
+    }
+
+    /// Whether to generate the gRPC client code.
+    pub fn build_client(mut self, enable: bool) -> Self {
+        self.build_client = enable;
+        self
+    }
+
+    /// Whether to generate the gRPC server stubs.
+    pub fn build_server(mut self, enable: bool) -> Self {
+        self.build_server = enable;
+        self
+    }
+
+    /// Generate the messages only, with the plain prost code generation: no service code, and
+    /// no tonic dependency in the [`modgen::Layout::Crate`] manifests. Fails the build if any
+    /// target generates a client or a server.
+    pub fn messages_only(mut self, enable: bool) -> Self {
+        self.messages_only = enable;
+        self
+    }
+
+    /// Generate the clients and servers without the tonic transport, so they compile on targets
+    /// without it, e.g. `wasm32-unknown-unknown` with a client like `tonic-web-wasm-client`. The
+    /// [`modgen::Layout::Crate`] manifests then depend on tonic without its `transport` feature.
+    pub fn no_transport(mut self, enable: bool) -> Self {
+        self.no_transport = enable;
+        self
+    }
+
+    /// Whether to build the well-known types.
+    pub fn with_well_known_types(mut self, enable: bool) -> Self {
+        self.with_well_known_types = enable;
+        self
+    }
+
+    /// Add a directory to the protobuf import path.
+    pub fn include_path(mut self, path: impl Into<path::PathBuf>) -> Self {
+        self.include_paths.push(path.into());
+        self
+    }
+
+    /// The output directory of the generated code, ignored if any targets are added.
+    pub fn output(mut self, path: impl Into<path::PathBuf>) -> Self {
+        self.output = path.into();
+        self
+    }
+
+    /// Create the temporary working directory in this directory.
+    pub fn temp_dir(mut self, path: impl Into<path::PathBuf>) -> Self {
+        self.temp_dir = Some(path.into());
+        self
+    }
+
+    /// Generate a file descriptor set and store it at this path.
+    pub fn file_descriptor_set_path(mut self, path: impl Into<path::PathBuf>) -> Self {
+        self.file_descriptor_set_path = Some(path.into());
+        self
+    }
+
+    /// Strip this directory prefix from the file names recorded in the file descriptor set.
+    pub fn descriptor_strip_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.descriptor_strip_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Place the file names recorded in the file descriptor set under this virtual root directory.
+    pub fn descriptor_root(mut self, root: impl Into<String>) -> Self {
+        self.descriptor_root = Some(root.into());
+        self
+    }
+
+    /// Merge the protobuf files of the file descriptor set at this path, e.g. exported from a
+    /// schema registry or compiled from a vendored tree with `protoc --include_imports`, into the
+    /// compiled ones. The local files can import the files in it, and the types of a package are
+    /// generated into a single module wherever they come from. Fails the build if a type is
+    /// defined by more than one file.
+    pub fn descriptor_set_in(mut self, path: impl Into<path::PathBuf>) -> Self {
+        self.descriptor_sets_in.push(path.into());
+        self
+    }
+
+    /// Verify that the generated code compiles by running `cargo check` on a throwaway crate.
+    pub fn verify_build(mut self, enable: bool) -> Self {
+        self.verify_build = enable;
+        self
+    }
+
+    /// Verify that the generated code compiles under every combination of the `client` and
+    /// `server` features.
+    pub fn verify_feature_matrix(mut self, enable: bool) -> Self {
+        self.verify_feature_matrix = enable;
+        self
+    }
+
+    /// Minimum supported Rust version of the generated code, recorded as the `rust-version` of the
+    /// verification crate and of the [`modgen::Layout::Crate`] outputs, so it requires one of them.
+    /// The verification crate is checked with the toolchain of this version, which has to be
+    /// installed with rustup. It can not be older than the toolchain required by prost and tonic.
+    pub fn msrv(mut self, version: verify::RustVersion) -> Self {
+        self.msrv = Some(version);
+        self
+    }
+
+    /// Compare the schema with the one of the generated crate published into the `baseline`
+    /// directory, e.g. extracted from the registry, and suggest the next version of the crate. A
+    /// [`manifest::Metadata::version`] lower than the suggested one fails the build.
+    pub fn semver_baseline(mut self, baseline: impl Into<path::PathBuf>) -> Self {
+        self.semver_baseline = Some(baseline.into());
+        self
+    }
+
+    /// Make the output identical across machines: any machine-specific path embedded into the
+    /// generated files is rejected, and the modification time of the written files is set to the
+    /// `SOURCE_DATE_EPOCH` environment variable, if any. The file names recorded in the file
+    /// descriptor sets are made relative on every run.
+    pub fn reproducible(mut self, enable: bool) -> Self {
+        self.reproducible = enable;
+        self
+    }
+
+    /// Print the `cargo:rerun-if-changed` directives for every compiled protobuf file and include
+    /// directory, so a build script is re-run whenever any of them changes.
+    pub fn cargo_integration(mut self, enable: bool) -> Self {
+        self.cargo_integration = enable;
+        self
+    }
+
+    /// Compile the protobuf files and report the module files that would be generated, without
+    /// writing anything into the output directories or the file descriptor set.
+    pub fn dry_run(mut self, enable: bool) -> Self {
+        self.dry_run = enable;
+        self
+    }
+
+    /// Add the `attribute` to the generated messages, enums and oneofs matching the protobuf
+    /// `path`, e.g. `#[derive(Eq, Hash)]` on `.crabs.Ferris`, in every target, ahead of the
+    /// attributes of the target itself.
+    pub fn type_attribute(mut self, path: impl Into<String>, attribute: impl Into<String>) -> Self {
+        self.type_attributes.push((path.into(), attribute.into()));
+        self
+    }
+
+    /// Add the `attribute` to the generated fields matching the protobuf `path`, e.g.
+    /// `.crabs.Ferris.name`, in every target, ahead of the attributes of the target itself.
+    pub fn field_attribute(
+        mut self,
+        path: impl Into<String>,
+        attribute: impl Into<String>,
+    ) -> Self {
+        self.field_attributes.push((path.into(), attribute.into()));
+        self
+    }
+
+    /// Add the attributes of the `rule` to the generated items of every target matching the
+    /// protobuf `path`, or the glob matched against their fully qualified names, e.g.
+    /// `.crabs.*Request`. The attributes matching none of the items of their kind fail the run.
+    pub fn attributes(mut self, path: impl Into<String>, rule: attributes::Rule) -> Self {
+        self.attributes.insert(path.into(), rule);
+        self
+    }
+
+    /// Use the existing Rust type at `rust_path` for the protobuf types matching `proto_path`
+    /// instead of generating them, e.g. `::prost_types` for `.google.protobuf`, in every target,
+    /// ahead of the extern paths of the target itself.
+    pub fn extern_path(
+        mut self,
+        proto_path: impl Into<String>,
+        rust_path: impl Into<String>,
+    ) -> Self {
+        self.extern_paths
+            .push((proto_path.into(), rust_path.into()));
+        self
+    }
+
+    /// Place the modules of a protobuf package into a separate output directory instead of the
+    /// [`Builder::output`] one. Ignored if any targets are added.
+    pub fn package_output(mut self, package: modgen::PackageOutput) -> Self {
+        self.package_outputs.push(package);
+        self
+    }
+
+    /// How the module tree is laid out in every output directory, a directory per module by
+    /// default, a single `generated.rs` with the modules nested inline, or a Cargo package named
+    /// after the output directory, with the module tree in its `src`.
+    pub fn output_layout(mut self, layout: modgen::Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Whether to place the generated files into the module tree after the packages of the
+    /// descriptor set, rather than after their dotted file names, failing on the files generated
+    /// for none of the packages.
+    pub fn descriptor_tree(mut self, enable: bool) -> Self {
+        self.descriptor_tree = enable;
+        self
+    }
+
+    /// Declare the generated modules with the `visibility`, `pub` by default, e.g. to embed the
+    /// module tree into a crate without exposing every protobuf package.
+    pub fn module_visibility(mut self, visibility: modgen::Visibility) -> Self {
+        self.visibilities.default = visibility;
+        self
+    }
+
+    /// Declare the module of the protobuf `package`, e.g. `corp.billing`, and the modules of the
+    /// packages nested in it, with the `visibility` instead of the [`Builder::module_visibility`].
+    pub fn package_visibility(
+        mut self,
+        package: impl Into<String>,
+        visibility: modgen::Visibility,
+    ) -> Self {
+        self.visibilities
+            .packages
+            .insert(package.into(), visibility);
+        self
+    }
+
+    /// Write a root file with this name, e.g. `lib.rs`, next to the root module of every output,
+    /// re-exporting the whole module tree under the [`Builder::root_attribute`]s. The output can
+    /// then be used as the source directory of a crate, or loaded with
+    /// `#[path = "<output>/lib.rs"] mod autogen;`.
+    pub fn root_file(mut self, name: impl Into<String>) -> Self {
+        self.root_file = Some(name.into());
+        self
+    }
+
+    /// Add an inner attribute to the [`Builder::root_file`], e.g. `#![allow(clippy::all)]`.
+    pub fn root_attribute(mut self, attribute: impl Into<String>) -> Self {
+        self.root_attributes.push(attribute.into());
+        self
+    }
+
+    /// Describe the crates generated with the [`modgen::Layout::Crate`] with the `metadata`, e.g.
+    /// their license and lints, so they can be published as they are.
+    pub fn crate_metadata(mut self, metadata: manifest::Metadata) -> Self {
+        self.crate_metadata = metadata;
+        self
+    }
+
+    /// Start every module file with this banner, e.g. `@generated by pbuildrs {version}, do not
+    /// edit`, commented out unless it already is a comment. The `{version}` is replaced with the
+    /// version of pbuildrs.
+    pub fn banner(mut self, banner: impl Into<String>) -> Self {
+        self.banner = Some(banner.into());
+        self
+    }
+
+    /// Start every module file with the contents of this file, e.g. the license header required on
+    /// every checked-in file, commented out unless it already is a comment, before the
+    /// [`Builder::banner`].
+    pub fn license_header(mut self, path: impl Into<path::PathBuf>) -> Self {
+        self.license_header = Some(path.into());
+        self
+    }
+
+    /// Whether to format the generated files with rustfmt, so they don't stand out next to the
+    /// formatted hand-written code. The files are left unformatted with a warning if rustfmt is
+    /// not installed.
+    pub fn rustfmt(mut self, enable: bool) -> Self {
+        self.rustfmt = enable;
+        self
+    }
+
+    /// The rustfmt binary the [`Builder::rustfmt`] option runs, the one in the `RUSTFMT`
+    /// environment variable or in the `PATH` by default.
+    pub fn rustfmt_path(mut self, path: impl Into<path::PathBuf>) -> Self {
+        self.rustfmt_path = Some(path.into());
+        self
+    }
+
+    /// The Rust edition the generated files are formatted with, 2021 by default.
+    pub fn rustfmt_edition(mut self, edition: rustfmt::Edition) -> Self {
+        self.rustfmt_edition = edition;
+        self
+    }
+
+    /// Write the generated files with the `newline` line endings, instead of the ones prost and
+    /// pbuildrs emit, e.g. to satisfy the hooks of a repository rejecting mixed line endings.
+    pub fn newline(mut self, newline: newline::Newline) -> Self {
+        self.newline = Some(newline);
+        self
+    }
+
+    /// Record the owners of the generated code in a block of this CODEOWNERS file, keeping the rest
+    /// of the file intact.
+    pub fn codeowners(mut self, path: impl Into<path::PathBuf>) -> Self {
+        self.codeowners = Some(path.into());
+        self
+    }
+
+    /// Assign the `owners` to the code generated from the protobuf `package` and every package
+    /// nested in it.
+    pub fn owner(
+        mut self,
+        package: impl Into<String>,
+        owners: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.owners
+            .insert(package.into(), owners.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Store the content hashes of the protobuf files, the configuration and the generated code in
+    /// this file, and skip the generation entirely if none of them changed since the previous run.
+    pub fn cache_file(mut self, path: impl Into<path::PathBuf>) -> Self {
+        self.cache_file = Some(path.into());
+        self
+    }
+
+    /// Store the code generated by every run in this directory, keyed by the hash of the protobuf
+    /// files and the configuration, and restore it instead of generating the code again when a
+    /// later run has the same key, e.g. after switching back to a previous branch.
+    pub fn cache_dir(mut self, path: impl Into<path::PathBuf>) -> Self {
+        self.cache_dir = Some(path.into());
+        self
+    }
+
+    /// Look the generated code up in this store too, after the [`Builder::cache_dir`] one, e.g. an
+    /// NFS mount populated by other CI runners. It is only ever read, the new entries are stored
+    /// in the [`Builder::cache_dir`].
+    pub fn shared_cache(mut self, path: impl Into<path::PathBuf>) -> Self {
+        self.shared_caches.push(path.into());
+        self
+    }
+
+    /// Record every run in this history file: when it started, the hash of the protobuf files,
+    /// how long it took and how it ended. Run `pbuildrs history` to display it.
+    pub fn history_file(mut self, path: impl Into<path::PathBuf>) -> Self {
+        self.history_file = Some(path.into());
+        self
+    }
+
+    /// Keep only this many of the most recent runs in the [`Builder::history_file`].
+    pub fn history_limit(mut self, limit: usize) -> Self {
+        self.history_limit = limit;
+        self
+    }
+
+    /// When the run fails, write a report reproducing it into this directory, e.g. to be attached
+    /// to a CI job: the effective configuration, the error and its causes, the output of `protoc`
+    /// and the patched protobuf files involved in the failure.
+    pub fn failure_report(mut self, dir: impl Into<path::PathBuf>) -> Self {
+        self.failure_report = Some(dir.into());
+        self
+    }
+
+    /// Retry invoking `protoc`, generating the code and writing the module tree up to this many
+    /// times when they fail with a transient I/O error, e.g. on a network file system.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retry.retries = retries;
+        self
+    }
+
+    /// How long to wait before the first retry, the delay doubles after every failed attempt.
+    pub fn retry_backoff(mut self, backoff: time::Duration) -> Self {
+        self.retry.backoff = backoff;
+        self
+    }
+
+    /// Render a progress bar across the patching, compilation and generation stages. It is only
+    /// shown when the standard output is a terminal.
+    pub fn progress(mut self, enable: bool) -> Self {
+        self.progress = enable;
+        self
+    }
+
+    /// Isolate `protoc` from the environment, and optionally from the network, while compiling the
+    /// protobuf files, for when they come from a source that is not fully trusted. The file system
+    /// is not confined.
+    pub fn sandbox(mut self, sandbox: sandbox::Sandbox) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Dispatch the `protoc` phase to the `backend`, e.g. a remote execution service, while the
+    /// protobuf files are still patched and the code generated locally.
+    pub fn execution(mut self, backend: impl execution::Backend + 'static) -> Self {
+        self.execution = Some(sync::Arc::new(backend));
+        self
+    }
+
+    /// The syntax the edition of the protobuf files is replaced with, `proto3` by default.
+    pub fn target_syntax(mut self, syntax: patcher::Syntax) -> Self {
+        self.target_syntax = syntax;
+        self
+    }
+
+    /// Report the messages, enums and files unreachable from this service, and every other entry
+    /// service, given by its fully qualified name, e.g. `crabs.CrabService`.
+    pub fn entry_service(mut self, service: impl Into<String>) -> Self {
+        self.entry_services.push(service.into());
+        self
+    }
+
+    /// Move the `message`, given by its fully qualified name, e.g. `crabs.GetFerrisReqProto`, and
+    /// the types nested in it into a `#[doc(hidden)]` module of the module it is generated in,
+    /// keeping the internal plumbing out of the published API. The messages marked with the
+    /// `(pbuildrs.internal)` custom option are hidden too.
+    pub fn internal_message(mut self, message: impl Into<String>) -> Self {
+        self.internal_messages.push(message.into());
+        self
+    }
+
+    /// Print the `field`, given by its fully qualified name, e.g. `crabs.Ferris.home_address`, as
+    /// `[REDACTED]` in the `Debug` output of its message, so logging the generated types doesn't
+    /// leak personal data. The `Debug` implementations of the message and of the types nested in
+    /// it are generated instead of derived.
+    pub fn sensitive_field(mut self, field: impl Into<String>) -> Self {
+        self.sensitive_fields.push(field.into());
+        self
+    }
+
+    /// Derive `serde::Serialize` and `serde::Deserialize` for the messages, enums and oneofs of the
+    /// `package`, e.g. `crabs`, and the packages nested in it, or of every package with `.`. The
+    /// fields of the messages default to their protobuf default values when left out, and the
+    /// [`modgen::Layout::Crate`] manifests depend on serde. Fails the build if a message in the
+    /// package has a field of a type serde is not derived for.
+    pub fn serde(mut self, package: impl Into<String>) -> Self {
+        self.serde.push(package.into());
+        self
+    }
+
+    /// Generate the `serde::Serialize` and `serde::Deserialize` implementations of every message
+    /// and enum with pbjson, following the canonical protobuf JSON mapping, e.g. camel-cased field
+    /// names, enums by their names and 64-bit integers as strings, unlike the derives of
+    /// [`Builder::serde`]. The well-known types are taken from `pbjson-types`, and the
+    /// [`modgen::Layout::Crate`] manifests depend on it, `pbjson` and serde.
+    pub fn pbjson(mut self, enable: bool) -> Self {
+        self.pbjson = enable;
+        self
+    }
+
+    /// Generate the `bytes` fields matching the protobuf `path` as `bytes::Bytes` instead of
+    /// `Vec<u8>`, e.g. `.crabs.Ferris.photo`, or every one of them with `.`, so they are decoded
+    /// without copying the buffer.
+    pub fn bytes(mut self, path: impl Into<String>) -> Self {
+        self.bytes.push(path.into());
+        self
+    }
+
+    /// Generate the map fields matching the protobuf `path` as `BTreeMap` instead of `HashMap`,
+    /// e.g. `.crabs.Ferris.claws`, or every one of them with `.`, so they are iterated and
+    /// serialized in the order of their keys.
+    pub fn btree_map(mut self, path: impl Into<String>) -> Self {
+        self.btree_map.push(path.into());
+        self
+    }
+
+    /// Generate the string fields matching the protobuf `path` as `bytes::Bytes` instead of
+    /// `String`, e.g. `.crabs.Ferris.name`, so they are decoded without copying the buffer or
+    /// validating the UTF-8, which is left to the readers of the fields. It can not be combined
+    /// with [`Builder::serde`] or [`Builder::pbjson`].
+    pub fn string_bytes(mut self, path: impl Into<String>) -> Self {
+        self.string_bytes.push(path.into());
+        self
+    }
+
+    /// Remove the package `prefix`, e.g. `corp`, from the Rust module paths of every package
+    /// starting with it. The longest matching prefix is removed.
+    pub fn strip_package_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.naming.strip_prefixes.push(prefix.into());
+        self
+    }
+
+    /// Name the module of every package `segment`, e.g. `v1beta1`, after the `module` instead,
+    /// e.g. `v1_beta1`.
+    pub fn rename_module(mut self, segment: impl Into<String>, module: impl Into<String>) -> Self {
+        self.naming.renames.insert(segment.into(), module.into());
+        self
+    }
+
+    /// Fail before compiling the protobuf files if any of them import each other, instead of only
+    /// reporting the cycles and leaving it to `protoc`.
+    pub fn fail_on_import_cycles(mut self, enable: bool) -> Self {
+        self.fail_on_import_cycles = enable;
+        self
+    }
+
+    /// Replace the existing output directories even if they are not marked as generated by
+    /// pbuildrs. Without it, a non-empty output directory is only replaced if it contains the
+    /// marker file written into every generated output.
+    pub fn force(mut self, enable: bool) -> Self {
+        self.force = enable;
+        self
+    }
+
+    /// Generate a conformance test module for every service of the targets building the client,
+    /// calling each unary method against the endpoint given by the
+    /// `PBUILDRS_CONFORMANCE_ENDPOINT` environment variable when the tests run.
+    pub fn conformance_tests(mut self, enable: bool) -> Self {
+        self.conformance_tests = enable;
+        self
+    }
+
+    /// Generate a client for every service of the targets building the client, which records the
+    /// calls made through the tonic client into a directory, or replays them in tests without a
+    /// server.
+    pub fn recording_client(mut self, enable: bool) -> Self {
+        self.recording_client = enable;
+        self
+    }
+
+    /// Whether to give every method of the generated server traits a default body answering with
+    /// `Unimplemented`, so the services can be implemented one method at a time. Streaming methods
+    /// return a boxed stream instead of an associated type then.
+    pub fn default_stubs(mut self, enable: bool) -> Self {
+        self.default_stubs = enable;
+        self
+    }
+
+    /// Whether to write a `reexports.rs` next to the root module of every target, aliasing every
+    /// generated message and enum by its fully qualified protobuf name under a flat module, e.g.
+    /// `flat::crabs_Ferris`, for the code looking the types up by their protobuf name.
+    pub fn reexports(mut self, enable: bool) -> Self {
+        self.reexports = enable;
+        self
+    }
+
+    /// Whether to write a `descriptor_endpoint.rs` next to the root module of every target, with a
+    /// module serving a file descriptor set over HTTP and notifying the clients when it changes,
+    /// e.g. after a rebuild in the watch mode. The module is only compiled into the debug builds.
+    pub fn descriptor_endpoint(mut self, enable: bool) -> Self {
+        self.descriptor_endpoint = enable;
+        self
+    }
+
+    /// Write a `wire_compat.rs` next to the root module of every target, with tests encoding every
+    /// message with the generated descriptors and decoding it with the file descriptor set of a
+    /// previous version at this path, and the other way around.
+    pub fn wire_compat_tests(mut self, previous: impl Into<path::PathBuf>) -> Self {
+        self.wire_compat_tests = Some(previous.into());
+        self
+    }
+
+    /// Whether to write the fully resolved options of the run into an `effective-config.toml` in
+    /// the output of every target, in the format of the configuration file with the paths relative
+    /// to the output, so the generated code can be audited and reproduced from it alone.
+    pub fn effective_config(mut self, enable: bool) -> Self {
+        self.effective_config = enable;
+        self
+    }
+
+    /// Whether to enable every check meant for CI: warn about every rewritten edition, fail on
+    /// imports of missing files before running `protoc`, fail when a field is removed without
+    /// reserving its number, and verify that the output is [reproducible](Self::reproducible).
+    /// The file descriptor set is required, it serves as the lock file of the schema the removed
+    /// fields are found against, and must exist unless it is
+    /// [allowed to be missing](Self::allow_missing_descriptor_set).
+    pub fn strict(mut self, enable: bool) -> Self {
+        self.strict = enable;
+        self
+    }
+
+    /// Whether the [strict](Self::strict) mode may run without the file descriptor set of the
+    /// previous run, skipping the check of the removed fields, e.g. on the first run, which writes
+    /// it.
+    pub fn allow_missing_descriptor_set(mut self, enable: bool) -> Self {
+        self.allow_missing_descriptor_set = enable;
+        self
+    }
+
+    /// Whether to forbid any network access, e.g. Cargo fetching the dependencies of the crate the
+    /// generated code is verified with, for the air-gapped build environments. The steps that
+    /// would need the network fail instead.
+    pub fn offline(mut self, enable: bool) -> Self {
+        self.offline = enable;
+        self
+    }
+
+    /// Check the compatibility of the compiled protobuf files with the schema registry at the
+    /// `url` before generating any code, failing if it rejects them. The registry is reached with
+    /// `curl`, authenticated with the credentials in the `PBUILDRS_REGISTRY_AUTH` environment
+    /// variable, if set.
+    pub fn schema_registry(mut self, url: impl Into<String>, api: registry::Api) -> Self {
+        self.registry = Some(registry::Registry {
+            url: url.into(),
+            api,
+        });
+        self
+    }
+
+    /// Whether to replace the editions missing from the [known ones](patcher::EDITIONS) with a
+    /// warning, instead of failing on them, since their semantics may not survive the replacement.
+    pub fn allow_unknown_editions(mut self, enable: bool) -> Self {
+        self.allow_unknown_editions = enable;
+        self
+    }
+
+    /// Generate a client for every service of the targets building the client, which sends the
+    /// calls to a primary endpoint, mirrors them to an optional shadow one and reports the
+    /// responses that differ.
+    pub fn shadow_client(mut self, enable: bool) -> Self {
+        self.shadow_client = enable;
+        self
+    }
+
+    /// Limit the size of the encoded request and response messages of the `target`, a method or
+    /// every method of a service given by its fully qualified name, e.g.
+    /// `crabs.CrabService/GetFerris`. A size guard wrapping the server implementation is generated
+    /// for every service with any limits, for the targets building the server.
+    pub fn max_message_size(mut self, target: impl Into<String>, bytes: usize) -> Self {
+        self.message_size_limits.insert(target.into(), bytes);
+        self
+    }
+
+    /// Publish the `message`, given by its fully qualified name, e.g. `crabs.FerrisMoved`, as an
+    /// event to the `topic`, a Kafka topic or a NATS subject. A `<message>_envelope` module is
+    /// generated next to the message, with the topic and the helpers encoding and decoding the
+    /// message framed with the id of its schema in the registry.
+    pub fn event(mut self, message: impl Into<String>, topic: impl Into<String>) -> Self {
+        self.events.insert(message.into(), topic.into());
+        self
+    }
+
+    /// Map database rows to the `message`, given by its fully qualified name, e.g. `crabs.Ferris`,
+    /// generating a `sqlx::FromRow` implementation behind the `sqlx` feature. Its scalar and enum
+    /// fields are decoded from the columns named after them, or from the `columns` keyed by the
+    /// name of the field, and the other fields are left at their defaults.
+    pub fn row_model(
+        mut self,
+        message: impl Into<String>,
+        columns: impl IntoIterator<Item = (impl Into<String>, impl Into<String>)>,
+    ) -> Self {
+        self.row_models.entry(message.into()).or_default().extend(
+            columns
+                .into_iter()
+                .map(|(field, column)| (field.into(), column.into())),
+        );
+        self
+    }
+
+    /// Default the `field`, given by its fully qualified name, e.g.
+    /// `crabs.ListFerrisRequest.page_size`, to the `value` instead of its protobuf zero value. A
+    /// `with_defaults` constructor is generated for every message with any defaults, since prost
+    /// derives the `Default` implementation. The value of an enum field is the name of the enum
+    /// value, and the field must be a singular scalar or enum field.
+    pub fn field_default(mut self, field: impl Into<String>, value: impl Into<String>) -> Self {
+        let field = field.into();
+        self.field_defaults
+            .insert(field.trim_start_matches('.').to_string(), value.into());
+        self
+    }
+
+    /// Report the encoded size of the `message`, given by its fully qualified name, e.g.
+    /// `crabs.Ferris`, generating a `field_sizes` method breaking the size down by field and a
+    /// `size_summary` one formatting it, to debug the size of the payloads.
+    pub fn encoded_size_report(mut self, message: impl Into<String>) -> Self {
+        self.encoded_size_reports.push(message.into());
+        self
+    }
+
+    /// Split the server trait of the `service`, given by its fully qualified name, into a trait
+    /// per group of methods, adding the `group` of the methods starting with any of the
+    /// `prefixes`. A method belongs to the group with the longest matching prefix, and every method
+    /// of a split service has to belong to one, an empty prefix matching all of them.
+    pub fn split_trait(
+        mut self,
+        service: impl Into<String>,
+        group: impl Into<String>,
+        prefixes: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.method_groups
+            .entry(service.into())
+            .or_default()
+            .entry(group.into())
+            .or_default()
+            .extend(prefixes.into_iter().map(Into::into));
+        self
+    }
+
+    /// Generate the client and the server of the `service`, given by its fully qualified name,
+    /// according to the `sides` rather than [`Builder::build_client`] and
+    /// [`Builder::build_server`], e.g. to only generate the server of one service and the client
+    /// of another one.
+    pub fn service_sides(mut self, service: impl Into<String>, sides: per_service::Sides) -> Self {
+        let service = service.into();
+        self.service_sides
+            .insert(service.trim_start_matches('.').to_string(), sides);
+        self
+    }
+
+    /// Add a generation target. Once any targets are added, the default one writing into the
+    /// [`Builder::output`] directory is no longer generated.
+    pub fn target(mut self, target: Target) -> Self {
+        self.targets.push(target);
+        self
+    }
+
+    /// Run the pipeline, replacing the contents of every output directory.
+    pub fn build(&self) -> Result<Report, Error> {
+        let started_at = time::SystemTime::now();
+        let result = self.build_cached();
+        self.record(started_at, &result);
+        self.report_failure(&result);
+
+        result
+    }
+
+    /// Like [`Builder::build`], but waits for `protoc` without blocking a thread, running the other
+    /// phases on the blocking threads of the tokio runtime, so many builders can be driven
+    /// concurrently. The runtime needs its I/O and time drivers enabled.
+    #[cfg(feature = "async")]
+    pub async fn build_async(&self) -> Result<Report, Error> {
+        let builder = sync::Arc::new(self.clone());
+        let started_at = time::SystemTime::now();
+        let result = builder.build_cached_async().await;
+
+        blocking(move || {
+            builder.record(started_at, &result);
+            builder.report_failure(&result);
+
+            result
+        })
+        .await
+    }
+
+    #[cfg(feature = "async")]
+    async fn build_cached_async(self: &sync::Arc<Self>) -> Result<Report, Error> {
+        let builder = self.clone();
+        let (targets, pending) = match blocking(move || builder.lookup()).await? {
+            Lookup::Cached(report) => return Ok(report),
+            Lookup::Run(targets, pending) => (targets, pending),
+        };
+
+        let builder = self.clone();
+        let prepared = blocking(move || builder.prepare(targets)).await?;
+        let (prepared, fds) = self.compile_async(prepared).await;
+
+        let builder = self.clone();
+        blocking(move || {
+            let report = builder.finish(prepared, fds?)?;
+            builder.store(pending, report)
+        })
+        .await
+    }
+
+    /// Like [`Builder::compile`], but awaits `protoc`. The execution backends are synchronous, so
+    /// they run on a blocking thread instead.
+    #[cfg(feature = "async")]
+    async fn compile_async(
+        self: &sync::Arc<Self>,
+        mut prepared: Prepared,
+    ) -> (
+        Prepared,
+        Result<tonic_prost_build::FileDescriptorSet, Error>,
+    ) {
+        if self.execution.is_some() {
+            let builder = self.clone();
+            return blocking(move || {
+                let fds = builder.compile(&mut prepared);
+                (prepared, fds)
+            })
+            .await;
+        }
+
+        prepared.progress.set_length(1);
+        prepared.progress.set_position(0);
+        prepared.progress.set_message("Compiling");
+
+        let sandbox = self.sandbox;
+        let workdir = prepared.tempdir.path();
+        let (protos, includes) = (&prepared.patched_files, &prepared.includes);
+        let (args, descriptor_set_path) = (
+            &prepared.protoc_args,
+            Some(prepared.compiled_descriptor_path.as_path()),
+        );
+        let fds = retry::run_async(
+            &self.retry,
+            &mut prepared.retries,
+            Error::is_transient,
+            |_| async move {
+                sandbox::load_fds_async(
+                    sandbox,
+                    workdir,
+                    protos,
+                    includes,
+                    args,
+                    descriptor_set_path,
+                )
+                .await
+                .map_err(|e| match (sandbox, e) {
+                    // Reported the same as the failures of prost running protoc.
+                    (sandbox::Sandbox::Off, sandbox::Error::Spawn(e, _)) => Error::CompileProto(e),
+                    (sandbox::Sandbox::Off, sandbox::Error::Protoc(output)) => {
+                        Error::CompileProto(io::Error::other(format!("protoc failed: {output}")))
+                    }
+                    (_, e) => Error::Sandbox(e),
+                })
+            },
+        )
+        .await
+        .map_err(|e| e.relocate(&prepared.patched));
+        if fds.is_ok() {
+            prepared.progress.inc(1);
+        }
+
+        (prepared, fds)
+    }
+
+    /// Write the failure report of the failed `result` into the [`Builder::failure_report`]
+    /// directory, if any.
+    fn report_failure(&self, result: &Result<Report, Error>) {
+        let written = result
+            .as_ref()
+            .err()
+            .zip(self.failure_report.as_ref())
+            .map(|(e, dir)| self.write_failure_report(dir, e).map(|()| dir));
+        match written {
+            Some(Ok(dir)) => tracing::info!("Wrote the failure report into: {}", dir.display()),
+            Some(Err(e)) => tracing::warn!("Failed to write the failure report: {e}"),
+            None => {}
+        }
+    }
+
+    /// Write the report reproducing the failed run into the `dir`. The protobuf files are patched
+    /// again, keeping the ones the `error` mentions, or all of them if it mentions none.
+    fn write_failure_report(&self, dir: &path::Path, error: &Error) -> Result<(), Error> {
+        let failure = failure_report::Failure::new(error);
+        let config = self.effective_config_for(dir).snapshot()?;
+        let stderr = error.protoc_stderr();
+
+        let tempdir = create_temp_working_dir(&self.temp_dir).map_err(Error::MkTempDir)?;
+        let exclusions = exclude::Exclusions::new(&self.exclude)?
+            .gitignore(self.respect_gitignore)
+            .follow_symlinks(self.follow_symlinks);
+        let patched_dir = tempdir.path().join("protos");
+        // The patching itself may be what failed, leaving no patched files to report.
+        let protos = crate::patch_protos_with_progress(
+            &self.sources,
+            &exclusions,
+            &patched_dir,
+            self.target_syntax,
+            self.allow_unknown_editions,
+            &indicatif::ProgressBar::hidden(),
+        )
+        .map(|patched| {
+            patched
+                .protos
+                .into_iter()
+                .filter_map(|proto| {
+                    let name = proto.destination.strip_prefix(&patched_dir).ok()?;
+                    let mentioned = failure.mentions(&portable::to_slash(name))
+                        || failure.mentions(&proto.source.display().to_string());
+
+                    Some((name.to_path_buf(), proto.destination.clone(), mentioned))
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+        let mentioned = protos.iter().any(|(_, _, mentioned)| *mentioned);
+        let protos = protos
+            .into_iter()
+            .filter(|(_, _, is_mentioned)| *is_mentioned || !mentioned)
+            .map(|(name, file, _)| (name, file))
+            .collect();
+
+        failure_report::write(
+            dir,
+            &failure_report::Report {
+                config: &config,
+                failure,
+                protoc_stderr: stderr.as_deref(),
+                protos,
+            },
+        )?;
+
+        Ok(())
+    }
+
+    /// Record the `result` of the run started at `started_at` in the [`Builder::history_file`], if
+    /// any.
+    fn record(&self, started_at: time::SystemTime, result: &Result<Report, Error>) {
+        let Some(history_file) = self.history_file.as_ref().filter(|_| !self.dry_run) else {
+            return;
+        };
+
+        let duration = started_at.elapsed().unwrap_or_default();
+
+        let inputs = match self.hash_inputs() {
+            Ok(inputs) => cache::hash_all(&inputs),
+            Err(e) => {
+                tracing::warn!("Failed to hash the protobuf files for the history: {e}");
+
+                return;
+            }
+        };
+        let outcome = match result {
+            Ok(report) if report.cached => history::Outcome::Cached,
+            Ok(_) => history::Outcome::Generated,
+            Err(e) => history::Outcome::Failed {
+                error: e.to_string(),
+            },
+        };
+        let compile_time = result.as_ref().ok().and_then(|report| {
+            report
+                .targets
+                .iter()
+                .filter_map(|target| target.compile_time)
+                .reduce(|total, time| total + time)
+        });
+        let millis =
+            |duration: time::Duration| u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+
+        let run = history::Run {
+            started_at: started_at
+                .duration_since(time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            inputs,
+            duration_ms: millis(duration),
+            compile_ms: compile_time.map(millis),
+            outcome,
+        };
+
+        if let Err(e) = history::record(history_file, run, self.history_limit) {
+            tracing::warn!("Failed to record the run in the history: {e}");
+        }
+    }
+
+    fn hash_inputs(&self) -> Result<collections::BTreeMap<path::PathBuf, String>, cache::Error> {
+        let is_input = |path: &path::Path| {
+            path.extension().is_some_and(|ext| ext == "proto")
+                || self.license_header.as_deref() == Some(path)
+        };
+
+        cache::hash_files(
+            &self
+                .sources
+                .iter()
+                .chain(&self.include_paths)
+                .chain(&self.descriptor_sets_in)
+                .chain(&self.license_header)
+                .map(path::PathBuf::as_path)
+                .collect::<Vec<_>>(),
+            is_input,
+        )
+    }
+
+    /// The effective configuration the cached code is generated with, with the paths relative to
+    /// the current directory. The options that do not change the generated code, like the caches
+    /// themselves, are left out of it.
+    fn cache_config(&self) -> Result<String, Error> {
+        let config = config::Config {
+            temp_dir: None,
+            incremental: None,
+            cache_file: None,
+            cache_dir: None,
+            shared_cache: vec![],
+            history: None,
+            history_file: None,
+            history_limit: None,
+            failure_report: None,
+            failure_report_dir: None,
+            ..self.effective_config_for(path::Path::new("."))
+        };
+
+        Ok(config.snapshot()?)
+    }
+
+    /// The key of the code generated by this builder in the stores, hashing the effective
+    /// configuration, the `protoc` binary and the protobuf files with the paths relative to the
+    /// current directory, so it is shared by the runs in other checkouts.
+    fn store_key(
+        &self,
+        protoc: &str,
+        inputs: &collections::BTreeMap<path::PathBuf, String>,
+    ) -> Result<String, Error> {
+        let inputs = inputs
+            .iter()
+            .map(|(path, hash)| (config::relative(path, path::Path::new(".")), hash.clone()))
+            .collect();
+
+        Ok(cache::key(&self.cache_config()?, protoc, &inputs))
+    }
+
+    /// The report of a run skipping the generation, as the outputs are up to date or restored.
+    fn cached_report(&self, fingerprint: &cache::Fingerprint) -> Report {
+        if self.cargo_integration {
+            self.rerun_if_changed(fingerprint.inputs.keys())
+                .iter()
+                .for_each(|directive| println!("{directive}"));
+        }
+
+        Report {
+            protos: vec![],
+            targets: vec![],
+            cached: true,
+            retries: 0,
+            unreachable: None,
+            versions: vec![],
+        }
+    }
+
+    fn build_cached(&self) -> Result<Report, Error> {
+        match self.lookup()? {
+            Lookup::Cached(report) => Ok(report),
+            Lookup::Run(targets, pending) => {
+                let report = self.run(targets)?;
+                self.store(pending, report)
+            }
+        }
+    }
+
+    /// Look the code up in the cache file of the previous run and the stores, before running the
+    /// pipeline.
+    fn lookup(&self) -> Result<Lookup, Error> {
+        let targets = self.resolve_targets()?;
+
+        let stores = self
+            .cache_dir
+            .iter()
+            .chain(&self.shared_caches)
+            .map(path::PathBuf::as_path)
+            .collect::<Vec<_>>();
+        if self.dry_run || (self.cache_file.is_none() && stores.is_empty()) {
+            return Ok(Lookup::Run(targets, None));
+        }
+
+        let inputs = self.hash_inputs()?;
+        let config = cache::hash_config(&self.cache_config()?);
+        let protoc = cache::hash_protoc(&sandbox::protoc());
+
+        let outputs = targets
+            .iter()
+            .flat_map(ResolvedTarget::outputs)
+            .chain(self.file_descriptor_set_path.as_deref())
+            .map(path::Path::to_path_buf)
+            .collect::<Vec<_>>();
+
+        let fingerprint = cache::Fingerprint {
+            config,
+            protoc,
+            inputs,
+            outputs: hash_outputs(&outputs)?,
+        };
+
+        let previous = match &self.cache_file {
+            Some(cache_file) => cache::load(cache_file)?,
+            None => None,
+        };
+        if previous.as_ref() == Some(&fingerprint) {
+            tracing::info!("Nothing changed since the previous run, skipping the generation");
+
+            return Ok(Lookup::Cached(self.cached_report(&fingerprint)));
+        }
+
+        // The outputs nested in another one are stored along with it.
+        let stored_outputs = outputs
+            .iter()
+            .filter(|output| {
+                !outputs
+                    .iter()
+                    .any(|other| other != *output && output.starts_with(other))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        let key = self.store_key(&fingerprint.protoc, &fingerprint.inputs)?;
+
+        let pending = Pending {
+            fingerprint,
+            outputs,
+            stored_outputs,
+            key,
+        };
+
+        match cache::find(&stores, &pending.key) {
+            Some(entry) => {
+                tracing::info!("Restoring the generated code from `{}`", entry.display());
+                cache::restore(&entry, &pending.stored_outputs(), self.force)?;
+
+                let report = self.cached_report(&pending.fingerprint);
+
+                Ok(Lookup::Cached(self.store(Some(pending), report)?))
+            }
+            None => Ok(Lookup::Run(targets, Some(pending))),
+        }
+    }
+
+    /// Store the code generated by the run in the [`Builder::cache_dir`] and its fingerprint in
+    /// the cache file.
+    fn store(&self, pending: Option<Pending>, report: Report) -> Result<Report, Error> {
+        let Some(mut pending) = pending else {
+            return Ok(report);
+        };
+
+        if !report.cached {
+            let saved = self
+                .cache_dir
+                .as_ref()
+                .map(|cache_dir| cache::save(cache_dir, &pending.key, &pending.stored_outputs()));
+            if let Some(Err(e)) = saved {
+                tracing::warn!("Failed to store the generated code in the cache: {e}");
+            }
+        }
+
+        pending.fingerprint.outputs = hash_outputs(&pending.outputs)?;
+        if let Some(cache_file) = &self.cache_file {
+            cache::store(cache_file, &pending.fingerprint)?;
+        }
+
+        Ok(report)
+    }
+
+    /// The tonic dependency of the generated crates.
+    fn tonic(&self) -> verify::Tonic {
+        match (self.messages_only, self.no_transport) {
+            (true, _) => verify::Tonic::Disabled,
+            (false, true) => verify::Tonic::Codegen,
+            (false, false) => verify::Tonic::Transport,
+        }
+    }
+
+    /// The serde support of the generated crates.
+    fn serde_support(&self) -> verify::Serde {
+        if self.pbjson {
+            verify::Serde::Pbjson
+        } else if self.serde.is_empty() {
+            verify::Serde::Disabled
+        } else if self.bytes.is_empty() {
+            verify::Serde::Derive
+        } else {
+            verify::Serde::DeriveBytes
+        }
+    }
+
+    fn run(&self, targets: Vec<ResolvedTarget>) -> Result<Report, Error> {
+        let mut prepared = self.prepare(targets)?;
+        let fds = self.compile(&mut prepared)?;
+
+        self.finish(prepared, fds)
+    }
+
+    /// Stage the outputs of the `targets` and patch the protobuf files, ready to be compiled.
+    fn prepare(&self, targets: Vec<ResolvedTarget>) -> Result<Prepared, Error> {
+        match self.previous_descriptor_set() {
+            None if self.strict => return Err(Error::StrictWithoutDescriptorSet),
+            Some(path) if self.strict && !self.allow_missing_descriptor_set && !path.exists() => {
+                return Err(Error::MissingPreviousDescriptorSet(path.to_path_buf()));
+            }
+            _ => {}
+        }
+        if self.messages_only {
+            validate_messages_only(&targets, &self.service_sides)?;
+        }
+        if let Some(target) = targets.iter().find(|target| {
+            target.msrv.is_some()
+                && !target.verify_build
+                && !target.verify_feature_matrix
+                && self.layout != modgen::Layout::Crate
+        }) {
+            return Err(Error::MsrvWithoutCrate(target.name.clone()));
+        }
+        if self.execution.is_some() {
+            execution::validate(self.offline, self.sandbox != sandbox::Sandbox::Off)?;
+        }
+        if self.registry.is_some() && self.offline {
+            return Err(registry::Error::Offline.into());
+        }
+        if self.pbjson {
+            pbjson::validate(
+                !self.serde.is_empty(),
+                targets.iter().any(|target| target.with_well_known_types),
+            )?;
+        }
+        if !self.string_bytes.is_empty() {
+            strings::validate(!self.serde.is_empty(), self.pbjson)?;
+        }
+        if let Some((_, generated)) = [
+            (self.conformance_tests, "conformance tests"),
+            (self.recording_client, "recording clients"),
+            (self.shadow_client, "shadow clients"),
+        ]
+        .into_iter()
+        .find(|(enabled, _)| self.no_transport && *enabled)
+        {
+            return Err(Error::NoTransport(generated));
+        }
+        if let Some(name) = &self.root_file {
+            root_file::validate(name, &self.root_attributes, self.layout)?;
+        }
+        root_file::validate_attributes(&self.crate_metadata.attributes)?;
+        let license = self
+            .license_header
+            .as_ref()
+            .map(|path| fs::read_to_string(path).map_err(|e| Error::LicenseHeader(e, path.clone())))
+            .transpose()?;
+        let banner = self
+            .banner
+            .as_ref()
+            .map(|banner| banner.replace("{version}", env!("CARGO_PKG_VERSION")));
+        let header = modgen::Header::new(license.as_deref(), banner.as_deref());
+
+        let exclusions = exclude::Exclusions::new(&self.exclude)?
+            .gitignore(self.respect_gitignore)
+            .follow_symlinks(self.follow_symlinks);
+        let tempdir = create_temp_working_dir(&self.temp_dir).map_err(Error::MkTempDir)?;
+
+        tracing::debug!(
+            "Created a temporary working directory: {}",
+            tempdir.path().display(),
+        );
+
+        let owned = targets
+            .iter()
+            .flat_map(|target| {
+                self.owners
+                    .iter()
+                    .map(|(package, owners)| codeowners::Entry {
+                        dir: modgen::layout_package_dir(
+                            &target.output,
+                            &target.package_outputs,
+                            &self.naming.package(package),
+                            self.layout,
+                        ),
+                        owners: owners.clone(),
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        let targets = targets
+            .into_iter()
+            .map(|target| -> Result<_, Error> {
+                let effective_config = match self.effective_config {
+                    true => Some(self.effective_config_for(&target.output).snapshot()?),
+                    false => None,
+                };
+
+                let manifests = match self.layout {
+                    modgen::Layout::Crate => target
+                        .outputs()
+                        .map(|output| {
+                            manifest::render(
+                                output,
+                                target.msrv.as_ref(),
+                                &self.crate_metadata,
+                                self.tonic(),
+                                self.serde_support(),
+                            )
+                        })
+                        .collect::<Result<_, _>>()?,
+                    _ => vec![],
+                };
+
+                Ok(ResolvedTarget {
+                    effective_config,
+                    manifests,
+                    header: header.clone(),
+                    ..target
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // The code is generated next to the outputs, which are only replaced once every target
+        // succeeded, so a failed run leaves the previous code intact.
+        let mut staged = vec![];
+        let targets = if self.dry_run {
+            targets
+        } else {
+            targets
+                .into_iter()
+                .map(|target| target.stage(&mut staged, self.force))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+
+        let progress = self.progress_bar();
+
+        progress.set_message("Patching");
+        let patched_dir = tempdir.path().join("protos");
+        let patched = crate::patch_protos_with_progress(
+            &self.sources,
+            &exclusions,
+            &patched_dir,
+            self.target_syntax,
+            self.allow_unknown_editions,
+            &progress,
+        )?;
+
+        if self.cargo_integration {
+            self.rerun_if_changed(patched.protos.iter().map(|proto| &proto.source))
+                .iter()
+                .for_each(|directive| println!("{directive}"));
+        }
+
+        let patched_files = patched
+            .destinations()
+            .map(path::Path::to_path_buf)
+            .collect::<Vec<_>>();
+        let relative_files = patched_files
+            .iter()
+            .filter_map(|file| file.strip_prefix(&patched_dir).ok())
+            .map(path::Path::to_path_buf)
+            .collect::<Vec<_>>();
+
+        if self.strict {
+            patched
+                .protos
+                .iter()
+                .filter_map(|proto| match &proto.outcome {
+                    patcher::Outcome::Replaced(edition) => Some((proto, edition)),
+                    patcher::Outcome::Untouched => None,
+                })
+                .for_each(|(proto, edition)| {
+                    tracing::warn!(
+                        "Replaced the edition `{edition}` of `{}` with `{}`",
+                        proto.source.display(),
+                        self.target_syntax.declaration(),
+                    );
+                });
+
+            let unresolved =
+                imports::find_unresolved(&patched_dir, &relative_files, &self.include_paths)?;
+            if !unresolved.is_empty() {
+                return Err(Error::UnresolvedImports(unresolved));
+            }
+        }
+
+        let cycles = imports::find_cycles(&patched_dir, &relative_files)?;
+        if self.fail_on_import_cycles && !cycles.is_empty() {
+            return Err(Error::ImportCycles(cycles));
+        }
+        cycles
+            .iter()
+            .for_each(|cycle| tracing::warn!("Found an import cycle: {cycle}"));
+
+        let mut includes = self.include_paths.clone();
+        includes.push(patched_dir.clone());
+
+        let descriptor_set_path = self
+            .file_descriptor_set_path
+            .as_ref()
+            .filter(|_| !self.dry_run)
+            .map(|path| {
+                staged
+                    .iter()
+                    .find_map(|staged| staged.stage(path))
+                    .unwrap_or_else(|| path.clone())
+            });
+
+        // The descriptor set of the previous run is read before it is replaced by the new one.
+        let previous = match self.previous_descriptor_set() {
+            Some(path) if self.strict && path.exists() => Some(descriptor::read(path)?),
+            _ => None,
+        };
+
+        let descriptor_sets = self
+            .descriptor_sets_in
+            .iter()
+            .map(|path| Ok((path.clone(), descriptor::read(path)?)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Error::MergeDescriptors)?;
+        let protoc_args = match self.descriptor_sets_in.is_empty() {
+            true => vec![],
+            false => vec![
+                descriptor::set_in_arg(&self.descriptor_sets_in)
+                    .map_err(Error::MergeDescriptors)?,
+            ],
+        };
+
+        let compiled_descriptor_path = descriptor_set_path
+            .clone()
+            .unwrap_or_else(|| tempdir.path().join("descriptor-set.bin"));
+
+        Ok(Prepared {
+            targets,
+            tempdir,
+            staged,
+            owned,
+            progress,
+            patched,
+            patched_dir,
+            patched_files,
+            includes,
+            descriptor_set_path,
+            compiled_descriptor_path,
+            previous,
+            descriptor_sets,
+            protoc_args,
+            retries: 0,
+        })
+    }
+
+    /// Compile the patched protobuf files with `protoc`, retrying the transient failures.
+    fn compile(
+        &self,
+        prepared: &mut Prepared,
+    ) -> Result<tonic_prost_build::FileDescriptorSet, Error> {
+        let mut prost_config = tonic_prost_build::Config::new();
+        prost_config.file_descriptor_set_path(&prepared.compiled_descriptor_path);
+        prepared.protoc_args.iter().for_each(|arg| {
+            prost_config.protoc_arg(arg);
+        });
+
+        prepared.progress.set_length(1);
+        prepared.progress.set_position(0);
+        prepared.progress.set_message("Compiling");
+
+        let fds = retry::run(
+            &self.retry,
+            &mut prepared.retries,
+            Error::is_transient,
+            |_| {
+                if let Some(backend) = &self.execution {
+                    return Ok(execution::load_fds(
+                        backend.as_ref(),
+                        &prepared.tempdir.path().join("input-root"),
+                        &prepared.patched_files,
+                        &prepared.includes,
+                        &self.descriptor_sets_in,
+                        Some(&prepared.compiled_descriptor_path),
+                    )?);
+                }
+                if self.sandbox == sandbox::Sandbox::Off {
+                    return prost_config
+                        .load_fds(&prepared.patched_files, &prepared.includes)
+                        .map_err(Error::CompileProto);
+                }
+
+                Ok(sandbox::load_fds(
+                    self.sandbox,
+                    prepared.tempdir.path(),
+                    &prepared.patched_files,
+                    &prepared.includes,
+                    &prepared.protoc_args,
+                    Some(&prepared.compiled_descriptor_path),
+                )?)
+            },
+        )
+        .map_err(|e| e.relocate(&prepared.patched))?;
+        prepared.progress.inc(1);
+
+        Ok(fds)
+    }
+
+    /// Generate the code of every target from the compiled `fds` and replace the outputs.
+    fn finish(
+        &self,
+        prepared: Prepared,
+        fds: tonic_prost_build::FileDescriptorSet,
+    ) -> Result<Report, Error> {
+        let Prepared {
+            targets,
+            tempdir,
+            staged,
+            owned,
+            progress,
+            patched,
+            patched_dir,
+            includes,
+            descriptor_set_path,
+            compiled_descriptor_path,
+            previous,
+            descriptor_sets,
+            mut retries,
+            ..
+        } = prepared;
+
+        let mut fds = descriptor::merge(fds, &descriptor_sets).map_err(Error::MergeDescriptors)?;
+        // The custom options are read before the descriptor set is rewritten without them.
+        let internal_messages = internal::resolve(
+            &fds,
+            &self.internal_messages,
+            &iter::once(compiled_descriptor_path.as_path())
+                .chain(descriptor_sets.iter().map(|(path, _)| path.as_path()))
+                .collect::<Vec<_>>(),
+        )?;
+        let paths = reproducible::machine_paths(
+            self.sources
+                .iter()
+                .chain(&includes)
+                .map(path::PathBuf::as_path),
+        );
+        let normalized = reproducible::normalize_fds(&mut fds, &paths);
+
+        if let Some(path) = &descriptor_set_path {
+            if !descriptor_sets.is_empty() || normalized {
+                descriptor::write(path, &fds)?;
+            }
+
+            let remap = descriptor::Remap {
+                strip_prefix: self.descriptor_strip_prefix.clone(),
+                root: self.descriptor_root.clone(),
+            };
+
+            if !remap.is_empty() {
+                descriptor::remap(path, &remap)?;
+                tracing::debug!("Remapped the file names in the file descriptor set");
+            }
+        }
+
+        if let Some(previous) = &previous {
+            let removed = reserved::find_unreserved(previous, &fds);
+            if !removed.is_empty() {
+                return Err(Error::UnreservedFields(removed));
+            }
+        }
+
+        let versions = targets
+            .iter()
+            .filter_map(|target| Some((target, target.semver_baseline.as_deref()?)))
+            .map(|(target, baseline)| -> Result<_, Error> {
+                let suggestion = semver::suggest(baseline, &fds)?;
+                suggestion
+                    .changes
+                    .iter()
+                    .for_each(|change| tracing::debug!("Schema change: {change}"));
+                tracing::info!(
+                    "The schema of the `{}` target needs a {} release after {}, version {}",
+                    target.name,
+                    suggestion.bump,
+                    suggestion.published,
+                    suggestion.next,
+                );
+
+                if let Some(version) = &self.crate_metadata.version {
+                    suggestion.enforce(version)?;
+                }
+
+                Ok((target.name.clone(), suggestion))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        size_guard::validate(&fds, &self.message_size_limits)?;
+        events::validate(&fds, &self.events)?;
+        rows::validate(&fds, &self.row_models)?;
+        defaults::validate(&fds, &self.field_defaults)?;
+        encoded_size::validate(&fds, &self.encoded_size_reports)?;
+        split::validate(&fds, &self.method_groups)?;
+        per_service::validate(&fds, &self.service_sides)?;
+        redact::validate(&fds, &self.sensitive_fields)?;
+        naming::validate(&fds, &self.naming)?;
+        targets.iter().try_for_each(|target| {
+            serialize::validate(
+                &fds,
+                &self.serde,
+                target.with_well_known_types,
+                &target.extern_paths,
+            )
+        })?;
+
+        if let Some(registry) = &self.registry {
+            let schemas = patched
+                .destinations()
+                .filter_map(|file| {
+                    let name = file.strip_prefix(&patched_dir).ok()?;
+                    Some((portable::to_slash(name), file.to_path_buf()))
+                })
+                .collect::<Vec<_>>();
+
+            registry::check(registry, &fds, &schemas, tempdir.path())?;
+        }
+
+        let unreachable = if self.entry_services.is_empty() {
+            None
+        } else {
+            let files = patched
+                .destinations()
+                .filter_map(|file| file.strip_prefix(&patched_dir).ok())
+                .map(portable::to_slash)
+                .collect::<Vec<_>>();
+
+            Some(reachability::unreachable(
+                &fds,
+                &self.entry_services,
+                &files,
+            )?)
+        };
+
+        progress.set_length(targets.len() as u64);
+        progress.set_position(0);
+        progress.set_message("Generating");
+
+        let mut targets = targets
+            .into_iter()
+            .map(|target| ResolvedTarget {
+                internal_messages: internal_messages.clone(),
+                ..target
+            })
+            .map(|target| {
+                let report = target.generate(
+                    &fds,
+                    tempdir.path(),
+                    &paths,
+                    descriptor_set_path.as_deref(),
+                    self,
+                    &mut retries,
+                );
+                progress.inc(1);
+
+                report
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        progress.finish_and_clear();
+
+        targets.iter_mut().for_each(|target| {
+            let unstage = |path: &path::Path| {
+                staged
+                    .iter()
+                    .find_map(|staged| staged.unstage(path))
+                    .unwrap_or_else(|| path.to_path_buf())
+            };
+
+            target.output = unstage(&target.output);
+            target
+                .files
+                .iter_mut()
+                .for_each(|file| *file = unstage(file));
+        });
+        staging::commit(staged)?;
+
+        if let Some(file) = self.codeowners.as_ref().filter(|_| !self.dry_run) {
+            let owned = owned
+                .into_iter()
+                .filter(|entry| {
+                    targets
+                        .iter()
+                        .flat_map(|target| &target.files)
+                        .any(|file| file.starts_with(&entry.dir))
+                })
+                .collect::<Vec<_>>();
+
+            codeowners::update(file, &owned)?;
+            tracing::info!("Updated the code owners in: {}", file.display());
+        }
+
+        Ok(Report {
+            protos: patched
+                .protos
+                .into_iter()
+                .map(|proto| ProtoReport {
+                    source: proto.source,
+                    outcome: proto.outcome,
+                })
+                .collect(),
+            targets,
+            cached: false,
+            retries,
+            unreachable,
+            versions,
+        })
+    }
+
+    /// The descriptor set of the previous run, checked for the removed fields in the strict mode.
+    fn previous_descriptor_set(&self) -> Option<&path::Path> {
+        self.previous_descriptor_set
+            .as_deref()
+            .or(self.file_descriptor_set_path.as_deref())
+    }
+
+    fn progress_bar(&self) -> indicatif::ProgressBar {
+        if !self.progress || !io::stdout().is_terminal() {
+            return indicatif::ProgressBar::hidden();
+        }
+
+        let style = indicatif::ProgressStyle::with_template("{msg:>10} [{bar:40}] {pos}/{len}")
+            .unwrap_or_else(|_| indicatif::ProgressStyle::default_bar())
+            .progress_chars("=> ");
+
+        indicatif::ProgressBar::new(0).with_style(style)
+    }
+
+    /// Generate the code into a temporary directory and compare it with the existing output
+    /// directories and file descriptor set, returning every file that differs. Nothing outside of
+    /// the temporary directory is written.
+    pub fn check(&self) -> Result<Vec<check::Difference>, Error> {
+        let tempdir = create_temp_working_dir(&self.temp_dir).map_err(Error::MkTempDir)?;
+        let expected_dir = tempdir.path().join("expected");
+
+        let mut pairs = vec![];
+        let mut redirect = |actual: path::PathBuf| {
+            let expected = expected_dir.join(pairs.len().to_string());
+            pairs.push((expected.clone(), actual));
+
+            expected
+        };
+
+        let builder = Self {
+            output: redirect(self.output.clone()),
+            package_outputs: self
+                .package_outputs
+                .iter()
+                .map(|package| package.clone().map_output(&mut redirect))
+                .collect(),
+            targets: self
+                .targets
+                .iter()
+                .map(|target| Target {
+                    output: redirect(target.output.clone()),
+                    package_outputs: target
+                        .package_outputs
+                        .iter()
+                        .map(|package| package.clone().map_output(&mut redirect))
+                        .collect(),
+                    verify_build: Some(false),
+                    verify_feature_matrix: Some(false),
+                    ..target.clone()
+                })
+                .collect(),
+            file_descriptor_set_path: self
+                .file_descriptor_set_path
+                .as_ref()
+                .map(|_| tempdir.path().join("descriptor.bin")),
+            previous_descriptor_set: self.previous_descriptor_set().map(path::Path::to_path_buf),
+            temp_dir: Some(tempdir.path().to_path_buf()),
+            verify_build: false,
+            verify_feature_matrix: false,
+            cargo_integration: false,
+            dry_run: false,
+            codeowners: None,
+            cache_file: None,
+            cache_dir: None,
+            shared_caches: vec![],
+            history_file: None,
+            failure_report: None,
+            registry: None,
+            effective_config: false,
+            ..self.clone()
+        };
+
+        builder.build()?;
+
+        // The snapshots describe the checked outputs rather than the redirected ones.
+        if self.effective_config {
+            self.resolve_targets()?
+                .iter()
+                .filter_map(|target| {
+                    pairs
+                        .iter()
+                        .find(|(_, actual)| *actual == target.output)
+                        .map(|(expected, _)| (expected, target))
+                })
+                .filter(|(expected, _)| expected.exists())
+                .try_for_each(|(expected, target)| -> Result<(), Error> {
+                    let path = expected.join(config::EFFECTIVE_PATH);
+                    fs::write(&path, self.effective_config_for(&target.output).snapshot()?)
+                        .map_err(|e| Error::WriteEffectiveConfig(e, path))
+                })?;
+        }
+
+        let mut differences = vec![];
+
+        if let Some(actual) = &self.file_descriptor_set_path {
+            differences.extend(check::compare_file(
+                &tempdir.path().join("descriptor.bin"),
+                actual,
+            )?);
+        }
+
+        pairs
+            .iter()
+            // Outputs that were not generated belong to the targets the default one is ignored for.
+            .filter(|(expected, _)| expected.exists())
+            .try_for_each(|(expected, actual)| -> Result<(), Error> {
+                differences.extend(check::compare_dir(expected, actual)?);
+
+                Ok(())
+            })?;
+
+        Ok(differences)
+    }
+
+    fn rerun_if_changed<'a>(
+        &'a self,
+        protos: impl IntoIterator<Item = &'a path::PathBuf>,
+    ) -> Vec<String> {
+        self.sources
+            .iter()
+            .chain(&self.include_paths)
+            .chain(protos)
+            .map(|path| format!("cargo:rerun-if-changed={}", path.display()))
+            .collect()
+    }
+
+    /// The configuration file reproducing this builder, with the paths relative to the `output`.
+    fn effective_config_for(&self, output: &path::Path) -> config::Config {
+        let (output_dir, package_output, targets) = match self.targets.is_empty() {
+            true => (
+                Some(self.output.clone()),
+                self.package_outputs.clone(),
+                vec![],
+            ),
+            false => (
+                None,
+                vec![],
+                self.targets.iter().map(Target::to_config).collect(),
+            ),
+        };
+
+        config::Config {
+            build_client: Some(self.build_client),
+            build_server: Some(self.build_server),
+            messages_only: Some(self.messages_only),
+            no_transport: Some(self.no_transport),
+            with_well_known_types: Some(self.with_well_known_types),
+            include_path: self.include_paths.clone(),
+            output_layout: Some(self.layout),
+            descriptor_tree: Some(self.descriptor_tree),
+            module_visibility: Some(self.visibilities.default),
+            package_visibility: self.visibilities.packages.clone(),
+            root_file: self.root_file.clone(),
+            root_attribute: self.root_attributes.clone(),
+            crate_metadata: Some(self.crate_metadata.clone())
+                .filter(|metadata| *metadata != manifest::Metadata::default()),
+            banner: self.banner.clone(),
+            license_header: self.license_header.clone(),
+            rustfmt: Some(self.rustfmt),
+            rustfmt_path: self.rustfmt_path.clone(),
+            rustfmt_edition: Some(self.rustfmt_edition),
+            newline: self.newline,
+            output: output_dir,
+            temp_dir: self.temp_dir.clone(),
+            with_file_descriptor_set: self.file_descriptor_set_path.clone(),
+            descriptor_strip_prefix: self.descriptor_strip_prefix.clone(),
+            descriptor_root: self.descriptor_root.clone(),
+            descriptor_set_in: self.descriptor_sets_in.clone(),
+            verify_build: Some(self.verify_build),
+            verify_feature_matrix: Some(self.verify_feature_matrix),
+            msrv: self.msrv.clone(),
+            semver_baseline: self.semver_baseline.clone(),
+            reproducible: Some(self.reproducible),
+            type_attribute: to_attributes(&self.type_attributes),
+            field_attribute: to_attributes(&self.field_attributes),
+            attributes: self.attributes.clone(),
+            extern_path: to_extern_paths(&self.extern_paths),
+            package_output,
+            codeowners: self.codeowners.clone(),
+            owners: self.owners.clone(),
+            incremental: Some(self.cache_file.is_some()),
+            cache_file: self.cache_file.clone(),
+            cache_dir: self.cache_dir.clone(),
+            shared_cache: self.shared_caches.clone(),
+            history: Some(self.history_file.is_some()),
+            history_file: self.history_file.clone(),
+            history_limit: Some(self.history_limit),
+            failure_report: Some(self.failure_report.is_some()),
+            failure_report_dir: self.failure_report.clone(),
+            retries: Some(self.retry.retries),
+            retry_backoff: Some(u64::try_from(self.retry.backoff.as_millis()).unwrap_or(u64::MAX)),
+            sandbox_protoc: Some(self.sandbox),
+            remote_executor: self
+                .execution
+                .as_ref()
+                .and_then(|backend| backend.command())
+                .map(<[String]>::to_vec)
+                .unwrap_or_default(),
+            target_syntax: Some(self.target_syntax),
+            entry_service: self.entry_services.clone(),
+            internal_message: self.internal_messages.clone(),
+            sensitive_field: self.sensitive_fields.clone(),
+            serde: self.serde.clone(),
+            pbjson: Some(self.pbjson),
+            bytes: self.bytes.clone(),
+            btree_map: self.btree_map.clone(),
+            string_bytes: self.string_bytes.clone(),
+            fail_on_import_cycles: Some(self.fail_on_import_cycles),
+            strict: Some(self.strict),
+            allow_missing_descriptor_set: Some(self.allow_missing_descriptor_set),
+            offline: Some(self.offline),
+            registry_url: self.registry.as_ref().map(|registry| registry.url.clone()),
+            registry_api: self.registry.as_ref().map(|registry| registry.api),
+            allow_unknown_editions: Some(self.allow_unknown_editions),
+            default_stubs: Some(self.default_stubs),
+            reexports: Some(self.reexports),
+            descriptor_endpoint: Some(self.descriptor_endpoint),
+            wire_compat_tests: self.wire_compat_tests.clone(),
+            effective_config: Some(self.effective_config),
+            conformance_tests: Some(self.conformance_tests),
+            recording_client: Some(self.recording_client),
+            shadow_client: Some(self.shadow_client),
+            max_message_size: self.message_size_limits.clone(),
+            events: self.events.clone(),
+            row_model: self.row_models.clone(),
+            field_default: self.field_defaults.clone(),
+            encoded_size_report: self.encoded_size_reports.clone(),
+            split_trait: self.method_groups.clone(),
+            service_sides: self.service_sides.clone(),
+            strip_package_prefix: self.naming.strip_prefixes.clone(),
+            rename_module: self.naming.renames.clone(),
+            exclude: self.exclude.clone(),
+            respect_gitignore: Some(self.respect_gitignore),
+            follow_symlinks: Some(self.follow_symlinks),
+            source: self.sources.clone(),
+            targets,
+        }
+        .relative_to(output)
+    }
+
+    fn resolve_targets(&self) -> Result<Vec<ResolvedTarget>, Error> {
+        let targets = if self.targets.is_empty() {
+            vec![ResolvedTarget {
+                name: DEFAULT_TARGET.to_string(),
+                output: self.output.clone(),
+                build_client: self.build_client,
+                build_server: self.build_server,
+                with_well_known_types: self.with_well_known_types,
+                verify_build: self.verify_build,
+                verify_feature_matrix: self.verify_feature_matrix,
+                msrv: self.msrv.clone(),
+                semver_baseline: self.semver_baseline.clone(),
+                type_attributes: self.type_attributes.clone(),
+                field_attributes: self.field_attributes.clone(),
+                extern_paths: self.extern_paths.clone(),
+                package_outputs: self.package_outputs.clone(),
+                effective_config: None,
+                manifests: vec![],
+                header: modgen::Header::default(),
+                internal_messages: vec![],
+            }]
+        } else {
+            self.targets
+                .iter()
+                .map(|target| target.resolve(self))
+                .collect()
+        };
+
+        targets.iter().enumerate().try_for_each(|(idx, target)| {
+            let mut components = path::Path::new(&target.name).components();
+            let is_valid = matches!(
+                (components.next(), components.next()),
+                (Some(path::Component::Normal(_)), None)
+            ) && !targets[..idx].iter().any(|other| other.name == target.name);
+
+            if is_valid {
+                Ok(())
+            } else {
+                Err(Error::InvalidTargetName(target.name.clone()))
+            }
+        })?;
+
+        // The packages are placed into their outputs once their modules are renamed.
+        let targets = targets
+            .into_iter()
+            .map(|target| ResolvedTarget {
+                package_outputs: target
+                    .package_outputs
+                    .into_iter()
+                    .map(|package| {
+                        package.map_package(|package| {
+                            self.naming
+                                .package(&package.join("."))
+                                .split('.')
+                                .map(str::to_string)
+                                .collect()
+                        })
+                    })
+                    .collect(),
+                ..target
+            })
+            .collect();
+
+        Ok(targets)
+    }
+}
+
+impl Target {
+    /// Create a target named `name` writing the generated code into the `output` directory. The
+    /// name has to be unique and can not contain path separators.
+    pub fn new(name: impl Into<String>, output: impl Into<path::PathBuf>) -> Self {
+        Self {
+            name: name.into(),
+            output: output.into(),
+            build_client: None,
+            build_server: None,
+            with_well_known_types: None,
+            verify_build: None,
+            verify_feature_matrix: None,
+            msrv: None,
+            semver_baseline: None,
+            type_attributes: vec![],
+            field_attributes: vec![],
+            extern_paths: vec![],
+            package_outputs: vec![],
+        }
+    }
+
+    pub fn build_client(mut self, enable: bool) -> Self {
+        self.build_client = Some(enable);
+        self
+    }
+
+    pub fn build_server(mut self, enable: bool) -> Self {
+        self.build_server = Some(enable);
+        self
+    }
+
+    pub fn with_well_known_types(mut self, enable: bool) -> Self {
+        self.with_well_known_types = Some(enable);
+        self
+    }
+
+    pub fn verify_build(mut self, enable: bool) -> Self {
+        self.verify_build = Some(enable);
+        self
+    }
+
+    pub fn verify_feature_matrix(mut self, enable: bool) -> Self {
+        self.verify_feature_matrix = Some(enable);
+        self
+    }
+
+    pub fn msrv(mut self, version: verify::RustVersion) -> Self {
+        self.msrv = Some(version);
+        self
+    }
+
+    pub fn semver_baseline(mut self, baseline: impl Into<path::PathBuf>) -> Self {
+        self.semver_baseline = Some(baseline.into());
+        self
+    }
+
+    /// Add an attribute to the messages and enums matching the protobuf `path` generated for this
+    /// target only.
+    pub fn type_attribute(mut self, path: impl Into<String>, attribute: impl Into<String>) -> Self {
+        self.type_attributes.push((path.into(), attribute.into()));
+        self
+    }
+
+    /// Add an attribute to the fields matching the protobuf `path` generated for this target only.
+    pub fn field_attribute(
+        mut self,
+        path: impl Into<String>,
+        attribute: impl Into<String>,
+    ) -> Self {
+        self.field_attributes.push((path.into(), attribute.into()));
+        self
+    }
+
+    /// Use the existing Rust type at `rust_path` for the protobuf types matching `proto_path`
+    /// instead of generating them for this target.
+    pub fn extern_path(
+        mut self,
+        proto_path: impl Into<String>,
+        rust_path: impl Into<String>,
+    ) -> Self {
+        self.extern_paths
+            .push((proto_path.into(), rust_path.into()));
+        self
+    }
+
+    /// Place the modules of a protobuf package into a separate output directory instead of the
+    /// target output.
+    pub fn package_output(mut self, package: modgen::PackageOutput) -> Self {
+        self.package_outputs.push(package);
+        self
+    }
+
+    fn resolve(&self, builder: &Builder) -> ResolvedTarget {
+        ResolvedTarget {
+            name: self.name.clone(),
+            output: self.output.clone(),
+            build_client: self.build_client.unwrap_or(builder.build_client),
+            build_server: self.build_server.unwrap_or(builder.build_server),
+            with_well_known_types: self
+                .with_well_known_types
+                .unwrap_or(builder.with_well_known_types),
+            verify_build: self.verify_build.unwrap_or(builder.verify_build),
+            verify_feature_matrix: self
+                .verify_feature_matrix
+                .unwrap_or(builder.verify_feature_matrix),
+            msrv: self.msrv.clone().or_else(|| builder.msrv.clone()),
+            semver_baseline: self
+                .semver_baseline
+                .clone()
+                .or_else(|| builder.semver_baseline.clone()),
+            type_attributes: builder
+                .type_attributes
+                .iter()
+                .chain(&self.type_attributes)
+                .cloned()
+                .collect(),
+            field_attributes: builder
+                .field_attributes
+                .iter()
+                .chain(&self.field_attributes)
+                .cloned()
+                .collect(),
+            extern_paths: builder
+                .extern_paths
+                .iter()
+                .chain(&self.extern_paths)
+                .cloned()
+                .collect(),
+            package_outputs: self.package_outputs.clone(),
+            effective_config: None,
+            manifests: vec![],
+            header: modgen::Header::default(),
+            internal_messages: vec![],
+        }
+    }
+
+    fn to_config(&self) -> config::Target {
+        config::Target {
+            name: self.name.clone(),
+            output: self.output.clone(),
+            build_client: self.build_client,
+            build_server: self.build_server,
+            with_well_known_types: self.with_well_known_types,
+            verify_build: self.verify_build,
+            verify_feature_matrix: self.verify_feature_matrix,
+            msrv: self.msrv.clone(),
+            semver_baseline: self.semver_baseline.clone(),
+            type_attribute: to_attributes(&self.type_attributes),
+            field_attribute: to_attributes(&self.field_attributes),
+            extern_path: to_extern_paths(&self.extern_paths),
+            package_output: self.package_outputs.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io, path};
+
+    #[test]
+    fn build_keeps_the_previous_output_on_failure() {
+        let root = tempfile::TempDir::new().expect("Failed to create test directory");
+        let src = root.path().join("proto");
+        let dst = root.path().join("out");
+
+        fs::create_dir_all(&src).expect("Failed to create test source directory");
+        fs::write(
+            src.join("broken.proto"),
+            "syntax = \"proto3\";\nmessage {\n",
+        )
+        .expect("Failed to create a test protobuf file");
+        fs::create_dir_all(&dst).expect("Failed to create test destination directory");
+        fs::write(dst.join(crate::staging::MARKER), "")
+            .expect("Failed to create a test marker file");
+        fs::write(dst.join("mod.rs"), "pub mod crabs;\n")
+            .expect("Failed to create a test module file");
+
+        let err = super::Builder::new(&src).output(&dst).build();
+        assert!(
+            matches!(err, Err(super::Error::CompileProto(_))),
+            "Expected `Err(Error::CompileProto)`, got: `{:?}`",
+            err
+        );
+
+        assert_eq!(
+            fs::read_to_string(dst.join("mod.rs")).ok().as_deref(),
+            Some("pub mod crabs;\n"),
+            "Expected the previous output to be intact",
+        );
+        assert_eq!(
+            fs::read_dir(root.path())
+                .expect("Failed to read test directory")
+                .count(),
+            2,
+            "Expected the staging directory to be removed",
+        );
+    }
+
+    #[test]
+    fn build_refuses_to_replace_unmarked_outputs() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        fs::write(dst.path().join("main.rs"), "fn main() {}\n")
+            .expect("Failed to create a test source file");
+
+        let err = super::Builder::new("./proto").output(dst.path()).build();
+        assert!(
+            matches!(
+                err,
+                Err(super::Error::Staging(crate::staging::Error::Unmarked(_)))
+            ),
+            "Expected `Err(Error::Staging(Unmarked))`, got: `{:?}`",
+            err
+        );
+        assert!(
+            dst.path().join("main.rs").exists(),
+            "Expected the unmarked output to be intact",
+        );
+
+        super::Builder::new("./proto")
+            .output(dst.path())
+            .force(true)
+            .build()
+            .expect("Failed to replace the forced output");
+        assert!(
+            dst.path().join(crate::staging::MARKER).is_file(),
+            "Expected the output to be marked as generated",
+        );
+    }
+
+    #[test]
+    fn build_fails_on_import_cycles() {
+        let src = tempfile::TempDir::new().expect("Failed to create test source directory");
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+
+        [("a.proto", "b.proto"), ("b.proto", "a.proto")]
+            .into_iter()
+            .for_each(|(file, import)| {
+                fs::write(
+                    src.path().join(file),
+                    format!("syntax = \"proto3\";\nimport \"{import}\";\n"),
+                )
+                .expect("Failed to create a test protobuf file")
+            });
+
+        let err = super::Builder::new(src.path())
+            .output(dst.path())
+            .fail_on_import_cycles(true)
+            .build();
+
+        assert!(
+            matches!(&err, Err(super::Error::ImportCycles(cycles)) if cycles.len() == 1),
+            "Expected `Err(Error::ImportCycles)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn build_messages_only_generates_no_service_code() {
+        let src = tempfile::TempDir::new().expect("Failed to create test source directory");
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        fs::write(
+            src.path().join("crabs.proto"),
+            "syntax = \"proto3\";\npackage crabs;\nmessage Crab {}\n\
+            service CrabService { rpc Pinch(Crab) returns (Crab); }\n",
+        )
+        .expect("Failed to create a test protobuf file");
+        let builder = super::Builder::new(src.path())
+            .output(dst.path().join("crabs"))
+            .output_layout(super::modgen::Layout::Crate)
+            .messages_only(true);
+
+        let err = builder.clone().build_server(true).build();
+        assert!(
+            matches!(err, Err(super::Error::MessagesOnly(_))),
+            "Expected `Err(Error::MessagesOnly)`, got: `{:?}`",
+            err
+        );
+
+        builder.build().expect("Failed to build the messages");
+        let read = |file: &str| {
+            fs::read_to_string(dst.path().join("crabs").join(file))
+                .expect("Failed to read a generated file")
+        };
+        assert!(
+            read("src/crabs/mod.rs").contains("pub struct Crab"),
+            "Expected the messages to be generated"
+        );
+        assert!(
+            !read("src/crabs/mod.rs").contains("tonic") && !read("Cargo.toml").contains("tonic"),
+            "Expected no tonic code or dependency"
+        );
+    }
+
+    #[test]
+    fn build_no_transport_generates_clients_without_connect() {
+        let src = tempfile::TempDir::new().expect("Failed to create test source directory");
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        fs::write(
+            src.path().join("crabs.proto"),
+            "syntax = \"proto3\";\npackage crabs;\nmessage Crab {}\n\
+            service CrabService { rpc Pinch(Crab) returns (Crab); }\n",
+        )
+        .expect("Failed to create a test protobuf file");
+        let builder = super::Builder::new(src.path())
+            .output(dst.path().join("crabs"))
+            .output_layout(super::modgen::Layout::Crate)
+            .build_client(true)
+            .no_transport(true);
+
+        let err = builder.clone().recording_client(true).build();
+        assert!(
+            matches!(err, Err(super::Error::NoTransport(_))),
+            "Expected `Err(Error::NoTransport)`, got: `{:?}`",
+            err
+        );
+
+        builder.build().expect("Failed to build the clients");
+        let read = |file: &str| {
+            fs::read_to_string(dst.path().join("crabs").join(file))
+                .expect("Failed to read a generated file")
+        };
+        let code = read("src/crabs/mod.rs");
+        assert!(
+            code.contains("pub struct CrabServiceClient") && !code.contains("transport"),
+            "Expected a client without the transport"
+        );
+        assert!(
+            read("Cargo.toml").contains("features = [\"codegen\"], optional = true"),
+            "Expected tonic without the transport feature"
+        );
+    }
+
+    #[test]
+    fn build_pbjson_appends_the_implementations_to_the_packages() {
+        let src = tempfile::TempDir::new().expect("Failed to create test source directory");
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        fs::write(
+            src.path().join("crabs.proto"),
+            "syntax = \"proto3\";\npackage crabs;\nimport \"google/protobuf/timestamp.proto\";\n\
+            message Crab { int64 weight = 1; google.protobuf.Timestamp born = 2; }\n",
+        )
+        .expect("Failed to create a test protobuf file");
+        let builder = super::Builder::new(src.path())
+            .output(dst.path().join("crabs"))
+            .output_layout(super::modgen::Layout::Crate)
+            .pbjson(true);
+
+        let err = builder.clone().serde(".").build();
+        assert!(
+            matches!(err, Err(super::Error::Pbjson(super::pbjson::Error::Serde))),
+            "Expected `Err(Error::Pbjson(Error::Serde))`, got: `{:?}`",
+            err
+        );
+
+        builder.build().expect("Failed to build the pbjson types");
+        let read = |file: &str| {
+            fs::read_to_string(dst.path().join("crabs").join(file))
+                .expect("Failed to read a generated file")
+        };
+        let code = read("src/crabs/mod.rs");
+        assert!(
+            code.contains("impl serde::Serialize for Crab")
+                && code.contains("::pbjson_types::Timestamp"),
+            "Expected the pbjson implementations along with the types"
+        );
+        assert!(
+            read("Cargo.toml").contains("\npbjson = "),
+            "Expected the pbjson dependency"
+        );
+    }
+
+    #[test]
+    fn build_merges_the_descriptor_sets_into_the_packages() {
+        let vendor = tempfile::TempDir::new().expect("Failed to create test vendor directory");
+        let src = tempfile::TempDir::new().expect("Failed to create test source directory");
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        fs::write(
+            vendor.path().join("claw.proto"),
+            "syntax = \"proto3\";\npackage crabs;\nmessage Claw {}\n",
+        )
+        .expect("Failed to create a test protobuf file");
+        let vendored = dst.path().join("vendor.bin");
+        super::Builder::new(vendor.path())
+            .output(dst.path().join("vendor"))
+            .file_descriptor_set_path(&vendored)
+            .build()
+            .expect("Failed to build the vendored descriptor set");
+
+        let builder = super::Builder::new(src.path())
+            .output(dst.path().join("out"))
+            .descriptor_set_in(&vendored);
+
+        fs::write(
+            src.path().join("ferris.proto"),
+            "syntax = \"proto3\";\npackage crabs;\nimport \"claw.proto\";\n\
+            message Ferris { Claw claw = 1; }\n",
+        )
+        .expect("Failed to create a test protobuf file");
+        builder
+            .clone()
+            .build()
+            .expect("Failed to build the merged packages");
+        let code = fs::read_to_string(dst.path().join("out/crabs/mod.rs"))
+            .expect("Failed to read the generated module");
+        assert!(
+            code.contains("pub struct Claw") && code.contains("pub struct Ferris"),
+            "Expected the types of both sources in a single module"
+        );
+
+        fs::write(
+            src.path().join("ferris.proto"),
+            "syntax = \"proto3\";\npackage crabs;\nmessage Claw {}\n",
+        )
+        .expect("Failed to create a test protobuf file");
+        let err = builder.build();
+        assert!(
+            matches!(
+                err,
+                Err(super::Error::MergeDescriptors(
+                    super::descriptor::Error::DuplicateType(..)
+                ))
+            ),
+            "Expected `Err(Error::MergeDescriptors(Error::DuplicateType))`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn build_serde_derives_the_traits_for_the_package() {
+        let src = tempfile::TempDir::new().expect("Failed to create test source directory");
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        fs::write(
+            src.path().join("crabs.proto"),
+            "syntax = \"proto3\";\npackage crabs;\nimport \"google/protobuf/timestamp.proto\";\n\
+            enum Kind { KIND_UNSPECIFIED = 0; }\n\
+            message Crab { string name = 1; Kind kind = 2; oneof home { string reef = 3; } }\n\
+            message Molt { google.protobuf.Timestamp at = 1; }\n",
+        )
+        .expect("Failed to create a test protobuf file");
+        let builder = super::Builder::new(src.path())
+            .output(dst.path().join("crabs"))
+            .output_layout(super::modgen::Layout::Crate)
+            .serde("crabs");
+
+        let err = builder.clone().build();
+        assert!(
+            matches!(err, Err(super::Error::Serialize(_))),
+            "Expected `Err(Error::Serialize)`, got: `{:?}`",
+            err
+        );
+
+        builder
+            .with_well_known_types(true)
+            .serde("google.protobuf")
+            .build()
+            .expect("Failed to build the serde types");
+        let read = |file: &str| {
+            fs::read_to_string(dst.path().join("crabs").join(file))
+                .expect("Failed to read a generated file")
+        };
+        let code = read("src/crabs/mod.rs");
+        assert_eq!(
+            code.matches(super::serialize::DERIVE).count(),
+            4,
+            "Expected serde on the messages, the enum and the oneof"
+        );
+        assert_eq!(
+            code.matches(super::serialize::DEFAULT).count(),
+            3,
+            "Expected the defaults on the fields outside of the oneof"
+        );
+        assert!(
+            read("Cargo.toml").contains("\nserde = "),
+            "Expected the serde dependency"
+        );
+    }
+
+    #[test]
+    fn build_rejects_the_registry_offline_before_compiling() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+
+        let err = super::Builder::new("./proto")
+            .output(dst.path().join("out"))
+            .schema_registry("http://localhost:8081", crate::registry::Api::Confluent)
+            .offline(true)
+            .build();
+        assert!(
+            matches!(
+                err,
+                Err(super::Error::Registry(crate::registry::Error::Offline))
+            ),
+            "Expected `Err(Error::Registry(Offline))`, got: `{:?}`",
+            err
+        );
+        assert!(
+            !dst.path().join("out").exists(),
+            "Expected nothing to be generated",
+        );
+    }
+
+    #[test]
+    fn build_rejects_the_msrv_without_a_crate() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        let builder = super::Builder::new("./proto")
+            .output(dst.path().join("out"))
+            .msrv("1.85".parse().expect("Failed to parse a Rust version"));
+
+        let err = builder.clone().build();
+        assert!(
+            matches!(&err, Err(super::Error::MsrvWithoutCrate(target)) if target == "default"),
+            "Expected `Err(Error::MsrvWithoutCrate)`, got: `{:?}`",
+            err
+        );
+
+        builder
+            .output_layout(super::modgen::Layout::Crate)
+            .build()
+            .expect("Failed to build the crate");
+        let manifest = fs::read_to_string(dst.path().join("out/Cargo.toml"))
+            .expect("Failed to read the manifest");
+        assert!(
+            manifest.contains("rust-version = \"1.85\""),
+            "Expected the Rust version in the manifest, got:\n{manifest}",
+        );
+    }
+
+    #[test]
+    fn build_strict_rejects_unreserved_removed_fields() {
+        let src = tempfile::TempDir::new().expect("Failed to create test source directory");
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        let proto = src.path().join("crabs.proto");
+        let builder = super::Builder::new(src.path())
+            .output(dst.path().join("out"))
+            .strict(true);
+
+        fs::write(&proto, "syntax = \"proto3\";\n").expect("Failed to create a test protobuf file");
+        let err = builder.build();
+        assert!(
+            matches!(err, Err(super::Error::StrictWithoutDescriptorSet)),
+            "Expected `Err(Error::StrictWithoutDescriptorSet)`, got: `{:?}`",
+            err
+        );
+
+        let builder = builder.file_descriptor_set_path(dst.path().join("descriptor.bin"));
+        fs::write(
+            &proto,
+            "syntax = \"proto3\";\nmessage Ferris {\n  string name = 1;\n  uint32 claws = 2;\n}\n",
+        )
+        .expect("Failed to create a test protobuf file");
+        let err = builder.build();
+        assert!(
+            matches!(err, Err(super::Error::MissingPreviousDescriptorSet(_))),
+            "Expected `Err(Error::MissingPreviousDescriptorSet)`, got: `{:?}`",
+            err
+        );
+
+        builder
+            .clone()
+            .allow_missing_descriptor_set(true)
+            .build()
+            .expect("Failed to run the builder");
+
+        fs::write(
+            &proto,
+            "syntax = \"proto3\";\nmessage Ferris {\n  string name = 1;\n}\n",
+        )
+        .expect("Failed to update the test protobuf file");
+        let err = builder.build();
+        assert!(
+            matches!(&err, Err(super::Error::UnreservedFields(removed)) if removed.len() == 1),
+            "Expected `Err(Error::UnreservedFields)`, got: `{:?}`",
+            err
+        );
+
+        fs::write(
+            &proto,
+            "syntax = \"proto3\";\nmessage Ferris {\n  reserved 2;\n  string name = 1;\n}\n",
+        )
+        .expect("Failed to update the test protobuf file");
+        builder.build().expect("Failed to run the builder");
+    }
+
+    #[test]
+    fn build_strict_rejects_missing_imports() {
+        let src = tempfile::TempDir::new().expect("Failed to create test source directory");
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+
+        fs::write(
+            src.path().join("crabs.proto"),
+            "syntax = \"proto3\";\nimport \"ferris.proto\";\n",
+        )
+        .expect("Failed to create a test protobuf file");
+
+        let err = super::Builder::new(src.path())
+            .output(dst.path().join("out"))
+            .file_descriptor_set_path(dst.path().join("descriptor.bin"))
+            .strict(true)
+            .allow_missing_descriptor_set(true)
+            .build();
+
+        assert!(
+            matches!(&err, Err(super::Error::UnresolvedImports(unresolved)) if unresolved.len() == 1),
+            "Expected `Err(Error::UnresolvedImports)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn build_reports_the_unreachable_schema() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+
+        let report = super::Builder::new("./proto")
+            .output(dst.path())
+            .entry_service("crabs.CrabService")
+            .build()
+            .expect("Failed to run the builder");
+
+        assert_eq!(
+            report.unreachable,
+            Some(crate::reachability::Unreachable {
+                files: vec!["orphan.proto".to_string()],
+                messages: vec!["Orphan".to_string()],
+                enums: vec![],
+            }),
+            "Expected only the orphan to be unreachable from the service",
+        );
+    }
+
+    #[test]
+    fn build_reports_protos_and_generated_files() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+
+        let report = super::Builder::new("./proto")
+            .output(dst.path())
+            .build()
+            .expect("Failed to run the builder");
+
+        assert!(
+            report
+                .protos
+                .iter()
+                .any(|proto| proto.source.ends_with("crabs/Ferris.proto")),
+            "Expected the report to contain `Ferris.proto`, got: {:?}",
+            report.protos,
+        );
+
+        assert_eq!(report.targets.len(), 1, "Expected a single default target");
+
+        let target = &report.targets[0];
+        assert_eq!(target.name, "default", "Invalid default target name");
+        assert!(
+            target.files.contains(&dst.path().join("crabs/mod.rs")),
+            "Expected the generated files to contain `crabs/mod.rs`, got: {:?}",
+            target.files,
+        );
+        target.files.iter().for_each(|file| {
+            assert!(
+                fs::exists(file).is_ok_and(|exists| exists),
+                "Expected the reported file `{}` to exist",
+                file.display(),
+            );
+        });
+        assert!(
+            target
+                .packages
+                .iter()
+                .any(|size| size.package == "crabs.disney.ariel" && size.lines > 0),
+            "Expected the size of the `crabs.disney.ariel` package to be reported, got: {:?}",
+            target.packages,
+        );
+        assert_eq!(
+            target.compile_time, None,
+            "Expected no compile time without a verification"
+        );
+    }
+
+    #[test]
+    fn build_applies_target_overrides() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+
+        let report = super::Builder::new("./proto")
+            .build_client(true)
+            .target(super::Target::new("types", dst.path().join("types")).build_client(false))
+            .target(super::Target::new("client", dst.path().join("client")))
+            .build()
+            .expect("Failed to run the builder");
+
+        assert_eq!(
+            report
+                .targets
+                .iter()
+                .map(|target| target.name.as_str())
+                .collect::<Vec<_>>(),
+            ["types", "client"],
+            "Expected the targets to be reported in the declaration order",
+        );
+
+        let types = fs::read_to_string(dst.path().join("types/crabs/mod.rs"))
+            .expect("Failed to read the generated file");
+        assert!(
+            !types.contains("struct CrabServiceClient"),
+            "Expected the types target to contain no client, got:\n{types}",
+        );
+
+        let client = fs::read_to_string(dst.path().join("client/crabs/mod.rs"))
+            .expect("Failed to read the generated file");
+        assert!(
+            client.contains("struct CrabServiceClient"),
+            "Expected the client target to fall back to the builder options, got:\n{client}",
+        );
+    }
+
+    #[test]
+    fn build_applies_target_attributes() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+
+        super::Builder::new("./proto")
+            .target(super::Target::new("plain", dst.path().join("plain")))
+            .target(
+                super::Target::new("annotated", dst.path().join("annotated"))
+                    .type_attribute(".crabs.Ferris", "#[non_exhaustive]")
+                    .field_attribute(".crabs.Ferris.type", "#[deprecated]"),
+            )
+            .build()
+            .expect("Failed to run the builder");
+
+        let plain = fs::read_to_string(dst.path().join("plain/crabs/mod.rs"))
+            .expect("Failed to read the generated file");
+        assert!(
+            !plain.contains("#[non_exhaustive]") && !plain.contains("#[deprecated]"),
+            "Expected the attributes to be limited to the annotated target, got:\n{plain}",
+        );
+
+        let annotated = fs::read_to_string(dst.path().join("annotated/crabs/mod.rs"))
+            .expect("Failed to read the generated file");
+        assert!(
+            annotated.contains("#[non_exhaustive]") && annotated.contains("#[deprecated]"),
+            "Expected the annotated target to contain the attributes, got:\n{annotated}",
+        );
+    }
+
+    #[test]
+    fn build_applies_builder_attributes_to_every_target() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+
+        super::Builder::new("./proto")
+            .type_attribute(".crabs.Ferris", "#[non_exhaustive]")
+            .field_attribute(".crabs.Ferris.type", "#[deprecated]")
+            .target(super::Target::new("plain", dst.path().join("plain")))
+            .target(
+                super::Target::new("annotated", dst.path().join("annotated"))
+                    .type_attribute(".crabs.Ferris", "#[derive(Eq, Hash)]"),
+            )
+            .build()
+            .expect("Failed to run the builder");
+
+        let plain = fs::read_to_string(dst.path().join("plain/crabs/mod.rs"))
+            .expect("Failed to read the generated file");
+        assert!(
+            plain.contains("#[non_exhaustive]") && plain.contains("#[deprecated]"),
+            "Expected the builder attributes in every target, got:\n{plain}",
+        );
+        assert!(
+            !plain.contains("#[derive(Eq, Hash)]"),
+            "Expected the target attributes to be limited to their target, got:\n{plain}",
+        );
+
+        let annotated = fs::read_to_string(dst.path().join("annotated/crabs/mod.rs"))
+            .expect("Failed to read the generated file");
+        assert!(
+            annotated.contains("#[non_exhaustive]") && annotated.contains("#[derive(Eq, Hash)]"),
+            "Expected both the builder and the target attributes, got:\n{annotated}",
+        );
+    }
+
+    #[test]
+    fn build_applies_builder_extern_paths_to_every_target() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+
+        super::Builder::new("./proto")
+            .build_client(true)
+            .extern_path(".crabs.disney", "::disney")
+            .target(super::Target::new("plain", dst.path().join("plain")))
+            .target(
+                super::Target::new("shared", dst.path().join("shared"))
+                    .extern_path(".crabs.sponge_bob", "::sponge_bob"),
+            )
+            .build()
+            .expect("Failed to run the builder");
+
+        let plain = fs::read_to_string(dst.path().join("plain/crabs/mod.rs"))
+            .expect("Failed to read the generated file");
+        assert!(
+            plain.contains("::disney::ariel::Sebastian"),
+            "Expected the builder extern paths in every target, got:\n{plain}",
+        );
+        assert!(
+            !dst.path().join("plain/crabs/disney").exists()
+                && !dst.path().join("shared/crabs/disney").exists(),
+            "Expected the extern packages not to be generated"
+        );
+        assert!(
+            dst.path().join("plain/crabs/sponge_bob").exists()
+                && !dst.path().join("shared/crabs/sponge_bob").exists(),
+            "Expected the target extern paths to be limited to their target"
+        );
+    }
+
+    #[test]
+    fn build_generates_the_matching_bytes_fields_as_bytes() {
+        let src = tempfile::TempDir::new().expect("Failed to create test source directory");
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        fs::write(
+            src.path().join("crabs.proto"),
+            "syntax = \"proto3\";\npackage crabs;\n\
+            message Crab {\n  bytes photo = 1;\n  bytes scan = 2;\n}\n",
+        )
+        .expect("Failed to create a test protobuf file");
+
+        super::Builder::new(src.path())
+            .output(dst.path().join("crabs"))
+            .output_layout(super::modgen::Layout::Crate)
+            .serde("crabs")
+            .bytes(".crabs.Crab.photo")
+            .build()
+            .expect("Failed to run the builder");
+
+        let read = |file: &str| {
+            fs::read_to_string(dst.path().join("crabs").join(file))
+                .expect("Failed to read a generated file")
+        };
+        let code = read("src/crabs/mod.rs");
+        assert!(
+            code.contains("pub photo: ::prost::bytes::Bytes")
+                && code.contains("pub scan: ::prost::alloc::vec::Vec<u8>"),
+            "Expected only the matching field to be `Bytes`, got:\n{code}",
+        );
+        let manifest = read("Cargo.toml");
+        assert!(
+            manifest.contains("bytes = { version = \"1\", features = [\"serde\"] }"),
+            "Expected the serde support of bytes in the manifest, got:\n{manifest}",
+        );
+    }
+
+    #[test]
+    fn build_generates_the_matching_map_fields_as_btree_maps() {
+        let src = tempfile::TempDir::new().expect("Failed to create test source directory");
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        fs::write(
+            src.path().join("crabs.proto"),
+            "syntax = \"proto3\";\npackage crabs;\n\
+            message Crab {\n  map<string, uint32> claws = 1;\n}\n\
+            message Shell {\n  map<string, uint32> spots = 1;\n}\n",
+        )
+        .expect("Failed to create a test protobuf file");
+
+        super::Builder::new(src.path())
+            .output(dst.path())
+            .pbjson(true)
+            .btree_map(".crabs.Crab.claws")
+            .build()
+            .expect("Failed to run the builder");
+
+        let code = fs::read_to_string(dst.path().join("crabs/mod.rs"))
+            .expect("Failed to read the generated file");
+        assert!(
+            code.contains("pub claws: ::prost::alloc::collections::BTreeMap<")
+                && code.contains("pub spots: ::std::collections::HashMap<"),
+            "Expected only the matching field to be a `BTreeMap`, got:\n{code}",
+        );
+        assert!(
+            code.contains("map_.next_value::<std::collections::BTreeMap<")
+                && code.contains("map_.next_value::<std::collections::HashMap<"),
+            "Expected pbjson to deserialize only the matching field into a `BTreeMap`, got:\n{code}",
+        );
+    }
+
+    #[test]
+    fn build_generates_the_matching_string_fields_as_bytes() {
+        let src = tempfile::TempDir::new().expect("Failed to create test source directory");
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        let descriptors = dst.path().join("descriptors.bin");
+        fs::write(
+            src.path().join("crabs.proto"),
+            "syntax = \"proto3\";\npackage crabs;\n\
+            message Crab {\n  string name = 1;\n  string nickname = 2;\n}\n",
+        )
+        .expect("Failed to create a test protobuf file");
+        let builder = super::Builder::new(src.path())
+            .output(dst.path().join("code"))
+            .file_descriptor_set_path(&descriptors)
+            .string_bytes(".crabs.Crab.name");
+
+        let err = builder.clone().serde("crabs").build();
+        assert!(
+            matches!(
+                err,
+                Err(super::Error::Strings(super::strings::Error::Serialization(
+                    "serde"
+                )))
+            ),
+            "Expected `Err(Error::Strings)`, got: `{:?}`",
+            err
+        );
+
+        builder.build().expect("Failed to run the builder");
+        let code = fs::read_to_string(dst.path().join("code/crabs/mod.rs"))
+            .expect("Failed to read the generated file");
+        assert!(
+            code.contains(
+                "#[prost(bytes = \"bytes\", tag = \"1\")]\n    pub name: ::prost::bytes::Bytes"
+            ) && code.contains("pub nickname: ::prost::alloc::string::String"),
+            "Expected only the matching field to be `Bytes`, got:\n{code}",
+        );
+
+        let fds = <prost_types::FileDescriptorSet as prost::Message>::decode(
+            fs::read(&descriptors)
+                .expect("Failed to read the descriptors")
+                .as_slice(),
+        )
+        .expect("Failed to decode the descriptors");
+        assert_eq!(
+            fds.file[0].message_type[0].field[0].r#type(),
+            prost_types::field_descriptor_proto::Type::String,
+            "Expected the descriptors to keep the string field",
+        );
+    }
+
+    #[test]
+    fn build_compiles_the_editions_as_proto2() {
+        let src = tempfile::TempDir::new().expect("Failed to create test source directory");
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        fs::write(
+            src.path().join("crabs.proto"),
+            "edition = \"2023\";\npackage crabs;\n\
+            message Ferris {\n  string name = 1;\n  \
+            uint32 claws = 2 [features.field_presence = LEGACY_REQUIRED];\n}\n",
+        )
+        .expect("Failed to create a test protobuf file");
+
+        super::Builder::new(src.path())
+            .output(dst.path())
+            .target_syntax(super::patcher::Syntax::Proto2)
+            .build()
+            .expect("Failed to run the builder");
+
+        let code = fs::read_to_string(dst.path().join("crabs/mod.rs"))
+            .expect("Failed to read the generated file");
+        assert!(
+            code.contains("pub name: ::core::option::Option<::prost::alloc::string::String>")
+                && code.contains("#[prost(uint32, required, tag = \"2\")]"),
+            "Expected the proto2 labels of the fields, got:\n{code}",
+        );
+    }
+
+    #[test]
+    fn build_hides_the_internal_messages() {
+        let src = tempfile::TempDir::new().expect("Failed to create test source directory");
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        fs::write(
+            src.path().join("options.proto"),
+            "syntax = \"proto3\";\npackage pbuildrs;\n\
+            import \"google/protobuf/descriptor.proto\";\n\
+            extend google.protobuf.MessageOptions {\n  bool internal = 50001;\n}\n",
+        )
+        .expect("Failed to create a test protobuf file");
+        fs::write(
+            src.path().join("crabs.proto"),
+            "syntax = \"proto3\";\npackage crabs;\nimport \"options.proto\";\n\
+            message GetFerrisReqProto {\n  option (pbuildrs.internal) = true;\n  string name = 1;\n}\n\
+            message Shell {\n  string color = 1;\n}\n\
+            message Ferris {\n  Shell shell = 1;\n}\n",
+        )
+        .expect("Failed to create a test protobuf file");
+
+        super::Builder::new(src.path())
+            .output(dst.path())
+            .internal_message("crabs.Shell")
+            .build()
+            .expect("Failed to run the builder");
+
+        let code = fs::read_to_string(dst.path().join("crabs/mod.rs"))
+            .expect("Failed to read the generated file");
+        assert!(
+            code.contains("#[doc(hidden)]\npub mod __internal {")
+                && code.contains("#[doc(hidden)]\npub use __internal::{GetFerrisReqProto, Shell};")
+                && code.contains("pub struct Ferris {"),
+            "Expected the internal messages to be moved into the hidden module, got:\n{code}",
+        );
+    }
+
+    #[test]
+    fn build_places_packages_into_their_outputs() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+
+        let report = super::Builder::new("./proto")
+            .output(dst.path().join("main"))
+            .package_output(
+                crate::modgen::PackageOutput::new("crabs.disney.*", dst.path().join("disney"))
+                    .expect("Failed to create a package output"),
+            )
+            .build()
+            .expect("Failed to run the builder");
+
+        let ariel = fs::read_to_string(dst.path().join("disney/ariel/mod.rs"))
+            .expect("Failed to read the generated file");
+        assert!(
+            ariel.contains("struct Sebastian"),
+            "Expected the mapped package to contain `Sebastian` struct, got:\n{ariel}",
+        );
+
+        let crabs = fs::read_to_string(dst.path().join("main/crabs/mod.rs"))
+            .expect("Failed to read the generated file");
+        assert!(
+            !crabs.contains("pub mod disney;"),
+            "Expected the mapped package to be removed from the main tree, got:\n{crabs}",
+        );
+
+        assert!(
+            report.targets[0]
+                .files
+                .contains(&dst.path().join("disney/mod.rs")),
+            "Expected the mapped package files to be reported, got: {:?}",
+            report.targets[0].files,
+        );
+    }
+
+    #[test]
+    fn build_dry_run_writes_nothing() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        let fds_path = dst.path().join("fds.bin");
+
+        let report = super::Builder::new("./proto")
+            .output(dst.path().join("out"))
+            .file_descriptor_set_path(&fds_path)
+            .dry_run(true)
+            .build()
+            .expect("Failed to run the builder");
+
+        assert!(
+            report.targets[0]
+                .files
+                .contains(&dst.path().join("out/crabs/mod.rs")),
+            "Expected the planned files to contain `crabs/mod.rs`, got: {:?}",
+            report.targets[0].files,
+        );
+        assert!(
+            !dst.path().join("out").exists() && !fds_path.exists(),
+            "Expected a dry run to write nothing",
+        );
+    }
+
+    #[test]
+    fn build_updates_codeowners() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        let file = dst.path().join("CODEOWNERS");
+
+        super::Builder::new("./proto")
+            .output(dst.path().join("out"))
+            .codeowners(&file)
+            .owner("crabs.disney", ["@ariel"])
+            .owner("crabs.missing", ["@nobody"])
+            .build()
+            .expect("Failed to run the builder");
+
+        assert_eq!(
+            fs::read_to_string(&file).expect("Failed to read the CODEOWNERS file"),
+            "# BEGIN pbuildrs generated owners\n/out/crabs/disney/ @ariel\n# END pbuildrs generated owners\n",
+            "Expected only the generated packages to be assigned owners",
+        );
+    }
+
+    #[test]
+    fn check_reports_outdated_output() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        let builder = super::Builder::new("./proto").output(dst.path().join("out"));
+
+        builder.build().expect("Failed to run the builder");
+
+        let differences = builder.check().expect("Failed to check the output");
+        assert!(
+            differences.is_empty(),
+            "Expected a fresh output to be up to date, got: {differences:?}",
+        );
+
+        fs::write(dst.path().join("out/crabs/mod.rs"), "struct Stale;\n")
+            .expect("Failed to modify the generated file");
+        fs::write(dst.path().join("out/stale.rs"), "struct Stale;\n")
+            .expect("Failed to create a stale file");
+        fs::remove_file(dst.path().join("out/crabs/disney/mod.rs"))
+            .expect("Failed to remove the generated file");
+
+        let differences = builder.check().expect("Failed to check the output");
+        assert_eq!(
+            differences,
+            vec![
+                crate::check::Difference::Missing(dst.path().join("out/crabs/disney/mod.rs")),
+                crate::check::Difference::Modified(dst.path().join("out/crabs/mod.rs")),
+                crate::check::Difference::Unexpected(dst.path().join("out/stale.rs")),
+            ],
+            "Invalid differences with the existing output",
+        );
+    }
+
+    #[test]
+    fn check_strict_reads_the_previous_descriptor_set() {
+        let src = tempfile::TempDir::new().expect("Failed to create test source directory");
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        let proto = src.path().join("crabs.proto");
+        let builder = super::Builder::new(src.path())
+            .output(dst.path().join("out"))
+            .file_descriptor_set_path(dst.path().join("descriptor.bin"))
+            .strict(true);
+
+        fs::write(
+            &proto,
+            "syntax = \"proto3\";\nmessage Ferris {\n  string name = 1;\n  uint32 claws = 2;\n}\n",
+        )
+        .expect("Failed to create a test protobuf file");
+        builder
+            .clone()
+            .allow_missing_descriptor_set(true)
+            .build()
+            .expect("Failed to run the builder");
+
+        let differences = builder.check().expect("Failed to check the output");
+        assert!(
+            differences.is_empty(),
+            "Expected a fresh output to be up to date, got: {differences:?}",
+        );
+
+        fs::write(
+            &proto,
+            "syntax = \"proto3\";\nmessage Ferris {\n  string name = 1;\n}\n",
+        )
+        .expect("Failed to update the test protobuf file");
+        let err = builder.check();
+        assert!(
+            matches!(&err, Err(super::Error::UnreservedFields(removed)) if removed.len() == 1),
+            "Expected `Err(Error::UnreservedFields)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn build_skips_unchanged_inputs() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        let src = dst.path().join("proto");
+        fs::create_dir_all(&src).expect("Failed to create the test source directory");
+        fs::write(
+            src.join("crabs.proto"),
+            "syntax = \"proto3\";\npackage crabs;\nmessage Ferris {}\n",
+        )
+        .expect("Failed to create a test protobuf file");
+
+        let builder = super::Builder::new(&src)
+            .output(dst.path().join("out"))
+            .cache_file(dst.path().join(crate::cache::DEFAULT_PATH));
+
+        let report = builder.build().expect("Failed to run the builder");
+        assert!(
+            !report.cached,
+            "Expected the first run to generate the code"
+        );
+
+        let report = builder.build().expect("Failed to run the builder");
+        assert!(report.cached, "Expected an unchanged run to be skipped");
+
+        fs::write(dst.path().join("out/crabs/mod.rs"), "struct Stale;\n")
+            .expect("Failed to modify the generated file");
+        let report = builder.build().expect("Failed to run the builder");
+        assert!(
+            !report.cached,
+            "Expected a modified output to be regenerated"
+        );
+
+        fs::write(
+            src.join("crabs.proto"),
+            "syntax = \"proto3\";\npackage crabs;\nmessage Crab {}\n",
+        )
+        .expect("Failed to modify the test protobuf file");
+        let report = builder.build().expect("Failed to run the builder");
+        assert!(
+            !report.cached,
+            "Expected a modified input to be regenerated"
+        );
+
+        let report = builder
+            .clone()
+            .build_client(true)
+            .build()
+            .expect("Failed to run the builder");
+        assert!(
+            !report.cached,
+            "Expected a modified configuration to be regenerated"
+        );
+    }
+
+    #[test]
+    fn build_restores_the_code_from_the_shared_cache() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        let src = dst.path().join("proto");
+        fs::create_dir_all(&src).expect("Failed to create the test source directory");
+        fs::write(
+            src.join("crabs.proto"),
+            "syntax = \"proto3\";\npackage crabs;\nmessage Ferris {}\n",
+        )
+        .expect("Failed to create a test protobuf file");
+        let out = dst.path().join("out");
+        let store = dst.path().join("store");
+
+        let report = super::Builder::new(&src)
+            .output(&out)
+            .cache_dir(&store)
+            .build()
+            .expect("Failed to run the builder");
+        assert!(
+            !report.cached,
+            "Expected the first run to generate the code"
+        );
+        let generated = fs::read_to_string(out.join("crabs/mod.rs"))
+            .expect("Failed to read the generated file");
+
+        // Another runner, starting without any outputs, only reads the store.
+        fs::remove_dir_all(&out).expect("Failed to remove the output directory");
+        let builder = super::Builder::new(&src).output(&out).shared_cache(&store);
+        let report = builder.build().expect("Failed to run the builder");
+        assert!(
+            report.cached,
+            "Expected the code to be restored from the store"
+        );
+        assert_eq!(
+            fs::read_to_string(out.join("crabs/mod.rs")).expect("Failed to read the restored file"),
+            generated,
+            "Expected the restored code to match the generated one",
+        );
+
+        let entries = || {
+            fs::read_dir(&store)
+                .expect("Failed to read the store")
+                .map(|entry| entry.expect("Failed to read a store entry").path())
+                .collect::<Vec<_>>()
+        };
+        let [entry] = entries().try_into().expect("Expected a single store entry");
+        fs::write(entry.join("0/crabs/mod.rs"), "struct Tampered;\n")
+            .expect("Failed to tamper with the store entry");
+        let report = builder.build().expect("Failed to run the builder");
+        assert!(!report.cached, "Expected a corrupted entry to be ignored");
+        assert_eq!(
+            fs::read_to_string(out.join("crabs/mod.rs"))
+                .expect("Failed to read the generated file"),
+            generated,
+            "Expected the code to be generated again",
+        );
+        assert_eq!(
+            entries(),
+            [entry],
+            "Expected the shared store to be left intact"
+        );
+    }
+
+    #[test]
+    fn build_writes_the_failure_report() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        let src = dst.path().join("proto");
+        fs::create_dir_all(src.join("crabs")).expect("Failed to create the test source directory");
+        fs::write(
+            src.join("crabs/ferris.proto"),
+            "syntax = \"proto3\";\npackage crabs;\nmessage Ferris { Shell shell = 1; }\n",
+        )
+        .expect("Failed to create a test protobuf file");
+        fs::write(
+            src.join("crabs/sebastian.proto"),
+            "syntax = \"proto3\";\npackage crabs;\nmessage Sebastian {}\n",
+        )
+        .expect("Failed to create a test protobuf file");
+        let report = dst.path().join("failure-report");
+
+        let err = super::Builder::new(&src)
+            .output(dst.path().join("out"))
+            .failure_report(&report)
+            .build();
+        assert!(
+            matches!(&err, Err(super::Error::CompileProto(_))),
+            "Expected `Err(Error::CompileProto)`, got: `{:?}`",
+            err
+        );
+
+        let failure: crate::failure_report::Failure = serde_json::from_slice(
+            &fs::read(report.join("error.json")).expect("Failed to read the error"),
+        )
+        .expect("Failed to parse the error");
+        assert!(
+            failure.mentions("crabs/ferris.proto"),
+            "Expected the error to point at the protobuf file"
+        );
+        assert!(
+            fs::read_to_string(report.join("protoc-stderr.txt"))
+                .expect("Failed to read the output of protoc")
+                .contains("\"Shell\" is not defined"),
+            "Expected the output of protoc in the report"
+        );
+        assert!(
+            report.join("config.toml").is_file(),
+            "Expected the effective configuration in the report"
+        );
+        assert!(
+            report.join("patched/crabs/ferris.proto").is_file()
+                && !report.join("patched/crabs/sebastian.proto").exists(),
+            "Expected only the offending patched file in the report"
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn build_async_generates_the_code_concurrently() {
+        fn spawn<F: Future + Send + 'static>(future: F) -> tokio::task::JoinHandle<F::Output>
+        where
+            F::Output: Send,
+        {
+            tokio::spawn(future)
+        }
+
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Failed to create a runtime");
+
+        let reports = runtime.block_on(async {
+            let builds = ["ferris", "sebastian"]
+                .into_iter()
+                .map(|name| {
+                    let builder = super::Builder::new("./proto").output(dst.path().join(name));
+                    spawn(async move { builder.build_async().await })
+                })
+                .collect::<Vec<_>>();
+
+            let mut reports = vec![];
+            for build in builds {
+                reports.push(build.await.expect("Failed to join the build"));
+            }
+            reports
+        });
+        reports.into_iter().for_each(|report| {
+            report.expect("Failed to run the builder");
+        });
+        ["ferris", "sebastian"].into_iter().for_each(|name| {
+            assert!(
+                fs::read_to_string(dst.path().join(name).join("crabs/mod.rs"))
+                    .expect("Failed to read the generated file")
+                    .contains("pub struct Ferris"),
+                "Expected the code generated by the `{name}` build"
+            );
+        });
+
+        let src = dst.path().join("broken");
+        fs::create_dir_all(&src).expect("Failed to create the test source directory");
+        fs::write(
+            src.join("crabs.proto"),
+            "syntax = \"proto3\";\nmessage Ferris { Shell shell = 1; }\n",
+        )
+        .expect("Failed to create a test protobuf file");
+        let err = runtime.block_on(
+            super::Builder::new(&src)
+                .output(dst.path().join("broken-out"))
+                .build_async(),
+        );
+        assert!(
+            matches!(&err, Err(super::Error::CompileProto(e)) if e.to_string().contains("crabs.proto")),
+            "Expected `Err(Error::CompileProto)`, got: `{:?}`",
+            err
+        );
+    }
+
+    /// Runs `protoc` from the input root, standing in for a remote execution service.
+    #[derive(Default, Debug)]
+    struct Local {
+        inputs: std::sync::Arc<std::sync::Mutex<Vec<path::PathBuf>>>,
+    }
+
+    impl crate::execution::Backend for Local {
+        fn execute(
+            &self,
+            action: &crate::execution::Action,
+        ) -> Result<Vec<u8>, crate::execution::Error> {
+            let protoc = std::env::var_os("PROTOC").unwrap_or_else(|| "protoc".into());
+            let status = std::process::Command::new(protoc)
+                .current_dir(&action.input_root)
+                .args(&action.arguments)
+                .status()
+                .expect("Failed to run protoc");
+            assert!(status.success(), "Expected protoc to succeed");
+            self.inputs
+                .lock()
+                .expect("Failed to lock the inputs")
+                .extend(action.inputs.iter().cloned());
+
+            Ok(fs::read(action.input_root.join(&action.output))
+                .expect("Failed to read the file descriptor set"))
+        }
+    }
+
+    #[test]
+    fn build_executes_protoc_with_the_backend() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        let src = dst.path().join("proto");
+        fs::create_dir_all(src.join("crabs")).expect("Failed to create the test source directory");
+        fs::write(
+            src.join("crabs/ferris.proto"),
+            "syntax = \"proto3\";\npackage crabs;\nmessage Ferris {}\n",
+        )
+        .expect("Failed to create a test protobuf file");
+        let out = dst.path().join("out");
+
+        let backend = Local::default();
+        let inputs = backend.inputs.clone();
+        super::Builder::new(&src)
+            .output(&out)
+            .execution(backend)
+            .build()
+            .expect("Failed to run the builder");
+        assert!(
+            fs::read_to_string(out.join("crabs/mod.rs"))
+                .expect("Failed to read the generated file")
+                .contains("pub struct Ferris"),
+            "Expected the code generated from the remote descriptor set"
+        );
+        assert!(
+            inputs
+                .lock()
+                .expect("Failed to lock the inputs")
+                .iter()
+                .all(|input| input.is_relative()),
+            "Expected the inputs relative to the input root"
+        );
+
+        let err = super::Builder::new(&src)
+            .output(&out)
+            .execution(Local::default())
+            .sandbox(crate::sandbox::Sandbox::Env)
+            .build();
+        assert!(
+            matches!(
+                &err,
+                Err(super::Error::Execution(crate::execution::Error::Sandboxed))
+            ),
+            "Expected `Err(Error::Execution(Sandboxed))`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn error_is_transient() {
+        let eio = || io::Error::from_raw_os_error(5);
+
+        assert!(
+            super::Error::Modularize(crate::modgen::Error::MkModFile(
+                eio(),
+                path::PathBuf::from("out/mod.rs"),
+            ))
+            .is_transient(),
+            "Expected `EIO` while writing the module tree to be transient",
+        );
+        assert!(
+            !super::Error::Modularize(crate::modgen::Error::Multiple(vec![
+                crate::modgen::Error::MkModFile(eio(), path::PathBuf::from("out/mod.rs")),
+                crate::modgen::Error::MkModFile(
+                    io::Error::from(io::ErrorKind::PermissionDenied),
+                    path::PathBuf::from("out/crabs/mod.rs"),
+                ),
+            ]))
+            .is_transient(),
+            "Expected the failures to be transient only if all of them are",
+        );
+        assert!(
+            super::Error::CompileProto(io::Error::from(io::ErrorKind::ExecutableFileBusy))
+                .is_transient(),
+            "Expected `ETXTBSY` while invoking protoc to be transient",
+        );
+        assert!(
+            !super::Error::CompileProto(io::Error::other("syntax error")).is_transient(),
+            "Expected a protoc failure not to be transient",
+        );
+        assert!(
+            !super::Error::MkTempDir(eio()).is_transient(),
+            "Expected the phases without retries not to be transient",
+        );
+    }
+
+    #[test]
+    fn rerun_if_changed_covers_sources_and_includes() {
+        let builder = super::Builder::new("proto").include_path("vendor");
+
+        let directives =
+            builder.rerun_if_changed(&[path::PathBuf::from("proto/crabs/Ferris.proto")]);
+
+        assert_eq!(
+            directives,
+            [
+                "cargo:rerun-if-changed=proto",
+                "cargo:rerun-if-changed=vendor",
+                "cargo:rerun-if-changed=proto/crabs/Ferris.proto",
+            ],
+            "Invalid rerun directives",
+        );
+    }
+
+    #[test]
+    fn build_rejects_invalid_target_names() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+
+        let err = super::Builder::new("./proto")
+            .target(super::Target::new("a/b", dst.path().join("out")))
+            .build();
+
+        assert!(
+            matches!(err, Err(super::Error::InvalidTargetName { .. })),
+            "Expected `Err(Error::InvalidTargetName)`, got: `{:?}`",
+            err
+        );
+        assert!(
+            !dst.path().join("out").exists(),
+            "Expected no output to be created for an invalid target",
+        );
+    }
+}
@@ -0,0 +1,623 @@
+use std::{env, ffi, fs, iter, path, time};
+
+use super::{Builder, Error, TargetReport};
+use crate::{
+    attributes, config, conformance, defaults, descriptor_endpoint, encoded_size, events, exclude,
+    internal, manifest, modgen, naming, newline, pbjson, per_service, portable, recording, redact,
+    reexports, reproducible, retry, root_file, rows, rustfmt, semver, serialize, services, shadow,
+    size, size_guard, split, staging, strings, verify, wire_compat,
+};
+
+/// A target with every option resolved against the builder.
+pub(super) struct ResolvedTarget {
+    pub(super) name: String,
+    pub(super) output: path::PathBuf,
+    pub(super) build_client: bool,
+    pub(super) build_server: bool,
+    pub(super) with_well_known_types: bool,
+    pub(super) verify_build: bool,
+    pub(super) verify_feature_matrix: bool,
+    pub(super) msrv: Option<verify::RustVersion>,
+    pub(super) semver_baseline: Option<path::PathBuf>,
+    pub(super) type_attributes: Vec<(String, String)>,
+    pub(super) field_attributes: Vec<(String, String)>,
+    pub(super) extern_paths: Vec<(String, String)>,
+    pub(super) package_outputs: Vec<modgen::PackageOutput>,
+    /// The snapshot of the effective configuration written into the output, if enabled.
+    pub(super) effective_config: Option<String>,
+    /// The manifests of the crates written into every output with the crate layout, rendered
+    /// before the outputs are staged.
+    pub(super) manifests: Vec<String>,
+    /// The header of the module files, read before the outputs are staged.
+    pub(super) header: modgen::Header,
+    /// The internal messages, from the configuration and the custom option, resolved once the
+    /// protobuf files are compiled.
+    pub(super) internal_messages: Vec<internal::Message>,
+}
+
+/// The code compiled by prost for a target, along with what the rest of the pipeline needs to
+/// know about how it was compiled.
+struct Compiled {
+    rules: attributes::Resolved,
+    /// The string fields prost compiled as bytes.
+    string_fields: Vec<String>,
+    extern_paths: Vec<(String, String)>,
+    compile_well_known_types: bool,
+    selection: per_service::Selection,
+}
+
+impl ResolvedTarget {
+    pub(super) fn outputs(&self) -> impl Iterator<Item = &path::Path> {
+        iter::once(self.output.as_path()).chain(
+            self.package_outputs
+                .iter()
+                .map(modgen::PackageOutput::output),
+        )
+    }
+
+    /// Redirect the outputs into staging directories next to them, added to the `staged` ones. The
+    /// package outputs nested in the main one are staged along with it.
+    pub(super) fn stage(
+        self,
+        staged: &mut Vec<staging::Staged>,
+        force: bool,
+    ) -> Result<Self, Error> {
+        let main = staging::Staged::new(&self.output, force)?;
+        let output = main.path().to_path_buf();
+
+        let package_outputs = self
+            .package_outputs
+            .into_iter()
+            .map(|package| -> Result<_, Error> {
+                if let Ok(rest) = package.output().strip_prefix(&self.output) {
+                    let nested = output.join(rest);
+
+                    return Ok(package.map_output(|_| nested));
+                }
+
+                let package_staged = staging::Staged::new(package.output(), force)?;
+                let package_output = package_staged.path().to_path_buf();
+                staged.push(package_staged);
+
+                Ok(package.map_output(|_| package_output))
+            })
+            .collect::<Result<_, _>>()?;
+        staged.push(main);
+
+        Ok(Self {
+            output,
+            package_outputs,
+            ..self
+        })
+    }
+
+    pub(super) fn prepare_output(&self) -> Result<(), Error> {
+        self.outputs().try_for_each(|output| {
+            if output.exists() {
+                tracing::debug!("Found previous output directory, cleaning up");
+                fs::remove_dir_all(output).map_err(Error::RemoveOutDir)?;
+                tracing::debug!("Previous output directory was removed");
+            }
+
+            fs::create_dir_all(output).map_err(Error::CreateOutDir)?;
+            tracing::debug!("Created an output directory: {}", output.display());
+
+            Ok(())
+        })
+    }
+
+    /// Compile the `fds` with prost into the `compiled_files_dir`, configured by the options of
+    /// the target and of the `builder`.
+    fn compile(
+        &self,
+        fds: &tonic_prost_build::FileDescriptorSet,
+        compiled_files_dir: &path::Path,
+        builder: &Builder,
+        retries: &mut u32,
+    ) -> Result<Compiled, Error> {
+        let rules = attributes::resolve(fds, &builder.attributes)?;
+        // Only prost sees the string fields as bytes, the original types are kept for the rest.
+        let (codegen_fds, string_fields) = strings::retype(fds, &builder.string_bytes);
+        let codegen = self.type_attributes.iter().chain(&rules.types).fold(
+            tonic_prost_build::configure(),
+            |codegen, (path, attribute)| codegen.type_attribute(path, attribute),
+        );
+        let codegen = rules
+            .enums
+            .iter()
+            .fold(codegen, |codegen, (path, attribute)| {
+                codegen.enum_attribute(path, attribute)
+            });
+        let codegen = match builder.sensitive_fields.is_empty() {
+            true => codegen,
+            false => codegen.skip_debug(redact::messages(&builder.sensitive_fields)),
+        };
+        let codegen = builder.serde.iter().fold(codegen, |codegen, package| {
+            codegen.type_attribute(serialize::path(package), serialize::DERIVE)
+        });
+        let codegen = serialize::fields(fds, &builder.serde)
+            .iter()
+            .fold(codegen, |codegen, field| {
+                codegen.field_attribute(field, serialize::DEFAULT)
+            });
+        let codegen = self
+            .field_attributes
+            .iter()
+            .chain(&rules.fields)
+            .fold(codegen, |codegen, (path, attribute)| {
+                codegen.field_attribute(path, attribute)
+            });
+        let codegen = builder
+            .bytes
+            .iter()
+            .chain(&string_fields)
+            .fold(codegen, |codegen, path| codegen.bytes(path));
+        let codegen = builder
+            .btree_map
+            .iter()
+            .fold(codegen, |codegen, path| codegen.btree_map(path));
+        // The well-known types are compiled for pbjson, only to be replaced by its extern ones.
+        let compile_well_known_types = self.with_well_known_types || builder.pbjson;
+        let extern_paths = match builder.pbjson {
+            true => pbjson::extern_paths(&self.extern_paths),
+            false => self.extern_paths.clone(),
+        };
+        let codegen = extern_paths
+            .iter()
+            .fold(codegen, |codegen, (proto_path, rust_path)| {
+                codegen.extern_path(proto_path, rust_path)
+            });
+
+        let codegen = codegen
+            .build_client(self.build_client)
+            .client_mod_attribute(".", r#"#[cfg(feature = "client")]"#)
+            .build_server(self.build_server)
+            .server_mod_attribute(".", r#"#[cfg(feature = "server")]"#)
+            .generate_default_stubs(builder.default_stubs)
+            .build_transport((self.build_client || self.build_server) && !builder.no_transport)
+            .compile_well_known_types(compile_well_known_types)
+            .out_dir(compiled_files_dir);
+
+        let selection = per_service::Selection {
+            overrides: builder.service_sides.clone(),
+            client: self.build_client,
+            server: self.build_server,
+        };
+
+        retry::run(&builder.retry, retries, Error::is_transient, |_| {
+            match selection.overrides.is_empty() {
+                true => codegen.clone().compile_fds(codegen_fds.clone()),
+                // The services are handed to a generator of their own sides instead.
+                false => {
+                    let mut config = tonic_prost_build::Config::new();
+                    config.service_generator(Box::new(
+                        selection.clone().generator(&codegen, !builder.no_transport),
+                    ));
+
+                    codegen
+                        .clone()
+                        .build_client(false)
+                        .build_server(false)
+                        .compile_fds_with_config(codegen_fds.clone(), config)
+                }
+            }
+            .map_err(|e| Error::GenerateCode(e, self.name.clone()))
+        })?;
+
+        Ok(Compiled {
+            rules,
+            string_fields,
+            extern_paths,
+            compile_well_known_types,
+            selection,
+        })
+    }
+
+    /// Append the code of the generators enabled on the `builder` to the `compiled` code in the
+    /// `compiled_files_dir`, e.g. the conformance tests or the pbjson implementations.
+    fn extend(
+        &self,
+        fds: &tonic_prost_build::FileDescriptorSet,
+        compiled_files_dir: &path::Path,
+        compiled: &Compiled,
+        builder: &Builder,
+    ) -> Result<(), Error> {
+        let Compiled {
+            string_fields,
+            extern_paths,
+            selection,
+            ..
+        } = compiled;
+        let (build_client, build_server) = selection.any();
+        let services = services::Codegen {
+            compile_well_known_types: compiled.compile_well_known_types,
+            extern_paths,
+            default_stubs: builder.default_stubs,
+        };
+
+        if builder.conformance_tests && build_client {
+            conformance::write(&selection.clients(fds), compiled_files_dir, &services)?;
+        }
+        if builder.recording_client && build_client {
+            recording::write(&selection.clients(fds), compiled_files_dir, &services)?;
+        }
+        if builder.shadow_client && build_client {
+            shadow::write(&selection.clients(fds), compiled_files_dir, &services)?;
+        }
+        if !builder.message_size_limits.is_empty() && build_server {
+            size_guard::write(
+                &selection.servers(fds),
+                compiled_files_dir,
+                &services,
+                &builder.message_size_limits,
+            )?;
+        }
+        if !builder.sensitive_fields.is_empty() {
+            redact::write(fds, compiled_files_dir, &builder.sensitive_fields)?;
+        }
+        if builder.pbjson {
+            pbjson::write(fds, compiled_files_dir, extern_paths, &builder.btree_map)?;
+        }
+        if !builder.events.is_empty() {
+            events::write(fds, compiled_files_dir, &builder.events)?;
+        }
+        if !builder.row_models.is_empty() {
+            rows::write(
+                fds,
+                compiled_files_dir,
+                &builder.row_models,
+                &builder.bytes,
+                string_fields,
+            )?;
+        }
+        if !builder.field_defaults.is_empty() {
+            defaults::write(fds, compiled_files_dir, &builder.field_defaults)?;
+        }
+        if !builder.encoded_size_reports.is_empty() {
+            encoded_size::write(fds, compiled_files_dir, &builder.encoded_size_reports)?;
+        }
+        if !builder.method_groups.is_empty() && build_server {
+            split::write(
+                &selection.servers(fds),
+                compiled_files_dir,
+                &services,
+                &builder.method_groups,
+            )?;
+        }
+        if !self.internal_messages.is_empty() {
+            internal::write(compiled_files_dir, &self.internal_messages)?;
+        }
+
+        Ok(())
+    }
+
+    /// The files a dry run would write besides the `planned` module files.
+    fn dry_run_files(
+        &self,
+        planned: Vec<path::PathBuf>,
+        module_dir: &path::Path,
+        builder: &Builder,
+    ) -> Vec<path::PathBuf> {
+        let mut files = planned;
+        files.extend(
+            self.outputs()
+                .zip(&self.manifests)
+                .map(|(output, _)| output.join(manifest::FILE)),
+        );
+        if !self.manifests.is_empty() {
+            files.push(self.output.join(semver::DESCRIPTORS));
+        }
+        if builder.reexports {
+            files.push(module_dir.join(reexports::FILE));
+        }
+        if builder.descriptor_endpoint {
+            files.push(module_dir.join(descriptor_endpoint::FILE));
+        }
+        if builder.wire_compat_tests.is_some() {
+            files.extend(wire_compat::files(module_dir));
+        }
+        if let Some(name) = &builder.root_file {
+            files.push(module_dir.join(name));
+        }
+        if self.effective_config.is_some() {
+            files.push(self.output.join(config::EFFECTIVE_PATH));
+        }
+
+        files
+    }
+
+    /// Write the module trees of the code in the `compiled_files_dir` into the outputs, along with
+    /// the other files enabled on the `builder`, e.g. the manifests or the root file, returning the
+    /// paths of every written file.
+    fn write(
+        &self,
+        fds: &tonic_prost_build::FileDescriptorSet,
+        compiled_files_dir: &path::Path,
+        input: &modgen::Input,
+        visibilities: &modgen::Visibilities,
+        builder: &Builder,
+    ) -> Result<Vec<path::PathBuf>, Error> {
+        let module_dir = builder.layout.module_dir(&self.output);
+
+        let mut files = modgen::modularize_packages(
+            compiled_files_dir,
+            &self.output,
+            &self.package_outputs,
+            input,
+            builder.layout,
+            visibilities,
+            &self.header,
+        )?;
+        files.extend(
+            self.outputs()
+                .zip(&self.manifests)
+                .map(|(output, manifest)| manifest::write(output, manifest))
+                .collect::<Result<Vec<_>, _>>()?,
+        );
+        // The crate records its schema, the baseline of the version after it is published.
+        if !self.manifests.is_empty() {
+            files.push(semver::write(&self.output, fds)?);
+        }
+        self.outputs()
+            .zip(&self.manifests)
+            .try_for_each(|(output, _)| {
+                manifest::write_attributes(
+                    &builder
+                        .layout
+                        .module_dir(output)
+                        .join(builder.layout.root_file()),
+                    &self.header,
+                    &builder.crate_metadata.attributes,
+                )
+            })?;
+        if builder.reexports {
+            // The types behind private modules can't be re-exported from the root module.
+            let reexported = prost_types::FileDescriptorSet {
+                file: fds
+                    .file
+                    .iter()
+                    .filter(|file| {
+                        visibilities.is_reachable(&builder.naming.package(file.package()))
+                    })
+                    .cloned()
+                    .collect(),
+            };
+            files.push(reexports::write(
+                &reexported,
+                compiled_files_dir,
+                &module_dir,
+                &self.package_outputs,
+                &self.extern_paths,
+                &builder.naming,
+                builder.layout,
+            )?);
+        }
+        if builder.descriptor_endpoint {
+            files.push(descriptor_endpoint::write(&module_dir, builder.layout)?);
+        }
+        if let Some(previous) = &builder.wire_compat_tests {
+            files.extend(wire_compat::write(
+                fds,
+                previous,
+                &module_dir,
+                builder.layout,
+            )?);
+        }
+        if let Some(name) = &builder.root_file {
+            files.push(root_file::write(
+                &module_dir,
+                name,
+                &builder.root_attributes,
+                builder.layout,
+            )?);
+        }
+        if let Some(snapshot) = &self.effective_config {
+            let path = self.output.join(config::EFFECTIVE_PATH);
+            fs::write(&path, snapshot).map_err(|e| Error::WriteEffectiveConfig(e, path.clone()))?;
+            files.push(path);
+        }
+
+        Ok(files)
+    }
+
+    /// Format the written `files` and rewrite their line endings, then, for the reproducible
+    /// output, check that no machine-specific `paths`, including the ones of the `workdir`, leaked
+    /// into them or into the descriptor set and set their modification time.
+    fn finish(
+        &self,
+        files: &[path::PathBuf],
+        workdir: &path::Path,
+        paths: &[path::PathBuf],
+        descriptor_set_path: Option<&path::Path>,
+        builder: &Builder,
+    ) -> Result<(), Error> {
+        if builder.rustfmt {
+            rustfmt::format(
+                builder.rustfmt_path.as_deref(),
+                builder.rustfmt_edition,
+                files,
+            )?;
+        }
+        if let Some(newline) = builder.newline {
+            newline::apply(files, newline)?;
+        }
+
+        if builder.reproducible || builder.strict {
+            let mut paths = paths.to_vec();
+            paths.extend(reproducible::machine_paths([workdir]));
+
+            self.outputs()
+                .try_for_each(|output| reproducible::check_dir(output, &paths))?;
+            if let Some(path) = descriptor_set_path {
+                reproducible::check_file(path, &paths)?;
+            }
+
+            tracing::info!("Verified that the generated code contains no machine-specific paths");
+
+            if let Some(mtime) = reproducible::source_date_epoch()? {
+                self.outputs()
+                    .try_for_each(|output| reproducible::set_mtimes(output, mtime))?;
+                if let Some(path) = descriptor_set_path {
+                    reproducible::set_mtime(path, mtime)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that the code written into the `module_dir` compiles, if enabled, returning how long
+    /// it took.
+    fn verify(
+        &self,
+        workdir: &path::Path,
+        module_dir: &path::Path,
+        selection: &per_service::Selection,
+        builder: &Builder,
+    ) -> Result<Option<time::Duration>, Error> {
+        if !self.verify_build && !self.verify_feature_matrix {
+            return Ok(None);
+        }
+
+        // The toolchain of the MSRV is picked by the rustup proxy rather than the cargo running
+        // pbuildrs.
+        let cargo = match self.msrv {
+            Some(_) => ffi::OsString::from("cargo"),
+            None => env::var_os("CARGO").unwrap_or_else(|| ffi::OsString::from("cargo")),
+        };
+
+        let features = if self.verify_feature_matrix {
+            verify::Features::matrix().to_vec()
+        } else {
+            let (client, server) = selection.any();
+            vec![verify::Features { client, server }]
+        };
+
+        let started = time::Instant::now();
+        verify::verify_build(
+            &cargo,
+            &workdir.join("verify").join(&self.name),
+            &workdir.join("verify-target"),
+            // The root file is checked along with its attributes when there is one.
+            &module_dir.join(
+                builder
+                    .root_file
+                    .as_deref()
+                    .unwrap_or(builder.layout.root_file()),
+            ),
+            &features,
+            verify::Scaffold {
+                rust_version: self.msrv.as_ref(),
+                serde: builder.serde_support(),
+            },
+            builder.offline,
+        )?;
+
+        Ok(Some(started.elapsed()))
+    }
+
+    /// Generate the code of this target from the `fds`: compile it with prost in the `workdir`,
+    /// extend it with the enabled generators, assemble it into the module trees of the outputs,
+    /// then format, check and verify the written files.
+    pub(super) fn generate(
+        self,
+        fds: &tonic_prost_build::FileDescriptorSet,
+        workdir: &path::Path,
+        paths: &[path::PathBuf],
+        descriptor_set_path: Option<&path::Path>,
+        builder: &Builder,
+        retries: &mut u32,
+    ) -> Result<TargetReport, Error> {
+        tracing::info!("Generating the `{}` target", self.name);
+
+        let compiled_files_dir = workdir.join("code").join(&self.name);
+        fs::create_dir_all(&compiled_files_dir)
+            .map_err(|e| Error::MkTempCompileDir(e, compiled_files_dir.clone()))?;
+        tracing::debug!(
+            "Created temporary directory for generated source code: {}",
+            compiled_files_dir.display()
+        );
+
+        let compiled = self.compile(fds, &compiled_files_dir, builder, retries)?;
+        self.extend(fds, &compiled_files_dir, &compiled, builder)?;
+        let Compiled {
+            rules, selection, ..
+        } = compiled;
+
+        let packages = size::measure(&compiled_files_dir)?;
+        if !builder.naming.is_empty() {
+            naming::apply(&compiled_files_dir, &builder.naming)?;
+        }
+        let input = modgen::Input {
+            walk: exclude::Exclusions::default().follow_symlinks(builder.follow_symlinks),
+            modules: builder
+                .descriptor_tree
+                .then(|| naming::package_modules(fds, &builder.naming)),
+            protos: naming::package_protos(fds, &builder.naming),
+        };
+        let module_dir = builder.layout.module_dir(&self.output);
+        // The packages are declared with their visibility once their modules are renamed.
+        let visibilities = modgen::Visibilities {
+            packages: builder
+                .visibilities
+                .packages
+                .iter()
+                .map(|(package, visibility)| (builder.naming.package(package), *visibility))
+                .collect(),
+            attributes: rules
+                .modules
+                .into_iter()
+                .map(|(package, attributes)| (builder.naming.package(&package), attributes))
+                .collect(),
+            ..builder.visibilities.clone()
+        };
+
+        let planned = modgen::plan(
+            &compiled_files_dir,
+            &self.output,
+            &self.package_outputs,
+            &input,
+            builder.layout,
+        )?;
+        // The reserved names are rejected on every platform, so a tree generated elsewhere can still
+        // be checked out on Windows, while the path length is only limited there.
+        portable::check(
+            &self.outputs().collect::<Vec<_>>(),
+            &planned,
+            cfg!(windows).then_some(portable::MAX_PATH),
+        )?;
+
+        if builder.dry_run {
+            let files = self.dry_run_files(planned, &module_dir, builder);
+
+            return Ok(TargetReport {
+                name: self.name,
+                output: self.output,
+                files,
+                packages,
+                compile_time: None,
+            });
+        }
+
+        let files = retry::run(&builder.retry, retries, Error::is_transient, |attempt| {
+            // The module files are created exclusively, so a partially written tree is cleaned up
+            // before trying again.
+            if attempt > 0 {
+                self.prepare_output()?;
+            }
+
+            self.write(fds, &compiled_files_dir, &input, &visibilities, builder)
+        })?;
+
+        self.finish(&files, workdir, paths, descriptor_set_path, builder)?;
+        let compile_time = self.verify(workdir, &module_dir, &selection, builder)?;
+
+        Ok(TargetReport {
+            name: self.name,
+            output: self.output,
+            files,
+            packages,
+            compile_time,
+        })
+    }
+}
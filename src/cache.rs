@@ -0,0 +1,373 @@
+use sha2::Digest;
+use std::{collections, fs, io, path};
+
+use crate::staging;
+
+/// The name of the cache file used when no other path is configured.
+pub const DEFAULT_PATH: &str = ".pbuildrs-cache.json";
+
+/// The file listing the hashes of every file in an entry of the store, checked before the entry
+/// is restored.
+const INDEX: &str = "index.json";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to read the files from `{1}`: {0}")]
+    ReadDir(walkdir::Error, path::PathBuf),
+    #[error("Failed to read the file `{1}`: {0}")]
+    ReadFile(io::Error, path::PathBuf),
+    #[error("Failed to read the cache file `{1}`: {0}")]
+    Load(io::Error, path::PathBuf),
+    #[error("Failed to write the cache file `{1}`: {0}")]
+    Store(io::Error, path::PathBuf),
+    #[error("Failed to serialize the cache: {0}")]
+    Serialize(serde_json::Error),
+    #[error("Failed to copy `{1}` into the cache entry: {0}")]
+    Save(io::Error, path::PathBuf),
+    #[error("Failed to restore `{1}` from the cache entry: {0}")]
+    Restore(io::Error, path::PathBuf),
+    #[error(transparent)]
+    Staging(#[from] staging::Error),
+}
+
+/// Content hashes of everything a run depends on and produces.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug)]
+pub struct Fingerprint {
+    /// The hash of the effective configuration and the version of pbuildrs.
+    pub config: String,
+    /// The hash of the `protoc` binary the protobuf files are compiled with.
+    pub protoc: String,
+    /// The hashes of every protobuf file in the source and include directories.
+    pub inputs: collections::BTreeMap<path::PathBuf, String>,
+    /// The hashes of every generated file.
+    pub outputs: collections::BTreeMap<path::PathBuf, String>,
+}
+
+fn hash(contents: &[u8]) -> String {
+    format!("{:x}", sha2::Sha256::digest(contents))
+}
+
+/// Hash the `config` string together with the version of pbuildrs, so upgrading it invalidates the
+/// cache.
+pub fn hash_config(config: &str) -> String {
+    hash(format!("{}\n{config}", env!("CARGO_PKG_VERSION")).as_bytes())
+}
+
+/// Hash the `protoc` binary, so replacing it, e.g. with another version, invalidates the cache.
+/// A binary that can't be read, e.g. one only found by the remote execution backend, is hashed by
+/// its path instead.
+pub fn hash_protoc(protoc: &path::Path) -> String {
+    match fs::read(protoc) {
+        Ok(contents) => hash(&contents),
+        Err(e) => {
+            tracing::debug!(
+                "Failed to read `{}`, hashing its path: {e}",
+                protoc.display()
+            );
+            hash(protoc.as_os_str().as_encoded_bytes())
+        }
+    }
+}
+
+/// Hash every file matching the `filter` under any of the `paths`, which can be either
+/// directories or files. Missing paths are skipped.
+pub fn hash_files(
+    paths: &[&path::Path],
+    filter: impl Fn(&path::Path) -> bool,
+) -> Result<collections::BTreeMap<path::PathBuf, String>, Error> {
+    paths
+        .iter()
+        .filter(|path| path.exists())
+        .flat_map(|root| {
+            walkdir::WalkDir::new(root)
+                .into_iter()
+                .map(move |entry| entry.map_err(|e| Error::ReadDir(e, root.to_path_buf())))
+        })
+        .filter(|entry| {
+            entry.as_ref().map_or(true, |entry| {
+                entry.file_type().is_file() && filter(entry.path())
+            })
+        })
+        .map(|entry| {
+            let path = entry?.into_path();
+            let contents = fs::read(&path).map_err(|e| Error::ReadFile(e, path.clone()))?;
+
+            Ok((path, hash(&contents)))
+        })
+        .collect()
+}
+
+/// Combine the `hashes` of several files into a single one, e.g. to tell whether any of them
+/// changed.
+pub fn hash_all(hashes: &collections::BTreeMap<path::PathBuf, String>) -> String {
+    hash(
+        hashes
+            .iter()
+            .map(|(path, hash)| format!("{}\0{hash}\n", path.display()))
+            .collect::<String>()
+            .as_bytes(),
+    )
+}
+
+/// Load the fingerprint of the previous run. A missing or unreadable cache is treated as if
+/// there was no previous run.
+pub fn load(file: &path::Path) -> Result<Option<Fingerprint>, Error> {
+    match fs::read(file) {
+        Ok(contents) => Ok(serde_json::from_slice(&contents).ok()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::Load(e, file.to_path_buf())),
+    }
+}
+
+pub fn store(file: &path::Path, fingerprint: &Fingerprint) -> Result<(), Error> {
+    let contents = serde_json::to_vec_pretty(fingerprint).map_err(Error::Serialize)?;
+
+    fs::write(file, contents).map_err(|e| Error::Store(e, file.to_path_buf()))
+}
+
+/// The key of the entry in the store holding the code generated from the `inputs` with the
+/// `config` and the `protoc` binary with the hash. The paths in both the `config` and the `inputs`
+/// have to be relative, so the runs in different checkouts share it.
+pub fn key(
+    config: &str,
+    protoc: &str,
+    inputs: &collections::BTreeMap<path::PathBuf, String>,
+) -> String {
+    hash(format!("{}\n{protoc}\n{}", hash_config(config), hash_all(inputs)).as_bytes())
+}
+
+/// Copy the file or the directory `from` into `to`.
+fn copy(from: &path::Path, to: &path::Path) -> io::Result<()> {
+    if from.is_file() {
+        return fs::copy(from, to).map(|_| ());
+    }
+
+    walkdir::WalkDir::new(from)
+        .into_iter()
+        .try_for_each(|entry| {
+            let entry = entry?;
+            let target = to.join(entry.path().strip_prefix(from).unwrap_or(entry.path()));
+
+            match entry.file_type().is_dir() {
+                true => fs::create_dir_all(target),
+                false => fs::copy(entry.path(), target).map(|_| ()),
+            }
+        })
+}
+
+/// The hashes of the files in the `entry`, relative to it, apart from its index.
+fn hash_entry(entry: &path::Path) -> Result<collections::BTreeMap<path::PathBuf, String>, Error> {
+    let index = entry.join(INDEX);
+
+    Ok(hash_files(&[entry], |path| path != index)?
+        .into_iter()
+        .map(|(path, hash)| {
+            let path = path.strip_prefix(entry).map(path::Path::to_path_buf);
+            (path.unwrap_or_default(), hash)
+        })
+        .collect())
+}
+
+/// Whether the `entry` holds exactly the files listed in its index, so a partially copied or
+/// tampered entry is never restored.
+fn is_intact(entry: &path::Path) -> Result<bool, Error> {
+    let index = entry.join(INDEX);
+    let contents = fs::read(&index).map_err(|e| Error::Load(e, index))?;
+
+    match serde_json::from_slice::<collections::BTreeMap<path::PathBuf, String>>(&contents) {
+        Ok(hashes) => Ok(hashes == hash_entry(entry)?),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Find the intact entry with the `key` in the first of the `stores` holding it.
+pub fn find(stores: &[&path::Path], key: &str) -> Option<path::PathBuf> {
+    stores
+        .iter()
+        .map(|store| store.join(key))
+        .filter(|entry| entry.is_dir())
+        .find(|entry| match is_intact(entry) {
+            Ok(true) => true,
+            Ok(false) => {
+                tracing::warn!("Ignoring the corrupted cache entry `{}`", entry.display());
+                false
+            }
+            Err(e) => {
+                tracing::warn!("Ignoring the cache entry `{}`: {e}", entry.display());
+                false
+            }
+        })
+}
+
+/// Copy the `outputs` of a run into the entry with the `key` in the `store`. The entry is assembled
+/// next to its final location and moved into place once complete, so the concurrent runs sharing
+/// the store never see it partially written.
+pub fn save(store: &path::Path, key: &str, outputs: &[&path::Path]) -> Result<(), Error> {
+    let save = |e, path: &path::Path| Error::Save(e, path.to_path_buf());
+    let entry = store.join(key);
+    if entry.exists() {
+        return Ok(());
+    }
+
+    fs::create_dir_all(store).map_err(|e| save(e, store))?;
+    let mut staged = tempfile::Builder::new()
+        .prefix(".pbuildrs-")
+        .tempdir_in(store)
+        .map_err(|e| save(e, store))?;
+
+    outputs
+        .iter()
+        .enumerate()
+        .filter(|(_, output)| output.exists())
+        .try_for_each(|(idx, output)| {
+            copy(output, &staged.path().join(idx.to_string())).map_err(|e| save(e, output))
+        })?;
+
+    let index = serde_json::to_vec_pretty(&hash_entry(staged.path())?).map_err(Error::Serialize)?;
+    fs::write(staged.path().join(INDEX), index).map_err(|e| save(e, &entry))?;
+
+    match fs::rename(staged.path(), &entry) {
+        Ok(()) => {
+            staged.disable_cleanup(true);
+            Ok(())
+        }
+        // Another run stored the same entry in the meantime.
+        Err(_) if entry.is_dir() => Ok(()),
+        Err(e) => Err(save(e, &entry)),
+    }
+}
+
+/// Replace the `outputs` with their copies in the `entry`, staged next to them first, so a failure
+/// leaves the previous outputs intact. The outputs can only be replaced when generated by pbuildrs,
+/// unless the replacement is `force`d.
+pub fn restore(entry: &path::Path, outputs: &[&path::Path], force: bool) -> Result<(), Error> {
+    let mut staged_dirs = vec![];
+    let mut staged_files = vec![];
+
+    outputs
+        .iter()
+        .enumerate()
+        .map(|(idx, output)| (entry.join(idx.to_string()), output))
+        .filter(|(copy, _)| copy.exists())
+        .try_for_each(|(from, output)| {
+            let restore = |e| Error::Restore(e, output.to_path_buf());
+
+            if from.is_dir() {
+                let staged = staging::Staged::new(output, force)?;
+                copy(&from, staged.path()).map_err(restore)?;
+                staged_dirs.push(staged);
+
+                return Ok(());
+            }
+
+            let parent = output
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .unwrap_or(path::Path::new("."));
+            fs::create_dir_all(parent).map_err(restore)?;
+            let staged = tempfile::NamedTempFile::new_in(parent).map_err(restore)?;
+            copy(&from, staged.path()).map_err(restore)?;
+            staged_files.push((staged, *output));
+
+            Ok::<_, Error>(())
+        })?;
+
+    staging::commit(staged_dirs)?;
+    staged_files.into_iter().try_for_each(|(staged, output)| {
+        staged
+            .persist(output)
+            .map(|_| ())
+            .map_err(|e| Error::Restore(e.error, output.to_path_buf()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections, fs, path};
+
+    #[test]
+    fn hash_files_detects_changes() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let proto = dir.path().join("crabs.proto");
+        fs::write(&proto, "syntax = \"proto3\";\n").expect("Failed to create a test file");
+        fs::write(dir.path().join("README.md"), "Crabs\n").expect("Failed to create a test file");
+
+        let is_proto = |path: &path::Path| path.extension().is_some_and(|ext| ext == "proto");
+        let hashes =
+            super::hash_files(&[dir.path()], is_proto).expect("Failed to hash the test files");
+        assert_eq!(
+            hashes.keys().collect::<Vec<_>>(),
+            [&proto],
+            "Expected only the matching files to be hashed",
+        );
+
+        fs::write(&proto, "edition = \"2023\";\n").expect("Failed to modify a test file");
+        assert_ne!(
+            super::hash_files(&[dir.path()], is_proto).expect("Failed to hash the test files"),
+            hashes,
+            "Expected the modified file to change the hash",
+        );
+    }
+
+    #[test]
+    fn key_depends_on_the_protoc_binary() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let (old, new) = (dir.path().join("protoc-33"), dir.path().join("protoc-34"));
+        fs::write(&old, "libprotoc 33.0").expect("Failed to create a test binary");
+        fs::write(&new, "libprotoc 34.0").expect("Failed to create a test binary");
+
+        let inputs = collections::BTreeMap::from([(
+            path::PathBuf::from("proto/crabs.proto"),
+            "abc".to_string(),
+        )]);
+        let key = |protoc: &path::Path| super::key("", &super::hash_protoc(protoc), &inputs);
+
+        assert_eq!(key(&old), key(&old), "Expected the key to be stable");
+        assert_ne!(
+            key(&old),
+            key(&new),
+            "Expected another protoc binary to change the key",
+        );
+        assert_ne!(
+            key(&dir.path().join("missing")),
+            key(&old),
+            "Expected a missing protoc binary to be hashed by its path",
+        );
+    }
+
+    #[test]
+    fn store_and_load_round_trip() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let file = dir.path().join(super::DEFAULT_PATH);
+
+        assert_eq!(
+            super::load(&file).expect("Failed to load a missing cache"),
+            None,
+            "Expected a missing cache to be empty",
+        );
+
+        let fingerprint = super::Fingerprint {
+            config: super::hash_config("build-client = true"),
+            protoc: super::hash_protoc(path::Path::new("/usr/bin/protoc")),
+            inputs: collections::BTreeMap::from([(
+                path::PathBuf::from("proto/crabs.proto"),
+                "abc".to_string(),
+            )]),
+            outputs: collections::BTreeMap::new(),
+        };
+        super::store(&file, &fingerprint).expect("Failed to store the cache");
+
+        assert_eq!(
+            super::load(&file).expect("Failed to load the cache"),
+            Some(fingerprint),
+            "Expected the loaded cache to match the stored one",
+        );
+
+        fs::write(&file, "{").expect("Failed to corrupt the cache");
+        assert_eq!(
+            super::load(&file).expect("Failed to load a corrupted cache"),
+            None,
+            "Expected a corrupted cache to be ignored",
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use std::{collections, fmt, fs, io, path};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to read the files from `{1}`: {0}")]
+    ReadDir(walkdir::Error, path::PathBuf),
+    #[error("Failed to read the file `{1}`: {0}")]
+    ReadFile(io::Error, path::PathBuf),
+}
+
+/// A file that differs between the freshly generated code and the existing output.
+#[derive(PartialEq, Debug)]
+pub enum Difference {
+    /// The file is generated, but missing from the output.
+    Missing(path::PathBuf),
+    /// The file is in the output, but no longer generated.
+    Unexpected(path::PathBuf),
+    /// The file in the output has different contents.
+    Modified(path::PathBuf),
+}
+
+impl Difference {
+    pub fn path(&self) -> &path::Path {
+        match self {
+            Self::Missing(path) | Self::Unexpected(path) | Self::Modified(path) => path,
+        }
+    }
+
+    /// How the file differs, e.g. `missing`.
+    pub fn change(&self) -> &'static str {
+        match self {
+            Self::Missing(_) => "missing",
+            Self::Unexpected(_) => "unexpected",
+            Self::Modified(_) => "modified",
+        }
+    }
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.change(), self.path().display())
+    }
+}
+
+fn list_files(dir: &path::Path) -> Result<collections::BTreeSet<path::PathBuf>, Error> {
+    if !dir.exists() {
+        return Ok(collections::BTreeSet::new());
+    }
+
+    walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|entry| match entry {
+            Ok(entry) if entry.file_type().is_dir() => None,
+            Ok(entry) => entry
+                .path()
+                .strip_prefix(dir)
+                .ok()
+                .map(|path| Ok(path.to_path_buf())),
+            Err(e) => Some(Err(Error::ReadDir(e, dir.to_path_buf()))),
+        })
+        .collect()
+}
+
+fn read(file: &path::Path) -> Result<Option<Vec<u8>>, Error> {
+    match fs::read(file) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::ReadFile(e, file.to_path_buf())),
+    }
+}
+
+/// Compare the `expected` file with the `actual` one.
+pub fn compare_file(
+    expected: &path::Path,
+    actual: &path::Path,
+) -> Result<Option<Difference>, Error> {
+    Ok(match (read(expected)?, read(actual)?) {
+        (Some(expected), Some(actual)) if expected == actual => None,
+        (Some(_), Some(_)) => Some(Difference::Modified(actual.to_path_buf())),
+        (Some(_), None) => Some(Difference::Missing(actual.to_path_buf())),
+        (None, Some(_)) => Some(Difference::Unexpected(actual.to_path_buf())),
+        (None, None) => None,
+    })
+}
+
+/// Compare every file in the `expected` directory with the `actual` one, returning the
+/// differences sorted by the path in the `actual` directory.
+pub fn compare_dir(expected: &path::Path, actual: &path::Path) -> Result<Vec<Difference>, Error> {
+    let files = list_files(expected)?
+        .into_iter()
+        .chain(list_files(actual)?)
+        .collect::<collections::BTreeSet<_>>();
+
+    files
+        .into_iter()
+        .filter_map(|file| compare_file(&expected.join(&file), &actual.join(&file)).transpose())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    #[test]
+    fn compare_dir_reports_every_difference() {
+        let expected = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let actual = tempfile::TempDir::new().expect("Failed to create a test directory");
+
+        fs::create_dir_all(expected.path().join("crabs"))
+            .expect("Failed to create a test directory");
+        fs::create_dir_all(actual.path().join("crabs")).expect("Failed to create a test directory");
+
+        [
+            (expected.path(), "mod.rs", "pub mod crabs;\n"),
+            (actual.path(), "mod.rs", "pub mod crabs;\n"),
+            (expected.path(), "crabs/mod.rs", "struct Ferris;\n"),
+            (actual.path(), "crabs/mod.rs", "struct Ferris {}\n"),
+            (expected.path(), "crabs/new.rs", "struct New;\n"),
+            (actual.path(), "crabs/old.rs", "struct Old;\n"),
+        ]
+        .into_iter()
+        .for_each(|(dir, file, contents)| {
+            fs::write(dir.join(file), contents).expect("Failed to create a test file")
+        });
+
+        let differences = super::compare_dir(expected.path(), actual.path())
+            .expect("Failed to compare the directories");
+
+        assert_eq!(
+            differences,
+            vec![
+                super::Difference::Modified(actual.path().join("crabs/mod.rs")),
+                super::Difference::Missing(actual.path().join("crabs/new.rs")),
+                super::Difference::Unexpected(actual.path().join("crabs/old.rs")),
+            ],
+            "Invalid differences between the directories",
+        );
+    }
+
+    #[test]
+    fn compare_dir_treats_missing_output_as_empty() {
+        let expected = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let actual = tempfile::TempDir::new().expect("Failed to create a test directory");
+
+        fs::write(expected.path().join("mod.rs"), "struct Ferris;\n")
+            .expect("Failed to create a test file");
+
+        let differences = super::compare_dir(expected.path(), &actual.path().join("missing"))
+            .expect("Failed to compare the directories");
+
+        assert_eq!(
+            differences,
+            vec![super::Difference::Missing(
+                actual.path().join("missing/mod.rs")
+            )],
+            "Expected every generated file to be missing",
+        );
+    }
+}
@@ -1,120 +1,1934 @@
-use std::{fs, io, path};
+use std::{collections, fs, io, iter, mem, path, process, time};
+use tracing_subscriber::filter;
 
-use crate::modgen;
+use crate::{
+    Builder, attributes, builder, cache, check, config, defaults, descriptor, events, execution,
+    failure_report, history, lookup, manifest, message, modgen, naming, newline, patcher,
+    per_service, reachability, registry, release, rename, rows, rustfmt, sandbox, size_guard,
+    split, usage, verify, verify_patch, watch,
+};
+
+/// The exit code of `pbuildrs patch` when the edition of the protobuf file was replaced.
+pub const REPLACED_EXIT_CODE: u8 = 3;
 
 /// Compile protobuf files into properly structured Rust code with modules using the Prost compiler.
 #[derive(clap::Parser)]
-#[command(version, about)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Compile the protobuf files, the default when no command is given
+    Build(Box<Args>),
+    /// Suggest which generated packages and features a crate needs, based on the types it uses
+    Suggest(SuggestArgs),
+    /// Patch the edition of a single protobuf file, or of the standard input for `-`, and write
+    /// the result to the standard output. Exits with 3 when the edition was replaced
+    Patch(PatchArgs),
+    /// Show the history of the previous runs recorded with `--history`, oldest first
+    History(HistoryArgs),
+    /// Print where the code of a protobuf message, enum or service is generated, given its fully
+    /// qualified name, e.g. `crabs.Ferris`
+    Where(WhereArgs),
+    /// Rename a protobuf package, or a message, enum or service within its scope, rewriting every
+    /// reference to it in the protobuf files, and print how the Rust paths change
+    Rename(RenameArgs),
+    /// Verify that a patched tree of protobuf files, or a single patched file, is exactly its
+    /// source with the editions replaced, re-running the edition detection on every source file
+    VerifyPatch(VerifyPatchArgs),
+    /// Regenerate the crates, verify that they compile and version them after the schema changes
+    /// since their published versions, optionally checking that they can be published
+    Release(Box<ReleaseArgs>),
+}
+
+#[derive(clap::Args)]
+struct ReleaseArgs {
+    /// Check that every generated crate can be published with `cargo publish --dry-run`
+    #[arg(long, default_value_t = false)]
+    publish_dry_run: bool,
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(clap::Args)]
+struct VerifyPatchArgs {
+    /// The source directory of the protobuf files, or a single protobuf file
+    src: path::PathBuf,
+    /// The patched directory, or the single patched file
+    patched: path::PathBuf,
+    /// The syntax the editions were replaced with
+    #[arg(long, value_enum, default_value_t)]
+    target_syntax: patcher::Syntax,
+    /// Accept the editions other than the known ones as replaced, like `--allow-unknown-editions`
+    #[arg(long, default_value_t = false)]
+    allow_unknown_editions: bool,
+}
+
+#[derive(clap::Args)]
+struct RenameArgs {
+    /// Load the source directories and the module naming rules from a configuration file,
+    /// `pbuildrs.toml` in the current directory is used by default if it exists
+    #[arg(long, short = 'c')]
+    config: Option<path::PathBuf>,
+    /// The fully qualified protobuf name to rename, e.g. `corp.old` or `corp.old.Ferris`
+    #[arg(long)]
+    from: String,
+    /// The new fully qualified protobuf name, e.g. `corp.new` or `corp.old.Crab`
+    #[arg(long)]
+    to: String,
+    /// Only print the files that would be rewritten, without writing them
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+    /// The source directories of the protobuf files
+    sources: Vec<path::PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct WhereArgs {
+    /// Load the options from a configuration file, `pbuildrs.toml` in the current directory is used
+    /// by default if it exists
+    #[arg(long, short = 'c')]
+    config: Option<path::PathBuf>,
+    /// The file descriptor set written by `--with-file-descriptor-set`, which the definitions are
+    /// looked up in
+    #[arg(long)]
+    descriptor_set: Option<path::PathBuf>,
+    /// The directory the code was generated into [default: out]
+    #[arg(long)]
+    output: Option<path::PathBuf>,
+    /// The fully qualified protobuf name of the definition
+    name: String,
+}
+
+#[derive(clap::Args)]
+struct HistoryArgs {
+    /// Load the history file from a configuration file, `pbuildrs.toml` in the current directory
+    /// is used by default if it exists
+    #[arg(long, short = 'c')]
+    config: Option<path::PathBuf>,
+    /// The file recording the history of the runs [default: .pbuildrs-history.json]
+    #[arg(long)]
+    history_file: Option<path::PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct PatchArgs {
+    /// The protobuf file to patch, `-` reads it from the standard input
+    input: path::PathBuf,
+    /// The syntax the edition is replaced with
+    #[arg(long, value_enum, default_value_t)]
+    target_syntax: patcher::Syntax,
+    /// Replace the editions other than the known ones with a warning instead of failing
+    #[arg(long, default_value_t = false)]
+    allow_unknown_editions: bool,
+}
+
+#[derive(clap::Args)]
+struct SuggestArgs {
+    /// Load the output directory from a configuration file, `pbuildrs.toml` in the current
+    /// directory is used by default if it exists
+    #[arg(long, short = 'c')]
+    config: Option<path::PathBuf>,
+    /// The directory the code was generated into [default: out]
+    #[arg(long)]
+    output: Option<path::PathBuf>,
+    /// The source directories of the crates using the generated code
+    #[arg(required = true)]
+    sources: Vec<path::PathBuf>,
+}
+
+impl Cli {
+    pub fn run(self) -> Result<process::ExitCode, Error> {
+        let args = match self.command {
+            Some(Command::Build(args)) => *args,
+            Some(Command::Suggest(args)) => {
+                return suggest(args).map(|_| process::ExitCode::SUCCESS);
+            }
+            Some(Command::Patch(args)) => return patch(args),
+            Some(Command::History(args)) => {
+                return history(args).map(|_| process::ExitCode::SUCCESS);
+            }
+            Some(Command::Where(args)) => {
+                return lookup(args).map(|_| process::ExitCode::SUCCESS);
+            }
+            Some(Command::Rename(args)) => {
+                return rename(args).map(|_| process::ExitCode::SUCCESS);
+            }
+            Some(Command::VerifyPatch(args)) => {
+                return verify_patch(args).map(|_| process::ExitCode::SUCCESS);
+            }
+            Some(Command::Release(args)) => {
+                return release(*args).map(|_| process::ExitCode::SUCCESS);
+            }
+            None => self.args,
+        };
+
+        args.init_logging();
+
+        run(args).map(|_| process::ExitCode::SUCCESS)
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Default, PartialEq, Debug)]
+enum MessageFormat {
+    /// Progress messages meant to be read by humans
+    #[default]
+    Human,
+    /// A line of JSON for every event
+    Json,
+}
+
+/// The negations of the flags of [`Args`] the configuration file can enable, e.g.
+/// `--no-build-client`, or `--transport` for `--no-transport`, each overriding the flag when given
+/// after it.
+#[derive(clap::Args, Clone, Copy, Default)]
+struct Negations {
+    #[arg(long, overrides_with = "build_client", hide = true)]
+    no_build_client: bool,
+    #[arg(long, overrides_with = "build_server", hide = true)]
+    no_build_server: bool,
+    #[arg(long, overrides_with = "messages_only", hide = true)]
+    no_messages_only: bool,
+    #[arg(long, overrides_with = "no_transport", hide = true)]
+    transport: bool,
+    #[arg(long, overrides_with = "with_well_known_types", hide = true)]
+    no_with_well_known_types: bool,
+    #[arg(long, overrides_with = "verify_build", hide = true)]
+    no_verify_build: bool,
+    #[arg(long, overrides_with = "verify_feature_matrix", hide = true)]
+    no_verify_feature_matrix: bool,
+    #[arg(long, overrides_with = "reproducible", hide = true)]
+    no_reproducible: bool,
+    #[arg(long, overrides_with = "descriptor_tree", hide = true)]
+    no_descriptor_tree: bool,
+    #[arg(long, overrides_with = "rustfmt", hide = true)]
+    no_rustfmt: bool,
+    #[arg(long, overrides_with = "incremental", hide = true)]
+    no_incremental: bool,
+    #[arg(long, overrides_with = "history", hide = true)]
+    no_history: bool,
+    #[arg(long, overrides_with = "failure_report", hide = true)]
+    no_failure_report: bool,
+    #[arg(long, overrides_with = "allow_unknown_editions", hide = true)]
+    no_allow_unknown_editions: bool,
+    #[arg(long, overrides_with = "pbjson", hide = true)]
+    no_pbjson: bool,
+    #[arg(long, overrides_with = "fail_on_import_cycles", hide = true)]
+    no_fail_on_import_cycles: bool,
+    #[arg(long, overrides_with = "strict", hide = true)]
+    no_strict: bool,
+    #[arg(long, overrides_with = "allow_missing_descriptor_set", hide = true)]
+    no_allow_missing_descriptor_set: bool,
+    #[arg(long, overrides_with = "offline", hide = true)]
+    no_offline: bool,
+    #[arg(long, overrides_with = "default_stubs", hide = true)]
+    no_default_stubs: bool,
+    #[arg(long, overrides_with = "reexports", hide = true)]
+    no_reexports: bool,
+    #[arg(long, overrides_with = "descriptor_endpoint", hide = true)]
+    no_descriptor_endpoint: bool,
+    #[arg(long, overrides_with = "effective_config", hide = true)]
+    no_effective_config: bool,
+    #[arg(long, overrides_with = "conformance_tests", hide = true)]
+    no_conformance_tests: bool,
+    #[arg(long, overrides_with = "recording_client", hide = true)]
+    no_recording_client: bool,
+    #[arg(long, overrides_with = "shadow_client", hide = true)]
+    no_shadow_client: bool,
+    #[arg(long, overrides_with = "respect_gitignore", hide = true)]
+    no_respect_gitignore: bool,
+    #[arg(long, overrides_with = "follow_symlinks", hide = true)]
+    no_follow_symlinks: bool,
+    #[arg(long, overrides_with = "serde", hide = true)]
+    no_serde: bool,
+}
+
+#[derive(clap::Args, Default)]
 pub struct Args {
+    /// Load the options from a configuration file, `pbuildrs.toml` in the current directory is used
+    /// by default if it exists. The command line arguments take precedence over the file, and the
+    /// flags it enables are turned off with their `--no-` negations, e.g. `--no-build-client`
+    #[arg(long, short = 'c')]
+    config: Option<path::PathBuf>,
+    #[command(flatten)]
+    negations: Negations,
     /// Whether to generate the gRPC client code
     #[arg(long, default_value_t = false)]
     build_client: bool,
     /// Whether to generate the gRPC server stubs
     #[arg(long, default_value_t = false)]
     build_server: bool,
+    /// Generate the messages only with the plain prost code generation, without any service code
+    /// or tonic dependency, failing if a client or a server is requested
+    #[arg(long, default_value_t = false)]
+    messages_only: bool,
+    /// Generate the clients and servers without the tonic transport, e.g. for the clients compiled
+    /// to `wasm32-unknown-unknown`
+    #[arg(long, default_value_t = false)]
+    no_transport: bool,
     /// Specify whether to build the well-known types
     #[arg(long, default_value_t = false)]
     with_well_known_types: bool,
     /// Add a directory to the Protobuf import path (can be specified multiple times)
     #[arg(long, short = 'I')]
     include_path: Vec<path::PathBuf>,
-    /// Specify the output path for the compiled files
-    #[arg(long, default_value = "out")]
-    output: path::PathBuf,
+    /// Specify the output path for the compiled files [default: out]
+    #[arg(long)]
+    output: Option<path::PathBuf>,
     /// Specify a path where to create a temporary working directory
     #[arg(long)]
     temp_dir: Option<path::PathBuf>,
     /// Generate a file descriptor set and store it at the location provided in this argument
     #[arg(long)]
     with_file_descriptor_set: Option<path::PathBuf>,
-    /// Specify the source path of the protobuf files to compile
+    /// Strip this directory prefix from the file names recorded in the file descriptor set
+    #[arg(long)]
+    descriptor_strip_prefix: Option<String>,
+    /// Place the file names recorded in the file descriptor set under this virtual root directory
+    #[arg(long)]
+    descriptor_root: Option<String>,
+    /// Merge the protobuf files of this file descriptor set, e.g. exported from a schema registry,
+    /// into the compiled ones, generating the types of every package into a single module (can be
+    /// specified multiple times)
+    #[arg(long, value_name = "FILE")]
+    descriptor_set_in: Vec<path::PathBuf>,
+    /// Verify that the generated code compiles by running `cargo check` on a throwaway crate
+    #[arg(long, default_value_t = false)]
+    verify_build: bool,
+    /// Verify that the generated code compiles under every combination of the `client` and
+    /// `server` features
+    #[arg(long, default_value_t = false)]
+    verify_feature_matrix: bool,
+    /// Minimum supported Rust version of the generated code, recorded as the `rust-version` of the
+    /// emitted and the verification crates, at least 1.85 as required by prost and tonic. The
+    /// verification crate is checked with this toolchain, installed with rustup. Requires
+    /// `--verify-build` or `--output-layout crate`
+    #[arg(long)]
+    msrv: Option<verify::RustVersion>,
+    /// Suggest the next version of the generated crate from the schema changes since the one
+    /// published into this directory, failing if `crate-metadata.version` is lower
+    #[arg(long, value_name = "DIR")]
+    semver_baseline: Option<path::PathBuf>,
+    /// Ensure the output is identical across machines: no machine-specific paths embedded into
+    /// the generated files, and their modification time set to `SOURCE_DATE_EPOCH`, if any
+    #[arg(long, default_value_t = false)]
+    reproducible: bool,
+    /// Print `cargo:rerun-if-changed` directives for the compiled protobuf files and include
+    /// directories, for use from a build script
+    #[arg(long, default_value_t = false)]
+    cargo_integration: bool,
+    /// Add an attribute to the generated messages, enums and oneofs matching a protobuf path, e.g.
+    /// `.crabs.Ferris=#[derive(PartialOrd)]` (can be specified multiple times)
+    #[arg(long, value_name = "PATH=ATTRIBUTE")]
+    type_attribute: Vec<config::Attribute>,
+    /// Add an attribute to the generated fields matching a protobuf path, e.g.
+    /// `.crabs.Ferris.name=#[deprecated]` (can be specified multiple times)
+    #[arg(long, value_name = "PATH=ATTRIBUTE")]
+    field_attribute: Vec<config::Attribute>,
+    /// Use an existing Rust type for the protobuf types matching a protobuf path instead of
+    /// generating them, e.g. `.google.protobuf=::prost_types` (can be specified multiple times)
+    #[arg(long, value_name = "PROTO_PATH=RUST_PATH")]
+    extern_path: Vec<config::ExternPath>,
+    /// Place the modules of a protobuf package, and every package nested in it, into a separate
+    /// output directory, e.g. `corp.billing.*=crates/billing/src/autogen` (can be specified
+    /// multiple times)
+    #[arg(long, value_name = "PACKAGE=DIR")]
+    package_output: Vec<modgen::PackageOutput>,
+    /// Lay the module tree out as a directory per module, as a single `generated.rs` with the
+    /// modules nested inline, to be included with `include!`, or as a Cargo package
+    #[arg(long, value_enum)]
+    output_layout: Option<modgen::Layout>,
+    /// Place the generated files into the module tree after the packages of the descriptor set
+    /// rather than after their file names, failing on the files of unknown packages
+    #[arg(long, default_value_t = false)]
+    descriptor_tree: bool,
+    /// Declare the generated modules as `pub`, `pub(crate)` or private
+    #[arg(long, value_enum)]
+    module_visibility: Option<modgen::Visibility>,
+    /// Declare the module of a package, and of the packages nested in it, with another visibility
+    /// than the `--module-visibility` (can be specified multiple times)
+    #[arg(long, value_name = "PACKAGE=VISIBILITY")]
+    package_visibility: Vec<modgen::PackageVisibility>,
+    /// Write a root file with this name, e.g. `lib.rs`, into every output, re-exporting the whole
+    /// module tree, so the output can be used as the source directory of a crate or loaded with
+    /// `#[path = "<output>/lib.rs"] mod autogen;`
+    #[arg(long, value_name = "NAME")]
+    root_file: Option<String>,
+    /// Add an inner attribute to the root file, e.g. `#![allow(clippy::all)]` (can be specified
+    /// multiple times)
+    #[arg(long, value_name = "ATTRIBUTE")]
+    root_attribute: Vec<String>,
+    /// Start every module file with this banner, commented out unless it already is a comment,
+    /// with `{version}` replaced by the version of pbuildrs, e.g. `@generated by pbuildrs
+    /// {version}, do not edit`
+    #[arg(long)]
+    banner: Option<String>,
+    /// Start every module file with the contents of this file, e.g. a license header, commented
+    /// out unless it already is a comment
+    #[arg(long, value_name = "FILE")]
+    license_header: Option<path::PathBuf>,
+    /// Format the generated files with rustfmt, leaving them unformatted with a warning if rustfmt
+    /// is not installed
+    #[arg(long, default_value_t = false)]
+    rustfmt: bool,
+    /// Run this rustfmt binary instead of the one in the `RUSTFMT` environment variable or in the
+    /// `PATH`
+    #[arg(long, value_name = "PATH")]
+    rustfmt_path: Option<path::PathBuf>,
+    /// Format the generated files with this Rust edition, 2021 by default
+    #[arg(long, value_enum)]
+    rustfmt_edition: Option<rustfmt::Edition>,
+    /// Write the generated files with these line endings instead of the ones prost emits
+    #[arg(long, value_enum)]
+    newline: Option<newline::Newline>,
+    /// Record the owners of the generated code, configured in the `owners` table of the
+    /// configuration file, in a block of this CODEOWNERS file
+    #[arg(long)]
+    codeowners: Option<path::PathBuf>,
+    #[arg(skip)]
+    owners: collections::BTreeMap<String, Vec<String>>,
+    #[arg(skip)]
+    service_sides: per_service::Overrides,
+    #[arg(skip)]
+    attributes: attributes::Rules,
+    #[arg(skip)]
+    crate_metadata: Option<manifest::Metadata>,
+    /// Skip the generation when neither the protobuf files, the configuration nor the generated
+    /// code changed since the previous run, tracked in the cache file
+    #[arg(long, default_value_t = false)]
+    incremental: bool,
+    /// The file recording the content hashes for the incremental builds [default:
+    /// .pbuildrs-cache.json]
+    #[arg(long)]
+    cache_file: Option<path::PathBuf>,
+    /// Store the generated code in this directory, keyed by the hash of the protobuf files and the
+    /// configuration, and restore it when a later run has the same key
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<path::PathBuf>,
+    /// Look the generated code up in this read-only store too, after the cache directory, e.g. a
+    /// mount shared by the CI runners (can be specified multiple times)
+    #[arg(long, value_name = "DIR")]
+    shared_cache: Vec<path::PathBuf>,
+    /// Record every run in the history file: when it started, the hash of the protobuf files, how
+    /// long it took and how it ended, see the `history` command
+    #[arg(long, default_value_t = false)]
+    history: bool,
+    /// The file recording the history of the runs [default: .pbuildrs-history.json]
+    #[arg(long)]
+    history_file: Option<path::PathBuf>,
+    /// How many of the most recent runs the history keeps [default: 50]
+    #[arg(long)]
+    history_limit: Option<usize>,
+    /// When the run fails, write the effective configuration, the error and its causes, the output
+    /// of protoc and the patched protobuf files involved into the failure report directory, e.g.
+    /// to be attached to a CI job
+    #[arg(long, default_value_t = false)]
+    failure_report: bool,
+    /// The directory the failure report is written into [default: failure-report]
+    #[arg(long, value_name = "DIR")]
+    failure_report_dir: Option<path::PathBuf>,
+    /// Retry invoking `protoc`, generating the code and writing the module tree up to this many
+    /// times when they fail with a transient I/O error, e.g. on a network file system
+    #[arg(long)]
+    retries: Option<u32>,
+    /// How long to wait before the first retry, in milliseconds, the delay doubles after every
+    /// failed attempt
+    #[arg(long, value_name = "MILLISECONDS")]
+    retry_backoff: Option<u64>,
+    /// Isolate protoc from the environment, and optionally from the network, while compiling the
+    /// protobuf files, for when they come from a source that is not fully trusted. The file system
+    /// is not confined
+    #[arg(long, value_enum)]
+    sandbox_protoc: Option<sandbox::Sandbox>,
+    /// Execute protoc remotely with this command, split on whitespace and run from a directory
+    /// holding every input, followed by the arguments of protoc, with `{inputs}` replaced with the
+    /// inputs and `{output}` with the file descriptor set to fetch, e.g.
+    /// "rewrapper --inputs={inputs} --output_files={output} -- protoc"
+    #[arg(long, value_name = "COMMAND", value_delimiter = ' ')]
+    remote_executor: Vec<String>,
+    /// The syntax the edition of the protobuf files is replaced with, `proto3` by default
+    #[arg(long, value_enum)]
+    target_syntax: Option<patcher::Syntax>,
+    /// Replace the editions other than the known ones, 2023 and 2024, with a warning instead of
+    /// failing, as their semantics may not survive the replacement
+    #[arg(long, default_value_t = false)]
+    allow_unknown_editions: bool,
+    /// Report the messages, enums and protobuf files unreachable from the services with these
+    /// fully qualified names, e.g. `crabs.CrabService` (can be specified multiple times)
+    #[arg(long, value_name = "SERVICE")]
+    entry_service: Vec<String>,
+    /// Move the message with this fully qualified name, e.g. `crabs.GetFerrisReqProto`, and the
+    /// types nested in it into a `#[doc(hidden)]` module, on top of the messages marked with the
+    /// `(pbuildrs.internal)` option (can be specified multiple times)
+    #[arg(long, value_name = "MESSAGE")]
+    internal_message: Vec<String>,
+    /// Print the field with this fully qualified name, e.g. `crabs.Ferris.home_address`, as
+    /// `[REDACTED]` in the `Debug` output of the generated types (can be specified multiple times)
+    #[arg(long, value_name = "FIELD")]
+    sensitive_field: Vec<String>,
+    /// Derive the serde traits for every generated message, enum and oneof, the `serde` list of
+    /// the config file enables them for the listed packages only
+    #[arg(long, default_value_t = false)]
+    serde: bool,
+    #[arg(skip)]
+    serde_packages: Vec<String>,
+    /// Generate the serde implementations of every message and enum with pbjson, following the
+    /// canonical protobuf JSON mapping
+    #[arg(long, default_value_t = false)]
+    pbjson: bool,
+    /// Generate the `bytes` fields matching a protobuf path as `bytes::Bytes` instead of
+    /// `Vec<u8>`, e.g. `.crabs.Ferris.photo`, or all of them with `.` (can be specified multiple
+    /// times)
+    #[arg(long, value_name = "PATH")]
+    bytes: Vec<String>,
+    /// Generate the map fields matching a protobuf path as `BTreeMap` instead of `HashMap`, e.g.
+    /// `.crabs.Ferris.claws`, or all of them with `.` (can be specified multiple times)
+    #[arg(long, value_name = "PATH")]
+    btree_map: Vec<String>,
+    /// Generate the string fields matching a protobuf path as `bytes::Bytes` instead of `String`,
+    /// without validating their UTF-8 when decoding, e.g. `.crabs.Ferris.name` (can be specified
+    /// multiple times)
+    #[arg(long, value_name = "PATH")]
+    string_bytes: Vec<String>,
+    /// Fail before compiling the protobuf files if any of them import each other, instead of only
+    /// reporting the import cycles
+    #[arg(long, default_value_t = false)]
+    fail_on_import_cycles: bool,
+    /// Enable every check meant for CI: warn about the rewritten editions, fail on imports of
+    /// missing files and on removed fields whose number is not reserved, and verify that the
+    /// output is reproducible. Requires `--with-file-descriptor-set`, which the removed fields are
+    /// found against and which must exist unless `--allow-missing-descriptor-set` is passed
+    #[arg(long, default_value_t = false)]
+    strict: bool,
+    /// Let the strict mode run without the file descriptor set of the previous run, skipping the
+    /// check of the removed fields, e.g. on the first run, which writes it
+    #[arg(long, default_value_t = false)]
+    allow_missing_descriptor_set: bool,
+    /// Forbid any network access, e.g. Cargo fetching the dependencies of the crate the generated
+    /// code is verified with, failing the steps that would need it
+    #[arg(long, default_value_t = false)]
+    offline: bool,
+    /// Check the compatibility of the protobuf files with the schema registry at this URL before
+    /// generating the code, failing if it rejects them. The credentials are read from the
+    /// `PBUILDRS_REGISTRY_AUTH` environment variable, e.g. `user:password`
+    #[arg(long, value_name = "URL")]
+    registry_url: Option<String>,
+    /// The API of the schema registry, `confluent` by default
+    #[arg(long, value_enum)]
+    registry_api: Option<registry::Api>,
+    /// Give every method of the generated server traits a default body answering with
+    /// `Unimplemented`, so the services can be implemented one method at a time
+    #[arg(long, default_value_t = false)]
+    default_stubs: bool,
+    /// Write a `reexports.rs` aliasing every generated message and enum by its fully qualified
+    /// protobuf name under a flat module, e.g. `flat::crabs_Ferris`
+    #[arg(long, default_value_t = false)]
+    reexports: bool,
+    /// Write a `descriptor_endpoint.rs` with a debug-only module serving a file descriptor set over
+    /// HTTP, notifying the clients whenever it changes, e.g. after a rebuild with `--watch`
+    #[arg(long, default_value_t = false)]
+    descriptor_endpoint: bool,
+    /// Write a `wire_compat.rs` with tests encoding every message with the generated descriptors
+    /// and decoding it with this file descriptor set of a previous version, and the other way
+    /// around, using `prost-reflect`
+    #[arg(long, value_name = "PREVIOUS_DESCRIPTOR_SET")]
+    wire_compat_tests: Option<path::PathBuf>,
+    /// Write the fully resolved options of the run into an `effective-config.toml` in every
+    /// output, usable as a configuration file reproducing the generated code
+    #[arg(long, default_value_t = false)]
+    effective_config: bool,
+    /// Generate a conformance test module for every service, calling each unary method against
+    /// the endpoint in the `PBUILDRS_CONFORMANCE_ENDPOINT` environment variable
+    #[arg(long, default_value_t = false)]
+    conformance_tests: bool,
+    /// Generate a client for every service recording the calls into a directory, or replaying
+    /// them in tests without a server
+    #[arg(long, default_value_t = false)]
+    recording_client: bool,
+    /// Generate a client for every service mirroring the calls to a shadow endpoint and reporting
+    /// the responses that differ from the primary endpoint
+    #[arg(long, default_value_t = false)]
+    shadow_client: bool,
+    /// Limit the size of the request and response messages of a method, or of every method of a
+    /// service, checked by a generated wrapper of the server (can be specified multiple times)
+    #[arg(long, value_name = "SERVICE[/METHOD]=BYTES")]
+    max_message_size: Vec<size_guard::Limit>,
+    /// Publish a message as an event to a Kafka topic or a NATS subject, generating the topic and
+    /// the helpers framing the message with its schema id (can be specified multiple times)
+    #[arg(long, value_name = "MESSAGE=TOPIC")]
+    event: Vec<events::Event>,
+    /// Generate a `sqlx::FromRow` implementation of a message, decoding its fields from the
+    /// columns named after them unless mapped otherwise (can be specified multiple times)
+    #[arg(long, value_name = "MESSAGE[=FIELD:COLUMN,...]")]
+    row_model: Vec<rows::Model>,
+    /// Default a singular scalar or enum field to a value other than its protobuf zero value in
+    /// the generated `with_defaults` constructor of the message (can be specified multiple times)
+    #[arg(long, value_name = "FIELD=VALUE")]
+    field_default: Vec<defaults::FieldDefault>,
+    /// Generate the helpers reporting the encoded size of a message broken down by field (can be
+    /// specified multiple times)
+    #[arg(long, value_name = "MESSAGE")]
+    encoded_size_report: Vec<String>,
+    /// Split the server trait of a service into a trait per group of the methods starting with any
+    /// of the prefixes, composed back into the full trait (can be specified multiple times)
+    #[arg(long, value_name = "SERVICE/GROUP=PREFIX[,PREFIX...]")]
+    split_trait: Vec<split::Group>,
+    /// Remove this package prefix, e.g. `corp`, from the Rust module paths of every package
+    /// starting with it (can be specified multiple times)
+    #[arg(long, value_name = "PACKAGE")]
+    strip_package_prefix: Vec<String>,
+    /// Name the module of every package segment after another module name, e.g.
+    /// `v1beta1=v1_beta1` (can be specified multiple times)
+    #[arg(long, value_name = "SEGMENT=MODULE")]
+    rename_module: Vec<naming::Rename>,
+    /// Report the lines and bytes of code generated from every protobuf package, and how long the
+    /// verification of the generated code took
+    #[arg(long, default_value_t = false)]
+    size_report: bool,
+    /// Replace the output directories even if they were not generated by pbuildrs, which marks
+    /// every generated output with a `.pbuildrs-generated` file
+    #[arg(long, default_value_t = false)]
+    force: bool,
+    /// Report which protobuf files would be patched and which module files would be generated,
+    /// without writing anything
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+    /// Regenerate the code into a temporary directory and fail if it differs from the existing
+    /// output, without modifying it
+    #[arg(long, default_value_t = false, conflicts_with_all = ["dry_run", "watch"])]
+    check: bool,
+    /// Watch the source and include directories and regenerate the code whenever a protobuf file
+    /// changes
+    #[arg(long, default_value_t = false)]
+    watch: bool,
+    /// Suppress the progress messages and print a single JSON object describing the result
+    /// instead, for use from scripts and build systems
+    #[arg(long, default_value_t = false, conflicts_with_all = ["watch", "cargo_integration"])]
+    porcelain: bool,
+    /// The format of the output, `json` prints a line of JSON for every patched protobuf file,
+    /// compile error and written module instead of the progress messages
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = MessageFormat::Human,
+        conflicts_with_all = ["porcelain", "progress", "watch", "cargo_integration"]
+    )]
+    message_format: MessageFormat,
+    /// Render a progress bar instead of the progress messages, only shown when the standard output
+    /// is a terminal
+    #[arg(long, default_value_t = false, conflicts_with = "porcelain")]
+    progress: bool,
+    /// Print more detailed progress messages, repeat for even more detail (e.g. `-vv`)
+    #[arg(short, long, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+    /// Print only the warnings and errors
+    #[arg(short, long, default_value_t = false)]
+    quiet: bool,
+    /// Skip the protobuf files and directories matching this glob pattern, relative to the source
+    /// path, e.g. `**/internal/**` (can be specified multiple times)
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+    /// Also skip the paths ignored by the `.gitignore` files of the git repository, the
+    /// `.pbuildrsignore` files are always respected
+    #[arg(long, default_value_t = false)]
+    respect_gitignore: bool,
+    /// Descend into the symlinked directories of the source paths, skipping the symlinks pointing
+    /// back at a directory being walked
+    #[arg(long, default_value_t = false)]
+    follow_symlinks: bool,
+    /// Specify the source paths of the protobuf files to compile, each file is placed relative to
+    /// the source it is found in
     #[arg()]
-    source: path::PathBuf,
+    source: Vec<path::PathBuf>,
+}
+
+/// The value of a flag `enabled` on the command line, `disabled` by its negation, or else taken
+/// from the `config` file.
+fn flag(enabled: bool, disabled: bool, config: Option<bool>) -> bool {
+    match (enabled, disabled) {
+        (true, _) => true,
+        (_, true) => false,
+        _ => config.unwrap_or_default(),
+    }
+}
+
+impl Args {
+    fn merge(self, config: config::Config) -> Self {
+        Self {
+            config: self.config,
+            negations: self.negations,
+            build_client: flag(
+                self.build_client,
+                self.negations.no_build_client,
+                config.build_client,
+            ),
+            build_server: flag(
+                self.build_server,
+                self.negations.no_build_server,
+                config.build_server,
+            ),
+            messages_only: flag(
+                self.messages_only,
+                self.negations.no_messages_only,
+                config.messages_only,
+            ),
+            no_transport: flag(
+                self.no_transport,
+                self.negations.transport,
+                config.no_transport,
+            ),
+            with_well_known_types: flag(
+                self.with_well_known_types,
+                self.negations.no_with_well_known_types,
+                config.with_well_known_types,
+            ),
+            include_path: if self.include_path.is_empty() {
+                config.include_path
+            } else {
+                self.include_path
+            },
+            output: self.output.or(config.output),
+            temp_dir: self.temp_dir.or(config.temp_dir),
+            with_file_descriptor_set: self
+                .with_file_descriptor_set
+                .or(config.with_file_descriptor_set),
+            descriptor_strip_prefix: self
+                .descriptor_strip_prefix
+                .or(config.descriptor_strip_prefix),
+            descriptor_root: self.descriptor_root.or(config.descriptor_root),
+            descriptor_set_in: if self.descriptor_set_in.is_empty() {
+                config.descriptor_set_in
+            } else {
+                self.descriptor_set_in
+            },
+            verify_build: flag(
+                self.verify_build,
+                self.negations.no_verify_build,
+                config.verify_build,
+            ),
+            verify_feature_matrix: flag(
+                self.verify_feature_matrix,
+                self.negations.no_verify_feature_matrix,
+                config.verify_feature_matrix,
+            ),
+            msrv: self.msrv.or(config.msrv),
+            semver_baseline: self.semver_baseline.or(config.semver_baseline),
+            reproducible: flag(
+                self.reproducible,
+                self.negations.no_reproducible,
+                config.reproducible,
+            ),
+            cargo_integration: self.cargo_integration,
+            output_layout: self.output_layout.or(config.output_layout),
+            descriptor_tree: flag(
+                self.descriptor_tree,
+                self.negations.no_descriptor_tree,
+                config.descriptor_tree,
+            ),
+            module_visibility: self.module_visibility.or(config.module_visibility),
+            package_visibility: if self.package_visibility.is_empty() {
+                config
+                    .package_visibility
+                    .into_iter()
+                    .map(|(package, visibility)| modgen::PackageVisibility {
+                        package,
+                        visibility,
+                    })
+                    .collect()
+            } else {
+                self.package_visibility
+            },
+            root_file: self.root_file.or(config.root_file),
+            root_attribute: if self.root_attribute.is_empty() {
+                config.root_attribute
+            } else {
+                self.root_attribute
+            },
+            banner: self.banner.or(config.banner),
+            license_header: self.license_header.or(config.license_header),
+            rustfmt: flag(self.rustfmt, self.negations.no_rustfmt, config.rustfmt),
+            rustfmt_path: self.rustfmt_path.or(config.rustfmt_path),
+            rustfmt_edition: self.rustfmt_edition.or(config.rustfmt_edition),
+            newline: self.newline.or(config.newline),
+            type_attribute: if self.type_attribute.is_empty() {
+                config.type_attribute
+            } else {
+                self.type_attribute
+            },
+            field_attribute: if self.field_attribute.is_empty() {
+                config.field_attribute
+            } else {
+                self.field_attribute
+            },
+            extern_path: if self.extern_path.is_empty() {
+                config.extern_path
+            } else {
+                self.extern_path
+            },
+            package_output: if self.package_output.is_empty() {
+                config.package_output
+            } else {
+                self.package_output
+            },
+            codeowners: self.codeowners.or(config.codeowners),
+            owners: config.owners,
+            service_sides: config.service_sides,
+            attributes: config.attributes,
+            crate_metadata: config.crate_metadata,
+            incremental: flag(
+                self.incremental,
+                self.negations.no_incremental,
+                config.incremental,
+            ),
+            cache_file: self.cache_file.or(config.cache_file),
+            cache_dir: self.cache_dir.or(config.cache_dir),
+            shared_cache: if self.shared_cache.is_empty() {
+                config.shared_cache
+            } else {
+                self.shared_cache
+            },
+            history: flag(self.history, self.negations.no_history, config.history),
+            history_file: self.history_file.or(config.history_file),
+            history_limit: self.history_limit.or(config.history_limit),
+            failure_report: flag(
+                self.failure_report,
+                self.negations.no_failure_report,
+                config.failure_report,
+            ),
+            failure_report_dir: self.failure_report_dir.or(config.failure_report_dir),
+            retries: self.retries.or(config.retries),
+            retry_backoff: self.retry_backoff.or(config.retry_backoff),
+            sandbox_protoc: self.sandbox_protoc.or(config.sandbox_protoc),
+            remote_executor: if self.remote_executor.is_empty() {
+                config.remote_executor
+            } else {
+                self.remote_executor
+            },
+            target_syntax: self.target_syntax.or(config.target_syntax),
+            allow_unknown_editions: flag(
+                self.allow_unknown_editions,
+                self.negations.no_allow_unknown_editions,
+                config.allow_unknown_editions,
+            ),
+            entry_service: if self.entry_service.is_empty() {
+                config.entry_service
+            } else {
+                self.entry_service
+            },
+            internal_message: if self.internal_message.is_empty() {
+                config.internal_message
+            } else {
+                self.internal_message
+            },
+            sensitive_field: if self.sensitive_field.is_empty() {
+                config.sensitive_field
+            } else {
+                self.sensitive_field
+            },
+            serde: self.serde,
+            serde_packages: match (self.serde, self.negations.no_serde) {
+                (true, _) => vec![".".to_string()],
+                (_, true) => vec![],
+                _ => config.serde,
+            },
+            pbjson: flag(self.pbjson, self.negations.no_pbjson, config.pbjson),
+            bytes: if self.bytes.is_empty() {
+                config.bytes
+            } else {
+                self.bytes
+            },
+            btree_map: if self.btree_map.is_empty() {
+                config.btree_map
+            } else {
+                self.btree_map
+            },
+            string_bytes: if self.string_bytes.is_empty() {
+                config.string_bytes
+            } else {
+                self.string_bytes
+            },
+            fail_on_import_cycles: flag(
+                self.fail_on_import_cycles,
+                self.negations.no_fail_on_import_cycles,
+                config.fail_on_import_cycles,
+            ),
+            strict: flag(self.strict, self.negations.no_strict, config.strict),
+            allow_missing_descriptor_set: flag(
+                self.allow_missing_descriptor_set,
+                self.negations.no_allow_missing_descriptor_set,
+                config.allow_missing_descriptor_set,
+            ),
+            offline: flag(self.offline, self.negations.no_offline, config.offline),
+            registry_url: self.registry_url.or(config.registry_url),
+            registry_api: self.registry_api.or(config.registry_api),
+            default_stubs: flag(
+                self.default_stubs,
+                self.negations.no_default_stubs,
+                config.default_stubs,
+            ),
+            reexports: flag(
+                self.reexports,
+                self.negations.no_reexports,
+                config.reexports,
+            ),
+            descriptor_endpoint: flag(
+                self.descriptor_endpoint,
+                self.negations.no_descriptor_endpoint,
+                config.descriptor_endpoint,
+            ),
+            wire_compat_tests: self.wire_compat_tests.or(config.wire_compat_tests),
+            effective_config: flag(
+                self.effective_config,
+                self.negations.no_effective_config,
+                config.effective_config,
+            ),
+            conformance_tests: flag(
+                self.conformance_tests,
+                self.negations.no_conformance_tests,
+                config.conformance_tests,
+            ),
+            recording_client: flag(
+                self.recording_client,
+                self.negations.no_recording_client,
+                config.recording_client,
+            ),
+            shadow_client: flag(
+                self.shadow_client,
+                self.negations.no_shadow_client,
+                config.shadow_client,
+            ),
+            max_message_size: if self.max_message_size.is_empty() {
+                config
+                    .max_message_size
+                    .into_iter()
+                    .map(|(target, bytes)| size_guard::Limit { target, bytes })
+                    .collect()
+            } else {
+                self.max_message_size
+            },
+            event: if self.event.is_empty() {
+                config
+                    .events
+                    .into_iter()
+                    .map(|(message, topic)| events::Event { message, topic })
+                    .collect()
+            } else {
+                self.event
+            },
+            row_model: if self.row_model.is_empty() {
+                config
+                    .row_model
+                    .into_iter()
+                    .map(|(message, columns)| rows::Model {
+                        message,
+                        columns: columns.into_iter().collect(),
+                    })
+                    .collect()
+            } else {
+                self.row_model
+            },
+            field_default: if self.field_default.is_empty() {
+                config
+                    .field_default
+                    .into_iter()
+                    .map(|(field, value)| defaults::FieldDefault { field, value })
+                    .collect()
+            } else {
+                self.field_default
+            },
+            encoded_size_report: if self.encoded_size_report.is_empty() {
+                config.encoded_size_report
+            } else {
+                self.encoded_size_report
+            },
+            split_trait: if self.split_trait.is_empty() {
+                config
+                    .split_trait
+                    .into_iter()
+                    .flat_map(|(service, groups)| {
+                        groups
+                            .into_iter()
+                            .map(move |(name, prefixes)| split::Group {
+                                service: service.clone(),
+                                name,
+                                prefixes,
+                            })
+                    })
+                    .collect()
+            } else {
+                self.split_trait
+            },
+            strip_package_prefix: if self.strip_package_prefix.is_empty() {
+                config.strip_package_prefix
+            } else {
+                self.strip_package_prefix
+            },
+            rename_module: if self.rename_module.is_empty() {
+                config
+                    .rename_module
+                    .into_iter()
+                    .map(|(segment, module)| naming::Rename { segment, module })
+                    .collect()
+            } else {
+                self.rename_module
+            },
+            size_report: self.size_report,
+            force: self.force,
+            dry_run: self.dry_run,
+            check: self.check,
+            watch: self.watch,
+            porcelain: self.porcelain,
+            message_format: self.message_format,
+            progress: self.progress,
+            verbose: self.verbose,
+            quiet: self.quiet,
+            exclude: if self.exclude.is_empty() {
+                config.exclude
+            } else {
+                self.exclude
+            },
+            respect_gitignore: flag(
+                self.respect_gitignore,
+                self.negations.no_respect_gitignore,
+                config.respect_gitignore,
+            ),
+            follow_symlinks: flag(
+                self.follow_symlinks,
+                self.negations.no_follow_symlinks,
+                config.follow_symlinks,
+            ),
+            source: if self.source.is_empty() {
+                config.source
+            } else {
+                self.source
+            },
+        }
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("Failed to create a temporary directory: {0}")]
-    MkTempDir(io::Error),
-    #[error("Failed to remove previous output directory: {0}")]
-    RemoveOutDir(io::Error),
-    #[error("Failed to create an output directory: {0}")]
-    CreateOutDir(io::Error),
-    #[error("Failed to compile the proto file: {0}")]
-    CompileProto(io::Error),
-    #[error("Failed to patch protobuf files: {0}")]
-    PatchEdition(#[from] crate::Error),
-    #[error("Failed to create a temporary directory for generate source code `{1}`: {0}")]
-    MkTempCompileDir(io::Error, path::PathBuf),
-    #[error("")]
-    Modularize(#[from] modgen::Error),
-}
-
-pub fn create_temp_working_dir(
-    path: &Option<path::PathBuf>,
-) -> Result<tempfile::TempDir, io::Error> {
-    let mut tempdir = tempfile::Builder::new();
-    let tempdir = tempdir.prefix("pbuildrs-");
-
-    if let Some(path) = path {
-        tempdir.tempdir_in(path)
-    } else {
-        tempdir.tempdir()
+    #[error("{0}")]
+    Build(#[from] builder::Error),
+    #[error("Failed to load the configuration: {0}")]
+    Config(#[from] config::Error),
+    #[error(
+        "No source path of the protobuf files was provided, pass it as an argument or set `source` in the configuration file"
+    )]
+    MissingSource,
+    #[error("Failed to watch for changes: {0}")]
+    Watch(#[from] watch::Error),
+    #[error("Failed to analyze the usage of the generated code: {0}")]
+    Usage(#[from] usage::Error),
+    #[error(
+        "The generated code is out of date, {} file(s) differ from the existing output",
+        .0.len()
+    )]
+    OutOfDate(Vec<check::Difference>),
+    #[error("Failed to open the protobuf file `{1}`: {0}")]
+    OpenInput(io::Error, path::PathBuf),
+    #[error("Failed to patch the protobuf file: {0}")]
+    Patch(#[from] patcher::Error),
+    #[error("Failed to load the history: {0}")]
+    History(#[from] history::Error),
+    #[error(
+        "No file descriptor set to look the definitions up in was provided, pass it in `--descriptor-set` or set `with-file-descriptor-set` in the configuration file"
+    )]
+    MissingDescriptorSet,
+    #[error("{0}")]
+    Descriptor(#[from] descriptor::Error),
+    #[error("No message, enum or service named `{0}` was found in the file descriptor set")]
+    UnknownDefinition(String),
+    #[error("Failed to rename: {0}")]
+    Rename(#[from] rename::Error),
+    #[error("{0}")]
+    VerifyPatch(#[from] verify_patch::Error),
+    #[error("The patched protobuf files differ from their sources in {0} file(s)")]
+    PatchMismatch(usize),
+    #[error("Failed to release the generated crates: {0}")]
+    Release(#[from] release::Error),
+}
+
+impl Args {
+    /// Print the progress messages to the standard error, at the level chosen by the verbosity
+    /// flags. The machine-readable output silences them entirely, while the progress bar replaces
+    /// them unless more details are requested.
+    fn init_logging(&self) {
+        let silent = self.porcelain || self.message_format == MessageFormat::Json;
+
+        let level = match (silent, self.quiet, self.verbose) {
+            (true, _, _) => filter::LevelFilter::OFF,
+            (false, true, _) => filter::LevelFilter::WARN,
+            (false, false, 0) if self.progress => filter::LevelFilter::WARN,
+            (false, false, 0) => filter::LevelFilter::INFO,
+            (false, false, 1) => filter::LevelFilter::DEBUG,
+            (false, false, _) => filter::LevelFilter::TRACE,
+        };
+
+        tracing_subscriber::fmt()
+            .with_max_level(level)
+            .with_writer(io::stderr)
+            .with_target(false)
+            .without_time()
+            .init();
+    }
+
+    /// The builder of every source path, the first one given to [`Builder::new`].
+    fn sourced_builder(&self, targets: Vec<config::Target>) -> Result<Builder, Error> {
+        let Some((source, sources)) = self.source.split_first() else {
+            return Err(Error::MissingSource);
+        };
+
+        Ok(sources
+            .iter()
+            .fold(self.builder(source, targets), |builder, source| {
+                builder.source(source)
+            }))
+    }
+
+    fn builder(&self, source: &path::Path, targets: Vec<config::Target>) -> Builder {
+        let mut builder = Builder::new(source)
+            .build_client(self.build_client)
+            .build_server(self.build_server)
+            .messages_only(self.messages_only)
+            .no_transport(self.no_transport)
+            .with_well_known_types(self.with_well_known_types)
+            .verify_build(self.verify_build)
+            .verify_feature_matrix(self.verify_feature_matrix)
+            .reproducible(self.reproducible)
+            .cargo_integration(self.cargo_integration)
+            .fail_on_import_cycles(self.fail_on_import_cycles)
+            .strict(self.strict)
+            .allow_missing_descriptor_set(self.allow_missing_descriptor_set)
+            .offline(self.offline)
+            .allow_unknown_editions(self.allow_unknown_editions)
+            .default_stubs(self.default_stubs)
+            .reexports(self.reexports)
+            .descriptor_endpoint(self.descriptor_endpoint)
+            .effective_config(self.effective_config)
+            .conformance_tests(self.conformance_tests)
+            .recording_client(self.recording_client)
+            .shadow_client(self.shadow_client)
+            .respect_gitignore(self.respect_gitignore)
+            .follow_symlinks(self.follow_symlinks)
+            .descriptor_tree(self.descriptor_tree)
+            .rustfmt(self.rustfmt)
+            .force(self.force)
+            .progress(self.progress)
+            .dry_run(self.dry_run);
+
+        builder = self
+            .include_path
+            .iter()
+            .fold(builder, |builder, path| builder.include_path(path));
+        builder = self
+            .exclude
+            .iter()
+            .fold(builder, |builder, pattern| builder.exclude(pattern));
+        builder = self
+            .max_message_size
+            .iter()
+            .fold(builder, |builder, limit| {
+                builder.max_message_size(&limit.target, limit.bytes)
+            });
+        builder = self.event.iter().fold(builder, |builder, event| {
+            builder.event(&event.message, &event.topic)
+        });
+        builder = self.row_model.iter().fold(builder, |builder, model| {
+            builder.row_model(&model.message, model.columns.iter().cloned())
+        });
+        builder = self.field_default.iter().fold(builder, |builder, default| {
+            builder.field_default(&default.field, &default.value)
+        });
+        builder = self
+            .encoded_size_report
+            .iter()
+            .fold(builder, |builder, message| {
+                builder.encoded_size_report(message)
+            });
+        builder = self.split_trait.iter().fold(builder, |builder, group| {
+            builder.split_trait(&group.service, &group.name, &group.prefixes)
+        });
+        builder = self
+            .strip_package_prefix
+            .iter()
+            .fold(builder, |builder, prefix| {
+                builder.strip_package_prefix(prefix)
+            });
+        builder = self.rename_module.iter().fold(builder, |builder, rename| {
+            builder.rename_module(&rename.segment, &rename.module)
+        });
+        builder = self
+            .entry_service
+            .iter()
+            .fold(builder, |builder, service| builder.entry_service(service));
+        builder = self
+            .internal_message
+            .iter()
+            .fold(builder, |builder, message| {
+                builder.internal_message(message)
+            });
+        builder = self
+            .sensitive_field
+            .iter()
+            .fold(builder, |builder, field| builder.sensitive_field(field));
+        builder = builder.pbjson(self.pbjson);
+        builder = self
+            .bytes
+            .iter()
+            .fold(builder, |builder, path| builder.bytes(path));
+        builder = self
+            .btree_map
+            .iter()
+            .fold(builder, |builder, path| builder.btree_map(path));
+        builder = self
+            .string_bytes
+            .iter()
+            .fold(builder, |builder, path| builder.string_bytes(path));
+        builder = self
+            .serde_packages
+            .iter()
+            .fold(builder, |builder, package| builder.serde(package));
+        builder = self.type_attribute.iter().fold(builder, |builder, attr| {
+            builder.type_attribute(&attr.path, &attr.attribute)
+        });
+        builder = self.field_attribute.iter().fold(builder, |builder, attr| {
+            builder.field_attribute(&attr.path, &attr.attribute)
+        });
+        builder = self.extern_path.iter().fold(builder, |builder, path| {
+            builder.extern_path(&path.proto_path, &path.rust_path)
+        });
+        builder = self
+            .package_output
+            .iter()
+            .fold(builder, |builder, package| {
+                builder.package_output(package.clone())
+            });
+
+        if let Some(output) = &self.output {
+            builder = builder.output(output);
+        }
+        if let Some(path) = &self.temp_dir {
+            builder = builder.temp_dir(path);
+        }
+        if let Some(path) = &self.with_file_descriptor_set {
+            builder = builder.file_descriptor_set_path(path);
+        }
+        if let Some(prefix) = &self.descriptor_strip_prefix {
+            builder = builder.descriptor_strip_prefix(prefix);
+        }
+        if let Some(root) = &self.descriptor_root {
+            builder = builder.descriptor_root(root);
+        }
+        builder = self
+            .descriptor_set_in
+            .iter()
+            .fold(builder, |builder, path| builder.descriptor_set_in(path));
+        if let Some(msrv) = &self.msrv {
+            builder = builder.msrv(msrv.clone());
+        }
+        if let Some(baseline) = &self.semver_baseline {
+            builder = builder.semver_baseline(baseline);
+        }
+        if let Some(layout) = self.output_layout {
+            builder = builder.output_layout(layout);
+        }
+        if let Some(visibility) = self.module_visibility {
+            builder = builder.module_visibility(visibility);
+        }
+        builder = self
+            .package_visibility
+            .iter()
+            .fold(builder, |builder, package| {
+                builder.package_visibility(&package.package, package.visibility)
+            });
+        if let Some(name) = &self.root_file {
+            builder = builder.root_file(name);
+        }
+        builder = self
+            .root_attribute
+            .iter()
+            .fold(builder, |builder, attribute| {
+                builder.root_attribute(attribute)
+            });
+        if let Some(banner) = &self.banner {
+            builder = builder.banner(banner);
+        }
+        if let Some(path) = &self.license_header {
+            builder = builder.license_header(path);
+        }
+        if let Some(path) = &self.rustfmt_path {
+            builder = builder.rustfmt_path(path);
+        }
+        if let Some(edition) = self.rustfmt_edition {
+            builder = builder.rustfmt_edition(edition);
+        }
+        if let Some(newline) = self.newline {
+            builder = builder.newline(newline);
+        }
+        if let Some(path) = &self.codeowners {
+            builder = builder.codeowners(path);
+        }
+        if let Some(url) = &self.registry_url {
+            builder = builder.schema_registry(url, self.registry_api.unwrap_or_default());
+        }
+        if let Some(previous) = &self.wire_compat_tests {
+            builder = builder.wire_compat_tests(previous);
+        }
+        if let Some(retries) = self.retries {
+            builder = builder.retries(retries);
+        }
+        if let Some(backoff) = self.retry_backoff {
+            builder = builder.retry_backoff(time::Duration::from_millis(backoff));
+        }
+        if let Some(sandbox) = self.sandbox_protoc {
+            builder = builder.sandbox(sandbox);
+        }
+        // Without a remote executor command protoc runs locally.
+        if let Ok(remote) = execution::Remote::new(self.remote_executor.clone()) {
+            builder = builder.execution(remote);
+        }
+        if let Some(syntax) = self.target_syntax {
+            builder = builder.target_syntax(syntax);
+        }
+        if self.incremental {
+            builder = builder.cache_file(
+                self.cache_file
+                    .as_deref()
+                    .unwrap_or(path::Path::new(cache::DEFAULT_PATH)),
+            );
+        }
+        if let Some(path) = &self.cache_dir {
+            builder = builder.cache_dir(path);
+        }
+        builder = self
+            .shared_cache
+            .iter()
+            .fold(builder, |builder, path| builder.shared_cache(path));
+        if self.history {
+            builder = builder.history_file(
+                self.history_file
+                    .as_deref()
+                    .unwrap_or(path::Path::new(history::DEFAULT_PATH)),
+            );
+        }
+        if let Some(limit) = self.history_limit {
+            builder = builder.history_limit(limit);
+        }
+        if self.failure_report {
+            builder = builder.failure_report(
+                self.failure_report_dir
+                    .as_deref()
+                    .unwrap_or(path::Path::new(failure_report::DEFAULT_PATH)),
+            );
+        }
+        builder = self
+            .owners
+            .iter()
+            .fold(builder, |builder, (package, owners)| {
+                builder.owner(package, owners)
+            });
+        builder = self
+            .service_sides
+            .iter()
+            .fold(builder, |builder, (service, sides)| {
+                builder.service_sides(service, *sides)
+            });
+        builder = self
+            .attributes
+            .iter()
+            .fold(builder, |builder, (path, rule)| {
+                builder.attributes(path, rule.clone())
+            });
+        if let Some(metadata) = &self.crate_metadata {
+            builder = builder.crate_metadata(metadata.clone());
+        }
+
+        targets.into_iter().fold(builder, |builder, target| {
+            builder.target(target_from_config(target))
+        })
     }
 }
 
-pub fn run(args: Args) -> Result<(), Error> {
-    if args.output.exists() {
-        println!("Found previous output directory, cleaning up");
-        fs::remove_dir_all(&args.output).map_err(Error::RemoveOutDir)?;
-        println!("Previous output directory was removed");
+fn target_from_config(config: config::Target) -> builder::Target {
+    let mut target = builder::Target::new(config.name, config.output);
+
+    if let Some(enable) = config.build_client {
+        target = target.build_client(enable);
+    }
+    if let Some(enable) = config.build_server {
+        target = target.build_server(enable);
+    }
+    if let Some(enable) = config.with_well_known_types {
+        target = target.with_well_known_types(enable);
+    }
+    if let Some(enable) = config.verify_build {
+        target = target.verify_build(enable);
+    }
+    if let Some(enable) = config.verify_feature_matrix {
+        target = target.verify_feature_matrix(enable);
+    }
+    if let Some(msrv) = config.msrv {
+        target = target.msrv(msrv);
+    }
+    if let Some(baseline) = config.semver_baseline {
+        target = target.semver_baseline(baseline);
     }
 
-    fs::create_dir_all(&args.output).map_err(Error::CreateOutDir)?;
-    println!("Created an output directory: {}", args.output.display());
+    let target = config
+        .type_attribute
+        .into_iter()
+        .fold(target, |target, attr| {
+            target.type_attribute(attr.path, attr.attribute)
+        });
+    let target = config
+        .field_attribute
+        .into_iter()
+        .fold(target, |target, attr| {
+            target.field_attribute(attr.path, attr.attribute)
+        });
 
-    let tempdir = create_temp_working_dir(&args.temp_dir).map_err(Error::MkTempDir)?;
+    let target = config.extern_path.into_iter().fold(target, |target, path| {
+        target.extern_path(path.proto_path, path.rust_path)
+    });
 
+    config
+        .package_output
+        .into_iter()
+        .fold(target, |target, package| target.package_output(package))
+}
+
+fn print_dry_run(report: &builder::Report) {
+    report.protos.iter().for_each(|proto| match proto.outcome {
+        crate::Outcome::Replaced(_) => {
+            println!("Would patch the edition of: {}", proto.source.display())
+        }
+        crate::Outcome::Untouched => println!("Would compile as is: {}", proto.source.display()),
+    });
+
+    report.targets.iter().for_each(|target| {
+        println!("Would generate the `{}` target:", target.name);
+
+        target
+            .files
+            .iter()
+            .for_each(|file| println!("  {}", file.display()));
+    });
+}
+
+fn print_unreachable(unreachable: &reachability::Unreachable) {
+    if unreachable.is_empty() {
+        println!("Every part of the schema is reachable from the entry services");
+
+        return;
+    }
+
+    [
+        ("files", &unreachable.files),
+        ("messages", &unreachable.messages),
+        ("enums", &unreachable.enums),
+    ]
+    .into_iter()
+    .filter(|(_, names)| !names.is_empty())
+    .for_each(|(kind, names)| {
+        println!("Unreachable {kind}:");
+        names.iter().for_each(|name| println!("  {name}"));
+    });
+}
+
+fn print_size_report(report: &builder::Report) {
+    report.targets.iter().for_each(|target| {
+        println!("Size of the generated `{}` target:", target.name);
+
+        let mut packages = target.packages.iter().collect::<Vec<_>>();
+        packages.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.package.cmp(&b.package)));
+
+        let width = packages
+            .iter()
+            .map(|size| size.package.len())
+            .max()
+            .unwrap_or_default();
+
+        packages.into_iter().for_each(|size| {
+            let package = if size.package.is_empty() {
+                "(no package)"
+            } else {
+                &size.package
+            };
+
+            println!(
+                "  {package:<width$}  {:>8} lines  {:>10} bytes",
+                size.lines, size.bytes,
+            );
+        });
+
+        if let Some(time) = target.compile_time {
+            println!(
+                "  Verified the generated code in {:.2}s",
+                time.as_secs_f64()
+            );
+        }
+    });
+}
+
+/// The single JSON object printed in the porcelain mode.
+#[derive(serde::Serialize)]
+struct Porcelain {
+    status: &'static str,
+    cached: bool,
+    retries: u32,
+    outputs: Vec<PorcelainOutput>,
+    errors: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+struct PorcelainOutput {
+    target: String,
+    output: String,
+    files: Vec<String>,
+}
+
+impl Porcelain {
+    fn new(result: &Result<Option<builder::Report>, Error>) -> Self {
+        let report = result.as_ref().ok().and_then(Option::as_ref);
+        let outputs = report
+            .iter()
+            .flat_map(|report| &report.targets)
+            .map(|target| PorcelainOutput {
+                target: target.name.clone(),
+                output: target.output.display().to_string(),
+                files: target
+                    .files
+                    .iter()
+                    .map(|file| file.display().to_string())
+                    .collect(),
+            })
+            .collect();
+        let errors = match result {
+            Ok(_) => vec![],
+            Err(e @ Error::OutOfDate(differences)) => differences
+                .iter()
+                .map(ToString::to_string)
+                .chain(iter::once(e.to_string()))
+                .collect(),
+            Err(e) => vec![e.to_string()],
+        };
+
+        Self {
+            status: if result.is_ok() { "success" } else { "failure" },
+            cached: report.is_some_and(|report| report.cached),
+            retries: report.map_or(0, |report| report.retries),
+            outputs,
+            errors,
+        }
+    }
+}
+
+fn suggest(args: SuggestArgs) -> Result<(), Error> {
+    let config = config::Config::discover(args.config.as_deref())?;
+    let output = args
+        .output
+        .or(config.output)
+        .unwrap_or_else(|| path::PathBuf::from(builder::DEFAULT_OUTPUT));
+
+    let usage = usage::analyze(&output, &args.sources)?;
+    let package = |name: &String| {
+        if name.is_empty() {
+            println!("  (no package)");
+        } else {
+            println!("  {name}");
+        }
+    };
+
+    println!("Packages used by the crate:");
+    usage.used.iter().for_each(package);
+    println!("Packages the crate does not use:");
+    usage.unused.iter().for_each(package);
+
+    let features = [("client", usage.client), ("server", usage.server)]
+        .into_iter()
+        .filter_map(|(feature, used)| used.then_some(feature))
+        .collect::<Vec<_>>();
     println!(
-        "Created a temporary working directory: {}",
-        tempdir.path().display(),
+        "Features used by the crate: {}",
+        if features.is_empty() {
+            "none".to_string()
+        } else {
+            features.join(", ")
+        }
     );
 
-    let patched_dir = tempdir.path().join("protos");
-    let patched_files = crate::patch_protos(&args.source, &patched_dir)?;
+    Ok(())
+}
+
+fn history(args: HistoryArgs) -> Result<(), Error> {
+    let config = config::Config::discover(args.config.as_deref())?;
+    let file = args
+        .history_file
+        .or(config.history_file)
+        .unwrap_or_else(|| path::PathBuf::from(history::DEFAULT_PATH));
+
+    let runs = history::load(&file)?;
+    if runs.is_empty() {
+        println!("No runs recorded in `{}` yet", file.display());
+    } else {
+        print!("{}", history::render(&runs));
+    }
+
+    Ok(())
+}
+
+fn lookup(args: WhereArgs) -> Result<(), Error> {
+    let config = config::Config::discover(args.config.as_deref())?;
+    let fds = descriptor::read(
+        &args
+            .descriptor_set
+            .or(config.with_file_descriptor_set)
+            .ok_or(Error::MissingDescriptorSet)?,
+    )?;
+    let output = args
+        .output
+        .or(config.output)
+        .unwrap_or_else(|| path::PathBuf::from(builder::DEFAULT_OUTPUT));
+    let naming = naming::Naming {
+        strip_prefixes: config.strip_package_prefix,
+        renames: config.rename_module,
+    };
+
+    let location = lookup::find(
+        &fds,
+        &args.name,
+        &naming,
+        &output,
+        &config.package_output,
+        config.output_layout.unwrap_or_default(),
+    )
+    .ok_or_else(|| Error::UnknownDefinition(args.name.clone()))?;
+
+    println!("{} ({})", args.name.trim_start_matches('.'), location.kind);
+    location
+        .rust_paths
+        .iter()
+        .for_each(|path| println!("  Rust path: {path}"));
+    println!("  Module:    {}", location.module_file.display());
+    println!("  Source:    {}", location.proto_file);
+
+    Ok(())
+}
+
+fn rename(args: RenameArgs) -> Result<(), Error> {
+    let config = config::Config::discover(args.config.as_deref())?;
+    let sources = match args.sources.is_empty() {
+        true => config.source,
+        false => args.sources,
+    };
+    if sources.is_empty() {
+        return Err(Error::MissingSource);
+    }
+    let naming = naming::Naming {
+        strip_prefixes: config.strip_package_prefix,
+        renames: config.rename_module,
+    };
+
+    let report = rename::rename(&sources, &args.from, &args.to, &naming, args.dry_run)?;
 
-    let compiled_files_dir = tempdir.path().join("code");
-    fs::create_dir_all(&compiled_files_dir)
-        .map_err(|e| Error::MkTempCompileDir(e, compiled_files_dir.clone()))?;
     println!(
-        "Created temporary directory for generated source code: {}",
-        compiled_files_dir.display()
+        "{} {} protobuf file(s):",
+        if args.dry_run {
+            "Would rewrite"
+        } else {
+            "Rewrote"
+        },
+        report.files.len(),
     );
+    report
+        .files
+        .iter()
+        .for_each(|file| println!("  {} ({} name(s))", file.path.display(), file.replacements));
+    println!("Rust paths:");
+    report
+        .rust_paths
+        .iter()
+        .for_each(|(old, new)| println!("  {old} -> {new}"));
 
-    let mut includes = args.include_path;
-    includes.push(patched_dir);
+    Ok(())
+}
+
+fn release(args: ReleaseArgs) -> Result<(), Error> {
+    args.args.init_logging();
 
-    let mut builder = tonic_prost_build::configure();
-    if let Some(path) = args.with_file_descriptor_set {
-        builder = builder.file_descriptor_set_path(path);
+    let mut config = config::Config::discover(args.args.config.as_deref())?;
+    let targets = mem::take(&mut config.targets);
+    let mut merged = args.args.merge(config);
+    // Both runs need the schema compiled, to suggest the version and to regenerate the crates.
+    merged.incremental = false;
+    merged.cache_dir = None;
+    merged.shared_cache = vec![];
+    if merged.output_layout != Some(modgen::Layout::Crate) {
+        return Err(release::Error::NotACrate.into());
     }
+    if args.publish_dry_run && merged.offline {
+        return Err(release::Error::Offline.into());
+    }
+
+    let metadata = merged.crate_metadata.clone().unwrap_or_default();
+    let builder = merged.sourced_builder(targets)?;
+
+    // The versions are suggested by a dry run first, as the version is part of the generated
+    // manifests.
+    let suggested = builder
+        .clone()
+        .crate_metadata(manifest::Metadata {
+            version: None,
+            ..metadata.clone()
+        })
+        .dry_run(true)
+        .build()?;
+    let version = release::version(&suggested.versions)?;
+    tracing::info!("Releasing the generated crates as version {version}");
 
-    builder
-        .build_client(args.build_client)
-        .client_mod_attribute(".", r#"#[cfg(feature = "client")]"#)
-        .build_server(args.build_server)
-        .server_mod_attribute(".", r#"#[cfg(feature = "server")]"#)
-        .build_transport(args.build_client || args.build_server)
-        .compile_well_known_types(args.with_well_known_types)
-        .out_dir(&compiled_files_dir)
-        .compile_protos(&patched_files, &includes)
-        .map_err(Error::CompileProto)?;
+    let report = builder
+        .crate_metadata(manifest::Metadata {
+            version: Some(version.clone()),
+            ..metadata
+        })
+        .dry_run(false)
+        .verify_build(true)
+        .build()?;
 
-    modgen::modularize(&compiled_files_dir, &args.output)?;
+    if args.publish_dry_run {
+        let target_dir = builder::create_temp_working_dir(&merged.temp_dir)
+            .map_err(builder::Error::MkTempDir)?;
+        let crates = report
+            .targets
+            .iter()
+            .flat_map(|target| release::crates(&target.files))
+            .collect::<Vec<_>>();
+
+        release::publish_dry_run(&crates, target_dir.path())?;
+    }
+
+    println!("{version}");
 
     Ok(())
 }
 
+fn verify_patch(args: VerifyPatchArgs) -> Result<(), Error> {
+    let report = verify_patch::verify(
+        &args.src,
+        &args.patched,
+        args.target_syntax,
+        args.allow_unknown_editions,
+    )?;
+
+    report
+        .replaced
+        .iter()
+        .for_each(|(path, edition)| println!("edition {edition}: {}", path.display()));
+    report
+        .differences
+        .iter()
+        .for_each(|difference| println!("{difference}"));
+
+    match report.differences.len() {
+        0 => {
+            println!(
+                "Verified {} patched protobuf file(s), {} unchanged",
+                report.replaced.len() + report.untouched,
+                report.untouched,
+            );
+
+            Ok(())
+        }
+        differences => Err(Error::PatchMismatch(differences)),
+    }
+}
+
+fn patch(args: PatchArgs) -> Result<process::ExitCode, Error> {
+    let outcome = patch_into(
+        &args.input,
+        io::stdout().lock(),
+        args.target_syntax,
+        args.allow_unknown_editions,
+    )?;
+    // The logging is not set up for the command, its standard output is the patched file.
+    if let Some(edition) = outcome.unknown_edition() {
+        eprintln!(
+            "Replaced the unknown edition `{edition}`, its semantics may differ from `{}`",
+            args.target_syntax.declaration(),
+        );
+    }
+
+    Ok(match outcome {
+        patcher::Outcome::Replaced(_) => process::ExitCode::from(REPLACED_EXIT_CODE),
+        patcher::Outcome::Untouched => process::ExitCode::SUCCESS,
+    })
+}
+
+/// Patch the edition of the `input` file, or of the standard input for `-`, into the `output`,
+/// replacing it with the `syntax`.
+fn patch_into(
+    input: &path::Path,
+    output: impl io::Write,
+    syntax: patcher::Syntax,
+    allow_unknown_editions: bool,
+) -> Result<patcher::Outcome, Error> {
+    let mut output = io::BufWriter::new(output);
+    let outcome = if input == path::Path::new("-") {
+        patcher::patch_edition(
+            io::stdin().lock(),
+            &mut output,
+            syntax,
+            allow_unknown_editions,
+        )?
+    } else {
+        let file = fs::File::open(input).map_err(|e| Error::OpenInput(e, input.to_path_buf()))?;
+
+        patcher::patch_edition(
+            io::BufReader::new(file),
+            &mut output,
+            syntax,
+            allow_unknown_editions,
+        )?
+    };
+    io::Write::flush(&mut output).map_err(patcher::Error::Write)?;
+
+    Ok(outcome)
+}
+
+/// The messages describing the `result` of a run, in the order they happened.
+fn messages(
+    result: &Result<Option<builder::Report>, Error>,
+    dry_run: bool,
+) -> Vec<message::Message> {
+    let mut messages = match result {
+        Ok(report) => report
+            .iter()
+            .flat_map(|report| message::from_report(report, dry_run))
+            .collect(),
+        Err(Error::OutOfDate(differences)) => differences
+            .iter()
+            .map(|difference| message::Message::OutputChanged {
+                path: difference.path().display().to_string(),
+                change: difference.change().to_string(),
+            })
+            .collect(),
+        Err(e) => {
+            let message = e.to_string();
+            let errors = message::compile_errors(&message);
+
+            if errors.is_empty() {
+                vec![message::Message::Error { message }]
+            } else {
+                errors
+            }
+        }
+    };
+
+    messages.push(message::Message::BuildFinished {
+        success: result.is_ok(),
+    });
+
+    messages
+}
+
+fn print_json(value: &impl serde::Serialize) {
+    match serde_json::to_string(value) {
+        Ok(json) => println!("{json}"),
+        Err(e) => eprintln!("Failed to serialize the result: {e}"),
+    }
+}
+
+pub fn run(args: Args) -> Result<(), Error> {
+    if args.porcelain {
+        let result = execute(args);
+        print_json(&Porcelain::new(&result));
+
+        return result.map(|_| ());
+    }
+
+    if args.message_format == MessageFormat::Json {
+        let dry_run = args.dry_run;
+        let result = execute(args);
+        messages(&result, dry_run).iter().for_each(print_json);
+
+        return result.map(|_| ());
+    }
+
+    execute(args).map(|_| ())
+}
+
+fn execute(args: Args) -> Result<Option<builder::Report>, Error> {
+    let mut config = config::Config::discover(args.config.as_deref())?;
+    let targets = mem::take(&mut config.targets);
+    let args = args.merge(config);
+
+    let builder = args.sourced_builder(targets)?;
+
+    if args.check {
+        let differences = builder.check()?;
+
+        if differences.is_empty() {
+            tracing::info!("The generated code is up to date");
+
+            return Ok(None);
+        }
+
+        if !args.porcelain && args.message_format == MessageFormat::Human {
+            differences
+                .iter()
+                .for_each(|difference| println!("  {difference}"));
+        }
+
+        return Err(Error::OutOfDate(differences));
+    }
+
+    if !args.watch {
+        let report = builder.build()?;
+
+        let human = !args.porcelain && args.message_format == MessageFormat::Human;
+        if args.dry_run && human {
+            print_dry_run(&report);
+        }
+        if args.size_report && human {
+            print_size_report(&report);
+        }
+        if let Some(unreachable) = report.unreachable.as_ref().filter(|_| human) {
+            print_unreachable(unreachable);
+        }
+
+        return Ok(Some(report));
+    }
+
+    let watcher = watch::Watcher::new(
+        &args
+            .source
+            .iter()
+            .chain(&args.include_path)
+            .cloned()
+            .collect::<Vec<_>>(),
+    )?;
+
+    loop {
+        match builder.build() {
+            Ok(report) if args.dry_run => print_dry_run(&report),
+            Ok(_) => {}
+            Err(e) => tracing::error!("{e}"),
+        }
+
+        tracing::info!("Watching for changes in the protobuf files");
+
+        watcher.wait()?.iter().for_each(|path| {
+            tracing::info!("Detected a change: {}", path.display());
+        });
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use prost::Message;
@@ -132,11 +1946,11 @@ mod tests {
             build_client: true,
             build_server: true,
             with_well_known_types: true,
-            include_path: vec![],
-            output: dst.path().to_owned(),
-            source: src,
-            temp_dir: None,
+            output: Some(dst.path().to_owned()),
+            source: vec![src],
             with_file_descriptor_set: Some(fds_path.clone()),
+            reproducible: true,
+            ..Default::default()
         };
 
         super::run(args).expect("Failed to run the application");
@@ -208,4 +2022,335 @@ mod tests {
             "Expected Ferris message package to be `crabs`"
         );
     }
+
+    #[test]
+    fn merge_prefers_command_line_arguments() {
+        let args = super::Args {
+            build_server: true,
+            output: Some(path::PathBuf::from("cli-out")),
+            ..Default::default()
+        };
+
+        let args = args.merge(crate::config::Config {
+            build_client: Some(true),
+            include_path: vec![path::PathBuf::from("vendor")],
+            output: Some(path::PathBuf::from("config-out")),
+            source: vec![path::PathBuf::from("proto")],
+            ..Default::default()
+        });
+
+        assert!(
+            args.build_client,
+            "Expected the client to be enabled by the configuration"
+        );
+        assert!(
+            args.build_server,
+            "Expected the server to be enabled by the arguments"
+        );
+        assert_eq!(
+            args.include_path,
+            vec![path::PathBuf::from("vendor")],
+            "Expected the include paths to be taken from the configuration",
+        );
+        assert_eq!(
+            args.output,
+            Some(path::PathBuf::from("cli-out")),
+            "Expected the output to be taken from the arguments",
+        );
+        assert_eq!(
+            args.source,
+            [path::PathBuf::from("proto")],
+            "Expected the source to be taken from the configuration",
+        );
+    }
+
+    #[test]
+    fn merge_lets_the_negations_override_the_configuration() {
+        use clap::Parser;
+
+        let cli = super::Cli::try_parse_from([
+            "pbuildrs",
+            "--no-build-client",
+            "--reexports",
+            "--no-reexports",
+            "--transport",
+            "--no-serde",
+            "proto",
+        ])
+        .expect("Failed to parse the arguments");
+        let args = cli.args.merge(crate::config::Config {
+            build_client: Some(true),
+            build_server: Some(true),
+            no_transport: Some(true),
+            serde: vec!["crabs".to_string()],
+            ..Default::default()
+        });
+
+        assert!(
+            !args.build_client
+                && !args.no_transport
+                && !args.reexports
+                && args.serde_packages.is_empty(),
+            "Expected the negations to turn off the flags of the configuration"
+        );
+        assert!(
+            args.build_server,
+            "Expected the flags without the negations to be kept"
+        );
+    }
+
+    #[test]
+    fn run_generates_every_target() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        let config_path = dst.path().join("pbuildrs.toml");
+
+        fs::write(
+            &config_path,
+            format!(
+                r#"
+source = {:?}
+
+[[target]]
+name = "types"
+output = "types"
+
+[[target]]
+name = "client"
+output = "client"
+build-client = true
+"#,
+                fs::canonicalize("./proto")
+                    .expect("Failed to resolve the test source directory")
+                    .display()
+                    .to_string(),
+            ),
+        )
+        .expect("Failed to write the test configuration");
+
+        super::run(super::Args {
+            config: Some(config_path),
+            ..Default::default()
+        })
+        .expect("Failed to run the application");
+
+        let types = fs::read_to_string(dst.path().join("types/crabs/mod.rs"))
+            .expect("Failed to read the generated file");
+        assert!(
+            types.contains("struct Ferris") && !types.contains("struct CrabServiceClient"),
+            "Expected the types target to contain only messages, got:\n{types}",
+        );
+
+        let client = fs::read_to_string(dst.path().join("client/crabs/mod.rs"))
+            .expect("Failed to read the generated file");
+        assert!(
+            client.contains("struct Ferris") && client.contains("struct CrabServiceClient"),
+            "Expected the client target to contain messages and the client, got:\n{client}",
+        );
+    }
+
+    #[test]
+    fn run_check_fails_on_outdated_output() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        let args = || super::Args {
+            output: Some(dst.path().join("out")),
+            source: vec![path::PathBuf::from("./proto")],
+            ..Default::default()
+        };
+
+        super::run(args()).expect("Failed to run the application");
+        super::run(super::Args {
+            check: true,
+            ..args()
+        })
+        .expect("Expected the fresh output to pass the check");
+
+        fs::remove_file(dst.path().join("out/crabs/mod.rs"))
+            .expect("Failed to remove a generated file");
+
+        let err = super::run(super::Args {
+            check: true,
+            ..args()
+        });
+        assert!(
+            matches!(&err, Err(super::Error::OutOfDate(differences)) if differences.len() == 1),
+            "Expected `Err(Error::OutOfDate(1))`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn cli_accepts_the_build_command() {
+        use clap::Parser;
+
+        let cli = super::Cli::try_parse_from(["pbuildrs", "build", "--check", "proto"])
+            .expect("Failed to parse the build command");
+        assert!(
+            matches!(cli.command, Some(super::Command::Build(args)) if args.check),
+            "Expected the build command to be parsed",
+        );
+
+        let cli = super::Cli::try_parse_from(["pbuildrs", "--build-client", "proto"])
+            .expect("Failed to parse the arguments without a command");
+        assert!(
+            cli.command.is_none() && cli.args.build_client,
+            "Expected the arguments to be parsed without a command",
+        );
+    }
+
+    #[test]
+    fn patch_into_writes_the_patched_file() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let input = dir.path().join("Ferris.proto");
+        fs::write(&input, "edition = \"2023\";\npackage crabs;\n")
+            .expect("Failed to create a test protobuf file");
+
+        let mut output = vec![];
+        let outcome = super::patch_into(&input, &mut output, crate::patcher::Syntax::Proto2, false)
+            .expect("Failed to patch the protobuf file");
+
+        assert_eq!(
+            outcome,
+            crate::Outcome::Replaced("2023".to_string()),
+            "Expected the edition to be replaced"
+        );
+        assert_eq!(
+            String::from_utf8(output).expect("The output is not valid UTF-8"),
+            "syntax = \"proto2\";\npackage crabs;\n",
+            "Invalid patched protobuf file",
+        );
+
+        let err = super::patch_into(
+            &dir.path().join("Missing.proto"),
+            vec![],
+            crate::patcher::Syntax::default(),
+            false,
+        );
+        assert!(
+            matches!(&err, Err(super::Error::OpenInput(..))),
+            "Expected `Err(Error::OpenInput)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn cli_accepts_verbosity_flags() {
+        use clap::Parser;
+
+        let cli = super::Cli::try_parse_from(["pbuildrs", "-vv", "proto"])
+            .expect("Failed to parse the verbosity flags");
+        assert_eq!(cli.args.verbose, 2, "Expected the verbosity to be counted");
+
+        let err = super::Cli::try_parse_from(["pbuildrs", "-v", "--quiet", "proto"]);
+        assert!(
+            err.is_err(),
+            "Expected `--verbose` and `--quiet` to be mutually exclusive"
+        );
+    }
+
+    #[test]
+    fn messages_point_compile_errors_at_the_source() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        let src = dst.path().join("proto");
+        fs::create_dir_all(src.join("crabs")).expect("Failed to create the test source directory");
+        fs::write(
+            src.join("crabs/Ferris.proto"),
+            "syntax = \"proto3\";\npackage crabs;\nmessage Ferris {\n  strin name = 1;\n}\n",
+        )
+        .expect("Failed to create a test protobuf file");
+
+        let result = super::execute(super::Args {
+            output: Some(dst.path().join("out")),
+            source: vec![src.clone()],
+            ..Default::default()
+        });
+
+        assert_eq!(
+            super::messages(&result, false),
+            vec![
+                crate::message::Message::CompileError {
+                    path: src.join("crabs/Ferris.proto").display().to_string(),
+                    line: 4,
+                    column: 3,
+                    message: "\"strin\" is not defined.".to_string(),
+                },
+                crate::message::Message::BuildFinished { success: false },
+            ],
+            "Invalid messages of a failed run",
+        );
+    }
+
+    #[test]
+    fn porcelain_reports_outputs_and_errors() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        let args = || super::Args {
+            output: Some(dst.path().join("out")),
+            source: vec![path::PathBuf::from("./proto")],
+            ..Default::default()
+        };
+
+        let result = super::execute(args());
+        let json = serde_json::to_value(super::Porcelain::new(&result))
+            .expect("Failed to serialize the result");
+        assert_eq!(json["status"], "success", "Invalid status, got:\n{json}");
+        assert_eq!(
+            json["outputs"][0]["target"], "default",
+            "Invalid output, got:\n{json}"
+        );
+        assert!(
+            json["outputs"][0]["files"]
+                .as_array()
+                .is_some_and(|files| !files.is_empty()),
+            "Expected the generated files to be reported, got:\n{json}",
+        );
+
+        let result = super::execute(super::Args {
+            source: vec![path::PathBuf::from("./missing")],
+            ..args()
+        });
+        let json = serde_json::to_value(super::Porcelain::new(&result))
+            .expect("Failed to serialize the result");
+        assert_eq!(json["status"], "failure", "Invalid status, got:\n{json}");
+        assert_eq!(
+            json["errors"].as_array().map(Vec::len),
+            Some(1),
+            "Expected the error to be reported, got:\n{json}",
+        );
+    }
+
+    #[test]
+    fn run_rejects_duplicate_target_names() {
+        let dst = tempfile::TempDir::new().expect("Failed to create test destination directory");
+        let config_path = dst.path().join("pbuildrs.toml");
+
+        fs::write(
+            &config_path,
+            r#"
+source = "proto"
+
+[[target]]
+name = "types"
+output = "a"
+
+[[target]]
+name = "types"
+output = "b"
+"#,
+        )
+        .expect("Failed to write the test configuration");
+
+        let err = super::run(super::Args {
+            config: Some(config_path),
+            ..Default::default()
+        });
+        assert!(
+            matches!(
+                err,
+                Err(super::Error::Build(
+                    crate::builder::Error::InvalidTargetName { .. }
+                ))
+            ),
+            "Expected `Err(Error::Build(builder::Error::InvalidTargetName))`, got: `{:?}`",
+            err
+        );
+    }
 }
@@ -0,0 +1,184 @@
+use std::{fs, io, path};
+
+use crate::portable;
+
+const BEGIN_MARKER: &str = "# BEGIN pbuildrs generated owners";
+const END_MARKER: &str = "# END pbuildrs generated owners";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to read the CODEOWNERS file `{1}`: {0}")]
+    Read(io::Error, path::PathBuf),
+    #[error("Failed to write the CODEOWNERS file `{1}`: {0}")]
+    Write(io::Error, path::PathBuf),
+    #[error("Failed to resolve the path `{1}`: {0}")]
+    Resolve(io::Error, path::PathBuf),
+    #[error("The generated directory `{0}` is outside of the repository root `{1}`")]
+    OutsideRoot(path::PathBuf, path::PathBuf),
+}
+
+/// A directory of generated code and the owners of the protobuf package it was generated from.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Entry {
+    pub dir: path::PathBuf,
+    pub owners: Vec<String>,
+}
+
+/// The directory the paths in the CODEOWNERS `file` are relative to. GitHub also looks the file up
+/// in the `.github` and `docs` directories, in which case the paths are still relative to their
+/// parent.
+fn root(file: &path::Path) -> path::PathBuf {
+    let dir = file.parent().unwrap_or(path::Path::new(""));
+
+    match dir.file_name() {
+        Some(name) if name == ".github" || name == "docs" => {
+            dir.parent().unwrap_or(path::Path::new("")).to_path_buf()
+        }
+        _ => dir.to_path_buf(),
+    }
+}
+
+fn render(entries: &[Entry], root: &path::Path) -> Result<String, Error> {
+    let absolute =
+        |path: &path::Path| path::absolute(path).map_err(|e| Error::Resolve(e, path.to_path_buf()));
+    let root = absolute(root)?;
+
+    let mut lines = entries
+        .iter()
+        .map(|entry| {
+            let dir = absolute(&entry.dir)?;
+            let dir = dir
+                .strip_prefix(&root)
+                .map_err(|_| Error::OutsideRoot(dir.clone(), root.clone()))?;
+
+            Ok((dir.to_path_buf(), entry.owners.join(" ")))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    // The last matching pattern takes precedence, so nested packages have to follow their parents.
+    lines.sort();
+
+    let rules = lines
+        .into_iter()
+        .map(|(dir, owners)| format!("/{}/ {owners}\n", portable::to_slash(&dir)))
+        .collect::<String>();
+
+    Ok(format!("{BEGIN_MARKER}\n{rules}{END_MARKER}\n"))
+}
+
+/// Replace the block of generated owners in the `contents`, appending it if there is none yet.
+fn splice(contents: &str, block: &str) -> String {
+    let start = contents.find(BEGIN_MARKER);
+    let end = contents
+        .find(END_MARKER)
+        .map(|end| end + END_MARKER.len())
+        .map(|end| end + usize::from(contents[end..].starts_with('\n')));
+
+    match (start, end) {
+        (Some(start), Some(end)) if start < end => {
+            format!("{}{block}{}", &contents[..start], &contents[end..])
+        }
+        _ if contents.is_empty() => block.to_string(),
+        _ if contents.ends_with('\n') => format!("{contents}\n{block}"),
+        _ => format!("{contents}\n\n{block}"),
+    }
+}
+
+/// Write the owners of the generated directories into the CODEOWNERS `file`, keeping any manually
+/// maintained rules outside of the generated block intact.
+pub fn update(file: &path::Path, entries: &[Entry]) -> Result<(), Error> {
+    let contents = match fs::read_to_string(file) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(Error::Read(e, file.to_path_buf())),
+    };
+
+    let block = render(entries, &root(file))?;
+
+    fs::write(file, splice(&contents, &block)).map_err(|e| Error::Write(e, file.to_path_buf()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path};
+
+    #[test]
+    fn update_keeps_manual_rules() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let file = dir.path().join(".github/CODEOWNERS");
+        fs::create_dir_all(dir.path().join(".github")).expect("Failed to create a test directory");
+        fs::write(
+            &file,
+            "* @corp/platform\n\n# BEGIN pbuildrs generated owners\n/stale/ @nobody\n# END pbuildrs generated owners\n/docs/ @corp/writers\n",
+        )
+        .expect("Failed to create a test CODEOWNERS file");
+
+        super::update(
+            &file,
+            &[
+                super::Entry {
+                    dir: dir.path().join("src/autogen/corp/billing/invoices"),
+                    owners: vec!["@corp/invoicing".to_string()],
+                },
+                super::Entry {
+                    dir: dir.path().join("src/autogen/corp/billing"),
+                    owners: vec!["@corp/billing".to_string(), "@ferris".to_string()],
+                },
+            ],
+        )
+        .expect("Failed to update the CODEOWNERS file");
+
+        assert_eq!(
+            fs::read_to_string(&file).expect("Failed to read the CODEOWNERS file"),
+            "* @corp/platform
+
+# BEGIN pbuildrs generated owners
+/src/autogen/corp/billing/ @corp/billing @ferris
+/src/autogen/corp/billing/invoices/ @corp/invoicing
+# END pbuildrs generated owners
+/docs/ @corp/writers
+",
+            "Invalid updated CODEOWNERS file",
+        );
+    }
+
+    #[test]
+    fn update_creates_missing_file() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let file = dir.path().join("CODEOWNERS");
+
+        super::update(
+            &file,
+            &[super::Entry {
+                dir: dir.path().join("out/crabs"),
+                owners: vec!["@ferris".to_string()],
+            }],
+        )
+        .expect("Failed to update the CODEOWNERS file");
+
+        assert_eq!(
+            fs::read_to_string(&file).expect("Failed to read the CODEOWNERS file"),
+            "# BEGIN pbuildrs generated owners\n/out/crabs/ @ferris\n# END pbuildrs generated owners\n",
+            "Invalid created CODEOWNERS file",
+        );
+    }
+
+    #[test]
+    fn update_rejects_directories_outside_of_the_root() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+
+        let err = super::update(
+            &dir.path().join("repo/CODEOWNERS"),
+            &[super::Entry {
+                dir: path::PathBuf::from("/elsewhere/crabs"),
+                owners: vec!["@ferris".to_string()],
+            }],
+        );
+
+        assert!(
+            matches!(err, Err(super::Error::OutsideRoot { .. })),
+            "Expected `Err(Error::OutsideRoot)`, got: `{:?}`",
+            err
+        );
+    }
+}
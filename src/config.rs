@@ -0,0 +1,725 @@
+use std::{collections, env, fs, io, path, str};
+
+use crate::{
+    attributes, manifest, modgen, newline, patcher, per_service, registry, rows, rustfmt, sandbox,
+    split, verify,
+};
+
+/// The name of the configuration file looked up in the current working directory.
+pub const DEFAULT_PATH: &str = "pbuildrs.toml";
+
+/// The name of the snapshot of the effective configuration written into the generated outputs.
+pub const EFFECTIVE_PATH: &str = "effective-config.toml";
+
+/// The environment variables affecting the generated code, recorded in the snapshots.
+const ENVIRONMENT: [&str; 2] = ["PROTOC", "PROTOC_INCLUDE"];
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to read the configuration file `{1}`: {0}")]
+    Read(io::Error, path::PathBuf),
+    #[error("Failed to parse the configuration file `{1}`: {0}")]
+    Parse(toml::de::Error, path::PathBuf),
+    #[error("Failed to serialize the effective configuration: {0}")]
+    Serialize(#[from] toml::ser::Error),
+}
+
+/// Options loaded from a `pbuildrs.toml` configuration file. Every option mirrors the command line
+/// argument of the same name.
+#[derive(serde::Deserialize, serde::Serialize, Default, PartialEq, Debug)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub build_client: Option<bool>,
+    pub build_server: Option<bool>,
+    pub messages_only: Option<bool>,
+    pub no_transport: Option<bool>,
+    pub with_well_known_types: Option<bool>,
+    #[serde(default)]
+    pub include_path: Vec<path::PathBuf>,
+    pub output: Option<path::PathBuf>,
+    pub temp_dir: Option<path::PathBuf>,
+    pub with_file_descriptor_set: Option<path::PathBuf>,
+    pub descriptor_strip_prefix: Option<String>,
+    pub descriptor_root: Option<String>,
+    #[serde(default)]
+    pub descriptor_set_in: Vec<path::PathBuf>,
+    pub verify_build: Option<bool>,
+    pub verify_feature_matrix: Option<bool>,
+    pub msrv: Option<verify::RustVersion>,
+    /// The directory of the published generated crate the next version is suggested against.
+    pub semver_baseline: Option<path::PathBuf>,
+    pub reproducible: Option<bool>,
+    #[serde(default)]
+    pub type_attribute: Vec<Attribute>,
+    #[serde(default)]
+    pub field_attribute: Vec<Attribute>,
+    /// The attributes of the generated items, keyed by a protobuf path or a glob.
+    #[serde(default)]
+    pub attributes: attributes::Rules,
+    /// The existing Rust types used in every target instead of generating the protobuf types.
+    #[serde(default)]
+    pub extern_path: Vec<ExternPath>,
+    #[serde(default)]
+    pub package_output: Vec<modgen::PackageOutput>,
+    pub output_layout: Option<modgen::Layout>,
+    pub descriptor_tree: Option<bool>,
+    pub module_visibility: Option<modgen::Visibility>,
+    /// The visibilities of the package modules, keyed by the protobuf package.
+    #[serde(default)]
+    pub package_visibility: collections::BTreeMap<String, modgen::Visibility>,
+    pub root_file: Option<String>,
+    /// The inner attributes of the root file, e.g. `#![allow(clippy::all)]`.
+    #[serde(default)]
+    pub root_attribute: Vec<String>,
+    /// The package metadata, the lints and the crate-level attributes of the generated crates.
+    pub crate_metadata: Option<manifest::Metadata>,
+    /// The banner every module file starts with, e.g. `@generated by pbuildrs {version}`.
+    pub banner: Option<String>,
+    /// The file with the license header every module file starts with.
+    pub license_header: Option<path::PathBuf>,
+    pub rustfmt: Option<bool>,
+    /// The rustfmt binary, looked up in the `PATH` unless it is a path.
+    pub rustfmt_path: Option<path::PathBuf>,
+    pub rustfmt_edition: Option<rustfmt::Edition>,
+    pub newline: Option<newline::Newline>,
+    pub codeowners: Option<path::PathBuf>,
+    /// The owners of the code generated from each protobuf package, recorded in the `codeowners`
+    /// file.
+    #[serde(default)]
+    pub owners: collections::BTreeMap<String, Vec<String>>,
+    pub incremental: Option<bool>,
+    pub cache_file: Option<path::PathBuf>,
+    /// The store of the generated code, restored by the runs with the same inputs.
+    pub cache_dir: Option<path::PathBuf>,
+    /// The read-only stores of the generated code looked up after the `cache-dir`.
+    #[serde(default)]
+    pub shared_cache: Vec<path::PathBuf>,
+    pub history: Option<bool>,
+    pub history_file: Option<path::PathBuf>,
+    pub history_limit: Option<usize>,
+    pub failure_report: Option<bool>,
+    /// The directory the report reproducing a failed run is written into.
+    pub failure_report_dir: Option<path::PathBuf>,
+    pub retries: Option<u32>,
+    /// The delay before the first retry, in milliseconds.
+    pub retry_backoff: Option<u64>,
+    pub sandbox_protoc: Option<sandbox::Sandbox>,
+    /// The command executing the protoc phase remotely, followed by the arguments of protoc.
+    #[serde(default)]
+    pub remote_executor: Vec<String>,
+    pub target_syntax: Option<patcher::Syntax>,
+    #[serde(default)]
+    pub entry_service: Vec<String>,
+    #[serde(default)]
+    pub internal_message: Vec<String>,
+    #[serde(default)]
+    pub sensitive_field: Vec<String>,
+    #[serde(default)]
+    pub serde: Vec<String>,
+    pub pbjson: Option<bool>,
+    /// The protobuf paths of the `bytes` fields generated as `bytes::Bytes`.
+    #[serde(default)]
+    pub bytes: Vec<String>,
+    /// The protobuf paths of the map fields generated as `BTreeMap`.
+    #[serde(default)]
+    pub btree_map: Vec<String>,
+    /// The protobuf paths of the string fields generated as `bytes::Bytes`.
+    #[serde(default)]
+    pub string_bytes: Vec<String>,
+    pub fail_on_import_cycles: Option<bool>,
+    pub strict: Option<bool>,
+    pub allow_missing_descriptor_set: Option<bool>,
+    pub offline: Option<bool>,
+    /// The URL of the schema registry checking the compatibility of the protobuf files.
+    pub registry_url: Option<String>,
+    pub registry_api: Option<registry::Api>,
+    pub allow_unknown_editions: Option<bool>,
+    pub default_stubs: Option<bool>,
+    pub reexports: Option<bool>,
+    pub descriptor_endpoint: Option<bool>,
+    /// The file descriptor set of the previous version the wire-compatibility tests run against.
+    pub wire_compat_tests: Option<path::PathBuf>,
+    pub effective_config: Option<bool>,
+    pub conformance_tests: Option<bool>,
+    pub recording_client: Option<bool>,
+    pub shadow_client: Option<bool>,
+    /// The size limits of the messages, keyed by the fully qualified name of the method or the
+    /// service.
+    #[serde(default)]
+    pub max_message_size: collections::BTreeMap<String, usize>,
+    /// The topics the events are published to, keyed by the fully qualified name of the message.
+    #[serde(default)]
+    pub events: collections::BTreeMap<String, String>,
+    /// The messages mapped from database rows, keyed by their fully qualified name, with the
+    /// columns of the fields not named after them.
+    #[serde(default)]
+    pub row_model: rows::Models,
+    /// The defaults of the fields, keyed by their fully qualified name.
+    #[serde(default)]
+    pub field_default: collections::BTreeMap<String, String>,
+    #[serde(default)]
+    pub encoded_size_report: Vec<String>,
+    /// The method groups the server traits are split into, keyed by the fully qualified name of
+    /// the service and the name of the group, listing the prefixes of the methods in the group.
+    #[serde(default)]
+    pub split_trait: split::Groups,
+    /// Whether to generate the client and the server of the services, keyed by the fully qualified
+    /// name of the service, overriding the `build-client` and `build-server` options.
+    #[serde(default)]
+    pub service_sides: per_service::Overrides,
+    #[serde(default)]
+    pub strip_package_prefix: Vec<String>,
+    /// The module names of the package segments, keyed by the segment.
+    #[serde(default)]
+    pub rename_module: collections::BTreeMap<String, String>,
+    /// Glob patterns of the paths skipped in every source, relative to the source path.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    pub respect_gitignore: Option<bool>,
+    pub follow_symlinks: Option<bool>,
+    /// A single source path, or a list of them.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub source: Vec<path::PathBuf>,
+    #[serde(default, rename = "target")]
+    pub targets: Vec<Target>,
+}
+
+/// A generation target, producing a separate module tree from the same compiled protobuf files.
+/// Unset options fall back to the top-level ones.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Target {
+    pub name: String,
+    pub output: path::PathBuf,
+    pub build_client: Option<bool>,
+    pub build_server: Option<bool>,
+    pub with_well_known_types: Option<bool>,
+    pub verify_build: Option<bool>,
+    pub verify_feature_matrix: Option<bool>,
+    pub msrv: Option<verify::RustVersion>,
+    /// The directory of the published generated crate the next version is suggested against.
+    pub semver_baseline: Option<path::PathBuf>,
+    #[serde(default)]
+    pub type_attribute: Vec<Attribute>,
+    #[serde(default)]
+    pub field_attribute: Vec<Attribute>,
+    #[serde(default)]
+    pub extern_path: Vec<ExternPath>,
+    #[serde(default)]
+    pub package_output: Vec<modgen::PackageOutput>,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Expected an attribute in the `<path>=<attribute>` format, got `{0}`")]
+pub struct AttributeError(String);
+
+/// An attribute added to the generated items matching the protobuf `path`.
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Attribute {
+    pub path: String,
+    pub attribute: String,
+}
+
+impl str::FromStr for Attribute {
+    type Err = AttributeError;
+
+    /// Parse the `<path>=<attribute>` form, split at the first `=`, as the protobuf paths never
+    /// contain one, unlike the attributes, e.g. `.crabs.Ferris=#[serde(rename = "ferris")]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((path, attribute)) if !path.trim().is_empty() && !attribute.trim().is_empty() => {
+                Ok(Self {
+                    path: path.trim().to_string(),
+                    attribute: attribute.trim().to_string(),
+                })
+            }
+            _ => Err(AttributeError(s.to_string())),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Expected an extern path in the `<proto-path>=<rust-path>` format, got `{0}`")]
+pub struct ExternPathError(String);
+
+/// An existing Rust type used instead of generating the protobuf types matching the `proto-path`.
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ExternPath {
+    pub proto_path: String,
+    pub rust_path: String,
+}
+
+impl str::FromStr for ExternPath {
+    type Err = ExternPathError;
+
+    /// Parse the `<proto-path>=<rust-path>` form, e.g. `.google.protobuf=::prost_types`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((proto_path, rust_path))
+                if !proto_path.trim().is_empty() && !rust_path.trim().is_empty() =>
+            {
+                Ok(Self {
+                    proto_path: proto_path.trim().to_string(),
+                    rust_path: rust_path.trim().to_string(),
+                })
+            }
+            _ => Err(ExternPathError(s.to_string())),
+        }
+    }
+}
+
+/// Accept either a single path or a list of them.
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<path::PathBuf>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(path::PathBuf),
+        Many(Vec<path::PathBuf>),
+    }
+
+    Ok(
+        match <OneOrMany as serde::Deserialize>::deserialize(deserializer)? {
+            OneOrMany::One(path) => vec![path],
+            OneOrMany::Many(paths) => paths,
+        },
+    )
+}
+
+impl Config {
+    /// Parse the configuration, resolving any relative paths against the `base` directory.
+    pub fn parse(contents: &str, base: &path::Path, file: &path::Path) -> Result<Self, Error> {
+        let config: Self =
+            toml::from_str(contents).map_err(|e| Error::Parse(e, file.to_path_buf()))?;
+
+        Ok(config.resolve(base))
+    }
+
+    /// Load the configuration from the `file`, resolving relative paths against its directory.
+    pub fn load(file: &path::Path) -> Result<Self, Error> {
+        let contents = fs::read_to_string(file).map_err(|e| Error::Read(e, file.to_path_buf()))?;
+        let base = file.parent().unwrap_or(path::Path::new(""));
+
+        Self::parse(&contents, base, file)
+    }
+
+    /// Load the configuration from the `file` if provided, otherwise from the `pbuildrs.toml` in
+    /// the current working directory if it exists.
+    pub fn discover(file: Option<&path::Path>) -> Result<Self, Error> {
+        match file {
+            Some(file) => Self::load(file),
+            None if path::Path::new(DEFAULT_PATH).is_file() => {
+                Self::load(path::Path::new(DEFAULT_PATH))
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    fn resolve(self, base: &path::Path) -> Self {
+        // Collecting the components drops the `.` ones, e.g. of an output relative to itself.
+        let resolve = |path: path::PathBuf| base.join(path).components().collect();
+
+        Self {
+            include_path: self.include_path.into_iter().map(resolve).collect(),
+            output: self.output.map(resolve),
+            temp_dir: self.temp_dir.map(resolve),
+            with_file_descriptor_set: self.with_file_descriptor_set.map(resolve),
+            descriptor_set_in: self.descriptor_set_in.into_iter().map(resolve).collect(),
+            package_output: self
+                .package_output
+                .into_iter()
+                .map(|package| package.map_output(resolve))
+                .collect(),
+            license_header: self.license_header.map(resolve),
+            codeowners: self.codeowners.map(resolve),
+            cache_file: self.cache_file.map(resolve),
+            cache_dir: self.cache_dir.map(resolve),
+            shared_cache: self.shared_cache.into_iter().map(resolve).collect(),
+            history_file: self.history_file.map(resolve),
+            failure_report_dir: self.failure_report_dir.map(resolve),
+            wire_compat_tests: self.wire_compat_tests.map(resolve),
+            semver_baseline: self.semver_baseline.map(resolve),
+            source: self.source.into_iter().map(resolve).collect(),
+            targets: self
+                .targets
+                .into_iter()
+                .map(|target| Target {
+                    output: resolve(target.output),
+                    semver_baseline: target.semver_baseline.map(resolve),
+                    package_output: target
+                        .package_output
+                        .into_iter()
+                        .map(|package| package.map_output(resolve))
+                        .collect(),
+                    ..target
+                })
+                .collect(),
+            ..self
+        }
+    }
+
+    /// Make every path relative to the `base` directory, the reverse of resolving them on load, so
+    /// the configuration can be loaded from a file placed there.
+    pub fn relative_to(self, base: &path::Path) -> Self {
+        let relative = |path: path::PathBuf| relative(&path, base);
+
+        Self {
+            include_path: self.include_path.into_iter().map(relative).collect(),
+            output: self.output.map(relative),
+            temp_dir: self.temp_dir.map(relative),
+            with_file_descriptor_set: self.with_file_descriptor_set.map(relative),
+            descriptor_set_in: self.descriptor_set_in.into_iter().map(relative).collect(),
+            package_output: self
+                .package_output
+                .into_iter()
+                .map(|package| package.map_output(relative))
+                .collect(),
+            license_header: self.license_header.map(relative),
+            codeowners: self.codeowners.map(relative),
+            cache_file: self.cache_file.map(relative),
+            cache_dir: self.cache_dir.map(relative),
+            shared_cache: self.shared_cache.into_iter().map(relative).collect(),
+            history_file: self.history_file.map(relative),
+            failure_report_dir: self.failure_report_dir.map(relative),
+            wire_compat_tests: self.wire_compat_tests.map(relative),
+            semver_baseline: self.semver_baseline.map(relative),
+            source: self.source.into_iter().map(relative).collect(),
+            targets: self
+                .targets
+                .into_iter()
+                .map(|target| Target {
+                    output: relative(target.output),
+                    semver_baseline: target.semver_baseline.map(relative),
+                    package_output: target
+                        .package_output
+                        .into_iter()
+                        .map(|package| package.map_output(relative))
+                        .collect(),
+                    ..target
+                })
+                .collect(),
+            ..self
+        }
+    }
+
+    /// The configuration in the format of the configuration file, preceded by the values of the
+    /// environment variables affecting the generated code as comments.
+    pub fn snapshot(&self) -> Result<String, Error> {
+        let mut snapshot = String::from(
+            "# The effective configuration of the run generating this directory, the paths are \
+            relative to it.\n",
+        );
+        ENVIRONMENT
+            .iter()
+            .filter_map(|name| Some((name, env::var_os(name)?)))
+            .for_each(|(name, value)| {
+                snapshot.push_str(&format!("# {name}={}\n", value.to_string_lossy()));
+            });
+        snapshot.push('\n');
+        snapshot.push_str(&toml::to_string(self)?);
+
+        Ok(snapshot)
+    }
+}
+
+/// The `path` relative to the `base` directory, e.g. `../proto` for `proto` from `out`.
+pub fn relative(path: &path::Path, base: &path::Path) -> path::PathBuf {
+    let (Ok(path), Ok(base)) = (path::absolute(path), path::absolute(base)) else {
+        return path.to_path_buf();
+    };
+
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let relative = base
+        .components()
+        .skip(common)
+        .map(|_| path::Component::ParentDir)
+        .chain(path.components().skip(common))
+        .collect::<path::PathBuf>();
+
+    match relative.as_os_str().is_empty() {
+        true => path::PathBuf::from("."),
+        false => relative,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path};
+
+    #[test]
+    fn parse_resolves_relative_paths() {
+        let config = super::Config::parse(
+            r#"
+build-client = true
+include-path = ["vendor", "/usr/include"]
+output = "src/autogen"
+source = "proto"
+msrv = "1.85"
+"#,
+            path::Path::new("/home/crab/project"),
+            path::Path::new("/home/crab/project/pbuildrs.toml"),
+        )
+        .expect("Failed to parse the configuration");
+
+        assert_eq!(
+            config,
+            super::Config {
+                build_client: Some(true),
+                include_path: vec![
+                    path::PathBuf::from("/home/crab/project/vendor"),
+                    path::PathBuf::from("/usr/include"),
+                ],
+                output: Some(path::PathBuf::from("/home/crab/project/src/autogen")),
+                source: vec![path::PathBuf::from("/home/crab/project/proto")],
+                msrv: Some("1.85".parse().expect("Failed to parse a Rust version")),
+                ..Default::default()
+            },
+            "Invalid parsed configuration",
+        );
+    }
+
+    #[test]
+    fn parse_accepts_multiple_sources() {
+        let config = super::Config::parse(
+            "source = [\"proto\", \"/vendor/thirdparty-proto\"]\n",
+            path::Path::new("/home/crab/project"),
+            path::Path::new("/home/crab/project/pbuildrs.toml"),
+        )
+        .expect("Failed to parse the configuration");
+
+        assert_eq!(
+            config.source,
+            [
+                path::PathBuf::from("/home/crab/project/proto"),
+                path::PathBuf::from("/vendor/thirdparty-proto"),
+            ],
+            "Invalid parsed sources",
+        );
+    }
+
+    #[test]
+    fn parse_rejects_unknown_options() {
+        let err = super::Config::parse(
+            "build-clients = true\n",
+            path::Path::new(""),
+            path::Path::new("pbuildrs.toml"),
+        );
+
+        assert!(
+            matches!(err, Err(super::Error::Parse { .. })),
+            "Expected `Err(Error::Parse)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn load_fails_if_the_file_is_missing() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+
+        let err = super::Config::load(&dir.path().join("pbuildrs.toml"));
+
+        assert!(
+            matches!(err, Err(super::Error::Read { .. })),
+            "Expected `Err(Error::Read)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn load_resolves_paths_relative_to_the_file() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let file = dir.path().join("pbuildrs.toml");
+        fs::write(&file, "source = \"proto\"\n").expect("Failed to create a test config file");
+
+        let config = super::Config::load(&file).expect("Failed to load the configuration");
+
+        assert_eq!(
+            config.source,
+            [dir.path().join("proto")],
+            "Expected the source to be relative to the configuration file",
+        );
+    }
+
+    #[test]
+    fn parse_resolves_package_outputs() {
+        let config = super::Config::parse(
+            r#"
+package-output = [{ package = "corp.billing.*", output = "crates/billing/src/autogen" }]
+"#,
+            path::Path::new("/home/crab/project"),
+            path::Path::new("/home/crab/project/pbuildrs.toml"),
+        )
+        .expect("Failed to parse the configuration");
+
+        assert_eq!(
+            config.package_output,
+            vec![
+                crate::modgen::PackageOutput::new(
+                    "corp.billing",
+                    "/home/crab/project/crates/billing/src/autogen",
+                )
+                .expect("Failed to create a package output")
+            ],
+            "Invalid parsed package outputs",
+        );
+    }
+
+    #[test]
+    fn parse_reads_targets() {
+        let config = super::Config::parse(
+            r##"
+source = "proto"
+
+[[target]]
+name = "types"
+output = "types/src/autogen"
+
+[[target]]
+name = "client"
+output = "client/src/autogen"
+build-client = true
+type-attribute = [{ path = ".", attribute = "#[derive(serde::Serialize)]" }]
+extern-path = [{ proto-path = ".google.protobuf", rust-path = "::prost_types" }]
+"##,
+            path::Path::new("/home/crab/project"),
+            path::Path::new("/home/crab/project/pbuildrs.toml"),
+        )
+        .expect("Failed to parse the configuration");
+
+        assert_eq!(
+            config.targets,
+            vec![
+                super::Target {
+                    name: "types".to_string(),
+                    output: path::PathBuf::from("/home/crab/project/types/src/autogen"),
+                    build_client: None,
+                    build_server: None,
+                    with_well_known_types: None,
+                    verify_build: None,
+                    verify_feature_matrix: None,
+                    msrv: None,
+                    semver_baseline: None,
+                    type_attribute: vec![],
+                    field_attribute: vec![],
+                    extern_path: vec![],
+                    package_output: vec![],
+                },
+                super::Target {
+                    name: "client".to_string(),
+                    output: path::PathBuf::from("/home/crab/project/client/src/autogen"),
+                    build_client: Some(true),
+                    build_server: None,
+                    with_well_known_types: None,
+                    verify_build: None,
+                    verify_feature_matrix: None,
+                    msrv: None,
+                    semver_baseline: None,
+                    type_attribute: vec![super::Attribute {
+                        path: ".".to_string(),
+                        attribute: "#[derive(serde::Serialize)]".to_string(),
+                    }],
+                    field_attribute: vec![],
+                    extern_path: vec![super::ExternPath {
+                        proto_path: ".google.protobuf".to_string(),
+                        rust_path: "::prost_types".to_string(),
+                    }],
+                    package_output: vec![],
+                },
+            ],
+            "Invalid parsed targets",
+        );
+    }
+
+    #[test]
+    fn snapshot_parses_back_relative_to_the_output() {
+        let config = super::Config {
+            build_client: Some(true),
+            output: Some(path::PathBuf::from("/home/crab/project/src/autogen")),
+            source: vec![path::PathBuf::from("/home/crab/project/proto")],
+            ..Default::default()
+        };
+
+        let snapshot = config
+            .relative_to(path::Path::new("/home/crab/project/src/autogen"))
+            .snapshot()
+            .expect("Failed to snapshot the configuration");
+        assert!(
+            snapshot.contains("source = [\"../../proto\"]\n"),
+            "Expected the paths relative to the output, got: {snapshot}",
+        );
+
+        let parsed = super::Config::parse(
+            &snapshot,
+            path::Path::new("/home/crab/project/src/autogen"),
+            path::Path::new("/home/crab/project/src/autogen/effective-config.toml"),
+        )
+        .expect("Failed to parse the snapshot");
+        assert_eq!(
+            (parsed.build_client, parsed.output, parsed.source),
+            (
+                Some(true),
+                Some(path::PathBuf::from("/home/crab/project/src/autogen")),
+                vec![path::PathBuf::from(
+                    "/home/crab/project/src/autogen/../../proto"
+                )],
+            ),
+            "Invalid configuration parsed from the snapshot",
+        );
+    }
+
+    #[test]
+    fn attribute_splits_at_the_first_equals_sign() {
+        let attribute = r#".crabs.Ferris=#[serde(rename = "ferris")]"#
+            .parse::<super::Attribute>()
+            .expect("Failed to parse an attribute");
+        assert_eq!(
+            attribute,
+            super::Attribute {
+                path: ".crabs.Ferris".to_string(),
+                attribute: r#"#[serde(rename = "ferris")]"#.to_string(),
+            },
+            "Invalid attribute parsed",
+        );
+
+        ["#[non_exhaustive]", "=#[non_exhaustive]", ".crabs.Ferris="]
+            .into_iter()
+            .for_each(|s| {
+                let err = s.parse::<super::Attribute>();
+                assert!(
+                    matches!(&err, Err(super::AttributeError(value)) if value == s),
+                    "Expected `Err(AttributeError)`, got: `{:?}`",
+                    err
+                );
+            });
+    }
+
+    #[test]
+    fn extern_path_splits_the_proto_and_rust_paths() {
+        let extern_path = ".google.protobuf = ::prost_types"
+            .parse::<super::ExternPath>()
+            .expect("Failed to parse an extern path");
+        assert_eq!(
+            extern_path,
+            super::ExternPath {
+                proto_path: ".google.protobuf".to_string(),
+                rust_path: "::prost_types".to_string(),
+            },
+            "Invalid extern path parsed",
+        );
+
+        let err = ".google.protobuf".parse::<super::ExternPath>();
+        assert!(
+            matches!(&err, Err(super::ExternPathError(value)) if value == ".google.protobuf"),
+            "Expected `Err(ExternPathError)`, got: `{:?}`",
+            err
+        );
+    }
+}
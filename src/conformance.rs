@@ -0,0 +1,134 @@
+use std::{fmt::Write as _, path};
+
+use crate::services;
+
+/// The environment variable holding the address of the endpoint the conformance tests run
+/// against. The tests are skipped when it is not set.
+pub const ENDPOINT_VAR: &str = "PBUILDRS_CONFORMANCE_ENDPOINT";
+
+/// The environment variable listing the methods expected to answer with `Unimplemented`,
+/// separated by commas, e.g. `crabs.CrabService/GetFerris`.
+pub const UNIMPLEMENTED_VAR: &str = "PBUILDRS_CONFORMANCE_UNIMPLEMENTED";
+
+/// The conformance test module of a service, calling every unary method of the service with a
+/// default request. Streaming methods are skipped.
+fn render(service: &services::Service<'_>) -> String {
+    let full_name = service.full_name();
+    let client = service.client();
+
+    let mut code = format!(
+        r#"
+/// Conformance tests of the `{full_name}` service, run against the endpoint in the
+/// `{ENDPOINT_VAR}` environment variable and skipped when it is not set.
+/// The methods listed in `{UNIMPLEMENTED_VAR}` are expected to be unimplemented.
+#[cfg(all(test, feature = "client"))]
+mod {module} {{
+    async fn conformance_client() -> Option<{client}<tonic::transport::Channel>> {{
+        let endpoint = std::env::var("{ENDPOINT_VAR}").ok()?;
+
+        Some(
+            {client}::connect(endpoint)
+                .await
+                .expect("Failed to connect to the conformance endpoint"),
+        )
+    }}
+
+    fn conformance_check<T>(method: &str, response: Result<tonic::Response<T>, tonic::Status>) {{
+        let unimplemented = std::env::var("{UNIMPLEMENTED_VAR}")
+            .unwrap_or_default()
+            .split(',')
+            .any(|name| name.trim() == method);
+
+        match response {{
+            Ok(_) => assert!(!unimplemented, "Expected `{{method}}` to be unimplemented"),
+            Err(status) if unimplemented => assert_eq!(
+                status.code(),
+                tonic::Code::Unimplemented,
+                "Expected `{{method}}` to be unimplemented, got: `{{status:?}}`",
+            ),
+            Err(status) => assert!(
+                !matches!(
+                    status.code(),
+                    tonic::Code::Unimplemented
+                        | tonic::Code::Unknown
+                        | tonic::Code::Internal
+                        | tonic::Code::Unavailable
+                        | tonic::Code::DataLoss
+                ),
+                "Unexpected status of `{{method}}`: `{{status:?}}`",
+            ),
+        }}
+    }}
+"#,
+        module = service.module("conformance"),
+    );
+
+    service.unary_methods().for_each(|method| {
+        let _ = write!(
+            code,
+            r#"
+    #[tokio::test]
+    async fn {function}() {{
+        let Some(mut client) = conformance_client().await else {{
+            return;
+        }};
+
+        let response: Result<tonic::Response<{output}>, tonic::Status> = client
+            .{function}(<{input} as ::core::default::Default>::default())
+            .await;
+        conformance_check("{full_name}/{method}", response);
+    }}
+"#,
+            function = method.function,
+            input = method.input,
+            output = method.output,
+            method = method.name,
+        );
+    });
+
+    code.push_str("}\n");
+    code
+}
+
+/// Append a conformance test module for every service of the `fds` to the code generated by
+/// prost into the `dir`.
+pub fn write(
+    fds: &prost_types::FileDescriptorSet,
+    dir: &path::Path,
+    codegen: &services::Codegen<'_>,
+) -> Result<(), services::Error> {
+    services::append(fds, dir, codegen, render)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn render_tests_the_unary_methods() {
+        let code =
+            crate::services::tests::render(&crate::services::tests::service(), super::render);
+
+        assert!(
+            code.contains("mod crab_service_conformance {"),
+            "Expected a module per service, got:\n{code}",
+        );
+        assert!(
+            code.contains("super::crab_service_client::CrabServiceClient::connect(endpoint)"),
+            "Expected the tonic client to be used, got:\n{code}",
+        );
+        assert!(
+            code.contains(
+                "let response: Result<tonic::Response<super::Ferris>, tonic::Status> = client\n            \
+                .get_ferris(<super::GetFerrisRequest as ::core::default::Default>::default())"
+            ),
+            "Expected the unary method to be called, got:\n{code}",
+        );
+        assert!(
+            code.contains("conformance_check(\"crabs.CrabService/GetFerris\", response);"),
+            "Expected the status to be checked, got:\n{code}",
+        );
+        assert!(
+            !code.contains("watch_ferris"),
+            "Expected the streaming method to be skipped, got:\n{code}",
+        );
+    }
+}
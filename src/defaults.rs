@@ -0,0 +1,445 @@
+use std::{collections, fmt::Write as _, fs, io, path, str};
+
+use prost_types::field_descriptor_proto::{Label, Type};
+
+use crate::services;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Invalid field default `{0}`, expected `<FIELD>=<VALUE>`")]
+    InvalidDefault(String),
+    #[error("The field with a default `{0}` is not a field of any message")]
+    UnknownField(String),
+    #[error("The field with a default `{0}` is not a singular scalar or enum field")]
+    UnsupportedField(String),
+    #[error("The default `{1}` is not a valid value of the field `{0}`")]
+    InvalidValue(String, String),
+    #[error("Failed to append the default constructors to `{1}`: {0}")]
+    Write(io::Error, path::PathBuf),
+}
+
+/// The default of a field, given by its fully qualified name, overriding the protobuf zero value,
+/// e.g. `crabs.ListFerrisRequest.page_size=50`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FieldDefault {
+    pub field: String,
+    pub value: String,
+}
+
+impl str::FromStr for FieldDefault {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, value) = s
+            .split_once('=')
+            .ok_or_else(|| Error::InvalidDefault(s.to_string()))?;
+        let field = field.trim().trim_start_matches('.');
+        if field.is_empty() {
+            return Err(Error::InvalidDefault(s.to_string()));
+        }
+
+        Ok(Self {
+            field: field.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+type Enums<'a> = collections::BTreeMap<String, &'a prost_types::EnumDescriptorProto>;
+
+fn push_enums<'a>(
+    found: &mut Enums<'a>,
+    scope: &str,
+    enums: &'a [prost_types::EnumDescriptorProto],
+    messages: &'a [prost_types::DescriptorProto],
+) {
+    enums.iter().for_each(|descriptor| {
+        found.insert(format!("{scope}.{}", descriptor.name()), descriptor);
+    });
+    messages.iter().for_each(|message| {
+        push_enums(
+            found,
+            &format!("{scope}.{}", message.name()),
+            &message.enum_type,
+            &message.nested_type,
+        );
+    });
+}
+
+/// The enums of the `fds`, including the nested ones, keyed by the paths the fields refer to them
+/// by, e.g. `.crabs.Ferris.Kind`.
+fn enums(fds: &prost_types::FileDescriptorSet) -> Enums<'_> {
+    let mut found = collections::BTreeMap::new();
+    fds.file.iter().for_each(|file| {
+        let scope = match file.package() {
+            "" => String::new(),
+            package => format!(".{package}"),
+        };
+        push_enums(&mut found, &scope, &file.enum_type, &file.message_type);
+    });
+
+    found
+}
+
+/// The Rust expression of the default `value` of the singular scalar or enum `field`, named
+/// `name`, or an error if the field has no such value or can't be given a default.
+fn literal(
+    enums: &Enums<'_>,
+    file: &prost_types::FileDescriptorProto,
+    name: &str,
+    field: &prost_types::FieldDescriptorProto,
+    value: &str,
+) -> Result<String, Error> {
+    let is_oneof = field.oneof_index.is_some() && !field.proto3_optional();
+    if is_oneof || field.label() == Label::Repeated {
+        return Err(Error::UnsupportedField(name.to_string()));
+    }
+
+    let invalid = || Error::InvalidValue(name.to_string(), value.to_string());
+    let trimmed = value.trim();
+    let literal = match field.r#type() {
+        Type::Double => trimmed
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .map(|value| format!("{value:?}")),
+        Type::Float => trimmed
+            .parse::<f32>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .map(|value| format!("{value:?}")),
+        Type::Int64 | Type::Sint64 | Type::Sfixed64 => {
+            trimmed.parse::<i64>().ok().map(|value| value.to_string())
+        }
+        Type::Uint64 | Type::Fixed64 => trimmed.parse::<u64>().ok().map(|value| value.to_string()),
+        Type::Int32 | Type::Sint32 | Type::Sfixed32 => {
+            trimmed.parse::<i32>().ok().map(|value| value.to_string())
+        }
+        Type::Uint32 | Type::Fixed32 => trimmed.parse::<u32>().ok().map(|value| value.to_string()),
+        Type::Bool => trimmed.parse::<bool>().ok().map(|value| value.to_string()),
+        Type::String => Some(format!("{value:?}.into()")),
+        Type::Bytes => Some(format!("{value:?}.as_bytes().into()")),
+        Type::Enum => enums
+            .get(field.type_name())
+            .and_then(|descriptor| descriptor.value.iter().find(|v| v.name() == trimmed))
+            .map(|value| value.number().to_string()),
+        Type::Message | Type::Group => return Err(Error::UnsupportedField(name.to_string())),
+    }
+    .ok_or_else(invalid)?;
+
+    Ok(match field.label() {
+        Label::Optional if field.proto3_optional() || file.syntax() != "proto3" => {
+            format!("Some({literal})")
+        }
+        _ => literal,
+    })
+}
+
+/// A message with any fields with a default, with the fully qualified names of the fields and the
+/// Rust expressions of their defaults.
+struct Overrides<'a> {
+    message: services::Message<'a>,
+    fields: Vec<(String, &'a prost_types::FieldDescriptorProto, String)>,
+}
+
+/// The messages of the `file` with any of the `defaults`, keyed by the fully qualified name of the
+/// field.
+fn overrides<'a>(
+    enums: &Enums<'_>,
+    file: &'a prost_types::FileDescriptorProto,
+    defaults: &collections::BTreeMap<String, String>,
+) -> Result<Vec<Overrides<'a>>, Error> {
+    services::messages(file)
+        .into_iter()
+        .map(|message| {
+            let fields = message
+                .descriptor
+                .field
+                .iter()
+                .filter_map(|field| {
+                    let name = format!("{}.{}", message.full_name, field.name());
+                    defaults.get(&name).map(|value| {
+                        literal(enums, file, &name, field, value)
+                            .map(|literal| (name, field, literal))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(Overrides { message, fields })
+        })
+        .filter(|overrides| {
+            overrides
+                .as_ref()
+                .map_or(true, |overrides| !overrides.fields.is_empty())
+        })
+        .collect()
+}
+
+/// Ensure every field with a default, given by its fully qualified name, is a singular scalar or
+/// enum field defined in the `fds`, and its default is a valid value of it.
+pub fn validate(
+    fds: &prost_types::FileDescriptorSet,
+    defaults: &collections::BTreeMap<String, String>,
+) -> Result<(), Error> {
+    let enums = enums(fds);
+    let mut found = collections::BTreeSet::new();
+    fds.file.iter().try_for_each(|file| {
+        overrides(&enums, file, defaults)?
+            .into_iter()
+            .flat_map(|overrides| overrides.fields)
+            .for_each(|(name, _, _)| {
+                found.insert(name);
+            });
+
+        Ok(())
+    })?;
+
+    defaults
+        .keys()
+        .find(|field| !found.contains(*field))
+        .map_or(Ok(()), |field| Err(Error::UnknownField(field.clone())))
+}
+
+/// The `with_defaults` constructor of the message, setting the fields to their defaults and
+/// leaving the other ones at their protobuf zero values.
+fn render(overrides: &Overrides<'_>) -> String {
+    let mut fields = String::new();
+    overrides.fields.iter().for_each(|(_, field, literal)| {
+        let _ = write!(
+            fields,
+            "\n            {}: {literal},",
+            services::to_snake(field.name()),
+        );
+    });
+    if overrides.fields.len() < overrides.message.descriptor.field.len() {
+        fields.push_str("\n            ..::core::default::Default::default()");
+    }
+
+    format!(
+        r#"
+impl {path} {{
+    /// A `{full_name}` with the defaults configured for its fields.
+    ///
+    /// The other fields are left at their protobuf zero values.
+    pub fn with_defaults() -> Self {{
+        Self {{{fields}
+        }}
+    }}
+}}
+"#,
+        path = overrides.message.path,
+        full_name = overrides.message.full_name,
+    )
+}
+
+/// Append the `with_defaults` constructors of the messages with any of the `defaults`, keyed by
+/// the fully qualified name of the field, to the code generated by prost into the `dir`, next to
+/// the package of the message.
+pub fn write(
+    fds: &prost_types::FileDescriptorSet,
+    dir: &path::Path,
+    defaults: &collections::BTreeMap<String, String>,
+) -> Result<(), Error> {
+    let enums = enums(fds);
+    fds.file.iter().try_for_each(|file| {
+        let code = overrides(&enums, file, defaults)?
+            .iter()
+            .map(render)
+            .collect::<String>();
+        if code.is_empty() {
+            return Ok(());
+        }
+
+        let path = dir.join(match file.package() {
+            "" => "_.rs".to_string(),
+            package => format!("{package}.rs"),
+        });
+        tracing::debug!("Appending the default constructors: {}", path.display());
+
+        fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .and_then(|mut out| io::Write::write_all(&mut out, code.as_bytes()))
+            .map_err(|e| Error::Write(e, path))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_types::field_descriptor_proto::{Label, Type};
+    use std::{collections, fs};
+
+    fn fds() -> prost_types::FileDescriptorSet {
+        let field = |name: &str, r#type: Type, label: Label| prost_types::FieldDescriptorProto {
+            name: Some(name.to_string()),
+            r#type: Some(r#type.into()),
+            label: Some(label.into()),
+            ..Default::default()
+        };
+
+        prost_types::FileDescriptorSet {
+            file: vec![prost_types::FileDescriptorProto {
+                package: Some("crabs".to_string()),
+                syntax: Some("proto3".to_string()),
+                message_type: vec![prost_types::DescriptorProto {
+                    name: Some("Shell".to_string()),
+                    nested_type: vec![prost_types::DescriptorProto {
+                        name: Some("ListFerrisRequest".to_string()),
+                        field: vec![
+                            field("page_size", Type::Uint32, Label::Optional),
+                            field("type", Type::String, Label::Optional),
+                            prost_types::FieldDescriptorProto {
+                                type_name: Some(".crabs.Shell.Order".to_string()),
+                                ..field("order", Type::Enum, Label::Optional)
+                            },
+                            field("claws", Type::Uint32, Label::Repeated),
+                            prost_types::FieldDescriptorProto {
+                                proto3_optional: Some(true),
+                                oneof_index: Some(0),
+                                ..field("ratio", Type::Double, Label::Optional)
+                            },
+                        ],
+                        ..Default::default()
+                    }],
+                    enum_type: vec![prost_types::EnumDescriptorProto {
+                        name: Some("Order".to_string()),
+                        value: vec![
+                            prost_types::EnumValueDescriptorProto {
+                                name: Some("ORDER_UNSPECIFIED".to_string()),
+                                number: Some(0),
+                                ..Default::default()
+                            },
+                            prost_types::EnumValueDescriptorProto {
+                                name: Some("ORDER_NEWEST".to_string()),
+                                number: Some(2),
+                                ..Default::default()
+                            },
+                        ],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    fn defaults(defaults: &[(&str, &str)]) -> collections::BTreeMap<String, String> {
+        defaults
+            .iter()
+            .map(|(field, value)| ((*field).to_string(), (*value).to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn field_default_parses_the_field_and_the_value() {
+        assert_eq!(
+            ".crabs.ListFerrisRequest.page_size=50"
+                .parse::<super::FieldDefault>()
+                .expect("Failed to parse a field default"),
+            super::FieldDefault {
+                field: "crabs.ListFerrisRequest.page_size".to_string(),
+                value: "50".to_string(),
+            },
+            "Invalid parsed field default",
+        );
+        assert_eq!(
+            "crabs.Ferris.name="
+                .parse::<super::FieldDefault>()
+                .expect("Failed to parse a field default")
+                .value,
+            "",
+        );
+
+        ["crabs.Ferris.name", "=50"]
+            .into_iter()
+            .for_each(|default| {
+                let result = default.parse::<super::FieldDefault>();
+                assert!(
+                    matches!(result, Err(super::Error::InvalidDefault(_))),
+                    "Expected `Err(Error::InvalidDefault(_))` for `{default}`, got: `{result:?}`",
+                );
+            });
+    }
+
+    #[test]
+    fn validate_rejects_invalid_defaults() {
+        super::validate(
+            &fds(),
+            &defaults(&[
+                ("crabs.Shell.ListFerrisRequest.page_size", "50"),
+                ("crabs.Shell.ListFerrisRequest.order", "ORDER_NEWEST"),
+            ]),
+        )
+        .expect("Expected the field defaults to be valid");
+
+        let err = super::validate(&fds(), &defaults(&[("crabs.Shell.page_size", "50")]));
+        assert!(
+            matches!(&err, Err(super::Error::UnknownField(field)) if field == "crabs.Shell.page_size"),
+            "Expected `Err(Error::UnknownField)`, got: `{:?}`",
+            err
+        );
+
+        let err = super::validate(
+            &fds(),
+            &defaults(&[("crabs.Shell.ListFerrisRequest.claws", "4")]),
+        );
+        assert!(
+            matches!(&err, Err(super::Error::UnsupportedField(_))),
+            "Expected `Err(Error::UnsupportedField)`, got: `{:?}`",
+            err
+        );
+
+        [
+            ("page_size", "-1"),
+            ("order", "ORDER_OLDEST"),
+            ("ratio", "NaN"),
+        ]
+        .into_iter()
+        .for_each(|(field, value)| {
+            let field = format!("crabs.Shell.ListFerrisRequest.{field}");
+            let result = super::validate(&fds(), &defaults(&[(&field, value)]));
+            assert!(
+                matches!(result, Err(super::Error::InvalidValue(_, _))),
+                "Expected `Err(Error::InvalidValue(_, _))` for `{value}`, got: `{result:?}`",
+            );
+        });
+    }
+
+    #[test]
+    fn write_generates_the_default_constructors() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+
+        super::write(
+            &fds(),
+            dir.path(),
+            &defaults(&[
+                ("crabs.Shell.ListFerrisRequest.page_size", "50"),
+                ("crabs.Shell.ListFerrisRequest.type", "hermit \"crab\""),
+                ("crabs.Shell.ListFerrisRequest.order", "ORDER_NEWEST"),
+                ("crabs.Shell.ListFerrisRequest.ratio", "0.5"),
+            ]),
+        )
+        .expect("Failed to write the default constructors");
+
+        let code = fs::read_to_string(dir.path().join("crabs.rs"))
+            .expect("Failed to read the generated code");
+        [
+            "impl shell::ListFerrisRequest {",
+            "    pub fn with_defaults() -> Self {",
+            "            page_size: 50,\n",
+            "            r#type: \"hermit \\\"crab\\\"\".into(),\n",
+            "            order: 2,\n",
+            "            ratio: Some(0.5),\n",
+            "            ..::core::default::Default::default()\n",
+        ]
+        .into_iter()
+        .for_each(|expected| {
+            assert!(
+                code.contains(expected),
+                "Expected `{expected}` in the default constructor, got:\n{code}",
+            );
+        });
+    }
+}
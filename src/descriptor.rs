@@ -0,0 +1,365 @@
+use prost::Message;
+use std::{collections, env, ffi, fs, io, path};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to read the file descriptor set `{1}`: {0}")]
+    Read(io::Error, path::PathBuf),
+    #[error("Failed to decode the file descriptor set `{1}`: {0}")]
+    Decode(prost::DecodeError, path::PathBuf),
+    #[error("Failed to write the file descriptor set `{1}`: {0}")]
+    Write(io::Error, path::PathBuf),
+    #[error("Failed to resolve the path of the file descriptor set `{1}`: {0}")]
+    Resolve(io::Error, path::PathBuf),
+    #[error("Failed to pass the file descriptor sets to protoc: {0}")]
+    JoinPaths(env::JoinPathsError),
+    #[error(
+        "The protobuf file `{0}` in the file descriptor set `{1}` differs from the one with the \
+        same name merged before it"
+    )]
+    ConflictingFile(String, path::PathBuf),
+    #[error("The type `{0}` is defined in both `{1}` and `{2}`")]
+    DuplicateType(String, String, String),
+    #[error(
+        "The protobuf file `{0}` imports `{1}`, which is missing from the file descriptor sets, \
+        build them with `protoc --include_imports`"
+    )]
+    MissingImport(String, String),
+}
+
+/// Rules to rewrite the file names recorded in a file descriptor set.
+#[derive(Default, Debug)]
+pub struct Remap {
+    /// Remove this directory prefix from every file name that starts with it.
+    pub strip_prefix: Option<String>,
+    /// Place every file name under this virtual root directory.
+    pub root: Option<String>,
+}
+
+impl Remap {
+    pub fn is_empty(&self) -> bool {
+        self.strip_prefix.is_none() && self.root.is_none()
+    }
+
+    fn apply(&self, name: &str) -> String {
+        let name = match &self.strip_prefix {
+            Some(prefix) => {
+                let prefix = prefix.trim_end_matches('/');
+
+                name.strip_prefix(prefix)
+                    .and_then(|rest| rest.strip_prefix('/'))
+                    .unwrap_or(name)
+            }
+            None => name,
+        };
+
+        match &self.root {
+            Some(root) => format!("{}/{name}", root.trim_end_matches('/')),
+            None => name.to_string(),
+        }
+    }
+}
+
+/// Read the file descriptor set stored at the `path`.
+pub fn read(path: &path::Path) -> Result<prost_types::FileDescriptorSet, Error> {
+    let contents = fs::read(path).map_err(|e| Error::Read(e, path.to_path_buf()))?;
+
+    prost_types::FileDescriptorSet::decode(contents.as_slice())
+        .map_err(|e| Error::Decode(e, path.to_path_buf()))
+}
+
+/// Write the `fds` into the file at the `path`.
+pub fn write(path: &path::Path, fds: &prost_types::FileDescriptorSet) -> Result<(), Error> {
+    fs::write(path, fds.encode_to_vec()).map_err(|e| Error::Write(e, path.to_path_buf()))
+}
+
+/// The `--descriptor_set_in` argument of protoc loading the file descriptor `sets`, so the
+/// compiled protobuf files can import the files in them.
+pub fn set_in_arg(sets: &[path::PathBuf]) -> Result<ffi::OsString, Error> {
+    let paths = sets
+        .iter()
+        .map(|set| path::absolute(set).map_err(|e| Error::Resolve(e, set.clone())))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut arg = ffi::OsString::from("--descriptor_set_in=");
+    arg.push(env::join_paths(paths).map_err(Error::JoinPaths)?);
+
+    Ok(arg)
+}
+
+/// The file without its source code info, which only some of the compilers keep, so the same
+/// protobuf file compiled in different places compares equal.
+fn without_source_info(
+    file: &prost_types::FileDescriptorProto,
+) -> prost_types::FileDescriptorProto {
+    prost_types::FileDescriptorProto {
+        source_code_info: None,
+        ..file.clone()
+    }
+}
+
+fn push_types<'a>(
+    types: &mut Vec<(String, &'a str)>,
+    file: &'a str,
+    scope: &str,
+    messages: &[prost_types::DescriptorProto],
+    enums: &[prost_types::EnumDescriptorProto],
+) {
+    enums.iter().for_each(|enumeration| {
+        types.push((format!("{scope}.{}", enumeration.name()), file));
+    });
+    messages.iter().for_each(|message| {
+        let name = format!("{scope}.{}", message.name());
+
+        push_types(types, file, &name, &message.nested_type, &message.enum_type);
+        types.push((name, file));
+    });
+}
+
+/// Merge the protobuf files of the file descriptor `sets`, read from their paths, into the
+/// compiled `fds`, so the types of every package are generated into a single module, no matter
+/// which of the sets contributed them. A file found in several sets is only kept once, as long as
+/// it is the same file. Fails on the types defined in more than one file, and on the imports
+/// missing from all of the sets.
+pub fn merge(
+    mut fds: prost_types::FileDescriptorSet,
+    sets: &[(path::PathBuf, prost_types::FileDescriptorSet)],
+) -> Result<prost_types::FileDescriptorSet, Error> {
+    for (path, set) in sets {
+        for file in &set.file {
+            match fds.file.iter().find(|merged| merged.name() == file.name()) {
+                Some(merged) if without_source_info(merged) == without_source_info(file) => {}
+                Some(_) => {
+                    return Err(Error::ConflictingFile(
+                        file.name().to_string(),
+                        path.clone(),
+                    ));
+                }
+                None => fds.file.push(file.clone()),
+            }
+        }
+    }
+
+    let mut types = vec![];
+    fds.file.iter().for_each(|file| {
+        let scope = match file.package() {
+            "" => String::new(),
+            package => format!(".{package}"),
+        };
+
+        push_types(
+            &mut types,
+            file.name(),
+            &scope,
+            &file.message_type,
+            &file.enum_type,
+        );
+        file.service.iter().for_each(|service| {
+            types.push((format!("{scope}.{}", service.name()), file.name()));
+        });
+    });
+
+    let mut defined = collections::BTreeMap::new();
+    for (name, file) in types {
+        if let Some(first) = defined.insert(name.clone(), file) {
+            return Err(Error::DuplicateType(
+                name.trim_start_matches('.').to_string(),
+                first.to_string(),
+                file.to_string(),
+            ));
+        }
+    }
+
+    let files = fds
+        .file
+        .iter()
+        .map(prost_types::FileDescriptorProto::name)
+        .collect::<collections::BTreeSet<_>>();
+    fds.file
+        .iter()
+        .flat_map(|file| file.dependency.iter().map(move |import| (file, import)))
+        .find(|(_, import)| !files.contains(import.as_str()))
+        .map_or(Ok(()), |(file, import)| {
+            Err(Error::MissingImport(
+                file.name().to_string(),
+                import.clone(),
+            ))
+        })?;
+
+    Ok(fds)
+}
+
+/// Rewrite the names of the files and their dependencies in the file descriptor set stored at the
+/// `path` according to the `remap` rules.
+pub fn remap(path: &path::Path, remap: &Remap) -> Result<(), Error> {
+    let mut fds = read(path)?;
+
+    fds.file.iter_mut().for_each(|file| {
+        if let Some(name) = &mut file.name {
+            *name = remap.apply(name);
+        }
+
+        file.dependency
+            .iter_mut()
+            .for_each(|dependency| *dependency = remap.apply(dependency));
+    });
+
+    write(path, &fds)
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+    use std::{fs, path};
+
+    #[test]
+    fn remap_strips_prefix_and_applies_root() {
+        let remap = super::Remap {
+            strip_prefix: Some("crabs/".to_string()),
+            root: Some("schemas/v1".to_string()),
+        };
+
+        assert_eq!(
+            remap.apply("crabs/Ferris.proto"),
+            "schemas/v1/Ferris.proto",
+            "Expected the prefix to be replaced with the virtual root",
+        );
+        assert_eq!(
+            remap.apply("crabsy/Ferris.proto"),
+            "schemas/v1/crabsy/Ferris.proto",
+            "Expected only whole directory prefixes to be stripped",
+        );
+    }
+
+    #[test]
+    fn remap_rewrites_file_names_and_dependencies() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let path = dir.path().join("fds.bin");
+
+        let fds = prost_types::FileDescriptorSet {
+            file: vec![
+                prost_types::FileDescriptorProto {
+                    name: Some("crabs/Ferris.proto".to_string()),
+                    ..Default::default()
+                },
+                prost_types::FileDescriptorProto {
+                    name: Some("crabs/CrabService.proto".to_string()),
+                    dependency: vec!["crabs/Ferris.proto".to_string()],
+                    ..Default::default()
+                },
+            ],
+        };
+        fs::write(&path, fds.encode_to_vec()).expect("Failed to write a test descriptor set");
+
+        super::remap(
+            &path,
+            &super::Remap {
+                strip_prefix: Some("crabs".to_string()),
+                root: None,
+            },
+        )
+        .expect("Failed to remap the descriptor set");
+
+        let contents = fs::read(&path).expect("Failed to read the remapped descriptor set");
+        let fds = prost_types::FileDescriptorSet::decode(contents.as_slice())
+            .expect("Failed to decode the remapped descriptor set");
+
+        assert_eq!(
+            fds.file[0].name.as_deref(),
+            Some("Ferris.proto"),
+            "Invalid remapped file name",
+        );
+        assert_eq!(
+            fds.file[1].dependency,
+            vec!["Ferris.proto".to_string()],
+            "Invalid remapped dependency names",
+        );
+    }
+
+    #[test]
+    fn merge_combines_the_packages_of_the_sets() {
+        let file =
+            |name: &str, message: &str, dependency: &[&str]| prost_types::FileDescriptorProto {
+                name: Some(name.to_string()),
+                package: Some("crabs".to_string()),
+                dependency: dependency.iter().map(|name| name.to_string()).collect(),
+                message_type: vec![prost_types::DescriptorProto {
+                    name: Some(message.to_string()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            };
+        let local = prost_types::FileDescriptorSet {
+            file: vec![file("crabs/ferris.proto", "Ferris", &[])],
+        };
+        let vendored = |files| {
+            (
+                path::PathBuf::from("vendor.bin"),
+                prost_types::FileDescriptorSet { file: files },
+            )
+        };
+
+        let fds = super::merge(
+            local.clone(),
+            &[vendored(vec![
+                file("crabs/ferris.proto", "Ferris", &[]),
+                file("crabs/claw.proto", "Claw", &["crabs/ferris.proto"]),
+            ])],
+        )
+        .expect("Failed to merge the descriptor sets");
+        assert_eq!(
+            fds.file.iter().map(|file| file.name()).collect::<Vec<_>>(),
+            ["crabs/ferris.proto", "crabs/claw.proto"],
+            "Invalid merged files",
+        );
+
+        let err = super::merge(
+            local.clone(),
+            &[vendored(vec![file("crabs/ferris.proto", "Crab", &[])])],
+        );
+        assert!(
+            matches!(err, Err(super::Error::ConflictingFile(..))),
+            "Expected `Err(Error::ConflictingFile)`, got: `{:?}`",
+            err
+        );
+
+        let err = super::merge(
+            local.clone(),
+            &[vendored(vec![file("vendor/ferris.proto", "Ferris", &[])])],
+        );
+        assert!(
+            matches!(&err, Err(super::Error::DuplicateType(name, ..)) if name == "crabs.Ferris"),
+            "Expected `Err(Error::DuplicateType)`, got: `{:?}`",
+            err
+        );
+
+        let err = super::merge(
+            local,
+            &[vendored(vec![file(
+                "crabs/claw.proto",
+                "Claw",
+                &["crabs/shell.proto"],
+            )])],
+        );
+        assert!(
+            matches!(err, Err(super::Error::MissingImport(..))),
+            "Expected `Err(Error::MissingImport)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn remap_fails_on_invalid_descriptor_set() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let path = dir.path().join("fds.bin");
+        fs::write(&path, b"\xff\xff\xff").expect("Failed to write a test descriptor set");
+
+        let err = super::remap(&path, &super::Remap::default());
+
+        assert!(
+            matches!(err, Err(super::Error::Decode { .. })),
+            "Expected `Err(Error::Decode)`, got: `{:?}`",
+            err
+        );
+    }
+}
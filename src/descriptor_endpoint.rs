@@ -0,0 +1,165 @@
+use std::{fs, io, path};
+
+use crate::modgen;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to write the descriptor endpoint `{1}`: {0}")]
+    Write(io::Error, path::PathBuf),
+}
+
+/// The file the descriptor endpoint is written into, next to the root module of the output.
+pub const FILE: &str = "descriptor_endpoint.rs";
+
+/// The development-only module serving the file descriptor set over HTTP. It only depends on the
+/// standard library, so it adds nothing to the dependencies of the generated code.
+const CODE: &str = r#"// This file is @generated by pbuildrs.
+
+/// A development-only HTTP endpoint serving the file descriptor set written by pbuildrs, so tools
+/// like grpcui can load the current schema and reload it whenever `pbuildrs --watch` regenerates
+/// it. Only compiled into the debug builds.
+#[cfg(debug_assertions)]
+pub mod descriptor_endpoint {
+    use std::{
+        fs,
+        io::{self, BufRead as _, Write as _},
+        net, path, thread, time,
+    };
+
+    /// How often the file descriptor set is checked for changes.
+    const POLL_INTERVAL: time::Duration = time::Duration::from_millis(500);
+
+    /// Serve the file descriptor set at the `path` on the `addr` from a background thread and
+    /// return the bound address, e.g. when binding the port `0`:
+    ///
+    /// - `GET /descriptor.bin` responds with the current contents of the file,
+    /// - `GET /events` streams a server-sent `reload` event every time the file changes.
+    pub fn serve(
+        addr: impl net::ToSocketAddrs,
+        path: impl Into<path::PathBuf>,
+    ) -> io::Result<net::SocketAddr> {
+        let listener = net::TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let path = path.into();
+
+        thread::spawn(move || {
+            listener.incoming().flatten().for_each(|stream| {
+                let path = path.clone();
+
+                thread::spawn(move || {
+                    // The client went away, there is no one left to report the failure to.
+                    let _ = respond(&stream, &path);
+                });
+            });
+        });
+
+        Ok(local_addr)
+    }
+
+    /// The modification time and the size of the file, which change whenever it is rewritten.
+    fn version(path: &path::Path) -> Option<(Option<time::SystemTime>, u64)> {
+        fs::metadata(path)
+            .ok()
+            .map(|metadata| (metadata.modified().ok(), metadata.len()))
+    }
+
+    fn respond(mut stream: &net::TcpStream, path: &path::Path) -> io::Result<()> {
+        let mut request = io::BufReader::new(stream);
+        let mut request_line = String::new();
+        request.read_line(&mut request_line)?;
+
+        // The headers are read but ignored, none of them change the response.
+        let mut header = String::new();
+        while request.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        match request_line.split_whitespace().nth(1) {
+            Some("/descriptor.bin") => match fs::read(path) {
+                Ok(contents) => {
+                    write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\n\
+                        Content-Length: {}\r\nConnection: close\r\n\r\n",
+                        contents.len(),
+                    )?;
+                    stream.write_all(&contents)
+                }
+                Err(_) => not_found(stream),
+            },
+            Some("/events") => {
+                stream.write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+                    Cache-Control: no-cache\r\n\r\n",
+                )?;
+
+                let mut last = version(path);
+                loop {
+                    thread::sleep(POLL_INTERVAL);
+
+                    let current = version(path);
+                    if current == last {
+                        // A comment, so a closed connection is noticed and its thread ends.
+                        stream.write_all(b":\n\n")?;
+                    } else {
+                        last = current;
+                        stream.write_all(b"event: reload\ndata: /descriptor.bin\n\n")?;
+                    }
+                }
+            }
+            _ => not_found(stream),
+        }
+    }
+
+    fn not_found(mut stream: &net::TcpStream) -> io::Result<()> {
+        stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+    }
+}
+"#;
+
+/// Write the descriptor endpoint to the [`FILE`] in the `dst` directory, and include it into the
+/// root module of the `dst` laid out with the `layout`.
+pub fn write(dst: &path::Path, layout: modgen::Layout) -> Result<path::PathBuf, Error> {
+    let path = dst.join(FILE);
+    tracing::debug!("Writing the descriptor endpoint: {}", path.display());
+    fs::write(&path, CODE).map_err(|e| Error::Write(e, path.clone()))?;
+
+    let root = dst.join(layout.root_file());
+    fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&root)
+        .and_then(|mut out| {
+            io::Write::write_all(&mut out, format!("\ninclude!(\"{FILE}\");\n").as_bytes())
+        })
+        .map_err(|e| Error::Write(e, root))?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    #[test]
+    fn write_includes_the_endpoint_into_the_root_module() {
+        let dst = tempfile::TempDir::new().expect("Failed to create a test directory");
+        fs::write(dst.path().join("mod.rs"), "pub mod crabs;\n")
+            .expect("Failed to write a test file");
+
+        let path = super::write(dst.path(), crate::modgen::Layout::Tree)
+            .expect("Failed to write the descriptor endpoint");
+
+        assert_eq!(path, dst.path().join(super::FILE));
+        assert!(
+            fs::read_to_string(&path)
+                .expect("Failed to read the descriptor endpoint")
+                .contains("#[cfg(debug_assertions)]\npub mod descriptor_endpoint {"),
+            "Expected the endpoint to be limited to the debug builds",
+        );
+        assert_eq!(
+            fs::read_to_string(dst.path().join("mod.rs")).expect("Failed to read the root module"),
+            "pub mod crabs;\n\ninclude!(\"descriptor_endpoint.rs\");\n",
+        );
+    }
+}
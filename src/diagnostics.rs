@@ -0,0 +1,73 @@
+use std::{collections, fmt, path, sync};
+
+/// Collects the failures reported by parallel workers, deduplicating them by cause, so a problem
+/// shared by many files (e.g. a missing include) is reported once together with every affected
+/// file, in a stable order regardless of how the work was scheduled.
+#[derive(Default, Debug)]
+pub struct Diagnostics {
+    causes: sync::Mutex<collections::BTreeMap<String, collections::BTreeSet<path::PathBuf>>>,
+}
+
+impl Diagnostics {
+    pub fn push(&self, cause: impl fmt::Display, file: impl Into<path::PathBuf>) {
+        self.causes
+            .lock()
+            .unwrap_or_else(sync::PoisonError::into_inner)
+            .entry(cause.to_string())
+            .or_default()
+            .insert(file.into());
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let causes = self
+            .causes
+            .lock()
+            .unwrap_or_else(sync::PoisonError::into_inner);
+
+        causes
+            .iter()
+            .enumerate()
+            .try_for_each(|(idx, (cause, files))| {
+                if idx > 0 {
+                    writeln!(f)?;
+                }
+
+                write!(f, "{cause}")?;
+                files
+                    .iter()
+                    .try_for_each(|file| write!(f, "\n  {}", file.display()))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rayon::prelude::*;
+    use std::path;
+
+    #[test]
+    fn display_groups_files_by_cause() {
+        let diagnostics = super::Diagnostics::default();
+
+        ["c.proto", "a.proto", "b.proto", "a.proto"]
+            .par_iter()
+            .for_each(|file| diagnostics.push("Import `missing.proto` not found", *file));
+        diagnostics.push(
+            "Permission denied (os error 13)",
+            path::PathBuf::from("locked.proto"),
+        );
+
+        assert_eq!(
+            diagnostics.to_string(),
+            "Import `missing.proto` not found
+  a.proto
+  b.proto
+  c.proto
+Permission denied (os error 13)
+  locked.proto",
+            "Invalid diagnostics report",
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::{collections, fs, io, path};
+
+use crate::services;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("The message `{0}` reporting its encoded size is not a message")]
+    UnknownMessage(String),
+    #[error("Failed to append the encoded size reports to `{1}`: {0}")]
+    Write(io::Error, path::PathBuf),
+}
+
+/// Ensure every message reporting its size, given by its fully qualified name, is defined in the
+/// `fds`.
+pub fn validate(fds: &prost_types::FileDescriptorSet, reports: &[String]) -> Result<(), Error> {
+    let names = fds
+        .file
+        .iter()
+        .flat_map(services::messages)
+        .map(|message| message.full_name)
+        .collect::<collections::BTreeSet<_>>();
+
+    reports
+        .iter()
+        .find(|message| !names.contains(message.trim_start_matches('.')))
+        .map_or(Ok(()), |message| {
+            Err(Error::UnknownMessage(message.clone()))
+        })
+}
+
+/// The fields of the Rust struct of the `message`, by the protobuf name of the field, or of the
+/// oneof the field belongs to, and the Rust name of the struct field.
+fn fields(message: &prost_types::DescriptorProto) -> Vec<(&str, String)> {
+    let mut oneofs = collections::BTreeSet::new();
+
+    message
+        .field
+        .iter()
+        .filter_map(|field| match field.oneof_index {
+            Some(index) if !field.proto3_optional() => {
+                let oneof = usize::try_from(index)
+                    .ok()
+                    .and_then(|index| message.oneof_decl.get(index))
+                    .map_or("", |oneof| oneof.name());
+
+                oneofs
+                    .insert(index)
+                    .then(|| (oneof, services::to_snake(oneof)))
+            }
+            _ => Some((field.name(), services::to_snake(field.name()))),
+        })
+        .collect()
+}
+
+/// The helpers reporting the encoded size of the `message` and of every one of its fields, each
+/// measured as the size of a copy of the message with only that field set.
+fn render(message: &services::Message<'_>) -> String {
+    let fields = fields(message.descriptor);
+    let rest = match fields.len() {
+        1 => "",
+        _ => "\n                    ..::core::default::Default::default()",
+    };
+    let sizes = fields
+        .iter()
+        .map(|(name, ident)| {
+            format!(
+                r#"
+            (
+                {name:?},
+                ::prost::Message::encoded_len(&Self {{
+                    {ident}: ::core::clone::Clone::clone(&self.{ident}),{rest}
+                }}),
+            ),"#
+            )
+        })
+        .collect::<String>();
+
+    format!(
+        r#"
+impl {path} {{
+    /// The number of bytes every field of the message takes up when encoded, including its tag,
+    /// by the name of the field, in the order the fields are declared in. The fields of a oneof
+    /// are reported under the name of the oneof.
+    pub fn field_sizes(&self) -> [(&'static str, usize); {count}] {{
+        [{sizes}
+        ]
+    }}
+
+    /// A summary of the encoded size of the message, followed by the sizes of its fields that are
+    /// set, largest first, e.g. `{full_name}: 120 bytes (name: 118, kind: 2)`.
+    pub fn size_summary(&self) -> ::std::string::String {{
+        let mut sizes = self.field_sizes();
+        sizes.sort_by_key(|(_, size)| ::core::cmp::Reverse(*size));
+        let fields = sizes
+            .iter()
+            .filter(|(_, size)| *size > 0)
+            .map(|(name, size)| ::std::format!("{{name}}: {{size}}"))
+            .collect::<::std::vec::Vec<_>>()
+            .join(", ");
+
+        ::std::format!(
+            "{full_name}: {{}} bytes ({{fields}})",
+            ::prost::Message::encoded_len(self),
+        )
+    }}
+}}
+"#,
+        path = message.path,
+        full_name = message.full_name,
+        count = fields.len(),
+    )
+}
+
+/// Append the size reporting helpers of the `reports`, the messages given by their fully
+/// qualified name, to the code generated by prost into the `dir`, next to the package of the
+/// message.
+pub fn write(
+    fds: &prost_types::FileDescriptorSet,
+    dir: &path::Path,
+    reports: &[String],
+) -> Result<(), Error> {
+    let reports = reports
+        .iter()
+        .map(|message| message.trim_start_matches('.'))
+        .collect::<collections::BTreeSet<_>>();
+
+    fds.file.iter().try_for_each(|file| {
+        let code = services::messages(file)
+            .iter()
+            .filter(|message| reports.contains(message.full_name.as_str()))
+            .map(render)
+            .collect::<String>();
+        if code.is_empty() {
+            return Ok(());
+        }
+
+        let path = dir.join(match file.package() {
+            "" => "_.rs".to_string(),
+            package => format!("{package}.rs"),
+        });
+        tracing::debug!("Appending the encoded size reports: {}", path.display());
+
+        fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .and_then(|mut out| io::Write::write_all(&mut out, code.as_bytes()))
+            .map_err(|e| Error::Write(e, path))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    fn fds() -> prost_types::FileDescriptorSet {
+        let field = |name: &str, oneof_index: Option<i32>| prost_types::FieldDescriptorProto {
+            name: Some(name.to_string()),
+            oneof_index,
+            ..Default::default()
+        };
+
+        prost_types::FileDescriptorSet {
+            file: vec![prost_types::FileDescriptorProto {
+                package: Some("crabs".to_string()),
+                message_type: vec![prost_types::DescriptorProto {
+                    name: Some("Ferris".to_string()),
+                    field: vec![
+                        field("type", None),
+                        field("email", Some(0)),
+                        field("phone", Some(0)),
+                    ],
+                    oneof_decl: vec![prost_types::OneofDescriptorProto {
+                        name: Some("contact".to_string()),
+                        ..Default::default()
+                    }],
+                    nested_type: vec![prost_types::DescriptorProto {
+                        name: Some("Claw".to_string()),
+                        field: vec![field("pin", None)],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn validate_rejects_unknown_messages() {
+        super::validate(
+            &fds(),
+            &["crabs.Ferris".to_string(), ".crabs.Ferris.Claw".to_string()],
+        )
+        .expect("Expected the messages to be valid");
+
+        let err = super::validate(&fds(), &["crabs.Claw".to_string()]);
+        assert!(
+            matches!(&err, Err(super::Error::UnknownMessage(message)) if message == "crabs.Claw"),
+            "Expected `Err(Error::UnknownMessage)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn write_reports_the_sizes_of_the_fields() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+
+        super::write(
+            &fds(),
+            dir.path(),
+            &["crabs.Ferris".to_string(), "crabs.Ferris.Claw".to_string()],
+        )
+        .expect("Failed to write the encoded size reports");
+
+        let code = fs::read_to_string(dir.path().join("crabs.rs"))
+            .expect("Failed to read the generated code");
+        [
+            "impl Ferris {",
+            "    pub fn field_sizes(&self) -> [(&'static str, usize); 2] {",
+            "                \"type\",\n",
+            "                    r#type: ::core::clone::Clone::clone(&self.r#type),\n",
+            "                \"contact\",\n",
+            "                    contact: ::core::clone::Clone::clone(&self.contact),\n",
+            "                    ..::core::default::Default::default()\n",
+            "            \"crabs.Ferris: {} bytes ({fields})\",\n",
+            "impl ferris::Claw {",
+            "                    pin: ::core::clone::Clone::clone(&self.pin),\n                }),",
+        ]
+        .into_iter()
+        .for_each(|expected| {
+            assert!(
+                code.contains(expected),
+                "Expected `{expected}` in the encoded size report, got:\n{code}",
+            );
+        });
+        assert!(
+            !code.contains("\"email\""),
+            "Expected the oneof fields to be reported together, got:\n{code}",
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use std::{collections, fs, io, path, str};
+
+use crate::services;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Invalid event `{0}`, expected `<MESSAGE>=<TOPIC>`")]
+    InvalidEvent(String),
+    #[error("The event `{0}` is not a message")]
+    UnknownMessage(String),
+    #[error("Failed to append the event envelopes to `{1}`: {0}")]
+    Write(io::Error, path::PathBuf),
+}
+
+/// A message published as an event to the `topic`, a Kafka topic or a NATS subject, given by its
+/// fully qualified name, e.g. `crabs.FerrisMoved`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Event {
+    pub message: String,
+    pub topic: String,
+}
+
+impl str::FromStr for Event {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (message, topic) = s
+            .split_once('=')
+            .ok_or_else(|| Error::InvalidEvent(s.to_string()))?;
+        let (message, topic) = (message.trim().trim_start_matches('.'), topic.trim());
+        if message.is_empty() || topic.is_empty() {
+            return Err(Error::InvalidEvent(s.to_string()));
+        }
+
+        Ok(Self {
+            message: message.to_string(),
+            topic: topic.to_string(),
+        })
+    }
+}
+
+/// Ensure every event is a message defined in the `fds`.
+pub fn validate(
+    fds: &prost_types::FileDescriptorSet,
+    events: &collections::BTreeMap<String, String>,
+) -> Result<(), Error> {
+    let names = fds
+        .file
+        .iter()
+        .flat_map(services::messages)
+        .map(|message| message.full_name)
+        .collect::<collections::BTreeSet<_>>();
+
+    events
+        .keys()
+        .find(|message| !names.contains(*message))
+        .map_or(Ok(()), |message| {
+            Err(Error::UnknownMessage(message.clone()))
+        })
+}
+
+/// The message indexes framed by the Confluent serializers, zigzag varints counting and listing
+/// the indexes, shortened to a single zero for the first message of the file.
+fn message_indexes(indexes: &[usize]) -> Vec<u8> {
+    if indexes == [0] {
+        return vec![0];
+    }
+
+    let mut bytes = vec![];
+    std::iter::once(indexes.len())
+        .chain(indexes.iter().copied())
+        .for_each(|value| {
+            let mut zigzag = value << 1;
+            while zigzag >= 0x80 {
+                bytes.push(u8::try_from(zigzag & 0x7f).expect("Masked to seven bits") | 0x80);
+                zigzag >>= 7;
+            }
+            bytes.push(u8::try_from(zigzag).expect("Shifted below eight bits"));
+        });
+
+    bytes
+}
+
+/// The envelope helpers of the `message` published to the `topic`, framing it in the wire format
+/// of the Confluent schema registry, also used for the NATS payloads.
+fn render(message: &services::Message, topic: &str) -> String {
+    let path = format!("super::{}", message.path);
+    let module = message
+        .names
+        .iter()
+        .map(|name| services::to_snake(name))
+        .collect::<Vec<_>>()
+        .join("_");
+    let indexes = message_indexes(&message.indexes)
+        .iter()
+        .map(u8::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    let full_name = &message.full_name;
+
+    format!(
+        r#"
+/// The envelope of the `{full_name}` event, framed in the wire format of the Confluent schema
+/// registry: a zero magic byte, the big-endian id of the schema, the indexes of the message in its
+/// protobuf file and the encoded message.
+pub mod {module}_envelope {{
+    /// The Kafka topic, or the NATS subject, the event is published to.
+    pub const TOPIC: &str = {topic:?};
+
+    /// The fully qualified protobuf name of the event.
+    pub const MESSAGE_NAME: &str = "{full_name}";
+
+    /// The indexes of the message in its protobuf file, as framed on the wire.
+    const MESSAGE_INDEXES: &[u8] = &[{indexes}];
+
+    /// Encode the `event` framed with the `schema_id` of its protobuf file in the registry.
+    pub fn encode(event: &{path}, schema_id: u32) -> Vec<u8> {{
+        let mut payload = vec![0];
+        payload.extend_from_slice(&schema_id.to_be_bytes());
+        payload.extend_from_slice(MESSAGE_INDEXES);
+        payload.extend(prost::Message::encode_to_vec(event));
+
+        payload
+    }}
+
+    /// Decode an event framed by [`encode`], along with the id of the schema it was encoded with.
+    pub fn decode(payload: &[u8]) -> std::io::Result<(u32, {path})> {{
+        let invalid = |reason: String| {{
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Invalid `{full_name}` envelope: {{reason}}"),
+            )
+        }};
+
+        let [0, a, b, c, d, rest @ ..] = payload else {{
+            return Err(invalid("expected a zero magic byte and a schema id".to_string()));
+        }};
+        let event = rest
+            .strip_prefix(MESSAGE_INDEXES)
+            .ok_or_else(|| invalid("framed for another message of the schema".to_string()))?;
+        let event =
+            <{path} as prost::Message>::decode(event).map_err(|e| invalid(e.to_string()))?;
+
+        Ok((u32::from_be_bytes([*a, *b, *c, *d]), event))
+    }}
+}}
+"#
+    )
+}
+
+/// Append the envelope helpers of the `events` defined in the `fds` to the code generated by prost
+/// into the `dir`, next to the package of the message.
+pub fn write(
+    fds: &prost_types::FileDescriptorSet,
+    dir: &path::Path,
+    events: &collections::BTreeMap<String, String>,
+) -> Result<(), Error> {
+    fds.file.iter().try_for_each(|file| {
+        let code = services::messages(file)
+            .iter()
+            .filter_map(|message| {
+                events
+                    .get(&message.full_name)
+                    .map(|topic| render(message, topic))
+            })
+            .collect::<String>();
+        if code.is_empty() {
+            return Ok(());
+        }
+
+        let path = dir.join(match file.package() {
+            "" => "_.rs".to_string(),
+            package => format!("{package}.rs"),
+        });
+        tracing::debug!("Appending the event envelopes: {}", path.display());
+
+        fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .and_then(|mut out| io::Write::write_all(&mut out, code.as_bytes()))
+            .map_err(|e| Error::Write(e, path))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections, fs};
+
+    fn fds() -> prost_types::FileDescriptorSet {
+        let message = |name: &str, nested_type| prost_types::DescriptorProto {
+            name: Some(name.to_string()),
+            nested_type,
+            ..Default::default()
+        };
+
+        prost_types::FileDescriptorSet {
+            file: vec![prost_types::FileDescriptorProto {
+                package: Some("crabs".to_string()),
+                message_type: vec![
+                    message("Ferris", vec![]),
+                    message("Shell", vec![message("Cracked", vec![])]),
+                ],
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn event_parses_the_message_and_the_topic() {
+        assert_eq!(
+            ".crabs.FerrisMoved = crabs.ferris-moved"
+                .parse::<super::Event>()
+                .expect("Failed to parse an event"),
+            super::Event {
+                message: "crabs.FerrisMoved".to_string(),
+                topic: "crabs.ferris-moved".to_string(),
+            },
+            "Invalid parsed event",
+        );
+
+        [
+            "crabs.FerrisMoved",
+            "crabs.FerrisMoved=",
+            "=crabs.ferris-moved",
+        ]
+        .into_iter()
+        .for_each(|event| {
+            let result = event.parse::<super::Event>();
+            assert!(
+                matches!(result, Err(super::Error::InvalidEvent(_))),
+                "Expected `Err(Error::InvalidEvent(_))` for `{event}`, got: `{result:?}`",
+            );
+        });
+    }
+
+    #[test]
+    fn validate_rejects_unknown_messages() {
+        let events = collections::BTreeMap::from([
+            ("crabs.Ferris".to_string(), "ferris".to_string()),
+            ("crabs.Shell.Cracked".to_string(), "shells".to_string()),
+        ]);
+        super::validate(&fds(), &events).expect("Expected the events to be valid");
+
+        let events = collections::BTreeMap::from([("crabs.Cracked".to_string(), "x".to_string())]);
+        let err = super::validate(&fds(), &events);
+        assert!(
+            matches!(&err, Err(super::Error::UnknownMessage(message)) if message == "crabs.Cracked"),
+            "Expected `Err(Error::UnknownMessage)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn message_indexes_match_the_confluent_framing() {
+        assert_eq!(super::message_indexes(&[0]), [0]);
+        assert_eq!(super::message_indexes(&[1]), [2, 2]);
+        assert_eq!(super::message_indexes(&[1, 0]), [4, 2, 0]);
+        assert_eq!(super::message_indexes(&[64]), [2, 128, 1]);
+    }
+
+    #[test]
+    fn write_appends_the_envelopes_to_the_package() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let events = collections::BTreeMap::from([(
+            "crabs.Shell.Cracked".to_string(),
+            "crabs.shell-cracked".to_string(),
+        )]);
+
+        super::write(&fds(), dir.path(), &events).expect("Failed to write the envelopes");
+
+        let code = fs::read_to_string(dir.path().join("crabs.rs"))
+            .expect("Failed to read the generated code");
+        assert!(
+            code.contains("pub mod shell_cracked_envelope {"),
+            "Expected an envelope module, got:\n{code}",
+        );
+        assert!(
+            code.contains("pub const TOPIC: &str = \"crabs.shell-cracked\";"),
+            "Expected the topic, got:\n{code}",
+        );
+        assert!(
+            code.contains("const MESSAGE_INDEXES: &[u8] = &[4, 2, 0];"),
+            "Expected the message indexes, got:\n{code}",
+        );
+        assert!(
+            code.contains("pub fn encode(event: &super::shell::Cracked, schema_id: u32)"),
+            "Expected the nested message path, got:\n{code}",
+        );
+        assert!(
+            !code.contains("ferris_envelope"),
+            "Expected no envelope for other messages, got:\n{code}",
+        );
+    }
+}
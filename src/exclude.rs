@@ -0,0 +1,211 @@
+use std::path;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Invalid exclusion pattern `{1}`: {0}")]
+    InvalidPattern(globset::Error, String),
+}
+
+/// The file listing, in the gitignore syntax, the paths to skip in the directory it is placed in.
+pub const IGNORE_FILE: &str = ".pbuildrsignore";
+
+/// Glob patterns of the paths skipped while looking for the protobuf files, matched against the
+/// path relative to the source directory, e.g. `**/internal/**`. A `*` does not cross directories,
+/// while `**` does.
+#[derive(Clone, Debug)]
+pub struct Exclusions {
+    set: globset::GlobSet,
+    gitignore: bool,
+    follow_symlinks: bool,
+}
+
+impl Default for Exclusions {
+    fn default() -> Self {
+        Self {
+            set: globset::GlobSet::empty(),
+            gitignore: false,
+            follow_symlinks: false,
+        }
+    }
+}
+
+impl Exclusions {
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Result<Self, Error> {
+        let set = patterns
+            .iter()
+            .try_fold(globset::GlobSetBuilder::new(), |mut set, pattern| {
+                let pattern = pattern.as_ref();
+                let glob = globset::GlobBuilder::new(pattern)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|e| Error::InvalidPattern(e, pattern.to_string()))?;
+                set.add(glob);
+
+                Ok(set)
+            })?
+            .build()
+            .map_err(|e| {
+                let patterns = patterns.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+
+                Error::InvalidPattern(e, patterns.join(", "))
+            })?;
+
+        Ok(Self {
+            set,
+            ..Default::default()
+        })
+    }
+
+    /// Also skip the paths ignored by the `.gitignore` files of the git repository.
+    pub fn gitignore(mut self, gitignore: bool) -> Self {
+        self.gitignore = gitignore;
+        self
+    }
+
+    /// Descend into the symlinked directories, skipping the ones pointing back at a directory
+    /// being walked.
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Whether the `path`, relative to the source directory, is excluded.
+    pub fn is_excluded(&self, path: &path::Path) -> bool {
+        self.set.is_match(path)
+    }
+
+    /// Walk the `root` directory, sorted by the file name, skipping the excluded paths and the
+    /// ones ignored by the [`IGNORE_FILE`]s, without descending into skipped directories.
+    pub(crate) fn walk(
+        &self,
+        root: &path::Path,
+    ) -> impl Iterator<Item = Result<ignore::DirEntry, ignore::Error>> {
+        let mut walk = ignore::WalkBuilder::new(root);
+        walk.standard_filters(false)
+            .add_custom_ignore_filename(IGNORE_FILE)
+            .parents(true)
+            .git_ignore(self.gitignore)
+            .git_exclude(self.gitignore)
+            .follow_links(self.follow_symlinks)
+            .sort_by_file_name(|a, b| a.cmp(b));
+
+        let set = self.set.clone();
+        let root = root.to_path_buf();
+        walk.filter_entry(move |entry| {
+            let is_excluded = entry
+                .path()
+                .strip_prefix(&root)
+                .is_ok_and(|path| set.is_match(path));
+            if is_excluded {
+                tracing::debug!("Excluded: {}", entry.path().display());
+            }
+
+            !is_excluded
+        });
+
+        walk.build().filter(|entry| match entry {
+            Err(e) if is_loop(e) => {
+                tracing::warn!("Skipping a symlink: {e}");
+                false
+            }
+            _ => true,
+        })
+    }
+}
+
+/// Whether the walk failed on a symlink pointing back at one of the directories being walked.
+fn is_loop(e: &ignore::Error) -> bool {
+    match e {
+        ignore::Error::Loop { .. } => true,
+        ignore::Error::WithPath { err, .. }
+        | ignore::Error::WithDepth { err, .. }
+        | ignore::Error::WithLineNumber { err, .. } => is_loop(err),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path};
+
+    #[test]
+    fn is_excluded_matches_relative_paths() {
+        let exclusions = super::Exclusions::new(&["**/internal/**", "fixtures", "*.test.proto"])
+            .expect("Failed to parse the exclusion patterns");
+
+        [
+            ("crabs/internal/ferris.proto", true),
+            ("internal/ferris.proto", true),
+            ("fixtures", true),
+            ("crabs/fixtures", false),
+            ("ferris.test.proto", true),
+            ("crabs/ferris.test.proto", false),
+            ("crabs/ferris.proto", false),
+        ]
+        .into_iter()
+        .for_each(|(file, expected)| {
+            assert_eq!(
+                exclusions.is_excluded(path::Path::new(file)),
+                expected,
+                "Invalid exclusion of `{file}`",
+            );
+        });
+    }
+
+    #[test]
+    fn new_rejects_invalid_patterns() {
+        let err = super::Exclusions::new(&["crabs/[ferris"]);
+
+        assert!(
+            matches!(&err, Err(super::Error::InvalidPattern(_, pattern)) if pattern == "crabs/[ferris"),
+            "Expected `Err(Error::InvalidPattern)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn walk_respects_the_gitignore_files_only_when_asked() {
+        let root = tempfile::tempdir().expect("Failed to create a test directory");
+        fs::create_dir(root.path().join(".git")).expect("Failed to create a test git directory");
+        [
+            (".gitignore", "*.tmp.proto\n"),
+            ("crabs/ferris.proto", ""),
+            ("crabs/ferris.tmp.proto", ""),
+        ]
+        .into_iter()
+        .for_each(|(file, content)| {
+            let path = root.path().join(file);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).expect("Failed to create a test subdirectory");
+            }
+
+            fs::write(path, content).expect("Failed to create a test file");
+        });
+
+        let protos = |exclusions: super::Exclusions| {
+            exclusions
+                .walk(&root.path().join("crabs"))
+                .map(|entry| {
+                    entry
+                        .expect("Failed to walk the test directory")
+                        .into_path()
+                })
+                .filter(|path| path.is_file())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            protos(super::Exclusions::default()),
+            vec![
+                root.path().join("crabs/ferris.proto"),
+                root.path().join("crabs/ferris.tmp.proto"),
+            ],
+            "Expected the `.gitignore` file to be skipped by default",
+        );
+        assert_eq!(
+            protos(super::Exclusions::default().gitignore(true)),
+            vec![root.path().join("crabs/ferris.proto")],
+            "Expected the `.gitignore` file to be respected",
+        );
+    }
+}
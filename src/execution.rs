@@ -0,0 +1,322 @@
+use prost::Message;
+use std::{env, ffi, fmt, fs, io, path, process};
+
+/// The name of the file descriptor set written by `protoc` into the input root.
+const DESCRIPTOR_SET: &str = "protoc-descriptor-set.bin";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("The protoc phase can not be executed remotely in the offline mode")]
+    Offline,
+    #[error("The protoc phase executed remotely can not be sandboxed, unset the sandbox")]
+    Sandboxed,
+    #[error("The remote executor command is empty")]
+    EmptyCommand,
+    #[error("The protobuf file `{0}` is in none of the include directories")]
+    Outside(path::PathBuf),
+    #[error("Failed to copy `{1}` into the input root: {0}")]
+    Copy(io::Error, path::PathBuf),
+    #[error("Failed to read the include directory `{1}`: {0}")]
+    ReadDir(walkdir::Error, path::PathBuf),
+    #[error("Failed to run the remote executor `{1}`: {0}")]
+    Spawn(io::Error, String),
+    #[error("The remote execution of protoc failed: {0}")]
+    Failed(String),
+    #[error("Failed to read the file descriptor set `{1}`: {0}")]
+    ReadDescriptorSet(io::Error, path::PathBuf),
+    #[error("Failed to decode the file descriptor set: {0}")]
+    DecodeDescriptorSet(prost::DecodeError),
+    #[error("Failed to write the file descriptor set `{1}`: {0}")]
+    WriteDescriptorSet(io::Error, path::PathBuf),
+}
+
+/// A `protoc` invocation packed into an input root holding every file it reads, with all the
+/// paths relative to it, so it can be executed on another machine from a copy of the root.
+#[derive(PartialEq, Debug)]
+pub struct Action {
+    /// The directory holding the inputs, the working directory of `protoc`.
+    pub input_root: path::PathBuf,
+    /// The files `protoc` reads, relative to the input root.
+    pub inputs: Vec<path::PathBuf>,
+    /// The arguments of `protoc`, without the program itself.
+    pub arguments: Vec<ffi::OsString>,
+    /// The file descriptor set `protoc` writes, relative to the input root.
+    pub output: path::PathBuf,
+}
+
+/// Where the `protoc` phase runs, e.g. a remote execution service, while the patching and the
+/// code generation stay local.
+pub trait Backend: fmt::Debug + Send + Sync {
+    /// Execute the `action`, returning the contents of the file descriptor set it writes.
+    fn execute(&self, action: &Action) -> Result<Vec<u8>, Error>;
+
+    /// The `remote-executor` command reproducing this backend in the configuration file, if any.
+    fn command(&self) -> Option<&[String]> {
+        None
+    }
+}
+
+/// A backend handing the actions to an executor command, e.g. the client of a REAPI service,
+/// which runs the command following it remotely. The command is run from the input root, followed
+/// by the arguments of `protoc`, with `{inputs}` in its arguments replaced with the comma-separated
+/// inputs and `{output}` with the file descriptor set to fetch back.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Remote {
+    command: Vec<String>,
+}
+
+impl Remote {
+    /// Create a backend running the `command`, e.g.
+    /// `["rewrapper", "--inputs={inputs}", "--output_files={output}", "--", "protoc"]`.
+    pub fn new(command: Vec<String>) -> Result<Self, Error> {
+        match command.is_empty() {
+            true => Err(Error::EmptyCommand),
+            false => Ok(Self { command }),
+        }
+    }
+}
+
+impl Backend for Remote {
+    fn execute(&self, action: &Action) -> Result<Vec<u8>, Error> {
+        let inputs = action
+            .inputs
+            .iter()
+            .map(|input| input.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(",");
+        let output = action.output.to_string_lossy();
+        let mut args = self.command.iter().map(|arg| {
+            ffi::OsString::from(
+                arg.replace("{inputs}", &inputs)
+                    .replace("{output}", &output),
+            )
+        });
+        let program = args.next().unwrap_or_default();
+
+        let mut command = process::Command::new(&program);
+        command
+            .current_dir(&action.input_root)
+            .args(args)
+            .args(&action.arguments);
+        tracing::debug!("Executing protoc remotely: {command:?}");
+
+        let result = command
+            .output()
+            .map_err(|e| Error::Spawn(e, program.to_string_lossy().into_owned()))?;
+        if !result.status.success() {
+            return Err(Error::Failed(
+                String::from_utf8_lossy(&result.stderr).into_owned(),
+            ));
+        }
+
+        let output = action.input_root.join(&action.output);
+        fs::read(&output).map_err(|e| Error::ReadDescriptorSet(e, output))
+    }
+
+    fn command(&self) -> Option<&[String]> {
+        Some(&self.command)
+    }
+}
+
+/// Ensure the actions can be executed remotely, neither `offline` nor in a local `sandbox`.
+pub fn validate(offline: bool, sandboxed: bool) -> Result<(), Error> {
+    if offline {
+        return Err(Error::Offline);
+    }
+    if sandboxed {
+        return Err(Error::Sandboxed);
+    }
+
+    Ok(())
+}
+
+/// Copy the protobuf files of the `include` directory, or the file itself, into the `dir`.
+fn copy_protos(include: &path::Path, dir: &path::Path) -> Result<Vec<path::PathBuf>, Error> {
+    walkdir::WalkDir::new(include)
+        .into_iter()
+        .map(|entry| entry.map_err(|e| Error::ReadDir(e, include.to_path_buf())))
+        .filter(|entry| {
+            entry.as_ref().map_or(true, |entry| {
+                entry.file_type().is_file()
+                    && entry.path().extension().is_some_and(|ext| ext == "proto")
+            })
+        })
+        .map(|entry| {
+            let entry = entry?;
+            let rest = entry.path().strip_prefix(include).unwrap_or(entry.path());
+            let target = dir.join(rest);
+
+            target
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|()| fs::copy(entry.path(), &target))
+                .map_err(|e| Error::Copy(e, entry.path().to_path_buf()))?;
+
+            Ok(target)
+        })
+        .collect()
+}
+
+/// Pack the compilation of the `protos` into an action, copying the protobuf files of the
+/// `includes`, along with the include directory of `protoc`, and the `descriptor_sets` into the
+/// `input_root`.
+pub fn prepare(
+    input_root: &path::Path,
+    protos: &[path::PathBuf],
+    includes: &[path::PathBuf],
+    descriptor_sets: &[path::PathBuf],
+) -> Result<Action, Error> {
+    let relative = |path: &path::Path| path.strip_prefix(input_root).unwrap_or(path).to_path_buf();
+    let copy = |e, path: &path::Path| Error::Copy(e, path.to_path_buf());
+
+    fs::create_dir_all(input_root).map_err(|e| copy(e, input_root))?;
+
+    let mut inputs = vec![];
+    let mut arguments = vec![
+        ffi::OsString::from("--include_imports"),
+        ffi::OsString::from("--include_source_info"),
+        ffi::OsString::from("-o"),
+        ffi::OsString::from(DESCRIPTOR_SET),
+    ];
+
+    if !descriptor_sets.is_empty() {
+        let sets = descriptor_sets
+            .iter()
+            .enumerate()
+            .map(|(idx, set)| {
+                let target = path::PathBuf::from("descriptor-sets").join(format!("{idx}.bin"));
+                fs::create_dir_all(input_root.join("descriptor-sets"))
+                    .and_then(|()| fs::copy(set, input_root.join(&target)))
+                    .map_err(|e| copy(e, set))?;
+                inputs.push(target.clone());
+
+                Ok(target.to_string_lossy().into_owned())
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        arguments.push(format!("--descriptor_set_in={}", sets.join(":")).into());
+    }
+
+    // Same as prost, the include directory of protoc goes after the user ones, so they can
+    // override the well-known types.
+    let protoc_include = env::var_os("PROTOC_INCLUDE").map(path::PathBuf::from);
+    let includes = includes
+        .iter()
+        .chain(&protoc_include)
+        .filter(|include| include.exists())
+        .collect::<Vec<_>>();
+    includes.iter().enumerate().try_for_each(|(idx, include)| {
+        let dir = path::PathBuf::from("includes").join(idx.to_string());
+        let copied = copy_protos(include, &input_root.join(&dir))?;
+        inputs.extend(copied.iter().map(|path| relative(path)));
+        arguments.push("-I".into());
+        arguments.push(dir.into_os_string());
+
+        Ok::<_, Error>(())
+    })?;
+
+    protos.iter().try_for_each(|proto| {
+        let (idx, rest) = includes
+            .iter()
+            .enumerate()
+            .find_map(|(idx, include)| Some((idx, proto.strip_prefix(include).ok()?)))
+            .ok_or_else(|| Error::Outside(proto.clone()))?;
+        arguments.push(
+            path::PathBuf::from("includes")
+                .join(idx.to_string())
+                .join(rest)
+                .into_os_string(),
+        );
+
+        Ok(())
+    })?;
+
+    Ok(Action {
+        input_root: input_root.to_path_buf(),
+        inputs,
+        arguments,
+        output: path::PathBuf::from(DESCRIPTOR_SET),
+    })
+}
+
+/// Compile the `protos` with the `backend` into a file descriptor set, copied to the
+/// `descriptor_set_path` if provided. The action is packed in the `input_root`.
+pub fn load_fds(
+    backend: &dyn Backend,
+    input_root: &path::Path,
+    protos: &[path::PathBuf],
+    includes: &[path::PathBuf],
+    descriptor_sets: &[path::PathBuf],
+    descriptor_set_path: Option<&path::Path>,
+) -> Result<tonic_prost_build::FileDescriptorSet, Error> {
+    let action = prepare(input_root, protos, includes, descriptor_sets)?;
+    let contents = backend.execute(&action)?;
+
+    if let Some(path) = descriptor_set_path {
+        fs::write(path, &contents).map_err(|e| Error::WriteDescriptorSet(e, path.to_path_buf()))?;
+    }
+
+    tonic_prost_build::FileDescriptorSet::decode(contents.as_slice())
+        .map_err(Error::DecodeDescriptorSet)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi, fs, path};
+
+    #[test]
+    fn prepare_makes_the_paths_relative_to_the_input_root() {
+        let src = tempfile::TempDir::new().expect("Failed to create a test source directory");
+        let root = tempfile::TempDir::new().expect("Failed to create a test input root");
+        fs::create_dir_all(src.path().join("crabs")).expect("Failed to create a test package");
+        let proto = src.path().join("crabs/ferris.proto");
+        fs::write(&proto, "syntax = \"proto3\";\n").expect("Failed to create a test file");
+        fs::write(src.path().join("README.md"), "Crabs\n").expect("Failed to create a test file");
+
+        let action = super::prepare(
+            root.path(),
+            std::slice::from_ref(&proto),
+            &[src.path().to_path_buf()],
+            &[],
+        )
+        .expect("Failed to prepare the action");
+
+        // The include directory of protoc, if any, follows the user ones.
+        assert_eq!(
+            action
+                .inputs
+                .iter()
+                .filter(|input| input.starts_with("includes/0"))
+                .collect::<Vec<_>>(),
+            [&path::PathBuf::from("includes/0/crabs/ferris.proto")],
+            "Expected only the protobuf files to be copied",
+        );
+        assert!(
+            action
+                .arguments
+                .windows(2)
+                .any(|args| args == [ffi::OsString::from("-I"), ffi::OsString::from("includes/0")]),
+            "Expected the include directory relative to the input root",
+        );
+        assert_eq!(
+            action.arguments.last(),
+            Some(&ffi::OsString::from("includes/0/crabs/ferris.proto")),
+            "Expected the protobuf file relative to the input root",
+        );
+        assert!(
+            root.path().join("includes/0/crabs/ferris.proto").is_file(),
+            "Expected the protobuf file in the input root",
+        );
+
+        let err = super::prepare(
+            root.path(),
+            &[path::PathBuf::from("/elsewhere/crabs.proto")],
+            &[src.path().to_path_buf()],
+            &[],
+        );
+        assert!(
+            matches!(&err, Err(super::Error::Outside(_))),
+            "Expected `Err(Error::Outside)`, got: `{:?}`",
+            err
+        );
+    }
+}
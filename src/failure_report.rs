@@ -0,0 +1,159 @@
+use std::{error, fs, io, path};
+
+/// The name of the failure report directory used when no other path is configured.
+pub const DEFAULT_PATH: &str = "failure-report";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to write the failure report `{1}`: {0}")]
+    Write(io::Error, path::PathBuf),
+    #[error("Failed to copy the patched file `{1}` into the failure report: {0}")]
+    Copy(io::Error, path::PathBuf),
+    #[error("Failed to serialize the error: {0}")]
+    Serialize(serde_json::Error),
+}
+
+/// The error failing a run, as written into the `error.json` file of the report.
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Failure {
+    /// The message of the error.
+    pub error: String,
+    /// The messages of the errors causing it, outermost first.
+    pub causes: Vec<String>,
+}
+
+impl Failure {
+    pub fn new(error: &dyn error::Error) -> Self {
+        Self {
+            error: error.to_string(),
+            causes: std::iter::successors(error.source(), |e| e.source())
+                .map(ToString::to_string)
+                .collect(),
+        }
+    }
+
+    /// Whether any of the messages mentions the `text`, e.g. the path of a protobuf file.
+    pub fn mentions(&self, text: &str) -> bool {
+        std::iter::once(&self.error)
+            .chain(&self.causes)
+            .any(|message| message.contains(text))
+    }
+}
+
+/// Everything needed to reproduce a failed run.
+#[derive(Debug)]
+pub struct Report<'a> {
+    /// The snapshot of the effective configuration.
+    pub config: &'a str,
+    pub failure: Failure,
+    /// The output of `protoc`, if it failed.
+    pub protoc_stderr: Option<&'a str>,
+    /// The patched protobuf files involved in the failure, by their path within the sources, and
+    /// their patched copy.
+    pub protos: Vec<(path::PathBuf, path::PathBuf)>,
+}
+
+/// Write the `report` into the `dir`: the `config.toml`, the `error.json`, the
+/// `protoc-stderr.txt` and the patched files in `patched/`, replacing the ones of a previous
+/// report.
+pub fn write(dir: &path::Path, report: &Report) -> Result<(), Error> {
+    let write = |name: &str, contents: &[u8]| {
+        let path = dir.join(name);
+        fs::write(&path, contents).map_err(|e| Error::Write(e, path))
+    };
+    let remove = |path: path::PathBuf, result: io::Result<()>| match result {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(Error::Write(e, path)),
+        _ => Ok(()),
+    };
+
+    fs::create_dir_all(dir).map_err(|e| Error::Write(e, dir.to_path_buf()))?;
+    let patched = dir.join("patched");
+    remove(patched.clone(), fs::remove_dir_all(&patched))?;
+    let stderr = dir.join("protoc-stderr.txt");
+    remove(stderr.clone(), fs::remove_file(&stderr))?;
+
+    write("config.toml", report.config.as_bytes())?;
+    write(
+        "error.json",
+        &serde_json::to_vec_pretty(&report.failure).map_err(Error::Serialize)?,
+    )?;
+    if let Some(stderr) = report.protoc_stderr {
+        write("protoc-stderr.txt", stderr.as_bytes())?;
+    }
+
+    report.protos.iter().try_for_each(|(name, file)| {
+        let target = patched.join(name);
+        target
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::copy(file, &target))
+            .map(|_| ())
+            .map_err(|e| Error::Copy(e, file.clone()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io, path};
+
+    #[derive(thiserror::Error, Debug)]
+    #[error("Failed to compile: {0}")]
+    struct Compile(#[from] io::Error);
+
+    #[test]
+    fn write_replaces_the_previous_report() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let proto = dir.path().join("ferris.proto");
+        fs::write(&proto, "syntax = \"proto3\";\n").expect("Failed to create a test file");
+        let report = dir.path().join("report");
+
+        let error = Compile(io::Error::other(
+            "crabs/ferris.proto:1:1: Expected a message",
+        ));
+        let failure = super::Failure::new(&error);
+        assert!(
+            failure.mentions("crabs/ferris.proto"),
+            "Expected the causes to mention the protobuf file"
+        );
+        super::write(
+            &report,
+            &super::Report {
+                config: "build-client = true\n",
+                failure,
+                protoc_stderr: Some("crabs/ferris.proto:1:1: Expected a message\n"),
+                protos: vec![(path::PathBuf::from("crabs/ferris.proto"), proto)],
+            },
+        )
+        .expect("Failed to write the report");
+
+        let failure: super::Failure = serde_json::from_slice(
+            &fs::read(report.join("error.json")).expect("Failed to read the error"),
+        )
+        .expect("Failed to parse the error");
+        assert_eq!(
+            failure.causes,
+            ["crabs/ferris.proto:1:1: Expected a message"],
+            "Expected the chain of the causes"
+        );
+        assert!(
+            report.join("patched/crabs/ferris.proto").is_file(),
+            "Expected the patched file in the report"
+        );
+
+        super::write(
+            &report,
+            &super::Report {
+                config: "build-client = true\n",
+                failure: super::Failure::new(&error),
+                protoc_stderr: None,
+                protos: vec![],
+            },
+        )
+        .expect("Failed to write the report");
+        assert!(
+            !report.join("patched").exists() && !report.join("protoc-stderr.txt").exists(),
+            "Expected the previous report to be replaced"
+        );
+    }
+}
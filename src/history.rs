@@ -0,0 +1,189 @@
+use std::{fmt::Write as _, fs, io, path};
+
+/// The name of the history file used when no other path is configured.
+pub const DEFAULT_PATH: &str = ".pbuildrs-history.json";
+
+/// How many runs the history keeps when no other limit is configured.
+pub const DEFAULT_LIMIT: usize = 50;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to read the history file `{1}`: {0}")]
+    Load(io::Error, path::PathBuf),
+    #[error("Failed to parse the history file `{1}`: {0}")]
+    Parse(serde_json::Error, path::PathBuf),
+    #[error("Failed to write the history file `{1}`: {0}")]
+    Store(io::Error, path::PathBuf),
+    #[error("Failed to serialize the history: {0}")]
+    Serialize(serde_json::Error),
+}
+
+/// How a recorded run ended.
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case", tag = "status")]
+pub enum Outcome {
+    Generated,
+    /// Nothing changed since the previous run, so the generation was skipped.
+    Cached,
+    Failed {
+        error: String,
+    },
+}
+
+/// A run of the builder, as recorded in the history file.
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Run {
+    /// When the run started, in seconds since the Unix epoch.
+    pub started_at: u64,
+    /// The hash of every protobuf file in the sources and the include paths.
+    pub inputs: String,
+    /// How long the whole run took, in milliseconds.
+    pub duration_ms: u64,
+    /// How long checking that the generated code compiles took, in milliseconds, if it was
+    /// verified.
+    pub compile_ms: Option<u64>,
+    pub outcome: Outcome,
+}
+
+/// Load the recorded runs, oldest first. A missing history file has no runs.
+pub fn load(file: &path::Path) -> Result<Vec<Run>, Error> {
+    match fs::read(file) {
+        Ok(contents) => {
+            serde_json::from_slice(&contents).map_err(|e| Error::Parse(e, file.to_path_buf()))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(Error::Load(e, file.to_path_buf())),
+    }
+}
+
+/// Append the `run` to the history `file`, keeping only the `limit` most recent runs. A history
+/// file that can not be parsed, e.g. one written by an incompatible version, is started over.
+pub fn record(file: &path::Path, run: Run, limit: usize) -> Result<(), Error> {
+    let mut runs = match load(file) {
+        Ok(runs) => runs,
+        Err(Error::Parse(e, _)) => {
+            tracing::warn!("Starting the history over, the previous one is unreadable: {e}");
+            vec![]
+        }
+        Err(e) => return Err(e),
+    };
+
+    runs.push(run);
+    runs.drain(..runs.len().saturating_sub(limit));
+
+    let contents = serde_json::to_vec_pretty(&runs).map_err(Error::Serialize)?;
+    fs::write(file, contents).map_err(|e| Error::Store(e, file.to_path_buf()))
+}
+
+/// Format the seconds since the Unix epoch as a UTC date and time, e.g. `2024-03-12 09:41:07`.
+fn format_time(secs: u64) -> String {
+    let (days, time) = (secs / 86_400, secs % 86_400);
+
+    // The civil date of the day count, shifted to start the years in March so the leap day is
+    // the last one of a year, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let (year, month) = match month {
+        0..10 => (era * 400 + year_of_era, month + 3),
+        _ => (era * 400 + year_of_era + 1, month - 9),
+    };
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60,
+    )
+}
+
+fn format_duration(ms: u64) -> String {
+    format!("{}.{:03}s", ms / 1_000, ms % 1_000)
+}
+
+/// Render the `runs` as a table, oldest first, marking the runs whose inputs changed since the
+/// previous one.
+pub fn render(runs: &[Run]) -> String {
+    let mut table = format!(
+        "{:<19}  {:>10}  {:>10}  {:<13}  OUTCOME\n",
+        "STARTED (UTC)", "DURATION", "COMPILE", "INPUTS"
+    );
+
+    let previous = std::iter::once(None).chain(runs.iter().map(Some));
+    runs.iter().zip(previous).for_each(|(run, previous)| {
+        let changed = match previous {
+            Some(previous) if previous.inputs != run.inputs => "*",
+            _ => " ",
+        };
+        let outcome = match &run.outcome {
+            Outcome::Generated => "generated".to_string(),
+            Outcome::Cached => "cached".to_string(),
+            Outcome::Failed { error } => format!("failed: {error}"),
+        };
+
+        let _ = writeln!(
+            table,
+            "{}  {:>10}  {:>10}  {:<12}{changed}  {outcome}",
+            format_time(run.started_at),
+            format_duration(run.duration_ms),
+            run.compile_ms.map(format_duration).unwrap_or_default(),
+            run.inputs.get(..12).unwrap_or(&run.inputs),
+        );
+    });
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn record_keeps_the_most_recent_runs() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let file = dir.path().join(super::DEFAULT_PATH);
+        let run = |started_at, outcome| super::Run {
+            started_at,
+            inputs: "4f1d6c0a9e2b7d3c".to_string(),
+            duration_ms: 1_250,
+            compile_ms: None,
+            outcome,
+        };
+
+        assert_eq!(
+            super::load(&file).expect("Failed to load a missing history"),
+            [],
+            "Expected a missing history to have no runs",
+        );
+
+        [
+            run(1_710_236_467, super::Outcome::Generated),
+            run(1_710_236_500, super::Outcome::Cached),
+            run(
+                1_710_236_600,
+                super::Outcome::Failed {
+                    error: "Ferris is missing".to_string(),
+                },
+            ),
+        ]
+        .into_iter()
+        .for_each(|run| super::record(&file, run, 2).expect("Failed to record a run"));
+
+        let runs = super::load(&file).expect("Failed to load the history");
+        assert_eq!(
+            runs.iter().map(|run| run.started_at).collect::<Vec<_>>(),
+            [1_710_236_500, 1_710_236_600],
+            "Expected only the most recent runs to be kept",
+        );
+        assert_eq!(
+            super::render(&runs[..1]),
+            "STARTED (UTC)          DURATION     COMPILE  INPUTS         OUTCOME\n\
+            2024-03-12 09:41:40      1.250s              4f1d6c0a9e2b   cached\n",
+            "Invalid rendered history",
+        );
+    }
+}
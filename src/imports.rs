@@ -0,0 +1,249 @@
+use std::{collections, fmt, fs, io, iter, path};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to read the protobuf file `{1}`: {0}")]
+    ReadFile(io::Error, path::PathBuf),
+}
+
+/// A chain of protobuf files importing each other, starting and ending with the same file.
+#[derive(PartialEq, Debug)]
+pub struct Cycle(pub Vec<String>);
+
+impl fmt::Display for Cycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.join(" -> "))
+    }
+}
+
+/// An import of a protobuf file that none of the include directories provide.
+#[derive(PartialEq, Debug)]
+pub struct Unresolved {
+    pub file: String,
+    pub import: String,
+}
+
+impl fmt::Display for Unresolved {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` imported by `{}`", self.import, self.file)
+    }
+}
+
+/// The directory of the well-known types, which `protoc` provides on its own.
+const WELL_KNOWN_TYPES: &str = "google/protobuf/";
+
+/// The files imported by the protobuf `source`, including the `public` and `weak` imports.
+fn parse(source: &str) -> Vec<String> {
+    source
+        .lines()
+        .map(|line| line.split("//").next().unwrap_or_default().trim())
+        .filter_map(|line| line.strip_prefix("import"))
+        .filter_map(|rest| {
+            let rest = rest.trim_start();
+            let rest = rest
+                .strip_prefix("public")
+                .or_else(|| rest.strip_prefix("weak"))
+                .unwrap_or(rest)
+                .trim_start();
+            let quote = rest.chars().next().filter(|c| matches!(c, '"' | '\''))?;
+            let (name, _) = rest[1..].split_once(quote)?;
+
+            Some(name.to_string())
+        })
+        .collect()
+}
+
+/// How far [`find_cycles`] got through a file. Reaching a file that is still being visited again
+/// closes a cycle.
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Visiting,
+    Done,
+}
+
+fn visit<'a>(
+    file: &'a str,
+    graph: &'a collections::BTreeMap<String, Vec<String>>,
+    states: &mut collections::BTreeMap<&'a str, State>,
+    stack: &mut Vec<&'a str>,
+    cycles: &mut Vec<Cycle>,
+) {
+    states.insert(file, State::Visiting);
+    stack.push(file);
+
+    graph
+        .get(file)
+        .into_iter()
+        .flatten()
+        .filter(|import| graph.contains_key(import.as_str()))
+        .for_each(|import| match states.get(import.as_str()) {
+            None => visit(import, graph, states, stack, cycles),
+            Some(State::Visiting) => {
+                let start = stack
+                    .iter()
+                    .position(|file| file == import)
+                    .unwrap_or_default();
+                let mut cycle = stack[start..]
+                    .iter()
+                    .map(|file| file.to_string())
+                    .collect::<Vec<_>>();
+                cycle.push(import.clone());
+
+                cycles.push(Cycle(cycle));
+            }
+            Some(State::Done) => {}
+        });
+
+    stack.pop();
+    states.insert(file, State::Done);
+}
+
+/// Find the import cycles among the protobuf `files`, given relative to the `root` directory the
+/// imports are resolved against. The imports of the files outside of the `root` are not followed.
+pub fn find_cycles(root: &path::Path, files: &[path::PathBuf]) -> Result<Vec<Cycle>, Error> {
+    let graph = files
+        .iter()
+        .map(|file| {
+            let path = root.join(file);
+            let source = fs::read_to_string(&path).map_err(|e| Error::ReadFile(e, path.clone()))?;
+
+            Ok((file.display().to_string(), parse(&source)))
+        })
+        .collect::<Result<collections::BTreeMap<_, _>, Error>>()?;
+
+    let mut states = collections::BTreeMap::new();
+    let mut cycles = vec![];
+
+    graph.keys().for_each(|file| {
+        if !states.contains_key(file.as_str()) {
+            visit(file, &graph, &mut states, &mut vec![], &mut cycles);
+        }
+    });
+
+    Ok(cycles)
+}
+
+/// Find the imports of the protobuf `files`, given relative to the `root` directory, that neither
+/// the `root` nor any of the `includes` contain. The well-known types are assumed to be provided
+/// by `protoc`.
+pub fn find_unresolved(
+    root: &path::Path,
+    files: &[path::PathBuf],
+    includes: &[path::PathBuf],
+) -> Result<Vec<Unresolved>, Error> {
+    let mut unresolved = vec![];
+
+    files.iter().try_for_each(|file| {
+        let path = root.join(file);
+        let source = fs::read_to_string(&path).map_err(|e| Error::ReadFile(e, path.clone()))?;
+
+        unresolved.extend(
+            parse(&source)
+                .into_iter()
+                .filter(|import| !import.starts_with(WELL_KNOWN_TYPES))
+                .filter(|import| {
+                    !iter::once(root)
+                        .chain(includes.iter().map(path::PathBuf::as_path))
+                        .any(|include| include.join(import).is_file())
+                })
+                .map(|import| Unresolved {
+                    file: file.display().to_string(),
+                    import,
+                }),
+        );
+
+        Ok(())
+    })?;
+
+    Ok(unresolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path};
+
+    #[test]
+    fn parse_finds_every_import() {
+        assert_eq!(
+            super::parse(
+                "syntax = \"proto3\";
+import \"crabs/Ferris.proto\";
+import public 'crabs/disney.proto'; // Sebastian
+  import weak \"crabs/sponge_bob.proto\";
+// import \"commented.proto\";
+message Imported {}
+"
+            ),
+            [
+                "crabs/Ferris.proto",
+                "crabs/disney.proto",
+                "crabs/sponge_bob.proto",
+            ],
+            "Invalid imports",
+        );
+    }
+
+    #[test]
+    fn find_cycles_reports_the_full_path() {
+        let root = tempfile::TempDir::new().expect("Failed to create a test directory");
+
+        [
+            (
+                "a.proto",
+                "import \"b.proto\";\nimport \"google/protobuf/empty.proto\";\n",
+            ),
+            ("b.proto", "import \"c.proto\";\n"),
+            ("c.proto", "import \"a.proto\";\n"),
+            ("d.proto", "import \"a.proto\";\n"),
+        ]
+        .into_iter()
+        .for_each(|(file, contents)| {
+            fs::write(root.path().join(file), contents).expect("Failed to create a test file")
+        });
+
+        let cycles = super::find_cycles(
+            root.path(),
+            &["a.proto", "b.proto", "c.proto", "d.proto"].map(path::PathBuf::from),
+        )
+        .expect("Failed to find the import cycles");
+
+        assert_eq!(
+            cycles.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["a.proto -> b.proto -> c.proto -> a.proto"],
+            "Invalid import cycles",
+        );
+    }
+
+    #[test]
+    fn find_unresolved_checks_every_include() {
+        let root = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let vendor = tempfile::TempDir::new().expect("Failed to create a test include directory");
+
+        fs::write(
+            root.path().join("ferris.proto"),
+            "import \"claw.proto\";\nimport \"vendor/shell.proto\";\nimport \"missing.proto\";\n\
+            import \"google/protobuf/timestamp.proto\";\n",
+        )
+        .expect("Failed to write a test file");
+        fs::write(root.path().join("claw.proto"), "").expect("Failed to write a test file");
+        fs::create_dir(vendor.path().join("vendor")).expect("Failed to create a test directory");
+        fs::write(vendor.path().join("vendor/shell.proto"), "")
+            .expect("Failed to write a test file");
+
+        let unresolved = super::find_unresolved(
+            root.path(),
+            &[path::PathBuf::from("ferris.proto")],
+            &[vendor.path().to_path_buf()],
+        )
+        .expect("Failed to find the unresolved imports");
+
+        assert_eq!(
+            unresolved,
+            [super::Unresolved {
+                file: "ferris.proto".to_string(),
+                import: "missing.proto".to_string(),
+            }],
+            "Invalid unresolved imports",
+        );
+    }
+}
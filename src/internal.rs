@@ -0,0 +1,596 @@
+use std::{collections, fs, io, ops, path};
+
+use prost::encoding::{self, WireType};
+use syn::visit::Visit;
+
+use crate::services;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("The internal message `{0}` is not defined in any of the protobuf files")]
+    UnknownMessage(String),
+    #[error("Failed to read the file descriptor set `{1}`: {0}")]
+    ReadDescriptorSet(io::Error, path::PathBuf),
+    #[error("Failed to read the internal messages from the file descriptor set `{1}`: {0}")]
+    DecodeDescriptorSet(prost::DecodeError, path::PathBuf),
+    #[error("Failed to read the generated code `{1}`: {0}")]
+    Read(io::Error, path::PathBuf),
+    #[error("Failed to parse the generated code `{1}`: {0}")]
+    Parse(syn::Error, path::PathBuf),
+    #[error("Failed to write the generated code `{1}`: {0}")]
+    Write(io::Error, path::PathBuf),
+}
+
+/// The custom message option marking a message as internal, declared by one of the protobuf
+/// files with any free extension number, e.g.
+/// `package pbuildrs; extend google.protobuf.MessageOptions { bool internal = 50000; }`.
+pub const OPTION: &str = ".pbuildrs.internal";
+
+/// The module the internal messages are moved into, next to the public items of their module.
+pub const MODULE: &str = "__internal";
+
+/// An internal message, by the protobuf package it is defined in and its name along with the
+/// names of the messages it is nested in, e.g. `["Ferris", "Claw"]`.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Message {
+    pub package: String,
+    pub names: Vec<String>,
+}
+
+impl Message {
+    /// The fully qualified name of the message, e.g. `.crabs.Ferris.Claw`.
+    pub fn path(&self) -> String {
+        match self.package.as_str() {
+            "" => format!(".{}", self.names.join(".")),
+            package => format!(".{package}.{}", self.names.join(".")),
+        }
+    }
+
+    /// The Rust module the message is generated into, relative to the module of its package,
+    /// e.g. `["ferris"]`.
+    fn module(&self) -> Vec<String> {
+        self.names[..self.names.len() - 1]
+            .iter()
+            .map(|name| services::to_snake(name))
+            .collect()
+    }
+}
+
+/// The path of the internal `message` the attributes of prost are matched against, e.g.
+/// `.crabs.GetFerrisReqProto`.
+pub fn path(message: &str) -> String {
+    format!(".{}", message.trim_start_matches('.'))
+}
+
+/// The number of the [`OPTION`] extension declared in the `fds`, if any.
+fn option_number(fds: &prost_types::FileDescriptorSet) -> Option<u32> {
+    fds.file
+        .iter()
+        .flat_map(|file| {
+            file.extension
+                .iter()
+                .map(move |extension| (file.package(), extension))
+        })
+        .find(|(package, extension)| {
+            extension.extendee() == ".google.protobuf.MessageOptions"
+                && format!(".{package}.{}", extension.name()) == OPTION
+        })
+        .and_then(|(_, extension)| u32::try_from(extension.number()).ok())
+}
+
+/// A field of an encoded protobuf message, only the varint and the length-delimited ones being
+/// read.
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Other,
+}
+
+/// The fields of the encoded protobuf message in the `buf`, along with their numbers. prost drops
+/// the custom options when decoding the descriptors, so they are read from the encoded ones.
+fn fields(mut buf: &[u8]) -> Result<Vec<(u32, Value<'_>)>, prost::DecodeError> {
+    let mut fields = vec![];
+
+    while !buf.is_empty() {
+        let (number, wire_type) = encoding::decode_key(&mut buf)?;
+        let value = match wire_type {
+            WireType::Varint => Value::Varint(encoding::decode_varint(&mut buf)?),
+            WireType::LengthDelimited => {
+                let mut rest = buf;
+                encoding::skip_field(wire_type, number, &mut rest, Default::default())?;
+                let mut value = &buf[..buf.len() - rest.len()];
+                buf = rest;
+
+                // Skip the length of the value.
+                encoding::decode_varint(&mut value)?;
+                Value::Bytes(value)
+            }
+            wire_type => {
+                encoding::skip_field(wire_type, number, &mut buf, Default::default())?;
+                Value::Other
+            }
+        };
+
+        fields.push((number, value));
+    }
+
+    Ok(fields)
+}
+
+/// Add the fully qualified names of the encoded `messages` in the `scope` with the option with
+/// the `number` set to the `marked` ones, along with the ones nested in them.
+fn push_marked<'a>(
+    marked: &mut collections::BTreeSet<String>,
+    scope: &str,
+    messages: impl IntoIterator<Item = &'a [u8]>,
+    number: u32,
+) -> Result<(), prost::DecodeError> {
+    messages.into_iter().try_for_each(|message| {
+        let (mut name, mut nested, mut is_marked) = (String::new(), vec![], false);
+        fields(message)?
+            .into_iter()
+            .try_for_each(|field| match field {
+                (1, Value::Bytes(bytes)) => {
+                    name = String::from_utf8_lossy(bytes).into_owned();
+                    Ok(())
+                }
+                (3, Value::Bytes(bytes)) => {
+                    nested.push(bytes);
+                    Ok(())
+                }
+                (7, Value::Bytes(options)) => {
+                    is_marked = fields(options)?.into_iter().any(|option| {
+                        matches!(option, (n, Value::Varint(value)) if n == number && value != 0)
+                    });
+                    Ok(())
+                }
+                _ => Ok::<_, prost::DecodeError>(()),
+            })?;
+
+        let name = format!("{scope}.{name}");
+        push_marked(marked, &name, nested, number)?;
+        if is_marked {
+            marked.insert(name);
+        }
+
+        Ok(())
+    })
+}
+
+/// The fully qualified names of the messages of the encoded file descriptor set in the `buf` with
+/// the option with the `number` set.
+fn marked(buf: &[u8], number: u32) -> Result<collections::BTreeSet<String>, prost::DecodeError> {
+    let mut marked = collections::BTreeSet::new();
+
+    fields(buf)?
+        .into_iter()
+        .filter_map(|field| match field {
+            (1, Value::Bytes(file)) => Some(file),
+            _ => None,
+        })
+        .try_for_each(|file| {
+            let fields = fields(file)?;
+            let scope = fields
+                .iter()
+                .find_map(|field| match field {
+                    (2, Value::Bytes(package)) => {
+                        Some(format!(".{}", String::from_utf8_lossy(package)))
+                    }
+                    _ => None,
+                })
+                .unwrap_or_default();
+            let messages = fields.iter().filter_map(|field| match field {
+                (4, Value::Bytes(message)) => Some(*message),
+                _ => None,
+            });
+
+            push_marked(&mut marked, &scope, messages, number)
+        })?;
+
+    Ok(marked)
+}
+
+/// The internal messages of the `fds`, given by their fully qualified name in the `messages`, or
+/// marked with the [`OPTION`] in the encoded file descriptor sets at the `descriptor_sets` paths
+/// they were compiled into, failing if any of the `messages` is not defined. The messages nested
+/// in another internal message are left out, since they are hidden along with it.
+pub fn resolve(
+    fds: &prost_types::FileDescriptorSet,
+    messages: &[String],
+    descriptor_sets: &[&path::Path],
+) -> Result<Vec<Message>, Error> {
+    let defined = fds
+        .file
+        .iter()
+        .flat_map(|file| {
+            services::messages(file).into_iter().map(|message| {
+                let message = Message {
+                    package: file.package().to_string(),
+                    names: message.names,
+                };
+
+                (message.path(), message)
+            })
+        })
+        .collect::<collections::BTreeMap<_, _>>();
+
+    let mut paths = messages
+        .iter()
+        .map(|message| match defined.contains_key(&path(message)) {
+            true => Ok(path(message)),
+            false => Err(Error::UnknownMessage(message.clone())),
+        })
+        .collect::<Result<collections::BTreeSet<_>, _>>()?;
+
+    if let Some(number) = option_number(fds) {
+        descriptor_sets.iter().try_for_each(|descriptor_set| {
+            let buf = fs::read(descriptor_set)
+                .map_err(|e| Error::ReadDescriptorSet(e, descriptor_set.to_path_buf()))?;
+            let marked = marked(&buf, number)
+                .map_err(|e| Error::DecodeDescriptorSet(e, descriptor_set.to_path_buf()))?;
+            paths.extend(marked);
+
+            Ok::<_, Error>(())
+        })?;
+    }
+
+    let is_nested = |path: &str| {
+        paths
+            .iter()
+            .any(|parent| path.starts_with(&format!("{parent}.")))
+    };
+
+    Ok(paths
+        .iter()
+        .filter(|path| !is_nested(path))
+        .filter_map(|path| defined.get(path).cloned())
+        .collect())
+}
+
+/// The items of the internal messages found in a module of the generated code: the byte ranges
+/// of their structs and of the modules of their nested types, along with the names to re-export.
+#[derive(Default)]
+struct Hidden {
+    ranges: Vec<ops::Range<usize>>,
+    names: Vec<String>,
+}
+
+/// Collects the insertions turning the relative paths of the moved items into the ones valid one
+/// module deeper, e.g. `super::Shell` into `super::super::Shell`.
+struct Deepener {
+    edits: Vec<(ops::Range<usize>, String)>,
+}
+
+impl<'ast> Visit<'ast> for Deepener {
+    fn visit_path(&mut self, path: &'ast syn::Path) {
+        if path.leading_colon.is_none() && path.segments[0].ident == "super" {
+            let start = path.segments[0].ident.span().byte_range().start;
+            self.edits.push((start..start, "super::".to_string()));
+        }
+
+        syn::visit::visit_path(self, path);
+    }
+
+    fn visit_attribute(&mut self, attribute: &'ast syn::Attribute) {
+        let tokens = match &attribute.meta {
+            syn::Meta::List(list) if list.path.is_ident("prost") => list.tokens.clone(),
+            _ => proc_macro2::TokenStream::new(),
+        };
+
+        tokens.into_iter().for_each(|token| {
+            let proc_macro2::TokenTree::Literal(literal) = token else {
+                return;
+            };
+            let range = literal.span().byte_range();
+
+            let Ok(value) =
+                syn::parse2::<syn::LitStr>(proc_macro2::TokenTree::Literal(literal).into())
+            else {
+                return;
+            };
+            let value = value.value();
+            if value.starts_with("super::") {
+                self.edits
+                    .push((range, format!("{:?}", format!("super::{value}"))));
+            }
+        });
+
+        syn::visit::visit_attribute(self, attribute);
+    }
+}
+
+/// The start of an item, at its first attribute, e.g. its doc comment, if any.
+fn start(attrs: &[syn::Attribute], keyword: proc_macro2::Span) -> usize {
+    attrs
+        .first()
+        .map_or(keyword, |attr| attr.pound_token.span)
+        .byte_range()
+        .start
+}
+
+/// Find the items of the `messages` generated into the `module` among the `items` of that module
+/// and of the modules nested in it, keyed by the module they are found in, and deepen their paths.
+fn find(
+    hidden: &mut collections::BTreeMap<Vec<String>, Hidden>,
+    deepener: &mut Deepener,
+    module: &[String],
+    items: &[syn::Item],
+    messages: &[&Message],
+) {
+    items.iter().for_each(|item| match item {
+        syn::Item::Struct(item) => {
+            let is_hidden = messages.iter().any(|message| {
+                message.module() == module
+                    && message.names.last().is_some_and(|name| item.ident == name)
+            });
+            if !is_hidden {
+                return;
+            }
+
+            let end = match &item.fields {
+                syn::Fields::Named(fields) => fields.brace_token.span.close(),
+                _ => item.semi_token.map_or(item.ident.span(), |semi| semi.span),
+            };
+            let hidden = hidden.entry(module.to_vec()).or_default();
+            hidden
+                .ranges
+                .push(start(&item.attrs, item.struct_token.span)..end.byte_range().end);
+            hidden.names.push(item.ident.to_string());
+            deepener.visit_item_struct(item);
+        }
+        syn::Item::Mod(item) => {
+            let ident = item.ident.to_string();
+            let is_hidden = messages.iter().any(|message| {
+                message.module() == module
+                    && message
+                        .names
+                        .last()
+                        .is_some_and(|name| services::to_snake(name) == ident)
+            });
+
+            let Some((brace, items)) = &item.content else {
+                return;
+            };
+            if is_hidden {
+                let hidden = hidden.entry(module.to_vec()).or_default();
+                hidden.ranges.push(
+                    start(&item.attrs, item.mod_token.span)..brace.span.close().byte_range().end,
+                );
+                hidden.names.push(ident);
+                deepener.visit_item_mod(item);
+            } else {
+                let mut nested = module.to_vec();
+                nested.push(ident);
+                find(hidden, deepener, &nested, items, messages);
+            }
+        }
+        _ => {}
+    });
+}
+
+/// Move the items of the internal `messages` out of the generated `code` of their package into a
+/// hidden [`MODULE`] of the module they are generated in, re-exported from there under the same
+/// names, so the rest of the generated code still refers to them.
+fn hide(code: &str, messages: &[&Message]) -> syn::Result<String> {
+    let file = syn::parse_file(code)?;
+
+    let mut hidden = collections::BTreeMap::new();
+    let mut deepener = Deepener { edits: vec![] };
+    find(&mut hidden, &mut deepener, &[], &file.items, messages);
+    drop(file);
+    proc_macro2::extra::invalidate_current_thread_spans();
+
+    let deepened = |range: &ops::Range<usize>| {
+        let mut moved = code[range.clone()].to_string();
+        let mut inner = deepener
+            .edits
+            .iter()
+            .filter(|(edit, _)| range.start <= edit.start && edit.end <= range.end)
+            .collect::<Vec<_>>();
+        inner.sort_by_key(|(edit, _)| edit.start);
+        inner.into_iter().rev().for_each(|(edit, text)| {
+            moved.replace_range(edit.start - range.start..edit.end - range.start, text);
+        });
+
+        moved
+    };
+
+    let mut edits = vec![];
+    hidden.into_values().for_each(|hidden| {
+        let moved = hidden
+            .ranges
+            .iter()
+            .map(deepened)
+            .collect::<Vec<_>>()
+            .join("\n");
+        let wrapped = format!(
+            "#[doc(hidden)]\npub mod {MODULE} {{\n#[allow(unused_imports)]\nuse super::*;\n\n\
+            {moved}\n}}\n#[doc(hidden)]\npub use {MODULE}::{{{}}};",
+            hidden.names.join(", "),
+        );
+
+        hidden
+            .ranges
+            .into_iter()
+            .enumerate()
+            .for_each(|(i, range)| {
+                edits.push((
+                    range,
+                    if i == 0 {
+                        wrapped.clone()
+                    } else {
+                        String::new()
+                    },
+                ));
+            });
+    });
+
+    let mut code = code.to_string();
+    edits.sort_by_key(|(range, _)| range.start);
+    edits
+        .into_iter()
+        .rev()
+        .for_each(|(range, edit)| code.replace_range(range, &edit));
+
+    Ok(code)
+}
+
+/// Hide the internal `messages` in the code generated by prost into the `dir`, moving them into a
+/// `#[doc(hidden)]` [`MODULE`] of the module they are generated in.
+pub fn write(dir: &path::Path, messages: &[Message]) -> Result<(), Error> {
+    let mut packages = collections::BTreeMap::<_, Vec<_>>::new();
+    messages.iter().for_each(|message| {
+        packages
+            .entry(message.package.as_str())
+            .or_default()
+            .push(message);
+    });
+
+    packages.into_iter().try_for_each(|(package, messages)| {
+        let path = dir.join(match package {
+            "" => "_.rs".to_string(),
+            package => format!("{package}.rs"),
+        });
+        // The packages of the extern paths are not generated.
+        if !path.is_file() {
+            return Ok(());
+        }
+        tracing::debug!("Hiding the internal messages: {}", path.display());
+
+        let code = fs::read_to_string(&path).map_err(|e| Error::Read(e, path.clone()))?;
+        let code = hide(&code, &messages).map_err(|e| Error::Parse(e, path.clone()))?;
+
+        fs::write(&path, code).map_err(|e| Error::Write(e, path))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    fn fds() -> prost_types::FileDescriptorSet {
+        prost_types::FileDescriptorSet {
+            file: vec![
+                prost_types::FileDescriptorProto {
+                    package: Some("pbuildrs".to_string()),
+                    extension: vec![prost_types::FieldDescriptorProto {
+                        name: Some("internal".to_string()),
+                        number: Some(50001),
+                        extendee: Some(".google.protobuf.MessageOptions".to_string()),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+                prost_types::FileDescriptorProto {
+                    package: Some("crabs".to_string()),
+                    message_type: vec![
+                        prost_types::DescriptorProto {
+                            name: Some("Ferris".to_string()),
+                            nested_type: vec![prost_types::DescriptorProto {
+                                name: Some("Claw".to_string()),
+                                ..Default::default()
+                            }],
+                            ..Default::default()
+                        },
+                        prost_types::DescriptorProto {
+                            name: Some("Shell".to_string()),
+                            ..Default::default()
+                        },
+                    ],
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+
+    /// The length-delimited field with the `number` and the `value`.
+    fn field(number: u32, value: &[u8]) -> Vec<u8> {
+        let mut buf = vec![];
+        prost::encoding::encode_key(number, prost::encoding::WireType::LengthDelimited, &mut buf);
+        prost::encoding::encode_varint(value.len() as u64, &mut buf);
+        buf.extend_from_slice(value);
+
+        buf
+    }
+
+    #[test]
+    fn resolve_rejects_unknown_messages() {
+        let messages = super::resolve(
+            &fds(),
+            &["crabs.Ferris".to_string(), ".crabs.Ferris.Claw".to_string()],
+            &[],
+        )
+        .expect("Expected the internal messages to be valid");
+        assert_eq!(
+            messages,
+            [super::Message {
+                package: "crabs".to_string(),
+                names: vec!["Ferris".to_string()],
+            }],
+            "Expected the messages nested in an internal one to be left out",
+        );
+
+        let err = super::resolve(&fds(), &["crabs.Claw".to_string()], &[]);
+        assert!(
+            matches!(&err, Err(super::Error::UnknownMessage(message)) if message == "crabs.Claw"),
+            "Expected `Err(Error::UnknownMessage)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn resolve_reads_the_custom_option() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let path = dir.path().join("descriptor.bin");
+
+        // `option (pbuildrs.internal) = true;`
+        let mut option = vec![];
+        prost::encoding::encode_key(50001, prost::encoding::WireType::Varint, &mut option);
+        prost::encoding::encode_varint(1, &mut option);
+        let shell = [field(1, b"Shell"), field(7, &option)].concat();
+        let ferris = field(1, b"Ferris");
+        let file = [field(2, b"crabs"), field(4, &ferris), field(4, &shell)].concat();
+        fs::write(&path, field(1, &file)).expect("Failed to create a test descriptor set");
+
+        let messages =
+            super::resolve(&fds(), &[], &[&path]).expect("Failed to resolve the internal messages");
+        assert_eq!(
+            messages,
+            [super::Message {
+                package: "crabs".to_string(),
+                names: vec!["Shell".to_string()],
+            }],
+            "Expected the message with the option to be internal",
+        );
+    }
+
+    #[test]
+    fn hide_moves_the_messages_into_the_internal_module() {
+        let code = "pub struct Shell {\n    pub ferris: Option<super::Ferris>,\n}\n\
+            /// A crab.\n\
+            #[derive(Clone)]\n\
+            pub struct Ferris {\n    #[prost(enumeration = \"ferris::Kind\")]\n    pub kind: i32,\n}\n\
+            /// Nested message and enum types in `Ferris`.\n\
+            pub mod ferris {\n    pub enum Kind {\n        Crab(super::super::Shell),\n    }\n\
+            #[prost(oneof = \"super::Ferris\")]\n    pub struct Claw {}\n}\n";
+        let message = super::Message {
+            package: "crabs".to_string(),
+            names: vec!["Ferris".to_string()],
+        };
+
+        let hidden = super::hide(code, &[&message]).expect("Failed to hide the messages");
+        assert_eq!(
+            hidden,
+            "pub struct Shell {\n    pub ferris: Option<super::Ferris>,\n}\n\
+            #[doc(hidden)]\npub mod __internal {\n#[allow(unused_imports)]\nuse super::*;\n\n\
+            /// A crab.\n\
+            #[derive(Clone)]\n\
+            pub struct Ferris {\n    #[prost(enumeration = \"ferris::Kind\")]\n    pub kind: i32,\n}\n\
+            /// Nested message and enum types in `Ferris`.\n\
+            pub mod ferris {\n    pub enum Kind {\n        Crab(super::super::super::Shell),\n    }\n\
+            #[prost(oneof = \"super::super::Ferris\")]\n    pub struct Claw {}\n}\n}\n\
+            #[doc(hidden)]\npub use __internal::{Ferris, ferris};\n\n",
+            "Expected the message and its nested types to be moved into the internal module",
+        );
+    }
+}
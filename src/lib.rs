@@ -1,14 +1,89 @@
+mod attributes;
+pub mod builder;
+mod cache;
+mod check;
+// Only public for the binary, it is not a part of the library API.
+#[doc(hidden)]
 pub mod cli;
+mod codeowners;
+mod config;
+mod conformance;
+mod defaults;
+mod descriptor;
+mod descriptor_endpoint;
+mod diagnostics;
+mod encoded_size;
+mod events;
+mod exclude;
+mod execution;
+mod failure_report;
+mod history;
+mod imports;
+mod internal;
+mod lookup;
+mod manifest;
+mod message;
 pub mod modgen;
-mod patcher;
+mod naming;
+mod newline;
+pub mod patcher;
+mod pbjson;
+mod per_service;
+mod portable;
+mod reachability;
+mod recording;
+mod redact;
+mod reexports;
+mod registry;
+mod release;
+mod rename;
+mod reproducible;
+mod reserved;
+mod retry;
+mod root_file;
+mod rows;
+mod rustfmt;
+mod sandbox;
+mod semver;
+mod serialize;
+mod services;
+mod shadow;
+mod size;
+mod size_guard;
+mod split;
+mod staging;
+mod strings;
+mod usage;
+mod verify;
+mod verify_patch;
+mod watch;
+mod wire_compat;
 
 use rayon::prelude::*;
-use std::{fs, io, path};
+use std::{collections, fs, io, path};
+
+pub use attributes::Rule as AttributeRule;
+pub use builder::{Builder, ProtoReport, Report, Target, TargetReport};
+pub use check::Difference;
+pub use exclude::{Error as ExclusionError, Exclusions};
+pub use execution::{Action, Backend, Error as ExecutionError, Remote};
+pub use manifest::Metadata as CrateMetadata;
+pub use modgen::{Layout, PackageOutput, PackageOutputError, Visibility};
+pub use newline::Newline;
+pub use patcher::Outcome;
+pub use per_service::Sides as ServiceSides;
+pub use reachability::Unreachable;
+pub use registry::Api as RegistryApi;
+pub use rustfmt::Edition as RustfmtEdition;
+pub use sandbox::Sandbox;
+pub use semver::{Bump, Change, Suggestion, Version, VersionError};
+pub use size::PackageSize;
+pub use verify::{RustVersion, RustVersionError};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Failed to read the Protobuf files from `{1}`: {0}")]
-    WalkDir(walkdir::Error, path::PathBuf),
+    WalkDir(ignore::Error, path::PathBuf),
     #[error("Failed to resolve the protobuf path `{1}`: {0}")]
     PathResolve(path::StripPrefixError, path::PathBuf),
     #[error("Failed to open the source file `{1}`: {0}")]
@@ -19,15 +94,88 @@ pub enum Error {
     CreatePatchedSubdir(io::Error, path::PathBuf),
     #[error("Failed to process the `{1}` protobuf file: {0}")]
     PatchEdition(patcher::Error, path::PathBuf),
+    #[error("The protobuf file `{0}` is also found in the source root `{1}`")]
+    DuplicateProto(path::PathBuf, path::PathBuf),
+    #[error("{} protobuf files failed:\n{}", .0.len(), Error::diagnostics(.0))]
+    Multiple(Vec<Error>),
 }
 
-pub fn patch_protos(
+impl Error {
+    /// Add the cause of the error, without the path of the affected file, to the `diagnostics`
+    /// under that path, so the same failure hitting many files is reported once.
+    fn report(&self, diagnostics: &diagnostics::Diagnostics) {
+        match self {
+            Self::WalkDir(e, path) => {
+                diagnostics.push(format!("Failed to read the Protobuf files: {e}"), path)
+            }
+            Self::PathResolve(e, path) => {
+                diagnostics.push(format!("Failed to resolve the protobuf path: {e}"), path)
+            }
+            Self::OpenSourceFile(e, path) => {
+                diagnostics.push(format!("Failed to open the source file: {e}"), path)
+            }
+            Self::OpenTempFile(e, path) => {
+                diagnostics.push(format!("Failed to create the patched file: {e}"), path)
+            }
+            Self::CreatePatchedSubdir(e, path) => diagnostics.push(
+                format!("Failed to create the destination subdirectory for patched files: {e}"),
+                path,
+            ),
+            Self::PatchEdition(e, path) => {
+                diagnostics.push(format!("Failed to process the protobuf file: {e}"), path)
+            }
+            Self::DuplicateProto(path, root) => diagnostics.push(
+                format!(
+                    "The protobuf file is also found in the source root `{}`",
+                    root.display()
+                ),
+                path,
+            ),
+            Self::Multiple(errors) => errors.iter().for_each(|e| e.report(diagnostics)),
+        }
+    }
+
+    fn diagnostics(errors: &[Error]) -> diagnostics::Diagnostics {
+        let diagnostics = diagnostics::Diagnostics::default();
+        errors.iter().for_each(|e| e.report(&diagnostics));
+
+        diagnostics
+    }
+}
+
+/// A protobuf file processed by [`patch_protos`].
+#[derive(Clone, PartialEq, Debug)]
+pub struct PatchedProto {
+    /// The path of the protobuf file in the source directory.
+    pub source: path::PathBuf,
+    /// The path of the file written into the destination directory.
+    pub destination: path::PathBuf,
+    /// Whether the edition of the file had to be rewritten.
+    pub outcome: Outcome,
+}
+
+/// The protobuf files processed by [`patch_protos`], sorted by their path within each root.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct PatchReport {
+    pub protos: Vec<PatchedProto>,
+}
+
+impl PatchReport {
+    /// The paths of the files written into the destination directory.
+    pub fn destinations(&self) -> impl Iterator<Item = &path::Path> {
+        self.protos.iter().map(|proto| proto.destination.as_path())
+    }
+}
+
+/// Find the files in the `src_dir`, creating every subdirectory in the `dst_dir` along the way.
+/// The excluded and ignored directories are not descended into.
+fn find_files(
     src_dir: &path::Path,
     dst_dir: &path::Path,
+    exclusions: &exclude::Exclusions,
 ) -> Result<Vec<path::PathBuf>, Error> {
-    let files = walkdir::WalkDir::new(src_dir)
-        .contents_first(false)
-        .into_iter()
+    exclusions
+        .walk(src_dir)
         .try_fold(vec![], |mut files, entry| -> Result<_, Error> {
             let path = entry
                 .map_err(|e| Error::WalkDir(e, src_dir.to_path_buf()))?
@@ -40,7 +188,7 @@ pub fn patch_protos(
                         .map_err(|e| Error::PathResolve(e, src_dir.to_path_buf()))?,
                 );
 
-                println!("Creating a subdirectory: {}", dst_path.display());
+                tracing::trace!("Creating a subdirectory: {}", dst_path.display());
 
                 fs::create_dir_all(&dst_path)
                     .map_err(|e| Error::CreatePatchedSubdir(e, dst_path))?;
@@ -49,21 +197,48 @@ pub fn patch_protos(
             }
 
             Ok(files)
-        })?;
-
-    files
-        .par_iter()
-        .filter(|file| file.extension().is_some_and(|ext| ext == "proto"))
-        .map(|proto| {
-            let path = proto
-                .strip_prefix(src_dir)
-                .map_err(|e| Error::PathResolve(e, src_dir.to_path_buf()))?;
+        })
+}
 
-            println!("Processing: {}", path.display());
+pub(crate) fn patch_protos_with_progress(
+    src_dirs: &[path::PathBuf],
+    exclusions: &exclude::Exclusions,
+    dst_dir: &path::Path,
+    syntax: patcher::Syntax,
+    allow_unknown_editions: bool,
+    progress: &indicatif::ProgressBar,
+) -> Result<PatchReport, Error> {
+    let mut errors = vec![];
+    let mut roots = collections::BTreeMap::new();
+    let mut protos = vec![];
+
+    // Every root is merged into the same tree, so each path can only come from one of them.
+    src_dirs
+        .iter()
+        .try_for_each(|src_dir| -> Result<(), Error> {
+            find_files(src_dir, dst_dir, exclusions)?
+                .into_iter()
+                .filter(|file| file.extension().is_some_and(|ext| ext == "proto"))
+                .for_each(|proto| match proto.strip_prefix(src_dir) {
+                    Ok(path) => match roots.insert(path.to_path_buf(), src_dir) {
+                        Some(other) => {
+                            errors.push(Error::DuplicateProto(proto.clone(), other.clone()))
+                        }
+                        None => protos.push((dst_dir.join(path), proto)),
+                    },
+                    Err(e) => errors.push(Error::PathResolve(e, src_dir.clone())),
+                });
+
+            Ok(())
+        })?;
+    progress.set_length(protos.len() as u64);
 
-            let src = fs::File::open(proto).map_err(|e| Error::OpenSourceFile(e, proto.clone()))?;
+    let results = protos
+        .into_par_iter()
+        .map(|(output, proto)| {
+            let src =
+                fs::File::open(&proto).map_err(|e| Error::OpenSourceFile(e, proto.clone()))?;
 
-            let output = dst_dir.join(path);
             let dst = fs::OpenOptions::new()
                 .write(true)
                 .truncate(true)
@@ -71,17 +246,86 @@ pub fn patch_protos(
                 .open(&output)
                 .map_err(|e| Error::OpenTempFile(e, output.clone()))?;
 
-            patcher::patch_edition(io::BufReader::new(src), dst)
-                .map_err(|e| Error::PatchEdition(e, proto.to_path_buf()))?;
+            let outcome = patcher::patch_edition(
+                io::BufReader::new(src),
+                dst,
+                syntax,
+                allow_unknown_editions,
+            )
+            .map_err(|e| Error::PatchEdition(e, proto.clone()))?;
+            if let Some(edition) = outcome.unknown_edition() {
+                tracing::warn!(
+                    "Replaced the unknown edition `{edition}` of `{}`, its semantics may differ \
+                    from `{}`",
+                    proto.display(),
+                    syntax.declaration(),
+                );
+            }
 
-            Ok(output)
+            Ok(PatchedProto {
+                source: proto,
+                destination: output,
+                outcome,
+            })
         })
-        .collect()
+        .inspect(|_| progress.inc(1))
+        .collect::<Vec<Result<_, Error>>>();
+
+    // Report every failure at once, in the order of the files, rather than just the first one.
+    let (protos, mut errors) =
+        results
+            .into_iter()
+            .fold((vec![], errors), |(mut protos, mut errors), result| {
+                match result {
+                    Ok(proto) => {
+                        tracing::debug!("Processed: {}", proto.source.display());
+                        protos.push(proto);
+                    }
+                    Err(e) => errors.push(e),
+                }
+
+                (protos, errors)
+            });
+
+    match errors.len() {
+        0 => Ok(PatchReport { protos }),
+        1 => Err(errors.remove(0)),
+        _ => Err(Error::Multiple(errors)),
+    }
+}
+
+/// Copy every protobuf file from the `src_dir` into the same place in the `dst_dir`, rewriting
+/// the edition prost can not compile into `syntax = "proto3"`, and report what happened to each of
+/// them.
+pub fn patch_protos(src_dir: &path::Path, dst_dir: &path::Path) -> Result<PatchReport, Error> {
+    patch_source_roots(
+        &[src_dir.to_path_buf()],
+        &exclude::Exclusions::default(),
+        dst_dir,
+    )
+}
+
+/// Like [`patch_protos`], but merges the protobuf files of several source roots into the `dst_dir`,
+/// each placed relative to its own root, skipping the paths matching the `exclusions`. A path
+/// found in more than one root is an error.
+pub fn patch_source_roots(
+    src_dirs: &[path::PathBuf],
+    exclusions: &exclude::Exclusions,
+    dst_dir: &path::Path,
+) -> Result<PatchReport, Error> {
+    patch_protos_with_progress(
+        src_dirs,
+        exclusions,
+        dst_dir,
+        patcher::Syntax::default(),
+        false,
+        &indicatif::ProgressBar::hidden(),
+    )
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{fs, os::unix::fs::PermissionsExt};
+    use std::{ffi, fs, os::unix::fs::PermissionsExt};
 
     use tempfile::tempdir;
 
@@ -173,6 +417,78 @@ mod tests {
         );
     }
 
+    #[test]
+    fn patch_proto_reports_every_failure_in_stable_order() {
+        let src_dir = tempdir().expect("Failed to create a test source directory");
+
+        ["b.proto", "a.proto", "c.proto"]
+            .into_iter()
+            .for_each(|file| {
+                let path = src_dir.path().join(file);
+                fs::File::create_new(&path).expect("Failed to create a test protobuf file");
+                fs::set_permissions(path, fs::Permissions::from_mode(0o000))
+                    .expect("Failed to set permissions on the test proto file");
+            });
+
+        let dst_dir = tempdir().expect("Failed to create a test destination directory");
+
+        let err = super::patch_protos(src_dir.path(), dst_dir.path())
+            .expect_err("Patcher didn't fail given unreadable proto files");
+
+        let super::Error::Multiple(errors) = &err else {
+            panic!("Expected `Error::Multiple`, got: {err:?}");
+        };
+        let files = errors
+            .iter()
+            .map(|e| match e {
+                super::Error::OpenSourceFile(_, path) => path.file_name(),
+                _ => panic!("Expected `Error::OpenSourceFile`, got: {e:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            ["a.proto", "b.proto", "c.proto"].map(|file| Some(ffi::OsStr::new(file))),
+            "Expected the failures of every file sorted by their path",
+        );
+
+        let err_msg = err.to_string();
+        assert!(
+            err_msg.starts_with("3 protobuf files failed:\nFailed to open the source file: "),
+            "Expected the failures to be grouped by their cause, got:\n> {err_msg}",
+        );
+    }
+
+    #[test]
+    fn patch_protos_reports_progress() {
+        let src_dir = tempdir().expect("Failed to create a test source directory");
+
+        ["a.proto", "b.proto", "README.md"]
+            .into_iter()
+            .for_each(|file| {
+                fs::write(src_dir.path().join(file), "syntax = \"proto3\";\n")
+                    .expect("Failed to create a test file")
+            });
+
+        let dst_dir = tempdir().expect("Failed to create a test destination directory");
+        let progress = indicatif::ProgressBar::hidden();
+
+        super::patch_protos_with_progress(
+            &[src_dir.path().to_path_buf()],
+            &super::exclude::Exclusions::default(),
+            dst_dir.path(),
+            super::patcher::Syntax::default(),
+            false,
+            &progress,
+        )
+        .expect("Failed to patch the test protobuf files");
+
+        assert_eq!(
+            (progress.position(), progress.length()),
+            (2, Some(2)),
+            "Expected every protobuf file to be counted",
+        );
+    }
+
     #[test]
     fn patch_proto_fails_if_it_can_not_create_a_patched_file() {
         let src_dir = tempdir().expect("Failed to create a test source directory");
@@ -239,17 +555,21 @@ message Foo {
 
         let dst_dir = tempdir().expect("Failed to create a test destination directory");
 
-        let result = super::patch_protos(src_dir.path(), dst_dir.path())
+        let report = super::patch_protos(src_dir.path(), dst_dir.path())
             .expect("Patcher failed to process proto files");
 
         assert_eq!(
-            result.len(),
-            1,
-            "Expected result to contain a single patched proto file path"
+            report.protos,
+            vec![super::PatchedProto {
+                source: src_dir.path().join("test.proto"),
+                destination: dst_dir.path().join("test.proto"),
+                outcome: super::Outcome::Replaced("2023".to_string()),
+            }],
+            "Expected the report to contain a single patched proto file"
         );
 
-        let patched =
-            fs::read_to_string(&result[0]).expect("Failed to read the patched proto filed");
+        let patched = fs::read_to_string(&report.protos[0].destination)
+            .expect("Failed to read the patched proto filed");
 
         assert_eq!(
             patched,
@@ -263,4 +583,234 @@ message Foo {
             "The patched file content is invalid"
         );
     }
+
+    #[test]
+    fn patch_proto_returns_files_in_stable_order() {
+        let src_dir = tempdir().expect("Failed to create a test source directory");
+
+        ["zoidberg.proto", "crabs/ferris.proto", "abby.proto"]
+            .into_iter()
+            .for_each(|file| {
+                let path = src_dir.path().join(file);
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent).expect("Failed to create a test subdirectory");
+                }
+
+                fs::write(path, "syntax = \"proto3\";\n")
+                    .expect("Failed to create a test protobuf file");
+            });
+
+        let dst_dir = tempdir().expect("Failed to create a test destination directory");
+
+        let report = super::patch_protos(src_dir.path(), dst_dir.path())
+            .expect("Patcher failed to process proto files");
+
+        assert_eq!(
+            report.destinations().collect::<Vec<_>>(),
+            vec![
+                dst_dir.path().join("abby.proto").as_path(),
+                dst_dir.path().join("crabs/ferris.proto").as_path(),
+                dst_dir.path().join("zoidberg.proto").as_path(),
+            ],
+            "Expected the patched files to be sorted by their path"
+        );
+    }
+
+    #[test]
+    fn patch_source_roots_merges_every_root() {
+        let proto_dir = tempdir().expect("Failed to create a test source directory");
+        let vendor_dir = tempdir().expect("Failed to create a test source directory");
+
+        [
+            (proto_dir.path(), "crabs/ferris.proto"),
+            (vendor_dir.path(), "thirdparty/sponge_bob.proto"),
+            (vendor_dir.path(), "crabs/ferris.proto"),
+        ]
+        .into_iter()
+        .for_each(|(root, file)| {
+            let path = root.join(file);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).expect("Failed to create a test subdirectory");
+            }
+
+            fs::write(path, "syntax = \"proto3\";\n")
+                .expect("Failed to create a test protobuf file");
+        });
+
+        let dst_dir = tempdir().expect("Failed to create a test destination directory");
+
+        let err = super::patch_source_roots(
+            &[
+                proto_dir.path().to_path_buf(),
+                vendor_dir.path().to_path_buf(),
+            ],
+            &super::exclude::Exclusions::default(),
+            dst_dir.path(),
+        );
+        assert!(
+            matches!(
+                &err,
+                Err(super::Error::DuplicateProto(path, root))
+                    if path == &vendor_dir.path().join("crabs/ferris.proto") && root == proto_dir.path()
+            ),
+            "Expected `Err(Error::DuplicateProto)`, got: `{:?}`",
+            err
+        );
+
+        fs::remove_file(vendor_dir.path().join("crabs/ferris.proto"))
+            .expect("Failed to remove a test protobuf file");
+        let dst_dir = tempdir().expect("Failed to create a test destination directory");
+
+        let report = super::patch_source_roots(
+            &[
+                proto_dir.path().to_path_buf(),
+                vendor_dir.path().to_path_buf(),
+            ],
+            &super::exclude::Exclusions::default(),
+            dst_dir.path(),
+        )
+        .expect("Patcher failed to process proto files");
+
+        assert_eq!(
+            report
+                .protos
+                .iter()
+                .map(|proto| (proto.source.as_path(), proto.destination.as_path()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    proto_dir.path().join("crabs/ferris.proto").as_path(),
+                    dst_dir.path().join("crabs/ferris.proto").as_path(),
+                ),
+                (
+                    vendor_dir
+                        .path()
+                        .join("thirdparty/sponge_bob.proto")
+                        .as_path(),
+                    dst_dir.path().join("thirdparty/sponge_bob.proto").as_path(),
+                ),
+            ],
+            "Expected every root to be stripped from its own files"
+        );
+    }
+
+    #[test]
+    fn patch_source_roots_skips_the_excluded_paths() {
+        let src_dir = tempdir().expect("Failed to create a test source directory");
+
+        [
+            "crabs/ferris.proto",
+            "crabs/internal/secret.proto",
+            "fixtures/broken.proto",
+        ]
+        .into_iter()
+        .for_each(|file| {
+            let path = src_dir.path().join(file);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).expect("Failed to create a test subdirectory");
+            }
+
+            fs::write(path, "syntax = \"proto3\";\n")
+                .expect("Failed to create a test protobuf file");
+        });
+
+        let dst_dir = tempdir().expect("Failed to create a test destination directory");
+        let exclusions = super::exclude::Exclusions::new(&["**/internal/**", "fixtures"])
+            .expect("Failed to parse the exclusion patterns");
+
+        let report =
+            super::patch_source_roots(&[src_dir.path().to_path_buf()], &exclusions, dst_dir.path())
+                .expect("Patcher failed to process proto files");
+
+        assert_eq!(
+            report.destinations().collect::<Vec<_>>(),
+            vec![dst_dir.path().join("crabs/ferris.proto").as_path()],
+            "Expected the excluded files to be skipped"
+        );
+        assert!(
+            !dst_dir.path().join("fixtures").exists(),
+            "Expected the excluded directory not to be descended into"
+        );
+    }
+
+    #[test]
+    fn patch_source_roots_skips_the_ignored_paths() {
+        let src_dir = tempdir().expect("Failed to create a test source directory");
+
+        [
+            ("crabs/ferris.proto", "syntax = \"proto3\";\n"),
+            ("crabs/experimental/ferris.proto", "syntax = \"proto3\";\n"),
+            ("vendor/google.proto", "syntax = \"proto3\";\n"),
+            (".pbuildrsignore", "/vendor/\n"),
+            ("crabs/.pbuildrsignore", "experimental/\n"),
+        ]
+        .into_iter()
+        .for_each(|(file, content)| {
+            let path = src_dir.path().join(file);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).expect("Failed to create a test subdirectory");
+            }
+
+            fs::write(path, content).expect("Failed to create a test file");
+        });
+
+        let dst_dir = tempdir().expect("Failed to create a test destination directory");
+        let report = super::patch_protos(src_dir.path(), dst_dir.path())
+            .expect("Patcher failed to process proto files");
+
+        assert_eq!(
+            report.destinations().collect::<Vec<_>>(),
+            vec![dst_dir.path().join("crabs/ferris.proto").as_path()],
+            "Expected the ignored files to be skipped"
+        );
+        assert!(
+            !dst_dir.path().join("vendor").exists(),
+            "Expected the ignored directory not to be descended into"
+        );
+    }
+
+    #[test]
+    fn patch_source_roots_follows_symlinks_only_when_asked() {
+        let shared_dir = tempdir().expect("Failed to create a test shared directory");
+        fs::write(
+            shared_dir.path().join("ferris.proto"),
+            "syntax = \"proto3\";\n",
+        )
+        .expect("Failed to create a test protobuf file");
+
+        let src_dir = tempdir().expect("Failed to create a test source directory");
+        fs::create_dir(src_dir.path().join("crabs")).expect("Failed to create a test subdirectory");
+        std::os::unix::fs::symlink(shared_dir.path(), src_dir.path().join("crabs/shared"))
+            .expect("Failed to symlink the shared directory");
+        std::os::unix::fs::symlink(src_dir.path(), src_dir.path().join("crabs/loop"))
+            .expect("Failed to symlink the source directory");
+
+        let patch = |follow_symlinks| {
+            let dst_dir = tempdir().expect("Failed to create a test destination directory");
+            let exclusions = super::exclude::Exclusions::default().follow_symlinks(follow_symlinks);
+            let report = super::patch_source_roots(
+                &[src_dir.path().to_path_buf()],
+                &exclusions,
+                dst_dir.path(),
+            )
+            .expect("Patcher failed to process proto files");
+
+            report
+                .protos
+                .into_iter()
+                .map(|proto| proto.source)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            patch(false),
+            Vec::<std::path::PathBuf>::new(),
+            "Expected the symlinked directory not to be descended into"
+        );
+        assert_eq!(
+            patch(true),
+            vec![src_dir.path().join("crabs/shared/ferris.proto")],
+            "Expected the symlinked directory to be descended into, skipping the loop"
+        );
+    }
 }
@@ -0,0 +1,240 @@
+use std::{fmt, path};
+
+use crate::{modgen, naming, reexports, services};
+
+/// What kind of protobuf definition a [`Location`] points at.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Kind {
+    Message,
+    Enum,
+    Service,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Message => "message",
+            Self::Enum => "enum",
+            Self::Service => "service",
+        })
+    }
+}
+
+/// Where the code of a protobuf definition is generated.
+#[derive(PartialEq, Debug)]
+pub struct Location {
+    pub kind: Kind,
+    /// The Rust paths of the generated types from the root module of the output, e.g.
+    /// `crabs::ferris::Claw`. A service has both its client and its server trait.
+    pub rust_paths: Vec<String>,
+    /// The module file the code is generated into.
+    pub module_file: path::PathBuf,
+    /// The protobuf file defining it, as recorded in the file descriptor set.
+    pub proto_file: String,
+}
+
+/// The kind of the definition at the `names` within the `messages` and the `enums`, e.g.
+/// `["Ferris", "Claw"]`.
+fn find_type(
+    messages: &[prost_types::DescriptorProto],
+    enums: &[prost_types::EnumDescriptorProto],
+    names: &[&str],
+) -> Option<Kind> {
+    match names {
+        [] => None,
+        [name] => enums
+            .iter()
+            .find(|item| item.name() == *name)
+            .map(|_| Kind::Enum)
+            .or_else(|| {
+                messages
+                    .iter()
+                    .find(|message| message.name() == *name)
+                    .map(|_| Kind::Message)
+            }),
+        [name, rest @ ..] => {
+            let message = messages.iter().find(|message| message.name() == *name)?;
+
+            find_type(&message.nested_type, &message.enum_type, rest)
+        }
+    }
+}
+
+/// The Rust paths from the root module of the output of the definition of the `kind` at the
+/// `names` within the protobuf `package`, e.g. `["Ferris", "Claw"]`, following the `naming` rules.
+pub(crate) fn rust_paths(
+    naming: &naming::Naming,
+    package: &str,
+    kind: Kind,
+    names: &[&str],
+) -> Vec<String> {
+    let Some((name, parents)) = names.split_last() else {
+        return vec![];
+    };
+    let module = reexports::module_path(naming, package)
+        .into_iter()
+        .chain(parents.iter().map(|parent| services::to_snake(parent)))
+        .collect::<Vec<_>>();
+    let path = |ident: &str| {
+        module
+            .iter()
+            .map(String::as_str)
+            .chain([ident])
+            .collect::<Vec<_>>()
+            .join("::")
+    };
+
+    match kind {
+        Kind::Service => {
+            let codegen = services::Codegen {
+                compile_well_known_types: false,
+                extern_paths: &[],
+                default_stubs: false,
+            };
+            let descriptor = prost_types::ServiceDescriptorProto {
+                name: Some(name.to_string()),
+                ..Default::default()
+            };
+            let service = services::Service::new(package, &descriptor, &codegen);
+
+            [service.client(), service.server()]
+                .iter()
+                .map(|ident| path(ident.trim_start_matches("super::")))
+                .collect()
+        }
+        Kind::Message | Kind::Enum => vec![path(&services::to_upper_camel(name))],
+    }
+}
+
+/// Find the message, the enum or the service with the fully qualified protobuf `name`, e.g.
+/// `crabs.Ferris.Claw`, in the `fds`, along with where its code is generated into the `output`
+/// following the `naming` rules, the `packages` mapped to other output directories and the
+/// `layout` of the module tree.
+pub fn find(
+    fds: &prost_types::FileDescriptorSet,
+    name: &str,
+    naming: &naming::Naming,
+    output: &path::Path,
+    packages: &[modgen::PackageOutput],
+    layout: modgen::Layout,
+) -> Option<Location> {
+    let name = name.trim_start_matches('.');
+
+    fds.file.iter().find_map(|file| {
+        let rest = match file.package() {
+            "" => name,
+            package => name.strip_prefix(package)?.strip_prefix('.')?,
+        };
+        let names = rest.split('.').collect::<Vec<_>>();
+
+        let kind = match names.as_slice() {
+            [service] if file.service.iter().any(|item| item.name() == *service) => Kind::Service,
+            _ => find_type(&file.message_type, &file.enum_type, &names)?,
+        };
+        let package = naming.package(file.package());
+        let module_file = modgen::module_file(output, packages, &package, layout);
+
+        Some(Location {
+            kind,
+            rust_paths: rust_paths(naming, file.package(), kind, &names),
+            module_file,
+            proto_file: file.name().to_string(),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path;
+
+    fn fds() -> prost_types::FileDescriptorSet {
+        let message = |name: &str, nested_type| prost_types::DescriptorProto {
+            name: Some(name.to_string()),
+            nested_type,
+            ..Default::default()
+        };
+
+        prost_types::FileDescriptorSet {
+            file: vec![
+                prost_types::FileDescriptorProto {
+                    name: Some("crabs/ferris.proto".to_string()),
+                    package: Some("corp.crabs.v1beta1".to_string()),
+                    message_type: vec![message("Ferris", vec![message("Claw", vec![])])],
+                    enum_type: vec![prost_types::EnumDescriptorProto {
+                        name: Some("Shell".to_string()),
+                        ..Default::default()
+                    }],
+                    service: vec![crate::services::tests::service()],
+                    ..Default::default()
+                },
+                prost_types::FileDescriptorProto {
+                    name: Some("root.proto".to_string()),
+                    message_type: vec![message("Sand", vec![])],
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn find_locates_the_generated_code() {
+        let naming = crate::naming::Naming {
+            strip_prefixes: vec!["corp".to_string()],
+            renames: [("v1beta1".to_string(), "v1_beta1".to_string())].into(),
+        };
+        let output = path::Path::new("out");
+        let find = |name| {
+            super::find(
+                &fds(),
+                name,
+                &naming,
+                output,
+                &[],
+                crate::modgen::Layout::Tree,
+            )
+        };
+
+        assert_eq!(
+            find(".corp.crabs.v1beta1.Ferris.Claw"),
+            Some(super::Location {
+                kind: super::Kind::Message,
+                rust_paths: vec!["crabs::v1_beta1::ferris::Claw".to_string()],
+                module_file: path::PathBuf::from("out/crabs/v1_beta1/mod.rs"),
+                proto_file: "crabs/ferris.proto".to_string(),
+            }),
+        );
+        assert_eq!(
+            find("corp.crabs.v1beta1.Shell").map(|location| location.kind),
+            Some(super::Kind::Enum),
+        );
+        assert_eq!(
+            find("corp.crabs.v1beta1.CrabService").map(|location| location.rust_paths),
+            Some(vec![
+                "crabs::v1_beta1::crab_service_client::CrabServiceClient".to_string(),
+                "crabs::v1_beta1::crab_service_server::CrabService".to_string(),
+            ]),
+        );
+        assert_eq!(
+            find("Sand").map(|location| (location.rust_paths, location.module_file)),
+            Some((vec!["Sand".to_string()], path::PathBuf::from("out/mod.rs"))),
+        );
+        assert_eq!(find("corp.crabs.v1beta1.Ferris.Leg"), None);
+
+        let packages = [crate::modgen::PackageOutput::new("crabs", "crabs-out")
+            .expect("Failed to create a package output")];
+        assert_eq!(
+            super::find(
+                &fds(),
+                "corp.crabs.v1beta1.Shell",
+                &naming,
+                output,
+                &packages,
+                crate::modgen::Layout::SingleFile,
+            )
+            .map(|location| location.module_file),
+            Some(path::PathBuf::from("crabs-out/generated.rs")),
+            "Expected the single file of the mapped output",
+        );
+        assert_eq!(find("corp.crabs.Ferris"), None);
+    }
+}
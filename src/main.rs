@@ -2,12 +2,13 @@ use clap::Parser;
 use pbuildrs::cli;
 use std::process;
 
-fn main() {
-    let args = cli::Args::parse();
+fn main() -> process::ExitCode {
+    match cli::Cli::parse().run() {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{e}");
 
-    if let Err(e) = pbuildrs::cli::run(args) {
-        eprintln!("{e}");
-
-        process::exit(1);
+            process::ExitCode::FAILURE
+        }
     }
 }
@@ -0,0 +1,321 @@
+use std::{fs, io, path};
+
+use crate::{modgen, semver, verify};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("The output directory `{0}` is not named after a valid crate name")]
+    InvalidName(path::PathBuf),
+    #[error("Failed to render the lints of the crate: {0}")]
+    Lints(toml::ser::Error),
+    #[error("Failed to write the crate manifest `{1}`: {0}")]
+    Write(io::Error, path::PathBuf),
+    #[error("Failed to read the crate root `{1}`: {0}")]
+    ReadRoot(io::Error, path::PathBuf),
+    #[error("Failed to write the crate root `{1}`: {0}")]
+    WriteRoot(io::Error, path::PathBuf),
+}
+
+/// The package metadata, the lints and the crate-level attributes of the generated crates, so
+/// they can be published as they are.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Default, PartialEq, Debug)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Metadata {
+    /// The version of the crates, `0.1.0` unless set.
+    pub version: Option<semver::Version>,
+    pub description: Option<String>,
+    /// An SPDX license expression, e.g. `MIT OR Apache-2.0`.
+    pub license: Option<String>,
+    pub repository: Option<String>,
+    /// Whether the crate can be published, `false` keeping it from being published by mistake.
+    pub publish: Option<bool>,
+    /// The `[lints]` table of the manifest, e.g. `rust.unsafe_code = "forbid"`.
+    #[serde(default)]
+    pub lints: toml::Table,
+    /// The inner attributes the crate root starts with, e.g. `#![forbid(unsafe_code)]`.
+    #[serde(default)]
+    pub attributes: Vec<String>,
+}
+
+impl Metadata {
+    /// The fields of the `[package]` table.
+    fn package(&self) -> String {
+        let string = |key: &str, value: &Option<String>| {
+            value
+                .as_ref()
+                .map(|value| format!("{key} = {}\n", toml::Value::String(value.clone())))
+                .unwrap_or_default()
+        };
+
+        [
+            string("description", &self.description),
+            string("license", &self.license),
+            string("repository", &self.repository),
+            self.publish
+                .map(|publish| format!("publish = {publish}\n"))
+                .unwrap_or_default(),
+        ]
+        .concat()
+    }
+
+    /// The `[lints]` table, if there are any lints.
+    fn lints(&self) -> Result<String, Error> {
+        #[derive(serde::Serialize)]
+        struct Lints<'a> {
+            lints: &'a toml::Table,
+        }
+
+        match self.lints.is_empty() {
+            true => Ok(String::new()),
+            false => toml::to_string(&Lints { lints: &self.lints })
+                .map(|lints| format!("\n{lints}"))
+                .map_err(Error::Lints),
+        }
+    }
+}
+
+/// The manifest of the Cargo package written into every output with the crate layout.
+pub const FILE: &str = "Cargo.toml";
+
+/// The version of the generated crates, unless set in their [`Metadata`].
+const VERSION: &str = "0.1.0";
+
+/// The crate name of the `output` directory, its name with any characters not allowed in a crate
+/// name replaced with dashes.
+fn name(output: &path::Path) -> Result<String, Error> {
+    let name = output
+        .file_name()
+        .map(|name| {
+            name.to_string_lossy()
+                .chars()
+                .map(|c| match c.is_ascii_alphanumeric() || c == '_' {
+                    true => c,
+                    false => '-',
+                })
+                .collect::<String>()
+        })
+        .filter(|name| name.starts_with(|c: char| c.is_ascii_alphabetic()))
+        .ok_or_else(|| Error::InvalidName(output.to_path_buf()))?;
+
+    Ok(name)
+}
+
+/// Render the manifest of the crate generated into the `output`, named after the directory and
+/// recording the `rust_version`, with the `metadata`, so it can be published or depended on
+/// by path, depending on `tonic` and on `serde` as needed by the generated code.
+pub fn render(
+    output: &path::Path,
+    rust_version: Option<&verify::RustVersion>,
+    metadata: &Metadata,
+    tonic: verify::Tonic,
+    serde: verify::Serde,
+) -> Result<String, Error> {
+    Ok(format!(
+        r#"# This file is @generated by pbuildrs.
+[package]
+name = "{name}"
+version = "{version}"
+edition = "{edition}"
+{rust_version}{package}
+{dependencies}{lints}"#,
+        name = name(output)?,
+        version = metadata
+            .version
+            .as_ref()
+            .map_or_else(|| VERSION.to_string(), ToString::to_string),
+        edition = verify::EDITION,
+        rust_version = rust_version
+            .map(|version| format!("rust-version = \"{version}\"\n"))
+            .unwrap_or_default(),
+        package = metadata.package(),
+        dependencies = verify::dependencies(tonic, serde),
+        lints = metadata.lints()?,
+    ))
+}
+
+/// Write the `manifest` rendered by [`render`] into the `dst` directory.
+pub fn write(dst: &path::Path, manifest: &str) -> Result<path::PathBuf, Error> {
+    let path = dst.join(FILE);
+    tracing::debug!("Writing the crate manifest: {}", path.display());
+    fs::write(&path, manifest).map_err(|e| Error::Write(e, path.clone()))?;
+
+    Ok(path)
+}
+
+/// Start the crate `root` with the inner `attributes`, right after its `header`.
+pub fn write_attributes(
+    root: &path::Path,
+    header: &modgen::Header,
+    attributes: &[String],
+) -> Result<(), Error> {
+    if attributes.is_empty() {
+        return Ok(());
+    }
+
+    let code = fs::read_to_string(root).map_err(|e| Error::ReadRoot(e, root.to_path_buf()))?;
+    let code = code.strip_prefix(header.as_str()).unwrap_or(&code);
+    let attributes = attributes
+        .iter()
+        .map(|attribute| format!("{attribute}\n"))
+        .collect::<String>();
+
+    fs::write(root, format!("{}{attributes}\n{code}", header.as_str()))
+        .map_err(|e| Error::WriteRoot(e, root.to_path_buf()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path};
+
+    #[test]
+    fn render_names_the_crate_after_the_output() {
+        let manifest = super::render(
+            path::Path::new("crates/billing.proto"),
+            None,
+            &super::Metadata::default(),
+            crate::verify::Tonic::Transport,
+            crate::verify::Serde::Disabled,
+        )
+        .expect("Failed to render the manifest");
+
+        assert!(
+            manifest.contains("[package]\nname = \"billing-proto\"\nversion = \"0.1.0\"\n"),
+            "Expected the crate to be named after the output, got:\n{manifest}",
+        );
+        assert!(
+            manifest.contains("client = [\"dep:tonic\", \"dep:tonic-prost\"]\n"),
+            "Expected the client feature, got:\n{manifest}",
+        );
+        assert!(
+            !manifest.contains("[workspace]"),
+            "Expected the crate to join the workspace it is in, got:\n{manifest}",
+        );
+
+        let version = "1.85".parse().expect("Failed to parse a Rust version");
+        let manifest = super::render(
+            path::Path::new("crabs"),
+            Some(&version),
+            &super::Metadata::default(),
+            crate::verify::Tonic::Transport,
+            crate::verify::Serde::Disabled,
+        )
+        .expect("Failed to render the manifest");
+        assert!(
+            manifest.contains("edition = \"2024\"\nrust-version = \"1.85\"\n"),
+            "Expected the Rust version to be recorded, got:\n{manifest}",
+        );
+
+        ["2024-protos", ".", ""].into_iter().for_each(|output| {
+            let result = super::render(
+                path::Path::new(output),
+                None,
+                &super::Metadata::default(),
+                crate::verify::Tonic::Transport,
+                crate::verify::Serde::Disabled,
+            );
+            assert!(
+                matches!(result, Err(super::Error::InvalidName(_))),
+                "Expected `Err(Error::InvalidName(_))` for `{output}`, got: `{result:?}`",
+            );
+        });
+    }
+
+    #[test]
+    fn render_leaves_out_tonic_for_the_messages() {
+        let manifest = super::render(
+            path::Path::new("crabs"),
+            None,
+            &super::Metadata::default(),
+            crate::verify::Tonic::Disabled,
+            crate::verify::Serde::Disabled,
+        )
+        .expect("Failed to render the manifest");
+
+        assert!(
+            manifest.contains("\n[dependencies]\nprost = "),
+            "Expected the prost dependencies, got:\n{manifest}",
+        );
+        assert!(
+            !manifest.contains("[features]") && !manifest.contains("tonic"),
+            "Expected no features or tonic dependencies, got:\n{manifest}",
+        );
+        toml::from_str::<toml::Table>(&manifest).expect("Expected a valid manifest");
+    }
+
+    #[test]
+    fn render_depends_on_serde_for_the_derives() {
+        let manifest = super::render(
+            path::Path::new("crabs"),
+            None,
+            &super::Metadata::default(),
+            crate::verify::Tonic::Disabled,
+            crate::verify::Serde::Derive,
+        )
+        .expect("Failed to render the manifest");
+
+        assert!(
+            manifest.contains("\nserde = { version = \"1\", features = [\"derive\"] }\n"),
+            "Expected the serde dependency, got:\n{manifest}",
+        );
+        toml::from_str::<toml::Table>(&manifest).expect("Expected a valid manifest");
+    }
+
+    #[test]
+    fn render_describes_the_crate_with_the_metadata() {
+        let metadata = super::Metadata {
+            version: Some("1.2.3".parse().expect("Failed to parse a version")),
+            description: Some("The \"crabs\" protos".to_string()),
+            license: Some("MIT OR Apache-2.0".to_string()),
+            publish: Some(false),
+            lints: toml::from_str("rust.unsafe_code = \"forbid\"\nclippy.all = \"allow\"")
+                .expect("Failed to parse the test lints"),
+            ..super::Metadata::default()
+        };
+
+        let manifest = super::render(
+            path::Path::new("crabs"),
+            None,
+            &metadata,
+            crate::verify::Tonic::Transport,
+            crate::verify::Serde::Disabled,
+        )
+        .expect("Failed to render the manifest");
+
+        assert!(
+            manifest.contains("\nversion = \"1.2.3\"\n"),
+            "Expected the version of the crate, got:\n{manifest}",
+        );
+        assert!(
+            manifest.contains(
+                "\ndescription = 'The \"crabs\" protos'\nlicense = \"MIT OR Apache-2.0\"\n\
+                publish = false\n\n[features]\n"
+            ),
+            "Expected the package metadata, got:\n{manifest}",
+        );
+        assert!(
+            manifest.ends_with(
+                "\n\n[lints.clippy]\nall = \"allow\"\n\n[lints.rust]\nunsafe_code = \"forbid\"\n"
+            ),
+            "Expected the lints table, got:\n{manifest}",
+        );
+        toml::from_str::<toml::Table>(&manifest).expect("Expected a valid manifest");
+    }
+
+    #[test]
+    fn write_attributes_follow_the_header() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let root = dir.path().join("lib.rs");
+        let header = crate::modgen::Header::new(Some("Copyright Crabs"), None);
+        fs::write(&root, format!("{}pub mod crabs;\n", header.as_str()))
+            .expect("Failed to write a test file");
+
+        super::write_attributes(&root, &header, &["#![forbid(unsafe_code)]".to_string()])
+            .expect("Failed to write the attributes");
+
+        assert_eq!(
+            fs::read_to_string(&root).expect("Failed to read the test file"),
+            "// Copyright Crabs\n\n#![forbid(unsafe_code)]\n\npub mod crabs;\n",
+            "Expected the attributes to follow the header",
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use crate::builder;
+
+/// A machine-readable event, printed as a single line of JSON, similar to the messages of
+/// `cargo --message-format=json`.
+#[derive(serde::Serialize, PartialEq, Debug)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum Message {
+    /// The edition of the protobuf file had to be patched before compiling it.
+    ProtoPatched { path: String },
+    /// The protobuf file was compiled as is.
+    ProtoSkipped { path: String },
+    /// `protoc` rejected the protobuf file.
+    CompileError {
+        path: String,
+        line: u32,
+        column: u32,
+        message: String,
+    },
+    /// The module file was written into the output directory of the target.
+    ModuleWritten { target: String, path: String },
+    /// The module file would be written by a dry run.
+    ModulePlanned { target: String, path: String },
+    /// The existing output differs from the freshly generated code.
+    OutputChanged { path: String, change: String },
+    /// Any other failure.
+    Error { message: String },
+    /// The run is over, always the last message.
+    BuildFinished { success: bool },
+}
+
+/// The messages describing the outcome of a [`builder::Builder::build`] run.
+pub fn from_report(report: &builder::Report, dry_run: bool) -> Vec<Message> {
+    let protos = report.protos.iter().map(|proto| {
+        let path = proto.source.display().to_string();
+
+        match proto.outcome {
+            crate::Outcome::Replaced(_) => Message::ProtoPatched { path },
+            crate::Outcome::Untouched => Message::ProtoSkipped { path },
+        }
+    });
+
+    let modules = report.targets.iter().flat_map(|target| {
+        target.files.iter().map(|file| {
+            let target = target.name.clone();
+            let path = file.display().to_string();
+
+            if dry_run {
+                Message::ModulePlanned { target, path }
+            } else {
+                Message::ModuleWritten { target, path }
+            }
+        })
+    });
+
+    protos.chain(modules).collect()
+}
+
+/// Split a `path:line:column` location into its parts.
+fn location(location: &str) -> Option<(&str, u32, u32)> {
+    let mut parts = location.rsplitn(3, ':');
+    let column = parts.next()?.parse().ok()?;
+    let line = parts.next()?.parse().ok()?;
+
+    Some((parts.next()?, line, column))
+}
+
+/// Extract the `path:line:column: message` diagnostics from the `output` of `protoc`, which may be
+/// prefixed with the context of the error.
+pub fn compile_errors(output: &str) -> Vec<Message> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let segments = line.split(": ").collect::<Vec<_>>();
+            let (idx, (path, line, column)) = segments
+                .iter()
+                .enumerate()
+                .find_map(|(idx, segment)| Some((idx, location(segment)?)))?;
+
+            Some(Message::CompileError {
+                path: path.to_string(),
+                line,
+                column,
+                message: segments[idx + 1..].join(": "),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn compile_errors_parses_protoc_output() {
+        let messages = super::compile_errors(
+            "Failed to compile the proto file: protoc failed: proto/crabs/Ferris.proto:4:3: \"strin\" is not defined.
+proto/crabs/Ferris.proto:7:1: Expected \"}\".
+crabs/Ferris.proto: Import \"missing.proto\" was not found or had errors.",
+        );
+
+        assert_eq!(
+            messages,
+            vec![
+                super::Message::CompileError {
+                    path: "proto/crabs/Ferris.proto".to_string(),
+                    line: 4,
+                    column: 3,
+                    message: "\"strin\" is not defined.".to_string(),
+                },
+                super::Message::CompileError {
+                    path: "proto/crabs/Ferris.proto".to_string(),
+                    line: 7,
+                    column: 1,
+                    message: "Expected \"}\".".to_string(),
+                },
+            ],
+            "Invalid compile errors",
+        );
+    }
+
+    #[test]
+    fn message_serializes_with_a_reason() {
+        let json = serde_json::to_string(&super::Message::ModuleWritten {
+            target: "default".to_string(),
+            path: "out/crabs/mod.rs".to_string(),
+        })
+        .expect("Failed to serialize the message");
+
+        assert_eq!(
+            json, r#"{"reason":"module-written","target":"default","path":"out/crabs/mod.rs"}"#,
+            "Invalid serialized message",
+        );
+    }
+}
@@ -1,14 +1,15 @@
+use crate::{diagnostics, exclude, services};
+use rayon::prelude::*;
 use std::{
-    collections, ffi, fs,
+    cmp, collections, ffi, fs,
     io::{self, Write},
-    os::unix::ffi::{OsStrExt, OsStringExt},
-    path,
+    path, str,
 };
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("Failed to read the directory containing generated Rust source files: {0}")]
-    ReadSourceDir(#[from] walkdir::Error),
+    #[error("Failed to read the directory containing generated Rust source files `{1}`: {0}")]
+    ReadSourceDir(ignore::Error, path::PathBuf),
     #[error("Failed to extract the file name from {0}")]
     FileName(path::PathBuf),
     #[error("Failed to create the module directory `{1}`: {0}")]
@@ -19,6 +20,443 @@ pub enum Error {
     WriteModFile(io::Error, path::PathBuf),
     #[error("Failed to read the source file `{1}`: {0}")]
     ReadSourceFile(io::Error, path::PathBuf),
+    #[error("The generated files `{0}` and `{1}` map to the same module")]
+    ConflictingFiles(path::PathBuf, path::PathBuf),
+    #[error(
+        "The protobuf files `{}` and `{}` map to the same module",
+        .0.join("`, `"),
+        .1.join("`, `")
+    )]
+    ConflictingProtos(Vec<String>, Vec<String>),
+    #[error("The generated file `{0}` belongs to none of the protobuf packages")]
+    UnknownPackage(path::PathBuf),
+    #[error("The package output `{0}` matches none of the generated packages")]
+    UnmatchedPackage(String),
+    #[error("{} module files failed:\n{}", .0.len(), Error::diagnostics(.0))]
+    Multiple(Vec<Error>),
+}
+
+impl Error {
+    /// Add the cause of the error, without the path of the affected file, to the `diagnostics`
+    /// under that path, so the same failure hitting many modules is reported once.
+    fn report(&self, diagnostics: &diagnostics::Diagnostics) {
+        match self {
+            Self::ReadSourceDir(e, path) => diagnostics.push(
+                format!("Failed to read the directory containing generated Rust source files: {e}"),
+                path,
+            ),
+            Self::FileName(path) => diagnostics.push("Failed to extract the file name", path),
+            Self::MkModDir(e, path) => {
+                diagnostics.push(format!("Failed to create the module directory: {e}"), path)
+            }
+            Self::MkModFile(e, path) => {
+                diagnostics.push(format!("Failed to create the module file: {e}"), path)
+            }
+            Self::WriteModFile(e, path) => {
+                diagnostics.push(format!("Failed to write the module file: {e}"), path)
+            }
+            Self::ReadSourceFile(e, path) => {
+                diagnostics.push(format!("Failed to read the source file: {e}"), path)
+            }
+            Self::ConflictingFiles(first, path) => diagnostics.push(
+                format!("Maps to the same module as `{}`", first.display()),
+                path,
+            ),
+            Self::ConflictingProtos(first, protos) => diagnostics.push(
+                format!("Maps to the same module as `{}`", first.join("`, `")),
+                protos.join(", "),
+            ),
+            Self::UnknownPackage(path) => {
+                diagnostics.push("Belongs to none of the protobuf packages", path)
+            }
+            Self::UnmatchedPackage(package) => {
+                diagnostics.push("Matches none of the generated packages", package)
+            }
+            Self::Multiple(errors) => errors.iter().for_each(|e| e.report(diagnostics)),
+        }
+    }
+
+    fn diagnostics(errors: &[Error]) -> diagnostics::Diagnostics {
+        let diagnostics = diagnostics::Diagnostics::default();
+        errors.iter().for_each(|e| e.report(&diagnostics));
+
+        diagnostics
+    }
+
+    /// A single error, or all of the `errors` together.
+    fn collect(mut errors: Vec<Error>) -> Option<Self> {
+        match errors.len() {
+            0 => None,
+            1 => errors.pop(),
+            _ => Some(Self::Multiple(errors)),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PackageOutputError {
+    #[error("Expected a package output in the `<package>=<directory>` format, got `{0}`")]
+    Format(String),
+    #[error("Invalid protobuf package `{0}`")]
+    Package(String),
+}
+
+/// Places the modules of a protobuf package, and every package nested in it, into a separate
+/// output directory instead of the main module tree.
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Debug)]
+#[serde(try_from = "RawPackageOutput", into = "RawPackageOutput")]
+pub struct PackageOutput {
+    package: Vec<String>,
+    output: path::PathBuf,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawPackageOutput {
+    package: String,
+    output: path::PathBuf,
+}
+
+impl PackageOutput {
+    /// Map the `package`, optionally followed by `.*`, to the `output` directory.
+    pub fn new(
+        package: &str,
+        output: impl Into<path::PathBuf>,
+    ) -> Result<Self, PackageOutputError> {
+        let parts = package
+            .strip_suffix(".*")
+            .unwrap_or(package)
+            .split('.')
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+
+        if parts.iter().any(String::is_empty) {
+            return Err(PackageOutputError::Package(package.to_string()));
+        }
+
+        Ok(Self {
+            package: parts,
+            output: output.into(),
+        })
+    }
+
+    pub fn output(&self) -> &path::Path {
+        &self.output
+    }
+
+    pub(crate) fn map_output(self, f: impl FnOnce(path::PathBuf) -> path::PathBuf) -> Self {
+        Self {
+            output: f(self.output),
+            ..self
+        }
+    }
+
+    pub(crate) fn map_package(self, f: impl FnOnce(Vec<String>) -> Vec<String>) -> Self {
+        Self {
+            package: f(self.package),
+            ..self
+        }
+    }
+}
+
+impl From<PackageOutput> for RawPackageOutput {
+    fn from(value: PackageOutput) -> Self {
+        Self {
+            package: value.package.join("."),
+            output: value.output,
+        }
+    }
+}
+
+impl TryFrom<RawPackageOutput> for PackageOutput {
+    type Error = PackageOutputError;
+
+    fn try_from(value: RawPackageOutput) -> Result<Self, Self::Error> {
+        Self::new(&value.package, value.output)
+    }
+}
+
+impl str::FromStr for PackageOutput {
+    type Err = PackageOutputError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((package, output)) if !output.is_empty() => Self::new(package, output),
+            _ => Err(PackageOutputError::Format(s.to_string())),
+        }
+    }
+}
+
+/// The file the whole module tree of an output is written into with the [`Layout::SingleFile`].
+pub const SINGLE_FILE: &str = "generated.rs";
+
+/// How the module tree is laid out in an output directory.
+#[derive(
+    clap::ValueEnum, serde::Deserialize, serde::Serialize, Clone, Copy, Default, PartialEq, Debug,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    /// A directory per module, with the code of the module in its `mod.rs`
+    #[default]
+    Tree,
+    /// A single `generated.rs` with every module nested inline, to be included with `include!`
+    SingleFile,
+    /// A Cargo package, with a `Cargo.toml` and the module tree in its `src`, rooted at `lib.rs`
+    Crate,
+}
+
+impl Layout {
+    /// The name of the root module file of an output directory.
+    pub fn root_file(self) -> &'static str {
+        match self {
+            Self::Tree => "mod.rs",
+            Self::SingleFile => SINGLE_FILE,
+            Self::Crate => "lib.rs",
+        }
+    }
+
+    /// The directory the root module of the `dst` output directory is written into.
+    pub fn module_dir(self, dst: &path::Path) -> path::PathBuf {
+        match self {
+            Self::Tree | Self::SingleFile => dst.to_path_buf(),
+            Self::Crate => dst.join("src"),
+        }
+    }
+}
+
+/// The visibility of the declaration of a generated module.
+#[derive(
+    clap::ValueEnum, serde::Deserialize, serde::Serialize, Clone, Copy, Default, PartialEq, Debug,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Visibility {
+    /// `pub mod`
+    #[default]
+    Public,
+    /// `pub(crate) mod`
+    Crate,
+    /// `mod`
+    Private,
+}
+
+impl Visibility {
+    fn keyword(self) -> &'static [u8] {
+        match self {
+            Self::Public => b"pub ",
+            Self::Crate => b"pub(crate) ",
+            Self::Private => b"",
+        }
+    }
+}
+
+/// The visibility of the generated modules, the `default` one unless overridden for a package,
+/// keyed by its module path, e.g. `corp.billing`, which applies to the packages nested in it too.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Visibilities {
+    pub default: Visibility,
+    pub packages: collections::BTreeMap<String, Visibility>,
+    /// The outer attributes of the module declarations, keyed by the module path.
+    pub attributes: collections::BTreeMap<String, Vec<String>>,
+}
+
+impl Visibilities {
+    /// The visibility of the module of the package with the `parts`, from the most specific
+    /// override covering it.
+    fn of(&self, parts: &[String]) -> Visibility {
+        self.packages
+            .iter()
+            .filter(|(package, _)| {
+                let package = package.split('.').collect::<Vec<_>>();
+
+                package.len() <= parts.len() && package.iter().zip(parts).all(|(a, b)| a == b)
+            })
+            .max_by_key(|(package, _)| package.len())
+            .map_or(self.default, |(_, visibility)| *visibility)
+    }
+
+    /// Whether the items of the module of the `package` can be reached from the root module, the
+    /// module of every package it is nested in but the outermost one being visible to it.
+    pub fn is_reachable(&self, package: &str) -> bool {
+        let parts = package
+            .split('.')
+            .filter(|part| !part.is_empty())
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+
+        (2..=parts.len()).all(|len| self.of(&parts[..len]) != Visibility::Private)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error(
+    "Expected a package visibility in the `<package>=<public|crate|private>` format, got `{0}`"
+)]
+pub struct PackageVisibilityError(String);
+
+/// The visibility of the module of a protobuf package, and of the packages nested in it, e.g.
+/// `corp.billing=crate`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PackageVisibility {
+    pub package: String,
+    pub visibility: Visibility,
+}
+
+impl str::FromStr for PackageVisibility {
+    type Err = PackageVisibilityError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (package, visibility) = s
+            .split_once('=')
+            .filter(|(package, _)| !package.trim().trim_start_matches('.').is_empty())
+            .ok_or_else(|| PackageVisibilityError(s.to_string()))?;
+        let visibility = <Visibility as clap::ValueEnum>::from_str(visibility.trim(), true)
+            .map_err(|_| PackageVisibilityError(s.to_string()))?;
+
+        Ok(Self {
+            package: package.trim().trim_start_matches('.').to_string(),
+            visibility,
+        })
+    }
+}
+
+/// The comments written at the top of every module file, e.g. the license header required on
+/// every checked-in file followed by a banner marking the file as generated.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Header(String);
+
+impl Header {
+    /// The `license` header followed by the `banner`, each commented out line by line unless it
+    /// already is a comment, and separated from each other and from the code by a blank line.
+    pub fn new(license: Option<&str>, banner: Option<&str>) -> Self {
+        let comment = |text: &str| {
+            let text = text.trim_end();
+            match text.trim_start().starts_with("//") || text.trim_start().starts_with("/*") {
+                true => format!("{text}\n"),
+                false => text
+                    .lines()
+                    .map(|line| match line.trim_end() {
+                        "" => "//\n".to_string(),
+                        line => format!("// {line}\n"),
+                    })
+                    .collect(),
+            }
+        };
+
+        Self(
+            [license, banner]
+                .into_iter()
+                .flatten()
+                .filter(|text| !text.trim().is_empty())
+                .map(|text| comment(text) + "\n")
+                .collect(),
+        )
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+/// The module paths of the protobuf packages, keyed by the name of the file their code is
+/// generated into, e.g. `crabs.v1.rs` for the `["crabs", "v1"]` module.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct PackageModules(collections::BTreeMap<ffi::OsString, Vec<String>>);
+
+impl PackageModules {
+    pub fn insert(&mut self, file: impl Into<ffi::OsString>, module: Vec<String>) {
+        self.0.insert(file.into(), module);
+    }
+}
+
+impl<F: Into<ffi::OsString>> FromIterator<(F, Vec<String>)> for PackageModules {
+    fn from_iter<I: IntoIterator<Item = (F, Vec<String>)>>(iter: I) -> Self {
+        Self(
+            iter.into_iter()
+                .map(|(file, module)| (file.into(), module))
+                .collect(),
+        )
+    }
+}
+
+/// How the generated Rust source files are read into the module tree.
+#[derive(Clone, Default, Debug)]
+pub struct Input {
+    /// The options the source directory is walked with, e.g. to follow the symlinks.
+    pub walk: exclude::Exclusions,
+    /// The modules of the protobuf packages to place the generated files after, rather than
+    /// inferring the modules from the dotted file names. The files matching none of the packages
+    /// are rejected.
+    pub modules: Option<PackageModules>,
+    /// The protobuf files every generated file is compiled from, keyed by its name, to report the
+    /// files mapping to the same module by their protobuf files rather than by the generated ones.
+    pub protos: collections::BTreeMap<ffi::OsString, Vec<String>>,
+}
+
+impl From<exclude::Exclusions> for Input {
+    fn from(walk: exclude::Exclusions) -> Self {
+        Self {
+            walk,
+            modules: None,
+            protos: collections::BTreeMap::new(),
+        }
+    }
+}
+
+impl Input {
+    /// The `error` with the conflicting generated files replaced with their protobuf files, if
+    /// they are known.
+    fn relocate(&self, error: Error) -> Error {
+        let protos = |file: &path::Path| {
+            file.file_name()
+                .and_then(|file_name| self.protos.get(file_name))
+                .cloned()
+        };
+
+        match error {
+            Error::ConflictingFiles(first, second) => match (protos(&first), protos(&second)) {
+                (Some(first), Some(second)) => Error::ConflictingProtos(first, second),
+                _ => Error::ConflictingFiles(first, second),
+            },
+            e => e,
+        }
+    }
+}
+
+/// The position of a module in the tree, deciding the visibility of its child modules, along with
+/// the header of the module files.
+#[derive(Clone, Copy)]
+struct Scope<'a> {
+    package: &'a [String],
+    visibilities: &'a Visibilities,
+    header: &'a Header,
+}
+
+impl Scope<'_> {
+    fn child(&self, module: &ffi::OsStr) -> Vec<String> {
+        let mut package = self.package.to_vec();
+        package.push(module_name(module).to_string_lossy().into_owned());
+
+        package
+    }
+
+    fn visibility(&self, module: &ffi::OsStr) -> Visibility {
+        self.visibilities.of(&self.child(module))
+    }
+
+    /// The outer attributes of the declaration of the child `module`, one per line.
+    fn attributes(&self, module: &ffi::OsStr) -> String {
+        self.visibilities
+            .attributes
+            .get(&self.child(module).join("."))
+            .into_iter()
+            .flatten()
+            .map(|attribute| format!("{attribute}\n"))
+            .collect()
+    }
 }
 
 struct Tree {
@@ -47,20 +485,135 @@ impl Tree {
 
         parts.push(package.into_os_string());
 
-        self.root = self.root.push(path, parts);
+        self.root = self.root.push(path, parts)?;
+
+        Ok(self)
+    }
+
+    /// Add the generated file at the `path` as the module of the package it is generated for
+    /// according to the `modules`.
+    fn push_package(
+        mut self,
+        path: path::PathBuf,
+        modules: &PackageModules,
+    ) -> Result<Self, Error> {
+        let module = path
+            .file_name()
+            .and_then(|file_name| modules.0.get(file_name))
+            .ok_or_else(|| Error::UnknownPackage(path.to_path_buf()))?;
+        let parts = module.iter().rev().map(ffi::OsString::from).collect();
+
+        self.root = self.root.push(path, parts)?;
 
         Ok(self)
     }
 
-    fn compile(self, dst: &path::Path) -> Result<(), Error> {
-        self.root.compile(dst.to_path_buf())
+    /// Split the tree into the module trees of every output directory, the `dst` one last, along
+    /// with the package at their root, failing if a package matches none of the generated ones,
+    /// e.g. because of a typo, rather than writing an empty module into its output.
+    fn split(
+        mut self,
+        dst: &path::Path,
+        packages: &[PackageOutput],
+    ) -> Result<Vec<(path::PathBuf, Vec<String>, Node)>, Error> {
+        // Detach the most nested packages first, so they are not carried along with their parents.
+        let mut packages = packages.iter().collect::<Vec<_>>();
+        packages.sort_by_key(|package| cmp::Reverse(package.package.len()));
+
+        let mut trees = packages
+            .into_iter()
+            .map(|package| {
+                let node = self
+                    .root
+                    .detach(&package.package)
+                    .ok_or_else(|| Error::UnmatchedPackage(package.package.join(".")))?;
+
+                Ok((package.output.clone(), package.package.clone(), node))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        trees.push((dst.to_path_buf(), vec![], self.root));
+
+        Ok(trees)
+    }
+
+    fn compile(
+        self,
+        dst: &path::Path,
+        packages: &[PackageOutput],
+        layout: Layout,
+        visibilities: &Visibilities,
+        header: &Header,
+    ) -> Result<Vec<path::PathBuf>, Error> {
+        let compiled = self
+            .split(dst, packages)?
+            .into_par_iter()
+            .map(|(dst, package, node)| {
+                let scope = Scope {
+                    package: &package,
+                    visibilities,
+                    header,
+                };
+
+                match layout {
+                    Layout::Tree => node.compile(dst, "mod.rs", scope),
+                    Layout::SingleFile => node.compile_single_file(dst, scope),
+                    Layout::Crate => {
+                        node.compile(layout.module_dir(&dst), layout.root_file(), scope)
+                    }
+                }
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .fold(Compiled::default(), Compiled::merge);
+
+        match Error::collect(compiled.errors) {
+            None => Ok(compiled.files),
+            Some(e) => Err(e),
+        }
+    }
+
+    fn plan(
+        self,
+        dst: &path::Path,
+        packages: &[PackageOutput],
+        layout: Layout,
+    ) -> Result<Vec<path::PathBuf>, Error> {
+        let mut files = vec![];
+
+        self.split(dst, packages)?
+            .into_iter()
+            .for_each(|(dst, _, node)| match layout {
+                Layout::Tree => node.plan(dst, "mod.rs", &mut files),
+                Layout::SingleFile => files.push(dst.join(SINGLE_FILE)),
+                Layout::Crate => node.plan(layout.module_dir(&dst), layout.root_file(), &mut files),
+            });
+
+        Ok(files)
+    }
+}
+
+/// The module files written by [`Node::compile`], in the order of the tree, along with the failures
+/// of the modules that could not be written.
+#[derive(Default)]
+struct Compiled {
+    files: Vec<path::PathBuf>,
+    errors: Vec<Error>,
+}
+
+impl Compiled {
+    fn merge(mut self, other: Self) -> Self {
+        self.files.extend(other.files);
+        self.errors.extend(other.errors);
+
+        self
     }
 }
 
 #[derive(PartialEq, Debug)]
 struct Node {
     path: Option<path::PathBuf>,
-    children: collections::HashMap<ffi::OsString, Node>,
+    children: collections::BTreeMap<ffi::OsString, Node>,
 }
 
 impl Node {
@@ -71,105 +624,393 @@ impl Node {
         }
     }
 
-    fn push(mut self, path: path::PathBuf, mut package: Vec<ffi::OsString>) -> Node {
+    /// Add the generated file at the `path` as the module of the `package`, given by its parts in
+    /// reverse order, failing if another file already maps to the same module, e.g. the same
+    /// protobuf file found in two include roots, or packages only differing in the characters
+    /// escaped in the module names.
+    fn push(mut self, path: path::PathBuf, mut package: Vec<ffi::OsString>) -> Result<Node, Error> {
         match package.pop() {
-            None => {
-                self.path = Some(path);
+            None => match self.path {
+                Some(first) => Err(Error::ConflictingFiles(first, path)),
+                None => {
+                    self.path = Some(path);
 
-                self
-            }
+                    Ok(self)
+                }
+            },
             Some(part) if part == "_" => self.push(path, package),
             Some(part) => {
+                let part = module_name(&part);
                 let child = self.children.remove(&part).unwrap_or_else(Node::new);
 
-                self.children.insert(part, child.push(path, package));
+                self.children.insert(part, child.push(path, package)?);
 
-                self
+                Ok(self)
             }
         }
     }
 
-    fn compile(self, dst: path::PathBuf) -> Result<(), Error> {
-        fs::create_dir_all(&dst).map_err(|err| Error::MkModDir(err, dst.clone()))?;
+    /// Remove the node at the `package` path, pruning any parents left without contents.
+    fn detach(&mut self, package: &[String]) -> Option<Node> {
+        let (part, rest) = package.split_first()?;
+        let key = self
+            .children
+            .keys()
+            .find(|key| module_name(key) == part.as_str())?
+            .clone();
+
+        if rest.is_empty() {
+            return self.children.remove(&key);
+        }
 
-        let has_children = !self.children.is_empty();
+        let child = self.children.get_mut(&key)?;
+        let node = child.detach(rest);
 
-        let mut children = self.children.into_iter().try_fold(
-            vec![],
-            |mut children, (module, node)| -> Result<_, Error> {
-                node.compile(dst.join(sanitize_path(&module)))?;
+        if child.path.is_none() && child.children.is_empty() {
+            self.children.remove(&key);
+        }
 
-                children.push(module);
+        node
+    }
 
-                Ok(children)
-            },
-        )?;
+    fn plan(self, dst: path::PathBuf, file: &str, files: &mut Vec<path::PathBuf>) {
+        self.children
+            .into_iter()
+            .for_each(|(module, node)| node.plan(dst.join(module_name(&module)), "mod.rs", files));
 
-        let dst = dst.join("mod.rs");
-        let mut mod_file =
-            fs::File::create_new(&dst).map_err(|e| Error::MkModFile(e, dst.clone()))?;
+        files.push(dst.join(file));
+    }
+
+    /// Write the module tree into the `dst`, with the module itself in the `file` and the nested
+    /// ones in their `mod.rs`, carrying on with the rest of the tree when a module fails, so every
+    /// failure is reported. The sibling subtrees are written in parallel.
+    fn compile(self, dst: path::PathBuf, file: &str, scope: Scope<'_>) -> Compiled {
+        if let Err(e) = fs::create_dir_all(&dst) {
+            return Compiled {
+                files: vec![],
+                errors: vec![Error::MkModDir(e, dst)],
+            };
+        }
+
+        let (children, nodes): (Vec<_>, Vec<_>) = self.children.into_iter().unzip();
+        let mut compiled = children
+            .par_iter()
+            .zip(nodes)
+            .map(|(module, node)| {
+                let package = scope.child(module);
+                let scope = Scope {
+                    package: &package,
+                    ..scope
+                };
+
+                node.compile(dst.join(module_name(module)), "mod.rs", scope)
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .fold(Compiled::default(), Compiled::merge);
+
+        let children = children
+            .into_iter()
+            .map(|module| (scope.attributes(&module), scope.visibility(&module), module))
+            .collect();
+        match Self::write(dst.join(file), children, self.path, scope.header) {
+            Ok(file) => compiled.files.push(file),
+            Err(e) => compiled.errors.push(e),
+        }
+
+        compiled
+    }
+
+    fn write(
+        dst: path::PathBuf,
+        children: Vec<(String, Visibility, ffi::OsString)>,
+        src: Option<path::PathBuf>,
+        header: &Header,
+    ) -> Result<path::PathBuf, Error> {
+        let has_children = !children.is_empty();
+
+        let mut mod_file = io::BufWriter::new(
+            fs::File::create_new(&dst).map_err(|e| Error::MkModFile(e, dst.clone()))?,
+        );
+        mod_file
+            .write_all(header.as_bytes())
+            .map_err(|e| Error::WriteModFile(e, dst.clone()))?;
 
-        children.sort();
         children
             .into_iter()
-            .try_for_each(|module| -> Result<(), Error> {
-                mod_file
-                    .write(b"pub mod ")
-                    .map_err(|e| Error::WriteModFile(e, dst.clone()))?;
-                mod_file
-                    .write(module.as_bytes())
-                    .map_err(|e| Error::WriteModFile(e, dst.clone()))?;
+            .try_for_each(|(attributes, visibility, module)| {
+                mod_file.write_all(attributes.as_bytes())?;
+                mod_file.write_all(visibility.keyword())?;
+                mod_file.write_all(b"mod ")?;
+                mod_file.write_all(module_ident(&module).as_bytes())?;
+                mod_file.write_all(b";\n")
+            })
+            .map_err(|e| Error::WriteModFile(e, dst.clone()))?;
+
+        if let Some(src) = src {
+            let mut contents =
+                fs::File::open(&src).map_err(|e| Error::ReadSourceFile(e, src.clone()))?;
+
+            if has_children {
                 mod_file
-                    .write(b";\n")
+                    .write_all(b"\n")
                     .map_err(|e| Error::WriteModFile(e, dst.clone()))?;
+            }
+
+            // The generated code is copied byte for byte, without holding it in memory.
+            io::copy(&mut contents, &mut mod_file)
+                .map_err(|e| Error::WriteModFile(e, dst.clone()))?;
+        }
+
+        // Dropping the writer would ignore the failure to write the buffered rest of the file.
+        mod_file
+            .flush()
+            .map_err(|e| Error::WriteModFile(e, dst.clone()))?;
+
+        Ok(dst)
+    }
+
+    /// Write the module tree into the [`SINGLE_FILE`] of the `dst`, with every module nested
+    /// inline in its parent.
+    fn compile_single_file(self, dst: path::PathBuf, scope: Scope<'_>) -> Compiled {
+        let write = || {
+            fs::create_dir_all(&dst).map_err(|e| Error::MkModDir(e, dst.clone()))?;
+
+            let file = dst.join(SINGLE_FILE);
+            let mut out = io::BufWriter::new(
+                fs::File::create_new(&file).map_err(|e| Error::MkModFile(e, file.clone()))?,
+            );
+            out.write_all(scope.header.as_bytes())
+                .map_err(|e| Error::WriteModFile(e, file.clone()))?;
+            self.write_inline(&mut out, &file, scope)?;
+            out.flush()
+                .map_err(|e| Error::WriteModFile(e, file.clone()))?;
+
+            Ok(file)
+        };
+
+        match write() {
+            Ok(file) => Compiled {
+                files: vec![file],
+                errors: vec![],
+            },
+            Err(e) => Compiled {
+                files: vec![],
+                errors: vec![e],
+            },
+        }
+    }
+
+    /// Write the child modules as inline `mod` blocks followed by the code of the module, in the
+    /// same order as a `mod.rs` would have them.
+    fn write_inline(
+        self,
+        out: &mut impl Write,
+        dst: &path::Path,
+        scope: Scope<'_>,
+    ) -> Result<(), Error> {
+        let has_children = !self.children.is_empty();
 
-                Ok(())
-            })?;
+        self.children.into_iter().try_for_each(|(module, node)| {
+            let package = scope.child(&module);
+            out.write_all(scope.attributes(&module).as_bytes())
+                .and_then(|_| out.write_all(scope.visibility(&module).keyword()))
+                .and_then(|_| out.write_all(b"mod "))
+                .and_then(|_| out.write_all(module_ident(&module).as_bytes()))
+                .and_then(|_| out.write_all(b" {\n"))
+                .map_err(|e| Error::WriteModFile(e, dst.to_path_buf()))?;
+            node.write_inline(
+                out,
+                dst,
+                Scope {
+                    package: &package,
+                    ..scope
+                },
+            )?;
+            out.write_all(b"}\n")
+                .map_err(|e| Error::WriteModFile(e, dst.to_path_buf()))
+        })?;
 
         if let Some(src) = self.path {
-            let contents =
-                fs::read_to_string(&src).map_err(|e| Error::ReadSourceFile(e, src.clone()))?;
+            let mut contents =
+                fs::File::open(&src).map_err(|e| Error::ReadSourceFile(e, src.clone()))?;
 
             if has_children {
-                mod_file
-                    .write(b"\n")
-                    .map_err(|e| Error::WriteModFile(e, dst.clone()))?;
+                out.write_all(b"\n")
+                    .map_err(|e| Error::WriteModFile(e, dst.to_path_buf()))?;
             }
 
-            mod_file
-                .write_all(contents.as_bytes())
-                .map_err(|e| Error::WriteModFile(e, dst.clone()))?;
+            io::copy(&mut contents, out).map_err(|e| Error::WriteModFile(e, dst.to_path_buf()))?;
         }
 
         Ok(())
     }
 }
 
+/// The name of the module of a package segment, which is also the name of its directory, with
+/// the characters not allowed in an identifier replaced with underscores and the keywords that
+/// can't be raw identifiers suffixed with one, the same way prost escapes them, e.g. `self_`.
+fn module_name(part: &ffi::OsStr) -> ffi::OsString {
+    let part = part.to_string_lossy();
+    let name = part
+        .strip_prefix("r#")
+        .unwrap_or(&part)
+        .chars()
+        .map(|c| match c == '_' || c.is_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect::<String>();
+    let ident = services::sanitize(name);
+
+    ffi::OsString::from(ident.strip_prefix("r#").unwrap_or(&ident))
+}
+
+/// The identifier the module of a package segment is declared with, a raw identifier for the
+/// keywords, e.g. `r#type`.
+fn module_ident(part: &ffi::OsStr) -> String {
+    services::sanitize(module_name(part).to_string_lossy().into_owned())
+}
+
 #[inline(always)]
-fn sanitize_path(part: &ffi::OsStr) -> ffi::OsString {
-    if part.as_bytes().starts_with(b"r#") {
-        ffi::OsString::from_vec(part.as_bytes()[2..].to_vec())
-    } else {
-        part.to_os_string()
+fn is_rust_file(e: &ignore::DirEntry) -> bool {
+    e.file_type().is_some_and(|t| t.is_file())
+        && e.path().extension().is_some_and(|ext| ext == "rs")
+}
+
+/// The most specific of the `packages` mapped to separate output directories covering the
+/// package with the `parts`.
+fn mapping<'a>(packages: &'a [PackageOutput], parts: &[&str]) -> Option<&'a PackageOutput> {
+    packages
+        .iter()
+        .filter(|mapped| {
+            mapped.package.len() <= parts.len()
+                && mapped.package.iter().zip(parts).all(|(a, b)| a == b)
+        })
+        .max_by_key(|mapped| mapped.package.len())
+}
+
+/// The output directory the protobuf `package` is generated into, either the `dst` or the one of
+/// the `packages` it is mapped to.
+pub fn package_root(dst: &path::Path, packages: &[PackageOutput], package: &str) -> path::PathBuf {
+    let parts = package.split('.').collect::<Vec<_>>();
+
+    mapping(packages, &parts).map_or_else(|| dst.to_path_buf(), |mapped| mapped.output.clone())
+}
+
+/// The directory the module of the protobuf `package` is generated into, taking the `packages`
+/// mapped to separate output directories into account.
+pub fn package_dir(dst: &path::Path, packages: &[PackageOutput], package: &str) -> path::PathBuf {
+    let parts = package.split('.').collect::<Vec<_>>();
+
+    mapping(packages, &parts).map_or_else(
+        || {
+            parts
+                .iter()
+                .fold(dst.to_path_buf(), |dir, part| dir.join(part))
+        },
+        |mapped| {
+            parts[mapped.package.len()..]
+                .iter()
+                .fold(mapped.output.clone(), |dir, part| dir.join(part))
+        },
+    )
+}
+
+/// Same as [`package_dir`], but within the source directory of the output with the `layout`.
+pub fn layout_package_dir(
+    dst: &path::Path,
+    packages: &[PackageOutput],
+    package: &str,
+    layout: Layout,
+) -> path::PathBuf {
+    let root = package_root(dst, packages, package);
+    let dir = package_dir(dst, packages, package);
+
+    match dir.strip_prefix(&root) {
+        Ok(nested) => layout.module_dir(&root).join(nested),
+        Err(_) => dir,
     }
 }
 
-#[inline(always)]
-fn is_rust_file(e: &walkdir::DirEntry) -> bool {
-    e.file_type().is_file() && e.path().extension().is_some_and(|ext| ext == "rs")
+/// The file the module of the protobuf `package` is generated into, taking the `packages` mapped
+/// to separate output directories and the `layout` of the module tree into account.
+pub fn module_file(
+    dst: &path::Path,
+    packages: &[PackageOutput],
+    package: &str,
+    layout: Layout,
+) -> path::PathBuf {
+    let root = package_root(dst, packages, package);
+
+    match layout {
+        Layout::Tree => package_dir(dst, packages, package).join("mod.rs"),
+        Layout::SingleFile => root.join(SINGLE_FILE),
+        Layout::Crate => match layout_package_dir(dst, packages, package, layout) {
+            dir if dir == layout.module_dir(&root) => dir.join(layout.root_file()),
+            dir => dir.join("mod.rs"),
+        },
+    }
 }
 
-pub fn modularize(src: &path::Path, dst: &path::Path) -> Result<(), Error> {
-    let files = walkdir::WalkDir::new(src)
-        .into_iter()
-        .collect::<Result<Vec<_>, _>>()?;
+/// Assemble the generated Rust source files in `src` into a module tree in `dst`, returning the
+/// paths of the written module files.
+pub fn modularize(src: &path::Path, dst: &path::Path) -> Result<Vec<path::PathBuf>, Error> {
+    modularize_packages(
+        src,
+        dst,
+        &[],
+        &Input::default(),
+        Layout::default(),
+        &Visibilities::default(),
+        &Header::default(),
+    )
+}
+
+/// Same as [`modularize`], but places the module trees of the `packages` into their own output
+/// directories, rooted at the package module itself, and lays every one of them out with the
+/// `layout`, declaring the modules with their `visibilities` and starting every module file with
+/// the `header`. The `src` directory is read with the `input` options, e.g. to follow the
+/// symlinks.
+pub fn modularize_packages(
+    src: &path::Path,
+    dst: &path::Path,
+    packages: &[PackageOutput],
+    input: &Input,
+    layout: Layout,
+    visibilities: &Visibilities,
+    header: &Header,
+) -> Result<Vec<path::PathBuf>, Error> {
+    read_tree(src, input)?.compile(dst, packages, layout, visibilities, header)
+}
+
+/// Compute the paths of the module files [`modularize_packages`] would write, without writing
+/// anything.
+pub fn plan(
+    src: &path::Path,
+    dst: &path::Path,
+    packages: &[PackageOutput],
+    input: &Input,
+    layout: Layout,
+) -> Result<Vec<path::PathBuf>, Error> {
+    read_tree(src, input)?.plan(dst, packages, layout)
+}
 
-    let tree = files
+fn read_tree(src: &path::Path, input: &Input) -> Result<Tree, Error> {
+    let files = input
+        .walk
+        .walk(src)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Error::ReadSourceDir(e, src.to_path_buf()))?;
+
+    files
         .into_iter()
         .filter(is_rust_file)
-        .try_fold(Tree::new(), |tree, entry| tree.push(entry.into_path()))?;
-
-    tree.compile(dst)
+        .try_fold(Tree::new(), |tree, entry| match &input.modules {
+            Some(modules) => tree.push_package(entry.into_path(), modules),
+            None => tree.push(entry.into_path()),
+        })
+        .map_err(|e| input.relocate(e))
 }
 
 #[cfg(test)]
@@ -186,7 +1027,7 @@ mod tests {
             tree.root,
             super::Node {
                 path: Some(path::PathBuf::from("/foo/_.rs")),
-                children: collections::HashMap::new(),
+                children: collections::BTreeMap::new(),
             },
             "The parsed tree has invalid structure",
         );
@@ -202,7 +1043,7 @@ mod tests {
             tree.root,
             super::Node {
                 path: None,
-                children: collections::HashMap::from([(
+                children: collections::BTreeMap::from([(
                     ffi::OsString::from("crabs"),
                     super::Node {
                         children: Default::default(),
@@ -226,31 +1067,31 @@ mod tests {
             tree.root,
             super::Node {
                 path: None,
-                children: collections::HashMap::from([(
+                children: collections::BTreeMap::from([(
                     ffi::OsString::from("crabs"),
                     super::Node {
                         path: Some(path::PathBuf::from("/tmp/proto/crabs.rs")),
-                        children: collections::HashMap::from([
+                        children: collections::BTreeMap::from([
                             (
                                 ffi::OsString::from("sponge_bob"),
                                 super::Node {
                                     path: Some(path::PathBuf::from(
                                         "/tmp/proto/crabs.sponge_bob.rs"
                                     )),
-                                    children: collections::HashMap::new(),
+                                    children: collections::BTreeMap::new(),
                                 }
                             ),
                             (
                                 ffi::OsString::from("disney"),
                                 super::Node {
                                     path: None,
-                                    children: collections::HashMap::from([(
+                                    children: collections::BTreeMap::from([(
                                         ffi::OsString::from("ariel"),
                                         super::Node {
                                             path: Some(path::PathBuf::from(
                                                 "/tmp/proto/crabs.disney.ariel.rs"
                                             )),
-                                            children: collections::HashMap::new(),
+                                            children: collections::BTreeMap::new(),
                                         },
                                     )]),
                                 }
@@ -263,6 +1104,86 @@ mod tests {
         );
     }
 
+    #[test]
+    fn node_push_rejects_conflicting_files() {
+        let tree = super::Tree::new()
+            .push(path::PathBuf::from("/tmp/proto/crabs.r#type.rs"))
+            .and_then(|t| t.push(path::PathBuf::from("/tmp/proto/crabs.sponge_bob.rs")))
+            .and_then(|t| t.push(path::PathBuf::from("/tmp/vendor/crabs.type.rs")));
+        assert!(
+            matches!(
+                &tree,
+                Err(super::Error::ConflictingFiles(first, second))
+                    if first == path::Path::new("/tmp/proto/crabs.r#type.rs")
+                        && second == path::Path::new("/tmp/vendor/crabs.type.rs")
+            ),
+            "Expected `Err(Error::ConflictingFiles(..))`, got: `{:?}`",
+            tree.map(|tree| tree.root)
+        );
+
+        let tree = super::Tree::new()
+            .push(path::PathBuf::from("/tmp/proto/crabs.sponge_bob.rs"))
+            .and_then(|t| t.push(path::PathBuf::from("/tmp/proto/crabs.sponge-bob.rs")));
+        assert!(
+            matches!(&tree, Err(super::Error::ConflictingFiles(_, _))),
+            "Expected `Err(Error::ConflictingFiles(_, _))`, got: `{:?}`",
+            tree.map(|tree| tree.root)
+        );
+    }
+
+    #[test]
+    fn modularize_reports_the_conflicting_protos() {
+        let dst =
+            tempfile::TempDir::new().expect("Failed to create destination directory for tests");
+        let src = tempfile::TempDir::new().expect("Failed to create source directory for tests");
+
+        ["crabs.sponge_bob.rs", "crabs.sponge-bob.rs"]
+            .into_iter()
+            .for_each(|file| {
+                fs::write(src.path().join(file), "struct SpongeBob;\n")
+                    .expect("Failed to create a test source file")
+            });
+
+        let input = super::Input {
+            protos: collections::BTreeMap::from([
+                (
+                    ffi::OsString::from("crabs.sponge_bob.rs"),
+                    vec!["crabs/sponge_bob.proto".to_string()],
+                ),
+                (
+                    ffi::OsString::from("crabs.sponge-bob.rs"),
+                    vec![
+                        "vendor/sponge-bob.proto".to_string(),
+                        "vendor/patrick.proto".to_string(),
+                    ],
+                ),
+            ]),
+            ..super::Input::default()
+        };
+        let err = super::modularize_packages(
+            src.path(),
+            dst.path(),
+            &[],
+            &input,
+            super::Layout::Tree,
+            &super::Visibilities::default(),
+            &super::Header::default(),
+        );
+        assert!(
+            matches!(
+                &err,
+                Err(super::Error::ConflictingProtos(first, second))
+                    if [first, second].contains(&&vec!["crabs/sponge_bob.proto".to_string()])
+                        && [first, second].contains(&&vec![
+                            "vendor/sponge-bob.proto".to_string(),
+                            "vendor/patrick.proto".to_string(),
+                        ])
+            ),
+            "Expected `Err(Error::ConflictingProtos(..))`, got: `{:?}`",
+            err
+        );
+    }
+
     #[test]
     fn modularize() {
         let dst =
@@ -286,7 +1207,21 @@ mod tests {
         fs::write(parallel_file, b"struct Parallel;\n")
             .expect("Failed to create a parallel source file for tests");
 
-        super::modularize(src.path(), dst.path()).expect("Failed to modularize the files");
+        let files =
+            super::modularize(src.path(), dst.path()).expect("Failed to modularize the files");
+
+        assert_eq!(
+            files,
+            vec![
+                dst.path().join("a/b/c/d/mod.rs"),
+                dst.path().join("a/b/c/mod.rs"),
+                dst.path().join("a/b/mod.rs"),
+                dst.path().join("a/mod.rs"),
+                dst.path().join("z/mod.rs"),
+                dst.path().join("mod.rs"),
+            ],
+            "Invalid list of the written module files",
+        );
 
         let output = fs::read_to_string(dst.path().join("a/b/c/d/mod.rs"))
             .expect("Unable to read output file");
@@ -331,6 +1266,451 @@ mod tests {
         );
     }
 
+    #[test]
+    fn modularize_packages_splits_the_tree() {
+        let dst =
+            tempfile::TempDir::new().expect("Failed to create destination directory for tests");
+
+        let src = tempfile::TempDir::new().expect("Failed to create source directory for tests");
+
+        [
+            ("corp.rs", "struct Corp;\n"),
+            ("corp.billing.rs", "struct Billing;\n"),
+            ("corp.billing.invoices.rs", "struct Invoice;\n"),
+            ("corp.hr.employees.rs", "struct Employee;\n"),
+        ]
+        .into_iter()
+        .for_each(|(file, contents)| {
+            fs::write(src.path().join(file), contents).expect("Failed to create a test source file")
+        });
+
+        let packages = [
+            super::PackageOutput::new("corp.billing.*", dst.path().join("billing"))
+                .expect("Failed to create a package output"),
+            super::PackageOutput::new("corp.hr", dst.path().join("hr"))
+                .expect("Failed to create a package output"),
+        ];
+
+        let files = super::modularize_packages(
+            src.path(),
+            &dst.path().join("main"),
+            &packages,
+            &super::Input::default(),
+            super::Layout::Tree,
+            &super::Visibilities::default(),
+            &super::Header::default(),
+        )
+        .expect("Failed to modularize the files");
+
+        assert_eq!(
+            files,
+            vec![
+                dst.path().join("billing/invoices/mod.rs"),
+                dst.path().join("billing/mod.rs"),
+                dst.path().join("hr/employees/mod.rs"),
+                dst.path().join("hr/mod.rs"),
+                dst.path().join("main/corp/mod.rs"),
+                dst.path().join("main/mod.rs"),
+            ],
+            "Invalid list of the written module files",
+        );
+
+        let output = fs::read_to_string(dst.path().join("billing/mod.rs"))
+            .expect("Unable to read output file");
+        assert_eq!(
+            "pub mod invoices;\n\nstruct Billing;\n", output,
+            "Invalid contents of the billing package module",
+        );
+
+        let output = fs::read_to_string(dst.path().join("main/corp/mod.rs"))
+            .expect("Unable to read output file");
+        assert_eq!(
+            "struct Corp;\n", output,
+            "Expected the mapped packages to be removed from the main tree",
+        );
+    }
+
+    #[test]
+    fn modularize_rejects_unmatched_package_outputs() {
+        let dst =
+            tempfile::TempDir::new().expect("Failed to create destination directory for tests");
+        let src = tempfile::TempDir::new().expect("Failed to create source directory for tests");
+        fs::write(src.path().join("corp.billing.rs"), "struct Billing;\n")
+            .expect("Failed to create a test source file");
+
+        let packages = [
+            super::PackageOutput::new("corp.biling.*", dst.path().join("billing"))
+                .expect("Failed to create a package output"),
+        ];
+
+        let err = super::modularize_packages(
+            src.path(),
+            &dst.path().join("main"),
+            &packages,
+            &super::Input::default(),
+            super::Layout::Tree,
+            &super::Visibilities::default(),
+            &super::Header::default(),
+        );
+        assert!(
+            matches!(&err, Err(super::Error::UnmatchedPackage(package)) if package == "corp.biling"),
+            "Expected `Err(Error::UnmatchedPackage)`, got: `{:?}`",
+            err
+        );
+        assert!(
+            !dst.path().join("billing").exists(),
+            "Expected nothing to be written for the unmatched package",
+        );
+    }
+
+    #[test]
+    fn plan_matches_modularize_without_writing() {
+        let dst =
+            tempfile::TempDir::new().expect("Failed to create destination directory for tests");
+
+        let src = tempfile::TempDir::new().expect("Failed to create source directory for tests");
+
+        ["_.rs", "a.b.rs", "a.c.rs", "z.rs"]
+            .into_iter()
+            .for_each(|file| {
+                fs::write(src.path().join(file), "struct Planned;\n")
+                    .expect("Failed to create a test source file")
+            });
+
+        let packages = [super::PackageOutput::new("a.c", dst.path().join("c"))
+            .expect("Failed to create a package output")];
+
+        let planned = super::plan(
+            src.path(),
+            &dst.path().join("main"),
+            &packages,
+            &super::Input::default(),
+            super::Layout::Tree,
+        )
+        .expect("Failed to plan the module files");
+
+        assert!(
+            fs::read_dir(dst.path())
+                .expect("Failed to read the destination directory")
+                .next()
+                .is_none(),
+            "Expected planning to write nothing",
+        );
+
+        let written = super::modularize_packages(
+            src.path(),
+            &dst.path().join("main"),
+            &packages,
+            &super::Input::default(),
+            super::Layout::Tree,
+            &super::Visibilities::default(),
+            &super::Header::default(),
+        )
+        .expect("Failed to modularize the files");
+
+        assert_eq!(
+            planned, written,
+            "Expected the planned files to match the written ones"
+        );
+    }
+
+    #[test]
+    fn modularize_packages_into_single_files() {
+        let dst =
+            tempfile::TempDir::new().expect("Failed to create destination directory for tests");
+
+        let src = tempfile::TempDir::new().expect("Failed to create source directory for tests");
+
+        [
+            ("_.rs", "struct Root;\n"),
+            ("a.b.rs", "struct Branch;\n"),
+            ("a.b.c.rs", "struct Leaf;\n"),
+            ("z.rs", "struct Mapped;\n"),
+        ]
+        .into_iter()
+        .for_each(|(file, contents)| {
+            fs::write(src.path().join(file), contents).expect("Failed to create a test source file")
+        });
+
+        let packages = [super::PackageOutput::new("z", dst.path().join("z"))
+            .expect("Failed to create a package output")];
+        let input = super::Input::default();
+
+        let planned = super::plan(
+            src.path(),
+            &dst.path().join("main"),
+            &packages,
+            &input,
+            super::Layout::SingleFile,
+        )
+        .expect("Failed to plan the module files");
+        let files = super::modularize_packages(
+            src.path(),
+            &dst.path().join("main"),
+            &packages,
+            &input,
+            super::Layout::SingleFile,
+            &super::Visibilities::default(),
+            &super::Header::default(),
+        )
+        .expect("Failed to modularize the files");
+
+        assert_eq!(
+            files,
+            vec![
+                dst.path().join("z/generated.rs"),
+                dst.path().join("main/generated.rs"),
+            ],
+            "Invalid list of the written module files",
+        );
+        assert_eq!(
+            planned, files,
+            "Expected the planned files to match the written ones"
+        );
+        assert_eq!(
+            fs::read_to_string(dst.path().join("main/generated.rs"))
+                .expect("Unable to read output file"),
+            "pub mod a {\npub mod b {\npub mod c {\nstruct Leaf;\n}\n\nstruct Branch;\n}\n}\n\n\
+            struct Root;\n",
+            "Invalid contents of the single file",
+        );
+        assert_eq!(
+            fs::read_to_string(dst.path().join("z/generated.rs"))
+                .expect("Unable to read output file"),
+            "struct Mapped;\n",
+            "Invalid contents of the mapped package file",
+        );
+    }
+
+    #[test]
+    fn modularize_packages_into_crates() {
+        let dst =
+            tempfile::TempDir::new().expect("Failed to create destination directory for tests");
+
+        let src = tempfile::TempDir::new().expect("Failed to create source directory for tests");
+
+        [
+            ("_.rs", "struct Root;\n"),
+            ("a.b.rs", "struct Branch;\n"),
+            ("z.y.rs", "struct Mapped;\n"),
+        ]
+        .into_iter()
+        .for_each(|(file, contents)| {
+            fs::write(src.path().join(file), contents).expect("Failed to create a test source file")
+        });
+
+        let main = dst.path().join("main");
+        let packages = [super::PackageOutput::new("z", dst.path().join("z"))
+            .expect("Failed to create a package output")];
+        let input = super::Input::default();
+        let layout = super::Layout::Crate;
+
+        let planned = super::plan(src.path(), &main, &packages, &input, layout)
+            .expect("Failed to plan the module files");
+        let files = super::modularize_packages(
+            src.path(),
+            &main,
+            &packages,
+            &input,
+            layout,
+            &super::Visibilities::default(),
+            &super::Header::default(),
+        )
+        .expect("Failed to modularize the files");
+
+        assert_eq!(
+            files,
+            vec![
+                dst.path().join("z/src/y/mod.rs"),
+                dst.path().join("z/src/lib.rs"),
+                dst.path().join("main/src/a/b/mod.rs"),
+                dst.path().join("main/src/a/mod.rs"),
+                dst.path().join("main/src/lib.rs"),
+            ],
+            "Invalid list of the written module files",
+        );
+        assert_eq!(
+            planned, files,
+            "Expected the planned files to match the written ones"
+        );
+        assert_eq!(
+            fs::read_to_string(dst.path().join("main/src/lib.rs"))
+                .expect("Unable to read output file"),
+            "pub mod a;\n\nstruct Root;\n",
+            "Invalid contents of the crate root",
+        );
+
+        [
+            ("", "main/src/lib.rs"),
+            ("a.b", "main/src/a/b/mod.rs"),
+            ("z", "z/src/lib.rs"),
+        ]
+        .into_iter()
+        .for_each(|(package, file)| {
+            assert_eq!(
+                super::module_file(&main, &packages, package, layout),
+                dst.path().join(file),
+                "Invalid module file of `{package}`",
+            );
+        });
+    }
+
+    #[test]
+    fn modularize_packages_with_visibilities() {
+        let dst =
+            tempfile::TempDir::new().expect("Failed to create destination directory for tests");
+
+        let src = tempfile::TempDir::new().expect("Failed to create source directory for tests");
+
+        [
+            ("a.b.rs", "struct Branch;\n"),
+            ("a.b.c.rs", "struct Leaf;\n"),
+            ("z.rs", "struct Z;\n"),
+        ]
+        .into_iter()
+        .for_each(|(file, contents)| {
+            fs::write(src.path().join(file), contents).expect("Failed to create a test source file")
+        });
+
+        let visibilities = super::Visibilities {
+            default: super::Visibility::Crate,
+            packages: collections::BTreeMap::from([
+                ("a.b".to_string(), super::Visibility::Private),
+                ("a.b.c".to_string(), super::Visibility::Public),
+            ]),
+            attributes: collections::BTreeMap::from([(
+                "z".to_string(),
+                vec!["#[allow(dead_code)]".to_string()],
+            )]),
+        };
+        let input = super::Input::default();
+
+        [
+            (super::Layout::Tree, "tree"),
+            (super::Layout::SingleFile, "single"),
+        ]
+        .into_iter()
+        .for_each(|(layout, dir)| {
+            super::modularize_packages(
+                src.path(),
+                &dst.path().join(dir),
+                &[],
+                &input,
+                layout,
+                &visibilities,
+                &super::Header::default(),
+            )
+            .expect("Failed to modularize the files");
+        });
+
+        let read = |file: &str| {
+            fs::read_to_string(dst.path().join(file)).expect("Unable to read output file")
+        };
+        assert_eq!(
+            read("tree/mod.rs"),
+            "pub(crate) mod a;\n#[allow(dead_code)]\npub(crate) mod z;\n"
+        );
+        assert_eq!(read("tree/a/mod.rs"), "mod b;\n");
+        assert_eq!(read("tree/a/b/mod.rs"), "pub mod c;\n\nstruct Branch;\n");
+        assert_eq!(
+            read("single/generated.rs"),
+            "pub(crate) mod a {\nmod b {\npub mod c {\nstruct Leaf;\n}\n\nstruct Branch;\n}\n}\n\
+            #[allow(dead_code)]\npub(crate) mod z {\nstruct Z;\n}\n",
+        );
+    }
+
+    #[test]
+    fn package_visibility_parses_the_package_and_the_visibility() {
+        assert_eq!(
+            ".corp.billing=crate"
+                .parse::<super::PackageVisibility>()
+                .expect("Failed to parse a package visibility"),
+            super::PackageVisibility {
+                package: "corp.billing".to_string(),
+                visibility: super::Visibility::Crate,
+            },
+        );
+
+        let visibilities = super::Visibilities {
+            default: super::Visibility::Private,
+            packages: collections::BTreeMap::from([("a.b".to_string(), super::Visibility::Crate)]),
+            ..Default::default()
+        };
+        ["", "x", "a.b.c", "x.y"]
+            .into_iter()
+            .zip([true, true, true, false])
+            .for_each(|(package, expected)| {
+                assert_eq!(
+                    visibilities.is_reachable(package),
+                    expected,
+                    "Invalid reachability of `{package}`",
+                );
+            });
+
+        ["corp.billing", "=crate", "corp.billing=protected"]
+            .into_iter()
+            .for_each(|visibility| {
+                let result = visibility.parse::<super::PackageVisibility>();
+                assert!(
+                    result.is_err(),
+                    "Expected an invalid package visibility `{visibility}`, got: `{result:?}`",
+                );
+            });
+    }
+
+    #[test]
+    fn package_dir_prefers_the_most_specific_mapping() {
+        let packages = [
+            super::PackageOutput::new("corp", "corp-out")
+                .expect("Failed to create a package output"),
+            super::PackageOutput::new("corp.billing", "billing-out")
+                .expect("Failed to create a package output"),
+        ];
+
+        assert_eq!(
+            super::package_dir(path::Path::new("out"), &packages, "corp.billing.invoices"),
+            path::PathBuf::from("billing-out/invoices"),
+            "Expected the most specific mapping to be used",
+        );
+        assert_eq!(
+            super::package_dir(path::Path::new("out"), &packages, "corp.hr"),
+            path::PathBuf::from("corp-out/hr"),
+            "Expected the parent mapping to be used",
+        );
+        assert_eq!(
+            super::package_dir(path::Path::new("out"), &packages, "crabs.disney"),
+            path::PathBuf::from("out/crabs/disney"),
+            "Expected unmapped packages to be placed into the main tree",
+        );
+    }
+
+    #[test]
+    fn package_output_from_str() {
+        let package = "corp.billing.*=crates/billing/src"
+            .parse::<super::PackageOutput>()
+            .expect("Failed to parse a package output");
+        assert_eq!(
+            package,
+            super::PackageOutput::new("corp.billing", "crates/billing/src")
+                .expect("Failed to create a package output"),
+            "Invalid parsed package output",
+        );
+
+        let err = "corp.billing".parse::<super::PackageOutput>();
+        assert!(
+            matches!(err, Err(super::PackageOutputError::Format { .. })),
+            "Expected `Err(PackageOutputError::Format)`, got: `{:?}`",
+            err
+        );
+
+        let err = "corp..billing=out".parse::<super::PackageOutput>();
+        assert!(
+            matches!(err, Err(super::PackageOutputError::Package { .. })),
+            "Expected `Err(PackageOutputError::Package)`, got: `{:?}`",
+            err
+        );
+    }
+
     #[test]
     fn modularize_walkdir_fails() {
         let dst =
@@ -368,10 +1748,24 @@ mod tests {
         fs::write(src.path().join("ro.rs"), "struct CreateDirFails;\n")
             .expect("Failed to create a test source file");
 
+        // The root module is still attempted after its child failed, so both failures are reported.
         let err = super::modularize(src.path(), dst.path());
         assert!(
-            matches!(err, Err(super::Error::MkModDir { .. })),
-            "Expected `Err(Error::MkModDir)`, got: `{:?}`",
+            matches!(
+                err.as_ref().map_err(|e| match e {
+                    super::Error::Multiple(errors) => errors.as_slice(),
+                    e => std::slice::from_ref(e),
+                }),
+                Err([super::Error::MkModDir(..), super::Error::MkModFile(..)])
+            ),
+            "Expected `Err(Error::Multiple([MkModDir, MkModFile]))`, got: `{:?}`",
+            err
+        );
+        assert!(
+            err.as_ref().is_err_and(|e| e
+                .to_string()
+                .starts_with("2 module files failed:\nFailed to create the module")),
+            "Expected the failures to be reported together, got: `{:?}`",
             err
         );
     }
@@ -424,6 +1818,29 @@ mod tests {
         );
     }
 
+    #[test]
+    fn modularize_copies_non_utf8_sources() {
+        let dst =
+            tempfile::TempDir::new().expect("Failed to create destination directory for tests");
+
+        let src = tempfile::TempDir::new().expect("Failed to create source directory for tests");
+
+        fs::write(
+            src.path().join("crabs.rs"),
+            b"// Ferris \xff\xfe\nstruct Crab;\n",
+        )
+        .expect("Failed to create a test source file");
+
+        super::modularize(src.path(), dst.path()).expect("Failed to modularize the files");
+
+        let output = fs::read(dst.path().join("crabs/mod.rs")).expect("Unable to read output file");
+        assert_eq!(
+            b"// Ferris \xff\xfe\nstruct Crab;\n"[..],
+            output,
+            "Invalid contents of the output module `crabs`",
+        );
+    }
+
     #[test]
     fn modularize_handles_keywords_properly() {
         let dst =
@@ -451,4 +1868,200 @@ mod tests {
             "Invalid contents of the output output module `foo`",
         );
     }
+
+    #[test]
+    fn modularize_escapes_keyword_and_invalid_module_names() {
+        let dst =
+            tempfile::TempDir::new().expect("Failed to create destination directory for tests");
+
+        let src = tempfile::TempDir::new().expect("Failed to create source directory for tests");
+
+        [
+            ("crabs.type.rs", "struct Type;\n"),
+            ("crabs.async.rs", "struct Async;\n"),
+            ("crabs.self.rs", "struct Itself;\n"),
+            ("crabs.sponge-bob.rs", "struct SpongeBob;\n"),
+            ("crabs.2024.rs", "struct Vintage;\n"),
+        ]
+        .into_iter()
+        .for_each(|(file, contents)| {
+            fs::write(src.path().join(file), contents).expect("Failed to create a test source file")
+        });
+
+        super::modularize(src.path(), &dst.path().join("tree"))
+            .expect("Failed to modularize the files");
+
+        let read = |file: &str| {
+            fs::read_to_string(dst.path().join(file)).expect("Unable to read output file")
+        };
+        assert_eq!(
+            read("tree/crabs/mod.rs"),
+            "pub mod _2024;\npub mod r#async;\npub mod self_;\npub mod sponge_bob;\n\
+            pub mod r#type;\n",
+            "Expected the module names to be escaped",
+        );
+        [
+            ("tree/crabs/type/mod.rs", "struct Type;\n"),
+            ("tree/crabs/async/mod.rs", "struct Async;\n"),
+            ("tree/crabs/self_/mod.rs", "struct Itself;\n"),
+            ("tree/crabs/sponge_bob/mod.rs", "struct SpongeBob;\n"),
+            ("tree/crabs/_2024/mod.rs", "struct Vintage;\n"),
+        ]
+        .into_iter()
+        .for_each(|(file, contents)| {
+            assert_eq!(read(file), contents, "Invalid contents of `{file}`");
+        });
+
+        super::modularize_packages(
+            src.path(),
+            &dst.path().join("single"),
+            &[],
+            &super::Input::default(),
+            super::Layout::SingleFile,
+            &super::Visibilities::default(),
+            &super::Header::default(),
+        )
+        .expect("Failed to modularize the files");
+        assert!(
+            read("single/generated.rs").contains("pub mod r#async {\nstruct Async;\n}\n"),
+            "Expected the inline module names to be escaped, got:\n{}",
+            read("single/generated.rs"),
+        );
+    }
+
+    #[test]
+    fn modularize_places_the_files_after_their_packages() {
+        let dst =
+            tempfile::TempDir::new().expect("Failed to create destination directory for tests");
+
+        let src = tempfile::TempDir::new().expect("Failed to create source directory for tests");
+
+        [
+            ("_.rs", "struct Root;\n"),
+            ("crabs.rs", "struct Crab;\n"),
+            ("crabs_v1.rs", "struct Ferris;\n"),
+        ]
+        .into_iter()
+        .for_each(|(file, contents)| {
+            fs::write(src.path().join(file), contents).expect("Failed to create a test source file")
+        });
+
+        let input = super::Input {
+            modules: Some(super::PackageModules::from_iter([
+                ("_.rs", vec![]),
+                ("crabs.rs", vec!["crabs".to_string()]),
+                ("crabs_v1.rs", vec!["crabs".to_string(), "v1".to_string()]),
+            ])),
+            ..super::Input::default()
+        };
+        super::modularize_packages(
+            src.path(),
+            &dst.path().join("tree"),
+            &[],
+            &input,
+            super::Layout::Tree,
+            &super::Visibilities::default(),
+            &super::Header::default(),
+        )
+        .expect("Failed to modularize the files");
+
+        let read = |file: &str| {
+            fs::read_to_string(dst.path().join(file)).expect("Unable to read output file")
+        };
+        [
+            ("tree/mod.rs", "pub mod crabs;\n\nstruct Root;\n"),
+            ("tree/crabs/mod.rs", "pub mod v1;\n\nstruct Crab;\n"),
+            ("tree/crabs/v1/mod.rs", "struct Ferris;\n"),
+        ]
+        .into_iter()
+        .for_each(|(file, contents)| {
+            assert_eq!(read(file), contents, "Invalid contents of `{file}`");
+        });
+
+        fs::write(src.path().join("crabs.v2.rs"), "struct Sponge;\n")
+            .expect("Failed to create a test source file");
+        let err = super::plan(
+            src.path(),
+            &dst.path().join("unknown"),
+            &[],
+            &input,
+            super::Layout::Tree,
+        );
+        assert!(
+            matches!(&err, Err(super::Error::UnknownPackage(path)) if path.ends_with("crabs.v2.rs")),
+            "Expected `Err(UnknownPackage)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn header_comments_out_the_license_and_the_banner() {
+        assert_eq!(
+            super::Header::new(
+                Some("Copyright Crabs\n\nSPDX-License-Identifier: MIT\n"),
+                Some("@generated by pbuildrs, do not edit"),
+            )
+            .0,
+            "// Copyright Crabs\n//\n// SPDX-License-Identifier: MIT\n\n\
+            // @generated by pbuildrs, do not edit\n\n",
+            "Invalid header",
+        );
+        assert_eq!(
+            super::Header::new(Some("/*\n * Copyright Crabs\n */\n\n"), None).0,
+            "/*\n * Copyright Crabs\n */\n\n",
+            "Expected the comments to be kept as they are",
+        );
+        assert_eq!(
+            super::Header::new(Some(" \n"), None),
+            super::Header::default(),
+            "Expected no header for a blank license",
+        );
+    }
+
+    #[test]
+    fn modularize_starts_every_module_file_with_the_header() {
+        let dst =
+            tempfile::TempDir::new().expect("Failed to create destination directory for tests");
+
+        let src = tempfile::TempDir::new().expect("Failed to create source directory for tests");
+
+        [("_.rs", "struct Root;\n"), ("a.b.rs", "struct Leaf;\n")]
+            .into_iter()
+            .for_each(|(file, contents)| {
+                fs::write(src.path().join(file), contents)
+                    .expect("Failed to create a test source file")
+            });
+
+        let header = super::Header::new(Some("Copyright Crabs"), Some("// @generated"));
+        [super::Layout::Tree, super::Layout::SingleFile]
+            .into_iter()
+            .for_each(|layout| {
+                let files = super::modularize_packages(
+                    src.path(),
+                    &dst.path().join(format!("{layout:?}")),
+                    &[],
+                    &super::Input::default(),
+                    layout,
+                    &super::Visibilities::default(),
+                    &header,
+                )
+                .expect("Failed to modularize the files");
+
+                files.iter().for_each(|file| {
+                    let contents = fs::read_to_string(file).expect("Unable to read output file");
+                    assert!(
+                        contents.starts_with("// Copyright Crabs\n\n// @generated\n\n"),
+                        "Expected the header at the top of `{}`, got:\n{contents}",
+                        file.display(),
+                    );
+                });
+            });
+
+        assert_eq!(
+            fs::read_to_string(dst.path().join("Tree/a/b/mod.rs"))
+                .expect("Unable to read output file"),
+            "// Copyright Crabs\n\n// @generated\n\nstruct Leaf;\n",
+            "Invalid contents of the output module `b`",
+        );
+    }
 }
@@ -0,0 +1,529 @@
+use std::{collections, ffi, fs, io, ops, path, str};
+
+use syn::visit::Visit;
+
+use crate::{modgen, services};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Invalid module rename `{0}`, expected `<SEGMENT>=<MODULE>`")]
+    InvalidRename(String),
+    #[error("Invalid package prefix `{0}`")]
+    InvalidPrefix(String),
+    #[error("The packages `{0}` and `{1}` are both generated into the `{2}` module")]
+    Collision(String, String, String),
+    #[error("Failed to read the generated code directory `{1}`: {0}")]
+    ReadDir(io::Error, path::PathBuf),
+    #[error("Failed to read the generated code `{1}`: {0}")]
+    Read(io::Error, path::PathBuf),
+    #[error("Failed to parse the generated code `{1}`: {0}")]
+    Parse(syn::Error, path::PathBuf),
+    #[error("Failed to write the generated code `{1}`: {0}")]
+    Write(io::Error, path::PathBuf),
+}
+
+/// A rename of a protobuf package segment into a Rust module name, e.g. `v1beta1=v1_beta1`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Rename {
+    pub segment: String,
+    pub module: String,
+}
+
+impl str::FromStr for Rename {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (segment, module) = s
+            .split_once('=')
+            .ok_or_else(|| Error::InvalidRename(s.to_string()))?;
+
+        Ok(Self {
+            segment: segment.trim().to_string(),
+            module: module.trim().to_string(),
+        })
+    }
+}
+
+/// Rules mapping the protobuf package segments to the Rust module names, applied to the module
+/// tree and to the references between the generated packages.
+#[derive(Clone, Default, Debug)]
+pub struct Naming {
+    /// Remove the longest of these package prefixes, e.g. `corp`, from every package starting
+    /// with it.
+    pub strip_prefixes: Vec<String>,
+    /// Rename every package segment matching a key into the module name it maps to.
+    pub renames: collections::BTreeMap<String, String>,
+}
+
+impl Naming {
+    pub fn is_empty(&self) -> bool {
+        self.strip_prefixes.is_empty() && self.renames.is_empty()
+    }
+
+    /// The Rust module path of the protobuf package given by its `segments`, as named by prost,
+    /// e.g. `["corp", "billing", "v1beta1"]` becomes `["billing", "v1_beta1"]`.
+    pub fn module_path<S: AsRef<str>>(&self, segments: &[S]) -> Vec<String> {
+        let unraw = |segment: &S| segment.as_ref().trim_start_matches("r#").to_string();
+
+        let stripped = self
+            .strip_prefixes
+            .iter()
+            .map(|prefix| {
+                prefix
+                    .trim_start_matches('.')
+                    .split('.')
+                    .collect::<Vec<_>>()
+            })
+            .filter(|prefix| {
+                prefix.len() <= segments.len()
+                    && prefix.iter().zip(segments).all(|(a, b)| *a == unraw(b))
+            })
+            .map(|prefix| prefix.len())
+            .max()
+            .unwrap_or_default();
+
+        segments[stripped..]
+            .iter()
+            .map(|segment| match self.renames.get(&unraw(segment)) {
+                Some(module) => services::sanitize(module.clone()),
+                None => segment.as_ref().to_string(),
+            })
+            .collect()
+    }
+
+    /// The protobuf `package` as it is laid out in the module tree, e.g. `corp.billing.v1beta1`
+    /// becomes `billing.v1_beta1`.
+    pub fn package(&self, package: &str) -> String {
+        let segments = package
+            .split('.')
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>();
+
+        self.module_path(&segments)
+            .iter()
+            .map(|module| module.trim_start_matches("r#"))
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+}
+
+/// Ensure the rules are valid and do not map different packages of the `fds` into the same
+/// module.
+pub fn validate(fds: &prost_types::FileDescriptorSet, naming: &Naming) -> Result<(), Error> {
+    naming.strip_prefixes.iter().try_for_each(|prefix| {
+        match prefix.trim_start_matches('.').split('.').any(str::is_empty) {
+            true => Err(Error::InvalidPrefix(prefix.clone())),
+            false => Ok(()),
+        }
+    })?;
+    naming.renames.iter().try_for_each(|(segment, module)| {
+        let is_identifier = !module.is_empty()
+            && module != "_"
+            && module
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        match !segment.is_empty() && is_identifier {
+            true => Ok(()),
+            false => Err(Error::InvalidRename(format!("{segment}={module}"))),
+        }
+    })?;
+
+    let packages = fds
+        .file
+        .iter()
+        .map(|file| file.package())
+        .collect::<collections::BTreeSet<_>>();
+
+    let mut modules = collections::BTreeMap::new();
+    packages.into_iter().try_for_each(|package| {
+        let segments = package
+            .split('.')
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>();
+        let module = naming.module_path(&segments).join("::");
+
+        match modules.insert(module.clone(), package) {
+            Some(other) => Err(Error::Collision(
+                other.to_string(),
+                package.to_string(),
+                module,
+            )),
+            None => Ok(()),
+        }
+    })
+}
+
+/// The module paths of the packages of the `fds`, keyed by the file prost generates every package
+/// into, once renamed with the `naming` rules.
+pub fn package_modules(
+    fds: &prost_types::FileDescriptorSet,
+    naming: &Naming,
+) -> modgen::PackageModules {
+    fds.file
+        .iter()
+        .map(|file| generated(file.package(), naming))
+        .collect()
+}
+
+/// The protobuf files of the `fds`, keyed by the name of the file prost generates their package
+/// into, once renamed.
+pub fn package_protos(
+    fds: &prost_types::FileDescriptorSet,
+    naming: &Naming,
+) -> collections::BTreeMap<ffi::OsString, Vec<String>> {
+    let mut protos = collections::BTreeMap::<_, Vec<_>>::new();
+    fds.file.iter().for_each(|file| {
+        let (file_name, _) = generated(file.package(), naming);
+        protos
+            .entry(ffi::OsString::from(file_name))
+            .or_default()
+            .push(file.name().to_string());
+    });
+
+    protos
+}
+
+/// The name of the file prost generates the `package` into, once renamed, along with its module
+/// path.
+fn generated(package: &str, naming: &Naming) -> (String, Vec<String>) {
+    let module = prost_build::Module::from_protobuf_package_name(package);
+    let path = naming.module_path(&module.parts().collect::<Vec<_>>());
+
+    match naming.is_empty() {
+        true => (module.to_file_name_or("_"), path),
+        false => (file_name(&path), path),
+    }
+}
+
+/// The package segments of a file generated by prost, e.g. `crabs.sponge_bob.rs`.
+fn segments(file: &path::Path) -> Option<Vec<String>> {
+    let stem = file.file_stem()?.to_str()?;
+
+    match stem {
+        "_" => Some(vec![]),
+        stem => Some(stem.split('.').map(str::to_string).collect()),
+    }
+}
+
+/// The file prost would have generated the package with the module `path` into.
+fn file_name(path: &[String]) -> String {
+    match path {
+        [] => "_.rs".to_string(),
+        path => format!("{}.rs", path.join(".")),
+    }
+}
+
+/// Rewrites the `super::` paths of a generated file, relative to the module they are in, so they
+/// keep pointing at the same items once the packages are renamed.
+struct Rewriter<'a> {
+    naming: &'a Naming,
+    packages: &'a collections::BTreeSet<Vec<String>>,
+    module: Vec<String>,
+    edits: Vec<(ops::Range<usize>, String)>,
+}
+
+impl Rewriter<'_> {
+    /// Map the absolute module `path`, starting with the longest known package, to its new path.
+    fn map(&self, path: &[String]) -> Vec<String> {
+        let Some(package) = (0..=path.len())
+            .rev()
+            .find(|len| self.packages.contains(&path[..*len]))
+        else {
+            return path.to_vec();
+        };
+
+        let mut mapped = self.naming.module_path(&path[..package]);
+        mapped.extend_from_slice(&path[package..]);
+        mapped
+    }
+
+    /// The new module prefix of the relative `path`, up to its last segment, if it changes.
+    fn relative(&self, path: &syn::Path) -> Option<String> {
+        let supers = path
+            .segments
+            .iter()
+            .take_while(|segment| segment.ident == "super")
+            .count();
+        if supers == 0
+            || supers >= path.segments.len()
+            || supers > self.module.len()
+            || path.leading_colon.is_some()
+        {
+            return None;
+        }
+
+        let modules = path
+            .segments
+            .iter()
+            .skip(supers)
+            .take(path.segments.len() - supers - 1)
+            .map(|segment| segment.ident.to_string())
+            .collect::<Vec<_>>();
+        let mut target = self.module[..self.module.len() - supers].to_vec();
+        target.extend_from_slice(&modules);
+
+        let current = self.map(&self.module);
+        let target = self.map(&target);
+        let common = current
+            .iter()
+            .zip(&target)
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        let prefix = |supers: usize, modules: &[String]| {
+            let mut prefix = "super::".repeat(supers);
+            modules.iter().for_each(|module| {
+                prefix.push_str(module);
+                prefix.push_str("::");
+            });
+            prefix
+        };
+
+        let old = prefix(supers, &modules);
+        let new = prefix(current.len() - common, &target[common..]);
+
+        (old != new).then_some(new)
+    }
+
+    /// Rewrite the relative paths in a string of a `#[prost(...)]` attribute, e.g.
+    /// `enumeration = "super::ferris::Type"`.
+    fn rewrite_str(&self, value: &str) -> Option<String> {
+        let mut rewritten = String::new();
+        let mut rest = value;
+
+        while let Some(start) = rest.find("super::") {
+            let len = rest[start..]
+                .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':' || c == '#'))
+                .unwrap_or(rest.len() - start);
+            let text = &rest[start..start + len];
+
+            rewritten.push_str(&rest[..start]);
+            match syn::parse_str::<syn::Path>(text)
+                .ok()
+                .and_then(|path| Some((self.relative(&path)?, path.segments.last()?.clone())))
+            {
+                Some((prefix, last)) => {
+                    rewritten.push_str(&prefix);
+                    rewritten.push_str(&last.ident.to_string());
+                }
+                None => rewritten.push_str(text),
+            }
+
+            rest = &rest[start + len..];
+        }
+        rewritten.push_str(rest);
+
+        (rewritten != value).then_some(rewritten)
+    }
+}
+
+impl<'ast> Visit<'ast> for Rewriter<'_> {
+    fn visit_item_mod(&mut self, item: &'ast syn::ItemMod) {
+        self.module.push(item.ident.to_string());
+        syn::visit::visit_item_mod(self, item);
+        self.module.pop();
+    }
+
+    fn visit_path(&mut self, path: &'ast syn::Path) {
+        if let Some(prefix) = self.relative(path) {
+            let start = path.segments[0].ident.span().byte_range().start;
+            let end = path.segments[path.segments.len() - 1]
+                .ident
+                .span()
+                .byte_range()
+                .start;
+
+            self.edits.push((start..end, prefix));
+        }
+
+        syn::visit::visit_path(self, path);
+    }
+
+    fn visit_attribute(&mut self, attribute: &'ast syn::Attribute) {
+        let tokens = match &attribute.meta {
+            syn::Meta::List(list) if list.path.is_ident("prost") => list.tokens.clone(),
+            _ => proc_macro2::TokenStream::new(),
+        };
+
+        tokens.into_iter().for_each(|token| {
+            let proc_macro2::TokenTree::Literal(literal) = token else {
+                return;
+            };
+            let range = literal.span().byte_range();
+
+            let Ok(value) =
+                syn::parse2::<syn::LitStr>(proc_macro2::TokenTree::Literal(literal).into())
+            else {
+                return;
+            };
+            if let Some(value) = self.rewrite_str(&value.value()) {
+                self.edits.push((range, format!("{value:?}")));
+            }
+        });
+
+        syn::visit::visit_attribute(self, attribute);
+    }
+}
+
+/// Rewrite the generated `code` of the `package`, keeping the relative paths between the
+/// `packages` valid once they are renamed.
+fn rewrite(
+    code: &str,
+    naming: &Naming,
+    packages: &collections::BTreeSet<Vec<String>>,
+    package: &[String],
+) -> syn::Result<String> {
+    let file = syn::parse_file(code)?;
+
+    let mut rewriter = Rewriter {
+        naming,
+        packages,
+        module: package.to_vec(),
+        edits: vec![],
+    };
+    rewriter.visit_file(&file);
+    drop(file);
+    proc_macro2::extra::invalidate_current_thread_spans();
+
+    let mut code = code.to_string();
+    rewriter.edits.sort_by_key(|(range, _)| range.start);
+    rewriter
+        .edits
+        .into_iter()
+        .rev()
+        .for_each(|(range, edit)| code.replace_range(range, &edit));
+
+    Ok(code)
+}
+
+/// Rename the files generated by prost into the `dir` after the module paths of their packages,
+/// rewriting the references between the packages to match.
+pub fn apply(dir: &path::Path, naming: &Naming) -> Result<(), Error> {
+    let files = fs::read_dir(dir)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|e| Error::ReadDir(e, dir.to_path_buf()))?
+        .into_iter()
+        .filter(|file| file.extension().is_some_and(|ext| ext == "rs"))
+        .filter_map(|file| Some((segments(&file)?, file)))
+        .collect::<collections::BTreeMap<_, _>>();
+    let packages = files.keys().cloned().collect::<collections::BTreeSet<_>>();
+
+    let files = files
+        .into_iter()
+        .map(|(package, file)| {
+            let code = fs::read_to_string(&file).map_err(|e| Error::Read(e, file.clone()))?;
+            let code = rewrite(&code, naming, &packages, &package)
+                .map_err(|e| Error::Parse(e, file.clone()))?;
+            fs::remove_file(&file).map_err(|e| Error::Write(e, file.clone()))?;
+
+            Ok((dir.join(file_name(&naming.module_path(&package))), code))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    files.into_iter().try_for_each(|(file, code)| {
+        tracing::debug!("Writing the renamed module: {}", file.display());
+
+        fs::write(&file, code).map_err(|e| Error::Write(e, file))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections, fs};
+
+    fn naming() -> super::Naming {
+        super::Naming {
+            strip_prefixes: vec!["corp".to_string()],
+            renames: collections::BTreeMap::from([("v1beta1".to_string(), "v1_beta1".to_string())]),
+        }
+    }
+
+    #[test]
+    fn module_path_strips_and_renames_the_segments() {
+        let naming = naming();
+
+        assert_eq!(
+            naming.module_path(&["corp", "billing", "v1beta1"]),
+            vec!["billing", "v1_beta1"],
+            "Invalid renamed module path",
+        );
+        assert_eq!(
+            naming.module_path(&["crabs", "r#type"]),
+            vec!["crabs", "r#type"],
+            "Expected the other packages to be left intact",
+        );
+    }
+
+    #[test]
+    fn validate_rejects_colliding_packages() {
+        let file = |package: &str| prost_types::FileDescriptorProto {
+            package: Some(package.to_string()),
+            ..Default::default()
+        };
+        let fds = prost_types::FileDescriptorSet {
+            file: vec![file("corp.billing"), file("billing")],
+        };
+
+        let err = super::validate(&fds, &naming());
+        assert!(
+            matches!(&err, Err(super::Error::Collision(_, _, module)) if module == "billing"),
+            "Expected `Err(Error::Collision)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn apply_rewrites_the_references_between_packages() {
+        let dir = tempfile::tempdir().expect("Failed to create a test directory");
+        [
+            (
+                "corp.billing.v1beta1.rs",
+                "/// Ünïcode comment.\npub struct Invoice {\n    \
+                #[prost(enumeration = \"super::super::super::crabs::Kind\", tag = \"1\")]\n    \
+                pub kind: i32,\n    \
+                pub ferris: super::super::super::crabs::Ferris,\n}\n\
+                pub mod invoice {\n    pub struct Line(pub super::super::Tax);\n}\n",
+            ),
+            ("corp.billing.rs", "pub struct Tax;\n"),
+            (
+                "crabs.rs",
+                "pub struct Ferris {\n    \
+                pub invoice: ::core::option::Option<super::corp::billing::v1beta1::Invoice>,\n}\n\
+                pub enum Kind {}\n",
+            ),
+        ]
+        .into_iter()
+        .for_each(|(file, code)| {
+            fs::write(dir.path().join(file), code).expect("Failed to create a test file");
+        });
+
+        super::apply(dir.path(), &naming()).expect("Failed to apply the naming rules");
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("billing.v1_beta1.rs"))
+                .expect("Failed to read the renamed file"),
+            "/// Ünïcode comment.\npub struct Invoice {\n    \
+            #[prost(enumeration = \"super::super::crabs::Kind\", tag = \"1\")]\n    \
+            pub kind: i32,\n    \
+            pub ferris: super::super::crabs::Ferris,\n}\n\
+            pub mod invoice {\n    pub struct Line(pub super::super::Tax);\n}\n",
+            "Invalid rewritten references",
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("crabs.rs")).expect("Failed to read a file"),
+            "pub struct Ferris {\n    \
+            pub invoice: ::core::option::Option<super::billing::v1_beta1::Invoice>,\n}\n\
+            pub enum Kind {}\n",
+            "Invalid rewritten references",
+        );
+        assert!(
+            dir.path().join("billing.rs").exists() && !dir.path().join("corp.billing.rs").exists(),
+            "Expected the files to be renamed",
+        );
+    }
+}
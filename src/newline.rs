@@ -0,0 +1,155 @@
+use std::{fs, io, path};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to read the generated file `{1}`: {0}")]
+    Read(io::Error, path::PathBuf),
+    #[error("Failed to write the generated file `{1}`: {0}")]
+    Write(io::Error, path::PathBuf),
+}
+
+/// The line endings of the generated files.
+#[derive(clap::ValueEnum, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Newline {
+    /// `\n`, as used on Unix
+    Lf,
+    /// `\r\n`, as used on Windows
+    Crlf,
+    /// The line endings of the platform pbuildrs runs on
+    Native,
+}
+
+impl Newline {
+    fn as_bytes(self) -> &'static [u8] {
+        match self {
+            Self::Lf => b"\n",
+            Self::Crlf => b"\r\n",
+            Self::Native if cfg!(windows) => b"\r\n",
+            Self::Native => b"\n",
+        }
+    }
+
+    /// Copy the `reader` into the `writer` line by line with all of its line endings, whichever
+    /// they were, replaced with this one, returning whether any of them changed. The lines are
+    /// copied as raw bytes, so the text doesn't have to be valid UTF-8.
+    fn copy(self, mut reader: impl io::BufRead, mut writer: impl io::Write) -> io::Result<bool> {
+        let newline = self.as_bytes();
+        let mut line = Vec::new();
+        let mut changed = false;
+
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                return Ok(changed);
+            }
+
+            match line.strip_suffix(b"\n") {
+                Some(content) => {
+                    let content = content.strip_suffix(b"\r").unwrap_or(content);
+                    changed |= &line[content.len()..] != newline;
+                    writer.write_all(content)?;
+                    writer.write_all(newline)?;
+                }
+                None => writer.write_all(&line)?,
+            }
+        }
+    }
+}
+
+/// Rewrite the line endings of the generated Rust sources and manifests among the `files` with the
+/// `newline`, leaving any other file, e.g. an encoded descriptor set, untouched. Every file is
+/// streamed into a temporary file next to it, which only replaces it if any line ending changed.
+pub fn apply(files: &[path::PathBuf], newline: Newline) -> Result<(), Error> {
+    files
+        .iter()
+        .filter(|file| {
+            file.extension()
+                .is_some_and(|ext| ext == "rs" || ext == "toml")
+        })
+        .try_for_each(|file| {
+            let read = |e| Error::Read(e, file.clone());
+            let write = |e| Error::Write(e, file.clone());
+
+            let source = fs::File::open(file).map_err(read)?;
+            let permissions = source.metadata().map_err(read)?.permissions();
+            let parent = file
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .unwrap_or(path::Path::new("."));
+            let staged = tempfile::NamedTempFile::new_in(parent).map_err(write)?;
+
+            let mut writer = io::BufWriter::new(staged.as_file());
+            let changed = newline
+                .copy(io::BufReader::new(source), &mut writer)
+                .map_err(read)?;
+            io::Write::flush(&mut writer).map_err(write)?;
+            drop(writer);
+            if !changed {
+                return Ok(());
+            }
+
+            fs::set_permissions(staged.path(), permissions).map_err(write)?;
+            staged.persist(file).map(|_| ()).map_err(|e| write(e.error))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    #[test]
+    fn apply_rewrites_the_line_endings() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let (code, manifest, descriptors) = (
+            dir.path().join("mod.rs"),
+            dir.path().join("Cargo.toml"),
+            dir.path().join("descriptors.bin"),
+        );
+        fs::write(&code, "pub mod crabs;\r\n\npub struct Crab;\n")
+            .expect("Failed to write a test file");
+        fs::write(&manifest, "[package]\n").expect("Failed to write a test file");
+        fs::write(&descriptors, "\n").expect("Failed to write a test file");
+        let files = [code.clone(), manifest.clone(), descriptors.clone()];
+        let read = |file| fs::read_to_string(file).expect("Failed to read a test file");
+
+        super::apply(&files, super::Newline::Crlf).expect("Failed to rewrite the line endings");
+        assert_eq!(
+            read(&code),
+            "pub mod crabs;\r\n\r\npub struct Crab;\r\n",
+            "Expected CRLF line endings",
+        );
+        assert_eq!(
+            read(&manifest),
+            "[package]\r\n",
+            "Expected CRLF line endings"
+        );
+        assert_eq!(
+            read(&descriptors),
+            "\n",
+            "Expected the other files to be untouched"
+        );
+
+        super::apply(&files, super::Newline::Lf).expect("Failed to rewrite the line endings");
+        assert_eq!(
+            read(&code),
+            "pub mod crabs;\n\npub struct Crab;\n",
+            "Expected LF line endings",
+        );
+    }
+
+    #[test]
+    fn apply_keeps_the_non_utf8_bytes() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let code = dir.path().join("mod.rs");
+        fs::write(&code, b"// Crab \xff\r\npub struct Crab;").expect("Failed to write a test file");
+
+        super::apply(std::slice::from_ref(&code), super::Newline::Lf)
+            .expect("Failed to rewrite the line endings");
+        assert_eq!(
+            fs::read(&code).expect("Failed to read a test file"),
+            b"// Crab \xff\npub struct Crab;",
+            "Expected the bytes to be kept with LF line endings",
+        );
+    }
+}
@@ -1,19 +1,72 @@
-use std::{cmp, io};
+use std::{cmp, fmt, io, iter, mem};
 
+/// The UTF-8 byte order mark some tools, mostly on Windows, start the files with.
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// The failures of [`patch_edition`]. The output may already be partially written when any of them
+/// is returned.
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Failed to read the input protobuf file: {0}")]
     Read(io::Error),
     #[error("Failed to write the output protobuf file: {0}")]
     Write(io::Error),
+    #[error("Failed to read the patched protobuf file: {0}")]
+    ReadPatched(io::Error),
     #[error("Failed to parse the protobuf file: Invalid parser state encountered")]
     InvalidState,
+    #[error(
+        "Unsupported edition `{0}`, only the editions {editions} can be replaced",
+        editions = EDITIONS.join(", ")
+    )]
+    UnsupportedEdition(String),
+    #[error("Unsupported edition `{0}`, the edition can not contain escape sequences")]
+    EscapedEdition(String),
 }
 
-#[derive(cmp::PartialEq, Debug)]
+/// The editions whose semantics the replacement is known to preserve, as far as prost is
+/// concerned.
+pub const EDITIONS: [&str; 2] = ["2023", "2024"];
+
+/// Whether [`patch_edition`] rewrote the input.
+#[derive(cmp::PartialEq, Clone, Debug)]
 pub enum Outcome {
+    /// The input did not declare an edition and was copied as is.
     Untouched,
-    Replaced,
+    /// The declaration of this edition, e.g. `2023`, was replaced with the target [`Syntax`].
+    Replaced(String),
+}
+
+impl Outcome {
+    /// The edition the input declared, if it is missing from the [`EDITIONS`].
+    pub fn unknown_edition(&self) -> Option<&str> {
+        match self {
+            Self::Replaced(edition) if !EDITIONS.contains(&edition.as_str()) => Some(edition),
+            _ => None,
+        }
+    }
+}
+
+/// The syntax [`patch_edition`] declares in place of the edition.
+#[derive(
+    clap::ValueEnum, serde::Deserialize, serde::Serialize, Clone, Copy, Default, PartialEq, Debug,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Syntax {
+    /// Replace the edition with `syntax = "proto3"`
+    #[default]
+    Proto3,
+    /// Replace the edition with `syntax = "proto2"`, for the consumers relying on its semantics
+    Proto2,
+}
+
+impl Syntax {
+    pub(crate) fn declaration(self) -> &'static str {
+        match self {
+            Self::Proto3 => r#"syntax = "proto3""#,
+            Self::Proto2 => r#"syntax = "proto2""#,
+        }
+    }
 }
 
 enum State {
@@ -146,13 +199,476 @@ impl TryFrom<State> for CommentContext {
     }
 }
 
-pub fn patch_edition(mut src: impl io::BufRead, mut dst: impl io::Write) -> Result<Outcome, Error> {
+/// Where [`Features`] is within the code following the edition declaration, or the tokenizer of
+/// the [renaming](crate::rename) is within a protobuf file.
+#[derive(Clone, Copy)]
+pub(crate) enum Scan {
+    Code,
+    Slash,
+    LineComment,
+    BlockComment,
+    BlockCommentStar,
+    Quoted(u8),
+    QuotedEscape(u8),
+}
+
+impl Scan {
+    /// The state after the `ch`, and whether the `ch` is part of the code rather than of a comment.
+    fn next(self, ch: u8) -> (Self, bool) {
+        match (self, ch) {
+            (Self::Code, b'/') => (Self::Slash, false),
+            (Self::Code, b'"' | b'\'') => (Self::Quoted(ch), true),
+            (Self::Code, _) => (Self::Code, true),
+            (Self::Slash, b'/') => (Self::LineComment, false),
+            (Self::Slash, b'*') => (Self::BlockComment, false),
+            // A lone slash is not valid protobuf, so it is left for `protoc` to report.
+            (Self::Slash, _) => (Self::Code, true),
+            (Self::LineComment, b'\n') => (Self::Code, false),
+            (Self::LineComment, _) => (Self::LineComment, false),
+            (Self::BlockComment, b'*') | (Self::BlockCommentStar, b'*') => {
+                (Self::BlockCommentStar, false)
+            }
+            (Self::BlockCommentStar, b'/') => (Self::Code, false),
+            (Self::BlockComment | Self::BlockCommentStar, _) => (Self::BlockComment, false),
+            (Self::Quoted(quote), b'\\') => (Self::QuotedEscape(quote), true),
+            (Self::Quoted(quote), _) if ch == quote => (Self::Code, true),
+            (Self::Quoted(quote), _) | (Self::QuotedEscape(quote), _) => {
+                (Self::Quoted(quote), true)
+            }
+        }
+    }
+
+    /// Advance to the state after the `ch`, telling whether the `ch` is a token of the code, and
+    /// whether it is one outside of any string literal, so it can be a punctuation.
+    pub(crate) fn advance(&mut self, ch: u8) -> (bool, bool) {
+        let previous = *self;
+        let (next, is_code) = previous.next(ch);
+        *self = next;
+
+        let is_token = is_code && !ch.is_ascii_whitespace();
+        let is_punctuation =
+            is_token && matches!(previous, Self::Code | Self::Slash) && matches!(next, Self::Code);
+
+        (is_token, is_punctuation)
+    }
+}
+
+/// The code of the `bytes` without the whitespace and the comments, e.g. `features.x=Y`.
+fn tokens(bytes: &[u8]) -> Vec<u8> {
+    let mut scan = Scan::Code;
+
+    bytes
+        .iter()
+        .copied()
+        .filter(|&ch| scan.advance(ch).0)
+        .collect()
+}
+
+/// The line breaks of the `bytes`, each either `\n` or `\r\n` as in the original, so removing the
+/// code around them keeps both the line numbers and the line endings of the file.
+fn line_breaks(bytes: &[u8]) -> Vec<u8> {
+    bytes
+        .iter()
+        .enumerate()
+        .filter(|&(_, &ch)| ch == b'\n')
+        .flat_map(|(pos, _)| match pos.checked_sub(1).map(|pos| bytes[pos]) {
+            Some(b'\r') => &b"\r\n"[..],
+            _ => &b"\n"[..],
+        })
+        .copied()
+        .collect()
+}
+
+/// The presence of the fields, set by the `field_presence` feature of the file, of a message or of
+/// the field itself.
+#[derive(Clone, Copy, PartialEq)]
+enum Presence {
+    /// The default of the editions, which the proto3 fields only have when labeled `optional`.
+    Explicit,
+    Implicit,
+    LegacyRequired,
+}
+
+impl Presence {
+    /// The presence set by the `tokens` of an option entry, e.g. `features.field_presence=IMPLICIT`.
+    fn parse(tokens: &[u8]) -> Option<Self> {
+        match tokens.strip_prefix(b"features.field_presence=")? {
+            b"EXPLICIT" => Some(Self::Explicit),
+            b"IMPLICIT" => Some(Self::Implicit),
+            b"LEGACY_REQUIRED" => Some(Self::LegacyRequired),
+            _ => None,
+        }
+    }
+}
+
+/// Strip the `features.*` entries from the options of a field or an enum value `statement`, e.g.
+/// `string name = 1 [features.field_presence = EXPLICIT];`, giving the expanded fields the
+/// `packed = false` option instead. Every singular `field`, which has the `presence` inherited
+/// from its file or message unless it sets its own, is labeled as needed to keep it: `optional`
+/// for the explicit presence, unless it is implicit in the proto3 `syntax`, and `required` for the
+/// legacy required presence in the proto2 one, which has no unlabeled fields. The line breaks of
+/// the removed entries are kept. Returns `None` when the statement is unchanged.
+fn downgrade_options(statement: &[u8], syntax: Syntax, field: Option<Presence>) -> Option<Vec<u8>> {
+    let mut scan = Scan::Code;
+    let mut nesting = 0usize;
+    let mut open = None;
+    let mut separators = vec![];
+
+    for (pos, &ch) in statement.iter().enumerate() {
+        if !scan.advance(ch).1 {
+            continue;
+        }
+
+        match ch {
+            b'[' if nesting == 0 => {
+                open = Some(pos);
+                nesting += 1;
+            }
+            b'[' | b'(' | b'{' => nesting += 1,
+            b']' if nesting == 1 && open.is_some() => {
+                separators.push(pos);
+                break;
+            }
+            b']' | b')' | b'}' => nesting = nesting.saturating_sub(1),
+            b',' if nesting == 1 && open.is_some() => separators.push(pos),
+            _ => {}
+        }
+    }
+
+    let options = open.zip(separators.last().copied());
+    let entries = options
+        .map(|(open, _)| {
+            iter::once(open)
+                .chain(separators.iter().copied())
+                .zip(separators.iter().copied())
+                .map(|(start, end)| &statement[start + 1..end])
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let (features, mut kept): (Vec<_>, Vec<_>) = entries
+        .into_iter()
+        .partition(|entry| tokens(entry).starts_with(b"features."));
+    let presence = field.map(|presence| {
+        features
+            .iter()
+            .find_map(|entry| Presence::parse(&tokens(entry)))
+            .unwrap_or(presence)
+    });
+    let label: Option<&[u8]> = match (syntax, presence) {
+        (_, None) | (Syntax::Proto3, Some(Presence::Implicit)) => None,
+        (Syntax::Proto2, Some(Presence::LegacyRequired)) => Some(b"required "),
+        (_, Some(_)) => Some(b"optional "),
+    };
+    let Some((open, close)) = options.filter(|_| !features.is_empty()) else {
+        return label.map(|label| [label, statement].concat());
+    };
+
+    // The repeated fields of the syntax files are packed by default, same as in the editions.
+    if features
+        .iter()
+        .any(|entry| tokens(entry) == b"features.repeated_field_encoding=EXPANDED")
+    {
+        kept.push(b"packed = false");
+    }
+    let mut removed = features
+        .iter()
+        .flat_map(|entry| line_breaks(entry))
+        .collect::<Vec<_>>();
+
+    let mut patched = Vec::with_capacity(statement.len());
+    if let Some(label) = label {
+        patched.extend_from_slice(label);
+    }
+    match kept.is_empty() {
+        true => {
+            let head = statement[..open].trim_ascii_end();
+            removed.extend(line_breaks(&statement[head.len()..open]));
+            patched.extend_from_slice(head);
+            patched.extend_from_slice(&removed);
+        }
+        false => {
+            patched.extend_from_slice(&statement[..=open]);
+            patched.extend_from_slice(&kept.join(&b","[..]));
+            patched.extend_from_slice(&removed);
+            patched.push(b']');
+        }
+    }
+    patched.extend_from_slice(&statement[close + 1..]);
+
+    Some(patched)
+}
+
+/// A body of a definition the [`Features`] are within.
+#[derive(Clone, Copy, PartialEq)]
+enum Body {
+    /// The body of a `message` or an `extend`, whose statements may be the fields needing a label.
+    Fields,
+    Other,
+}
+
+/// Downgrades the edition features in the code following the replaced edition declaration: the
+/// `option features.* = ...;` statements, which are only valid in the editions, are removed, and
+/// so are the `features.*` options of the fields and the enum values, see [`downgrade_options`].
+/// The `field_presence` set by the removed statements applies to the fields following them in the
+/// same file or message, as the options are declared ahead of the fields by convention.
+/// Every statement is held back until it ends, or turns out to be a definition with a body, since
+/// it can span multiple lines. The line breaks of the removed code are kept, so the line numbers
+/// reported by `protoc` still match the original file.
+struct Features {
+    syntax: Syntax,
+    /// The bodies of the definitions the statement is nested in, innermost last, along with the
+    /// presence of their fields.
+    bodies: Vec<(Body, Presence)>,
+    /// The presence of the fields set for the whole file.
+    presence: Presence,
+    scan: Scan,
+    /// The nesting level of the brackets, the parentheses and the braces of the option values
+    /// within the statement.
+    nesting: usize,
+    /// The statement held back, starting at its first token, if one is being read.
+    statement: Option<Vec<u8>>,
+    /// The tokens the statement starts with, as far as they are needed to recognize the options.
+    tokens: Vec<u8>,
+}
+
+impl Features {
+    const OPTION: &'static [u8] = b"option";
+    const PREFIX: &'static [u8] = b"optionfeatures.";
+    /// The keywords of the statements in the bodies of the messages which are not fields, or are
+    /// labeled already.
+    const NOT_FIELDS: [&'static [u8]; 6] = [
+        b"option",
+        b"reserved",
+        b"extensions",
+        b"optional",
+        b"required",
+        b"repeated",
+    ];
+
+    fn new(syntax: Syntax) -> Self {
+        Self {
+            syntax,
+            bodies: vec![],
+            presence: Presence::Explicit,
+            scan: Scan::Code,
+            nesting: 0,
+            statement: None,
+            tokens: Vec::with_capacity(Self::PREFIX.len()),
+        }
+    }
+
+    /// The identifier the `statement` starts with, e.g. `message`.
+    fn keyword(statement: &[u8]) -> &[u8] {
+        let len = statement
+            .iter()
+            .take_while(|ch| ch.is_ascii_alphanumeric() || **ch == b'_')
+            .count();
+
+        &statement[..len]
+    }
+
+    /// Whether the ended `statement` is a singular field without a label, which is not a map.
+    fn is_unlabeled_field(&self, statement: &[u8]) -> bool {
+        self.bodies.last().map(|(body, _)| *body) == Some(Body::Fields)
+            && !Self::NOT_FIELDS.contains(&Self::keyword(statement))
+            && !matches!(
+                tokens(statement).as_slice(),
+                b";" | [b'm', b'a', b'p', b'<', ..]
+            )
+    }
+
+    /// The presence of the fields in the innermost body.
+    fn presence(&mut self) -> &mut Presence {
+        match self.bodies.last_mut() {
+            Some((_, presence)) => presence,
+            None => &mut self.presence,
+        }
+    }
+
+    /// Write the ended `statement` to the `dst`, downgrading its features.
+    fn release(&mut self, statement: &[u8], dst: &mut impl io::Write) -> Result<(), Error> {
+        let field = self.is_unlabeled_field(statement).then(|| *self.presence());
+
+        match self.tokens == Self::PREFIX {
+            true => {
+                let tokens = tokens(statement);
+                let option = tokens
+                    .strip_prefix(Self::OPTION)
+                    .and_then(|option| option.strip_suffix(b";"));
+                if let Some(presence) = option.and_then(Presence::parse) {
+                    *self.presence() = presence;
+                }
+
+                dst.write_all(&line_breaks(statement))
+            }
+            false => match downgrade_options(statement, self.syntax, field) {
+                Some(patched) => dst.write_all(&patched),
+                None => dst.write_all(statement),
+            },
+        }
+        .map_err(Error::Write)
+    }
+
+    /// Copy the `chunk` to the `dst`, holding back the statement being read.
+    fn feed(&mut self, chunk: &[u8], dst: &mut impl io::Write) -> Result<(), Error> {
+        let mut copied = 0;
+
+        for (pos, &ch) in chunk.iter().enumerate() {
+            let (is_token, is_punctuation) = self.scan.advance(ch);
+
+            if self.statement.is_none() && is_punctuation && ch == b'}' {
+                self.bodies.pop();
+            }
+            if self.statement.is_none() && is_token && !(is_punctuation && ch == b'}') {
+                dst.write_all(&chunk[copied..pos]).map_err(Error::Write)?;
+                copied = pos;
+                self.statement = Some(vec![]);
+                self.nesting = 0;
+                self.tokens.clear();
+            }
+
+            let Some(mut statement) = self.statement.take() else {
+                continue;
+            };
+
+            statement.extend_from_slice(&chunk[copied..=pos]);
+            copied = pos + 1;
+
+            if is_token && self.tokens.len() < Self::PREFIX.len() {
+                self.tokens.push(ch);
+            }
+
+            match (is_punctuation, ch) {
+                // The values of the options may be message literals, so only the definitions
+                // are released once their body starts.
+                (true, b'{') if self.nesting == 0 && !self.tokens.starts_with(Self::OPTION) => {
+                    let body = match Self::keyword(&statement) {
+                        b"message" | b"extend" => Body::Fields,
+                        _ => Body::Other,
+                    };
+                    let presence = *self.presence();
+                    self.bodies.push((body, presence));
+                    dst.write_all(&statement).map_err(Error::Write)?;
+                }
+                (true, b'[' | b'(' | b'{') => {
+                    self.nesting += 1;
+                    self.statement = Some(statement);
+                }
+                // A body closed before the statement ended, which `protoc` rejects anyway.
+                (true, b'}') if self.nesting == 0 => {
+                    self.bodies.pop();
+                    dst.write_all(&statement).map_err(Error::Write)?;
+                }
+                (true, b']' | b')' | b'}') => {
+                    self.nesting = self.nesting.saturating_sub(1);
+                    self.statement = Some(statement);
+                }
+                (true, b';') if self.nesting == 0 => self.release(&statement, dst)?,
+                _ => self.statement = Some(statement),
+            }
+        }
+
+        match &mut self.statement {
+            Some(statement) => statement.extend_from_slice(&chunk[copied..]),
+            None => dst.write_all(&chunk[copied..]).map_err(Error::Write)?,
+        }
+
+        Ok(())
+    }
+
+    /// Copy the statement still held back at the end of the file, which `protoc` rejects anyway.
+    fn finish(self, dst: &mut impl io::Write) -> Result<(), Error> {
+        match self.statement {
+            Some(statement) => dst.write_all(&statement).map_err(Error::Write),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The value of the edition `declaration`, e.g. `2023` for `edition = /* Ferris */ "2023"`, which
+/// ends with the closing quote of the value.
+fn edition_value(declaration: &[u8]) -> Result<String, Error> {
+    let mut scan = Scan::Code;
+    let open = declaration
+        .iter()
+        .position(|&ch| {
+            scan.advance(ch);
+            matches!(scan, Scan::Quoted(_))
+        })
+        .ok_or(Error::InvalidState)?;
+    let value = declaration
+        .get(open + 1..declaration.len() - 1)
+        .ok_or(Error::InvalidState)?;
+    let edition = String::from_utf8_lossy(value).into_owned();
+
+    match value.contains(&b'\\') {
+        true => Err(Error::EscapedEdition(edition)),
+        false => Ok(edition),
+    }
+}
+
+/// Copy the protobuf file from `src` to `dst`, replacing its `edition = "..."` declaration with the
+/// `syntax` declaration, e.g. `syntax = "proto3"`, so prost can compile it.
+///
+/// Only the first statement of the file is considered, after any whitespace and comments, which
+/// may also appear between the tokens of the declaration. The edition is rejected if its value
+/// contains any escape sequence, as no edition does.
+/// The edition features following a replaced edition are downgraded, as they are invalid in the
+/// `syntax` files: the `option features.* = ...;` statements and the `features.*` options of the
+/// fields are removed, keeping only their line breaks, the singular fields outside of the oneofs
+/// and the maps are labeled `optional` unless the field, its message or the file sets the implicit
+/// presence, and the expanded repeated fields get the `packed = false` option. With the proto2
+/// `syntax`, every one of those fields is labeled, `required` for the legacy required presence and
+/// `optional` otherwise.
+/// Everything else, including the terminating `;`, a leading byte order mark and the `\r\n` line
+/// endings, is copied byte for byte. Files declaring a
+/// syntax, or anything else first, are copied unchanged. The editions missing from the [`EDITIONS`]
+/// are rejected, as their semantics may not survive the replacement, unless `allow_unknown`, in
+/// which case the caller is expected to warn about the [`Outcome::unknown_edition`]. The input
+/// is streamed line by line, so the whole file is never held in memory, and `dst` is not flushed.
+///
+/// ```
+/// let mut patched = vec![];
+/// let outcome = pbuildrs::patcher::patch_edition(
+///     "edition = \"2023\"; // Ferris\npackage crabs;\n".as_bytes(),
+///     &mut patched,
+///     pbuildrs::patcher::Syntax::Proto3,
+///     false,
+/// )
+/// .expect("Failed to patch the protobuf file");
+///
+/// assert_eq!(outcome, pbuildrs::patcher::Outcome::Replaced("2023".to_string()));
+/// assert_eq!(patched, b"syntax = \"proto3\"; // Ferris\npackage crabs;\n");
+/// ```
+pub fn patch_edition(
+    mut src: impl io::BufRead,
+    mut dst: impl io::Write,
+    syntax: Syntax,
+    allow_unknown: bool,
+) -> Result<Outcome, Error> {
     let mut line = Vec::with_capacity(1 << 14);
     // let mut line = Vec::with_capacity(30|29);
     let mut state = State::None;
     let mut outcome = Outcome::Untouched;
+    let mut features = Features::new(syntax);
+    let mut at_start = true;
 
     while src.read_until(b'\n', &mut line).map_err(Error::Read)? > 0 {
+        // The byte order mark is copied as is, `protoc` skips it too.
+        if mem::take(&mut at_start) && line.starts_with(BOM) {
+            dst.write_all(BOM).map_err(Error::Write)?;
+            line.drain(..BOM.len());
+        }
+
+        // The rest of a file whose edition was replaced is only stripped of the feature options.
+        if matches!(outcome, Outcome::Replaced(_)) {
+            features.feed(&line, &mut dst)?;
+            line.clear();
+
+            continue;
+        }
+
         state = line
             .iter()
             .enumerate()
@@ -160,14 +676,19 @@ pub fn patch_edition(mut src: impl io::BufRead, mut dst: impl io::Write) -> Resu
 
         match state.get_bounds() {
             Some((to, Some(from))) => {
+                let edition = edition_value(&line[to..from])?;
+                if !allow_unknown && !EDITIONS.contains(&edition.as_str()) {
+                    return Err(Error::UnsupportedEdition(edition));
+                }
+
                 dst.write_all(&line[0..to]).map_err(Error::Write)?;
-                dst.write_all(r#"syntax = "proto3""#.as_bytes())
+                dst.write_all(syntax.declaration().as_bytes())
                     .map_err(Error::Write)?;
-                dst.write_all(&line[from..]).map_err(Error::Write)?;
+                features.feed(&line[from..], &mut dst)?;
 
                 line.clear();
 
-                outcome = Outcome::Replaced;
+                outcome = Outcome::Replaced(edition);
             }
             Some((to, None)) => {
                 dst.write_all(&line[0..to]).map_err(Error::Write)?;
@@ -188,9 +709,176 @@ pub fn patch_edition(mut src: impl io::BufRead, mut dst: impl io::Write) -> Resu
         };
     }
 
+    features.finish(&mut dst)?;
+
     Ok(outcome)
 }
 
+/// A position in a protobuf file, with the line and the column of the byte counted from one.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// How a patched protobuf file departs from the patch of its source.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Mismatch {
+    /// The patched file has another byte at the position.
+    Differs(Position),
+    /// The patched file ends at the position, before the patch of the source does.
+    Truncated(Position),
+    /// The patched file goes on past the position, where the patch of the source ends.
+    Trailing(Position),
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Differs(position) => write!(f, "differs at {position}"),
+            Self::Truncated(position) => write!(f, "ends early at {position}"),
+            Self::Trailing(position) => write!(f, "has trailing contents at {position}"),
+        }
+    }
+}
+
+/// A writer comparing the bytes written to it with the ones read from the patched file, keeping
+/// track of the position of the first mismatch.
+struct Comparison<R> {
+    patched: R,
+    position: Position,
+    mismatch: Option<Mismatch>,
+    error: Option<io::Error>,
+    buffer: Vec<u8>,
+}
+
+impl<R: io::Read> Comparison<R> {
+    fn new(patched: R) -> Self {
+        Self {
+            patched,
+            position: Position { line: 1, column: 1 },
+            mismatch: None,
+            error: None,
+            buffer: vec![],
+        }
+    }
+
+    /// Read up to the length of the `buffer` from the patched file, stopping only at its end.
+    fn fill(&mut self, len: usize) -> io::Result<usize> {
+        self.buffer.resize(len, 0);
+
+        let mut filled = 0;
+        while filled < len {
+            match self.patched.read(&mut self.buffer[filled..]) {
+                Ok(0) => break,
+                Ok(read) => filled += read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(filled)
+    }
+
+    fn advance(&mut self, ch: u8) {
+        match ch {
+            b'\n' => {
+                self.position.line += 1;
+                self.position.column = 1;
+            }
+            _ => self.position.column += 1,
+        }
+    }
+
+    /// The first mismatch, including the patched file going on past the end of the patch.
+    fn finish(mut self) -> Result<Option<Mismatch>, Error> {
+        if let Some(e) = self.error {
+            return Err(Error::ReadPatched(e));
+        }
+        if self.mismatch.is_none() && self.fill(1).map_err(Error::ReadPatched)? > 0 {
+            self.mismatch = Some(Mismatch::Trailing(self.position));
+        }
+
+        Ok(self.mismatch)
+    }
+}
+
+impl<R: io::Read> io::Write for Comparison<R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.mismatch.is_some() || self.error.is_some() {
+            return Ok(buf.len());
+        }
+
+        // The failures to read the patched file are reported by `finish`, not as the failures to
+        // write the patch of the source.
+        let filled = match self.fill(buf.len()) {
+            Ok(filled) => filled,
+            Err(e) => {
+                self.error = Some(e);
+                return Ok(buf.len());
+            }
+        };
+
+        for (pos, &ch) in buf.iter().enumerate() {
+            if pos == filled {
+                self.mismatch = Some(Mismatch::Truncated(self.position));
+                break;
+            }
+            if self.buffer[pos] != ch {
+                self.mismatch = Some(Mismatch::Differs(self.position));
+                break;
+            }
+            self.advance(ch);
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Check that the `patched` protobuf file is exactly the `src` one patched by [`patch_edition`]
+/// with the `syntax`, without holding either of them in memory, returning how the source was
+/// patched along with the position the patched file departs from it at, if it does.
+///
+/// ```
+/// let (outcome, mismatch) = pbuildrs::patcher::verify_patch(
+///     "edition = \"2023\";\npackage crabs;\n".as_bytes(),
+///     "syntax = \"proto3\";\npackage crab;\n".as_bytes(),
+///     pbuildrs::patcher::Syntax::Proto3,
+///     false,
+/// )
+/// .expect("Failed to verify the patched protobuf file");
+///
+/// assert_eq!(outcome, pbuildrs::patcher::Outcome::Replaced("2023".to_string()));
+/// assert_eq!(
+///     mismatch,
+///     Some(pbuildrs::patcher::Mismatch::Differs(pbuildrs::patcher::Position {
+///         line: 2,
+///         column: 13,
+///     })),
+/// );
+/// ```
+pub fn verify_patch(
+    src: impl io::BufRead,
+    patched: impl io::Read,
+    syntax: Syntax,
+    allow_unknown: bool,
+) -> Result<(Outcome, Option<Mismatch>), Error> {
+    let mut comparison = Comparison::new(patched);
+    let outcome = patch_edition(src, &mut comparison, syntax, allow_unknown)?;
+
+    Ok((outcome, comparison.finish()?))
+}
+
 #[cfg(test)]
 mod tests {
     use std::io;
@@ -207,7 +895,12 @@ message Ferris {
 "#;
 
         let mut output = Vec::new();
-        let result = super::patch_edition(io::BufReader::new(input.as_bytes()), &mut output);
+        let result = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            false,
+        );
         let outcome = result.expect("Faled to copy the data");
 
         assert_eq!(
@@ -234,12 +927,17 @@ message Ferris {
 
         let mut output = Vec::new();
 
-        let result = super::patch_edition(io::BufReader::new(input.as_bytes()), &mut output);
+        let result = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            false,
+        );
 
         let outcome = result.expect("Faled to copy the data");
 
         assert_eq!(
-            super::Outcome::Replaced,
+            super::Outcome::Replaced("2023".to_string()),
             outcome,
             "Expected the edition to be replaced with syntax"
         );
@@ -252,7 +950,7 @@ message Ferris {
 package crabs;
 
 message Ferris {
-  string type = 1;
+  optional string type = 1;
 }
 "#,
             output,
@@ -272,7 +970,12 @@ message Ferris {
 "#;
 
         let mut output = Vec::new();
-        let result = super::patch_edition(io::BufReader::new(input.as_bytes()), &mut output);
+        let result = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            false,
+        );
         let outcome = result.expect("Faled to copy the data");
 
         assert_eq!(
@@ -311,11 +1014,16 @@ message Ferris {
 "#;
 
         let mut output = Vec::new();
-        let result = super::patch_edition(io::BufReader::new(input.as_bytes()), &mut output);
+        let result = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            false,
+        );
         let outcome = result.expect("Faled to copy the data");
 
         assert_eq!(
-            super::Outcome::Replaced,
+            super::Outcome::Replaced("2023".to_string()),
             outcome,
             "Expected the edition to be replaced with syntax"
         );
@@ -330,7 +1038,7 @@ syntax = "proto3";
 package crabs;
 
 message Ferris {
-  string type = 1;
+  optional string type = 1;
 }
 "#,
             output,
@@ -350,7 +1058,12 @@ message Ferris {
 "#;
 
         let mut output = Vec::new();
-        let result = super::patch_edition(io::BufReader::new(input.as_bytes()), &mut output);
+        let result = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            false,
+        );
         let outcome = result.expect("Faled to copy the data");
 
         assert_eq!(
@@ -388,11 +1101,16 @@ message Ferris {
 "#;
 
         let mut output = Vec::new();
-        let result = super::patch_edition(io::BufReader::new(input.as_bytes()), &mut output);
+        let result = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            false,
+        );
         let outcome = result.expect("Faled to copy the data");
 
         assert_eq!(
-            super::Outcome::Replaced,
+            super::Outcome::Replaced("2023".to_string()),
             outcome,
             "Expected the edition to be replaced with syntax"
         );
@@ -406,7 +1124,7 @@ message Ferris {
 package crabs;
 
 message Ferris {
-  string type = 1;
+  optional string type = 1;
 }
 "#,
             output,
@@ -425,7 +1143,12 @@ message Ferris {
 "#;
 
         let mut output = Vec::new();
-        let result = super::patch_edition(io::BufReader::new(input.as_bytes()), &mut output);
+        let result = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            false,
+        );
         let outcome = result.expect("Faled to copy the data");
 
         assert_eq!(
@@ -461,11 +1184,16 @@ message Ferris {
 "#;
 
         let mut output = Vec::new();
-        let result = super::patch_edition(io::BufReader::new(input.as_bytes()), &mut output);
+        let result = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            false,
+        );
         let outcome = result.expect("Faled to copy the data");
 
         assert_eq!(
-            super::Outcome::Replaced,
+            super::Outcome::Replaced("2023".to_string()),
             outcome,
             "Expected the edition to be replaced with syntax"
         );
@@ -478,7 +1206,7 @@ message Ferris {
 package crabs;
 
 message Ferris {
-  string type = 1;
+  optional string type = 1;
 }
 "#,
             output,
@@ -497,7 +1225,12 @@ message Ferris {
 "#;
 
         let mut output = Vec::new();
-        let result = super::patch_edition(io::BufReader::new(input.as_bytes()), &mut output);
+        let result = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            false,
+        );
         let outcome = result.expect("Faled to copy the data");
 
         assert_eq!(
@@ -536,7 +1269,12 @@ message Ferris {
 "#;
 
         let mut output = Vec::new();
-        let result = super::patch_edition(io::BufReader::new(input.as_bytes()), &mut output);
+        let result = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            false,
+        );
         let outcome = result.expect("Faled to copy the data");
 
         assert_eq!(
@@ -580,11 +1318,16 @@ message Ferris {
 "#;
 
         let mut output = Vec::new();
-        let result = super::patch_edition(io::BufReader::new(input.as_bytes()), &mut output);
+        let result = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            false,
+        );
         let outcome = result.expect("Faled to copy the data");
 
         assert_eq!(
-            super::Outcome::Replaced,
+            super::Outcome::Replaced("2023".to_string()),
             outcome,
             "Expected the edition to be replaced with syntax",
         );
@@ -602,7 +1345,7 @@ syntax = "proto3";
 package crabs;
 
 message Ferris {
-  string type = 1;
+  optional string type = 1;
 }
 "#,
             output,
@@ -619,11 +1362,16 @@ message Ferris {}
 "#;
 
         let mut output = Vec::new();
-        let result = super::patch_edition(io::BufReader::new(input.as_bytes()), &mut output);
+        let result = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            false,
+        );
         let outcome = result.expect("Faled to copy the data");
 
         assert_eq!(
-            super::Outcome::Replaced,
+            super::Outcome::Replaced("2023".to_string()),
             outcome,
             "Expected the edition to be replaced with syntax",
         );
@@ -651,11 +1399,16 @@ message Ferris {}
 "#;
 
         let mut output = Vec::new();
-        let result = super::patch_edition(io::BufReader::new(input.as_bytes()), &mut output);
+        let result = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            false,
+        );
         let outcome = result.expect("Faled to copy the data");
 
         assert_eq!(
-            super::Outcome::Replaced,
+            super::Outcome::Replaced("2023".to_string()),
             outcome,
             "Expected the edition to be replaced with syntax",
         );
@@ -686,11 +1439,16 @@ message Ferris {}
 "#;
 
         let mut output = Vec::new();
-        let result = super::patch_edition(io::BufReader::new(input.as_bytes()), &mut output);
+        let result = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            false,
+        );
         let outcome = result.expect("Faled to copy the data");
 
         assert_eq!(
-            super::Outcome::Replaced,
+            super::Outcome::Replaced("2023".to_string()),
             outcome,
             "Expected the edition to be replaced with syntax",
         );
@@ -709,4 +1467,395 @@ message Ferris {}
             output,
         );
     }
+
+    #[test]
+    fn copy_replace_with_proto2() {
+        let input = r#"edition = "2023";
+
+package crabs;
+"#;
+
+        let mut output = Vec::new();
+        let result = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto2,
+            false,
+        );
+        let outcome = result.expect("Faled to copy the data");
+
+        assert_eq!(
+            super::Outcome::Replaced("2023".to_string()),
+            outcome,
+            "Expected the edition to be replaced with syntax",
+        );
+
+        let output = String::from_utf8(output).expect("The resulting copy is corrupted");
+
+        assert_eq!(
+            r#"syntax = "proto2";
+
+package crabs;
+"#,
+            output,
+        );
+    }
+
+    #[test]
+    fn copy_replace_labeling_proto2_fields() {
+        let input = r#"edition = "2023";
+
+package crabs;
+
+message Ferris {
+  string name = 1;
+  .crabs.Shell shell = 2 [features.field_presence = LEGACY_REQUIRED];
+  repeated int32 legs = 3;
+  map<string, string> tags = 4;
+  reserved 5;
+  oneof claw {
+    string left = 6;
+  }
+  message Shell {
+    uint32 spots = 1 [deprecated = true];
+  }
+}
+
+enum Mood {
+  MOOD_UNSPECIFIED = 0;
+}
+"#;
+
+        let mut output = Vec::new();
+        let result = super::patch_edition(
+            io::BufReader::with_capacity(16, input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto2,
+            false,
+        );
+        result.expect("Faled to copy the data");
+
+        let output = String::from_utf8(output).expect("The resulting copy is corrupted");
+
+        assert_eq!(
+            r#"syntax = "proto2";
+
+package crabs;
+
+message Ferris {
+  optional string name = 1;
+  required .crabs.Shell shell = 2;
+  repeated int32 legs = 3;
+  map<string, string> tags = 4;
+  reserved 5;
+  oneof claw {
+    string left = 6;
+  }
+  message Shell {
+    optional uint32 spots = 1 [deprecated = true];
+  }
+}
+
+enum Mood {
+  MOOD_UNSPECIFIED = 0;
+}
+"#,
+            output,
+        );
+    }
+
+    #[test]
+    fn copy_replace_removing_file_features() {
+        let input = r#"edition = "2023";
+
+package crabs;
+
+option features.field_presence = IMPLICIT;
+option java_package = "com.crabs"; // features.enum_type = OPEN;
+option features.(pb.cpp) = {
+  legacy_closed_enum: true
+};
+option /* Sebastian */ features
+  .enum_type = CLOSED;
+
+message Ferris {
+  option features.utf8_validation = NONE;
+  string type = 1 [default = "option features.x = 1;"];
+}
+"#;
+
+        let mut output = Vec::new();
+        let result = super::patch_edition(
+            io::BufReader::with_capacity(16, input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            false,
+        );
+        let outcome = result.expect("Faled to copy the data");
+
+        assert_eq!(
+            super::Outcome::Replaced("2023".to_string()),
+            outcome,
+            "Expected the edition to be replaced with syntax",
+        );
+
+        let output = String::from_utf8(output).expect("The resulting copy is corrupted");
+
+        assert_eq!(
+            r#"syntax = "proto3";
+
+package crabs;
+
+
+option java_package = "com.crabs"; // features.enum_type = OPEN;
+
+
+
+
+
+
+message Ferris {
+  
+  string type = 1 [default = "option features.x = 1;"];
+}
+"#,
+            output,
+        );
+    }
+
+    #[test]
+    fn copy_replace_downgrading_field_features() {
+        let input = r#"edition = "2023";
+
+package crabs;
+
+message Ferris {
+  string name = 1 [features.field_presence = EXPLICIT];
+  uint32 claws = 2 [
+    deprecated = true,
+    features.field_presence = IMPLICIT
+  ];
+  repeated int32 legs = 3 [features.repeated_field_encoding = EXPANDED, deprecated = true];
+  map<string, string> tags = 4;
+}
+
+enum Shell {
+  option features.enum_type = CLOSED;
+  SHELL_UNSPECIFIED = 0 [features.(pb.cpp).legacy = true];
+}
+"#;
+
+        let mut output = Vec::new();
+        let result = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            false,
+        );
+        let outcome = result.expect("Faled to copy the data");
+
+        assert_eq!(
+            super::Outcome::Replaced("2023".to_string()),
+            outcome,
+            "Expected the edition to be replaced with syntax",
+        );
+
+        let output = String::from_utf8(output).expect("The resulting copy is corrupted");
+
+        assert_eq!(
+            r#"syntax = "proto3";
+
+package crabs;
+
+message Ferris {
+  optional string name = 1;
+  uint32 claws = 2 [
+    deprecated = true
+
+];
+  repeated int32 legs = 3 [ deprecated = true,packed = false];
+  map<string, string> tags = 4;
+}
+
+enum Shell {
+  
+  SHELL_UNSPECIFIED = 0;
+}
+"#,
+            output,
+        );
+    }
+
+    #[test]
+    fn copy_replace_keeping_the_default_presence() {
+        let input = r#"edition = "2023";
+
+package crabs;
+
+message Ferris {
+  string name = 1;
+  .crabs.Mood mood = 2;
+  Shell shell = 3;
+  repeated int32 legs = 4;
+  map<string, string> tags = 5;
+  oneof claw {
+    string left = 6;
+  }
+  message Shell {
+    option features.field_presence = IMPLICIT;
+    uint32 spots = 1;
+    uint32 cracks = 2 [features.field_presence = EXPLICIT];
+  }
+  int32 age = 7;
+}
+"#;
+
+        let mut output = Vec::new();
+        let result = super::patch_edition(
+            io::BufReader::with_capacity(16, input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            false,
+        );
+        result.expect("Faled to copy the data");
+
+        let output = String::from_utf8(output).expect("The resulting copy is corrupted");
+
+        assert_eq!(
+            r#"syntax = "proto3";
+
+package crabs;
+
+message Ferris {
+  optional string name = 1;
+  optional .crabs.Mood mood = 2;
+  optional Shell shell = 3;
+  repeated int32 legs = 4;
+  map<string, string> tags = 5;
+  oneof claw {
+    string left = 6;
+  }
+  message Shell {
+    
+    uint32 spots = 1;
+    optional uint32 cracks = 2;
+  }
+  optional int32 age = 7;
+}
+"#,
+            output,
+        );
+    }
+
+    #[test]
+    fn copy_unchanged_keeping_file_features() {
+        let input = r#"syntax = "proto2";
+
+option features.field_presence = IMPLICIT;
+"#;
+
+        let mut output = Vec::new();
+        let result = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            false,
+        );
+        let outcome = result.expect("Faled to copy the data");
+
+        assert_eq!(
+            super::Outcome::Untouched,
+            outcome,
+            "Expected the file to be copied without changes",
+        );
+        assert_eq!(
+            input.as_bytes(),
+            output.as_slice(),
+            "Expected only the files with an edition to be stripped of the features",
+        );
+    }
+
+    #[test]
+    fn copy_replace_keeping_bom_and_crlf() {
+        let input = "\u{feff}// Ferris\r\nedition = \"2023\";\r\n\r\n\
+            option features.field_presence = IMPLICIT;\r\n\r\n\
+            message Ferris {\r\n  string name = 1 [\r\n    features.field_presence = EXPLICIT\r\n  ];\r\n}\r\n";
+
+        let mut output = Vec::new();
+        let result = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            false,
+        );
+        let outcome = result.expect("Faled to copy the data");
+
+        assert_eq!(
+            super::Outcome::Replaced("2023".to_string()),
+            outcome,
+            "Expected the edition behind the byte order mark to be replaced with syntax",
+        );
+
+        let output = String::from_utf8(output).expect("The resulting copy is corrupted");
+
+        assert_eq!(
+            "\u{feff}// Ferris\r\nsyntax = \"proto3\";\r\n\r\n\r\n\r\n\
+            message Ferris {\r\n  optional string name = 1\r\n\r\n;\r\n}\r\n",
+            output,
+        );
+    }
+
+    #[test]
+    fn copy_rejects_unknown_edition() {
+        let input = "edition = \"2099\";\npackage crabs;\n";
+
+        let mut output = Vec::new();
+        let result = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            false,
+        );
+        assert!(
+            matches!(&result, Err(super::Error::UnsupportedEdition(edition)) if edition == "2099"),
+            "Expected `Err(Error::UnsupportedEdition)`, got: `{:?}`",
+            result
+        );
+
+        let mut output = Vec::new();
+        let outcome = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            &mut output,
+            super::Syntax::Proto3,
+            true,
+        )
+        .expect("Faled to copy the data");
+
+        assert_eq!(
+            outcome.unknown_edition(),
+            Some("2099"),
+            "Expected the unknown edition to be reported",
+        );
+        assert_eq!(
+            output, b"syntax = \"proto3\";\npackage crabs;\n",
+            "Expected the unknown edition to be replaced",
+        );
+    }
+
+    #[test]
+    fn copy_rejects_escaped_edition() {
+        let input = "edition = /* \"2099\" */ \"20\\\"23\";\npackage crabs;\n";
+
+        let result = super::patch_edition(
+            io::BufReader::new(input.as_bytes()),
+            Vec::new(),
+            super::Syntax::Proto3,
+            true,
+        );
+        assert!(
+            matches!(&result, Err(super::Error::EscapedEdition(edition)) if edition == "20\\\"23"),
+            "Expected `Err(Error::EscapedEdition)`, got: `{:?}`",
+            result
+        );
+    }
 }
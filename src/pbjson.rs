@@ -0,0 +1,266 @@
+use crate::attributes;
+use prost::Message;
+use std::{collections, fs, io, path};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(
+        "The pbjson implementations conflict with the serde derives, set either `--pbjson` or \
+        `--serde`"
+    )]
+    Serde,
+    #[error(
+        "The well-known types are taken from `pbjson-types` for their canonical JSON mapping, \
+        they can not be compiled with `--with-well-known-types`"
+    )]
+    WellKnownTypes,
+    #[error(
+        "pbjson deserializes either every map field of `{0}` and the messages nested in it into a \
+        `BTreeMap`, or none of them, the `btree-map` paths must match them alike"
+    )]
+    BTreeMap(String),
+    #[error("Failed to generate the pbjson implementations: {0}")]
+    Generate(io::Error),
+    #[error("Failed to append the pbjson implementations to `{1}`: {0}")]
+    Write(io::Error, path::PathBuf),
+}
+
+/// The well-known types mapped to the canonical JSON, taken from `pbjson-types` instead of
+/// `prost-types`.
+const WELL_KNOWN_TYPES: (&str, &str) = (".google.protobuf", "::pbjson_types");
+
+/// Ensure the pbjson implementations are generated neither along with the serde derives of the
+/// `serde` packages, nor for the well-known types compiled `with_well_known_types`.
+pub fn validate(serde: bool, with_well_known_types: bool) -> Result<(), Error> {
+    if serde {
+        return Err(Error::Serde);
+    }
+    if with_well_known_types {
+        return Err(Error::WellKnownTypes);
+    }
+
+    Ok(())
+}
+
+/// The `extern_paths` of a target along with the well-known types from `pbjson-types`, unless
+/// they are mapped already.
+pub fn extern_paths(extern_paths: &[(String, String)]) -> Vec<(String, String)> {
+    let (proto_path, rust_path) = WELL_KNOWN_TYPES;
+    let mut paths = extern_paths.to_vec();
+    if !paths.iter().any(|(path, _)| path == proto_path) {
+        paths.push((proto_path.to_string(), rust_path.to_string()));
+    }
+
+    paths
+}
+
+/// Whether the map fields of the messages are matched by the `btree_map` paths, by the fully
+/// qualified names of the messages with any map fields.
+fn push_map_fields(
+    found: &mut Vec<(String, bool)>,
+    scope: &str,
+    messages: &[prost_types::DescriptorProto],
+    btree_map: &[String],
+) -> Result<(), Error> {
+    messages.iter().try_for_each(|message| {
+        let name = format!("{scope}.{}", message.name());
+        let entries = message
+            .nested_type
+            .iter()
+            .filter(|nested| nested.options.as_ref().is_some_and(|o| o.map_entry()))
+            .map(|nested| format!("{name}.{}", nested.name()))
+            .collect::<Vec<_>>();
+        let matched = message
+            .field
+            .iter()
+            .filter(|field| entries.iter().any(|entry| entry == field.type_name()))
+            .map(|field| {
+                let field = format!("{name}.{}", field.name());
+                btree_map
+                    .iter()
+                    .any(|path| attributes::matches(path, &field))
+            })
+            .collect::<collections::BTreeSet<_>>();
+
+        match matched.len() {
+            0 => {}
+            1 => found.extend(matched.into_iter().map(|matched| (name.clone(), matched))),
+            _ => return Err(Error::BTreeMap(name)),
+        }
+        push_map_fields(found, &name, &message.nested_type, btree_map)
+    })
+}
+
+/// The paths of the messages pbjson deserializes the map fields of into a `BTreeMap`, matching
+/// the map fields generated as `BTreeMap` by prost for the `btree_map` paths. Unlike prost, pbjson
+/// matches the messages along with the ones nested in them, so the map fields of a message and
+/// its nested messages need to be matched alike.
+fn btree_map_messages(
+    fds: &prost_types::FileDescriptorSet,
+    btree_map: &[String],
+) -> Result<Vec<String>, Error> {
+    let mut found = vec![];
+    fds.file.iter().try_for_each(|file| {
+        let scope = match file.package() {
+            "" => String::new(),
+            package => format!(".{package}"),
+        };
+        push_map_fields(&mut found, &scope, &file.message_type, btree_map)
+    })?;
+
+    let (matched, unmatched): (Vec<_>, Vec<_>) =
+        found.into_iter().partition(|(_, matched)| *matched);
+    unmatched
+        .iter()
+        .find(|(name, _)| {
+            matched
+                .iter()
+                .any(|(message, _)| name.starts_with(&format!("{message}.")))
+        })
+        .map_or(Ok(()), |(name, _)| Err(Error::BTreeMap(name.clone())))?;
+
+    Ok(matched.into_iter().map(|(name, _)| name).collect())
+}
+
+/// Generate the `serde::Serialize` and `serde::Deserialize` implementations of the messages and
+/// enums in the `fds` with pbjson, and append them to the files of their packages in the `dir`,
+/// so they end up in the same modules as the types. The types of the `extern_paths` are left out,
+/// and the map fields matching the `btree_map` paths are deserialized into a `BTreeMap`.
+pub fn write(
+    fds: &prost_types::FileDescriptorSet,
+    dir: &path::Path,
+    extern_paths: &[(String, String)],
+    btree_map: &[String],
+) -> Result<(), Error> {
+    let mut builder = pbjson_build::Builder::new();
+    builder
+        .register_descriptors(&fds.encode_to_vec())
+        .map_err(Error::Generate)?;
+    extern_paths.iter().for_each(|(proto_path, rust_path)| {
+        builder.extern_path(proto_path, rust_path);
+    });
+    builder.exclude(
+        extern_paths
+            .iter()
+            .map(|(proto_path, _)| proto_path.clone()),
+    );
+    builder.btree_map(btree_map_messages(fds, btree_map)?);
+
+    fds.file
+        .iter()
+        .filter(|file| file.package().is_empty())
+        .for_each(|file| {
+            tracing::warn!(
+                "Skipping the pbjson implementations of `{}`, which has no package",
+                file.name()
+            );
+        });
+    let packages = fds
+        .file
+        .iter()
+        .map(prost_types::FileDescriptorProto::package)
+        .filter(|package| !package.is_empty())
+        .map(|package| format!(".{package}"))
+        .collect::<collections::BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    builder
+        .generate(&packages, |_| Ok(vec![]))
+        .map_err(Error::Generate)?
+        .into_iter()
+        .try_for_each(|(package, code)| {
+            let path = dir.join(format!("{package}.rs"));
+            tracing::debug!("Appending the pbjson implementations: {}", path.display());
+
+            fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&path)
+                .and_then(|mut out| io::Write::write_all(&mut out, &code))
+                .map_err(|e| Error::Write(e, path))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_types::field_descriptor_proto::{Label, Type};
+
+    fn message(name: &str, maps: &[&str]) -> prost_types::DescriptorProto {
+        let entries = maps.iter().map(|map| prost_types::DescriptorProto {
+            name: Some(format!("{}Entry", crate::services::to_upper_camel(map))),
+            options: Some(prost_types::MessageOptions {
+                map_entry: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        prost_types::DescriptorProto {
+            name: Some(name.to_string()),
+            field: maps
+                .iter()
+                .map(|map| prost_types::FieldDescriptorProto {
+                    name: Some((*map).to_string()),
+                    r#type: Some(Type::Message.into()),
+                    label: Some(Label::Repeated.into()),
+                    type_name: Some(format!(
+                        ".crabs.{name}.{}Entry",
+                        crate::services::to_upper_camel(map)
+                    )),
+                    ..Default::default()
+                })
+                .collect(),
+            nested_type: entries.collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn btree_map_messages_match_the_messages_of_the_map_fields() {
+        let fds = prost_types::FileDescriptorSet {
+            file: vec![prost_types::FileDescriptorProto {
+                package: Some("crabs".to_string()),
+                message_type: vec![
+                    message("Ferris", &["claws", "legs"]),
+                    message("Shell", &["spots"]),
+                ],
+                ..Default::default()
+            }],
+        };
+
+        let messages = super::btree_map_messages(&fds, &[".crabs.Ferris".to_string()])
+            .expect("Failed to match the messages");
+        assert_eq!(
+            messages,
+            [".crabs.Ferris"],
+            "Expected only the message of the matching map fields",
+        );
+
+        let err = super::btree_map_messages(&fds, &[".crabs.Ferris.claws".to_string()]);
+        assert!(
+            matches!(&err, Err(super::Error::BTreeMap(message)) if message == ".crabs.Ferris"),
+            "Expected `Err(Error::BTreeMap)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn extern_paths_keep_the_mapped_well_known_types() {
+        assert_eq!(
+            super::extern_paths(&[(".shells".to_string(), "::shells".to_string())]),
+            [
+                (".shells".to_string(), "::shells".to_string()),
+                (".google.protobuf".to_string(), "::pbjson_types".to_string()),
+            ],
+            "Expected the well-known types from `pbjson-types`",
+        );
+
+        let mapped = [(".google.protobuf".to_string(), "::wkt".to_string())];
+        assert_eq!(
+            super::extern_paths(&mapped),
+            mapped,
+            "Expected the mapped well-known types to be kept",
+        );
+    }
+}
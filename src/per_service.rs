@@ -0,0 +1,233 @@
+use std::collections;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("The client and server generation is set for `{0}`, which is not a service")]
+    UnknownService(String),
+}
+
+/// Whether to generate the client and the server of a service, falling back to the setting of
+/// the target for the unset ones.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Sides {
+    pub client: Option<bool>,
+    pub server: Option<bool>,
+}
+
+/// The generated sides of the services, keyed by the fully qualified name of the service, e.g.
+/// `crabs.CrabService`.
+pub type Overrides = collections::BTreeMap<String, Sides>;
+
+fn full_name(package: &str, service: &str) -> String {
+    match package {
+        "" => service.to_string(),
+        package => format!("{package}.{service}"),
+    }
+}
+
+/// Ensure the sides are set for the services of the `fds` only.
+pub fn validate(fds: &prost_types::FileDescriptorSet, overrides: &Overrides) -> Result<(), Error> {
+    let services = fds
+        .file
+        .iter()
+        .flat_map(|file| {
+            file.service
+                .iter()
+                .map(|service| full_name(file.package(), service.name()))
+        })
+        .collect::<collections::BTreeSet<_>>();
+
+    match overrides
+        .keys()
+        .find(|service| !services.contains(*service))
+    {
+        Some(service) => Err(Error::UnknownService(service.clone())),
+        None => Ok(()),
+    }
+}
+
+/// The sides generated for every service of a target, the `client` and the `server` of the target
+/// unless overridden.
+#[derive(Clone, Debug)]
+pub struct Selection {
+    pub overrides: Overrides,
+    pub client: bool,
+    pub server: bool,
+}
+
+impl Selection {
+    /// Whether the client and the server of the `service`, given by its fully qualified name, are
+    /// generated.
+    fn sides(&self, service: &str) -> (bool, bool) {
+        let sides = self.overrides.get(service).copied().unwrap_or_default();
+
+        (
+            sides.client.unwrap_or(self.client),
+            sides.server.unwrap_or(self.server),
+        )
+    }
+
+    /// Whether the client, or the server, of any service may be generated.
+    pub fn any(&self) -> (bool, bool) {
+        self.overrides
+            .values()
+            .fold((self.client, self.server), |(client, server), sides| {
+                (
+                    client || sides.client.unwrap_or_default(),
+                    server || sides.server.unwrap_or_default(),
+                )
+            })
+    }
+
+    fn retain(
+        &self,
+        fds: &prost_types::FileDescriptorSet,
+        side: impl Fn((bool, bool)) -> bool,
+    ) -> prost_types::FileDescriptorSet {
+        let mut fds = fds.clone();
+        fds.file.iter_mut().for_each(|file| {
+            let package = file.package().to_string();
+            file.service
+                .retain(|service| side(self.sides(&full_name(&package, service.name()))));
+        });
+
+        fds
+    }
+
+    /// The `fds` without the services whose client is not generated, for the code built on top of
+    /// the clients.
+    pub fn clients(&self, fds: &prost_types::FileDescriptorSet) -> prost_types::FileDescriptorSet {
+        self.retain(fds, |(client, _)| client)
+    }
+
+    /// The `fds` without the services whose server is not generated, for the code built on top of
+    /// the servers.
+    pub fn servers(&self, fds: &prost_types::FileDescriptorSet) -> prost_types::FileDescriptorSet {
+        self.retain(fds, |(_, server)| server)
+    }
+
+    /// A service generator handing every service to a generator of the `codegen` configured with
+    /// the sides of the service, and the tonic `transport` if enabled.
+    pub fn generator(self, codegen: &tonic_prost_build::Builder, transport: bool) -> Generator {
+        let generators = [(true, true), (true, false), (false, true)]
+            .into_iter()
+            .map(|(client, server)| {
+                let generator = codegen
+                    .clone()
+                    .build_client(client)
+                    .build_server(server)
+                    .build_transport(transport)
+                    .service_generator();
+
+                ((client, server), generator)
+            })
+            .collect();
+
+        Generator {
+            selection: self,
+            generators,
+        }
+    }
+}
+
+/// A prost service generator generating the client and the server of every service according to
+/// its [`Sides`].
+pub struct Generator {
+    selection: Selection,
+    generators: collections::BTreeMap<(bool, bool), Box<dyn prost_build::ServiceGenerator>>,
+}
+
+impl prost_build::ServiceGenerator for Generator {
+    fn generate(&mut self, service: prost_build::Service, buf: &mut String) {
+        let sides = self
+            .selection
+            .sides(&full_name(&service.package, &service.proto_name));
+
+        if let Some(generator) = self.generators.get_mut(&sides) {
+            generator.generate(service, buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections;
+
+    fn fds() -> prost_types::FileDescriptorSet {
+        let service = |name: &str| prost_types::ServiceDescriptorProto {
+            name: Some(name.to_string()),
+            ..Default::default()
+        };
+
+        prost_types::FileDescriptorSet {
+            file: vec![prost_types::FileDescriptorProto {
+                package: Some("crabs".to_string()),
+                service: vec![service("CrabService"), service("ShellService")],
+                ..Default::default()
+            }],
+        }
+    }
+
+    fn services(fds: &prost_types::FileDescriptorSet) -> Vec<&str> {
+        fds.file
+            .iter()
+            .flat_map(|file| file.service.iter().map(|service| service.name()))
+            .collect()
+    }
+
+    #[test]
+    fn validate_rejects_unknown_services() {
+        let sides = super::Sides {
+            client: Some(false),
+            server: Some(true),
+        };
+
+        super::validate(
+            &fds(),
+            &collections::BTreeMap::from([("crabs.CrabService".to_string(), sides)]),
+        )
+        .expect("Expected the overrides to be valid");
+
+        let err = super::validate(
+            &fds(),
+            &collections::BTreeMap::from([("crabs.Crab".to_string(), sides)]),
+        );
+        assert!(
+            matches!(&err, Err(super::Error::UnknownService(service)) if service == "crabs.Crab"),
+            "Expected `Err(Error::UnknownService)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn selection_overrides_the_sides_of_the_target() {
+        let selection = super::Selection {
+            overrides: collections::BTreeMap::from([(
+                "crabs.CrabService".to_string(),
+                super::Sides {
+                    client: Some(false),
+                    server: Some(true),
+                },
+            )]),
+            client: true,
+            server: false,
+        };
+
+        assert_eq!(
+            selection.any(),
+            (true, true),
+            "Expected both sides to be generated"
+        );
+        assert_eq!(
+            services(&selection.clients(&fds())),
+            vec!["ShellService"],
+            "Invalid services with a client",
+        );
+        assert_eq!(
+            services(&selection.servers(&fds())),
+            vec!["CrabService"],
+            "Invalid services with a server",
+        );
+    }
+}
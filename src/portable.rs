@@ -0,0 +1,148 @@
+use std::{io, path};
+
+/// The longest path Windows supports unless the long paths are enabled, without the terminating
+/// null character.
+pub const MAX_PATH: usize = 259;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(
+        "The generated path `{1}` contains `{0}`, a reserved device name on Windows, rename the \
+        module, e.g. with `--rename-module {0}={0}_`"
+    )]
+    ReservedName(String, path::PathBuf),
+    #[error(
+        "The generated path `{0}` is {1} characters long, over the {MAX_PATH} characters Windows \
+        supports by default, write the output closer to the root of the drive, shorten the module \
+        paths with `--strip-package-prefix` or enable the long paths in Windows"
+    )]
+    TooLong(path::PathBuf, usize),
+    #[error("Failed to resolve the generated path `{1}`: {0}")]
+    Resolve(io::Error, path::PathBuf),
+}
+
+/// Whether Windows reserves the file `name` for a device, whatever its extension and letter case,
+/// e.g. `con`, `NUL.rs` or `com1`.
+fn is_reserved(name: &str) -> bool {
+    let stem = name
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(' ')
+        .to_ascii_uppercase();
+
+    match stem.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" => true,
+        stem => {
+            (stem.starts_with("COM") || stem.starts_with("LPT"))
+                && stem.len() == 4
+                && stem.as_bytes()[3].is_ascii_digit()
+        }
+    }
+}
+
+/// The `path` with its components separated by `/` on every platform, e.g. to refer to it from a
+/// CODEOWNERS file or to match it against the protobuf file names.
+pub fn to_slash(path: &path::Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Ensure the generated `files` can be written and checked out on Windows too: none of their
+/// components below the output directory they are in, one of the `outputs`, is a reserved device
+/// name, and none of them is longer than the `max_len`, if any. The paths with a reserved name are
+/// reported relative to their output directory, which may still be a staging one.
+pub fn check(
+    outputs: &[&path::Path],
+    files: &[path::PathBuf],
+    max_len: Option<usize>,
+) -> Result<(), Error> {
+    files.iter().try_for_each(|file| {
+        let relative = outputs
+            .iter()
+            .filter_map(|output| file.strip_prefix(output).ok())
+            .min_by_key(|relative| relative.components().count())
+            .unwrap_or(file);
+        if let Some(name) = relative
+            .components()
+            .filter_map(|component| match component {
+                path::Component::Normal(name) => name.to_str(),
+                _ => None,
+            })
+            .find(|name| is_reserved(name))
+        {
+            return Err(Error::ReservedName(
+                name.to_string(),
+                relative.to_path_buf(),
+            ));
+        }
+
+        if let Some(max_len) = max_len {
+            let absolute =
+                path::absolute(file).map_err(|e| Error::Resolve(e, file.to_path_buf()))?;
+            let len = absolute.as_os_str().len();
+            if len > max_len {
+                return Err(Error::TooLong(file.clone(), len));
+            }
+        }
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path;
+
+    #[test]
+    fn check_rejects_reserved_names_and_long_paths() {
+        let output = path::Path::new("/out/aux");
+        let file = |path: &str| output.join(path);
+
+        super::check(
+            &[output],
+            &[
+                file("mod.rs"),
+                file("crabs/console/mod.rs"),
+                file("com10/mod.rs"),
+            ],
+            Some(super::MAX_PATH),
+        )
+        .expect("Expected the paths to be valid, the output directory itself is not checked");
+
+        ["con/mod.rs", "crabs/Nul/mod.rs", "lpt1.rs", "crabs/aux .rs"]
+            .into_iter()
+            .for_each(|path| {
+                let err = super::check(&[output], &[file(path)], None);
+                assert!(
+                    matches!(&err, Err(super::Error::ReservedName(_, file)) if file == path::Path::new(path)),
+                    "Expected `Err(ReservedName)` for `{path}`, got: `{:?}`",
+                    err
+                );
+            });
+
+        let long = file(&"crabs/".repeat(50)).join("mod.rs");
+        let err = super::check(
+            &[output],
+            std::slice::from_ref(&long),
+            Some(super::MAX_PATH),
+        );
+        assert!(
+            matches!(&err, Err(super::Error::TooLong(file, len)) if *file == long && *len > 259),
+            "Expected `Err(TooLong)`, got: `{:?}`",
+            err
+        );
+        super::check(&[output], &[long], None).expect("Expected the length to be unchecked");
+    }
+
+    #[test]
+    fn to_slash_joins_the_components_with_slashes() {
+        assert_eq!(
+            super::to_slash(&path::Path::new("crabs").join("sponge_bob").join("mod.rs")),
+            "crabs/sponge_bob/mod.rs",
+            "Invalid path",
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections;
+
+use crate::services;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("The entry service `{0}` is not defined in any of the protobuf files")]
+    UnknownEntry(String),
+}
+
+/// The parts of the schema no entry service depends on, sorted by name.
+#[derive(Default, PartialEq, Debug)]
+pub struct Unreachable {
+    /// The files defining neither an entry service nor a reachable message or enum.
+    pub files: Vec<String>,
+    pub messages: Vec<String>,
+    pub enums: Vec<String>,
+}
+
+impl Unreachable {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.messages.is_empty() && self.enums.is_empty()
+    }
+}
+
+/// A message or an enum, keyed by its fully qualified name, e.g. `.crabs.Ferris`.
+struct Item {
+    file: String,
+    is_enum: bool,
+    /// The message the item is nested in, which has to be kept along with it.
+    parent: Option<String>,
+    /// The types of the fields of a message.
+    references: Vec<String>,
+}
+
+#[derive(Default)]
+struct Index {
+    items: collections::BTreeMap<String, Item>,
+    /// The file and the request and response types of every service.
+    services: collections::BTreeMap<String, (String, Vec<String>)>,
+}
+
+impl Index {
+    fn new(fds: &prost_types::FileDescriptorSet) -> Self {
+        let mut index = Self::default();
+
+        fds.file.iter().for_each(|file| {
+            let scope = match file.package() {
+                "" => String::new(),
+                package => format!(".{package}"),
+            };
+
+            services::messages(file)
+                .iter()
+                .for_each(|message| index.push_message(file.name(), message));
+            index.push_enums(file.name(), &scope, None, &file.enum_type);
+
+            file.service.iter().for_each(|service| {
+                let types = service
+                    .method
+                    .iter()
+                    .flat_map(|method| [method.input_type(), method.output_type()])
+                    .map(str::to_string)
+                    .collect();
+
+                index.services.insert(
+                    format!("{scope}.{}", service.name()),
+                    (file.name().to_string(), types),
+                );
+            });
+        });
+
+        index
+    }
+
+    fn push_message(&mut self, file: &str, message: &services::Message) {
+        let name = format!(".{}", message.full_name);
+        // Only the nested messages have a parent, the scope of the others is their package.
+        let parent = match message.names.len() {
+            1 => None,
+            _ => name.rsplit_once('.').map(|(parent, _)| parent.to_string()),
+        };
+
+        self.push_enums(file, &name, Some(&name), &message.descriptor.enum_type);
+        self.items.insert(
+            name,
+            Item {
+                file: file.to_string(),
+                is_enum: false,
+                parent,
+                references: message
+                    .descriptor
+                    .field
+                    .iter()
+                    .filter(|field| !field.type_name().is_empty())
+                    .map(|field| field.type_name().to_string())
+                    .collect(),
+            },
+        );
+    }
+
+    fn push_enums(
+        &mut self,
+        file: &str,
+        scope: &str,
+        parent: Option<&str>,
+        enums: &[prost_types::EnumDescriptorProto],
+    ) {
+        enums.iter().for_each(|item| {
+            self.items.insert(
+                format!("{scope}.{}", item.name()),
+                Item {
+                    file: file.to_string(),
+                    is_enum: true,
+                    parent: parent.map(str::to_string),
+                    references: vec![],
+                },
+            );
+        });
+    }
+}
+
+/// Find the messages, enums and files of the `fds` that can not be reached from any of the
+/// `entries`, the fully qualified names of the services the schema is published for, e.g.
+/// `crabs.CrabService`. Only the items defined in the `files` are reported, so the imported
+/// dependencies, like the well-known types, are left out.
+pub fn unreachable(
+    fds: &prost_types::FileDescriptorSet,
+    entries: &[String],
+    files: &[String],
+) -> Result<Unreachable, Error> {
+    let index = Index::new(fds);
+
+    let mut reachable_files = collections::BTreeSet::new();
+    let mut pending = entries
+        .iter()
+        .map(|entry| {
+            let name = format!(".{}", entry.trim_start_matches('.'));
+            let (file, types) = index
+                .services
+                .get(&name)
+                .ok_or_else(|| Error::UnknownEntry(entry.clone()))?;
+            reachable_files.insert(file.as_str());
+
+            Ok(types.iter().map(String::as_str))
+        })
+        .collect::<Result<Vec<_>, Error>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+    let mut reachable = collections::BTreeSet::new();
+    while let Some(name) = pending.pop() {
+        let Some(item) = index.items.get(name) else {
+            continue;
+        };
+
+        if reachable.insert(name) {
+            reachable_files.insert(item.file.as_str());
+            pending.extend(item.references.iter().map(String::as_str));
+            pending.extend(item.parent.as_deref());
+        }
+    }
+
+    let (enums, messages) = index
+        .items
+        .iter()
+        .filter(|(name, item)| files.contains(&item.file) && !reachable.contains(name.as_str()))
+        .partition::<Vec<_>, _>(|(_, item)| item.is_enum);
+    let names = |items: Vec<(&String, &Item)>| {
+        items
+            .into_iter()
+            .map(|(name, _)| name.trim_start_matches('.').to_string())
+            .collect()
+    };
+
+    let mut unreachable_files = files
+        .iter()
+        .filter(|file| !reachable_files.contains(file.as_str()))
+        .cloned()
+        .collect::<Vec<_>>();
+    unreachable_files.sort();
+
+    Ok(Unreachable {
+        files: unreachable_files,
+        messages: names(messages),
+        enums: names(enums),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    fn message(name: &str, fields: &[&str]) -> prost_types::DescriptorProto {
+        prost_types::DescriptorProto {
+            name: Some(name.to_string()),
+            field: fields
+                .iter()
+                .map(|type_name| prost_types::FieldDescriptorProto {
+                    type_name: Some(type_name.to_string()),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn fds() -> prost_types::FileDescriptorSet {
+        prost_types::FileDescriptorSet {
+            file: vec![
+                prost_types::FileDescriptorProto {
+                    name: Some("crabs/service.proto".to_string()),
+                    package: Some("crabs".to_string()),
+                    message_type: vec![
+                        message("GetFerrisRequest", &[]),
+                        prost_types::DescriptorProto {
+                            enum_type: vec![prost_types::EnumDescriptorProto {
+                                name: Some("Mood".to_string()),
+                                ..Default::default()
+                            }],
+                            ..message("Ferris", &[".crabs.disney.Sebastian"])
+                        },
+                    ],
+                    service: vec![prost_types::ServiceDescriptorProto {
+                        name: Some("CrabService".to_string()),
+                        method: vec![prost_types::MethodDescriptorProto {
+                            input_type: Some(".crabs.GetFerrisRequest".to_string()),
+                            output_type: Some(".crabs.Ferris".to_string()),
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+                prost_types::FileDescriptorProto {
+                    name: Some("crabs/disney.proto".to_string()),
+                    package: Some("crabs.disney".to_string()),
+                    message_type: vec![
+                        message("Sebastian", &[".crabs.disney.Ariel.Voice"]),
+                        prost_types::DescriptorProto {
+                            nested_type: vec![message("Voice", &[]), message("Tail", &[])],
+                            ..message("Ariel", &[])
+                        },
+                    ],
+                    ..Default::default()
+                },
+                prost_types::FileDescriptorProto {
+                    name: Some("crabs/sponge_bob.proto".to_string()),
+                    package: Some("crabs.sponge_bob".to_string()),
+                    message_type: vec![message("MrKrabs", &[".google.protobuf.Empty"])],
+                    ..Default::default()
+                },
+                prost_types::FileDescriptorProto {
+                    name: Some("google/protobuf/empty.proto".to_string()),
+                    package: Some("google.protobuf".to_string()),
+                    message_type: vec![message("Empty", &[])],
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn unreachable_follows_the_entry_services() {
+        let files = [
+            "crabs/disney.proto",
+            "crabs/service.proto",
+            "crabs/sponge_bob.proto",
+        ]
+        .map(str::to_string);
+
+        let unreachable = super::unreachable(&fds(), &["crabs.CrabService".to_string()], &files)
+            .expect("Failed to find the unreachable schema");
+
+        assert_eq!(
+            unreachable,
+            super::Unreachable {
+                files: vec!["crabs/sponge_bob.proto".to_string()],
+                messages: vec![
+                    "crabs.disney.Ariel.Tail".to_string(),
+                    "crabs.sponge_bob.MrKrabs".to_string(),
+                ],
+                enums: vec!["crabs.Ferris.Mood".to_string()],
+            },
+            "Invalid unreachable schema",
+        );
+    }
+
+    #[test]
+    fn unreachable_fails_on_unknown_entries() {
+        let err = super::unreachable(&fds(), &[".crabs.Missing".to_string()], &[]);
+
+        assert!(
+            matches!(&err, Err(super::Error::UnknownEntry(entry)) if entry == ".crabs.Missing"),
+            "Expected `Err(Error::UnknownEntry)`, got: `{:?}`",
+            err
+        );
+    }
+}
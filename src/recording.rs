@@ -0,0 +1,200 @@
+use std::{fmt::Write as _, path};
+
+use crate::services;
+
+/// The recording client of a service, wrapping the tonic client of every unary method. Streaming
+/// methods are left out.
+fn render(service: &services::Service<'_>) -> String {
+    let full_name = service.full_name();
+    let client = service.client();
+    let name = service.name();
+
+    let mut code = format!(
+        r#"
+/// A client of the `{full_name}` service recording every call into a directory, or replaying the
+/// recorded calls without a server, matching them by the encoded request.
+#[cfg(feature = "client")]
+pub mod {module} {{
+    use prost::Message as _;
+
+    enum Mode {{
+        Record({client}<tonic::transport::Channel>),
+        Replay,
+    }}
+
+    pub struct {name}RecordingClient {{
+        mode: Mode,
+        dir: std::path::PathBuf,
+    }}
+
+    impl {name}RecordingClient {{
+        /// Call the service through the `client`, recording every call into the `dir`.
+        pub fn record(
+            client: {client}<tonic::transport::Channel>,
+            dir: impl Into<std::path::PathBuf>,
+        ) -> Self {{
+            Self {{
+                mode: Mode::Record(client),
+                dir: dir.into().join("{full_name}"),
+            }}
+        }}
+
+        /// Answer every call with the one recorded into the `dir` for the same request.
+        pub fn replay(dir: impl Into<std::path::PathBuf>) -> Self {{
+            Self {{
+                mode: Mode::Replay,
+                dir: dir.into().join("{full_name}"),
+            }}
+        }}
+"#,
+        module = service.module("recording"),
+    );
+
+    service.unary_methods().for_each(|method| {
+        let _ = write!(
+            code,
+            r#"
+        pub async fn {function}(
+            &mut self,
+            request: impl tonic::IntoRequest<{input}>,
+        ) -> Result<tonic::Response<{output}>, tonic::Status> {{
+            let request = request.into_request();
+            let encoded = request.get_ref().encode_to_vec();
+            let dir = self.dir.join("{method}");
+
+            let client = match &mut self.mode {{
+                Mode::Record(client) => client,
+                Mode::Replay => {{
+                    let response = replay(&dir, &encoded)?;
+
+                    return <{output}>::decode(response.as_slice())
+                        .map(tonic::Response::new)
+                        .map_err(|e| {{
+                            tonic::Status::data_loss(format!("Invalid recorded response: {{e}}"))
+                        }});
+                }}
+            }};
+
+            let response = client.{function}(request).await;
+            record(
+                &dir,
+                &encoded,
+                response.as_ref().map(|response| response.get_ref().encode_to_vec()),
+            )?;
+
+            response
+        }}
+"#,
+            function = method.function,
+            input = method.input,
+            output = method.output,
+            method = method.name,
+        );
+    });
+
+    code.push_str(
+        r#"    }
+
+    /// Save the call as the next `<n>.request.pb` file in the `dir`, along with either the
+    /// `<n>.response.pb` file or the `<n>.status` one holding the code and the message.
+    fn record(
+        dir: &std::path::Path,
+        request: &[u8],
+        response: Result<Vec<u8>, &tonic::Status>,
+    ) -> Result<(), tonic::Status> {
+        let failed = |e: std::io::Error| {
+            tonic::Status::internal(format!("Failed to record the call into `{}`: {e}", dir.display()))
+        };
+
+        std::fs::create_dir_all(dir).map_err(failed)?;
+        let call = (0..)
+            .find(|n| !dir.join(format!("{n}.request.pb")).exists())
+            .unwrap_or_default();
+
+        match response {
+            Ok(response) => std::fs::write(dir.join(format!("{call}.response.pb")), response),
+            Err(status) => std::fs::write(
+                dir.join(format!("{call}.status")),
+                format!("{}\n{}", i32::from(status.code()), status.message()),
+            ),
+        }
+        .and_then(|_| std::fs::write(dir.join(format!("{call}.request.pb")), request))
+        .map_err(failed)
+    }
+
+    /// Find the call recorded in the `dir` with the same `request` and return its response.
+    fn replay(dir: &std::path::Path, request: &[u8]) -> Result<Vec<u8>, tonic::Status> {
+        let call = (0..)
+            .map(|n| (n, std::fs::read(dir.join(format!("{n}.request.pb")))))
+            .take_while(|(_, recorded)| recorded.is_ok())
+            .find(|(_, recorded)| recorded.as_deref().is_ok_and(|recorded| recorded == request))
+            .map(|(n, _)| n)
+            .ok_or_else(|| {
+                tonic::Status::not_found(format!(
+                    "No call with the same request is recorded in `{}`",
+                    dir.display(),
+                ))
+            })?;
+
+        if let Ok(status) = std::fs::read_to_string(dir.join(format!("{call}.status"))) {
+            let (code, message) = status.split_once('\n').unwrap_or((&status, ""));
+            let code = code.parse().map(tonic::Code::from_i32).unwrap_or(tonic::Code::Unknown);
+
+            return Err(tonic::Status::new(code, message));
+        }
+
+        std::fs::read(dir.join(format!("{call}.response.pb"))).map_err(|e| {
+            tonic::Status::data_loss(format!("Failed to read the recorded response: {e}"))
+        })
+    }
+}
+"#,
+    );
+    code
+}
+
+/// Append a recording client for every service of the `fds` to the code generated by prost into
+/// the `dir`.
+pub fn write(
+    fds: &prost_types::FileDescriptorSet,
+    dir: &path::Path,
+    codegen: &services::Codegen<'_>,
+) -> Result<(), services::Error> {
+    services::append(fds, dir, codegen, render)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn render_wraps_the_unary_methods() {
+        let code =
+            crate::services::tests::render(&crate::services::tests::service(), super::render);
+
+        assert!(
+            code.contains("pub mod crab_service_recording {"),
+            "Expected a module per service, got:\n{code}",
+        );
+        assert!(
+            code.contains(
+                "Record(super::crab_service_client::CrabServiceClient<tonic::transport::Channel>),"
+            ),
+            "Expected the tonic client to be wrapped, got:\n{code}",
+        );
+        assert!(
+            code.contains(
+                "pub async fn get_ferris(\n            &mut self,\n            \
+                request: impl tonic::IntoRequest<super::GetFerrisRequest>,\n        \
+                ) -> Result<tonic::Response<super::Ferris>, tonic::Status> {"
+            ),
+            "Expected the unary method to be wrapped, got:\n{code}",
+        );
+        assert!(
+            code.contains("let dir = self.dir.join(\"GetFerris\");"),
+            "Expected the calls to be recorded per method, got:\n{code}",
+        );
+        assert!(
+            !code.contains("watch_ferris"),
+            "Expected the streaming method to be skipped, got:\n{code}",
+        );
+    }
+}
@@ -0,0 +1,382 @@
+use std::{collections, fmt::Write as _, fs, io, path};
+
+use crate::services;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("The sensitive field `{0}` is not a field of any message")]
+    UnknownField(String),
+    #[error("Failed to append the redacted `Debug` implementations to `{1}`: {0}")]
+    Write(io::Error, path::PathBuf),
+}
+
+/// What the sensitive fields are printed as.
+const REDACTED: &str = "[REDACTED]";
+
+/// The fully qualified name of the message the sensitive `field` is defined in, e.g.
+/// `crabs.Ferris` for `crabs.Ferris.secret`.
+fn message_of(field: &str) -> &str {
+    field.rsplit_once('.').map_or("", |(message, _)| message)
+}
+
+/// The messages the `Debug` implementation is not derived for by prost, so it can be generated
+/// with the `fields` redacted, as the paths prost matches them against, e.g. `.crabs.Ferris`. The
+/// types nested in these messages are skipped along with them.
+pub fn messages(fields: &[String]) -> collections::BTreeSet<String> {
+    fields
+        .iter()
+        .map(|field| format!(".{}", message_of(field.trim_start_matches('.'))))
+        .collect()
+}
+
+fn push_fields(
+    found: &mut collections::BTreeSet<String>,
+    scope: &str,
+    messages: &[prost_types::DescriptorProto],
+) {
+    messages.iter().for_each(|message| {
+        let name = match scope {
+            "" => message.name().to_string(),
+            scope => format!("{scope}.{}", message.name()),
+        };
+
+        message.field.iter().for_each(|field| {
+            found.insert(format!("{name}.{}", field.name()));
+        });
+        push_fields(found, &name, &message.nested_type);
+    });
+}
+
+/// Ensure every sensitive field, given by its fully qualified name, is defined in the `fds`.
+pub fn validate(fds: &prost_types::FileDescriptorSet, fields: &[String]) -> Result<(), Error> {
+    let mut found = collections::BTreeSet::new();
+    fds.file
+        .iter()
+        .for_each(|file| push_fields(&mut found, file.package(), &file.message_type));
+
+    fields
+        .iter()
+        .find(|field| !found.contains(field.trim_start_matches('.')))
+        .map_or(Ok(()), |field| Err(Error::UnknownField(field.clone())))
+}
+
+/// The `Debug` implementations of a message without the derived one, of its oneofs and of the
+/// types nested in it, printing the `sensitive` fields as [`REDACTED`].
+fn render(
+    code: &mut String,
+    name: &str,
+    module: &str,
+    message: &prost_types::DescriptorProto,
+    sensitive: &collections::BTreeSet<String>,
+) {
+    let path = format!("{module}{}", services::to_upper_camel(message.name()));
+    let nested = format!("{module}{}::", services::to_snake(message.name()));
+    let is_sensitive = |field: &prost_types::FieldDescriptorProto| {
+        sensitive.contains(&format!("{name}.{}", field.name()))
+    };
+    let is_oneof = |field: &prost_types::FieldDescriptorProto| {
+        field.oneof_index.is_some() && !field.proto3_optional()
+    };
+
+    let mut fields = String::new();
+    let mut oneofs = collections::BTreeSet::new();
+    message.field.iter().for_each(|field| {
+        let (label, ident, value) = match field.oneof_index {
+            Some(index) if is_oneof(field) => {
+                if !oneofs.insert(index) {
+                    return;
+                }
+                let oneof = usize::try_from(index)
+                    .ok()
+                    .and_then(|index| message.oneof_decl.get(index))
+                    .map_or("", |oneof| oneof.name());
+
+                (oneof, services::to_snake(oneof), None)
+            }
+            _ => (
+                field.name(),
+                services::to_snake(field.name()),
+                is_sensitive(field).then_some(REDACTED),
+            ),
+        };
+
+        let _ = match value {
+            Some(value) => write!(
+                fields,
+                "\n            .field({label:?}, &format_args!({value:?}))"
+            ),
+            None => write!(fields, "\n            .field({label:?}, &self.{ident})"),
+        };
+    });
+
+    let _ = write!(
+        code,
+        r#"
+impl ::core::fmt::Debug for {path} {{
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {{
+        f.debug_struct({name:?}){fields}
+            .finish()
+    }}
+}}
+"#,
+        name = message.name(),
+    );
+
+    message
+        .oneof_decl
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| {
+            message.field.iter().any(|field| {
+                is_oneof(field)
+                    && usize::try_from(field.oneof_index()).is_ok_and(|oneof| oneof == *index)
+            })
+        })
+        .for_each(|(index, oneof)| {
+            let variants = message
+                .field
+                .iter()
+                .filter(|field| {
+                    is_oneof(field)
+                        && usize::try_from(field.oneof_index()).is_ok_and(|oneof| oneof == index)
+                })
+                .map(|field| {
+                    let variant = services::to_upper_camel(field.name());
+                    let (binding, value) = match is_sensitive(field) {
+                        true => ("_", format!("&format_args!({REDACTED:?})")),
+                        false => ("value", "value".to_string()),
+                    };
+
+                    format!(
+                        "\n            Self::{variant}({binding}) => \
+                        f.debug_tuple({variant:?}).field({value}).finish(),"
+                    )
+                })
+                .collect::<String>();
+
+            let _ = write!(
+                code,
+                r#"
+impl ::core::fmt::Debug for {nested}{oneof} {{
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {{
+        match self {{{variants}
+        }}
+    }}
+}}
+"#,
+                oneof = services::to_upper_camel(oneof.name()),
+            );
+        });
+
+    message.enum_type.iter().for_each(|descriptor| {
+        let _ = write!(
+            code,
+            r#"
+impl ::core::fmt::Debug for {nested}{name} {{
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {{
+        f.write_str(self.as_str_name())
+    }}
+}}
+"#,
+            name = services::to_upper_camel(descriptor.name()),
+        );
+    });
+
+    message
+        .nested_type
+        .iter()
+        .filter(|nested| !nested.options.as_ref().is_some_and(|o| o.map_entry()))
+        .for_each(|nested_message| {
+            render(
+                code,
+                &format!("{name}.{}", nested_message.name()),
+                &nested,
+                nested_message,
+                sensitive,
+            )
+        });
+}
+
+/// Render the `Debug` implementations of the `messages` without the derived one, or of the ones
+/// nested in them, whose `Debug` implementation is derived otherwise.
+fn render_skipped(
+    code: &mut String,
+    scope: &str,
+    module: &str,
+    messages: &[prost_types::DescriptorProto],
+    skipped: &collections::BTreeSet<String>,
+    sensitive: &collections::BTreeSet<String>,
+) {
+    messages.iter().for_each(|message| {
+        let name = match scope {
+            "" => message.name().to_string(),
+            scope => format!("{scope}.{}", message.name()),
+        };
+
+        match skipped.contains(&format!(".{name}")) {
+            true => render(code, &name, module, message, sensitive),
+            false => render_skipped(
+                code,
+                &name,
+                &format!("{module}{}::", services::to_snake(message.name())),
+                &message.nested_type,
+                skipped,
+                sensitive,
+            ),
+        }
+    });
+}
+
+/// Append the `Debug` implementations of the messages with any of the sensitive `fields` to the
+/// code generated by prost into the `dir`, next to the package of the message.
+pub fn write(
+    fds: &prost_types::FileDescriptorSet,
+    dir: &path::Path,
+    fields: &[String],
+) -> Result<(), Error> {
+    let sensitive = fields
+        .iter()
+        .map(|field| field.trim_start_matches('.').to_string())
+        .collect::<collections::BTreeSet<_>>();
+    let skipped = messages(fields);
+
+    fds.file.iter().try_for_each(|file| {
+        let mut code = String::new();
+        render_skipped(
+            &mut code,
+            file.package(),
+            "",
+            &file.message_type,
+            &skipped,
+            &sensitive,
+        );
+        if code.is_empty() {
+            return Ok(());
+        }
+
+        let path = dir.join(match file.package() {
+            "" => "_.rs".to_string(),
+            package => format!("{package}.rs"),
+        });
+        tracing::debug!(
+            "Appending the redacted `Debug` implementations: {}",
+            path.display()
+        );
+
+        fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .and_then(|mut out| io::Write::write_all(&mut out, code.as_bytes()))
+            .map_err(|e| Error::Write(e, path))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    fn fds() -> prost_types::FileDescriptorSet {
+        let field = |name: &str, oneof_index: Option<i32>| prost_types::FieldDescriptorProto {
+            name: Some(name.to_string()),
+            oneof_index,
+            ..Default::default()
+        };
+
+        prost_types::FileDescriptorSet {
+            file: vec![prost_types::FileDescriptorProto {
+                package: Some("crabs".to_string()),
+                message_type: vec![prost_types::DescriptorProto {
+                    name: Some("Ferris".to_string()),
+                    field: vec![
+                        field("name", None),
+                        field("home_address", None),
+                        field("email", Some(0)),
+                        field("phone", Some(0)),
+                    ],
+                    oneof_decl: vec![prost_types::OneofDescriptorProto {
+                        name: Some("contact".to_string()),
+                        ..Default::default()
+                    }],
+                    nested_type: vec![prost_types::DescriptorProto {
+                        name: Some("Claw".to_string()),
+                        field: vec![field("pin", None)],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn messages_are_the_ones_with_sensitive_fields() {
+        assert_eq!(
+            super::messages(&[
+                "crabs.Ferris.home_address".to_string(),
+                ".crabs.Ferris.phone".to_string(),
+            ]),
+            [".crabs.Ferris".to_string()].into(),
+        );
+    }
+
+    #[test]
+    fn validate_rejects_unknown_fields() {
+        super::validate(
+            &fds(),
+            &[
+                "crabs.Ferris.home_address".to_string(),
+                ".crabs.Ferris.Claw.pin".to_string(),
+            ],
+        )
+        .expect("Expected the sensitive fields to be valid");
+
+        let err = super::validate(&fds(), &["crabs.Ferris.pin".to_string()]);
+        assert!(
+            matches!(&err, Err(super::Error::UnknownField(field)) if field == "crabs.Ferris.pin"),
+            "Expected `Err(Error::UnknownField)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn write_redacts_the_sensitive_fields() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+
+        super::write(
+            &fds(),
+            dir.path(),
+            &[
+                "crabs.Ferris.home_address".to_string(),
+                "crabs.Ferris.phone".to_string(),
+            ],
+        )
+        .expect("Failed to write the redacted implementations");
+
+        let code = fs::read_to_string(dir.path().join("crabs.rs"))
+            .expect("Failed to read the generated code");
+        [
+            "impl ::core::fmt::Debug for Ferris {",
+            "            .field(\"name\", &self.name)\n",
+            "            .field(\"home_address\", &format_args!(\"[REDACTED]\"))\n",
+            "            .field(\"contact\", &self.contact)\n",
+            "            Self::Email(value) => f.debug_tuple(\"Email\").field(value).finish(),\n",
+            "            Self::Phone(_) => f.debug_tuple(\"Phone\")",
+            "impl ::core::fmt::Debug for ferris::Claw {",
+            "            .field(\"pin\", &self.pin)\n",
+        ]
+        .into_iter()
+        .for_each(|expected| {
+            assert!(
+                code.contains(expected),
+                "Expected `{expected}` in the redacted implementations, got:\n{code}",
+            );
+        });
+        assert_eq!(
+            code.matches(".field(\"contact\"").count(),
+            1,
+            "Expected the oneof to be printed once, got:\n{code}",
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use std::{collections, fmt::Write as _, fs, io, path};
+
+use crate::{modgen, naming, services};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to write the re-exports `{1}`: {0}")]
+    Write(io::Error, path::PathBuf),
+}
+
+/// The file the re-exports are written into, next to the root module of the output.
+pub const FILE: &str = "reexports.rs";
+
+/// Collect the generated messages and enums into the `aliases`, keyed by the flat name, e.g.
+/// `crabs_Ferris_Claw`, along with their Rust path from the root module, e.g.
+/// `crabs::ferris::Claw`.
+fn push_types(
+    aliases: &mut collections::BTreeMap<String, String>,
+    flat: &str,
+    module: &str,
+    messages: &[prost_types::DescriptorProto],
+    enums: &[prost_types::EnumDescriptorProto],
+) {
+    let mut push = |name: &str| {
+        let alias = format!("{flat}{name}");
+        let path = format!("{module}{}", services::to_upper_camel(name));
+
+        if let Some(other) = aliases.insert(alias.clone(), path.clone()) {
+            tracing::warn!(
+                "Both `{other}` and `{path}` are re-exported as `{alias}`, keeping the latter"
+            );
+        }
+    };
+
+    // The entries of the map fields are not generated as messages.
+    let messages = messages.iter().filter(|message| {
+        !message
+            .options
+            .as_ref()
+            .is_some_and(prost_types::MessageOptions::map_entry)
+    });
+
+    enums.iter().for_each(|item| push(item.name()));
+    messages.clone().for_each(|message| push(message.name()));
+
+    messages.for_each(|message| {
+        push_types(
+            aliases,
+            &format!("{flat}{}_", message.name()),
+            &format!("{module}{}::", services::to_snake(message.name())),
+            &message.nested_type,
+            &message.enum_type,
+        );
+    });
+}
+
+/// The Rust module path of the protobuf `package`, after the `naming` rules.
+pub(crate) fn module_path(naming: &naming::Naming, package: &str) -> Vec<String> {
+    let segments = package
+        .split('.')
+        .filter(|segment| !segment.is_empty())
+        .map(services::to_snake)
+        .collect::<Vec<_>>();
+
+    naming.module_path(&segments)
+}
+
+/// The flat aliases of every message and enum of the `fds` whose package `is_generated`, leaving
+/// out the types replaced by the `extern_paths`.
+fn aliases(
+    fds: &prost_types::FileDescriptorSet,
+    is_generated: impl Fn(&str) -> bool,
+    extern_paths: &[(String, String)],
+    naming: &naming::Naming,
+) -> collections::BTreeMap<String, String> {
+    let mut aliases = collections::BTreeMap::new();
+
+    fds.file
+        .iter()
+        .filter(|file| is_generated(file.package()))
+        .for_each(|file| {
+            let (flat, module) = match file.package() {
+                "" => (String::new(), String::new()),
+                package => (
+                    format!("{}_", package.replace('.', "_")),
+                    module_path(naming, package)
+                        .iter()
+                        .map(|module| format!("{module}::"))
+                        .collect(),
+                ),
+            };
+
+            push_types(
+                &mut aliases,
+                &flat,
+                &module,
+                &file.message_type,
+                &file.enum_type,
+            );
+        });
+
+    // The types replaced by an extern path are not generated, so there is nothing to re-export.
+    let externs = extern_paths
+        .iter()
+        .map(|(proto_path, _)| proto_path.trim_start_matches('.').replace('.', "_"))
+        .collect::<Vec<_>>();
+    aliases.retain(|alias, _| {
+        !externs.iter().any(|prefix| {
+            alias == prefix
+                || alias
+                    .strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.starts_with('_'))
+        })
+    });
+
+    aliases
+}
+
+fn render(aliases: &collections::BTreeMap<String, String>) -> String {
+    let mut code = String::from(
+        r#"// This file is @generated by pbuildrs.
+
+/// Every generated message and enum under a flat name made of its fully qualified protobuf name,
+/// e.g. `flat::crabs_Ferris` for `crabs.Ferris`.
+#[allow(non_camel_case_types)]
+pub mod flat {
+"#,
+    );
+
+    aliases.iter().for_each(|(alias, path)| {
+        let _ = writeln!(code, "    pub use super::{path} as {alias};");
+    });
+
+    code.push_str("}\n");
+    code
+}
+
+/// Write the flat re-exports of the types generated into the `compiled_dir` to the [`FILE`] in the
+/// `dst` directory, and include it into the root module of the `dst` laid out with the `layout`.
+/// The packages mapped to other output directories are left out.
+pub fn write(
+    fds: &prost_types::FileDescriptorSet,
+    compiled_dir: &path::Path,
+    dst: &path::Path,
+    packages: &[modgen::PackageOutput],
+    extern_paths: &[(String, String)],
+    naming: &naming::Naming,
+    layout: modgen::Layout,
+) -> Result<path::PathBuf, Error> {
+    let is_generated = |package: &str| {
+        let module = module_path(naming, package);
+        let file = match module.is_empty() {
+            true => "_.rs".to_string(),
+            false => format!("{}.rs", module.join(".")),
+        };
+        let package = naming.package(package);
+        let is_mapped = package
+            .split('.')
+            .filter(|part| !part.is_empty())
+            .fold(dst.to_path_buf(), |dir, part| dir.join(part))
+            != modgen::package_dir(dst, packages, &package);
+
+        compiled_dir.join(file).exists() && !is_mapped
+    };
+
+    let path = dst.join(FILE);
+    tracing::debug!("Writing the re-exports: {}", path.display());
+    let aliases = aliases(fds, is_generated, extern_paths, naming);
+    fs::write(&path, render(&aliases)).map_err(|e| Error::Write(e, path.clone()))?;
+
+    let root = dst.join(layout.root_file());
+    fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&root)
+        .and_then(|mut out| {
+            io::Write::write_all(&mut out, format!("\ninclude!(\"{FILE}\");\n").as_bytes())
+        })
+        .map_err(|e| Error::Write(e, root))?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections;
+
+    #[test]
+    fn aliases_flatten_the_generated_types() {
+        let message =
+            |name: &str, nested: Vec<prost_types::DescriptorProto>| prost_types::DescriptorProto {
+                name: Some(name.to_string()),
+                nested_type: nested,
+                ..Default::default()
+            };
+        let file = |package: &str, messages| prost_types::FileDescriptorProto {
+            package: Some(package.to_string()),
+            message_type: messages,
+            ..Default::default()
+        };
+        let fds = prost_types::FileDescriptorSet {
+            file: vec![
+                file(
+                    "crabs",
+                    vec![message(
+                        "Ferris",
+                        vec![
+                            message("Claw", vec![]),
+                            prost_types::DescriptorProto {
+                                options: Some(prost_types::MessageOptions {
+                                    map_entry: Some(true),
+                                    ..Default::default()
+                                }),
+                                ..message("LegsEntry", vec![])
+                            },
+                        ],
+                    )],
+                ),
+                file(
+                    "crabs.sponge_bob",
+                    vec![message("MrKrabs", vec![]), message("Plankton", vec![])],
+                ),
+                file("google.protobuf", vec![message("Timestamp", vec![])]),
+            ],
+        };
+
+        let extern_paths = [(
+            ".crabs.sponge_bob.Plankton".to_string(),
+            "::plankton::Plankton".to_string(),
+        )];
+        let aliases = super::aliases(
+            &fds,
+            |package| package != "google.protobuf",
+            &extern_paths,
+            &crate::naming::Naming::default(),
+        );
+
+        assert_eq!(
+            aliases,
+            collections::BTreeMap::from([
+                ("crabs_Ferris".to_string(), "crabs::Ferris".to_string()),
+                (
+                    "crabs_Ferris_Claw".to_string(),
+                    "crabs::ferris::Claw".to_string()
+                ),
+                (
+                    "crabs_sponge_bob_MrKrabs".to_string(),
+                    "crabs::sponge_bob::MrKrabs".to_string()
+                ),
+            ]),
+            "Invalid re-exported types",
+        );
+        assert!(
+            super::render(&aliases)
+                .contains("    pub use super::crabs::ferris::Claw as crabs_Ferris_Claw;\n"),
+            "Expected the nested type to be re-exported",
+        );
+    }
+}
@@ -0,0 +1,388 @@
+use prost::Message as _;
+use std::{env, ffi, fmt::Write as _, fs, io, path, process};
+
+/// The environment variable holding the credentials of the schema registry, e.g. `user:password`.
+/// They are handed to `curl` on its standard input rather than its command line.
+pub const AUTH_VAR: &str = "PBUILDRS_REGISTRY_AUTH";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("The schema registry can not be reached in the offline mode")]
+    Offline,
+    #[error("Failed to run curl `{1}`: {0}")]
+    Spawn(io::Error, path::PathBuf),
+    #[error("Failed to reach the schema registry: {0}")]
+    Curl(String),
+    #[error("Unexpected response from the schema registry for `{0}`, HTTP {1}: {2}")]
+    Response(String, u16, String),
+    #[error(
+        "The schema registry rejected `{subject}` as incompatible: {}",
+        .messages.join(", ")
+    )]
+    Rejected {
+        subject: String,
+        messages: Vec<String>,
+    },
+    #[error("Failed to read the protobuf file `{1}`: {0}")]
+    ReadSchema(io::Error, path::PathBuf),
+    #[error("Failed to write the request to the schema registry `{1}`: {0}")]
+    WriteRequest(io::Error, path::PathBuf),
+}
+
+/// The API of the schema registry checking the compatibility of the protobuf files.
+#[derive(
+    clap::ValueEnum, serde::Deserialize, serde::Serialize, Clone, Copy, Default, PartialEq, Debug,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Api {
+    /// The Confluent Schema Registry, checking every protobuf file against the latest version of
+    /// the subject named after it
+    #[default]
+    Confluent,
+    /// Any HTTP endpoint accepting the encoded file descriptor set in a POST request, rejecting
+    /// it with a 4xx status
+    Generic,
+}
+
+/// A schema registry the new descriptors are checked against before generating the code.
+#[derive(Clone, Debug)]
+pub struct Registry {
+    pub url: String,
+    pub api: Api,
+}
+
+/// A compatibility check submitted to the registry.
+#[derive(PartialEq, Debug)]
+struct Request {
+    /// What is checked, the protobuf file or the whole descriptor set.
+    subject: String,
+    url: String,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+/// Encode the `subject` as a single segment of a URL path.
+fn encode_segment(subject: &str) -> String {
+    subject.bytes().fold(String::new(), |mut encoded, byte| {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => {
+                let _ = write!(encoded, "%{byte:02X}");
+            }
+        }
+        encoded
+    })
+}
+
+/// The requests checking the `fds` against the `registry`. The Confluent registry is given the
+/// `schemas`, the name of every protobuf file of the sources along with its patched contents,
+/// referencing the files they import by their subjects.
+fn requests(
+    registry: &Registry,
+    fds: &prost_types::FileDescriptorSet,
+    schemas: &[(String, path::PathBuf)],
+) -> Result<Vec<Request>, Error> {
+    let url = registry.url.trim_end_matches('/');
+
+    match registry.api {
+        Api::Generic => Ok(vec![Request {
+            subject: "the file descriptor set".to_string(),
+            url: url.to_string(),
+            content_type: "application/x-protobuf",
+            body: fds.encode_to_vec(),
+        }]),
+        Api::Confluent => schemas
+            .iter()
+            .map(|(name, path)| {
+                let schema =
+                    fs::read_to_string(path).map_err(|e| Error::ReadSchema(e, path.clone()))?;
+                // The well-known types are built into the registry.
+                let references = fds
+                    .file
+                    .iter()
+                    .find(|file| file.name() == name)
+                    .into_iter()
+                    .flat_map(|file| &file.dependency)
+                    .filter(|dependency| !dependency.starts_with("google/protobuf/"))
+                    .map(|dependency| {
+                        // The version `-1` stands for the latest one of the subject.
+                        serde_json::json!({
+                            "name": dependency,
+                            "subject": dependency,
+                            "version": -1,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let body = serde_json::json!({
+                    "schemaType": "PROTOBUF",
+                    "schema": schema,
+                    "references": references,
+                });
+
+                Ok(Request {
+                    subject: name.clone(),
+                    url: format!(
+                        "{url}/compatibility/subjects/{}/versions/latest?verbose=true",
+                        encode_segment(name),
+                    ),
+                    content_type: "application/vnd.schemaregistry.v1+json",
+                    body: body.to_string().into_bytes(),
+                })
+            })
+            .collect(),
+    }
+}
+
+/// The verdict of the registry on the `request`, from the `status` and the `body` of its response.
+fn verdict(api: Api, request: &Request, status: u16, body: &str) -> Result<(), Error> {
+    #[derive(serde::Deserialize)]
+    struct Compatibility {
+        is_compatible: bool,
+        #[serde(default)]
+        messages: Vec<String>,
+    }
+
+    let unexpected = || Error::Response(request.subject.clone(), status, body.trim().to_string());
+
+    match (api, status) {
+        (Api::Confluent, 200) => match serde_json::from_str::<Compatibility>(body) {
+            Ok(compatibility) if compatibility.is_compatible => Ok(()),
+            Ok(compatibility) => Err(Error::Rejected {
+                subject: request.subject.clone(),
+                messages: compatibility.messages,
+            }),
+            Err(_) => Err(unexpected()),
+        },
+        (Api::Confluent, 404) => {
+            tracing::info!(
+                "The schema registry has no `{}` subject yet, nothing to be compatible with",
+                request.subject
+            );
+            Ok(())
+        }
+        (Api::Generic, 200..300) => Ok(()),
+        (Api::Generic, 400..500) => Err(Error::Rejected {
+            subject: request.subject.clone(),
+            messages: vec![body.trim().to_string()],
+        }),
+        _ => Err(unexpected()),
+    }
+}
+
+/// The `curl` binary set in the `CURL` environment variable, or the one in the `PATH`.
+fn curl() -> path::PathBuf {
+    env::var_os("CURL")
+        .map(path::PathBuf::from)
+        .unwrap_or_else(|| path::PathBuf::from("curl"))
+}
+
+/// Submit the `request` with `curl`, returning the status and the body of the response.
+fn send(
+    curl: &ffi::OsStr,
+    request: &Request,
+    workdir: &path::Path,
+) -> Result<(u16, String), Error> {
+    let body_path = workdir.join("registry-request");
+    fs::write(&body_path, &request.body).map_err(|e| Error::WriteRequest(e, body_path.clone()))?;
+
+    // The credentials are passed as a curl config on the standard input, so they do not show up
+    // in the list of processes.
+    let config = env::var(AUTH_VAR)
+        .map(|auth| {
+            format!(
+                "user = \"{}\"\n",
+                auth.replace('\\', "\\\\").replace('"', "\\\"")
+            )
+        })
+        .unwrap_or_default();
+
+    let mut child = process::Command::new(curl)
+        .args([
+            "--silent",
+            "--show-error",
+            "--config",
+            "-",
+            "--request",
+            "POST",
+        ])
+        .arg("--header")
+        .arg(format!("Content-Type: {}", request.content_type))
+        .arg("--data-binary")
+        .arg(format!("@{}", body_path.display()))
+        .args(["--write-out", "\n%{http_code}"])
+        .arg(&request.url)
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Spawn(e, curl.into()))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        io::Write::write_all(&mut stdin, config.as_bytes())
+            .map_err(|e| Error::Spawn(e, curl.into()))?;
+    }
+
+    let output = child
+        .wait_with_output()
+        .map_err(|e| Error::Spawn(e, curl.into()))?;
+    if !output.status.success() {
+        return Err(Error::Curl(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (body, status) = stdout.rsplit_once('\n').unwrap_or(("", &stdout));
+    let status = status
+        .trim()
+        .parse()
+        .map_err(|_| Error::Curl(format!("Invalid HTTP status `{status}`")))?;
+
+    Ok((status, body.to_string()))
+}
+
+/// Check the `fds` against the `registry` with `curl`, failing if it rejects any of them as
+/// incompatible. The Confluent registry is given the `schemas`, the name of every protobuf file of
+/// the sources along with its patched contents. The request bodies are written to the `workdir`.
+pub fn check(
+    registry: &Registry,
+    fds: &prost_types::FileDescriptorSet,
+    schemas: &[(String, path::PathBuf)],
+    workdir: &path::Path,
+) -> Result<(), Error> {
+    let curl = curl();
+
+    requests(registry, fds, schemas)?
+        .iter()
+        .try_for_each(|request| {
+            tracing::info!(
+                "Checking the compatibility of `{}` with the schema registry",
+                request.subject
+            );
+            let (status, body) = send(curl.as_os_str(), request, workdir)?;
+
+            verdict(registry.api, request, status, &body)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path};
+
+    fn fds() -> prost_types::FileDescriptorSet {
+        prost_types::FileDescriptorSet {
+            file: vec![prost_types::FileDescriptorProto {
+                name: Some("crabs/ferris.proto".to_string()),
+                dependency: vec![
+                    "crabs/shell.proto".to_string(),
+                    "google/protobuf/timestamp.proto".to_string(),
+                ],
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn requests_reference_the_imported_subjects() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let schema = dir.path().join("ferris.proto");
+        fs::write(&schema, "syntax = \"proto3\";\n").expect("Failed to write a test file");
+        let registry = super::Registry {
+            url: "http://registry:8081/".to_string(),
+            api: super::Api::Confluent,
+        };
+
+        let requests = super::requests(
+            &registry,
+            &fds(),
+            &[("crabs/ferris.proto".to_string(), schema)],
+        )
+        .expect("Failed to build the requests");
+
+        assert_eq!(requests.len(), 1, "Expected a request per protobuf file");
+        assert_eq!(
+            requests[0].url,
+            "http://registry:8081/compatibility/subjects/crabs%2Fferris.proto/versions/latest\
+            ?verbose=true",
+        );
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&requests[0].body)
+                .expect("Failed to parse the request body"),
+            serde_json::json!({
+                "schemaType": "PROTOBUF",
+                "schema": "syntax = \"proto3\";\n",
+                "references": [
+                    { "name": "crabs/shell.proto", "subject": "crabs/shell.proto", "version": -1 },
+                ],
+            }),
+        );
+
+        let result = super::requests(
+            &registry,
+            &fds(),
+            &[(
+                "crabs/ferris.proto".to_string(),
+                path::PathBuf::from("missing.proto"),
+            )],
+        );
+        assert!(
+            matches!(result, Err(super::Error::ReadSchema(..))),
+            "Expected `Err(Error::ReadSchema(..))`, got: `{result:?}`",
+        );
+    }
+
+    #[test]
+    fn verdict_follows_the_registry_response() {
+        let request = super::Request {
+            subject: "crabs/ferris.proto".to_string(),
+            url: "http://registry:8081".to_string(),
+            content_type: "application/json",
+            body: vec![],
+        };
+        let verdict = |api, status, body| super::verdict(api, &request, status, body);
+
+        assert!(
+            verdict(super::Api::Confluent, 200, r#"{"is_compatible": true}"#).is_ok(),
+            "Expected the compatible schema to pass",
+        );
+        assert!(
+            verdict(super::Api::Confluent, 404, r#"{"error_code": 40401}"#).is_ok(),
+            "Expected a new subject to pass",
+        );
+        assert!(
+            verdict(super::Api::Generic, 204, "").is_ok(),
+            "Expected a successful status to pass",
+        );
+
+        let result = verdict(
+            super::Api::Confluent,
+            200,
+            r#"{"is_compatible": false, "messages": ["Field `claws` was removed"]}"#,
+        );
+        assert!(
+            matches!(
+                &result,
+                Err(super::Error::Rejected { messages, .. })
+                    if messages == &["Field `claws` was removed"]
+            ),
+            "Expected `Err(Error::Rejected)`, got: `{result:?}`",
+        );
+
+        let result = verdict(super::Api::Generic, 409, "Incompatible\n");
+        assert!(
+            matches!(
+                &result,
+                Err(super::Error::Rejected { messages, .. }) if messages == &["Incompatible"]
+            ),
+            "Expected `Err(Error::Rejected)`, got: `{result:?}`",
+        );
+
+        let result = verdict(super::Api::Confluent, 500, "Internal error");
+        assert!(
+            matches!(result, Err(super::Error::Response(_, 500, _))),
+            "Expected `Err(Error::Response(..))`, got: `{result:?}`",
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use std::{env, ffi, fs, io, path, process};
+
+use crate::{manifest, semver};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("The release versions the generated crates, set `--output-layout crate`")]
+    NotACrate,
+    #[error(
+        "No target has a published crate to suggest the next version against, set \
+        `--semver-baseline`"
+    )]
+    NoBaseline,
+    #[error("The generated crates can not be checked for publishing in the offline mode")]
+    Offline,
+    #[error("Failed to run `cargo publish`: {0}")]
+    SpawnCargo(io::Error),
+    #[error("`cargo publish --dry-run` failed for the crate `{1}`, exited with {0}")]
+    Publish(process::ExitStatus, path::PathBuf),
+    #[error("Failed to remove the lock file `{1}` left by `cargo publish`: {0}")]
+    RemoveLock(io::Error, path::PathBuf),
+}
+
+/// The version the crates are released with, the highest one suggested for their targets, so the
+/// crates released together share their version.
+pub fn version(versions: &[(String, semver::Suggestion)]) -> Result<semver::Version, Error> {
+    versions
+        .iter()
+        .map(|(_, suggestion)| &suggestion.next)
+        .max()
+        .cloned()
+        .ok_or(Error::NoBaseline)
+}
+
+/// The directories of the crates among the generated `files`, the ones holding their manifest.
+pub fn crates(files: &[path::PathBuf]) -> Vec<path::PathBuf> {
+    files
+        .iter()
+        .filter(|file| file.file_name().is_some_and(|name| name == manifest::FILE))
+        .filter_map(|file| file.parent())
+        .map(path::Path::to_path_buf)
+        .collect()
+}
+
+/// Check that every crate in the `crates` directories can be published with `cargo publish
+/// --dry-run`, building them in the `target_dir`. The lock files Cargo creates in the crates are
+/// removed again, so they stay as generated.
+pub fn publish_dry_run(crates: &[path::PathBuf], target_dir: &path::Path) -> Result<(), Error> {
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| ffi::OsString::from("cargo"));
+
+    crates.iter().try_for_each(|dir| {
+        tracing::info!(
+            "Checking that the crate `{}` can be published",
+            dir.display()
+        );
+
+        let lock = dir.join("Cargo.lock");
+        let locked = lock.exists();
+        let status = process::Command::new(&cargo)
+            .args(["publish", "--dry-run", "--allow-dirty"])
+            .arg("--manifest-path")
+            .arg(dir.join(manifest::FILE))
+            .arg("--target-dir")
+            .arg(target_dir)
+            .status()
+            .map_err(Error::SpawnCargo)?;
+
+        if !locked && lock.exists() {
+            fs::remove_file(&lock).map_err(|e| Error::RemoveLock(e, lock.clone()))?;
+        }
+
+        match status.success() {
+            true => Ok(()),
+            false => Err(Error::Publish(status, dir.clone())),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path;
+
+    #[test]
+    fn crates_are_the_directories_with_a_manifest() {
+        let files =
+            ["crabs/Cargo.toml", "crabs/src/lib.rs", "shells/Cargo.toml"].map(path::PathBuf::from);
+
+        assert_eq!(
+            super::crates(&files),
+            vec![path::PathBuf::from("crabs"), path::PathBuf::from("shells")],
+            "Invalid crate directories",
+        );
+    }
+
+    #[test]
+    fn version_is_the_highest_suggestion() {
+        let suggestion = |published: &str, bump| {
+            let published = published
+                .parse::<crate::semver::Version>()
+                .expect("Failed to parse a version");
+
+            crate::semver::Suggestion {
+                next: published.next(bump),
+                published,
+                bump,
+                changes: vec![],
+            }
+        };
+
+        let version = super::version(&[
+            (
+                "crabs".to_string(),
+                suggestion("1.4.2", crate::semver::Bump::Minor),
+            ),
+            (
+                "shells".to_string(),
+                suggestion("1.4.2", crate::semver::Bump::Major),
+            ),
+        ])
+        .expect("Expected a version");
+        assert_eq!(version.to_string(), "2.0.0", "Invalid release version");
+
+        let err = super::version(&[]);
+        assert!(
+            matches!(err, Err(super::Error::NoBaseline)),
+            "Expected `Err(Error::NoBaseline)`, got: `{:?}`",
+            err
+        );
+    }
+}
@@ -0,0 +1,575 @@
+use std::{collections, fs, io, ops, path};
+
+use crate::{exclude, lookup, naming, patcher, reexports};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Invalid protobuf name `{0}`, expected a fully qualified name, e.g. `crabs.Ferris`")]
+    InvalidName(String),
+    #[error("Failed to read the protobuf files from `{1}`: {0}")]
+    WalkDir(ignore::Error, path::PathBuf),
+    #[error("Failed to read the protobuf file `{1}`: {0}")]
+    Read(io::Error, path::PathBuf),
+    #[error("Failed to write the protobuf file `{1}`: {0}")]
+    Write(io::Error, path::PathBuf),
+    #[error("No package, message, enum or service named `{0}` is defined in the protobuf files")]
+    NotFound(String),
+    #[error("`{0}` is already defined in the protobuf files")]
+    AlreadyDefined(String),
+    #[error(
+        "Moving `{0}` into another scope as `{1}` is not supported, only a package or the last part of the name of a definition can be renamed"
+    )]
+    Move(String, String),
+}
+
+/// A protobuf file rewritten by [`rename`].
+#[derive(PartialEq, Debug)]
+pub struct Rewritten {
+    pub path: path::PathBuf,
+    /// How many names were replaced in the file.
+    pub replacements: usize,
+}
+
+/// The outcome of a [`rename`].
+#[derive(PartialEq, Debug)]
+pub struct Report {
+    /// The rewritten protobuf files, sorted by path within each source directory.
+    pub files: Vec<Rewritten>,
+    /// The Rust paths of the generated code before and after the renaming, e.g. the module of a
+    /// renamed package or the type of a renamed message.
+    pub rust_paths: Vec<(String, String)>,
+}
+
+/// A token of the protobuf code, ignoring the whitespace and the comments.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Token {
+    /// An identifier, a possibly qualified name or a number, e.g. `.crabs.Ferris`.
+    Ident,
+    /// A string literal, including its quotes.
+    Literal,
+    Punct(u8),
+}
+
+fn tokenize(source: &[u8]) -> Vec<(Token, ops::Range<usize>)> {
+    let mut scan = patcher::Scan::Code;
+    let mut tokens: Vec<(Token, ops::Range<usize>)> = vec![];
+
+    source.iter().enumerate().for_each(|(pos, &ch)| {
+        let token = match scan.advance(ch) {
+            (false, _) => return,
+            (true, false) => Token::Literal,
+            (true, true) if ch.is_ascii_alphanumeric() || matches!(ch, b'_' | b'.') => Token::Ident,
+            (true, true) => Token::Punct(ch),
+        };
+
+        match tokens.last_mut() {
+            Some((last, range))
+                if *last == token && !matches!(token, Token::Punct(_)) && range.end == pos =>
+            {
+                range.end = pos + 1
+            }
+            _ => tokens.push((token, pos..pos + 1)),
+        }
+    });
+
+    tokens
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Role {
+    /// The name in a `package` statement.
+    Package,
+    /// The name a message, an enum or a service is defined with.
+    Definition(lookup::Kind),
+    /// Anything else, which may refer to a definition.
+    Reference,
+}
+
+/// A name in a protobuf file, along with the scope it appears in.
+#[derive(Debug)]
+struct Name {
+    role: Role,
+    text: String,
+    range: ops::Range<usize>,
+    package: String,
+    /// The names of the messages the name is nested in, outermost first.
+    parents: Vec<String>,
+}
+
+impl Name {
+    /// The scope the name is resolved in, e.g. `["crabs", "Ferris"]` within the `crabs.Ferris`
+    /// message.
+    fn scope(&self) -> Vec<&str> {
+        self.package
+            .split('.')
+            .filter(|segment| !segment.is_empty())
+            .chain(self.parents.iter().map(String::as_str))
+            .collect()
+    }
+
+    /// The fully qualified name of a definition.
+    fn full_name(&self) -> String {
+        self.scope()
+            .into_iter()
+            .chain([self.text.as_str()])
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+}
+
+/// Every name in the protobuf `source`. The names followed by `=` or `(` are left out, since they
+/// are the names of the fields, the options, the enum values or the methods rather than of the
+/// types.
+fn names(source: &[u8]) -> Vec<Name> {
+    let tokens = tokenize(source);
+    let text = |range: &ops::Range<usize>| String::from_utf8_lossy(&source[range.clone()]).into();
+    let token = |pos: usize| tokens.get(pos).map(|(token, _)| *token);
+
+    let mut package = String::new();
+    // The messages the braces open, if any, and the one the next brace opens.
+    let mut parents: Vec<Option<String>> = vec![];
+    let mut pending = None;
+    let mut names = vec![];
+    let mut pos = 0;
+
+    while let Some((current, range)) = tokens.get(pos) {
+        let kind = match &source[range.clone()] {
+            b"message" => Some(lookup::Kind::Message),
+            b"enum" => Some(lookup::Kind::Enum),
+            b"service" => Some(lookup::Kind::Service),
+            _ => None,
+        };
+        let mut push = |role, range: &ops::Range<usize>| {
+            names.push(Name {
+                role,
+                text: text(range),
+                range: range.clone(),
+                package: package.clone(),
+                parents: parents.iter().flatten().cloned().collect(),
+            })
+        };
+
+        match (current, token(pos + 1), token(pos + 2)) {
+            (Token::Punct(b'{'), _, _) => parents.push(pending.take()),
+            (Token::Punct(b'}'), _, _) => {
+                parents.pop();
+            }
+            (Token::Ident, Some(Token::Ident), _) if &source[range.clone()] == b"package" => {
+                pos += 1;
+                push(Role::Package, &tokens[pos].1);
+                package = text(&tokens[pos].1);
+            }
+            (Token::Ident, Some(Token::Ident), Some(Token::Punct(b'{'))) if kind.is_some() => {
+                let kind = kind.unwrap_or(lookup::Kind::Message);
+                pos += 1;
+                push(Role::Definition(kind), &tokens[pos].1);
+                // The enum values and the methods are not nested in the scope of their parent.
+                pending = (kind == lookup::Kind::Message).then(|| text(&tokens[pos].1));
+            }
+            (Token::Ident, Some(Token::Punct(b'=' | b'(')), _) => {}
+            (Token::Ident, _, _) => push(Role::Reference, range),
+            _ => {}
+        }
+
+        pos += 1;
+    }
+
+    names
+}
+
+/// The `name` with its `from` prefix replaced with the `to`, if it is the `from` or nested in it.
+fn renamed(name: &str, from: &str, to: &str) -> Option<String> {
+    match name.strip_prefix(from)? {
+        "" => Some(to.to_string()),
+        rest => rest.strip_prefix('.').map(|rest| format!("{to}.{rest}")),
+    }
+}
+
+/// The fully qualified name the relative `name` refers to, resolved from the innermost `scope`
+/// outwards to the first one its first part is `known` in, along with that scope. Names starting
+/// with a dot are already fully qualified.
+fn resolve(
+    name: &str,
+    scope: &[&str],
+    known: &collections::BTreeSet<String>,
+) -> Option<(String, Option<String>)> {
+    if let Some(name) = name.strip_prefix('.') {
+        return Some((name.to_string(), None));
+    }
+
+    let first = name.split('.').next()?;
+
+    (0..=scope.len()).rev().find_map(|len| {
+        let prefix = scope[..len].join(".");
+        let qualify = |name: &str| match prefix.as_str() {
+            "" => name.to_string(),
+            prefix => format!("{prefix}.{name}"),
+        };
+
+        known
+            .contains(&qualify(first))
+            .then(|| (qualify(name), Some(prefix.clone())))
+    })
+}
+
+/// A protobuf name, made of identifiers separated by dots.
+fn is_valid(name: &str) -> bool {
+    name.split('.').all(|part| {
+        part.starts_with(|ch: char| ch.is_ascii_alphabetic() || ch == '_')
+            && part
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+    })
+}
+
+/// Rename the package, or the message, the enum or the service, with the fully qualified
+/// protobuf name `from` to the `to` in every protobuf file in the `sources`: the `package`
+/// statements, the definition and every reference to it, relative or fully qualified, are
+/// rewritten, while the comments and the string literals are left untouched. The references are
+/// resolved the way `protoc` does, against the definitions found in the `sources`. The Rust paths
+/// of the generated code are reported following the `naming` rules. Nothing is written when
+/// `dry_run`.
+///
+/// A definition can only be renamed within its scope, e.g. `crabs.Ferris` to `crabs.Crab`, since
+/// moving it into another package means moving it into another file.
+pub fn rename(
+    sources: &[path::PathBuf],
+    from: &str,
+    to: &str,
+    naming: &naming::Naming,
+    dry_run: bool,
+) -> Result<Report, Error> {
+    let (from, to) = (from.trim_start_matches('.'), to.trim_start_matches('.'));
+    if let Some(name) = [from, to].into_iter().find(|name| !is_valid(name)) {
+        return Err(Error::InvalidName(name.to_string()));
+    }
+
+    let walk = exclude::Exclusions::default();
+    let files = sources
+        .iter()
+        .map(|src| {
+            let mut files = walk
+                .walk(src)
+                .map(|entry| {
+                    entry
+                        .map(ignore::DirEntry::into_path)
+                        .map_err(|e| Error::WalkDir(e, src.clone()))
+                })
+                .filter(|path| {
+                    path.as_ref().map_or(true, |path| {
+                        path.is_file() && path.extension().is_some_and(|ext| ext == "proto")
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            files.sort();
+
+            Ok(files)
+        })
+        .collect::<Result<Vec<_>, Error>>()?
+        .into_iter()
+        .flatten()
+        .map(|path| {
+            let source = fs::read(&path).map_err(|e| Error::Read(e, path.clone()))?;
+            let names = names(&source);
+
+            Ok((path, source, names))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    // Every package, along with its parents, and every definition, by its fully qualified name.
+    let mut packages = collections::BTreeSet::new();
+    let mut definitions = collections::BTreeMap::new();
+    files
+        .iter()
+        .flat_map(|(_, _, names)| names)
+        .for_each(|name| match name.role {
+            Role::Package => {
+                let mut parts = name.text.split('.').collect::<Vec<_>>();
+                while !parts.is_empty() {
+                    packages.insert(parts.join("."));
+                    parts.pop();
+                }
+            }
+            Role::Definition(_) => {
+                definitions.insert(name.full_name(), name);
+            }
+            Role::Reference => {}
+        });
+
+    if packages.contains(to) || definitions.contains_key(to) {
+        return Err(Error::AlreadyDefined(to.to_string()));
+    }
+    let rust_paths = match (packages.contains(from), definitions.get(from)) {
+        (true, _) => packages
+            .iter()
+            .filter_map(|package| {
+                let module = |package: &str| reexports::module_path(naming, package).join("::");
+
+                renamed(package, from, to).map(|new| (module(package), module(&new)))
+            })
+            .collect(),
+        (false, Some(definition)) => {
+            let (scope, name) = to.rsplit_once('.').unwrap_or(("", to));
+            if scope != definition.scope().join(".") {
+                return Err(Error::Move(from.to_string(), to.to_string()));
+            }
+
+            let Role::Definition(kind) = definition.role else {
+                return Err(Error::NotFound(from.to_string()));
+            };
+            let paths = |name| {
+                let names = definition
+                    .parents
+                    .iter()
+                    .map(String::as_str)
+                    .chain([name])
+                    .collect::<Vec<_>>();
+
+                lookup::rust_paths(naming, &definition.package, kind, &names)
+            };
+
+            paths(&definition.text)
+                .into_iter()
+                .zip(paths(name))
+                .collect()
+        }
+        (false, None) => return Err(Error::NotFound(from.to_string())),
+    };
+
+    let known = packages
+        .iter()
+        .cloned()
+        .chain(definitions.keys().cloned())
+        .collect();
+    let mut rewritten = vec![];
+
+    for (path, source, names) in &files {
+        let replacements = names
+            .iter()
+            .filter_map(|name| {
+                let new = match name.role {
+                    Role::Package => renamed(&name.text, from, to),
+                    Role::Definition(_) if name.full_name() == from => {
+                        to.rsplit('.').next().map(str::to_string)
+                    }
+                    Role::Definition(_) => None,
+                    Role::Reference => {
+                        let (full_name, prefix) = resolve(&name.text, &name.scope(), &known)?;
+                        let full_name = renamed(&full_name, from, to)?;
+
+                        // The scope the name is relative to may be renamed along with it.
+                        Some(
+                            match prefix.map(|prefix| renamed(&prefix, from, to).unwrap_or(prefix))
+                            {
+                                Some(prefix) if prefix.is_empty() => full_name,
+                                Some(prefix) => match full_name.strip_prefix(&format!("{prefix}."))
+                                {
+                                    Some(relative) => relative.to_string(),
+                                    None => format!(".{full_name}"),
+                                },
+                                None => format!(".{full_name}"),
+                            },
+                        )
+                    }
+                };
+
+                new.filter(|new| *new != name.text)
+                    .map(|new| (name.range.clone(), new))
+            })
+            .collect::<Vec<_>>();
+
+        if replacements.is_empty() {
+            continue;
+        }
+
+        if !dry_run {
+            let mut patched = Vec::with_capacity(source.len());
+            let copied = replacements.iter().fold(0, |copied, (range, new)| {
+                patched.extend_from_slice(&source[copied..range.start]);
+                patched.extend_from_slice(new.as_bytes());
+
+                range.end
+            });
+            patched.extend_from_slice(&source[copied..]);
+
+            fs::write(path, patched).map_err(|e| Error::Write(e, path.clone()))?;
+        }
+
+        rewritten.push(Rewritten {
+            path: path.clone(),
+            replacements: replacements.len(),
+        });
+    }
+
+    Ok(Report {
+        files: rewritten,
+        rust_paths,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    const FERRIS: &str = r#"syntax = "proto3";
+
+// Ferris lives in corp.old.
+package corp.old;
+
+message Ferris {
+  message Claw { int32 size = 1; }
+  Claw left = 1;
+  corp.old.Ferris.Claw right = 2;
+  string note = 3 [json_name = "corp.old.Ferris"];
+}
+
+service CrabService {
+  rpc GetFerris(Ferris) returns (Ferris);
+}
+"#;
+
+    const SEA: &str = r#"syntax = "proto3";
+
+package corp.other;
+
+import "crabs/ferris.proto";
+
+message Sea {
+  old.Ferris ferris = 1;
+  .corp.old.Ferris.Claw claw = 2;
+}
+"#;
+
+    fn sources() -> tempfile::TempDir {
+        let src = tempfile::TempDir::new().expect("Failed to create a test directory");
+        fs::create_dir(src.path().join("crabs")).expect("Failed to create a test directory");
+        fs::write(src.path().join("crabs/ferris.proto"), FERRIS)
+            .expect("Failed to write a test file");
+        fs::write(src.path().join("sea.proto"), SEA).expect("Failed to write a test file");
+
+        src
+    }
+
+    #[test]
+    fn rename_rewrites_the_definition_and_its_references() {
+        let src = sources();
+
+        let report = super::rename(
+            &[src.path().to_path_buf()],
+            "corp.old.Ferris",
+            "corp.old.Crab",
+            &crate::naming::Naming::default(),
+            false,
+        )
+        .expect("Failed to rename the message");
+
+        assert_eq!(
+            report,
+            super::Report {
+                files: vec![
+                    super::Rewritten {
+                        path: src.path().join("crabs/ferris.proto"),
+                        replacements: 4,
+                    },
+                    super::Rewritten {
+                        path: src.path().join("sea.proto"),
+                        replacements: 2,
+                    },
+                ],
+                rust_paths: vec![(
+                    "corp::old::Ferris".to_string(),
+                    "corp::old::Crab".to_string()
+                )],
+            },
+        );
+        assert_eq!(
+            fs::read_to_string(src.path().join("crabs/ferris.proto"))
+                .expect("Failed to read the renamed file"),
+            FERRIS
+                .replace("message Ferris {", "message Crab {")
+                .replace("corp.old.Ferris.Claw right", "corp.old.Crab.Claw right")
+                .replace("(Ferris) returns (Ferris)", "(Crab) returns (Crab)"),
+            "Expected the comments and the string literals to be left untouched",
+        );
+        assert_eq!(
+            fs::read_to_string(src.path().join("sea.proto"))
+                .expect("Failed to read the renamed file"),
+            SEA.replace("old.Ferris ferris", "old.Crab ferris")
+                .replace(".corp.old.Ferris.Claw", ".corp.old.Crab.Claw"),
+        );
+    }
+
+    #[test]
+    fn rename_dry_run_reports_the_renamed_package() {
+        let src = sources();
+
+        let report = super::rename(
+            &[src.path().to_path_buf()],
+            "corp.old",
+            "corp.new",
+            &crate::naming::Naming::default(),
+            true,
+        )
+        .expect("Failed to rename the package");
+
+        assert_eq!(
+            report
+                .files
+                .iter()
+                .map(|file| file.replacements)
+                .collect::<Vec<_>>(),
+            [2, 2],
+        );
+        assert_eq!(
+            report.rust_paths,
+            [("corp::old".to_string(), "corp::new".to_string())],
+        );
+        assert_eq!(
+            fs::read_to_string(src.path().join("sea.proto")).expect("Failed to read the file"),
+            SEA,
+            "Expected nothing to be written in a dry run",
+        );
+    }
+
+    #[test]
+    fn rename_rejects_invalid_renames() {
+        let src = sources();
+        let rename = |from, to| {
+            super::rename(
+                &[src.path().to_path_buf()],
+                from,
+                to,
+                &crate::naming::Naming::default(),
+                false,
+            )
+        };
+
+        let err = rename("corp.old.Ferris", "corp.other.Ferris");
+        assert!(
+            matches!(err, Err(super::Error::Move(..))),
+            "Expected `Err(Error::Move)`, got: `{:?}`",
+            err
+        );
+
+        let err = rename("corp.old.Ferris", "corp.old.CrabService");
+        assert!(
+            matches!(err, Err(super::Error::AlreadyDefined(..))),
+            "Expected `Err(Error::AlreadyDefined)`, got: `{:?}`",
+            err
+        );
+
+        let err = rename("corp.old.Krabs", "corp.old.Crab");
+        assert!(
+            matches!(err, Err(super::Error::NotFound(..))),
+            "Expected `Err(Error::NotFound)`, got: `{:?}`",
+            err
+        );
+
+        let err = rename("corp.old.Ferris", "corp.old.1Crab");
+        assert!(
+            matches!(err, Err(super::Error::InvalidName(..))),
+            "Expected `Err(Error::InvalidName)`, got: `{:?}`",
+            err
+        );
+    }
+}
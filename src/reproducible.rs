@@ -0,0 +1,253 @@
+use std::{cmp, env, ffi, fs, io, path, time};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to read the generated files from `{1}`: {0}")]
+    ReadDir(walkdir::Error, path::PathBuf),
+    #[error("Failed to read the generated file `{1}`: {0}")]
+    ReadFile(io::Error, path::PathBuf),
+    #[error("The generated file `{0}` embeds the machine-specific path `{1}`")]
+    EmbeddedPath(path::PathBuf, path::PathBuf),
+    #[error(
+        "Invalid `SOURCE_DATE_EPOCH` `{0}`, expected the number of seconds since the Unix epoch"
+    )]
+    SourceDateEpoch(String),
+    #[error("Failed to set the modification time of the generated file `{1}`: {0}")]
+    SetTime(io::Error, path::PathBuf),
+}
+
+/// The `path` with its Windows separators replaced with `/`.
+fn to_slash(path: &str) -> String {
+    path.replace('\\', "/")
+}
+
+/// The `roots` as the prefixes of the paths under them, e.g. `/home/crab/proto/`, separated by `/`
+/// and the longest first, so the nested roots are matched before the ones containing them.
+fn prefixes(roots: &[path::PathBuf]) -> Vec<String> {
+    let mut prefixes = roots
+        .iter()
+        .filter_map(|root| root.to_str())
+        .map(|root| format!("{}/", to_slash(root).trim_end_matches('/')))
+        .collect::<Vec<_>>();
+    prefixes.sort_by_key(|prefix| cmp::Reverse(prefix.len()));
+
+    prefixes
+}
+
+/// Rewrite the names of the files and of their dependencies in the `fds` to be separated by `/`
+/// and relative to the one of the `roots` they are under, if any, returning whether any changed.
+pub fn normalize_fds(fds: &mut prost_types::FileDescriptorSet, roots: &[path::PathBuf]) -> bool {
+    let prefixes = prefixes(roots);
+    let mut normalized = false;
+    let mut normalize = |name: &mut String| {
+        let slashed = to_slash(name);
+        let relative = prefixes
+            .iter()
+            .find_map(|prefix| slashed.strip_prefix(prefix.as_str()))
+            .unwrap_or(&slashed);
+        if relative != name {
+            *name = relative.to_string();
+            normalized = true;
+        }
+    };
+
+    fds.file.iter_mut().for_each(|file| {
+        file.name.iter_mut().for_each(&mut normalize);
+        file.dependency.iter_mut().for_each(&mut normalize);
+    });
+
+    normalized
+}
+
+fn find_embedded_path<'a>(
+    contents: &[u8],
+    paths: &'a [path::PathBuf],
+) -> Option<&'a path::PathBuf> {
+    paths.iter().find(|path| {
+        let needle = path.as_os_str().as_encoded_bytes();
+
+        !needle.is_empty()
+            && contents
+                .windows(needle.len())
+                .any(|window| window == needle)
+    })
+}
+
+/// Ensure that none of the `paths` are embedded into the `file`.
+pub fn check_file(file: &path::Path, paths: &[path::PathBuf]) -> Result<(), Error> {
+    let contents = fs::read(file).map_err(|e| Error::ReadFile(e, file.to_path_buf()))?;
+
+    match find_embedded_path(&contents, paths) {
+        Some(path) => Err(Error::EmbeddedPath(file.to_path_buf(), path.clone())),
+        None => Ok(()),
+    }
+}
+
+/// Ensure that none of the `paths` are embedded into any file inside of the `dir`.
+pub fn check_dir(dir: &path::Path, paths: &[path::PathBuf]) -> Result<(), Error> {
+    walkdir::WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .try_for_each(|entry| {
+            let entry = entry.map_err(|e| Error::ReadDir(e, dir.to_path_buf()))?;
+
+            if entry.file_type().is_file() {
+                check_file(entry.path(), paths)
+            } else {
+                Ok(())
+            }
+        })
+}
+
+/// The time set by the `SOURCE_DATE_EPOCH` environment variable, if any, which the reproducible
+/// builds use to replace the current time.
+pub fn source_date_epoch() -> Result<Option<time::SystemTime>, Error> {
+    let Some(value) = env::var_os("SOURCE_DATE_EPOCH") else {
+        return Ok(None);
+    };
+    let value = value.to_string_lossy();
+
+    value
+        .trim()
+        .parse()
+        .map(|secs| Some(time::UNIX_EPOCH + time::Duration::from_secs(secs)))
+        .map_err(|_| Error::SourceDateEpoch(value.into_owned()))
+}
+
+/// Set the modification time of every file inside of the `dir` to the `mtime`.
+pub fn set_mtimes(dir: &path::Path, mtime: time::SystemTime) -> Result<(), Error> {
+    walkdir::WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .try_for_each(|entry| {
+            let entry = entry.map_err(|e| Error::ReadDir(e, dir.to_path_buf()))?;
+
+            if entry.file_type().is_file() {
+                set_mtime(entry.path(), mtime)
+            } else {
+                Ok(())
+            }
+        })
+}
+
+/// Set the modification time of the `file` to the `mtime`.
+pub fn set_mtime(file: &path::Path, mtime: time::SystemTime) -> Result<(), Error> {
+    fs::File::options()
+        .write(true)
+        .open(file)
+        .and_then(|file| file.set_modified(mtime))
+        .map_err(|e| Error::SetTime(e, file.to_path_buf()))
+}
+
+/// Resolve the machine-specific absolute forms of the `paths` that must not leak into the output.
+pub fn machine_paths<'a>(paths: impl IntoIterator<Item = &'a path::Path>) -> Vec<path::PathBuf> {
+    paths
+        .into_iter()
+        .flat_map(|path| {
+            [
+                Some(path.to_path_buf()).filter(|path| path.is_absolute()),
+                fs::canonicalize(path).ok(),
+            ]
+        })
+        .flatten()
+        .filter(|path| path.as_os_str() != ffi::OsStr::new("/"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path, time};
+
+    #[test]
+    fn check_dir_succeeds_without_embedded_paths() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        fs::write(dir.path().join("mod.rs"), "struct Clean;\n")
+            .expect("Failed to create a test source file");
+
+        super::check_dir(dir.path(), &[path::PathBuf::from("/home/crab/proto")])
+            .expect("Expected the directory to contain no embedded paths");
+    }
+
+    #[test]
+    fn check_dir_fails_on_embedded_paths() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        fs::create_dir(dir.path().join("crabs")).expect("Failed to create a test subdirectory");
+        fs::write(
+            dir.path().join("crabs/mod.rs"),
+            "// Generated from /home/crab/proto/crabs/Ferris.proto\nstruct Ferris;\n",
+        )
+        .expect("Failed to create a test source file");
+
+        let err = super::check_dir(dir.path(), &[path::PathBuf::from("/home/crab/proto")]);
+        assert!(
+            matches!(err, Err(super::Error::EmbeddedPath { .. })),
+            "Expected `Err(Error::EmbeddedPath)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn machine_paths_resolves_relative_paths() {
+        let paths = super::machine_paths([path::Path::new("./proto")]);
+        let expected = fs::canonicalize("./proto").expect("Failed to resolve the test path");
+
+        assert_eq!(paths, vec![expected], "Invalid machine-specific paths");
+    }
+
+    #[test]
+    fn normalize_fds_makes_the_file_names_relative() {
+        let mut fds = prost_types::FileDescriptorSet {
+            file: vec![prost_types::FileDescriptorProto {
+                name: Some("/home/crab/proto/crabs/ferris.proto".to_string()),
+                dependency: vec![
+                    "/home/crab/proto/crabs/shell.proto".to_string(),
+                    "crabs\\claw.proto".to_string(),
+                    "google/protobuf/timestamp.proto".to_string(),
+                ],
+                ..Default::default()
+            }],
+        };
+
+        assert!(
+            super::normalize_fds(&mut fds, &[path::PathBuf::from("/home/crab/proto")]),
+            "Expected the file names to be normalized"
+        );
+        assert_eq!(
+            (fds.file[0].name(), fds.file[0].dependency.as_slice()),
+            (
+                "crabs/ferris.proto",
+                &[
+                    "crabs/shell.proto".to_string(),
+                    "crabs/claw.proto".to_string(),
+                    "google/protobuf/timestamp.proto".to_string(),
+                ][..]
+            ),
+            "Expected the file names to be relative to the root",
+        );
+    }
+
+    #[test]
+    fn set_mtimes_sets_the_modification_time_of_every_file() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        fs::create_dir(dir.path().join("crabs")).expect("Failed to create a test subdirectory");
+        let files = [dir.path().join("mod.rs"), dir.path().join("crabs/mod.rs")];
+        files.iter().for_each(|file| {
+            fs::write(file, "struct Ferris;\n").expect("Failed to create a test source file");
+        });
+
+        let mtime = time::UNIX_EPOCH + time::Duration::from_secs(1_700_000_000);
+        super::set_mtimes(dir.path(), mtime).expect("Failed to set the modification times");
+
+        files.iter().for_each(|file| {
+            let modified = fs::metadata(file)
+                .and_then(|metadata| metadata.modified())
+                .expect("Failed to read the modification time");
+            assert_eq!(
+                modified,
+                mtime,
+                "Invalid modification time of `{}`",
+                file.display()
+            );
+        });
+    }
+}
@@ -0,0 +1,136 @@
+use std::{collections, fmt};
+
+/// A field of a message in the previous schema that was removed without reserving its number.
+#[derive(PartialEq, Debug)]
+pub struct Removed {
+    /// The fully qualified name of the message, e.g. `crabs.Ferris`.
+    pub message: String,
+    pub field: String,
+    pub number: i32,
+}
+
+impl fmt::Display for Removed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}.{}` = {}", self.message, self.field, self.number)
+    }
+}
+
+/// Collect the messages, and the messages nested in them, keyed by their fully qualified name.
+fn collect<'a>(
+    messages: &mut collections::BTreeMap<String, &'a prost_types::DescriptorProto>,
+    scope: &str,
+    nested: &'a [prost_types::DescriptorProto],
+) {
+    nested.iter().for_each(|message| {
+        let name = match scope {
+            "" => message.name().to_string(),
+            scope => format!("{scope}.{}", message.name()),
+        };
+
+        collect(messages, &name, &message.nested_type);
+        messages.insert(name, message);
+    });
+}
+
+fn messages(
+    fds: &prost_types::FileDescriptorSet,
+) -> collections::BTreeMap<String, &prost_types::DescriptorProto> {
+    let mut messages = collections::BTreeMap::new();
+
+    fds.file
+        .iter()
+        .for_each(|file| collect(&mut messages, file.package(), &file.message_type));
+
+    messages
+}
+
+/// Find the fields of the `previous` schema that are missing from their message in the `current`
+/// one without their number being reserved, so a later field could reuse it and misread the data
+/// written by the older code. The messages removed altogether are not checked.
+pub fn find_unreserved(
+    previous: &prost_types::FileDescriptorSet,
+    current: &prost_types::FileDescriptorSet,
+) -> Vec<Removed> {
+    let current = messages(current);
+
+    messages(previous)
+        .into_iter()
+        .filter_map(|(name, message)| {
+            let current = *current.get(&name)?;
+
+            Some((name, message, current))
+        })
+        .flat_map(|(name, previous, current)| {
+            let is_kept = |number: i32| {
+                current.field.iter().any(|field| field.number() == number)
+                    || current
+                        .reserved_range
+                        .iter()
+                        // The reserved ranges of the messages are exclusive of their end.
+                        .any(|range| (range.start()..range.end()).contains(&number))
+            };
+
+            previous
+                .field
+                .iter()
+                .filter(move |field| !is_kept(field.number()))
+                .map(move |field| Removed {
+                    message: name.clone(),
+                    field: field.name().to_string(),
+                    number: field.number(),
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn find_unreserved_reports_reused_numbers() {
+        let field = |name: &str, number| prost_types::FieldDescriptorProto {
+            name: Some(name.to_string()),
+            number: Some(number),
+            ..Default::default()
+        };
+        let fds = |fields, reserved: Vec<(i32, i32)>| prost_types::FileDescriptorSet {
+            file: vec![prost_types::FileDescriptorProto {
+                package: Some("crabs".to_string()),
+                message_type: vec![prost_types::DescriptorProto {
+                    name: Some("Ferris".to_string()),
+                    nested_type: vec![prost_types::DescriptorProto {
+                        name: Some("Claw".to_string()),
+                        field: fields,
+                        reserved_range: reserved
+                            .into_iter()
+                            .map(
+                                |(start, end)| prost_types::descriptor_proto::ReservedRange {
+                                    start: Some(start),
+                                    end: Some(end),
+                                },
+                            )
+                            .collect(),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        let previous = fds(
+            vec![field("size", 1), field("color", 2), field("sharpness", 3)],
+            vec![],
+        );
+        let current = fds(vec![field("size", 1)], vec![(3, 4)]);
+
+        assert_eq!(
+            super::find_unreserved(&previous, &current),
+            [super::Removed {
+                message: "crabs.Ferris.Claw".to_string(),
+                field: "color".to_string(),
+                number: 2,
+            }],
+            "Invalid removed fields",
+        );
+    }
+}
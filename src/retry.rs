@@ -0,0 +1,137 @@
+use std::{fmt, io, thread, time};
+
+/// The raw OS error code of `EIO`, shared by Linux and macOS, which network file systems report for
+/// transient failures.
+const EIO: i32 = 5;
+
+/// How many times a phase is attempted and how long to wait before the first retry, doubling the
+/// delay after every failed attempt.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Policy {
+    pub retries: u32,
+    pub backoff: time::Duration,
+}
+
+/// Whether the `error` is likely to go away on its own, e.g. `EIO` on a network file system or
+/// `ETXTBSY` when spawning a `protoc` binary that is still being written.
+pub fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ExecutableFileBusy
+            | io::ErrorKind::ResourceBusy
+            | io::ErrorKind::StaleNetworkFileHandle
+    ) || error.raw_os_error() == Some(EIO)
+}
+
+/// Run the `attempt` until it succeeds, fails with an error the `transient` predicate rejects, or
+/// the retries of the `policy` are exhausted. The attempt receives its zero-based index, so it can
+/// clean up after a previous failure, and every retry is added to `retries`.
+pub fn run<T, E: fmt::Display>(
+    policy: &Policy,
+    retries: &mut u32,
+    transient: impl Fn(&E) -> bool,
+    mut attempt: impl FnMut(u32) -> Result<T, E>,
+) -> Result<T, E> {
+    let mut backoff = policy.backoff;
+    let mut index = 0;
+
+    loop {
+        match attempt(index) {
+            Err(e) if index < policy.retries && transient(&e) => {
+                tracing::warn!(
+                    "Retrying in {backoff:?} after a transient failure (attempt {} of {}): {e}",
+                    index + 1,
+                    policy.retries + 1,
+                );
+
+                thread::sleep(backoff);
+                backoff = backoff.saturating_mul(2);
+                index += 1;
+                *retries += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Like [`run`], but awaits the `attempt` and the backoff without blocking the thread.
+#[cfg(feature = "async")]
+pub async fn run_async<T, E: fmt::Display, F: Future<Output = Result<T, E>>>(
+    policy: &Policy,
+    retries: &mut u32,
+    transient: impl Fn(&E) -> bool,
+    mut attempt: impl FnMut(u32) -> F,
+) -> Result<T, E> {
+    let mut backoff = policy.backoff;
+    let mut index = 0;
+
+    loop {
+        match attempt(index).await {
+            Err(e) if index < policy.retries && transient(&e) => {
+                tracing::warn!(
+                    "Retrying in {backoff:?} after a transient failure (attempt {} of {}): {e}",
+                    index + 1,
+                    policy.retries + 1,
+                );
+
+                tokio::time::sleep(backoff).await;
+                backoff = backoff.saturating_mul(2);
+                index += 1;
+                *retries += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, time};
+
+    const POLICY: super::Policy = super::Policy {
+        retries: 2,
+        backoff: time::Duration::ZERO,
+    };
+
+    #[test]
+    fn run_retries_transient_failures() {
+        let mut retries = 0;
+
+        let result = super::run(&POLICY, &mut retries, super::is_transient, |index| {
+            if index < 2 {
+                Err(io::Error::from_raw_os_error(super::EIO))
+            } else {
+                Ok(index)
+            }
+        });
+
+        assert_eq!(result.ok(), Some(2), "Expected the last attempt to succeed");
+        assert_eq!(retries, 2, "Expected every retry to be counted");
+    }
+
+    #[test]
+    fn run_gives_up() {
+        let mut retries = 0;
+
+        let result = super::run(&POLICY, &mut retries, super::is_transient, |_| {
+            Err::<(), _>(io::Error::from(io::ErrorKind::ExecutableFileBusy))
+        });
+        assert!(
+            result.is_err_and(|e| e.kind() == io::ErrorKind::ExecutableFileBusy),
+            "Expected the error to be returned once the retries are exhausted",
+        );
+        assert_eq!(retries, 2, "Expected every retry to be counted");
+
+        let mut retries = 0;
+        let result = super::run(&POLICY, &mut retries, super::is_transient, |_| {
+            Err::<(), _>(io::Error::from(io::ErrorKind::NotFound))
+        });
+        assert!(
+            result.is_err(),
+            "Expected a permanent failure to be returned"
+        );
+        assert_eq!(retries, 0, "Expected a permanent failure not to be retried");
+    }
+}
@@ -0,0 +1,131 @@
+use std::{fmt::Write as _, fs, io, path};
+
+use crate::modgen;
+
+/// The private module the root file loads the generated module tree into, before re-exporting
+/// all of it.
+const MODULE: &str = "generated_tree";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(
+        "Invalid root file name `{0}`, expected the name of a Rust file other than the root module"
+    )]
+    InvalidName(String),
+    #[error(
+        "Invalid root attribute `{0}`, expected an inner attribute, e.g. `#![allow(clippy::all)]`"
+    )]
+    InvalidAttribute(String),
+    #[error("Failed to write the root file `{1}`: {0}")]
+    Write(io::Error, path::PathBuf),
+}
+
+/// Check the `name` of the root file and its inner `attributes` upfront, so a typo fails the run
+/// before anything is generated.
+pub fn validate(name: &str, attributes: &[String], layout: modgen::Layout) -> Result<(), Error> {
+    let is_file_name = !name.contains(['/', '\\']) && name.len() > 3 && name.ends_with(".rs");
+    if !is_file_name || name == layout.root_file() {
+        return Err(Error::InvalidName(name.to_string()));
+    }
+
+    validate_attributes(attributes)
+}
+
+/// Check that the `attributes` are all inner attributes.
+pub fn validate_attributes(attributes: &[String]) -> Result<(), Error> {
+    match attributes
+        .iter()
+        .find(|attribute| !(attribute.starts_with("#![") && attribute.ends_with(']')))
+    {
+        Some(attribute) => Err(Error::InvalidAttribute(attribute.clone())),
+        None => Ok(()),
+    }
+}
+
+/// The root file applying the inner `attributes` to the module tree and re-exporting it, so it
+/// can be used as the root of a crate or loaded with `#[path = "<output>/<name>"] mod autogen;`.
+fn render(attributes: &[String], layout: modgen::Layout) -> String {
+    let mut code = "// This file is @generated by pbuildrs.\n".to_string();
+
+    attributes.iter().for_each(|attribute| {
+        let _ = writeln!(code, "{attribute}");
+    });
+
+    let _ = write!(
+        code,
+        "\n#[path = \"{}\"]\nmod {MODULE};\n\npub use {MODULE}::*;\n",
+        layout.root_file(),
+    );
+
+    code
+}
+
+/// Write the root file with the `name` and the inner `attributes` into the `dst` directory, next
+/// to the root module of the tree laid out with the `layout`.
+pub fn write(
+    dst: &path::Path,
+    name: &str,
+    attributes: &[String],
+    layout: modgen::Layout,
+) -> Result<path::PathBuf, Error> {
+    let path = dst.join(name);
+    tracing::debug!("Writing the root file: {}", path.display());
+    fs::write(&path, render(attributes, layout)).map_err(|e| Error::Write(e, path.clone()))?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn render_reexports_the_tree_with_the_attributes() {
+        let attributes = [
+            "#![allow(clippy::all)]".to_string(),
+            "#![allow(missing_docs)]".to_string(),
+        ];
+
+        assert_eq!(
+            super::render(&attributes, crate::modgen::Layout::Tree),
+            "// This file is @generated by pbuildrs.\n\
+            #![allow(clippy::all)]\n\
+            #![allow(missing_docs)]\n\
+            \n\
+            #[path = \"mod.rs\"]\n\
+            mod generated_tree;\n\
+            \n\
+            pub use generated_tree::*;\n",
+        );
+        assert!(
+            super::render(&[], crate::modgen::Layout::SingleFile)
+                .contains("#[path = \"generated.rs\"]\n"),
+            "Expected the single file to be loaded",
+        );
+    }
+
+    #[test]
+    fn validate_rejects_invalid_names_and_attributes() {
+        let layout = crate::modgen::Layout::Tree;
+        let attributes = ["#![allow(clippy::all)]".to_string()];
+
+        assert!(
+            super::validate("lib.rs", &attributes, layout).is_ok(),
+            "Expected a valid root file",
+        );
+
+        ["mod.rs", "src/lib.rs", "lib", ".rs"]
+            .into_iter()
+            .for_each(|name| {
+                let result = super::validate(name, &[], layout);
+                assert!(
+                    matches!(result, Err(super::Error::InvalidName(_))),
+                    "Expected `Err(Error::InvalidName(_))` for `{name}`, got: `{result:?}`",
+                );
+            });
+
+        let result = super::validate("lib.rs", &["#[allow(clippy::all)]".to_string()], layout);
+        assert!(
+            matches!(result, Err(super::Error::InvalidAttribute(_))),
+            "Expected `Err(Error::InvalidAttribute(_))`, got: `{result:?}`",
+        );
+    }
+}
@@ -0,0 +1,411 @@
+use std::{collections, fmt::Write as _, fs, io, path, str};
+
+use prost_types::field_descriptor_proto::{Label, Type};
+
+use crate::{attributes, services};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(
+        "Invalid row model `{0}`, expected `<MESSAGE>[=<FIELD>:<COLUMN>[,<FIELD>:<COLUMN>...]]`"
+    )]
+    InvalidModel(String),
+    #[error("The row model `{0}` is not a message")]
+    UnknownMessage(String),
+    #[error("The column of the row model `{0}` is mapped from `{1}`, which is not a field of it")]
+    UnknownField(String, String),
+    #[error("Failed to append the row mappings to `{1}`: {0}")]
+    Write(io::Error, path::PathBuf),
+}
+
+/// The messages mapped from database rows, keyed by their fully qualified name, with the columns
+/// of the fields not named after them, keyed by the name of the field.
+pub type Models = collections::BTreeMap<String, collections::BTreeMap<String, String>>;
+
+/// A message mapped from database rows, given by its fully qualified name, with the `columns` of
+/// the fields not named after them, e.g. `crabs.Ferris=id:ferris_id,name:ferris_name`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Model {
+    pub message: String,
+    pub columns: Vec<(String, String)>,
+}
+
+impl str::FromStr for Model {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (message, columns) = s.split_once('=').unwrap_or((s, ""));
+        let message = message.trim().trim_start_matches('.');
+        if message.is_empty() {
+            return Err(Error::InvalidModel(s.to_string()));
+        }
+
+        let columns = columns
+            .split(',')
+            .map(str::trim)
+            .filter(|column| !column.is_empty())
+            .map(|column| {
+                column
+                    .split_once(':')
+                    .map(|(field, column)| (field.trim(), column.trim()))
+                    .filter(|(field, column)| !field.is_empty() && !column.is_empty())
+                    .map(|(field, column)| (field.to_string(), column.to_string()))
+                    .ok_or_else(|| Error::InvalidModel(s.to_string()))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            message: message.to_string(),
+            columns,
+        })
+    }
+}
+
+/// Ensure every model is a message defined in the `fds`, and its columns are mapped from its
+/// fields.
+pub fn validate(fds: &prost_types::FileDescriptorSet, models: &Models) -> Result<(), Error> {
+    let messages = fds
+        .file
+        .iter()
+        .flat_map(services::messages)
+        .map(|message| (message.full_name, message.descriptor))
+        .collect::<collections::BTreeMap<_, _>>();
+
+    models.iter().try_for_each(|(model, columns)| {
+        let message = messages
+            .get(model)
+            .ok_or_else(|| Error::UnknownMessage(model.clone()))?;
+
+        columns
+            .keys()
+            .find(|field| !message.field.iter().any(|f| f.name() == field.as_str()))
+            .map_or(Ok(()), |field| {
+                Err(Error::UnknownField(model.clone(), field.clone()))
+            })
+    })
+}
+
+/// The Rust type prost generates for the scalar or enum `field`, or `None` for the message, map
+/// and oneof fields a column can't be decoded into.
+fn column_type(
+    file: &prost_types::FileDescriptorProto,
+    field: &prost_types::FieldDescriptorProto,
+) -> Option<String> {
+    let scalar = match field.r#type() {
+        Type::Double => "f64",
+        Type::Float => "f32",
+        Type::Int64 | Type::Sint64 | Type::Sfixed64 => "i64",
+        Type::Uint64 | Type::Fixed64 => "u64",
+        Type::Int32 | Type::Sint32 | Type::Sfixed32 | Type::Enum => "i32",
+        Type::Uint32 | Type::Fixed32 => "u32",
+        Type::Bool => "bool",
+        Type::String => "String",
+        Type::Bytes => "Vec<u8>",
+        Type::Message | Type::Group => return None,
+    };
+    let is_oneof = field.oneof_index.is_some() && !field.proto3_optional();
+    if is_oneof {
+        return None;
+    }
+
+    Some(match field.label() {
+        Label::Repeated => format!("Vec<{scalar}>"),
+        Label::Optional if field.proto3_optional() || file.syntax() != "proto3" => {
+            format!("Option<{scalar}>")
+        }
+        _ => scalar.to_string(),
+    })
+}
+
+/// The `sqlx::FromRow` implementation of the `message`, decoding every scalar and enum field from
+/// the column named after it, unless it is mapped from another one in the `columns`, and leaving
+/// the other fields at their defaults. The fields matching the `bytes` paths and the `strings`
+/// fields are `bytes::Bytes`, which sqlx can't decode, so they are decoded as `Vec<u8>` or
+/// `String` and converted.
+fn render(
+    file: &prost_types::FileDescriptorProto,
+    message: &services::Message<'_>,
+    columns: &collections::BTreeMap<String, String>,
+    bytes: &[String],
+    strings: &[String],
+) -> String {
+    let mut bounds = collections::BTreeSet::new();
+    let mut fields = String::new();
+    let mut skipped = false;
+
+    message.descriptor.field.iter().for_each(|descriptor| {
+        let Some(column_type) = column_type(file, descriptor) else {
+            skipped = true;
+            return;
+        };
+        let column = columns
+            .get(descriptor.name())
+            .map_or(descriptor.name(), String::as_str);
+
+        let name = format!(".{}.{}", message.full_name, descriptor.name());
+        let is_bytes = match descriptor.r#type() {
+            Type::Bytes => bytes.iter().any(|path| attributes::matches(path, &name)),
+            Type::String => strings.contains(&name),
+            _ => false,
+        };
+        let (column_type_arg, conversion) = match (is_bytes, descriptor.label()) {
+            (false, _) => (String::new(), ""),
+            (true, Label::Repeated) => (
+                format!("::<{column_type}, _>"),
+                ".into_iter().map(Into::into).collect()",
+            ),
+            (true, _) if column_type.starts_with("Option<") => {
+                (format!("::<{column_type}, _>"), ".map(Into::into)")
+            }
+            (true, _) => (format!("::<{column_type}, _>"), ".into()"),
+        };
+
+        let _ = write!(
+            fields,
+            "\n            {}: sqlx::Row::try_get{column_type_arg}(row, {column:?})?{conversion},",
+            services::to_snake(descriptor.name()),
+        );
+        bounds.insert(column_type);
+    });
+    if skipped {
+        fields.push_str("\n            ..Default::default()");
+    }
+
+    let bounds = bounds
+        .iter()
+        .map(|column_type| {
+            format!("\n    {column_type}: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,")
+        })
+        .collect::<String>();
+
+    format!(
+        r#"
+/// Maps a database row to the `{full_name}` persistence model, by the names of its columns.
+#[cfg(feature = "sqlx")]
+impl<'r, R: sqlx::Row> sqlx::FromRow<'r, R> for {path}
+where
+    &'r str: sqlx::ColumnIndex<R>,{bounds}
+{{
+    fn from_row(row: &'r R) -> Result<Self, sqlx::Error> {{
+        Ok(Self {{{fields}
+        }})
+    }}
+}}
+"#,
+        full_name = message.full_name,
+        path = message.path,
+    )
+}
+
+/// Append the row mappings of the `models` defined in the `fds` to the code generated by prost
+/// into the `dir`, next to the package of the message, with the fields matching the `bytes` paths
+/// and the fully qualified `strings` fields generated as `bytes::Bytes`.
+pub fn write(
+    fds: &prost_types::FileDescriptorSet,
+    dir: &path::Path,
+    models: &Models,
+    bytes: &[String],
+    strings: &[String],
+) -> Result<(), Error> {
+    fds.file.iter().try_for_each(|file| {
+        let code = services::messages(file)
+            .iter()
+            .filter_map(|message| {
+                models
+                    .get(&message.full_name)
+                    .map(|columns| render(file, message, columns, bytes, strings))
+            })
+            .collect::<String>();
+        if code.is_empty() {
+            return Ok(());
+        }
+
+        let path = dir.join(match file.package() {
+            "" => "_.rs".to_string(),
+            package => format!("{package}.rs"),
+        });
+        tracing::debug!("Appending the row mappings: {}", path.display());
+
+        fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .and_then(|mut out| io::Write::write_all(&mut out, code.as_bytes()))
+            .map_err(|e| Error::Write(e, path))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_types::field_descriptor_proto::{Label, Type};
+    use std::{collections, fs};
+
+    fn fds() -> prost_types::FileDescriptorSet {
+        let field = |name: &str, r#type: Type, label: Label| prost_types::FieldDescriptorProto {
+            name: Some(name.to_string()),
+            r#type: Some(r#type.into()),
+            label: Some(label.into()),
+            ..Default::default()
+        };
+
+        prost_types::FileDescriptorSet {
+            file: vec![prost_types::FileDescriptorProto {
+                package: Some("crabs".to_string()),
+                syntax: Some("proto3".to_string()),
+                message_type: vec![prost_types::DescriptorProto {
+                    name: Some("Shell".to_string()),
+                    nested_type: vec![prost_types::DescriptorProto {
+                        name: Some("Ferris".to_string()),
+                        field: vec![
+                            field("id", Type::Int64, Label::Optional),
+                            field("type", Type::String, Label::Optional),
+                            field("claws", Type::Uint32, Label::Repeated),
+                            field("shell", Type::Message, Label::Optional),
+                            field("photo", Type::Bytes, Label::Optional),
+                            field("scans", Type::Bytes, Label::Repeated),
+                            prost_types::FieldDescriptorProto {
+                                proto3_optional: Some(true),
+                                oneof_index: Some(0),
+                                ..field("nickname", Type::String, Label::Optional)
+                            },
+                        ],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn model_parses_the_message_and_the_columns() {
+        assert_eq!(
+            ".crabs.Ferris = id:ferris_id, type:kind"
+                .parse::<super::Model>()
+                .expect("Failed to parse a row model"),
+            super::Model {
+                message: "crabs.Ferris".to_string(),
+                columns: vec![
+                    ("id".to_string(), "ferris_id".to_string()),
+                    ("type".to_string(), "kind".to_string()),
+                ],
+            },
+            "Invalid parsed row model",
+        );
+        assert_eq!(
+            "crabs.Ferris"
+                .parse::<super::Model>()
+                .expect("Failed to parse a row model")
+                .columns,
+            [],
+        );
+
+        ["=id:ferris_id", "crabs.Ferris=id", "crabs.Ferris=id:"]
+            .into_iter()
+            .for_each(|model| {
+                let result = model.parse::<super::Model>();
+                assert!(
+                    matches!(result, Err(super::Error::InvalidModel(_))),
+                    "Expected `Err(Error::InvalidModel(_))` for `{model}`, got: `{result:?}`",
+                );
+            });
+    }
+
+    #[test]
+    fn validate_rejects_unknown_messages_and_fields() {
+        let models = |message: &str, field: &str| {
+            collections::BTreeMap::from([(
+                message.to_string(),
+                collections::BTreeMap::from([(field.to_string(), "column".to_string())]),
+            )])
+        };
+
+        super::validate(&fds(), &models("crabs.Shell.Ferris", "id"))
+            .expect("Expected the row models to be valid");
+
+        let err = super::validate(&fds(), &models("crabs.Ferris", "id"));
+        assert!(
+            matches!(&err, Err(super::Error::UnknownMessage(message)) if message == "crabs.Ferris"),
+            "Expected `Err(Error::UnknownMessage)`, got: `{:?}`",
+            err
+        );
+
+        let err = super::validate(&fds(), &models("crabs.Shell.Ferris", "name"));
+        assert!(
+            matches!(&err, Err(super::Error::UnknownField(_, field)) if field == "name"),
+            "Expected `Err(Error::UnknownField)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn write_maps_the_scalar_fields_to_columns() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let models = collections::BTreeMap::from([(
+            "crabs.Shell.Ferris".to_string(),
+            collections::BTreeMap::from([("id".to_string(), "ferris_id".to_string())]),
+        )]);
+
+        super::write(&fds(), dir.path(), &models, &[], &[])
+            .expect("Failed to write the row mappings");
+
+        let code = fs::read_to_string(dir.path().join("crabs.rs"))
+            .expect("Failed to read the generated code");
+        [
+            "impl<'r, R: sqlx::Row> sqlx::FromRow<'r, R> for shell::Ferris",
+            "    Vec<u32>: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,\n",
+            "    Option<String>: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,\n",
+            "            id: sqlx::Row::try_get(row, \"ferris_id\")?,\n",
+            "            r#type: sqlx::Row::try_get(row, \"type\")?,\n",
+            "            nickname: sqlx::Row::try_get(row, \"nickname\")?,\n",
+            "            ..Default::default()\n",
+        ]
+        .into_iter()
+        .for_each(|expected| {
+            assert!(
+                code.contains(expected),
+                "Expected `{expected}` in the row mapping, got:\n{code}",
+            );
+        });
+        assert!(
+            !code.contains("shell: "),
+            "Expected the message field to be skipped, got:\n{code}",
+        );
+    }
+
+    #[test]
+    fn write_converts_the_bytes_fields() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let models = collections::BTreeMap::from([(
+            "crabs.Shell.Ferris".to_string(),
+            collections::BTreeMap::new(),
+        )]);
+
+        super::write(
+            &fds(),
+            dir.path(),
+            &models,
+            &["Ferris.scans".to_string()],
+            &[".crabs.Shell.Ferris.nickname".to_string()],
+        )
+        .expect("Failed to write the row mappings");
+
+        let code = fs::read_to_string(dir.path().join("crabs.rs"))
+            .expect("Failed to read the generated code");
+        [
+            "            photo: sqlx::Row::try_get(row, \"photo\")?,\n",
+            "            scans: sqlx::Row::try_get::<Vec<Vec<u8>>, _>(row, \"scans\")?\
+            .into_iter().map(Into::into).collect(),\n",
+            "            nickname: sqlx::Row::try_get::<Option<String>, _>(row, \"nickname\")?\
+            .map(Into::into),\n",
+        ]
+        .into_iter()
+        .for_each(|expected| {
+            assert!(
+                code.contains(expected),
+                "Expected `{expected}` in the row mapping, got:\n{code}",
+            );
+        });
+    }
+}
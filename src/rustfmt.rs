@@ -0,0 +1,155 @@
+use std::{env, io, path, process};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to run rustfmt `{1}`: {0}")]
+    Spawn(io::Error, path::PathBuf),
+    #[error("rustfmt failed to format the generated code, exited with {0}: {1}")]
+    Format(process::ExitStatus, String),
+}
+
+/// The Rust edition the generated code is parsed and formatted with.
+#[derive(
+    clap::ValueEnum, serde::Deserialize, serde::Serialize, Clone, Copy, Default, PartialEq, Debug,
+)]
+pub enum Edition {
+    #[value(name = "2015")]
+    #[serde(rename = "2015")]
+    E2015,
+    #[value(name = "2018")]
+    #[serde(rename = "2018")]
+    E2018,
+    #[default]
+    #[value(name = "2021")]
+    #[serde(rename = "2021")]
+    E2021,
+    #[value(name = "2024")]
+    #[serde(rename = "2024")]
+    E2024,
+}
+
+impl Edition {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::E2015 => "2015",
+            Self::E2018 => "2018",
+            Self::E2021 => "2021",
+            Self::E2024 => "2024",
+        }
+    }
+}
+
+/// The `rustfmt` binary set in the `RUSTFMT` environment variable, or the one in the `PATH`.
+fn rustfmt() -> path::PathBuf {
+    env::var_os("RUSTFMT")
+        .map(path::PathBuf::from)
+        .unwrap_or_else(|| path::PathBuf::from("rustfmt"))
+}
+
+/// Format the Rust source `files` in place with the `rustfmt` binary, or the default one, with
+/// the `edition`. The files are left unformatted with a warning if rustfmt is not installed, so a
+/// missing toolchain component does not fail the whole run.
+pub fn format(
+    rustfmt: Option<&path::Path>,
+    edition: Edition,
+    files: &[path::PathBuf],
+) -> Result<(), Error> {
+    let rustfmt = rustfmt.map_or_else(self::rustfmt, path::Path::to_path_buf);
+    let files = files
+        .iter()
+        .filter(|file| file.extension().is_some_and(|ext| ext == "rs"))
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let output = process::Command::new(&rustfmt)
+        .arg("--edition")
+        .arg(edition.as_str())
+        .args(&files)
+        .stdin(process::Stdio::null())
+        .output();
+    let output = match output {
+        Ok(output) => output,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            tracing::warn!(
+                "rustfmt `{}` is not installed, leaving the generated code unformatted",
+                rustfmt.display()
+            );
+
+            return Ok(());
+        }
+        Err(e) => return Err(Error::Spawn(e, rustfmt)),
+    };
+
+    if !output.status.success() {
+        return Err(Error::Format(
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    tracing::info!("Formatted {} generated files with rustfmt", files.len());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path};
+
+    #[test]
+    fn format_rewrites_the_rust_files() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let (code, manifest) = (dir.path().join("mod.rs"), dir.path().join("Cargo.toml"));
+        fs::write(&code, "pub struct Crab{pub legs:u32}\n").expect("Failed to write a test file");
+        fs::write(&manifest, "[package]\n").expect("Failed to write a test file");
+
+        super::format(
+            None,
+            super::Edition::default(),
+            &[code.clone(), manifest.clone()],
+        )
+        .expect("Failed to format the test files");
+
+        assert_eq!(
+            fs::read_to_string(&code).expect("Failed to read the formatted file"),
+            "pub struct Crab {\n    pub legs: u32,\n}\n",
+            "Expected the Rust file to be formatted",
+        );
+    }
+
+    #[test]
+    fn format_skips_a_missing_rustfmt() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let code = dir.path().join("mod.rs");
+        fs::write(&code, "pub struct Crab{}\n").expect("Failed to write a test file");
+
+        let res = super::format(
+            Some(&dir.path().join("missing-rustfmt")),
+            super::Edition::default(),
+            std::slice::from_ref(&code),
+        );
+        assert!(
+            res.is_ok(),
+            "Expected a missing rustfmt to be skipped, got: `{:?}`",
+            res
+        );
+        assert_eq!(
+            fs::read_to_string(&code).expect("Failed to read the test file"),
+            "pub struct Crab{}\n",
+            "Expected the file to be left unformatted",
+        );
+
+        let err = super::format(
+            Some(path::Path::new("false")),
+            super::Edition::default(),
+            &[code],
+        );
+        assert!(
+            matches!(err, Err(super::Error::Format(..))),
+            "Expected `Err(Format)`, got: `{:?}`",
+            err
+        );
+    }
+}
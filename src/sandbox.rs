@@ -0,0 +1,294 @@
+use prost::Message;
+use std::{env, ffi, fs, io, path, process};
+
+/// The name of the file descriptor set written by `protoc` into the workspace.
+const DESCRIPTOR_SET: &str = "protoc-descriptor-set.bin";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to resolve the path `{1}`: {0}")]
+    Resolve(io::Error, path::PathBuf),
+    #[error("Failed to run protoc `{1}`: {0}")]
+    Spawn(io::Error, path::PathBuf),
+    #[error("protoc failed: {0}")]
+    Protoc(String),
+    #[error("Failed to read the file descriptor set `{1}`: {0}")]
+    ReadDescriptorSet(io::Error, path::PathBuf),
+    #[error("Failed to decode the file descriptor set: {0}")]
+    DecodeDescriptorSet(prost::DecodeError),
+    #[error("Failed to write the file descriptor set `{1}`: {0}")]
+    WriteDescriptorSet(io::Error, path::PathBuf),
+    #[cfg(target_os = "linux")]
+    #[error("Failed to build the seccomp filter: {0}")]
+    Seccomp(seccompiler::Error),
+    #[cfg(not(target_os = "linux"))]
+    #[error("The seccomp sandbox is only supported on Linux")]
+    Unsupported,
+}
+
+/// How much `protoc` is restricted while compiling the protobuf files, for when they come from a
+/// source that is not fully trusted. None of the levels confine the file system, `protoc` can
+/// still read and write any file the user running pbuildrs can.
+#[derive(
+    clap::ValueEnum, serde::Deserialize, serde::Serialize, Clone, Copy, Default, PartialEq, Debug,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Sandbox {
+    /// Run protoc with the environment and the working directory of pbuildrs
+    #[default]
+    Off,
+    /// Clear the environment variables and run protoc from the temporary workspace, which only
+    /// isolates its environment and leaves the file system accessible
+    Env,
+    /// Additionally deny protoc any network access with a seccomp filter, Linux only
+    Seccomp,
+}
+
+/// The `protoc` binary set in the `PROTOC` environment variable, or the one found in the `PATH`,
+/// resolved upfront as the environment of the sandboxed process is cleared.
+pub fn protoc() -> path::PathBuf {
+    if let Some(protoc) = env::var_os("PROTOC") {
+        return path::PathBuf::from(protoc);
+    }
+
+    env::var_os("PATH")
+        .iter()
+        .flat_map(env::split_paths)
+        .map(|dir| dir.join("protoc"))
+        .find(|protoc| protoc.is_file())
+        .unwrap_or_else(|| path::PathBuf::from("protoc"))
+}
+
+#[cfg(target_os = "linux")]
+fn deny_network(command: &mut process::Command) -> Result<(), Error> {
+    use std::os::unix::process::CommandExt;
+
+    let rules = [
+        libc::SYS_socket,
+        libc::SYS_socketpair,
+        libc::SYS_connect,
+        libc::SYS_bind,
+        libc::SYS_listen,
+        libc::SYS_accept,
+        libc::SYS_accept4,
+        libc::SYS_ptrace,
+    ]
+    .into_iter()
+    .map(|syscall| (syscall, vec![]))
+    .collect();
+
+    let arch = env::consts::ARCH
+        .try_into()
+        .map_err(|e| Error::Seccomp(seccompiler::Error::Backend(e)))?;
+    let filter = seccompiler::SeccompFilter::new(
+        rules,
+        seccompiler::SeccompAction::Allow,
+        seccompiler::SeccompAction::Errno(libc::EPERM.unsigned_abs()),
+        arch,
+    )
+    .map_err(|e| Error::Seccomp(seccompiler::Error::Backend(e)))?;
+    let program = seccompiler::BpfProgram::try_from(filter)
+        .map_err(|e| Error::Seccomp(seccompiler::Error::Backend(e)))?;
+
+    // SAFETY: the filter is compiled before forking, so installing it in the child only issues
+    // the `prctl` and `seccomp` system calls, without allocating or taking any locks.
+    unsafe {
+        command.pre_exec(move || {
+            seccompiler::apply_filter(&program).map_err(|_| io::Error::last_os_error())
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn deny_network(_command: &mut process::Command) -> Result<(), Error> {
+    Err(Error::Unsupported)
+}
+
+/// The `protoc` command compiling the `protos` restricted by the `sandbox`, along with the path of
+/// the file descriptor set it writes into the `workdir`. The `workdir` is used as the working
+/// directory of `protoc`, so the paths of the `protos`, the `includes` and the file descriptor set
+/// are made absolute. The extra `args` are passed as is, so any path in them has to be absolute.
+fn command(
+    sandbox: Sandbox,
+    workdir: &path::Path,
+    protos: &[path::PathBuf],
+    includes: &[path::PathBuf],
+    args: &[ffi::OsString],
+) -> Result<(process::Command, path::PathBuf), Error> {
+    let absolute =
+        |path: &path::Path| path::absolute(path).map_err(|e| Error::Resolve(e, path.to_path_buf()));
+
+    let output = absolute(&workdir.join(DESCRIPTOR_SET))?;
+
+    let mut command = process::Command::new(protoc());
+    if sandbox != Sandbox::Off {
+        command.env_clear();
+    }
+    command
+        .current_dir(workdir)
+        .arg("--include_imports")
+        .arg("--include_source_info")
+        .arg("-o")
+        .arg(&output)
+        .args(args);
+
+    // Same as prost, the include directory of protoc goes after the user ones, so they can
+    // override the well-known types.
+    let protoc_include = env::var_os("PROTOC_INCLUDE").map(path::PathBuf::from);
+    includes
+        .iter()
+        .filter(|include| include.exists())
+        .chain(&protoc_include)
+        .try_for_each(|include| -> Result<(), Error> {
+            command
+                .arg("-I")
+                .arg(ffi::OsString::from(absolute(include)?));
+            Ok(())
+        })?;
+    protos.iter().try_for_each(|proto| -> Result<(), Error> {
+        command.arg(absolute(proto)?);
+        Ok(())
+    })?;
+
+    if sandbox == Sandbox::Seccomp {
+        deny_network(&mut command)?;
+    }
+
+    Ok((command, output))
+}
+
+fn check(result: &process::Output) -> Result<(), Error> {
+    match result.status.success() {
+        true => Ok(()),
+        false => Err(Error::Protoc(
+            String::from_utf8_lossy(&result.stderr).into_owned(),
+        )),
+    }
+}
+
+/// Compile the `protos` into a file descriptor set with `protoc` restricted by the `sandbox`. The
+/// `workdir` is used as the working directory of `protoc`, which writes the file descriptor set
+/// there, so the paths of the `protos` and the `includes` are made absolute, while the extra
+/// `args` are passed as is and have to use absolute paths. The file descriptor set is then copied
+/// to the `descriptor_set_path` if provided.
+pub fn load_fds(
+    sandbox: Sandbox,
+    workdir: &path::Path,
+    protos: &[path::PathBuf],
+    includes: &[path::PathBuf],
+    args: &[ffi::OsString],
+    descriptor_set_path: Option<&path::Path>,
+) -> Result<tonic_prost_build::FileDescriptorSet, Error> {
+    let (mut command, output) = command(sandbox, workdir, protos, includes, args)?;
+
+    tracing::debug!("Running a sandboxed protoc: {command:?}");
+
+    let result = command
+        .output()
+        .map_err(|e| Error::Spawn(e, command.get_program().into()))?;
+    check(&result)?;
+
+    let contents = fs::read(&output).map_err(|e| Error::ReadDescriptorSet(e, output.clone()))?;
+
+    if let Some(path) = descriptor_set_path {
+        fs::write(path, &contents).map_err(|e| Error::WriteDescriptorSet(e, path.to_path_buf()))?;
+    }
+
+    tonic_prost_build::FileDescriptorSet::decode(contents.as_slice())
+        .map_err(Error::DecodeDescriptorSet)
+}
+
+/// Like [`load_fds`], but waits for `protoc` and the file system without blocking the thread.
+#[cfg(feature = "async")]
+pub async fn load_fds_async(
+    sandbox: Sandbox,
+    workdir: &path::Path,
+    protos: &[path::PathBuf],
+    includes: &[path::PathBuf],
+    args: &[ffi::OsString],
+    descriptor_set_path: Option<&path::Path>,
+) -> Result<tonic_prost_build::FileDescriptorSet, Error> {
+    let (command, output) = command(sandbox, workdir, protos, includes, args)?;
+    let protoc = path::PathBuf::from(command.get_program());
+    let mut command = tokio::process::Command::from(command);
+
+    tracing::debug!("Running protoc: {command:?}");
+
+    let result = command
+        .output()
+        .await
+        .map_err(|e| Error::Spawn(e, protoc))?;
+    check(&result)?;
+
+    let contents = tokio::fs::read(&output)
+        .await
+        .map_err(|e| Error::ReadDescriptorSet(e, output.clone()))?;
+
+    if let Some(path) = descriptor_set_path {
+        tokio::fs::write(path, &contents)
+            .await
+            .map_err(|e| Error::WriteDescriptorSet(e, path.to_path_buf()))?;
+    }
+
+    tonic_prost_build::FileDescriptorSet::decode(contents.as_slice())
+        .map_err(Error::DecodeDescriptorSet)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path};
+
+    #[test]
+    fn load_fds_compiles_in_the_workspace() {
+        let workdir = tempfile::TempDir::new().expect("Failed to create a test workspace");
+        let src = tempfile::TempDir::new().expect("Failed to create a test source directory");
+        let proto = src.path().join("crabs.proto");
+        fs::write(
+            &proto,
+            "syntax = \"proto3\";\npackage crabs;\nmessage Ferris {}\n",
+        )
+        .expect("Failed to create a test protobuf file");
+
+        [super::Sandbox::Env, super::Sandbox::Seccomp]
+            .into_iter()
+            .for_each(|sandbox| {
+                let fds = super::load_fds(
+                    sandbox,
+                    workdir.path(),
+                    std::slice::from_ref(&proto),
+                    &[src.path().to_path_buf()],
+                    &[],
+                    None,
+                )
+                .unwrap_or_else(|e| panic!("Failed to compile with the {sandbox:?} sandbox: {e}"));
+
+                assert_eq!(
+                    fds.file.iter().map(|file| file.name()).collect::<Vec<_>>(),
+                    ["crabs.proto"],
+                    "Invalid file descriptor set compiled with the {sandbox:?} sandbox",
+                );
+            });
+    }
+
+    #[test]
+    fn load_fds_reports_protoc_failures() {
+        let workdir = tempfile::TempDir::new().expect("Failed to create a test workspace");
+
+        let err = super::load_fds(
+            super::Sandbox::Env,
+            workdir.path(),
+            &[path::PathBuf::from("/missing/crabs.proto")],
+            &[path::PathBuf::from("/missing")],
+            &[],
+            None,
+        );
+
+        assert!(
+            matches!(err, Err(super::Error::Protoc(_))),
+            "Expected `Err(Error::Protoc)`, got: `{:?}`",
+            err
+        );
+    }
+}
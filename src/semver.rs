@@ -0,0 +1,638 @@
+use prost::Message as _;
+use std::{cmp, collections, fmt, fs, io, num, path, str};
+
+use crate::{descriptor, manifest};
+
+/// The file descriptor set the crate was generated from, written next to the manifest of every
+/// crate, so its published version can be compared with the next one.
+pub const DESCRIPTORS: &str = "descriptors.bin";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to read the manifest of the published crate `{1}`: {0}")]
+    ReadManifest(io::Error, path::PathBuf),
+    #[error("Failed to parse the manifest of the published crate `{1}`: {0}")]
+    ParseManifest(toml::de::Error, path::PathBuf),
+    #[error("The manifest of the published crate `{0}` has no `package.version`")]
+    MissingVersion(path::PathBuf),
+    #[error("Invalid version of the published crate `{1}`: {0}")]
+    PublishedVersion(VersionError, path::PathBuf),
+    #[error(transparent)]
+    Descriptors(#[from] descriptor::Error),
+    #[error("Failed to write the file descriptor set of the crate `{1}`: {0}")]
+    Write(io::Error, path::PathBuf),
+    #[error(
+        "The schema changes since {published} need a {bump} release, version {next} or later, \
+        but the crate is versioned {version}: {}",
+        .changes.join(", ")
+    )]
+    Behind {
+        published: Version,
+        bump: Bump,
+        next: Version,
+        version: Version,
+        changes: Vec<String>,
+    },
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum VersionError {
+    #[error("Expected a version in the `<major>.<minor>.<patch>` format, got `{0}`")]
+    Format(String),
+    #[error("Invalid version component `{1}`: {0}")]
+    Component(num::ParseIntError, String),
+}
+
+/// The version of a crate, without any pre-release or build metadata.
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(try_from = "String", into = "String")]
+pub struct Version {
+    major: u64,
+    minor: u64,
+    patch: u64,
+}
+
+impl Version {
+    /// The next version after this one for a release with the `bump`. Before `1.0.0`, the leftmost
+    /// non-zero component is the one changed by the breaking releases, as Cargo expects.
+    pub fn next(&self, bump: Bump) -> Self {
+        let Self {
+            major,
+            minor,
+            patch,
+        } = *self;
+
+        let (major, minor, patch) = match bump {
+            Bump::Major if major > 0 => (major + 1, 0, 0),
+            Bump::Major if minor > 0 => (0, minor + 1, 0),
+            Bump::Minor if major > 0 => (major, minor + 1, 0),
+            Bump::Major | Bump::Minor | Bump::Patch => (major, minor, patch + 1),
+        };
+
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+}
+
+impl str::FromStr for Version {
+    type Err = VersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |part: &str| {
+            part.parse::<u64>()
+                .map_err(|e| VersionError::Component(e, part.to_string()))
+        };
+
+        match s.split('.').collect::<Vec<_>>().as_slice() {
+            [major, minor, patch] => Ok(Self {
+                major: parse(major)?,
+                minor: parse(minor)?,
+                patch: parse(patch)?,
+            }),
+            _ => Err(VersionError::Format(s.to_string())),
+        }
+    }
+}
+
+impl TryFrom<String> for Version {
+    type Error = VersionError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Version> for String {
+    fn from(value: Version) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// The release a schema change needs, ordered from the least to the most disruptive.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Bump {
+    Patch,
+    Minor,
+    Major,
+}
+
+impl fmt::Display for Bump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Patch => "patch",
+            Self::Minor => "minor",
+            Self::Major => "major",
+        })
+    }
+}
+
+/// A change between two versions of the schema and the release it needs.
+#[derive(PartialEq, Debug)]
+pub struct Change {
+    pub bump: Bump,
+    pub description: String,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.description)
+    }
+}
+
+/// The definitions of a schema, keyed by their fully qualified name.
+#[derive(Default)]
+struct Definitions<'a> {
+    messages: collections::BTreeMap<String, &'a prost_types::DescriptorProto>,
+    enums: collections::BTreeMap<String, &'a prost_types::EnumDescriptorProto>,
+    services: collections::BTreeMap<String, &'a prost_types::ServiceDescriptorProto>,
+}
+
+fn full_name(scope: &str, name: &str) -> String {
+    match scope {
+        "" => name.to_string(),
+        scope => format!("{scope}.{name}"),
+    }
+}
+
+impl<'a> Definitions<'a> {
+    fn new(fds: &'a prost_types::FileDescriptorSet) -> Self {
+        let mut definitions = Self::default();
+
+        fds.file.iter().for_each(|file| {
+            definitions.collect(file.package(), &file.message_type, &file.enum_type);
+            definitions.services.extend(
+                file.service
+                    .iter()
+                    .map(|service| (full_name(file.package(), service.name()), service)),
+            );
+        });
+
+        definitions
+    }
+
+    /// Collect the `messages` and `enums` of the `scope`, and the ones nested in the messages.
+    fn collect(
+        &mut self,
+        scope: &str,
+        messages: &'a [prost_types::DescriptorProto],
+        enums: &'a [prost_types::EnumDescriptorProto],
+    ) {
+        self.enums.extend(
+            enums
+                .iter()
+                .map(|descriptor| (full_name(scope, descriptor.name()), descriptor)),
+        );
+
+        messages.iter().for_each(|message| {
+            let name = full_name(scope, message.name());
+            self.collect(&name, &message.nested_type, &message.enum_type);
+            self.messages.insert(name, message);
+        });
+    }
+}
+
+/// The `definitions` of the `scope`, keyed by their fully qualified name.
+fn keyed<'a, T>(
+    scope: &str,
+    definitions: &'a [T],
+    name: impl Fn(&T) -> &str,
+) -> collections::BTreeMap<String, &'a T> {
+    definitions
+        .iter()
+        .map(|definition| (full_name(scope, name(definition)), definition))
+        .collect()
+}
+
+/// Compare the `previous` and `current` definitions of a `kind`, keyed by their fully qualified
+/// name, adding the removed ones, needing a major release, and the added ones, needing a minor
+/// one, to the `changes`. The definitions in both are returned to be compared further.
+fn diff<'a, T>(
+    kind: &str,
+    previous: &collections::BTreeMap<String, &'a T>,
+    current: &collections::BTreeMap<String, &'a T>,
+    changes: &mut Vec<Change>,
+) -> Vec<(String, &'a T, &'a T)> {
+    changes.extend(
+        previous
+            .keys()
+            .filter(|name| !current.contains_key(*name))
+            .map(|name| Change {
+                bump: Bump::Major,
+                description: format!("the {kind} `{name}` was removed"),
+            }),
+    );
+    changes.extend(
+        current
+            .keys()
+            .filter(|name| !previous.contains_key(*name))
+            .map(|name| Change {
+                bump: Bump::Minor,
+                description: format!("the {kind} `{name}` was added"),
+            }),
+    );
+
+    previous
+        .iter()
+        .filter_map(|(name, previous)| Some((name.clone(), *previous, *current.get(name)?)))
+        .collect()
+}
+
+/// Add a change of the `kind` named `name` needing a major release to the `changes` if its
+/// `previous` and `current` shapes, `what` the generated code depends on, differ.
+fn compare<S: PartialEq>(
+    kind: &str,
+    name: &str,
+    what: &str,
+    (previous, current): (S, S),
+    changes: &mut Vec<Change>,
+) {
+    if previous != current {
+        changes.push(Change {
+            bump: Bump::Major,
+            description: format!("the {kind} `{name}` changed its {what}"),
+        });
+    }
+}
+
+/// The changes from the `previous` schema to the `current` one, breaking the generated code, the
+/// removed, renamed or retyped definitions, or extending it, the added ones. The changes needing
+/// a major release come first.
+pub fn changes<'a>(
+    previous: &'a prost_types::FileDescriptorSet,
+    current: &'a prost_types::FileDescriptorSet,
+) -> Vec<Change> {
+    let (previous, current) = (Definitions::new(previous), Definitions::new(current));
+    let mut changes = vec![];
+
+    diff(
+        "message",
+        &previous.messages,
+        &current.messages,
+        &mut changes,
+    )
+    .into_iter()
+    .for_each(|(message, previous, current)| {
+        let fields = |descriptor: &'a prost_types::DescriptorProto| {
+            keyed(
+                &message,
+                &descriptor.field,
+                prost_types::FieldDescriptorProto::name,
+            )
+        };
+        let shape = |field: &prost_types::FieldDescriptorProto| {
+            (
+                field.number(),
+                field.r#type(),
+                field.type_name().to_string(),
+                field.label(),
+                field.proto3_optional(),
+                field.oneof_index,
+            )
+        };
+
+        diff("field", &fields(previous), &fields(current), &mut changes)
+            .into_iter()
+            .for_each(|(field, previous, current)| {
+                compare(
+                    "field",
+                    &field,
+                    "number, type or cardinality",
+                    (shape(previous), shape(current)),
+                    &mut changes,
+                );
+            });
+    });
+
+    diff("enum", &previous.enums, &current.enums, &mut changes)
+        .into_iter()
+        .for_each(|(descriptor, previous, current)| {
+            let values = |enumeration: &'a prost_types::EnumDescriptorProto| {
+                keyed(
+                    &descriptor,
+                    &enumeration.value,
+                    prost_types::EnumValueDescriptorProto::name,
+                )
+            };
+
+            diff(
+                "enum value",
+                &values(previous),
+                &values(current),
+                &mut changes,
+            )
+            .into_iter()
+            .for_each(|(value, previous, current)| {
+                compare(
+                    "enum value",
+                    &value,
+                    "number",
+                    (previous.number(), current.number()),
+                    &mut changes,
+                );
+            });
+        });
+
+    diff(
+        "service",
+        &previous.services,
+        &current.services,
+        &mut changes,
+    )
+    .into_iter()
+    .for_each(|(service, previous, current)| {
+        let methods = |descriptor: &'a prost_types::ServiceDescriptorProto| {
+            keyed(
+                &service,
+                &descriptor.method,
+                prost_types::MethodDescriptorProto::name,
+            )
+        };
+        let shape = |method: &prost_types::MethodDescriptorProto| {
+            (
+                method.input_type().to_string(),
+                method.output_type().to_string(),
+                method.client_streaming(),
+                method.server_streaming(),
+            )
+        };
+
+        diff(
+            "method",
+            &methods(previous),
+            &methods(current),
+            &mut changes,
+        )
+        .into_iter()
+        .for_each(|(method, previous, current)| {
+            compare(
+                "method",
+                &method,
+                "request, response or streaming",
+                (shape(previous), shape(current)),
+                &mut changes,
+            );
+        });
+    });
+
+    // The sort is stable, keeping the changes of the same release in the order they were found.
+    changes.sort_by_key(|change| cmp::Reverse(change.bump));
+    changes
+}
+
+/// The next version of a generated crate suggested from the changes to its schema since its
+/// published version.
+#[derive(Debug)]
+pub struct Suggestion {
+    pub published: Version,
+    pub bump: Bump,
+    pub next: Version,
+    pub changes: Vec<Change>,
+}
+
+impl Suggestion {
+    /// Ensure the `version` of the crate is at least the suggested one.
+    pub fn enforce(&self, version: &Version) -> Result<(), Error> {
+        if *version >= self.next {
+            return Ok(());
+        }
+
+        Err(Error::Behind {
+            published: self.published.clone(),
+            bump: self.bump,
+            next: self.next.clone(),
+            version: version.clone(),
+            changes: self
+                .changes
+                .iter()
+                .filter(|change| change.bump == self.bump)
+                .map(ToString::to_string)
+                .collect(),
+        })
+    }
+}
+
+/// The version of the crate published into the `baseline` directory, read from its manifest.
+fn published_version(baseline: &path::Path) -> Result<Version, Error> {
+    let path = baseline.join(manifest::FILE);
+    let manifest = fs::read_to_string(&path).map_err(|e| Error::ReadManifest(e, path.clone()))?;
+    let manifest = toml::from_str::<toml::Table>(&manifest)
+        .map_err(|e| Error::ParseManifest(e, path.clone()))?;
+
+    manifest
+        .get("package")
+        .and_then(|package| package.get("version"))
+        .and_then(toml::Value::as_str)
+        .ok_or_else(|| Error::MissingVersion(path.clone()))?
+        .parse()
+        .map_err(|e| Error::PublishedVersion(e, path))
+}
+
+/// Suggest the next version of the crate published into the `baseline` directory, e.g. extracted
+/// from the registry, generated from the `current` schema: a major release for the breaking
+/// changes to the schema, a minor one for the additive changes and a patch one otherwise. The
+/// baseline needs the [`DESCRIPTORS`] the crate was generated from.
+pub fn suggest(
+    baseline: &path::Path,
+    current: &prost_types::FileDescriptorSet,
+) -> Result<Suggestion, Error> {
+    let published = published_version(baseline)?;
+    let previous = descriptor::read(&baseline.join(DESCRIPTORS))?;
+    let changes = changes(&previous, current);
+    let bump = changes
+        .iter()
+        .map(|change| change.bump)
+        .max()
+        .unwrap_or(Bump::Patch);
+
+    Ok(Suggestion {
+        next: published.next(bump),
+        published,
+        bump,
+        changes,
+    })
+}
+
+/// Write the `fds` the crate is generated from into the [`DESCRIPTORS`] of its `dst` directory.
+pub fn write(
+    dst: &path::Path,
+    fds: &prost_types::FileDescriptorSet,
+) -> Result<path::PathBuf, Error> {
+    let path = dst.join(DESCRIPTORS);
+    fs::write(&path, fds.encode_to_vec()).map_err(|e| Error::Write(e, path.clone()))?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    fn fds(fields: &[(&str, i32)], values: &[&str]) -> prost_types::FileDescriptorSet {
+        prost_types::FileDescriptorSet {
+            file: vec![prost_types::FileDescriptorProto {
+                name: Some("crabs.proto".to_string()),
+                package: Some("crabs".to_string()),
+                message_type: vec![prost_types::DescriptorProto {
+                    name: Some("Crab".to_string()),
+                    field: fields
+                        .iter()
+                        .map(|(name, number)| prost_types::FieldDescriptorProto {
+                            name: Some(name.to_string()),
+                            number: Some(*number),
+                            ..Default::default()
+                        })
+                        .collect(),
+                    ..Default::default()
+                }],
+                enum_type: vec![prost_types::EnumDescriptorProto {
+                    name: Some("Shell".to_string()),
+                    value: values
+                        .iter()
+                        .zip(0..)
+                        .map(|(name, number)| prost_types::EnumValueDescriptorProto {
+                            name: Some(name.to_string()),
+                            number: Some(number),
+                            ..Default::default()
+                        })
+                        .collect(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    fn descriptions(changes: &[super::Change]) -> Vec<(super::Bump, &str)> {
+        changes
+            .iter()
+            .map(|change| (change.bump, change.description.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn changes_classify_the_schema_changes() {
+        let previous = fds(&[("legs", 1), ("claws", 2)], &["HARD", "SOFT"]);
+
+        assert_eq!(
+            super::changes(&previous, &previous),
+            vec![],
+            "Expected no changes"
+        );
+        assert_eq!(
+            descriptions(&super::changes(
+                &previous,
+                &fds(
+                    &[("legs", 1), ("claws", 2), ("eyes", 3)],
+                    &["HARD", "SOFT", "SPIKY"]
+                ),
+            )),
+            vec![
+                (super::Bump::Minor, "the field `crabs.Crab.eyes` was added"),
+                (
+                    super::Bump::Minor,
+                    "the enum value `crabs.Shell.SPIKY` was added"
+                ),
+            ],
+            "Invalid additive changes",
+        );
+        assert_eq!(
+            descriptions(&super::changes(
+                &previous,
+                &fds(&[("legs", 3), ("pincers", 2)], &["SOFT", "HARD"]),
+            )),
+            vec![
+                (
+                    super::Bump::Major,
+                    "the field `crabs.Crab.claws` was removed"
+                ),
+                (
+                    super::Bump::Major,
+                    "the field `crabs.Crab.legs` changed its number, type or cardinality",
+                ),
+                (
+                    super::Bump::Major,
+                    "the enum value `crabs.Shell.HARD` changed its number"
+                ),
+                (
+                    super::Bump::Major,
+                    "the enum value `crabs.Shell.SOFT` changed its number"
+                ),
+                (
+                    super::Bump::Minor,
+                    "the field `crabs.Crab.pincers` was added"
+                ),
+            ],
+            "Invalid breaking changes",
+        );
+    }
+
+    #[test]
+    fn next_follows_the_cargo_semver_rules() {
+        let next = |version: &str, bump| {
+            version
+                .parse::<super::Version>()
+                .expect("Failed to parse a version")
+                .next(bump)
+                .to_string()
+        };
+
+        assert_eq!(next("1.2.3", super::Bump::Major), "2.0.0");
+        assert_eq!(next("1.2.3", super::Bump::Minor), "1.3.0");
+        assert_eq!(next("1.2.3", super::Bump::Patch), "1.2.4");
+        assert_eq!(next("0.2.3", super::Bump::Major), "0.3.0");
+        assert_eq!(next("0.2.3", super::Bump::Minor), "0.2.4");
+        assert_eq!(next("0.0.3", super::Bump::Major), "0.0.4");
+
+        let err = "1.2".parse::<super::Version>();
+        assert!(
+            matches!(err, Err(super::VersionError::Format(_))),
+            "Expected `Err(VersionError::Format)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn suggest_compares_with_the_published_crate() {
+        let baseline = tempfile::TempDir::new().expect("Failed to create a test directory");
+        fs::write(
+            baseline.path().join("Cargo.toml"),
+            "[package]\nname = \"crabs\"\nversion = \"1.4.2\"\n",
+        )
+        .expect("Failed to write a test manifest");
+        super::write(baseline.path(), &fds(&[("legs", 1)], &["HARD"]))
+            .expect("Failed to write the test descriptors");
+
+        let suggestion = super::suggest(baseline.path(), &fds(&[("legs", 1)], &["HARD", "SOFT"]))
+            .expect("Failed to suggest the next version");
+        assert_eq!(
+            (suggestion.bump, suggestion.next.to_string()),
+            (super::Bump::Minor, "1.5.0".to_string()),
+            "Invalid suggestion",
+        );
+        suggestion
+            .enforce(&"1.5.0".parse().expect("Failed to parse a version"))
+            .expect("Expected the suggested version to be accepted");
+
+        let err = super::suggest(baseline.path(), &fds(&[], &["HARD"]))
+            .expect("Failed to suggest the next version")
+            .enforce(&"1.5.0".parse().expect("Failed to parse a version"));
+        assert!(
+            matches!(
+                &err,
+                Err(super::Error::Behind { bump: super::Bump::Major, changes, .. })
+                    if changes == &["the field `crabs.Crab.legs` was removed"]
+            ),
+            "Expected `Err(Error::Behind)`, got: `{:?}`",
+            err
+        );
+    }
+}
@@ -0,0 +1,266 @@
+use prost_types::field_descriptor_proto::Type;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("The serde package `{0}` defines none of the protobuf types")]
+    UnknownPackage(String),
+    #[error(
+        "The field `{0}` has the type `{1}` without the serde derives, enable `--serde` for its \
+        package"
+    )]
+    Unsupported(String, String),
+    #[error(
+        "The field `{0}` has the well-known type `{1}` from `prost-types`, which has no serde \
+        support, set `--with-well-known-types` and enable `--serde` for `google.protobuf`"
+    )]
+    WellKnownType(String, String),
+}
+
+/// The derives added to the messages, enums and oneofs of the serde packages.
+pub const DERIVE: &str = "#[derive(serde::Serialize, serde::Deserialize)]";
+
+/// The attribute added to the fields of the messages in the serde packages, so the fields left out
+/// of the serialized form take their protobuf default values, like they do in the binary encoding.
+pub const DEFAULT: &str = "#[serde(default)]";
+
+/// The package of the well-known types, taken from `prost-types` unless they are compiled.
+const WELL_KNOWN_TYPES: &str = ".google.protobuf";
+
+/// The path of the serde `package` the attributes of prost are matched against, e.g. `.crabs`, or
+/// `.` for every package.
+pub fn path(package: &str) -> String {
+    format!(".{}", package.trim_start_matches('.'))
+}
+
+/// Whether the `path` matches the type with the fully qualified `name`, e.g. `.crabs.Ferris`,
+/// being the type itself or one of the packages or messages it is nested in.
+fn matches(path: &str, name: &str) -> bool {
+    path == "."
+        || name
+            .strip_prefix(path)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+}
+
+#[derive(Default)]
+struct Types<'a> {
+    messages: Vec<(String, &'a prost_types::DescriptorProto)>,
+    enums: Vec<String>,
+}
+
+fn push_types<'a>(
+    types: &mut Types<'a>,
+    scope: &str,
+    messages: &'a [prost_types::DescriptorProto],
+    enums: &[prost_types::EnumDescriptorProto],
+) {
+    enums
+        .iter()
+        .for_each(|enumeration| types.enums.push(format!("{scope}.{}", enumeration.name())));
+    messages.iter().for_each(|message| {
+        let name = format!("{scope}.{}", message.name());
+
+        push_types(types, &name, &message.nested_type, &message.enum_type);
+        types.messages.push((name, message));
+    });
+}
+
+fn types(fds: &prost_types::FileDescriptorSet) -> Types<'_> {
+    let mut types = Types::default();
+    fds.file.iter().for_each(|file| {
+        let scope = match file.package() {
+            "" => String::new(),
+            package => format!(".{package}"),
+        };
+
+        push_types(&mut types, &scope, &file.message_type, &file.enum_type);
+    });
+
+    types
+}
+
+/// The paths of the fields of the messages in the serde `packages` the [`DEFAULT`] attribute is
+/// added to, e.g. `.crabs.Ferris.name`. The oneofs are left out, as their variants would be
+/// matched along with them.
+pub fn fields(fds: &prost_types::FileDescriptorSet, packages: &[String]) -> Vec<String> {
+    let paths = packages
+        .iter()
+        .map(|package| path(package))
+        .collect::<Vec<_>>();
+
+    types(fds)
+        .messages
+        .into_iter()
+        .filter(|(_, message)| !message.options.as_ref().is_some_and(|o| o.map_entry()))
+        .filter(|(name, _)| paths.iter().any(|path| matches(path, name)))
+        .flat_map(|(name, message)| {
+            message
+                .field
+                .iter()
+                .filter(|field| field.oneof_index.is_none() || field.proto3_optional())
+                .map(move |field| format!("{name}.{}", field.name()))
+        })
+        .collect()
+}
+
+/// Ensure every serde package, given by its name, e.g. `crabs`, defines some of the types in the
+/// `fds`, and that the messages in them only have fields of the types the serde traits are derived
+/// for, the ones in the serde packages or the `extern_paths`. The well-known types only derive them
+/// when compiled along with the rest, `with_well_known_types`.
+pub fn validate(
+    fds: &prost_types::FileDescriptorSet,
+    packages: &[String],
+    with_well_known_types: bool,
+    extern_paths: &[(String, String)],
+) -> Result<(), Error> {
+    let types = types(fds);
+    let paths = packages
+        .iter()
+        .map(|package| path(package))
+        .collect::<Vec<_>>();
+
+    if let Some(package) = packages.iter().find(|package| {
+        let path = path(package);
+        !types
+            .messages
+            .iter()
+            .map(|(name, _)| name)
+            .chain(&types.enums)
+            .any(|name| matches(&path, name))
+    }) {
+        return Err(Error::UnknownPackage(package.clone()));
+    }
+
+    types
+        .messages
+        .iter()
+        .filter(|(name, _)| paths.iter().any(|path| matches(path, name)))
+        .flat_map(|(name, message)| message.field.iter().map(move |field| (name, field)))
+        .filter(|(_, field)| matches!(field.r#type(), Type::Message | Type::Group))
+        .try_for_each(|(message, field)| {
+            let type_name = field.type_name();
+            let error = |error: fn(String, String) -> Error| {
+                Err(error(
+                    format!("{}.{}", message.trim_start_matches('.'), field.name()),
+                    type_name.trim_start_matches('.').to_string(),
+                ))
+            };
+
+            if extern_paths
+                .iter()
+                .any(|(proto_path, _)| matches(&path(proto_path), type_name))
+            {
+                Ok(())
+            } else if !with_well_known_types && matches(WELL_KNOWN_TYPES, type_name) {
+                error(Error::WellKnownType)
+            } else if !paths.iter().any(|path| matches(path, type_name)) {
+                error(Error::Unsupported)
+            } else {
+                Ok(())
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_types::field_descriptor_proto::Type;
+
+    fn fds() -> prost_types::FileDescriptorSet {
+        let field = |name: &str, type_name: Option<&str>, oneof_index: Option<i32>| {
+            prost_types::FieldDescriptorProto {
+                name: Some(name.to_string()),
+                r#type: Some(match type_name {
+                    Some(_) => Type::Message,
+                    None => Type::String,
+                } as i32),
+                type_name: type_name.map(str::to_string),
+                oneof_index,
+                ..Default::default()
+            }
+        };
+
+        prost_types::FileDescriptorSet {
+            file: vec![
+                prost_types::FileDescriptorProto {
+                    package: Some("crabs".to_string()),
+                    message_type: vec![prost_types::DescriptorProto {
+                        name: Some("Ferris".to_string()),
+                        field: vec![
+                            field("name", None, None),
+                            field("shell", Some(".shells.Shell"), None),
+                            field("nickname", None, Some(0)),
+                        ],
+                        oneof_decl: vec![prost_types::OneofDescriptorProto {
+                            name: Some("alias".to_string()),
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+                prost_types::FileDescriptorProto {
+                    package: Some("shells".to_string()),
+                    message_type: vec![prost_types::DescriptorProto {
+                        name: Some("Shell".to_string()),
+                        field: vec![field("molted", Some(".google.protobuf.Timestamp"), None)],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn fields_leave_out_the_oneofs() {
+        assert_eq!(
+            super::fields(&fds(), &["crabs".to_string()]),
+            vec![
+                ".crabs.Ferris.name".to_string(),
+                ".crabs.Ferris.shell".to_string()
+            ],
+            "Invalid serde fields",
+        );
+    }
+
+    #[test]
+    fn validate_rejects_the_types_without_serde() {
+        let fds = fds();
+
+        let err = super::validate(&fds, &["lobsters".to_string()], false, &[]);
+        assert!(
+            matches!(&err, Err(super::Error::UnknownPackage(package)) if package == "lobsters"),
+            "Expected `Err(Error::UnknownPackage)`, got: `{:?}`",
+            err
+        );
+
+        let err = super::validate(&fds, &["crabs".to_string()], false, &[]);
+        assert!(
+            matches!(
+                &err,
+                Err(super::Error::Unsupported(field, _)) if field == "crabs.Ferris.shell"
+            ),
+            "Expected `Err(Error::Unsupported)`, got: `{:?}`",
+            err
+        );
+
+        let err = super::validate(&fds, &[".".to_string()], false, &[]);
+        assert!(
+            matches!(
+                &err,
+                Err(super::Error::WellKnownType(field, _)) if field == "shells.Shell.molted"
+            ),
+            "Expected `Err(Error::WellKnownType)`, got: `{:?}`",
+            err
+        );
+
+        super::validate(&fds, &[".".to_string()], true, &[])
+            .expect("Expected the compiled well-known types to derive serde");
+        super::validate(
+            &fds,
+            &["crabs".to_string()],
+            false,
+            &[(".shells".to_string(), "::shells".to_string())],
+        )
+        .expect("Expected the extern types to be trusted with serde");
+    }
+}
@@ -0,0 +1,439 @@
+use std::{fs, io, path};
+
+use heck::{ToSnakeCase, ToUpperCamelCase};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to append to the generated code `{1}`: {0}")]
+    Write(io::Error, path::PathBuf),
+}
+
+/// Escape the identifiers the same way prost does, so the generated paths point at the generated
+/// code.
+pub(crate) fn sanitize(ident: String) -> String {
+    match ident.as_str() {
+        "as" | "break" | "const" | "continue" | "else" | "enum" | "false" | "fn" | "for" | "if"
+        | "impl" | "in" | "let" | "loop" | "match" | "mod" | "move" | "mut" | "pub" | "ref"
+        | "return" | "static" | "struct" | "trait" | "true" | "type" | "unsafe" | "use"
+        | "where" | "while" | "dyn" | "abstract" | "become" | "box" | "do" | "final" | "macro"
+        | "override" | "priv" | "typeof" | "unsized" | "virtual" | "yield" | "async" | "await"
+        | "try" | "gen" => format!("r#{ident}"),
+        "self" | "super" | "extern" | "crate" => format!("{ident}_"),
+        _ if ident.starts_with(|c: char| c.is_numeric()) => format!("_{ident}"),
+        _ => ident,
+    }
+}
+
+pub(crate) fn to_snake(name: &str) -> String {
+    sanitize(name.to_snake_case())
+}
+
+pub(crate) fn to_upper_camel(name: &str) -> String {
+    sanitize(name.to_upper_camel_case())
+}
+
+/// A message of a protobuf file, with the Rust path of its type relative to the package module.
+pub(crate) struct Message<'a> {
+    pub(crate) full_name: String,
+    pub(crate) path: String,
+    /// The names of the message and of the messages it is nested in, outermost first, e.g.
+    /// `["Ferris", "Claw"]`.
+    pub(crate) names: Vec<String>,
+    /// The indexes of the message and of the messages it is nested in, within their parents.
+    pub(crate) indexes: Vec<usize>,
+    pub(crate) descriptor: &'a prost_types::DescriptorProto,
+}
+
+fn push_messages<'a>(
+    found: &mut Vec<Message<'a>>,
+    scope: &str,
+    module: &str,
+    parent: (&[String], &[usize]),
+    messages: &'a [prost_types::DescriptorProto],
+) {
+    messages.iter().enumerate().for_each(|(index, message)| {
+        let full_name = match scope {
+            "" => message.name().to_string(),
+            scope => format!("{scope}.{}", message.name()),
+        };
+        let nested = format!("{module}{}::", to_snake(message.name()));
+        let names = [parent.0, &[message.name().to_string()]].concat();
+        let indexes = [parent.1, &[index]].concat();
+
+        push_messages(
+            found,
+            &full_name,
+            &nested,
+            (&names, &indexes),
+            &message.nested_type,
+        );
+        found.push(Message {
+            full_name,
+            path: format!("{module}{}", to_upper_camel(message.name())),
+            names,
+            indexes,
+            descriptor: message,
+        });
+    });
+}
+
+/// The messages of the `file`, including the nested ones, every one after the messages nested in
+/// it.
+pub(crate) fn messages(file: &prost_types::FileDescriptorProto) -> Vec<Message<'_>> {
+    let mut found = vec![];
+    push_messages(
+        &mut found,
+        file.package(),
+        "",
+        (&[], &[]),
+        &file.message_type,
+    );
+
+    found
+}
+
+/// The options the code was generated with, which decide where the request and response types
+/// of the services are found.
+pub struct Codegen<'a> {
+    pub compile_well_known_types: bool,
+    pub extern_paths: &'a [(String, String)],
+    /// Whether the methods of the server traits have default bodies, which makes the streaming
+    /// ones return a boxed stream rather than an associated type.
+    pub default_stubs: bool,
+}
+
+/// A protobuf service, along with the names tonic generates its client with.
+pub struct Service<'a> {
+    package: &'a str,
+    descriptor: &'a prost_types::ServiceDescriptorProto,
+    codegen: &'a Codegen<'a>,
+}
+
+impl<'a> Service<'a> {
+    pub(crate) fn new(
+        package: &'a str,
+        descriptor: &'a prost_types::ServiceDescriptorProto,
+        codegen: &'a Codegen<'a>,
+    ) -> Self {
+        Self {
+            package,
+            descriptor,
+            codegen,
+        }
+    }
+
+    /// The name of the service in Rust, e.g. `CrabService`.
+    pub fn name(&self) -> String {
+        to_upper_camel(self.descriptor.name())
+    }
+
+    /// The fully qualified protobuf name of the service, e.g. `crabs.CrabService`.
+    pub fn full_name(&self) -> String {
+        match self.package {
+            "" => self.descriptor.name().to_string(),
+            package => format!("{package}.{}", self.descriptor.name()),
+        }
+    }
+
+    /// The name of a module generated for the service, e.g. `crab_service_conformance`.
+    pub fn module(&self, suffix: &str) -> String {
+        format!("{}_{suffix}", self.name().to_snake_case())
+    }
+
+    /// The name tonic gives the modules of the service, e.g. `crab_service` for `CrabService`.
+    fn tonic_module(&self) -> String {
+        let name = self.name();
+        let mut chars = name.chars().peekable();
+        let mut module = String::new();
+
+        while let Some(c) = chars.next() {
+            module.push(c.to_ascii_lowercase());
+            if chars.peek().is_some_and(|next| next.is_uppercase()) {
+                module.push('_');
+            }
+        }
+
+        module
+    }
+
+    /// The path of the tonic client, as seen from a module nested in the package module.
+    pub fn client(&self) -> String {
+        format!(
+            "super::{}_client::{}Client",
+            self.tonic_module(),
+            self.name()
+        )
+    }
+
+    /// The path of the tonic server trait, as seen from a module nested in the package module.
+    pub fn server(&self) -> String {
+        format!("super::{}_server::{}", self.tonic_module(), self.name())
+    }
+
+    /// Whether the server trait methods have default bodies, see [`Codegen::default_stubs`].
+    pub fn has_default_stubs(&self) -> bool {
+        self.codegen.default_stubs
+    }
+
+    /// Every method of the service.
+    pub fn methods(&self) -> impl Iterator<Item = Method> {
+        self.descriptor.method.iter().map(|method| Method {
+            name: method.name().to_string(),
+            function: to_snake(method.name()),
+            input: self.resolve(method.input_type()),
+            output: self.resolve(method.output_type()),
+            client_streaming: method.client_streaming(),
+            server_streaming: method.server_streaming(),
+        })
+    }
+
+    /// The Rust type of the response of the `method` in the server trait, e.g.
+    /// `Self::WatchFerrisStream` for a server streaming one.
+    pub fn response(&self, method: &Method) -> String {
+        match method.server_streaming {
+            true if self.has_default_stubs() => {
+                format!("tonic::codegen::BoxStream<{}>", method.output)
+            }
+            true => format!("Self::{}", method.stream()),
+            false => method.output.clone(),
+        }
+    }
+
+    /// The methods taking and returning a single message.
+    pub fn unary_methods(&self) -> impl Iterator<Item = Method> {
+        self.methods()
+            .filter(|method| !method.client_streaming && !method.server_streaming)
+    }
+
+    /// The Rust path of the fully qualified protobuf type `name`, e.g. `.crabs.Ferris`, as seen
+    /// from a module nested in the package module.
+    fn resolve(&self, name: &str) -> String {
+        let known = name
+            .strip_prefix(".google.protobuf.")
+            .filter(|_| !self.codegen.compile_well_known_types);
+        if let Some(known) = known {
+            return match known {
+                "Empty" => "()".to_string(),
+                "StringValue" => "::prost::alloc::string::String".to_string(),
+                known => format!("::prost_types::{known}"),
+            };
+        }
+
+        if let Some(path) = self.resolve_extern(name) {
+            return path;
+        }
+
+        let mut local = self
+            .package
+            .split('.')
+            .filter(|part| !part.is_empty())
+            .peekable();
+        let mut parts = name.trim_start_matches('.').split('.');
+        let ident = parts.next_back().unwrap_or_default();
+        let mut parts = parts.peekable();
+
+        while local.peek().is_some() && local.peek() == parts.peek() {
+            local.next();
+            parts.next();
+        }
+
+        std::iter::once("super".to_string())
+            .chain(local.map(|_| "super".to_string()))
+            .chain(parts.map(to_snake))
+            .chain(std::iter::once(to_upper_camel(ident)))
+            .collect::<Vec<_>>()
+            .join("::")
+    }
+
+    /// The Rust path of a type replaced by the longest matching extern path, if any.
+    fn resolve_extern(&self, name: &str) -> Option<String> {
+        self.codegen
+            .extern_paths
+            .iter()
+            .filter_map(|(proto_path, rust_path)| {
+                let proto_path = format!(".{}", proto_path.trim_start_matches('.'));
+                if name == proto_path {
+                    return Some((proto_path.len(), rust_path.clone()));
+                }
+
+                let rest = name.strip_prefix(&proto_path)?.strip_prefix('.')?;
+                let mut parts = rest.split('.');
+                let ident = parts.next_back().unwrap_or_default();
+
+                let path = std::iter::once(rust_path.clone())
+                    .chain(parts.map(to_snake))
+                    .chain(std::iter::once(to_upper_camel(ident)))
+                    .collect::<Vec<_>>()
+                    .join("::");
+
+                Some((proto_path.len(), path))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, path)| path)
+    }
+}
+
+/// A method of a [`Service`].
+pub struct Method {
+    /// The protobuf name of the method, e.g. `GetFerris`.
+    pub name: String,
+    /// The name of the client function calling the method, e.g. `get_ferris`.
+    pub function: String,
+    /// The Rust path of the request type.
+    pub input: String,
+    /// The Rust path of the response type.
+    pub output: String,
+    pub client_streaming: bool,
+    pub server_streaming: bool,
+}
+
+impl Method {
+    /// The Rust type of the request of the method in the server trait.
+    pub fn request(&self) -> String {
+        match self.client_streaming {
+            true => format!("tonic::Streaming<{}>", self.input),
+            false => self.input.clone(),
+        }
+    }
+
+    /// The name of the associated type of the server trait streaming the responses, e.g.
+    /// `WatchFerrisStream`.
+    pub fn stream(&self) -> String {
+        format!("{}Stream", self.name)
+    }
+}
+
+/// Append the code `render`ed for every service of the `fds` to the code generated by prost into
+/// the `dir`, next to the package the service is defined in.
+pub fn append(
+    fds: &prost_types::FileDescriptorSet,
+    dir: &path::Path,
+    codegen: &Codegen<'_>,
+    render: impl Fn(&Service<'_>) -> String,
+) -> Result<(), Error> {
+    fds.file
+        .iter()
+        .filter(|file| !file.service.is_empty())
+        .try_for_each(|file| {
+            let code = file
+                .service
+                .iter()
+                .map(|descriptor| render(&Service::new(file.package(), descriptor, codegen)))
+                .collect::<String>();
+
+            let path = dir.join(match file.package() {
+                "" => "_.rs".to_string(),
+                package => format!("{package}.rs"),
+            });
+            tracing::debug!("Appending to the generated code: {}", path.display());
+
+            fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&path)
+                .and_then(|mut out| io::Write::write_all(&mut out, code.as_bytes()))
+                .map_err(|e| Error::Write(e, path))
+        })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    pub(crate) fn service() -> prost_types::ServiceDescriptorProto {
+        let method = |name: &str, streaming: bool| prost_types::MethodDescriptorProto {
+            name: Some(name.to_string()),
+            input_type: Some(".crabs.GetFerrisRequest".to_string()),
+            output_type: Some(".crabs.Ferris".to_string()),
+            server_streaming: Some(streaming),
+            ..Default::default()
+        };
+
+        prost_types::ServiceDescriptorProto {
+            name: Some("CrabService".to_string()),
+            method: vec![method("GetFerris", false), method("WatchFerris", true)],
+            ..Default::default()
+        }
+    }
+
+    /// Render the `service` of the `crabs` package.
+    pub(crate) fn render(
+        service: &prost_types::ServiceDescriptorProto,
+        render: impl Fn(&super::Service<'_>) -> String,
+    ) -> String {
+        let codegen = super::Codegen {
+            compile_well_known_types: false,
+            extern_paths: &[],
+            default_stubs: false,
+        };
+
+        render_with(service, &codegen, render)
+    }
+
+    /// Render the `service` of the `crabs` package generated with the `codegen` options.
+    pub(crate) fn render_with(
+        service: &prost_types::ServiceDescriptorProto,
+        codegen: &super::Codegen<'_>,
+        render: impl Fn(&super::Service<'_>) -> String,
+    ) -> String {
+        render(&super::Service {
+            package: "crabs",
+            descriptor: service,
+            codegen,
+        })
+    }
+
+    #[test]
+    fn resolve_matches_the_generated_paths() {
+        let descriptor = service();
+        let extern_paths = [(".crabs.disney".to_string(), "::disney".to_string())];
+        let codegen = super::Codegen {
+            compile_well_known_types: false,
+            extern_paths: &extern_paths,
+            default_stubs: false,
+        };
+        let service = |package| super::Service {
+            package,
+            descriptor: &descriptor,
+            codegen: &codegen,
+        };
+
+        [
+            (".crabs.service.Ferris", "super::Ferris"),
+            (".crabs.service.Ferris.Type", "super::ferris::Type"),
+            (
+                ".crabs.sponge_bob.MrKrabs",
+                "super::super::sponge_bob::MrKrabs",
+            ),
+            (".crabs.disney.Sebastian", "::disney::Sebastian"),
+            (".google.protobuf.Empty", "()"),
+            (".google.protobuf.Timestamp", "::prost_types::Timestamp"),
+        ]
+        .into_iter()
+        .for_each(|(name, expected)| {
+            assert_eq!(
+                service("crabs.service").resolve(name),
+                expected,
+                "Invalid path of `{name}`",
+            );
+        });
+
+        assert_eq!(
+            service("").resolve(".Ferris"),
+            "super::Ferris",
+            "Invalid path in the empty package",
+        );
+    }
+
+    #[test]
+    fn client_matches_the_tonic_client() {
+        let descriptor = prost_types::ServiceDescriptorProto {
+            name: Some("HTTPService".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            render(&descriptor, |service| service.client()),
+            "super::http_service_client::HttpServiceClient",
+            "Invalid path of the client",
+        );
+    }
+}
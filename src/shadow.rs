@@ -0,0 +1,207 @@
+use std::{fmt::Write as _, path};
+
+use crate::services;
+
+/// The shadow client of a service, wrapping the tonic client of every unary method. Streaming
+/// methods are left out.
+fn render(service: &services::Service<'_>) -> String {
+    let full_name = service.full_name();
+    let client = service.client();
+    let name = service.name();
+
+    let mut code = format!(
+        r#"
+/// A client of the `{full_name}` service sending every call to the primary endpoint and mirroring
+/// it to an optional shadow endpoint, reporting the calls the shadow answers differently.
+#[cfg(feature = "client")]
+pub mod {module} {{
+    /// A call the shadow endpoint answered differently than the primary one.
+    #[derive(Debug)]
+    pub struct Diff {{
+        /// The fully qualified name of the method, e.g. `crabs.CrabService/GetFerris`.
+        pub method: &'static str,
+        pub primary: String,
+        pub shadow: String,
+    }}
+
+    impl std::fmt::Display for Diff {{
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {{
+            write!(
+                f,
+                "The shadow response of `{{}}` differs, primary: {{}}, shadow: {{}}",
+                self.method, self.primary, self.shadow,
+            )
+        }}
+    }}
+
+    pub struct {name}ShadowClient {{
+        primary: {client}<tonic::transport::Channel>,
+        shadow: Option<{client}<tonic::transport::Channel>>,
+        report: std::sync::Arc<dyn Fn(Diff) + Send + Sync>,
+    }}
+
+    impl {name}ShadowClient {{
+        /// Send every call to the `primary` client only, until a shadow one is added.
+        pub fn new(primary: {client}<tonic::transport::Channel>) -> Self {{
+            Self {{
+                primary,
+                shadow: None,
+                report: std::sync::Arc::new(|diff| eprintln!("{{diff}}")),
+            }}
+        }}
+
+        /// Mirror every call to the `shadow` client.
+        pub fn shadow(mut self, shadow: {client}<tonic::transport::Channel>) -> Self {{
+            self.shadow = Some(shadow);
+            self
+        }}
+
+        /// Report the differing calls to the `report` callback instead of the standard error.
+        pub fn on_diff(mut self, report: impl Fn(Diff) + Send + Sync + 'static) -> Self {{
+            self.report = std::sync::Arc::new(report);
+            self
+        }}
+"#,
+        module = service.module("shadow"),
+    );
+
+    service.unary_methods().for_each(|method| {
+        let _ = write!(
+            code,
+            r#"
+        pub async fn {function}(
+            &mut self,
+            request: impl tonic::IntoRequest<{input}>,
+        ) -> Result<tonic::Response<{output}>, tonic::Status> {{
+            let request = request.into_request();
+            let Some(shadow) = self.shadow.as_mut() else {{
+                return self.primary.{function}(request).await;
+            }};
+
+            let mut mirrored = tonic::Request::new(request.get_ref().clone());
+            *mirrored.metadata_mut() = request.metadata().clone();
+
+            let (primary, mirrored) =
+                join(self.primary.{function}(request), shadow.{function}(mirrored)).await;
+            compare(&self.report, "{full_name}/{method}", &primary, &mirrored);
+
+            primary
+        }}
+"#,
+            function = method.function,
+            input = method.input,
+            output = method.output,
+            method = method.name,
+        );
+    });
+
+    code.push_str(
+        r#"    }
+
+    /// Report the call if the `shadow` response differs from the `primary` one. Failed calls are
+    /// only compared by their status code.
+    fn compare<T: PartialEq + std::fmt::Debug>(
+        report: &std::sync::Arc<dyn Fn(Diff) + Send + Sync>,
+        method: &'static str,
+        primary: &Result<tonic::Response<T>, tonic::Status>,
+        shadow: &Result<tonic::Response<T>, tonic::Status>,
+    ) {
+        let describe = |response: &Result<tonic::Response<T>, tonic::Status>| match response {
+            Ok(response) => format!("{:?}", response.get_ref()),
+            Err(status) => format!("{:?}: {}", status.code(), status.message()),
+        };
+
+        let is_same = match (primary, shadow) {
+            (Ok(primary), Ok(shadow)) => primary.get_ref() == shadow.get_ref(),
+            (Err(primary), Err(shadow)) => primary.code() == shadow.code(),
+            _ => false,
+        };
+        if !is_same {
+            report(Diff {
+                method,
+                primary: describe(primary),
+                shadow: describe(shadow),
+            });
+        }
+    }
+
+    /// Wait for both calls at once, so the shadow endpoint does not delay the primary response by
+    /// more than its own latency.
+    async fn join<A: std::future::Future, B: std::future::Future>(a: A, b: B) -> (A::Output, B::Output) {
+        let (mut a, mut b) = (std::pin::pin!(a), std::pin::pin!(b));
+        let (mut a_output, mut b_output) = (None, None);
+
+        std::future::poll_fn(|cx| {
+            if a_output.is_none() {
+                if let std::task::Poll::Ready(output) = std::future::Future::poll(a.as_mut(), cx) {
+                    a_output = Some(output);
+                }
+            }
+            if b_output.is_none() {
+                if let std::task::Poll::Ready(output) = std::future::Future::poll(b.as_mut(), cx) {
+                    b_output = Some(output);
+                }
+            }
+
+            match (a_output.take(), b_output.take()) {
+                (Some(a), Some(b)) => std::task::Poll::Ready((a, b)),
+                (a, b) => {
+                    a_output = a;
+                    b_output = b;
+                    std::task::Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+}
+"#,
+    );
+    code
+}
+
+/// Append a shadow client for every service of the `fds` to the code generated by prost into the
+/// `dir`.
+pub fn write(
+    fds: &prost_types::FileDescriptorSet,
+    dir: &path::Path,
+    codegen: &services::Codegen<'_>,
+) -> Result<(), services::Error> {
+    services::append(fds, dir, codegen, render)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn render_mirrors_the_unary_methods() {
+        let code =
+            crate::services::tests::render(&crate::services::tests::service(), super::render);
+
+        assert!(
+            code.contains("pub mod crab_service_shadow {"),
+            "Expected a module per service, got:\n{code}",
+        );
+        assert!(
+            code.contains(
+                "primary: super::crab_service_client::CrabServiceClient<tonic::transport::Channel>,"
+            ),
+            "Expected the tonic client to be wrapped, got:\n{code}",
+        );
+        assert!(
+            code.contains(
+                "join(self.primary.get_ferris(request), shadow.get_ferris(mirrored)).await;"
+            ),
+            "Expected the unary method to be mirrored, got:\n{code}",
+        );
+        assert!(
+            code.contains(
+                "compare(&self.report, \"crabs.CrabService/GetFerris\", &primary, &mirrored);"
+            ),
+            "Expected the responses to be compared, got:\n{code}",
+        );
+        assert!(
+            !code.contains("watch_ferris"),
+            "Expected the streaming method to be skipped, got:\n{code}",
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use std::{fs, io, path};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to read the generated files from `{1}`: {0}")]
+    ReadDir(walkdir::Error, path::PathBuf),
+    #[error("Failed to read the generated file `{1}`: {0}")]
+    ReadFile(io::Error, path::PathBuf),
+}
+
+/// The size of the code generated from a single protobuf package.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PackageSize {
+    /// The protobuf package, empty for the files without one.
+    pub package: String,
+    pub lines: usize,
+    pub bytes: u64,
+}
+
+/// Measure the code prost generated into the `dir`, which writes a single file named after every
+/// protobuf package, returning the sizes sorted by package.
+pub fn measure(dir: &path::Path) -> Result<Vec<PackageSize>, Error> {
+    let mut sizes = walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|entry| match entry {
+            Ok(entry) if entry.path().extension().is_some_and(|ext| ext == "rs") => {
+                Some(Ok(entry.into_path()))
+            }
+            Ok(_) => None,
+            Err(e) => Some(Err(Error::ReadDir(e, dir.to_path_buf()))),
+        })
+        .map(|file| {
+            let file = file?;
+            let contents = fs::read(&file).map_err(|e| Error::ReadFile(e, file.clone()))?;
+            let package = file
+                .file_stem()
+                .map(|stem| stem.to_string_lossy())
+                .filter(|stem| stem != "_")
+                .unwrap_or_default()
+                .into_owned();
+
+            Ok(PackageSize {
+                package,
+                lines: contents.iter().filter(|&&byte| byte == b'\n').count(),
+                bytes: contents.len() as u64,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    sizes.sort_by(|a, b| a.package.cmp(&b.package));
+
+    Ok(sizes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    #[test]
+    fn measure_reports_every_package() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+
+        [
+            (
+                "crabs.disney.rs",
+                "pub struct Ariel;\npub struct Sebastian;\n",
+            ),
+            ("_.rs", "pub struct Root;\n"),
+            ("crabs.rs", "pub struct Ferris;\n"),
+            ("README.md", "Crabs\n"),
+        ]
+        .into_iter()
+        .for_each(|(file, contents)| {
+            fs::write(dir.path().join(file), contents).expect("Failed to create a test file")
+        });
+
+        let sizes = super::measure(dir.path()).expect("Failed to measure the generated code");
+
+        assert_eq!(
+            sizes,
+            vec![
+                super::PackageSize {
+                    package: String::new(),
+                    lines: 1,
+                    bytes: 17,
+                },
+                super::PackageSize {
+                    package: "crabs".to_string(),
+                    lines: 1,
+                    bytes: 19,
+                },
+                super::PackageSize {
+                    package: "crabs.disney".to_string(),
+                    lines: 2,
+                    bytes: 40,
+                },
+            ],
+            "Invalid sizes of the generated packages",
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use std::{collections, fmt::Write as _, path, str};
+
+use crate::services;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Invalid message size limit `{0}`, expected `<SERVICE>[/<METHOD>]=<BYTES>`")]
+    InvalidLimit(String),
+    #[error("The message size limit targets `{0}`, which is not a service or a method of one")]
+    UnknownTarget(String),
+}
+
+/// The maximum size of the encoded request and response messages of a method, or of every method
+/// of a service, given by its fully qualified name, e.g. `crabs.CrabService/GetFerris`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Limit {
+    pub target: String,
+    pub bytes: usize,
+}
+
+impl str::FromStr for Limit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (target, bytes) = s
+            .split_once('=')
+            .ok_or_else(|| Error::InvalidLimit(s.to_string()))?;
+        let bytes = bytes
+            .trim()
+            .parse()
+            .map_err(|_| Error::InvalidLimit(s.to_string()))?;
+
+        Ok(Self {
+            target: target.trim().trim_start_matches('.').to_string(),
+            bytes,
+        })
+    }
+}
+
+/// Ensure every limit targets a service or a method defined in the `fds`.
+pub fn validate(
+    fds: &prost_types::FileDescriptorSet,
+    limits: &collections::BTreeMap<String, usize>,
+) -> Result<(), Error> {
+    let targets = fds
+        .file
+        .iter()
+        .flat_map(|file| {
+            file.service.iter().flat_map(move |service| {
+                let name = match file.package() {
+                    "" => service.name().to_string(),
+                    package => format!("{package}.{}", service.name()),
+                };
+
+                service
+                    .method
+                    .iter()
+                    .map(|method| format!("{name}/{}", method.name()))
+                    .chain(std::iter::once(name.clone()))
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect::<collections::BTreeSet<_>>();
+
+    limits
+        .keys()
+        .find(|target| !targets.contains(*target))
+        .map_or(Ok(()), |target| Err(Error::UnknownTarget(target.clone())))
+}
+
+/// The size guard of a service with any limits, wrapping the implementation of the tonic server
+/// trait. Only the unary methods are checked, the streaming ones are passed through.
+fn render(
+    service: &services::Service<'_>,
+    limits: &collections::BTreeMap<String, usize>,
+) -> String {
+    let full_name = service.full_name();
+    let limit = |method: &str| {
+        limits
+            .get(&format!("{full_name}/{method}"))
+            .or_else(|| limits.get(&full_name))
+            .copied()
+    };
+    if !service
+        .unary_methods()
+        .any(|method| limit(&method.name).is_some())
+    {
+        return String::new();
+    }
+
+    let server = service.server();
+    let name = service.name();
+
+    let mut code = format!(
+        r#"
+/// A wrapper of an implementation of the `{full_name}` service answering with `ResourceExhausted`
+/// the calls whose request or response is larger than the size limit of the method.
+#[cfg(feature = "server")]
+pub mod {module} {{
+    pub struct {name}SizeGuard<T> {{
+        inner: T,
+    }}
+
+    impl<T> {name}SizeGuard<T> {{
+        pub fn new(inner: T) -> Self {{
+            Self {{ inner }}
+        }}
+    }}
+
+    fn check(method: &str, kind: &str, len: usize, limit: usize) -> Result<(), tonic::Status> {{
+        if len > limit {{
+            return Err(tonic::Status::resource_exhausted(format!(
+                "The {{kind}} of `{{method}}` is {{len}} bytes, over the limit of {{limit}} bytes",
+            )));
+        }}
+
+        Ok(())
+    }}
+
+    #[tonic::async_trait]
+    impl<T: {server}> {server} for {name}SizeGuard<T> {{"#,
+        module = service.module("size_guard"),
+    );
+
+    service.methods().for_each(|method| {
+        if method.server_streaming && !service.has_default_stubs() {
+            let _ = write!(code, "\n        type {0} = T::{0};\n", method.stream());
+        }
+
+        let body = match limit(&method.name) {
+            Some(bytes) if !method.client_streaming && !method.server_streaming => format!(
+                r#"let method = "{full_name}/{method}";
+            check(method, "request", prost::Message::encoded_len(request.get_ref()), {bytes})?;
+
+            let response = self.inner.{function}(request).await?;
+            check(method, "response", prost::Message::encoded_len(response.get_ref()), {bytes})?;
+
+            Ok(response)"#,
+                method = method.name,
+                function = method.function,
+            ),
+            _ => format!("self.inner.{}(request).await", method.function),
+        };
+
+        let _ = write!(
+            code,
+            r#"
+        async fn {function}(
+            &self,
+            request: tonic::Request<{request}>,
+        ) -> Result<tonic::Response<{response}>, tonic::Status> {{
+            {body}
+        }}
+"#,
+            function = method.function,
+            request = method.request(),
+            response = service.response(&method),
+        );
+    });
+
+    code.push_str("    }\n}\n");
+    code
+}
+
+/// Append a size guard for every service of the `fds` with any `limits` to the code generated by
+/// prost into the `dir`.
+pub fn write(
+    fds: &prost_types::FileDescriptorSet,
+    dir: &path::Path,
+    codegen: &services::Codegen<'_>,
+    limits: &collections::BTreeMap<String, usize>,
+) -> Result<(), services::Error> {
+    services::append(fds, dir, codegen, |service| render(service, limits))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections;
+
+    #[test]
+    fn limit_parses_the_target_and_the_size() {
+        assert_eq!(
+            ".crabs.CrabService/GetFerris=1024"
+                .parse::<super::Limit>()
+                .expect("Failed to parse a limit"),
+            super::Limit {
+                target: "crabs.CrabService/GetFerris".to_string(),
+                bytes: 1024,
+            },
+            "Invalid parsed limit",
+        );
+
+        let err = "crabs.CrabService=1k".parse::<super::Limit>();
+        assert!(
+            matches!(&err, Err(super::Error::InvalidLimit(limit)) if limit == "crabs.CrabService=1k"),
+            "Expected `Err(Error::InvalidLimit)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn validate_rejects_unknown_targets() {
+        let fds = prost_types::FileDescriptorSet {
+            file: vec![prost_types::FileDescriptorProto {
+                package: Some("crabs".to_string()),
+                service: vec![crate::services::tests::service()],
+                ..Default::default()
+            }],
+        };
+
+        let limits = collections::BTreeMap::from([
+            ("crabs.CrabService".to_string(), 1024),
+            ("crabs.CrabService/GetFerris".to_string(), 512),
+        ]);
+        super::validate(&fds, &limits).expect("Expected the limits to be valid");
+
+        let limits = collections::BTreeMap::from([("crabs.CrabService/Missing".to_string(), 1)]);
+        let err = super::validate(&fds, &limits);
+        assert!(
+            matches!(&err, Err(super::Error::UnknownTarget(target)) if target == "crabs.CrabService/Missing"),
+            "Expected `Err(Error::UnknownTarget)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn render_checks_the_limited_unary_methods() {
+        let limits = collections::BTreeMap::from([("crabs.CrabService".to_string(), 1024)]);
+        let code = crate::services::tests::render(&crate::services::tests::service(), |service| {
+            super::render(service, &limits)
+        });
+
+        assert!(
+            code.contains(
+                "impl<T: super::crab_service_server::CrabService> \
+                super::crab_service_server::CrabService for CrabServiceSizeGuard<T> {"
+            ),
+            "Expected the server trait to be implemented, got:\n{code}",
+        );
+        assert!(
+            code.contains(
+                "check(method, \"request\", prost::Message::encoded_len(request.get_ref()), 1024)?;"
+            ),
+            "Expected the request size to be checked, got:\n{code}",
+        );
+        assert!(
+            code.contains("type WatchFerrisStream = T::WatchFerrisStream;"),
+            "Expected the streaming method to be passed through, got:\n{code}",
+        );
+
+        let code = crate::services::tests::render(&crate::services::tests::service(), |service| {
+            super::render(service, &collections::BTreeMap::new())
+        });
+        assert!(
+            code.is_empty(),
+            "Expected no guard without limits, got:\n{code}"
+        );
+    }
+
+    #[test]
+    fn render_boxes_the_streams_of_the_default_stubs() {
+        let limits = collections::BTreeMap::from([("crabs.CrabService".to_string(), 1024)]);
+        let codegen = crate::services::Codegen {
+            compile_well_known_types: false,
+            extern_paths: &[],
+            default_stubs: true,
+        };
+        let code = crate::services::tests::render_with(
+            &crate::services::tests::service(),
+            &codegen,
+            |service| super::render(service, &limits),
+        );
+
+        assert!(
+            code.contains(
+                ") -> Result<tonic::Response<tonic::codegen::BoxStream<super::Ferris>>, tonic::Status> {"
+            ),
+            "Expected the boxed stream to be returned, got:\n{code}",
+        );
+        assert!(
+            !code.contains("type WatchFerrisStream"),
+            "Expected no associated stream type, got:\n{code}",
+        );
+    }
+}
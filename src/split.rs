@@ -0,0 +1,343 @@
+use std::{collections, fmt::Write as _, path, str};
+
+use heck::{ToSnakeCase, ToUpperCamelCase};
+
+use crate::services;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Invalid method group `{0}`, expected `<SERVICE>/<GROUP>=<PREFIX>[,<PREFIX>...]`")]
+    InvalidGroup(String),
+    #[error("The method groups are defined for `{0}`, which is not a service")]
+    UnknownService(String),
+    #[error("The method `{1}` of the `{0}` service does not start with any of its group prefixes")]
+    UngroupedMethod(String, String),
+}
+
+/// The method groups of every split service, keyed by the fully qualified name of the service and
+/// the name of the group, listing the prefixes of the methods in the group.
+pub type Groups = collections::BTreeMap<String, collections::BTreeMap<String, Vec<String>>>;
+
+/// A group of the methods of a service starting with any of the `prefixes`, e.g.
+/// `crabs.CrabService/krabs=GetMrKrabs,GetBetsyKrabs`. An empty prefix matches every method.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Group {
+    pub service: String,
+    pub name: String,
+    pub prefixes: Vec<String>,
+}
+
+impl str::FromStr for Group {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (target, prefixes) = s
+            .split_once('=')
+            .ok_or_else(|| Error::InvalidGroup(s.to_string()))?;
+        let (service, name) = target
+            .trim()
+            .trim_start_matches('.')
+            .split_once('/')
+            .filter(|(service, name)| !service.is_empty() && !name.is_empty())
+            .ok_or_else(|| Error::InvalidGroup(s.to_string()))?;
+
+        Ok(Self {
+            service: service.to_string(),
+            name: name.to_string(),
+            prefixes: prefixes.split(',').map(|p| p.trim().to_string()).collect(),
+        })
+    }
+}
+
+/// The group of the `method`, the one with the longest prefix it starts with.
+fn group_of<'a>(
+    groups: &'a collections::BTreeMap<String, Vec<String>>,
+    method: &str,
+) -> Option<&'a str> {
+    groups
+        .iter()
+        .flat_map(|(group, prefixes)| prefixes.iter().map(move |prefix| (group, prefix)))
+        .filter(|(_, prefix)| method.starts_with(prefix.as_str()))
+        .max_by_key(|(_, prefix)| prefix.len())
+        .map(|(group, _)| group.as_str())
+}
+
+/// Ensure the groups are defined for the services of the `fds` only, and every method of a split
+/// service belongs to a group.
+pub fn validate(fds: &prost_types::FileDescriptorSet, groups: &Groups) -> Result<(), Error> {
+    let services = fds
+        .file
+        .iter()
+        .flat_map(|file| {
+            file.service.iter().map(move |service| {
+                let name = match file.package() {
+                    "" => service.name().to_string(),
+                    package => format!("{package}.{}", service.name()),
+                };
+
+                (name, service)
+            })
+        })
+        .collect::<collections::BTreeMap<_, _>>();
+
+    groups.iter().try_for_each(|(name, groups)| {
+        let service = services
+            .get(name)
+            .ok_or_else(|| Error::UnknownService(name.clone()))?;
+
+        service
+            .method
+            .iter()
+            .find(|method| group_of(groups, method.name()).is_none())
+            .map_or(Ok(()), |method| {
+                Err(Error::UngroupedMethod(
+                    name.clone(),
+                    method.name().to_string(),
+                ))
+            })
+    })
+}
+
+/// The server trait of a split service, as a trait per group of methods and a struct composing
+/// their implementations into the full trait.
+fn render(service: &services::Service<'_>, groups: &Groups) -> String {
+    let full_name = service.full_name();
+    let Some(groups) = groups.get(&full_name) else {
+        return String::new();
+    };
+
+    let name = service.name();
+    let server = service.server();
+    let params = groups
+        .keys()
+        .map(|group| group.to_upper_camel_case())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut code = format!(
+        r#"
+/// The `{full_name}` server trait split into a trait per group of methods, so each group can be
+/// implemented on its own and composed back into the full trait by `{name}Split`.
+#[cfg(feature = "server")]
+pub mod {module} {{"#,
+        module = service.module("split"),
+    );
+
+    groups.keys().for_each(|group| {
+        let _ = write!(
+            code,
+            r#"
+    /// The `{group}` methods of the `{full_name}` service.
+    #[tonic::async_trait]
+    pub trait {name}{trait_name}: std::marker::Send + std::marker::Sync + 'static {{"#,
+            trait_name = group.to_upper_camel_case(),
+        );
+
+        service
+            .methods()
+            .filter(|method| group_of(groups, &method.name) == Some(group.as_str()))
+            .for_each(|method| {
+                if method.server_streaming && !service.has_default_stubs() {
+                    let _ = write!(
+                        code,
+                        r#"
+        type {stream}: tonic::codegen::tokio_stream::Stream<
+                Item = Result<{output}, tonic::Status>,
+            > + std::marker::Send
+            + 'static;
+"#,
+                        stream = method.stream(),
+                        output = method.output,
+                    );
+                }
+
+                let body = match service.has_default_stubs() {
+                    true => {
+                        " {\n            Err(tonic::Status::unimplemented(\"Not yet implemented\"))\n        }"
+                    }
+                    false => ";",
+                };
+                let _ = write!(
+                    code,
+                    r#"
+        async fn {function}(
+            &self,
+            request: tonic::Request<{request}>,
+        ) -> Result<tonic::Response<{response}>, tonic::Status>{body}
+"#,
+                    function = method.function,
+                    request = method.request(),
+                    response = service.response(&method),
+                );
+            });
+
+        code.push_str("    }\n");
+    });
+
+    let _ = write!(
+        code,
+        r#"
+    /// The implementations of every group of methods, composed into the full server trait.
+    pub struct {name}Split<{params}> {{
+"#,
+    );
+    groups.keys().for_each(|group| {
+        let _ = writeln!(
+            code,
+            "        pub {field}: {param},",
+            field = group.to_snake_case(),
+            param = group.to_upper_camel_case(),
+        );
+    });
+
+    let bounds = groups
+        .keys()
+        .map(|group| format!("{0}: {name}{0}", group.to_upper_camel_case()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let _ = write!(
+        code,
+        r#"    }}
+
+    #[tonic::async_trait]
+    impl<{bounds}> {server} for {name}Split<{params}> {{"#,
+    );
+
+    service.methods().for_each(|method| {
+        let Some(group) = group_of(groups, &method.name) else {
+            return;
+        };
+
+        if method.server_streaming && !service.has_default_stubs() {
+            let _ = write!(
+                code,
+                "\n        type {stream} = {param}::{stream};\n",
+                stream = method.stream(),
+                param = group.to_upper_camel_case(),
+            );
+        }
+
+        let _ = write!(
+            code,
+            r#"
+        async fn {function}(
+            &self,
+            request: tonic::Request<{request}>,
+        ) -> Result<tonic::Response<{response}>, tonic::Status> {{
+            self.{field}.{function}(request).await
+        }}
+"#,
+            function = method.function,
+            request = method.request(),
+            response = service.response(&method),
+            field = group.to_snake_case(),
+        );
+    });
+
+    code.push_str("    }\n}\n");
+    code
+}
+
+/// Append the split server traits of every service of the `fds` with any `groups` to the code
+/// generated by prost into the `dir`.
+pub fn write(
+    fds: &prost_types::FileDescriptorSet,
+    dir: &path::Path,
+    codegen: &services::Codegen<'_>,
+    groups: &Groups,
+) -> Result<(), services::Error> {
+    services::append(fds, dir, codegen, |service| render(service, groups))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections;
+
+    fn groups(groups: &[(&str, &[&str])]) -> super::Groups {
+        let groups = groups
+            .iter()
+            .map(|(group, prefixes)| {
+                (
+                    group.to_string(),
+                    prefixes.iter().map(|prefix| prefix.to_string()).collect(),
+                )
+            })
+            .collect();
+
+        collections::BTreeMap::from([("crabs.CrabService".to_string(), groups)])
+    }
+
+    #[test]
+    fn group_parses_the_service_and_the_prefixes() {
+        assert_eq!(
+            ".crabs.CrabService/krabs=GetMrKrabs, GetBetsyKrabs"
+                .parse::<super::Group>()
+                .expect("Failed to parse a group"),
+            super::Group {
+                service: "crabs.CrabService".to_string(),
+                name: "krabs".to_string(),
+                prefixes: vec!["GetMrKrabs".to_string(), "GetBetsyKrabs".to_string()],
+            },
+            "Invalid parsed group",
+        );
+
+        let err = "crabs.CrabService=GetMrKrabs".parse::<super::Group>();
+        assert!(
+            matches!(&err, Err(super::Error::InvalidGroup(group)) if group == "crabs.CrabService=GetMrKrabs"),
+            "Expected `Err(Error::InvalidGroup)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn validate_rejects_ungrouped_methods() {
+        let fds = prost_types::FileDescriptorSet {
+            file: vec![prost_types::FileDescriptorProto {
+                package: Some("crabs".to_string()),
+                service: vec![crate::services::tests::service()],
+                ..Default::default()
+            }],
+        };
+
+        super::validate(&fds, &groups(&[("get", &["Get"]), ("rest", &[""])]))
+            .expect("Expected the groups to be valid");
+
+        let err = super::validate(&fds, &groups(&[("get", &["Get"])]));
+        assert!(
+            matches!(&err, Err(super::Error::UngroupedMethod(_, method)) if method == "WatchFerris"),
+            "Expected `Err(Error::UngroupedMethod)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn render_composes_the_group_traits() {
+        let groups = groups(&[("get", &["Get"]), ("watch", &["Watch"])]);
+        let code = crate::services::tests::render(&crate::services::tests::service(), |service| {
+            super::render(service, &groups)
+        });
+
+        assert!(
+            code.contains(
+                "pub trait CrabServiceWatch: std::marker::Send + std::marker::Sync + 'static {\n        \
+                type WatchFerrisStream:"
+            ),
+            "Expected a trait per group, got:\n{code}",
+        );
+        assert!(
+            code.contains(
+                "impl<Get: CrabServiceGet, Watch: CrabServiceWatch> \
+                super::crab_service_server::CrabService for CrabServiceSplit<Get, Watch> {"
+            ),
+            "Expected the server trait to be composed, got:\n{code}",
+        );
+        assert!(
+            code.contains("type WatchFerrisStream = Watch::WatchFerrisStream;"),
+            "Expected the stream type of the group, got:\n{code}",
+        );
+        assert!(
+            code.contains("self.get.get_ferris(request).await"),
+            "Expected the method to be delegated to its group, got:\n{code}",
+        );
+    }
+}
@@ -0,0 +1,349 @@
+use std::{ffi, fs, io, path};
+
+/// The file marking a directory generated by pbuildrs, which can be replaced without asking.
+pub const MARKER: &str = ".pbuildrs-generated";
+
+const MARKER_CONTENTS: &str =
+    "This directory is generated by pbuildrs and replaced on every run, do not edit it.\n";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(
+        "Refusing to replace `{0}`, it has no `{MARKER}` file so it may not be generated by \
+        pbuildrs, force the replacement to overwrite it anyway"
+    )]
+    Unmarked(path::PathBuf),
+    #[error("Failed to read the output directory `{1}`: {0}")]
+    ReadOutput(io::Error, path::PathBuf),
+    #[error("Failed to create a staging directory next to `{1}`: {0}")]
+    Create(io::Error, path::PathBuf),
+    #[error("Failed to write the marker file into `{1}`: {0}")]
+    WriteMarker(io::Error, path::PathBuf),
+    #[error("Failed to move the generated code into `{1}`: {0}")]
+    Replace(io::Error, path::PathBuf),
+}
+
+/// An output directory generated next to its final location, on the same file system, so it can
+/// replace the previous output with a rename once the whole run succeeded. The staging directory
+/// is removed if the run fails, leaving the previous output intact.
+#[derive(Debug)]
+pub struct Staged {
+    dir: tempfile::TempDir,
+    output: path::PathBuf,
+}
+
+/// Ensure the existing `output` can be replaced, which is the case when it is missing, empty or
+/// marked as generated by pbuildrs, unless the replacement is `force`d.
+fn ensure_replaceable(output: &path::Path, force: bool) -> Result<(), Error> {
+    if force || !output.exists() || output.join(MARKER).is_file() {
+        return Ok(());
+    }
+
+    let is_empty = output.is_dir()
+        && fs::read_dir(output)
+            .map_err(|e| Error::ReadOutput(e, output.to_path_buf()))?
+            .next()
+            .is_none();
+    if is_empty {
+        Ok(())
+    } else {
+        Err(Error::Unmarked(output.to_path_buf()))
+    }
+}
+
+impl Staged {
+    /// Create a staging directory for the `output`, failing if the output exists, but was not
+    /// generated by pbuildrs, unless the replacement is `force`d.
+    pub fn new(output: &path::Path, force: bool) -> Result<Self, Error> {
+        ensure_replaceable(output, force)?;
+
+        let parent = output
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(path::Path::new("."));
+        fs::create_dir_all(parent).map_err(|e| Error::Create(e, output.to_path_buf()))?;
+
+        let mut prefix = ffi::OsString::from(".");
+        prefix.push(output.file_name().unwrap_or(ffi::OsStr::new("output")));
+        prefix.push(".pbuildrs-");
+
+        let dir = tempfile::Builder::new()
+            .prefix(&prefix)
+            .tempdir_in(parent)
+            .map_err(|e| Error::Create(e, output.to_path_buf()))?;
+        tracing::debug!(
+            "Created a staging directory for `{}`: {}",
+            output.display(),
+            dir.path().display(),
+        );
+
+        Ok(Self {
+            dir,
+            output: output.to_path_buf(),
+        })
+    }
+
+    pub fn path(&self) -> &path::Path {
+        self.dir.path()
+    }
+
+    /// The location in the staging directory of a `path` inside the output.
+    pub fn stage(&self, path: &path::Path) -> Option<path::PathBuf> {
+        path.strip_prefix(&self.output)
+            .ok()
+            .map(|rest| self.dir.path().join(rest))
+    }
+
+    /// The final location of a `path` inside the staging directory.
+    pub fn unstage(&self, path: &path::Path) -> Option<path::PathBuf> {
+        path.strip_prefix(self.dir.path())
+            .ok()
+            .map(|rest| self.output.join(rest))
+    }
+
+    /// Where the previous output is moved aside while the staging directory replaces it.
+    fn backup(&self) -> path::PathBuf {
+        let mut backup = self.dir.path().as_os_str().to_os_string();
+        backup.push(".old");
+
+        path::PathBuf::from(backup)
+    }
+
+    /// Move the previous output aside, returning whether there was one.
+    fn set_aside(&self) -> Result<bool, Error> {
+        if !self.output.exists() {
+            return Ok(false);
+        }
+
+        fs::rename(&self.output, self.backup())
+            .map_err(|e| Error::Replace(e, self.output.clone()))?;
+
+        Ok(true)
+    }
+
+    /// Put the previous output moved aside back in place, if there was one.
+    fn restore(&self, previous: bool) {
+        if !previous {
+            return;
+        }
+
+        if let Err(e) = fs::rename(self.backup(), &self.output) {
+            tracing::error!(
+                "Failed to restore the previous output `{}` from `{}`: {e}",
+                self.output.display(),
+                self.backup().display(),
+            );
+        }
+    }
+
+    fn swap(&self) -> Result<(), Error> {
+        fs::rename(self.dir.path(), &self.output)
+            .map_err(|e| Error::Replace(e, self.output.clone()))
+    }
+
+    /// Move the new output back into the staging directory, removed along with it.
+    fn unswap(&self) {
+        if let Err(e) = fs::rename(&self.output, self.dir.path()) {
+            tracing::error!(
+                "Failed to move the generated code out of `{}`: {e}",
+                self.output.display(),
+            );
+        }
+    }
+}
+
+/// Move every staging directory into place of its output, all or nothing. The previous outputs
+/// are first all moved aside, and put back along with the ones already replaced if any of the
+/// staging directories fails to replace its output. They are only removed once every new output is
+/// in place.
+pub fn commit(mut staged: Vec<Staged>) -> Result<(), Error> {
+    staged.iter().try_for_each(|output| {
+        fs::write(output.dir.path().join(MARKER), MARKER_CONTENTS)
+            .map_err(|e| Error::WriteMarker(e, output.output.clone()))
+    })?;
+
+    let mut previous = vec![];
+    for output in &staged {
+        match output.set_aside() {
+            Ok(exists) => previous.push(exists),
+            Err(e) => {
+                staged
+                    .iter()
+                    .zip(&previous)
+                    .for_each(|(output, exists)| output.restore(*exists));
+
+                return Err(e);
+            }
+        }
+    }
+
+    for (swapped, output) in staged.iter().enumerate() {
+        if let Err(e) = output.swap() {
+            staged[..swapped].iter().rev().for_each(Staged::unswap);
+            staged
+                .iter()
+                .zip(&previous)
+                .rev()
+                .for_each(|(output, exists)| output.restore(*exists));
+
+            return Err(e);
+        }
+    }
+
+    staged
+        .iter_mut()
+        .zip(previous)
+        .for_each(|(output, exists)| {
+            output.dir.disable_cleanup(true);
+            if !exists {
+                return;
+            }
+
+            if let Err(e) = fs::remove_dir_all(output.backup()) {
+                tracing::warn!(
+                    "Failed to remove the previous output moved to `{}`: {e}",
+                    output.backup().display(),
+                );
+            }
+        });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    #[test]
+    fn commit_replaces_the_output() {
+        let root = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let output = root.path().join("out");
+        fs::create_dir_all(output.join("stale")).expect("Failed to create a test output");
+        fs::write(output.join(super::MARKER), "").expect("Failed to create a test marker");
+
+        let staged =
+            super::Staged::new(&output, false).expect("Failed to create a staging directory");
+        fs::write(staged.path().join("mod.rs"), "pub mod crabs;\n")
+            .expect("Failed to create a test file");
+        assert_eq!(
+            staged.unstage(&staged.path().join("mod.rs")),
+            Some(output.join("mod.rs")),
+            "Invalid final location of the staged file",
+        );
+
+        super::commit(vec![staged]).expect("Failed to commit the staging directory");
+
+        let mut entries = fs::read_dir(root.path())
+            .expect("Failed to read the test directory")
+            .map(|entry| entry.expect("Failed to read a test entry").file_name())
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(entries, ["out"], "Expected no leftovers next to the output");
+        assert_eq!(
+            fs::read_to_string(output.join("mod.rs")).ok().as_deref(),
+            Some("pub mod crabs;\n"),
+            "Expected the staged file in the output",
+        );
+        assert!(
+            !output.join("stale").exists(),
+            "Expected the previous output to be removed",
+        );
+        assert!(
+            output.join(super::MARKER).is_file(),
+            "Expected the output to be marked as generated",
+        );
+    }
+
+    #[test]
+    fn commit_restores_every_output_if_one_fails() {
+        let root = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let (crabs, ferris) = (root.path().join("crabs"), root.path().join("ferris"));
+        [&crabs, &ferris].iter().for_each(|output| {
+            fs::create_dir_all(output).expect("Failed to create a test output");
+            fs::write(output.join("mod.rs"), "pub mod old;\n")
+                .expect("Failed to create a test file");
+        });
+
+        let staged = [&crabs, &ferris, &ferris]
+            .iter()
+            .map(|output| {
+                let staged =
+                    super::Staged::new(output, true).expect("Failed to create a staging directory");
+                fs::write(staged.path().join("mod.rs"), "pub mod new;\n")
+                    .expect("Failed to create a test file");
+
+                staged
+            })
+            .collect();
+
+        // The second staging directory of the same output can not replace the first one.
+        let err = super::commit(staged);
+        assert!(
+            matches!(&err, Err(super::Error::Replace(_, path)) if path == &ferris),
+            "Expected `Err(Error::Replace)`, got: `{:?}`",
+            err
+        );
+
+        let mut entries = fs::read_dir(root.path())
+            .expect("Failed to read the test directory")
+            .map(|entry| entry.expect("Failed to read a test entry").file_name())
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(
+            entries,
+            ["crabs", "ferris"],
+            "Expected no leftovers next to the outputs"
+        );
+        [&crabs, &ferris].iter().for_each(|output| {
+            assert_eq!(
+                fs::read_to_string(output.join("mod.rs")).ok().as_deref(),
+                Some("pub mod old;\n"),
+                "Expected the previous output `{}` to be restored",
+                output.display(),
+            );
+        });
+    }
+
+    #[test]
+    fn dropping_keeps_the_output() {
+        let root = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let output = root.path().join("out");
+        fs::create_dir_all(&output).expect("Failed to create a test output");
+        fs::write(output.join("mod.rs"), "pub mod ferris;\n")
+            .expect("Failed to create a test file");
+
+        let staged =
+            super::Staged::new(&output, true).expect("Failed to create a staging directory");
+        let staging = staged.path().to_path_buf();
+        drop(staged);
+
+        assert!(
+            !staging.exists(),
+            "Expected the staging directory to be removed"
+        );
+        assert_eq!(
+            fs::read_to_string(output.join("mod.rs")).ok().as_deref(),
+            Some("pub mod ferris;\n"),
+            "Expected the previous output to be intact",
+        );
+    }
+
+    #[test]
+    fn new_refuses_unmarked_outputs() {
+        let root = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let output = root.path().join("src");
+        fs::create_dir_all(&output).expect("Failed to create a test output");
+
+        super::Staged::new(&output, false).expect("Expected an empty output to be replaceable");
+
+        fs::write(output.join("main.rs"), "fn main() {}\n").expect("Failed to create a test file");
+        let err = super::Staged::new(&output, false);
+        assert!(
+            matches!(&err, Err(super::Error::Unmarked(path)) if path == &output),
+            "Expected `Err(Error::Unmarked)`, got: `{:?}`",
+            err
+        );
+
+        super::Staged::new(&output, true).expect("Expected a forced output to be replaceable");
+    }
+}
@@ -0,0 +1,181 @@
+use prost_types::field_descriptor_proto::Type;
+
+use crate::attributes;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(
+        "The string fields generated as `bytes::Bytes` would be serialized as byte arrays, they \
+        can not be combined with `--{0}`"
+    )]
+    Serialization(&'static str),
+}
+
+/// Ensure the string fields are generated as `bytes::Bytes` without the serde derives of the
+/// `serde` packages or the `pbjson` implementations, which would serialize them as byte arrays.
+pub fn validate(serde: bool, pbjson: bool) -> Result<(), Error> {
+    match (serde, pbjson) {
+        (true, _) => Err(Error::Serialization("serde")),
+        (_, true) => Err(Error::Serialization("pbjson")),
+        _ => Ok(()),
+    }
+}
+
+/// Retype the matching string fields of the `messages`, leaving out the entries of the map
+/// fields, since prost does not generate `bytes::Bytes` maps and rejects the bytes keys.
+fn retype_messages(
+    fields: &mut Vec<String>,
+    scope: &str,
+    messages: &mut [prost_types::DescriptorProto],
+    paths: &[String],
+) {
+    messages
+        .iter_mut()
+        .filter(|message| !message.options.as_ref().is_some_and(|o| o.map_entry()))
+        .for_each(|message| {
+            let name = format!("{scope}.{}", message.name());
+            message
+                .field
+                .iter_mut()
+                .filter(|field| field.r#type() == Type::String)
+                .for_each(|field| {
+                    let path = format!("{name}.{}", field.name());
+                    if paths.iter().any(|p| attributes::matches(p, &path)) {
+                        field.set_type(Type::Bytes);
+                        fields.push(path);
+                    }
+                });
+
+            retype_messages(fields, &name, &mut message.nested_type, paths);
+        });
+}
+
+/// The `fds` with the string fields matching the `paths` turned into bytes fields, for prost to
+/// generate them as `bytes::Bytes`, along with the fully qualified names of those fields. Both
+/// are encoded alike on the wire, so only the generated code sees the change.
+pub fn retype(
+    fds: &prost_types::FileDescriptorSet,
+    paths: &[String],
+) -> (prost_types::FileDescriptorSet, Vec<String>) {
+    let mut fds = fds.clone();
+    let mut fields = vec![];
+    fds.file.iter_mut().for_each(|file| {
+        let scope = match file.package() {
+            "" => String::new(),
+            package => format!(".{package}"),
+        };
+        retype_messages(&mut fields, &scope, &mut file.message_type, paths);
+    });
+
+    (fds, fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_types::field_descriptor_proto::Type;
+
+    fn field(name: &str, r#type: Type) -> prost_types::FieldDescriptorProto {
+        prost_types::FieldDescriptorProto {
+            name: Some(name.to_string()),
+            r#type: Some(r#type.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn retype_turns_the_matching_string_fields_into_bytes() {
+        let fds = prost_types::FileDescriptorSet {
+            file: vec![prost_types::FileDescriptorProto {
+                package: Some("crabs".to_string()),
+                message_type: vec![prost_types::DescriptorProto {
+                    name: Some("Ferris".to_string()),
+                    field: vec![
+                        field("name", Type::String),
+                        field("nickname", Type::String),
+                        field("claws", Type::Uint32),
+                    ],
+                    nested_type: vec![prost_types::DescriptorProto {
+                        name: Some("Shell".to_string()),
+                        field: vec![field("name", Type::String)],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        let (retyped, fields) = super::retype(&fds, &["name".to_string(), "claws".to_string()]);
+        assert_eq!(
+            fields,
+            [".crabs.Ferris.name", ".crabs.Ferris.Shell.name"],
+            "Expected only the matching string fields",
+        );
+        let ferris = &retyped.file[0].message_type[0];
+        assert_eq!(
+            ferris
+                .field
+                .iter()
+                .map(prost_types::FieldDescriptorProto::r#type)
+                .collect::<Vec<_>>(),
+            [Type::Bytes, Type::String, Type::Uint32],
+            "Expected the matching string fields to be bytes fields",
+        );
+    }
+
+    #[test]
+    fn retype_leaves_the_map_entries_out() {
+        let fds = prost_types::FileDescriptorSet {
+            file: vec![prost_types::FileDescriptorProto {
+                package: Some("crabs".to_string()),
+                message_type: vec![prost_types::DescriptorProto {
+                    name: Some("Ferris".to_string()),
+                    field: vec![
+                        field("name", Type::String),
+                        prost_types::FieldDescriptorProto {
+                            type_name: Some(".crabs.Ferris.TagsEntry".to_string()),
+                            ..field("tags", Type::Message)
+                        },
+                    ],
+                    nested_type: vec![prost_types::DescriptorProto {
+                        name: Some("TagsEntry".to_string()),
+                        field: vec![field("key", Type::String), field("value", Type::String)],
+                        options: Some(prost_types::MessageOptions {
+                            map_entry: Some(true),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        let (retyped, fields) = super::retype(&fds, &[".crabs".to_string()]);
+        assert_eq!(
+            fields,
+            [".crabs.Ferris.name"],
+            "Expected the map entries to be left out",
+        );
+        assert!(
+            retyped.file[0].message_type[0].nested_type[0]
+                .field
+                .iter()
+                .all(|field| field.r#type() == Type::String),
+            "Expected the map entries to keep their string fields",
+        );
+    }
+
+    #[test]
+    fn validate_rejects_the_serialization() {
+        let err = super::validate(true, false);
+        assert!(
+            matches!(err, Err(super::Error::Serialization("serde"))),
+            "Expected `Err(Error::Serialization)`, got: `{:?}`",
+            err
+        );
+
+        super::validate(false, false).expect("Failed to validate without serialization");
+    }
+}
@@ -0,0 +1,225 @@
+use std::{collections, fs, io, path};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to read the files from `{1}`: {0}")]
+    ReadDir(walkdir::Error, path::PathBuf),
+    #[error("Failed to read the file `{1}`: {0}")]
+    ReadFile(io::Error, path::PathBuf),
+}
+
+/// The generated packages and features a consuming crate depends on.
+#[derive(PartialEq, Debug)]
+pub struct Usage {
+    /// The packages the crate references, directly or through another used package.
+    pub used: Vec<String>,
+    /// The packages that can be left out without breaking the crate.
+    pub unused: Vec<String>,
+    /// Whether the crate uses any of the generated gRPC clients.
+    pub client: bool,
+    /// Whether the crate implements any of the generated gRPC services.
+    pub server: bool,
+}
+
+/// The items a generated package defines and every identifier its code refers to.
+struct Package {
+    name: String,
+    items: collections::BTreeSet<String>,
+    identifiers: collections::BTreeSet<String>,
+}
+
+fn identifiers(source: &str) -> impl Iterator<Item = &str> {
+    source
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|identifier| !identifier.is_empty())
+}
+
+/// The names of the structs, enums and traits defined in the `source`.
+fn items(source: &str) -> collections::BTreeSet<String> {
+    identifiers(source)
+        .zip(identifiers(source).skip(1))
+        .filter(|(keyword, _)| matches!(*keyword, "struct" | "enum" | "trait"))
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+fn rust_files(dir: &path::Path) -> impl Iterator<Item = Result<path::PathBuf, Error>> {
+    walkdir::WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(move |entry| match entry {
+            Ok(entry) if entry.file_type().is_file() => entry
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == "rs")
+                .then(|| Ok(entry.into_path())),
+            Ok(_) => None,
+            Err(e) => Some(Err(Error::ReadDir(e, dir.to_path_buf()))),
+        })
+}
+
+fn read(file: &path::Path) -> Result<String, Error> {
+    fs::read_to_string(file).map_err(|e| Error::ReadFile(e, file.to_path_buf()))
+}
+
+/// Read every package from the module tree `generated` by pbuildrs, where the code of a package
+/// is in the `mod.rs` of its directory.
+fn read_packages(generated: &path::Path) -> Result<Vec<Package>, Error> {
+    rust_files(generated)
+        .filter(|file| {
+            file.as_ref().map_or(true, |file| {
+                file.file_name().is_some_and(|name| name == "mod.rs")
+            })
+        })
+        .map(|file| {
+            let file = file?;
+            let source = read(&file)?;
+            let name = file
+                .parent()
+                .and_then(|dir| dir.strip_prefix(generated).ok())
+                .map(|dir| {
+                    dir.iter()
+                        .map(|part| part.to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join(".")
+                })
+                .unwrap_or_default();
+
+            Ok(Package {
+                name,
+                items: items(&source),
+                identifiers: identifiers(&source).map(str::to_string).collect(),
+            })
+        })
+        .filter(|package| {
+            package
+                .as_ref()
+                .map_or(true, |package| !package.items.is_empty())
+        })
+        .collect()
+}
+
+/// Find out which packages of the code `generated` by pbuildrs are used by the crates with the
+/// `sources`. A package is used when any of its types is referenced, either by the crates or by
+/// the code of another used package. The analysis only compares names, so a type sharing its name
+/// with a type of another package keeps both packages.
+pub fn analyze(generated: &path::Path, sources: &[path::PathBuf]) -> Result<Usage, Error> {
+    let mut packages = read_packages(generated)?;
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let direct = sources
+        .iter()
+        .flat_map(|dir| rust_files(dir))
+        .map(|file| {
+            let source = read(&file?)?;
+
+            Ok(identifiers(&source).map(str::to_string).collect::<Vec<_>>())
+        })
+        .collect::<Result<Vec<_>, Error>>()?
+        .into_iter()
+        .flatten()
+        .collect::<collections::BTreeSet<_>>();
+
+    let mut referenced = direct.clone();
+    let mut used = collections::BTreeSet::new();
+
+    loop {
+        let found = packages
+            .iter()
+            .filter(|package| !used.contains(&package.name))
+            .filter(|package| !package.items.is_disjoint(&referenced))
+            .collect::<Vec<_>>();
+
+        if found.is_empty() {
+            break;
+        }
+
+        found.into_iter().for_each(|package| {
+            used.insert(package.name.clone());
+            referenced.extend(package.identifiers.iter().cloned());
+        });
+    }
+
+    let uses = |suffix: &str| {
+        packages
+            .iter()
+            .flat_map(|package| &package.items)
+            .any(|item| item.ends_with(suffix) && direct.contains(item))
+    };
+    let client = uses("Client");
+    let server = uses("Server");
+
+    let (used, unused) = packages
+        .into_iter()
+        .map(|package| package.name)
+        .partition(|name| used.contains(name));
+
+    Ok(Usage {
+        used,
+        unused,
+        client,
+        server,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path};
+
+    #[test]
+    fn analyze_follows_references_between_packages() {
+        let generated = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let consumer = tempfile::TempDir::new().expect("Failed to create a test directory");
+
+        [
+            ("mod.rs", "pub mod crabs;\n"),
+            (
+                "crabs/mod.rs",
+                "pub mod disney;\npub mod sponge_bob;\npub struct Ferris {\n    pub friend: Option<disney::ariel::Sebastian>,\n}\npub mod crab_service_client {\n    pub struct CrabServiceClient<T> {\n        inner: T,\n    }\n}\n",
+            ),
+            ("crabs/disney/mod.rs", "pub mod ariel;\n"),
+            ("crabs/disney/ariel/mod.rs", "pub struct Sebastian {}\n"),
+            ("crabs/sponge_bob/mod.rs", "pub struct BetsyKrabs {}\n"),
+        ]
+        .into_iter()
+        .for_each(|(file, contents)| {
+            let file = generated.path().join(file);
+            fs::create_dir_all(file.parent().expect("Expected a parent directory"))
+                .expect("Failed to create a test directory");
+            fs::write(file, contents).expect("Failed to create a test file");
+        });
+
+        fs::write(
+            consumer.path().join("main.rs"),
+            "use autogen::crabs::{crab_service_client::CrabServiceClient, Ferris};\n",
+        )
+        .expect("Failed to create a test file");
+
+        let usage = super::analyze(generated.path(), &[consumer.path().to_path_buf()])
+            .expect("Failed to analyze the usage");
+
+        assert_eq!(
+            usage,
+            super::Usage {
+                used: vec!["crabs".to_string(), "crabs.disney.ariel".to_string()],
+                unused: vec!["crabs.sponge_bob".to_string()],
+                client: true,
+                server: false,
+            },
+            "Invalid usage of the generated code",
+        );
+    }
+
+    #[test]
+    fn analyze_fails_on_missing_sources() {
+        let generated = tempfile::TempDir::new().expect("Failed to create a test directory");
+
+        let err = super::analyze(generated.path(), &[path::PathBuf::from("/missing")]);
+
+        assert!(
+            matches!(err, Err(super::Error::ReadDir(..))),
+            "Expected `Err(Error::ReadDir)`, got: `{:?}`",
+            err
+        );
+    }
+}
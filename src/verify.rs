@@ -0,0 +1,614 @@
+use std::{ffi, fmt, fs, io, num, path, process, str};
+
+const PROST_VERSION: &str = "0.14";
+const TONIC_VERSION: &str = "0.14";
+const SERDE_VERSION: &str = "1";
+const PBJSON_VERSION: &str = "0.9";
+const BYTES_VERSION: &str = "1";
+
+/// The edition of the crates including the generated code.
+pub(crate) const EDITION: &str = "2024";
+/// The oldest toolchain building the pinned prost and tonic releases.
+const MIN_RUST_VERSION: (u64, u64) = (1, 85);
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to resolve the root module of the generated code `{1}`: {0}")]
+    ResolveOutDir(io::Error, path::PathBuf),
+    #[error("Failed to create the verification crate directory `{1}`: {0}")]
+    MkCrateDir(io::Error, path::PathBuf),
+    #[error("Failed to write the verification crate file `{1}`: {0}")]
+    WriteCrateFile(io::Error, path::PathBuf),
+    #[error("Failed to run `cargo check` on the verification crate: {0}")]
+    SpawnCargo(io::Error),
+    #[error(
+        "The generated code failed to compile with features `{1}`, `cargo check` exited with {0}"
+    )]
+    CargoCheck(process::ExitStatus, Features),
+    #[error(
+        "The generated code failed to compile with features `{1}` in the offline mode, `cargo \
+        check` exited with {0}, the dependencies may be missing from the local Cargo cache"
+    )]
+    CargoCheckOffline(process::ExitStatus, Features),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RustVersionError {
+    #[error("Expected a Rust version in the `<major>.<minor>[.<patch>]` format, got `{0}`")]
+    Format(String),
+    #[error("Invalid Rust version component `{1}`: {0}")]
+    Component(num::ParseIntError, String),
+    #[error(
+        "The Rust version `{0}` is too old, prost and tonic `{PROST_VERSION}` require at least \
+        {major}.{minor}",
+        major = MIN_RUST_VERSION.0,
+        minor = MIN_RUST_VERSION.1
+    )]
+    Unsupported(String),
+}
+
+/// A Rust toolchain version, recorded as the `rust-version` of the emitted crates. It can not be
+/// older than the toolchain the dependencies of the generated code require.
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Debug)]
+#[serde(try_from = "String", into = "String")]
+pub struct RustVersion {
+    major: u64,
+    minor: u64,
+    patch: Option<u64>,
+}
+
+impl str::FromStr for RustVersion {
+    type Err = RustVersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |part: &str| {
+            part.parse::<u64>()
+                .map_err(|e| RustVersionError::Component(e, part.to_string()))
+        };
+
+        let version = match s.split('.').collect::<Vec<_>>().as_slice() {
+            [major, minor] => Self {
+                major: parse(major)?,
+                minor: parse(minor)?,
+                patch: None,
+            },
+            [major, minor, patch] => Self {
+                major: parse(major)?,
+                minor: parse(minor)?,
+                patch: Some(parse(patch)?),
+            },
+            _ => return Err(RustVersionError::Format(s.to_string())),
+        };
+
+        if (version.major, version.minor) < MIN_RUST_VERSION {
+            return Err(RustVersionError::Unsupported(s.to_string()));
+        }
+
+        Ok(version)
+    }
+}
+
+impl TryFrom<String> for RustVersion {
+    type Error = RustVersionError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<RustVersion> for String {
+    fn from(value: RustVersion) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for RustVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)?;
+
+        if let Some(patch) = self.patch {
+            write!(f, ".{patch}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A combination of the `client` and `server` features to check the generated code with.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Features {
+    pub client: bool,
+    pub server: bool,
+}
+
+impl Features {
+    /// Every combination of the features: none, client only, server only and both.
+    pub fn matrix() -> [Self; 4] {
+        [(false, false), (true, false), (false, true), (true, true)]
+            .map(|(client, server)| Self { client, server })
+    }
+
+    fn to_arg(self) -> String {
+        [(self.client, "client"), (self.server, "server")]
+            .into_iter()
+            .filter_map(|(enabled, feature)| enabled.then_some(feature))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_arg() {
+            features if features.is_empty() => f.write_str("none"),
+            features => f.write_str(&features),
+        }
+    }
+}
+
+/// The tonic dependency of a crate including the generated code.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Tonic {
+    /// No tonic dependency, for the messages only.
+    Disabled,
+    /// Tonic without its transport, e.g. for the clients compiled to WebAssembly.
+    Codegen,
+    /// Tonic along with its transport.
+    Transport,
+}
+
+/// The serde support of the generated code.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub enum Serde {
+    /// No serde support.
+    #[default]
+    Disabled,
+    /// The serde traits derived for the generated types.
+    Derive,
+    /// The serde traits derived for the generated types, some of which have `bytes::Bytes`
+    /// fields, needing the serde support of `bytes`.
+    DeriveBytes,
+    /// The implementations generated by pbjson, following the canonical protobuf JSON mapping.
+    Pbjson,
+}
+
+/// The verification crate scaffolded around the generated code.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Scaffold<'a> {
+    /// The toolchain the crate is kept compatible with, recorded as its `rust-version` and checked
+    /// with.
+    pub rust_version: Option<&'a RustVersion>,
+    /// The serde support of the generated code the crate depends on serde for.
+    pub serde: Serde,
+}
+
+/// The `client` and `server` features of a crate including the generated code, along with the
+/// dependencies of the code. Without `tonic`, the code only
+/// depends on prost and the crate has no features. The `serde` support of the code adds serde
+/// and, for pbjson, its runtime along with the well-known types it maps to the canonical JSON.
+pub(crate) fn dependencies(tonic: Tonic, serde: Serde) -> String {
+    let serde = match serde {
+        Serde::Disabled => String::new(),
+        Serde::Derive => {
+            format!("serde = {{ version = \"{SERDE_VERSION}\", features = [\"derive\"] }}\n")
+        }
+        Serde::DeriveBytes => format!(
+            "bytes = {{ version = \"{BYTES_VERSION}\", features = [\"serde\"] }}\n\
+            serde = {{ version = \"{SERDE_VERSION}\", features = [\"derive\"] }}\n"
+        ),
+        Serde::Pbjson => format!(
+            "pbjson = \"{PBJSON_VERSION}\"\npbjson-types = \"{PBJSON_VERSION}\"\n\
+            serde = \"{SERDE_VERSION}\"\n"
+        ),
+    };
+
+    let tonic_crate_features = match tonic {
+        Tonic::Disabled => {
+            return format!(
+                r#"[dependencies]
+prost = "{PROST_VERSION}"
+prost-types = "{PROST_VERSION}"
+{serde}"#
+            );
+        }
+        Tonic::Codegen => r#""codegen""#,
+        Tonic::Transport => r#""codegen", "transport""#,
+    };
+
+    format!(
+        r#"[features]
+client = ["dep:tonic", "dep:tonic-prost"]
+server = ["dep:tonic", "dep:tonic-prost"]
+
+[dependencies]
+prost = "{PROST_VERSION}"
+prost-types = "{PROST_VERSION}"
+tonic = {{ version = "{TONIC_VERSION}", default-features = false, features = [{tonic_crate_features}], optional = true }}
+tonic-prost = {{ version = "{TONIC_VERSION}", optional = true }}
+{serde}"#
+    )
+}
+
+fn manifest(scaffold: Scaffold, tonic: bool) -> String {
+    format!(
+        r#"[package]
+name = "pbuildrs-verify"
+version = "0.0.0"
+edition = "{EDITION}"
+{rust_version}publish = false
+
+[workspace]
+
+{dependencies}"#,
+        rust_version = scaffold
+            .rust_version
+            .map(|version| format!("rust-version = \"{version}\"\n"))
+            .unwrap_or_default(),
+        dependencies = dependencies(
+            match tonic {
+                true => Tonic::Transport,
+                false => Tonic::Disabled,
+            },
+            scaffold.serde,
+        ),
+    )
+}
+
+fn lib(root_module: &path::Path) -> String {
+    format!(
+        "#[path = {:?}]\npub mod autogen;\n",
+        root_module.display().to_string(),
+    )
+}
+
+fn write_crate(
+    crate_dir: &path::Path,
+    root_module: &path::Path,
+    scaffold: Scaffold,
+    tonic: bool,
+) -> Result<(), Error> {
+    let src_dir = crate_dir.join("src");
+    fs::create_dir_all(&src_dir).map_err(|e| Error::MkCrateDir(e, src_dir.clone()))?;
+
+    let manifest_path = crate_dir.join("Cargo.toml");
+    fs::write(&manifest_path, manifest(scaffold, tonic))
+        .map_err(|e| Error::WriteCrateFile(e, manifest_path))?;
+
+    let lib_path = src_dir.join("lib.rs");
+    fs::write(&lib_path, lib(root_module)).map_err(|e| Error::WriteCrateFile(e, lib_path))?;
+
+    Ok(())
+}
+
+/// Create a throwaway crate in `crate_dir` that includes the generated code from its `root_module`
+/// file and run `cargo check` on it once for every feature combination in `features`, sharing the
+/// build artifacts in `target_dir` between runs. The crate is set up as described by the
+/// `scaffold`, and only depends on tonic if any of the `features` enables the client or the
+/// server. When `offline`, Cargo is not allowed to access the network,
+/// so the dependencies of the crate need to be in its local cache already. With the
+/// [`Scaffold::rust_version`], the crate is checked with the toolchain of that version, picked by
+/// rustup, which needs to be installed.
+pub fn verify_build(
+    cargo: &ffi::OsStr,
+    crate_dir: &path::Path,
+    target_dir: &path::Path,
+    root_module: &path::Path,
+    features: &[Features],
+    scaffold: Scaffold,
+    offline: bool,
+) -> Result<(), Error> {
+    let root_module = fs::canonicalize(root_module)
+        .map_err(|e| Error::ResolveOutDir(e, root_module.to_path_buf()))?;
+
+    let tonic = features
+        .iter()
+        .any(|features| features.client || features.server);
+    write_crate(crate_dir, &root_module, scaffold, tonic)?;
+    tracing::debug!(
+        "Created a verification crate: {}",
+        crate_dir.join("Cargo.toml").display()
+    );
+
+    features.iter().try_for_each(|&features| {
+        tracing::info!("Checking the generated code with features: {features}");
+
+        let mut command = process::Command::new(cargo);
+        command
+            .arg("check")
+            .arg("--no-default-features")
+            .arg("--target-dir")
+            .arg(target_dir)
+            .current_dir(crate_dir);
+        if offline {
+            command.arg("--offline");
+        }
+        if let Some(version) = scaffold.rust_version {
+            // The compiler of the toolchain running pbuildrs, e.g. from a build script, would
+            // override the one rustup picks.
+            command
+                .env("RUSTUP_TOOLCHAIN", version.to_string())
+                .env_remove("RUSTC");
+        }
+
+        let arg = features.to_arg();
+        if !arg.is_empty() {
+            command.arg("--features").arg(arg);
+        }
+
+        let status = command.status().map_err(Error::SpawnCargo)?;
+
+        match (status.success(), offline) {
+            (true, _) => {}
+            (false, true) => return Err(Error::CargoCheckOffline(status, features)),
+            (false, false) => return Err(Error::CargoCheck(status, features)),
+        }
+
+        Ok(())
+    })?;
+
+    tracing::info!("The generated code compiled successfully");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi, fs, os::unix::fs::PermissionsExt, path};
+
+    #[test]
+    fn write_crate_includes_generated_tree() {
+        let crate_dir = tempfile::TempDir::new().expect("Failed to create a test crate directory");
+
+        super::write_crate(
+            crate_dir.path(),
+            path::Path::new("/tmp/generated/mod.rs"),
+            super::Scaffold::default(),
+            true,
+        )
+        .expect("Failed to write the verification crate");
+
+        let manifest = fs::read_to_string(crate_dir.path().join("Cargo.toml"))
+            .expect("Failed to read the verification crate manifest");
+        assert!(
+            manifest.contains("\n[workspace]\n"),
+            "Expected the verification crate to be detached from any workspace, got:\n{manifest}",
+        );
+        assert!(
+            manifest.contains(r#"client = ["dep:tonic", "dep:tonic-prost"]"#),
+            "Expected the verification crate to declare the `client` feature, got:\n{manifest}",
+        );
+        assert!(
+            manifest.contains(r#"server = ["dep:tonic", "dep:tonic-prost"]"#),
+            "Expected the verification crate to declare the `server` feature, got:\n{manifest}",
+        );
+        assert!(
+            manifest.contains("edition = \"2024\"\n"),
+            "Expected the verification crate to use the latest edition, got:\n{manifest}",
+        );
+        assert!(
+            !manifest.contains("rust-version"),
+            "Expected the verification crate to not record the Rust version, got:\n{manifest}",
+        );
+
+        let lib = fs::read_to_string(crate_dir.path().join("src/lib.rs"))
+            .expect("Failed to read the verification crate library");
+        assert_eq!(
+            lib, "#[path = \"/tmp/generated/mod.rs\"]\npub mod autogen;\n",
+            "Invalid verification crate library",
+        );
+    }
+
+    #[test]
+    fn verify_build_succeeds_if_cargo_succeeds() {
+        let generated = tempfile::TempDir::new().expect("Failed to create a test output directory");
+        let crate_dir = tempfile::TempDir::new().expect("Failed to create a test crate directory");
+
+        super::verify_build(
+            ffi::OsStr::new("true"),
+            crate_dir.path(),
+            &crate_dir.path().join("target"),
+            generated.path(),
+            &super::Features::matrix(),
+            super::Scaffold::default(),
+            false,
+        )
+        .expect("Expected the verification to succeed");
+    }
+
+    #[test]
+    fn verify_build_leaves_out_tonic_without_the_services() {
+        let generated = tempfile::TempDir::new().expect("Failed to create a test output directory");
+        let crate_dir = tempfile::TempDir::new().expect("Failed to create a test crate directory");
+
+        super::verify_build(
+            ffi::OsStr::new("true"),
+            crate_dir.path(),
+            &crate_dir.path().join("target"),
+            generated.path(),
+            &[super::Features {
+                client: false,
+                server: false,
+            }],
+            super::Scaffold::default(),
+            false,
+        )
+        .expect("Expected the verification to succeed");
+
+        let manifest = fs::read_to_string(crate_dir.path().join("Cargo.toml"))
+            .expect("Failed to read the verification crate manifest");
+        assert!(
+            !manifest.contains("[features]") && !manifest.contains("tonic"),
+            "Expected the verification crate to only depend on prost, got:\n{manifest}",
+        );
+    }
+
+    #[test]
+    fn verify_build_checks_with_the_msrv_toolchain() {
+        let generated = tempfile::TempDir::new().expect("Failed to create a test output directory");
+        let crate_dir = tempfile::TempDir::new().expect("Failed to create a test crate directory");
+        let cargo = crate_dir.path().join("cargo.sh");
+        let toolchain = crate_dir.path().join("toolchain");
+        fs::write(
+            &cargo,
+            format!(
+                "#!/bin/sh\necho \"$RUSTUP_TOOLCHAIN\" > {:?}\n",
+                toolchain.display().to_string(),
+            ),
+        )
+        .expect("Failed to create a test cargo script");
+        fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755))
+            .expect("Failed to make the test cargo script executable");
+
+        let version = "1.85".parse().expect("Failed to parse a Rust version");
+        super::verify_build(
+            cargo.as_os_str(),
+            crate_dir.path(),
+            &crate_dir.path().join("target"),
+            generated.path(),
+            &[super::Features {
+                client: false,
+                server: false,
+            }],
+            super::Scaffold {
+                rust_version: Some(&version),
+                serde: super::Serde::Disabled,
+            },
+            false,
+        )
+        .expect("Expected the verification to succeed");
+
+        assert_eq!(
+            fs::read_to_string(&toolchain).ok().as_deref(),
+            Some("1.85\n"),
+            "Expected cargo to run with the toolchain of the MSRV",
+        );
+    }
+
+    #[test]
+    fn verify_build_fails_if_cargo_fails() {
+        let generated = tempfile::TempDir::new().expect("Failed to create a test output directory");
+        let crate_dir = tempfile::TempDir::new().expect("Failed to create a test crate directory");
+
+        let err = super::verify_build(
+            ffi::OsStr::new("false"),
+            crate_dir.path(),
+            &crate_dir.path().join("target"),
+            generated.path(),
+            &[super::Features {
+                client: false,
+                server: false,
+            }],
+            super::Scaffold::default(),
+            false,
+        );
+        assert!(
+            matches!(err, Err(super::Error::CargoCheck { .. })),
+            "Expected `Err(Error::CargoCheck)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn verify_build_fails_offline_if_cargo_fails() {
+        let generated = tempfile::TempDir::new().expect("Failed to create a test output directory");
+        let crate_dir = tempfile::TempDir::new().expect("Failed to create a test crate directory");
+
+        let err = super::verify_build(
+            ffi::OsStr::new("false"),
+            crate_dir.path(),
+            &crate_dir.path().join("target"),
+            generated.path(),
+            &[super::Features {
+                client: false,
+                server: false,
+            }],
+            super::Scaffold::default(),
+            true,
+        );
+        assert!(
+            matches!(err, Err(super::Error::CargoCheckOffline { .. })),
+            "Expected `Err(Error::CargoCheckOffline)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn features_matrix_covers_every_combination() {
+        let matrix = super::Features::matrix().map(|features| features.to_string());
+
+        assert_eq!(
+            matrix,
+            ["none", "client", "server", "client,server"],
+            "Invalid feature matrix",
+        );
+    }
+
+    #[test]
+    fn verify_build_fails_if_cargo_is_missing() {
+        let generated = tempfile::TempDir::new().expect("Failed to create a test output directory");
+        let crate_dir = tempfile::TempDir::new().expect("Failed to create a test crate directory");
+
+        let err = super::verify_build(
+            ffi::OsStr::new("/nonexistent/cargo"),
+            crate_dir.path(),
+            &crate_dir.path().join("target"),
+            generated.path(),
+            &[super::Features {
+                client: false,
+                server: false,
+            }],
+            super::Scaffold::default(),
+            false,
+        );
+        assert!(
+            matches!(err, Err(super::Error::SpawnCargo { .. })),
+            "Expected `Err(Error::SpawnCargo)`, got: `{:?}`",
+            err
+        );
+    }
+
+    #[test]
+    fn manifest_records_rust_version() {
+        let version = "1.85".parse().expect("Failed to parse a Rust version");
+        let manifest = super::manifest(
+            super::Scaffold {
+                rust_version: Some(&version),
+                serde: super::Serde::Disabled,
+            },
+            true,
+        );
+
+        assert!(
+            manifest.contains("edition = \"2024\"\nrust-version = \"1.85\"\n"),
+            "Expected the manifest to record the 2024 edition and the Rust version, got:\n{manifest}",
+        );
+        assert!(
+            manifest.contains(r#"client = ["dep:tonic", "dep:tonic-prost"]"#),
+            "Expected the manifest to use namespaced features, got:\n{manifest}",
+        );
+    }
+
+    #[test]
+    fn rust_version_parse_fails_on_invalid_input() {
+        let err = "1".parse::<super::RustVersion>();
+        assert!(
+            matches!(err, Err(super::RustVersionError::Format { .. })),
+            "Expected `Err(RustVersionError::Format)`, got: `{:?}`",
+            err
+        );
+
+        let err = "1.x".parse::<super::RustVersion>();
+        assert!(
+            matches!(err, Err(super::RustVersionError::Component { .. })),
+            "Expected `Err(RustVersionError::Component)`, got: `{:?}`",
+            err
+        );
+
+        let err = "1.70".parse::<super::RustVersion>();
+        assert!(
+            matches!(err, Err(super::RustVersionError::Unsupported { .. })),
+            "Expected `Err(RustVersionError::Unsupported)`, got: `{:?}`",
+            err
+        );
+    }
+}
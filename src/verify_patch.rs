@@ -0,0 +1,250 @@
+use std::{collections, fmt, fs, io, path};
+
+use crate::patcher;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to read the protobuf files from `{1}`: {0}")]
+    ReadDir(walkdir::Error, path::PathBuf),
+    #[error("Failed to open the protobuf file `{1}`: {0}")]
+    Open(io::Error, path::PathBuf),
+    #[error("Failed to verify the patched protobuf file `{1}`: {0}")]
+    Patch(patcher::Error, path::PathBuf),
+}
+
+/// A protobuf file of the patched tree that is not exactly the patch of its source.
+#[derive(PartialEq, Debug)]
+pub enum Difference {
+    /// The source file has no patched counterpart.
+    Missing(path::PathBuf),
+    /// The patched file has no source.
+    Unexpected(path::PathBuf),
+    /// The patched file departs from the patch of its source.
+    Mismatch(path::PathBuf, patcher::Mismatch),
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(path) => write!(f, "missing: {}", path.display()),
+            Self::Unexpected(path) => write!(f, "unexpected: {}", path.display()),
+            Self::Mismatch(path, mismatch) => write!(f, "{}: {mismatch}", path.display()),
+        }
+    }
+}
+
+/// The result of verifying a patched tree against its source.
+#[derive(Default, PartialEq, Debug)]
+pub struct Report {
+    /// The editions of the source files that were replaced, keyed by the path of the patched file.
+    pub replaced: collections::BTreeMap<path::PathBuf, String>,
+    /// The number of source files without an edition, which are copied unchanged.
+    pub untouched: usize,
+    pub differences: Vec<Difference>,
+}
+
+/// The protobuf files under the `root`, relative to it, or an empty path standing for the `root`
+/// itself if it is a file.
+fn list_files(root: &path::Path) -> Result<collections::BTreeSet<path::PathBuf>, Error> {
+    if root.is_file() {
+        return Ok(collections::BTreeSet::from([path::PathBuf::new()]));
+    }
+
+    walkdir::WalkDir::new(root)
+        .into_iter()
+        .filter_map(|entry| match entry {
+            Ok(entry)
+                if entry.file_type().is_file()
+                    && entry.path().extension().is_some_and(|ext| ext == "proto") =>
+            {
+                entry
+                    .path()
+                    .strip_prefix(root)
+                    .ok()
+                    .map(|path| Ok(path.to_path_buf()))
+            }
+            Ok(_) => None,
+            Err(e) => Some(Err(Error::ReadDir(e, root.to_path_buf()))),
+        })
+        .collect()
+}
+
+/// Check that every protobuf file of the `patched` tree is exactly the one of the `src` tree
+/// patched with the `syntax`, re-running the edition detection on every source file, so a
+/// corrupted or manually edited patched tree is caught. Either both are directories, or both are
+/// single files.
+pub fn verify(
+    src: &path::Path,
+    patched: &path::Path,
+    syntax: patcher::Syntax,
+    allow_unknown: bool,
+) -> Result<Report, Error> {
+    let sources = list_files(src)?;
+    let patched_files = match patched.exists() {
+        true => list_files(patched)?,
+        false => collections::BTreeSet::new(),
+    };
+    let mut report = Report::default();
+
+    let join = |root: &path::Path, file: &path::Path| match file.as_os_str().is_empty() {
+        true => root.to_path_buf(),
+        false => root.join(file),
+    };
+
+    sources.iter().try_for_each(|file| {
+        let (src, patched) = (join(src, file), join(patched, file));
+        if !patched_files.contains(file) {
+            report.differences.push(Difference::Missing(patched));
+            return Ok(());
+        }
+
+        let open = |path: &path::Path| {
+            fs::File::open(path).map_err(|e| Error::Open(e, path.to_path_buf()))
+        };
+        let (outcome, mismatch) = patcher::verify_patch(
+            io::BufReader::new(open(&src)?),
+            io::BufReader::new(open(&patched)?),
+            syntax,
+            allow_unknown,
+        )
+        .map_err(|e| Error::Patch(e, patched.clone()))?;
+
+        match outcome {
+            patcher::Outcome::Replaced(edition) => {
+                report.replaced.insert(patched.clone(), edition);
+            }
+            patcher::Outcome::Untouched => report.untouched += 1,
+        }
+        if let Some(mismatch) = mismatch {
+            report
+                .differences
+                .push(Difference::Mismatch(patched, mismatch));
+        }
+
+        Ok(())
+    })?;
+
+    report.differences.extend(
+        patched_files
+            .difference(&sources)
+            .map(|file| Difference::Unexpected(join(patched, file))),
+    );
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path};
+
+    fn write(root: &path::Path, files: &[(&str, &str)]) {
+        files.iter().for_each(|(file, contents)| {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().expect("A test file has a parent"))
+                .expect("Failed to create a test directory");
+            fs::write(path, contents).expect("Failed to write a test file");
+        });
+    }
+
+    #[test]
+    fn verify_accepts_the_patched_tree() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let (src, patched) = (dir.path().join("src"), dir.path().join("patched"));
+        write(
+            &src,
+            &[
+                (
+                    "crabs/Ferris.proto",
+                    "edition = \"2023\";\npackage crabs;\n",
+                ),
+                (
+                    "crabs/Shell.proto",
+                    "syntax = \"proto3\";\npackage crabs;\n",
+                ),
+                ("README.md", "Crabs\n"),
+            ],
+        );
+        write(
+            &patched,
+            &[
+                (
+                    "crabs/Ferris.proto",
+                    "syntax = \"proto3\";\npackage crabs;\n",
+                ),
+                (
+                    "crabs/Shell.proto",
+                    "syntax = \"proto3\";\npackage crabs;\n",
+                ),
+            ],
+        );
+
+        let report = super::verify(&src, &patched, crate::patcher::Syntax::Proto3, false)
+            .expect("Failed to verify the patched tree");
+
+        assert_eq!(
+            report,
+            super::Report {
+                replaced: [(patched.join("crabs/Ferris.proto"), "2023".to_string())].into(),
+                untouched: 1,
+                differences: vec![],
+            },
+            "Expected the patched tree to match its source",
+        );
+    }
+
+    #[test]
+    fn verify_reports_the_differences() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let (src, patched) = (dir.path().join("src"), dir.path().join("patched"));
+        write(
+            &src,
+            &[
+                ("Edited.proto", "edition = \"2023\";\npackage crabs;\n"),
+                ("Longer.proto", "package crabs;\n"),
+                ("Missing.proto", "package crabs;\n"),
+                ("Shorter.proto", "package crabs;\n"),
+            ],
+        );
+        write(
+            &patched,
+            &[
+                ("Edited.proto", "syntax = \"proto3\";\npackage crab;\n"),
+                ("Longer.proto", "package crabs;\n\n"),
+                ("Shorter.proto", "package crabs;"),
+                ("Unexpected.proto", "package crabs;\n"),
+            ],
+        );
+
+        let report = super::verify(&src, &patched, crate::patcher::Syntax::Proto3, false)
+            .expect("Failed to verify the patched tree");
+
+        let position = |line, column| crate::patcher::Position { line, column };
+        assert_eq!(
+            report.differences,
+            vec![
+                super::Difference::Mismatch(
+                    patched.join("Edited.proto"),
+                    crate::patcher::Mismatch::Differs(position(2, 13)),
+                ),
+                super::Difference::Mismatch(
+                    patched.join("Longer.proto"),
+                    crate::patcher::Mismatch::Trailing(position(2, 1)),
+                ),
+                super::Difference::Missing(patched.join("Missing.proto")),
+                super::Difference::Mismatch(
+                    patched.join("Shorter.proto"),
+                    crate::patcher::Mismatch::Truncated(position(1, 15)),
+                ),
+                super::Difference::Unexpected(patched.join("Unexpected.proto")),
+            ],
+            "Invalid differences of the patched tree",
+        );
+
+        let err = super::verify(&src, &patched, crate::patcher::Syntax::Proto2, false);
+        assert!(
+            matches!(&err, Ok(report) if report.differences.len() == 5),
+            "Expected the proto2 patch to differ too, got: `{:?}`",
+            err
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use notify::Watcher as _;
+use std::{path, sync::mpsc, time};
+
+/// How long to wait for more changes after the first one before triggering a rebuild, so editors
+/// saving several files at once only cause a single rebuild.
+const DEBOUNCE: time::Duration = time::Duration::from_millis(200);
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to create a filesystem watcher: {0}")]
+    Create(notify::Error),
+    #[error("Failed to watch the directory `{1}`: {0}")]
+    Watch(notify::Error, path::PathBuf),
+    #[error("Failed to receive filesystem changes: {0}")]
+    Receive(notify::Error),
+    #[error("The filesystem watcher stopped unexpectedly")]
+    Disconnected,
+}
+
+/// Watches a set of directories for changes to protobuf files.
+pub struct Watcher {
+    _watcher: notify::RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+}
+
+fn is_relevant(event: &notify::Event) -> bool {
+    !matches!(event.kind, notify::EventKind::Access(_))
+        && event
+            .paths
+            .iter()
+            .any(|path| path.extension().is_some_and(|ext| ext == "proto"))
+}
+
+impl Watcher {
+    pub fn new(paths: &[path::PathBuf]) -> Result<Self, Error> {
+        let (tx, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx).map_err(Error::Create)?;
+
+        paths.iter().try_for_each(|path| {
+            watcher
+                .watch(path, notify::RecursiveMode::Recursive)
+                .map_err(|e| Error::Watch(e, path.clone()))
+        })?;
+
+        Ok(Self {
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    /// Block until at least one protobuf file changes and return the changed paths.
+    pub fn wait(&self) -> Result<Vec<path::PathBuf>, Error> {
+        let mut changed = vec![];
+
+        while changed.is_empty() {
+            let event = self
+                .events
+                .recv()
+                .map_err(|_| Error::Disconnected)?
+                .map_err(Error::Receive)?;
+
+            if is_relevant(&event) {
+                changed.extend(event.paths);
+            }
+        }
+
+        while let Ok(event) = self.events.recv_timeout(DEBOUNCE) {
+            let event = event.map_err(Error::Receive)?;
+
+            if is_relevant(&event) {
+                changed.extend(event.paths);
+            }
+        }
+
+        changed.sort();
+        changed.dedup();
+
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path, thread, time};
+
+    #[test]
+    fn is_relevant_ignores_non_proto_files() {
+        let event = notify::Event::new(notify::EventKind::Create(notify::event::CreateKind::File))
+            .add_path(path::PathBuf::from("/tmp/proto/README.md"));
+
+        assert!(
+            !super::is_relevant(&event),
+            "Expected changes to non-proto files to be ignored",
+        );
+    }
+
+    #[test]
+    fn is_relevant_ignores_access() {
+        let event = notify::Event::new(notify::EventKind::Access(notify::event::AccessKind::Any))
+            .add_path(path::PathBuf::from("/tmp/proto/crabs.proto"));
+
+        assert!(
+            !super::is_relevant(&event),
+            "Expected file access to be ignored",
+        );
+    }
+
+    #[test]
+    fn wait_returns_changed_protos() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let watcher =
+            super::Watcher::new(&[dir.path().to_path_buf()]).expect("Failed to create a watcher");
+
+        let file = dir.path().join("crabs.proto");
+        let writer = {
+            let file = file.clone();
+
+            thread::spawn(move || {
+                thread::sleep(time::Duration::from_millis(50));
+                fs::write(&file, "syntax = \"proto3\";\n").expect("Failed to write a test file");
+            })
+        };
+
+        let changed = watcher.wait().expect("Failed to wait for changes");
+        writer.join().expect("Failed to write a test file");
+
+        assert!(
+            changed.iter().any(|path| path.ends_with("crabs.proto")),
+            "Expected the changed protobuf file to be reported, got: {changed:?}",
+        );
+    }
+
+    #[test]
+    fn new_fails_on_missing_directory() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a test directory");
+
+        let err = super::Watcher::new(&[dir.path().join("missing")]);
+        assert!(
+            matches!(err, Err(super::Error::Watch { .. })),
+            "Expected `Err(Error::Watch)`, got: `{:?}`",
+            err.err()
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use prost::Message as _;
+use std::{fs, io, path};
+
+use crate::{descriptor, modgen};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Previous(#[from] descriptor::Error),
+    #[error("Failed to write the wire-compatibility tests `{1}`: {0}")]
+    Write(io::Error, path::PathBuf),
+}
+
+/// The file the wire-compatibility tests are written into, next to the root module of the output.
+pub const FILE: &str = "wire_compat.rs";
+
+/// The file descriptor set of the generated code, embedded into the tests.
+pub const CURRENT_FILE: &str = "wire_compat_current.bin";
+
+/// The file descriptor set of the previous version, embedded into the tests.
+pub const PREVIOUS_FILE: &str = "wire_compat_previous.bin";
+
+/// The test module encoding and decoding every message with dynamic messages of `prost-reflect`,
+/// so it works with any schema without generating code per message.
+const CODE: &str = r#"// This file is @generated by pbuildrs.
+
+/// Wire-compatibility tests between the current descriptors and the previous ones given to
+/// pbuildrs, encoding every message defined by both versions with one of them and decoding it
+/// with the other. They need `prost-reflect` as a dev-dependency.
+#[cfg(test)]
+mod wire_compat {
+    use prost::Message as _;
+    use prost_reflect::{DescriptorPool, DynamicMessage, Kind, MessageDescriptor, Value};
+
+    const CURRENT: &[u8] = include_bytes!("wire_compat_current.bin");
+    const PREVIOUS: &[u8] = include_bytes!("wire_compat_previous.bin");
+
+    fn pool(descriptors: &[u8]) -> DescriptorPool {
+        DescriptorPool::decode(descriptors).expect("Failed to decode the file descriptor set")
+    }
+
+    /// A value of the `kind` other than its default, so it is written on the wire.
+    fn sample(kind: &Kind) -> Value {
+        match kind {
+            Kind::Double => Value::F64(1.5),
+            Kind::Float => Value::F32(1.5),
+            Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 => Value::I32(-7),
+            Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 => Value::I64(-7),
+            Kind::Uint32 | Kind::Fixed32 => Value::U32(7),
+            Kind::Uint64 | Kind::Fixed64 => Value::U64(7),
+            Kind::Bool => Value::Bool(true),
+            Kind::String => Value::String("sample".to_string()),
+            Kind::Bytes => Value::Bytes(prost::bytes::Bytes::from_static(b"sample")),
+            Kind::Message(message) => Value::Message(DynamicMessage::new(message.clone())),
+            Kind::Enum(descriptor) => Value::EnumNumber(
+                descriptor
+                    .values()
+                    .map(|value| value.number())
+                    .find(|number| *number != 0)
+                    .unwrap_or(1),
+            ),
+        }
+    }
+
+    /// A message with every field set, except the maps. Only the last field of a oneof is kept.
+    fn populated(descriptor: &MessageDescriptor) -> DynamicMessage {
+        let mut message = DynamicMessage::new(descriptor.clone());
+
+        descriptor
+            .fields()
+            .filter(|field| !field.is_map())
+            .for_each(|field| {
+                let value = match sample(&field.kind()) {
+                    value if field.is_list() => Value::List(vec![value]),
+                    value => value,
+                };
+                message.set_field(&field, value);
+            });
+
+        message
+    }
+
+    /// Encode a populated `written` message and decode it as the `read` one, expecting:
+    ///
+    /// - the fields known to both to keep their values, when their types match,
+    /// - the fields only the writer knows to be kept as unknown fields, so encoding the message
+    ///   again and decoding it as the `written` one gives the original message back,
+    /// - the fields only the reader knows to be left at their defaults.
+    fn check_message(written: &MessageDescriptor, read: &MessageDescriptor) -> Result<(), String> {
+        let message = populated(written);
+        let decoded = DynamicMessage::decode(read.clone(), message.encode_to_vec().as_slice())
+            .map_err(|e| format!("failed to decode it: {e}"))?;
+
+        read.fields().try_for_each(|field| {
+            let Some(writer) = written.get_field(field.number()) else {
+                return if *decoded.get_field(&field) == Value::default_value_for_field(&field) {
+                    Ok(())
+                } else {
+                    Err(format!("`{}` is not left at its default", field.name()))
+                };
+            };
+
+            match (writer.kind(), field.kind()) {
+                (Kind::Message(_), Kind::Message(_))
+                    if message.has_field(&writer) != decoded.has_field(&field) =>
+                {
+                    Err(format!("the presence of `{}` changed", field.name()))
+                }
+                (Kind::Message(_), _) | (_, Kind::Message(_)) => Ok(()),
+                (written_kind, read_kind)
+                    if std::mem::discriminant(&written_kind) == std::mem::discriminant(&read_kind)
+                        && message.get_field(&writer) != decoded.get_field(&field) =>
+                {
+                    Err(format!("the value of `{}` changed", field.name()))
+                }
+                // Otherwise the type changed, decoding it without an error is all it takes.
+                _ => Ok(()),
+            }
+        })?;
+
+        let again = DynamicMessage::decode(written.clone(), decoded.encode_to_vec().as_slice())
+            .map_err(|e| format!("failed to decode it again: {e}"))?;
+        if again == message {
+            Ok(())
+        } else {
+            Err("the fields unknown to the reader were not kept".to_string())
+        }
+    }
+
+    /// Check every message of the `writer` also defined by the `reader`.
+    fn check(writer: &DescriptorPool, reader: &DescriptorPool) {
+        let failures = writer
+            .all_messages()
+            .filter(|written| !written.is_map_entry())
+            .filter_map(|written| {
+                let read = reader.get_message_by_name(written.full_name())?;
+
+                check_message(&written, &read)
+                    .err()
+                    .map(|e| format!("`{}`: {e}", written.full_name()))
+            })
+            .collect::<Vec<_>>();
+
+        assert!(
+            failures.is_empty(),
+            "Found wire-incompatible messages:\n{}",
+            failures.join("\n"),
+        );
+    }
+
+    #[test]
+    fn current_messages_decode_with_the_previous_descriptors() {
+        check(&pool(CURRENT), &pool(PREVIOUS));
+    }
+
+    #[test]
+    fn previous_messages_decode_with_the_current_descriptors() {
+        check(&pool(PREVIOUS), &pool(CURRENT));
+    }
+}
+"#;
+
+/// The files written by [`write`] into the `dst` directory.
+pub fn files(dst: &path::Path) -> [path::PathBuf; 3] {
+    [FILE, CURRENT_FILE, PREVIOUS_FILE].map(|file| dst.join(file))
+}
+
+/// Write the wire-compatibility tests of the `fds` against the file descriptor set of the
+/// `previous` version to the [`FILE`] in the `dst` directory, along with both descriptor sets, and
+/// include them into the root module of the `dst` laid out with the `layout`.
+pub fn write(
+    fds: &prost_types::FileDescriptorSet,
+    previous: &path::Path,
+    dst: &path::Path,
+    layout: modgen::Layout,
+) -> Result<Vec<path::PathBuf>, Error> {
+    // Decoded first, so an invalid descriptor set fails the build rather than the tests.
+    let previous = descriptor::read(previous)?;
+    let [code, current_file, previous_file] = files(dst);
+
+    tracing::debug!("Writing the wire-compatibility tests: {}", code.display());
+    fs::write(&code, CODE).map_err(|e| Error::Write(e, code.clone()))?;
+    fs::write(&current_file, fds.encode_to_vec())
+        .map_err(|e| Error::Write(e, current_file.clone()))?;
+    fs::write(&previous_file, previous.encode_to_vec())
+        .map_err(|e| Error::Write(e, previous_file.clone()))?;
+
+    let root = dst.join(layout.root_file());
+    fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&root)
+        .and_then(|mut out| {
+            io::Write::write_all(&mut out, format!("\ninclude!(\"{FILE}\");\n").as_bytes())
+        })
+        .map_err(|e| Error::Write(e, root))?;
+
+    Ok(vec![code, current_file, previous_file])
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message as _;
+    use std::fs;
+
+    #[test]
+    fn write_embeds_both_descriptor_sets() {
+        let dst = tempfile::TempDir::new().expect("Failed to create a test directory");
+        let fds = |package: &str| prost_types::FileDescriptorSet {
+            file: vec![prost_types::FileDescriptorProto {
+                name: Some("crabs.proto".to_string()),
+                package: Some(package.to_string()),
+                ..Default::default()
+            }],
+        };
+        let previous = dst.path().join("previous.bin");
+        fs::write(&previous, fds("crabs.v1").encode_to_vec()).expect("Failed to write a test file");
+        fs::write(dst.path().join("mod.rs"), "pub mod crabs;\n")
+            .expect("Failed to write a test file");
+
+        let files = super::write(
+            &fds("crabs.v2"),
+            &previous,
+            dst.path(),
+            crate::modgen::Layout::Tree,
+        )
+        .expect("Failed to write the wire-compatibility tests");
+
+        assert_eq!(files, super::files(dst.path()));
+        assert_eq!(
+            crate::descriptor::read(&dst.path().join(super::CURRENT_FILE))
+                .expect("Failed to read the current descriptors"),
+            fds("crabs.v2"),
+        );
+        assert_eq!(
+            crate::descriptor::read(&dst.path().join(super::PREVIOUS_FILE))
+                .expect("Failed to read the previous descriptors"),
+            fds("crabs.v1"),
+        );
+        assert_eq!(
+            fs::read_to_string(dst.path().join("mod.rs")).expect("Failed to read the root module"),
+            "pub mod crabs;\n\ninclude!(\"wire_compat.rs\");\n",
+        );
+
+        let result = super::write(
+            &fds("crabs.v2"),
+            &dst.path().join("missing.bin"),
+            dst.path(),
+            crate::modgen::Layout::Tree,
+        );
+        assert!(
+            matches!(
+                result,
+                Err(super::Error::Previous(crate::descriptor::Error::Read(..)))
+            ),
+            "Expected `Err(Error::Previous(..))`, got: `{result:?}`",
+        );
+    }
+}